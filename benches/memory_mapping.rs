@@ -186,6 +186,46 @@ bench_randomized_access_with_n_entries!(
     bench_randomized_access_with_1024_entries_unaligned
 );
 
+macro_rules! bench_local_access_with_n_entries {
+    (do_bench, $name:ident, $mem:tt, $n:expr) => {
+        #[bench]
+        fn $name(bencher: &mut Bencher) {
+            let mut prng = new_prng!();
+            let (memory_regions, _end_address) =
+                generate_memory_regions($n, MemoryState::Readable, None);
+            let config = Config::default();
+            let memory_mapping = $mem::new(memory_regions, &config, &SBPFVersion::V2).unwrap();
+            // The accesses stay within the first 4 regions, which all fit into the mapping cache
+            bencher.iter(|| {
+                let _ = memory_mapping.map(
+                    AccessType::Load,
+                    0x100000000 * (1 + prng.gen::<u64>() % 4) + prng.gen::<u64>() % 4,
+                    1,
+                );
+            });
+        }
+    };
+    ($n:expr, $aligned:ident, $unaligned:ident) => {
+        bench_local_access_with_n_entries!(do_bench, $aligned, AlignedMemoryMapping, $n);
+        bench_local_access_with_n_entries!(do_bench, $unaligned, UnalignedMemoryMapping, $n);
+    };
+}
+bench_local_access_with_n_entries!(
+    16,
+    bench_local_access_with_0016_entries_aligned,
+    bench_local_access_with_0016_entries_unaligned
+);
+bench_local_access_with_n_entries!(
+    256,
+    bench_local_access_with_0256_entries_aligned,
+    bench_local_access_with_0256_entries_unaligned
+);
+bench_local_access_with_n_entries!(
+    1024,
+    bench_local_access_with_1024_entries_aligned,
+    bench_local_access_with_1024_entries_unaligned
+);
+
 macro_rules! bench_randomized_mapping_with_n_entries {
     (do_bench, $name:ident, $mem:tt, $n:expr) => {
         #[bench]
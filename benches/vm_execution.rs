@@ -10,9 +10,12 @@ extern crate solana_rbpf;
 extern crate test;
 
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
-use solana_rbpf::{ebpf, memory_region::MemoryRegion, program::FunctionRegistry, vm::Config};
+use solana_rbpf::{ebpf, memory_region::MemoryRegion};
 use solana_rbpf::{
-    elf::Executable, program::BuiltinProgram, verifier::RequisiteVerifier, vm::TestContextObject,
+    elf::Executable,
+    program::{BuiltinProgram, FunctionRegistry},
+    verifier::RequisiteVerifier,
+    vm::{Config, TestContextObject},
 };
 use std::{fs::File, io::Read, sync::Arc};
 use test::Bencher;
@@ -70,6 +73,43 @@ fn bench_init_jit_start(bencher: &mut Bencher) {
     });
 }
 
+/// Compare with and without `--features threaded-dispatch`
+#[bench]
+fn bench_interpreter_without_accounting(bencher: &mut Bencher) {
+    let executable = solana_rbpf::assembler::assemble::<TestContextObject>(
+        "
+    mov r0, 0
+    add r0, 1
+    xor r1, r0
+    lsh r1, 1
+    jlt r0, 0x20000, -4
+    exit",
+        Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_instruction_meter: false,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::default();
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    bencher.iter(|| {
+        let (_instruction_count, result) = vm.execute_program(&executable, true);
+        assert!(result.is_ok(), "{:?}", result);
+    });
+}
+
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
 fn bench_jit_vs_interpreter(
     bencher: &mut Bencher,
@@ -4,6 +4,7 @@ use solana_rbpf::{
     assembler::assemble,
     ebpf,
     elf::Executable,
+    error::{EbpfError, ProgramResult},
     memory_region::{MemoryMapping, MemoryRegion},
     program::{BuiltinProgram, FunctionRegistry},
     static_analysis::Analysis,
@@ -59,7 +60,14 @@ fn main() {
                 .short('u')
                 .long("use")
                 .takes_value(true)
-                .possible_values(&["cfg", "debugger", "disassembler", "interpreter", "jit"])
+                .possible_values(&[
+                    "cfg",
+                    "debugger",
+                    "disassembler",
+                    "interpreter",
+                    "jit",
+                    "symbols",
+                ])
                 .required(true),
         )
         .arg(
@@ -91,9 +99,46 @@ fn main() {
                 .short('p')
                 .long("prof"),
         )
+        .arg(
+            Arg::new("layout")
+                .about("Display the memory layout of the program")
+                .long("layout"),
+        )
+        .arg(
+            Arg::new("fail syscall")
+                .about("Fail the COUNTth invocation of the syscall NAME")
+                .long("fail-syscall")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("NAME:COUNT"),
+        )
+        .arg(
+            Arg::new("expect result")
+                .about("Exit with code 1 unless the program returns VALUE")
+                .long("expect-result")
+                .takes_value(true)
+                .value_name("VALUE")
+                .conflicts_with("expect error"),
+        )
+        .arg(
+            Arg::new("expect error")
+                .about(
+                    "Exit with code 1 unless the program fails with an error containing SUBSTRING",
+                )
+                .long("expect-error")
+                .takes_value(true)
+                .value_name("SUBSTRING"),
+        )
         .get_matches();
+    let expected_result = matches.value_of("expect result").map(|value| {
+        match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse::<u64>(),
+        }
+        .expect("--expect-result expects a decimal or 0x prefixed hexadecimal number")
+    });
 
-    let loader = Arc::new(BuiltinProgram::new_loader(
+    let mut loader = BuiltinProgram::new_loader(
         Config {
             enable_instruction_tracing: matches.is_present("trace")
                 || matches.is_present("profile"),
@@ -101,7 +146,21 @@ fn main() {
             ..Config::default()
         },
         FunctionRegistry::default(),
-    ));
+    );
+    for fault in matches.values_of("fail syscall").into_iter().flatten() {
+        let (name, count) = fault
+            .rsplit_once(':')
+            .expect("--fail-syscall expects NAME:COUNT");
+        let count = count.parse::<u64>().unwrap();
+        loader
+            .inject_syscall_fault(
+                name.as_bytes(),
+                count,
+                format!("injected failure of invocation {count} of {name}"),
+            )
+            .unwrap();
+    }
+    let loader = Arc::new(loader);
     #[allow(unused_mut)]
     let mut executable = match matches.value_of("assembler") {
         Some(asm_file_name) => {
@@ -114,13 +173,42 @@ fn main() {
             let mut file = File::open(Path::new(matches.value_of("elf").unwrap())).unwrap();
             let mut elf = Vec::new();
             file.read_to_end(&mut elf).unwrap();
+            let issues = Executable::<TestContextObject>::validate_report(&elf, loader.clone());
+            for issue in issues.iter() {
+                println!("ELF issue: {issue}");
+            }
             Executable::<TestContextObject>::from_elf(&elf, loader)
                 .map_err(|err| format!("Executable constructor failed: {err:?}"))
         }
     }
     .unwrap();
 
-    executable.verify::<RequisiteVerifier>().unwrap();
+    if let Err(err) = executable.verify::<RequisiteVerifier>() {
+        let mut message = format!("Verification failed: {:?}", err);
+        if let EbpfError::VerifierError(err) = &err {
+            if let Some(context) = executable.verifier_error_context(err) {
+                message.push_str(&format!(" at {}", context));
+                if let Some(location) = executable.get_source_location(context.pc) {
+                    message.push_str(&format!(" ({})", location));
+                }
+                message.push_str(&format!("\n{}", executable.render_snippet(context.pc, 3)));
+            }
+        }
+        panic!("{}", message);
+    }
+
+    if matches.is_present("layout") {
+        for region in executable.memory_layout() {
+            println!(
+                "{:#018x}-{:#018x} r{}{} {}",
+                region.vm_addr,
+                region.vm_addr.saturating_add(region.len),
+                if region.writable { 'w' } else { '-' },
+                if region.executable { 'x' } else { '-' },
+                region.name,
+            );
+        }
+    }
 
     let mut mem = match matches.value_of("input").unwrap().parse::<usize>() {
         Ok(allocate) => vec![0u8; allocate],
@@ -155,28 +243,14 @@ fn main() {
     );
     let regions: Vec<MemoryRegion> = vec![
         executable.get_ro_region(),
-        MemoryRegion::new_writable_gapped(
-            stack.as_slice_mut(),
-            ebpf::MM_STACK_START,
-            if !sbpf_version.dynamic_stack_frames() && config.enable_stack_frame_gaps {
-                config.stack_frame_size as u64
-            } else {
-                0
-            },
-        ),
+        MemoryRegion::new_stack(stack.as_slice_mut(), config, sbpf_version),
         MemoryRegion::new_writable(heap.as_slice_mut(), ebpf::MM_HEAP_START),
         MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START),
     ];
 
     let memory_mapping = MemoryMapping::new(regions, config, sbpf_version).unwrap();
 
-    let mut vm = EbpfVm::new(
-        executable.get_loader().clone(),
-        executable.get_sbpf_version(),
-        &mut context_object,
-        memory_mapping,
-        stack_len,
-    );
+    let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, stack_len).unwrap();
 
     let analysis = if matches.value_of("use") == Some("cfg")
         || matches.value_of("use") == Some("disassembler")
@@ -206,6 +280,21 @@ fn main() {
                 .unwrap();
             return;
         }
+        Some("symbols") => {
+            for symbol in executable.get_function_symbols() {
+                println!(
+                    "function {:>8} {:>8} {}",
+                    symbol.pc, symbol.size, symbol.name
+                );
+            }
+            for symbol in executable.get_data_symbols() {
+                println!(
+                    "data     {:#x} {:>8} {}",
+                    symbol.vm_addr, symbol.size, symbol.name
+                );
+            }
+            return;
+        }
         _ => {}
     }
 
@@ -216,6 +305,22 @@ fn main() {
         vm.execute_program(&executable, matches.value_of("use").unwrap() != "jit");
     println!("Result: {result:?}");
     println!("Instruction Count: {instruction_count}");
+    if result.is_err() {
+        if let Some(location) = executable.get_source_location(vm.registers[11] as usize) {
+            println!("Source Location: {location}");
+        }
+    }
+    if let ProgramResult::Err(
+        EbpfError::AccessViolation(_, vm_addr, _, _)
+        | EbpfError::StackAccessViolation(_, vm_addr, _, _),
+    ) = result
+    {
+        println!("Memory near the fault:\n");
+        let stdout = std::io::stdout();
+        vm.memory_mapping
+            .dump_range(vm_addr.saturating_sub(32) & !15, 80, &mut stdout.lock())
+            .unwrap();
+    }
     if matches.is_present("trace") {
         println!("Trace:\n");
         let stdout = std::io::stdout();
@@ -237,4 +342,19 @@ fn main() {
             .visualize_graphically(&mut file, Some(&dynamic_analysis))
             .unwrap();
     }
+    let mismatch = match (expected_result, matches.value_of("expect error"), &result) {
+        (Some(expected), _, ProgramResult::Ok(value)) if *value == expected => None,
+        (Some(expected), _, _) => Some(format!("Expected result {expected}")),
+        (_, Some(expected), ProgramResult::Err(error))
+            if format!("{error:?}").contains(expected) =>
+        {
+            None
+        }
+        (_, Some(expected), _) => Some(format!("Expected an error containing {expected:?}")),
+        (None, None, _) => None,
+    };
+    if let Some(mismatch) = mismatch {
+        eprintln!("{mismatch}, got {result:?}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,208 @@
+// Copyright 2024 Solana Maintainers <maintainers@solana.com>
+//
+// Licensed under the Apache License, Version 2.0 <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+extern crate solana_rbpf;
+extern crate test_utils;
+use solana_rbpf::{
+    ebpf,
+    elf::Executable,
+    memory_region::MemoryRegion,
+    program::{BuiltinProgram, FunctionRegistry, SBPFVersion},
+    static_analysis::Analysis,
+    verifier::RequisiteVerifier,
+    vm::{Config, TestContextObject},
+};
+use std::sync::Arc;
+use test_utils::create_vm;
+
+// Measures the host cost of every opcode on the current machine and suggests a cost table.
+//
+// Each opcode is repeated REPETITIONS times in a straight line program. The time of an otherwise
+// identical program without the repetitions is subtracted and the remainder divided by
+// REPETITIONS. The suggested cost of an opcode is its JIT (or interpreter, if the JIT is not
+// available) cost relative to the cheapest measured opcode.
+//
+// Usage: cargo run --release --example calibrate_costs [v1|v2]
+
+const REPETITIONS: usize = 1000;
+const RUNS: usize = 64;
+
+fn timestamp() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    }
+}
+
+fn instruction(opc: u8, dst: u8, src: u8, off: i16, imm: i64) -> ebpf::Insn {
+    ebpf::Insn {
+        ptr: 0,
+        opc,
+        dst,
+        src,
+        off,
+        imm,
+    }
+}
+
+/// Chooses operands which let the opcode execute without faulting
+///
+/// r1 points to the input region, r3 is the scratch destination and r4 holds a one.
+fn operands(opc: u8) -> ebpf::Insn {
+    match opc & 0x07 {
+        ebpf::BPF_LDX => instruction(opc, 3, 1, 0, 0),
+        ebpf::BPF_ST => instruction(opc, 1, 0, 0, 1),
+        ebpf::BPF_STX => instruction(opc, 1, 3, 0, 0),
+        ebpf::BPF_JMP => instruction(opc, 3, 4, 0, 1),
+        _ => match opc {
+            ebpf::LE | ebpf::BE => instruction(opc, 3, 0, 0, 64),
+            _ => instruction(opc, 3, 4, 0, 1),
+        },
+    }
+}
+
+fn build_program(insn: Option<&ebpf::Insn>) -> Vec<u8> {
+    let mut program = Vec::new();
+    program.extend_from_slice(&instruction(ebpf::MOV64_IMM, 3, 0, 0, 1).to_array());
+    program.extend_from_slice(&instruction(ebpf::MOV64_IMM, 4, 0, 0, 1).to_array());
+    if let Some(insn) = insn {
+        for _ in 0..REPETITIONS {
+            program.extend_from_slice(&insn.to_array());
+            if insn.opc == ebpf::LD_DW_IMM {
+                program.extend_from_slice(&[0; ebpf::INSN_SIZE]);
+            }
+        }
+    }
+    program.extend_from_slice(&instruction(ebpf::EXIT, 0, 0, 0, 0).to_array());
+    program
+}
+
+/// Returns the minimal number of ticks it took to run the program in the interpreter and the JIT
+fn measure(
+    program: &[u8],
+    loader: &Arc<BuiltinProgram<TestContextObject>>,
+    sbpf_version: &SBPFVersion,
+) -> Option<(u64, Option<u64>)> {
+    #[allow(unused_mut)]
+    let mut executable = Executable::<TestContextObject>::from_text_bytes(
+        program,
+        loader.clone(),
+        sbpf_version.clone(),
+        FunctionRegistry::default(),
+    )
+    .ok()?;
+    executable.verify::<RequisiteVerifier>().ok()?;
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    let jit_available = executable.jit_compile().is_ok();
+    #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+    let jit_available = false;
+    let mut ticks = [u64::MAX; 2];
+    for _ in 0..RUNS {
+        for (interpreted, ticks) in [true, false].iter().zip(ticks.iter_mut()) {
+            if !*interpreted && !jit_available {
+                continue;
+            }
+            let mut mem = [0u8; 64];
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            let mut context_object = TestContextObject::new((REPETITIONS * 2 + 3) as u64);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            let start = timestamp();
+            let (_instruction_count, result) = vm.execute_program(&executable, *interpreted);
+            let end = timestamp();
+            if result.is_err() {
+                return None;
+            }
+            *ticks = (*ticks).min(end.saturating_sub(start));
+        }
+    }
+    Some((ticks[0], jit_available.then_some(ticks[1])))
+}
+
+fn main() {
+    let sbpf_version = match std::env::args().nth(1).as_deref() {
+        Some("v1") => SBPFVersion::V1,
+        Some("v2") | None => SBPFVersion::V2,
+        Some(other) => panic!("Unknown SBPF version {}, expected v1 or v2", other),
+    };
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            noop_instruction_rate: 0,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    let (baseline_interpreter, baseline_jit) =
+        measure(&build_program(None), &loader, &sbpf_version).unwrap();
+
+    let mut measurements = Vec::new();
+    for opc in 0..=u8::MAX {
+        if matches!(opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT) {
+            continue;
+        }
+        let insn = operands(opc);
+        let program = build_program(Some(&insn));
+        let Some((interpreter, jit)) = measure(&program, &loader, &sbpf_version) else {
+            continue;
+        };
+        let per_instruction =
+            |ticks: u64, baseline: u64| ticks.saturating_sub(baseline) as f64 / REPETITIONS as f64;
+        let executable = Executable::<TestContextObject>::from_text_bytes(
+            &program,
+            loader.clone(),
+            sbpf_version.clone(),
+            FunctionRegistry::default(),
+        )
+        .unwrap();
+        let analysis = Analysis::from_executable(&executable).unwrap();
+        let mnemonic = analysis.disassemble_instruction(&analysis.instructions[2]);
+        measurements.push((
+            opc,
+            mnemonic.split(' ').next().unwrap_or_default().to_string(),
+            per_instruction(interpreter, baseline_interpreter),
+            jit.zip(baseline_jit)
+                .map(|(jit, baseline)| per_instruction(jit, baseline)),
+        ));
+    }
+
+    let reference = |interpreter: f64, jit: Option<f64>| jit.unwrap_or(interpreter);
+    let cheapest = measurements
+        .iter()
+        .map(|(_opc, _name, interpreter, jit)| reference(*interpreter, *jit))
+        .filter(|ticks| *ticks > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    println!("opcode  mnemonic  interpreter  jit  (ticks per instruction)");
+    for (opc, name, interpreter, jit) in measurements.iter() {
+        let jit = jit.map_or_else(|| "-".to_string(), |jit| format!("{jit:.2}"));
+        println!("{opc:#04x}  {name:<8}  {interpreter:>10.2}  {jit:>6}");
+    }
+    println!();
+    println!("// Suggested cost table, relative to the cheapest opcode");
+    println!(
+        "const OPCODE_COSTS: [(u8, u64); {}] = [",
+        measurements.len()
+    );
+    for (opc, name, interpreter, jit) in measurements.iter() {
+        let cost = (reference(*interpreter, *jit) / cheapest).round().max(1.0) as u64;
+        println!("    ({opc:#04x}, {cost}), // {name}");
+    }
+    println!("];");
+}
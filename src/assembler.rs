@@ -20,6 +20,7 @@ use crate::{
     ebpf::{self, Insn},
     elf::Executable,
     program::{BuiltinProgram, FunctionRegistry, SBPFVersion},
+    verifier::is_opcode_supported,
     vm::ContextObject,
 };
 use std::{collections::HashMap, sync::Arc};
@@ -270,7 +271,14 @@ fn resolve_label(
 /// # Examples
 ///
 /// ```
-/// use solana_rbpf::{assembler::assemble, program::BuiltinProgram, vm::{Config, TestContextObject}};
+/// use solana_rbpf::{assembler::assemble, program::{BuiltinProgram, FunctionRegistry}, vm::{Config, TestContextObject}};
+/// let loader = BuiltinProgram::new_loader(
+///     Config {
+///         enable_sbpf_v2: false,
+///         ..Config::default()
+///     },
+///     FunctionRegistry::default(),
+/// );
 /// let executable = assemble::<TestContextObject>(
 ///    "add64 r1, 0x605
 ///     mov64 r2, 0x32
@@ -278,7 +286,7 @@ fn resolve_label(
 ///     be16 r0
 ///     neg64 r2
 ///     exit",
-///     std::sync::Arc::new(BuiltinProgram::new_mock()),
+///     std::sync::Arc::new(loader),
 /// ).unwrap();
 /// let program = executable.get_text_bytes().1;
 /// println!("{:?}", program);
@@ -454,6 +462,11 @@ pub fn assemble<C: ContextObject>(
                             }
                             _ => Err(format!("Unexpected operands: {operands:?}")),
                         }?;
+                        if !is_opcode_supported(insn.opc, &sbpf_version) {
+                            return Err(format!(
+                                "Instruction {name} is not supported by {sbpf_version:?}"
+                            ));
+                        }
                         insn.ptr = insn_ptr;
                         instructions.push(insn);
                         insn_ptr += 1;
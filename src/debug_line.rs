@@ -0,0 +1,465 @@
+//! Source locations from the DWARF .debug_line section
+//!
+//! Only the line number programs are decoded, which is enough to map an instruction back to
+//! the file and line it was compiled from. Versions 2 to 5 of the format are supported.
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::{collections::BTreeMap, fmt};
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+/// File and line an instruction was compiled from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// Path of the source file, including its directory if known
+    pub file: String,
+    /// Line in the source file, starting at one
+    pub line: u64,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Maps addresses to source locations
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    /// Source locations by the first address they cover, `None` ends a sequence
+    rows: BTreeMap<u64, Option<SourceLocation>>,
+}
+
+impl LineTable {
+    /// Decodes all line number programs of a .debug_line section
+    ///
+    /// The strings of DWARF 5 file names are looked up in `line_strings` (.debug_line_str)
+    /// and `strings` (.debug_str). Returns `None` if the section is malformed.
+    pub fn parse(debug_line: &[u8], line_strings: &[u8], strings: &[u8]) -> Option<Self> {
+        let mut result = Self::default();
+        let mut reader = Reader::new(debug_line);
+        while !reader.is_empty() {
+            let mut unit_length = reader.u32()? as u64;
+            let offset_size = if unit_length == 0xffff_ffff {
+                unit_length = reader.u64()?;
+                8
+            } else {
+                4
+            };
+            let unit = reader.take(unit_length as usize)?;
+            result.parse_unit(Reader::new(unit), offset_size, line_strings, strings)?;
+        }
+        Some(result)
+    }
+
+    fn parse_unit(
+        &mut self,
+        mut reader: Reader,
+        offset_size: usize,
+        line_strings: &[u8],
+        strings: &[u8],
+    ) -> Option<()> {
+        let version = reader.u16()?;
+        if !(2..=5).contains(&version) {
+            return None;
+        }
+        let mut address_size = 8;
+        if version >= 5 {
+            address_size = reader.u8()?;
+            let _segment_selector_size = reader.u8()?;
+        }
+        let header_length = reader.offset(offset_size)?;
+        let mut program = reader.clone();
+        program.take(header_length as usize)?;
+        let minimum_instruction_length = reader.u8()? as u64;
+        if version >= 4 {
+            let _maximum_operations_per_instruction = reader.u8()?;
+        }
+        let _default_is_stmt = reader.u8()?;
+        let line_base = reader.u8()? as i8 as i64;
+        let line_range = reader.u8()?;
+        let opcode_base = reader.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return None;
+        }
+        let standard_opcode_lengths = reader.take(opcode_base.saturating_sub(1) as usize)?;
+
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        if version >= 5 {
+            for entry in reader.entries(offset_size, line_strings, strings)? {
+                directories.push(entry.0);
+            }
+            for (name, directory_index) in reader.entries(offset_size, line_strings, strings)? {
+                files.push(join_path(directories.get(directory_index as usize), name));
+            }
+        } else {
+            // Index zero is the compilation directory and file, which are not listed
+            directories.push(String::new());
+            files.push(String::new());
+            loop {
+                let directory = reader.c_str()?;
+                if directory.is_empty() {
+                    break;
+                }
+                directories.push(directory);
+            }
+            loop {
+                let name = reader.c_str()?;
+                if name.is_empty() {
+                    break;
+                }
+                let directory_index = reader.uleb128()?;
+                let _modification_time = reader.uleb128()?;
+                let _length = reader.uleb128()?;
+                files.push(join_path(directories.get(directory_index as usize), name));
+            }
+        }
+
+        let mut address = 0u64;
+        let mut file = 1u64;
+        let mut line = 1u64;
+        let location = |files: &[String], file: u64, line: u64| {
+            Some(SourceLocation {
+                file: files.get(file as usize).cloned().unwrap_or_default(),
+                line,
+            })
+        };
+        while !program.is_empty() {
+            let opcode = program.u8()?;
+            if opcode >= opcode_base {
+                let adjusted_opcode = opcode.saturating_sub(opcode_base);
+                address = address.wrapping_add(
+                    (adjusted_opcode.checked_div(line_range)? as u64)
+                        .wrapping_mul(minimum_instruction_length),
+                );
+                line = line.wrapping_add_signed(
+                    line_base.wrapping_add(adjusted_opcode.checked_rem(line_range)? as i64),
+                );
+                self.rows.insert(address, location(&files, file, line));
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = program.uleb128()?;
+                    let mut extended = Reader::new(program.take(length as usize)?);
+                    match extended.u8()? {
+                        DW_LNE_END_SEQUENCE => {
+                            self.rows.entry(address).or_insert(None);
+                            address = 0;
+                            file = 1;
+                            line = 1;
+                        }
+                        DW_LNE_SET_ADDRESS => {
+                            address = match address_size {
+                                4 => extended.u32()? as u64,
+                                8 => extended.u64()?,
+                                _ => return None,
+                            };
+                        }
+                        DW_LNE_DEFINE_FILE => {
+                            let name = extended.c_str()?;
+                            let directory_index = extended.uleb128()?;
+                            files.push(join_path(directories.get(directory_index as usize), name));
+                        }
+                        _ => {}
+                    }
+                }
+                DW_LNS_COPY => {
+                    self.rows.insert(address, location(&files, file, line));
+                }
+                DW_LNS_ADVANCE_PC => {
+                    address = address
+                        .wrapping_add(program.uleb128()?.wrapping_mul(minimum_instruction_length));
+                }
+                DW_LNS_ADVANCE_LINE => line = line.wrapping_add_signed(program.sleb128()?),
+                DW_LNS_SET_FILE => file = program.uleb128()?,
+                DW_LNS_CONST_ADD_PC => {
+                    address = address.wrapping_add(
+                        (255u8.saturating_sub(opcode_base).checked_div(line_range)? as u64)
+                            .wrapping_mul(minimum_instruction_length),
+                    );
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    address = address.wrapping_add(program.u16()? as u64);
+                }
+                _ => {
+                    // Skip the operands of the other standard opcodes
+                    let operand_count =
+                        *standard_opcode_lengths.get(opcode.saturating_sub(1) as usize)?;
+                    for _ in 0..operand_count {
+                        program.uleb128()?;
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Returns the source location of the instruction at `address`
+    pub fn lookup(&self, address: u64) -> Option<&SourceLocation> {
+        self.rows
+            .range(..=address)
+            .next_back()
+            .and_then(|(_address, location)| location.as_ref())
+    }
+
+    /// Converts the addresses into pcs of a text section starting at `text_address`
+    ///
+    /// Rows outside of the `instruction_count` instructions of the text section are dropped.
+    pub(crate) fn into_pcs(self, text_address: u64, instruction_count: usize) -> Self {
+        let text_end = text_address.saturating_add(
+            (instruction_count as u64).saturating_mul(crate::ebpf::INSN_SIZE as u64),
+        );
+        let mut rows = BTreeMap::new();
+        for (address, location) in self.rows {
+            if address < text_address || address >= text_end {
+                continue;
+            }
+            let pc = address
+                .saturating_sub(text_address)
+                .checked_div(crate::ebpf::INSN_SIZE as u64)
+                .unwrap_or_default();
+            rows.insert(pc, location);
+        }
+        rows.insert(instruction_count as u64, None);
+        Self { rows }
+    }
+
+    /// Approximate number of bytes in memory
+    pub fn mem_size(&self) -> usize {
+        self.rows.values().fold(
+            self.rows
+                .len()
+                .saturating_mul(std::mem::size_of::<(u64, Option<SourceLocation>)>()),
+            |total, location| {
+                total.saturating_add(location.as_ref().map_or(0, |location| location.file.len()))
+            },
+        )
+    }
+}
+
+fn join_path(directory: Option<&String>, name: String) -> String {
+    match directory {
+        Some(directory) if !directory.is_empty() && !name.starts_with('/') => {
+            format!("{directory}/{name}")
+        }
+        _ => name,
+    }
+}
+
+#[derive(Clone)]
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let head = self.bytes.get(..length)?;
+        self.bytes = &self.bytes[length..];
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(LittleEndian::read_u16)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(LittleEndian::read_u32)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(LittleEndian::read_u64)
+    }
+
+    fn offset(&mut self, offset_size: usize) -> Option<u64> {
+        if offset_size == 8 {
+            self.u64()
+        } else {
+            self.u32().map(|offset| offset as u64)
+        }
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut result = 0u64;
+        let mut shift = 0u32;
+        loop {
+            let byte = self.u8()?;
+            result |= ((byte & 0x7f) as u64).checked_shl(shift).unwrap_or(0);
+            shift = shift.saturating_add(7);
+            if byte & 0x80 == 0 {
+                return Some(result);
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let mut result = 0i64;
+        let mut shift = 0u32;
+        loop {
+            let byte = self.u8()?;
+            result |= ((byte & 0x7f) as i64).checked_shl(shift).unwrap_or(0);
+            shift = shift.saturating_add(7);
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1i64 << shift;
+                }
+                return Some(result);
+            }
+        }
+    }
+
+    fn c_str(&mut self) -> Option<String> {
+        let length = self.bytes.iter().position(|byte| *byte == 0)?;
+        let string = String::from_utf8_lossy(self.take(length)?).to_string();
+        self.take(1)?;
+        Some(string)
+    }
+
+    /// Reads a DWARF 5 directory or file name table, as pairs of path and directory index
+    fn entries(
+        &mut self,
+        offset_size: usize,
+        line_strings: &[u8],
+        strings: &[u8],
+    ) -> Option<Vec<(String, u64)>> {
+        let format_count = self.u8()?;
+        let mut formats = Vec::new();
+        for _ in 0..format_count {
+            formats.push((self.uleb128()?, self.uleb128()?));
+        }
+        let entry_count = self.uleb128()?;
+        let mut entries = Vec::new();
+        for _ in 0..entry_count {
+            let mut path = String::new();
+            let mut directory_index = 0;
+            for (content_type, form) in formats.iter() {
+                let string_at = |strings: &[u8], offset: u64| {
+                    Reader::new(strings.get(offset as usize..)?).c_str()
+                };
+                let value = match *form {
+                    DW_FORM_STRING => Value::String(self.c_str()?),
+                    DW_FORM_LINE_STRP => {
+                        Value::String(string_at(line_strings, self.offset(offset_size)?)?)
+                    }
+                    DW_FORM_STRP => Value::String(string_at(strings, self.offset(offset_size)?)?),
+                    DW_FORM_UDATA => Value::Number(self.uleb128()?),
+                    DW_FORM_DATA1 => Value::Number(self.u8()? as u64),
+                    DW_FORM_DATA2 => Value::Number(self.u16()? as u64),
+                    DW_FORM_DATA4 => Value::Number(self.u32()? as u64),
+                    DW_FORM_DATA8 => Value::Number(self.u64()?),
+                    DW_FORM_DATA16 => {
+                        self.take(16)?;
+                        Value::Number(0)
+                    }
+                    DW_FORM_BLOCK => {
+                        let length = self.uleb128()?;
+                        self.take(length as usize)?;
+                        Value::Number(0)
+                    }
+                    _ => return None,
+                };
+                match (*content_type, value) {
+                    (DW_LNCT_PATH, Value::String(string)) => path = string,
+                    (DW_LNCT_DIRECTORY_INDEX, Value::Number(number)) => directory_index = number,
+                    _ => {}
+                }
+            }
+            entries.push((path, directory_index));
+        }
+        Some(entries)
+    }
+}
+
+enum Value {
+    String(String),
+    Number(u64),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Assembled by llvm-mc -g from four instructions on the lines 4, 5, 7 and 8 of prog.s
+    const DEBUG_LINE_V4: &[u8] = &[
+        0x38, 0x00, 0x00, 0x00, 0x04, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x08, 0x01, 0x01, 0xfb, 0x0e,
+        0x0d, 0x00, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x70,
+        0x72, 0x6f, 0x67, 0x2e, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0x02, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x21, 0x22, 0x21, 0x02, 0x01, 0x00, 0x01, 0x01,
+    ];
+    const DEBUG_LINE_V5: &[u8] = &[
+        0x55, 0x00, 0x00, 0x00, 0x05, 0x00, 0x08, 0x00, 0x37, 0x00, 0x00, 0x00, 0x08, 0x01, 0x01,
+        0xfb, 0x0e, 0x0d, 0x00, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01,
+        0x01, 0x01, 0x1f, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01, 0x1f, 0x02, 0x0f, 0x05, 0x1e,
+        0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x60, 0x72, 0x89, 0x32, 0xc0, 0xc1, 0x8d, 0x9c, 0xab,
+        0x11, 0x11, 0xb9, 0x97, 0x20, 0x88, 0x8c, 0x04, 0x00, 0x00, 0x09, 0x02, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x21, 0x22, 0x21, 0x02, 0x01, 0x00, 0x01, 0x01,
+    ];
+    const DEBUG_LINE_STR_V5: &[u8] = b"/tmp/dw\0prog.s\0";
+
+    fn check_lines(line_table: &LineTable, file: &str) {
+        for (address, line) in [(0, 4), (4, 4), (8, 5), (0x10, 7), (0x18, 8)] {
+            assert_eq!(
+                line_table.lookup(address),
+                Some(&SourceLocation {
+                    file: file.to_string(),
+                    line,
+                })
+            );
+        }
+        assert_eq!(line_table.lookup(0x20), None);
+    }
+
+    #[test]
+    fn test_parse_line_table() {
+        let line_table = LineTable::parse(DEBUG_LINE_V4, &[], &[]).unwrap();
+        check_lines(&line_table, "prog.s");
+        let line_table = LineTable::parse(DEBUG_LINE_V5, DEBUG_LINE_STR_V5, &[]).unwrap();
+        check_lines(&line_table, "/tmp/dw/prog.s");
+        assert_eq!(
+            line_table.lookup(8).unwrap().to_string(),
+            "/tmp/dw/prog.s:5"
+        );
+
+        let line_table = LineTable::parse(DEBUG_LINE_V4, &[], &[])
+            .unwrap()
+            .into_pcs(8, 2);
+        assert_eq!(line_table.lookup(0).unwrap().line, 5);
+        assert_eq!(line_table.lookup(1).unwrap().line, 7);
+        assert_eq!(line_table.lookup(2), None);
+
+        assert!(LineTable::parse(&DEBUG_LINE_V4[..20], &[], &[]).is_none());
+    }
+}
@@ -17,7 +17,9 @@ use core::convert::TryInto;
 use bpf_arch::reg::id::BpfRegId;
 use bpf_arch::reg::BpfRegs;
 use bpf_arch::Bpf;
+use gdbstub::target::ext::base::reverse_exec::ReplayLogPosition;
 use gdbstub::target::ext::base::singlethread::{SingleThreadBase, SingleThreadResume};
+use gdbstub::target::ext::breakpoints::WatchKind;
 use gdbstub::target::ext::lldb_register_info_override::{Callback, CallbackToken};
 use gdbstub::target::ext::section_offsets::Offsets;
 
@@ -25,7 +27,7 @@ use crate::{
     ebpf,
     error::{EbpfError, ProgramResult},
     interpreter::{DebugState, Interpreter},
-    memory_region::AccessType,
+    memory_region::{AccessType, WatchpointKind},
     vm::ContextObject,
 };
 
@@ -42,8 +44,12 @@ fn wait_for_tcp(port: u16) -> DynResult<TcpStream> {
     Ok(stream)
 }
 
+/// Number of steps which can be undone by reverse-step and reverse-continue
+const REVERSE_EXECUTION_CAPACITY: usize = 1 << 20;
+
 /// Connect to the debugger and hand over the control of the interpreter
 pub fn execute<C: ContextObject>(interpreter: &mut Interpreter<C>, port: u16) {
+    interpreter.start_recording(REVERSE_EXECUTION_CAPACITY);
     let connection: Box<dyn ConnectionExt<Error = std::io::Error>> =
         Box::new(wait_for_tcp(port).expect("Cannot connect to Debugger"));
     let mut dbg = GdbStub::new(connection)
@@ -72,7 +78,16 @@ pub fn execute<C: ContextObject>(interpreter: &mut Interpreter<C>, port: u16) {
                 let conn = dbg_inner.borrow_conn();
                 match interpreter.debug_state {
                     DebugState::Step => {
-                        let mut stop_reason = if interpreter.step() {
+                        let (running, watchpoint_hit) = step_over_watchpoints(interpreter);
+                        let mut stop_reason = if let (true, Some((kind, addr))) =
+                            (running, watchpoint_hit)
+                        {
+                            SingleThreadStopReason::Watch {
+                                tid: (),
+                                kind,
+                                addr,
+                            }
+                        } else if running {
                             SingleThreadStopReason::DoneStep
                         } else if let ProgramResult::Ok(result) = &interpreter.vm.program_result {
                             SingleThreadStopReason::Exited(*result as u8)
@@ -84,12 +99,58 @@ pub fn execute<C: ContextObject>(interpreter: &mut Interpreter<C>, port: u16) {
                         }
                         dbg_inner.report_stop(interpreter, stop_reason).unwrap()
                     }
+                    DebugState::ReverseStep => {
+                        let stop_reason = if interpreter.step_back() {
+                            SingleThreadStopReason::DoneStep
+                        } else {
+                            SingleThreadStopReason::ReplayLog {
+                                tid: None,
+                                pos: ReplayLogPosition::Begin,
+                            }
+                        };
+                        dbg_inner.report_stop(interpreter, stop_reason).unwrap()
+                    }
+                    DebugState::ReverseContinue => loop {
+                        if conn.peek().unwrap().is_some() {
+                            let byte = dbg_inner.borrow_conn().read().unwrap();
+                            break dbg_inner.incoming_data(interpreter, byte).unwrap();
+                        }
+                        if !interpreter.step_back() {
+                            break dbg_inner
+                                .report_stop(
+                                    interpreter,
+                                    SingleThreadStopReason::ReplayLog {
+                                        tid: None,
+                                        pos: ReplayLogPosition::Begin,
+                                    },
+                                )
+                                .unwrap();
+                        }
+                        if interpreter.breakpoints.contains(&interpreter.get_dbg_pc()) {
+                            break dbg_inner
+                                .report_stop(interpreter, SingleThreadStopReason::SwBreak(()))
+                                .unwrap();
+                        }
+                    },
                     DebugState::Continue => loop {
                         if conn.peek().unwrap().is_some() {
                             let byte = dbg_inner.borrow_conn().read().unwrap();
                             break dbg_inner.incoming_data(interpreter, byte).unwrap();
                         }
-                        if interpreter.step() {
+                        let (running, watchpoint_hit) = step_over_watchpoints(interpreter);
+                        if running {
+                            if let Some((kind, addr)) = watchpoint_hit {
+                                break dbg_inner
+                                    .report_stop(
+                                        interpreter,
+                                        SingleThreadStopReason::Watch {
+                                            tid: (),
+                                            kind,
+                                            addr,
+                                        },
+                                    )
+                                    .unwrap();
+                            }
                             if interpreter.breakpoints.contains(&interpreter.get_dbg_pc()) {
                                 break dbg_inner
                                     .report_stop(interpreter, SingleThreadStopReason::SwBreak(()))
@@ -117,6 +178,36 @@ pub fn execute<C: ContextObject>(interpreter: &mut Interpreter<C>, port: u16) {
     }
 }
 
+/// Steps like `Interpreter::step()`, but completes an access which hit a watchpoint
+///
+/// GDB expects to stop after the watched access, so the step is undone and repeated with the
+/// watchpoints suspended. Returns whether the program is still running and the kind and address
+/// of the access which hit a watchpoint.
+fn step_over_watchpoints<C: ContextObject>(
+    interpreter: &mut Interpreter<C>,
+) -> (bool, Option<(WatchKind, u64)>) {
+    if interpreter.step() {
+        return (true, None);
+    }
+    let (access_type, vm_addr) = match interpreter.vm.program_result {
+        ProgramResult::Err(EbpfError::WatchpointHit(access_type, vm_addr, _len)) => {
+            (access_type, vm_addr)
+        }
+        _ => return (false, None),
+    };
+    if !interpreter.step_back() {
+        return (false, None);
+    }
+    interpreter.vm.memory_mapping.set_watchpoints_enabled(false);
+    let running = interpreter.step();
+    interpreter.vm.memory_mapping.set_watchpoints_enabled(true);
+    let kind = match access_type {
+        AccessType::Load => WatchKind::Read,
+        AccessType::Store => WatchKind::Write,
+    };
+    (running, Some((kind, vm_addr)))
+}
+
 impl<'a, 'b, C: ContextObject> Target for Interpreter<'a, 'b, C> {
     type Arch = Bpf;
     type Error = &'static str;
@@ -140,6 +231,11 @@ impl<'a, 'b, C: ContextObject> Target for Interpreter<'a, 'b, C> {
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_monitor_cmd(&mut self) -> Option<target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_lldb_register_info_override(
         &mut self,
@@ -149,6 +245,39 @@ impl<'a, 'b, C: ContextObject> Target for Interpreter<'a, 'b, C> {
     }
 }
 
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+impl<'a, 'b, C: ContextObject> target::ext::monitor_cmd::MonitorCmd for Interpreter<'a, 'b, C> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: target::ext::monitor_cmd::ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        let cmd = String::from_utf8_lossy(cmd);
+        let mut words = cmd.split_whitespace();
+        match (words.next(), words.next().and_then(parse_number)) {
+            (Some("examine"), Some(vm_addr)) => {
+                let len = words.next().and_then(parse_number).unwrap_or(64);
+                let mut dump = Vec::new();
+                self.vm
+                    .memory_mapping
+                    .dump_range(vm_addr, len, &mut dump)
+                    .map_err(|_| "Cannot dump memory")?;
+                out.write_raw(&dump);
+            }
+            _ => {
+                gdbstub::outputln!(out, "Usage: monitor examine <address> [length]");
+            }
+        }
+        Ok(())
+    }
+}
+
 fn get_host_ptr<C: ContextObject>(
     interpreter: &mut Interpreter<C>,
     mut vm_addr: u64,
@@ -166,6 +295,20 @@ fn get_host_ptr<C: ContextObject>(
     }
 }
 
+/// Inverse of `Interpreter::get_dbg_pc()`, rejects addresses outside of the text section
+fn set_dbg_pc<C: ContextObject>(
+    interpreter: &mut Interpreter<C>,
+    dbg_pc: u64,
+) -> Result<(), TargetError<&'static str>> {
+    let pc = dbg_pc
+        .checked_sub(interpreter.executable.get_text_section_offset())
+        .and_then(|offset| offset.checked_div(ebpf::INSN_SIZE as u64))
+        .ok_or(TargetError::NonFatal)?;
+    interpreter
+        .set_register(11, pc)
+        .map_err(|_| TargetError::NonFatal)
+}
+
 impl<'a, 'b, C: ContextObject> SingleThreadBase for Interpreter<'a, 'b, C> {
     fn read_registers(&mut self, regs: &mut BpfRegs) -> TargetResult<(), Self> {
         for i in 0..10 {
@@ -177,11 +320,11 @@ impl<'a, 'b, C: ContextObject> SingleThreadBase for Interpreter<'a, 'b, C> {
     }
 
     fn write_registers(&mut self, regs: &BpfRegs) -> TargetResult<(), Self> {
+        set_dbg_pc(self, regs.pc)?;
         for i in 0..10 {
             self.reg[i] = regs.r[i];
         }
         self.reg[ebpf::FRAME_PTR_REG] = regs.sp;
-        self.reg[11] = regs.pc;
         Ok(())
     }
 
@@ -252,7 +395,7 @@ impl<'a, 'b, C: ContextObject> target::ext::base::single_register_access::Single
         match reg_id {
             BpfRegId::Gpr(i) => self.reg[i as usize] = r,
             BpfRegId::Sp => self.reg[ebpf::FRAME_PTR_REG] = r,
-            BpfRegId::Pc => self.reg[11] = r,
+            BpfRegId::Pc => set_dbg_pc(self, r)?,
             BpfRegId::InstructionCountRemaining => (),
         }
         Ok(())
@@ -276,6 +419,38 @@ impl<'a, 'b, C: ContextObject> SingleThreadResume for Interpreter<'a, 'b, C> {
     ) -> Option<target::ext::base::singlethread::SingleThreadSingleStepOps<'_, Self>> {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_reverse_step(
+        &mut self,
+    ) -> Option<target::ext::base::reverse_exec::ReverseStepOps<'_, (), Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_reverse_cont(
+        &mut self,
+    ) -> Option<target::ext::base::reverse_exec::ReverseContOps<'_, (), Self>> {
+        Some(self)
+    }
+}
+
+impl<'a, 'b, C: ContextObject> target::ext::base::reverse_exec::ReverseStep<()>
+    for Interpreter<'a, 'b, C>
+{
+    fn reverse_step(&mut self, _tid: ()) -> Result<(), Self::Error> {
+        self.debug_state = DebugState::ReverseStep;
+        Ok(())
+    }
+}
+
+impl<'a, 'b, C: ContextObject> target::ext::base::reverse_exec::ReverseCont<()>
+    for Interpreter<'a, 'b, C>
+{
+    fn reverse_cont(&mut self) -> Result<(), Self::Error> {
+        self.debug_state = DebugState::ReverseContinue;
+        Ok(())
+    }
 }
 
 impl<'a, 'b, C: ContextObject> target::ext::base::singlethread::SingleThreadSingleStep
@@ -311,6 +486,13 @@ impl<'a, 'b, C: ContextObject> target::ext::breakpoints::Breakpoints for Interpr
     ) -> Option<target::ext::breakpoints::SwBreakpointOps<'_, Self>> {
         Some(self)
     }
+
+    #[inline(always)]
+    fn support_hw_watchpoint(
+        &mut self,
+    ) -> Option<target::ext::breakpoints::HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
 }
 
 impl<'a, 'b, C: ContextObject> target::ext::breakpoints::SwBreakpoint for Interpreter<'a, 'b, C> {
@@ -338,6 +520,40 @@ impl<'a, 'b, C: ContextObject> target::ext::breakpoints::SwBreakpoint for Interp
     }
 }
 
+fn watchpoint_kind(kind: WatchKind) -> WatchpointKind {
+    match kind {
+        WatchKind::Read => WatchpointKind::Read,
+        WatchKind::Write => WatchpointKind::Write,
+        WatchKind::ReadWrite => WatchpointKind::Any,
+    }
+}
+
+impl<'a, 'b, C: ContextObject> target::ext::breakpoints::HwWatchpoint for Interpreter<'a, 'b, C> {
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        Ok(self
+            .vm
+            .memory_mapping
+            .add_watchpoint(addr, len, watchpoint_kind(kind)))
+    }
+
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: u64,
+        len: u64,
+        kind: WatchKind,
+    ) -> TargetResult<bool, Self> {
+        Ok(self
+            .vm
+            .memory_mapping
+            .remove_watchpoint(addr, len, watchpoint_kind(kind)))
+    }
+}
+
 impl<'a, 'b, C: ContextObject> target::ext::lldb_register_info_override::LldbRegisterInfoOverride
     for Interpreter<'a, 'b, C>
 {
@@ -18,6 +18,7 @@
 //! <https://www.kernel.org/doc/Documentation/networking/filter.txt>, or for a shorter version of
 //! the list of the operation codes: <https://github.com/iovisor/bpf-docs/blob/master/eBPF.md>
 
+use crate::error::EbpfError;
 use byteorder::{ByteOrder, LittleEndian};
 use hash32::{Hash, Hasher, Murmur3Hasher};
 use std::fmt;
@@ -590,6 +591,32 @@ pub fn get_insn(prog: &[u8], pc: usize) -> Insn {
     );
     get_insn_unchecked(prog, pc)
 }
+
+/// Same as `get_insn` except that it fails instead of panicking if `pc` is out of bounds
+///
+/// ```
+/// use solana_rbpf::{ebpf, error::EbpfError};
+///
+/// let prog = &[
+///     0xb7, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+///     0x95, 0x00, 0x00, 0x00, 0x00, 0x00              // two bytes missing
+///     ];
+/// assert_eq!(ebpf::get_insn_checked(prog, 0).unwrap().opc, 0xb7);
+/// assert!(matches!(
+///     ebpf::get_insn_checked(prog, 1),
+///     Err(EbpfError::InstructionOutOfBounds(1))
+/// ));
+/// ```
+pub fn get_insn_checked(prog: &[u8], pc: usize) -> Result<Insn, EbpfError> {
+    match pc
+        .checked_add(1)
+        .and_then(|count| count.checked_mul(INSN_SIZE))
+    {
+        Some(end) if end <= prog.len() => Ok(get_insn_unchecked(prog, pc)),
+        _ => Err(EbpfError::InstructionOutOfBounds(pc)),
+    }
+}
+
 /// Same as `get_insn` except not checked
 pub fn get_insn_unchecked(prog: &[u8], pc: usize) -> Insn {
     Insn {
@@ -1,36 +1,67 @@
 //! This module relocates a BPF ELF
 
 // Note: Typically ELF shared objects are loaded using the program headers and
-// not the section headers.  Since we are leveraging the elfkit crate its much
-// easier to use the section headers.  There are cases (reduced size, obfuscation)
+// not the section headers.  Since the parser borrows the section headers from
+// the input bytes its much easier to use the section headers.  There are cases (reduced size, obfuscation)
 // where the section headers may be removed from the ELF.  If that happens then
 // this loader will need to be re-written to use the program headers instead.
 
 use crate::{
     aligned_memory::{is_memory_aligned, AlignedMemory},
+    debug_line::{LineTable, SourceLocation},
+    disassembler::disassemble_instruction,
     ebpf::{self, EF_SBPF_V2, HOST_ALIGN, INSN_SIZE},
     elf_parser::{
         consts::{
-            ELFCLASS64, ELFDATA2LSB, ELFOSABI_NONE, EM_BPF, EM_SBPF, ET_DYN, R_X86_64_32,
-            R_X86_64_64, R_X86_64_NONE, R_X86_64_RELATIVE,
+            ELFCLASS64, ELFDATA2LSB, ELFMAG, ELFOSABI_NONE, EM_BPF, EM_SBPF, ET_DYN, EV_CURRENT,
+            R_BPF_64_ABS32, R_BPF_64_ABS64, R_BPF_64_NODYLD32, R_X86_64_32, R_X86_64_64,
+            R_X86_64_NONE, R_X86_64_RELATIVE, SHF_ALLOC, SHF_EXECINSTR, SHN_UNDEF, SHT_PROGBITS,
+            SHT_STRTAB, SHT_SYMTAB,
         },
-        types::{Elf64Phdr, Elf64Shdr, Elf64Word},
+        types::{Elf64Ehdr, Elf64Phdr, Elf64Shdr, Elf64Sym, Elf64Word, ElfIdent},
         Elf64, ElfParserError,
     },
     error::EbpfError,
     memory_region::MemoryRegion,
-    program::{BuiltinProgram, FunctionRegistry, SBPFVersion},
-    verifier::Verifier,
-    vm::{Config, ContextObject},
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
+    static_analysis::CfgNode,
+    verifier::{
+        Verifier, VerifierDiagnostic, VerifierError, VerifierErrorContext, VerifierExemption,
+        VerifierExemptionKind,
+    },
+    vm::{Config, ContextObject, DynamicAnalysis},
 };
+#[cfg(feature = "unstable")]
+use crate::{verification_cache::VerificationCache, verifier::VerifierPipeline};
 
+#[cfg(feature = "threaded-dispatch")]
+use crate::interpreter::{DispatchTable, Handler};
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
 use crate::jit::{JitCompiler, JitProgram};
+#[cfg(all(feature = "mmap", not(target_os = "windows")))]
+use crate::mapped_file::MappedFile;
+#[cfg(all(
+    feature = "unstable",
+    feature = "jit",
+    not(target_os = "windows"),
+    target_arch = "x86_64"
+))]
+use crate::{jit_cache::JitCache, section_allocator::SectionAllocator};
 use byteorder::{ByteOrder, LittleEndian};
-use std::{collections::BTreeMap, fmt::Debug, mem, ops::Range, str, sync::Arc};
+use rustc_demangle::demangle;
+use sha2::{Digest, Sha256};
+#[cfg(any(
+    all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"),
+    feature = "threaded-dispatch"
+))]
+use std::sync::OnceLock;
+use std::{collections::BTreeMap, convert::TryFrom, fmt::Debug, mem, ops::Range, str, sync::Arc};
+#[cfg(all(feature = "mmap", not(target_os = "windows")))]
+use std::{fs::File, path::Path};
 
 /// Error definitions
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum ElfError {
     /// Failed to parse ELF file
     #[error("Failed to parse ELF file: {0}")]
@@ -101,6 +132,51 @@ pub enum ElfError {
     /// Invalid program header
     #[error("Invalid ELF program header")]
     InvalidProgramHeader,
+    /// Malformed or unresolvable note in the .sbpf.verifier section
+    #[error("Invalid note in the .sbpf.verifier section: {0}")]
+    InvalidVerifierNote(String),
+    /// Symbol name exceeds the maximum length
+    #[error("Symbol name ({0}) is longer than {1} bytes")]
+    SymbolNameTooLong(String, usize),
+    /// Function symbol defined at two different instructions
+    #[error("Duplicate function symbol ({0}) at instruction #{1} and #{2}")]
+    DuplicateSymbolName(String, usize, usize),
+    /// Text without any instruction
+    #[error("Text contains no instructions")]
+    EmptyText,
+    /// Text length which is not a multiple of the instruction size
+    #[error("Text length {0} is not a multiple of {} bytes", ebpf::INSN_SIZE)]
+    UnalignedTextLength(usize),
+    /// Text longer than `Config::max_text_length`
+    #[error("Text length {0} exceeds the limit of {1} bytes")]
+    TextTooLong(usize, usize),
+    /// Relocated lddw immediate which points outside of the loaded sections
+    #[error("Relocation ({0}) of lddw at instruction #{1} points to {2:#x}, outside of the loaded sections")]
+    LddwOutsideLoadedSections(String, usize, u64),
+    /// Malformed .sbpf.relocated section
+    #[error("Invalid .sbpf.relocated section: {0}")]
+    InvalidRelocatedSection(String),
+    /// Failed to open or map the file
+    #[error("Failed to read the file: {0}")]
+    FailedToReadFile(String),
+    /// The .text and .text.* sections leave gaps or overlap
+    #[error("Text sections are not contiguous")]
+    NonContiguousTextSections,
+    /// The requested entrypoint is not a function symbol of the ELF
+    #[error("Entrypoint function {0} not found")]
+    EntrypointNotFound(String),
+    /// Section is mapped writable and executable, see `Config::reject_wx_sections`
+    #[error("Section {0} is writable and executable")]
+    WritableAndExecutableSection(String),
+    /// Executable section which is not loaded as text, see `Config::reject_wx_sections`
+    #[error("Section {0} is executable but not a text section")]
+    ExecutableSectionOutsideText(String),
+    /// The input is zstd compressed but could not be decompressed
+    #[error("Failed to decompress the ELF: {0}")]
+    FailedToDecompress(String),
+    /// The instructions and relocations exceed `Config::verification_budget`
+    #[error("Relocating {0} instructions and relocations exceeds the verification budget of {1}")]
+    VerificationBudgetExceeded(usize, usize),
 }
 
 impl From<ElfParserError> for ElfError {
@@ -125,6 +201,86 @@ impl From<ElfParserError> for ElfError {
     }
 }
 
+/// How to load calls to symbols which are neither defined in the ELF nor registered in the loader,
+/// and data references to symbols which are not defined in the ELF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnresolvedSymbolPolicy {
+    /// Fail with [ElfError::UnresolvedSymbol]
+    Error,
+    /// Keep the call, which fails with `EbpfError::UnsupportedInstruction` when executed
+    ///
+    /// Data references get the null address, so that accessing them faults.
+    StubWithTrap,
+    /// Replace the call by an instruction which sets r0 to zero
+    ///
+    /// Data references get the null address, so that loading them yields zero.
+    StubReturningZero,
+}
+
+impl UnresolvedSymbolPolicy {
+    /// The policy implied by `config.reject_broken_elfs`
+    pub fn from_config(config: &Config) -> Self {
+        if config.reject_broken_elfs {
+            Self::Error
+        } else {
+            Self::StubWithTrap
+        }
+    }
+}
+
+/// How to load text bytes whose length is invalid, see `Executable::new_from_text_bytes()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextLengthPolicy {
+    /// Fail with [ElfError::EmptyText], [ElfError::UnalignedTextLength] or [ElfError::TextTooLong]
+    Reject,
+    /// Fill up the last instruction with zeros, cut off at `Config::max_text_length`
+    ///
+    /// Empty text becomes a single zero instruction, which the verifier rejects.
+    Pad,
+    /// Drop the incomplete last instruction, cut off at `Config::max_text_length`
+    ///
+    /// Empty text is still rejected.
+    Truncate,
+}
+
+impl TextLengthPolicy {
+    /// Returns the length which `text_length` is adjusted to
+    pub fn adjusted_length(self, text_length: usize, config: &Config) -> Result<usize, ElfError> {
+        let max_text_length = config
+            .max_text_length
+            .saturating_sub(config.max_text_length % ebpf::INSN_SIZE);
+        let remainder = text_length % ebpf::INSN_SIZE;
+        match self {
+            Self::Reject => {
+                if text_length == 0 {
+                    Err(ElfError::EmptyText)
+                } else if remainder != 0 {
+                    Err(ElfError::UnalignedTextLength(text_length))
+                } else if text_length > config.max_text_length {
+                    Err(ElfError::TextTooLong(text_length, config.max_text_length))
+                } else {
+                    Ok(text_length)
+                }
+            }
+            Self::Pad => {
+                let padded_length = if remainder == 0 {
+                    text_length.max(ebpf::INSN_SIZE)
+                } else {
+                    text_length.saturating_add(ebpf::INSN_SIZE.saturating_sub(remainder))
+                };
+                match padded_length.min(max_text_length) {
+                    0 => Err(ElfError::EmptyText),
+                    length => Ok(length),
+                }
+            }
+            Self::Truncate => match text_length.saturating_sub(remainder).min(max_text_length) {
+                0 => Err(ElfError::EmptyText),
+                length => Ok(length),
+            },
+        }
+    }
+}
+
 fn get_section(elf: &Elf64, name: &[u8]) -> Result<Elf64Shdr, ElfError> {
     for section_header in elf.section_header_table() {
         if elf.section_name(section_header.sh_name)? == name {
@@ -139,6 +295,224 @@ fn get_section(elf: &Elf64, name: &[u8]) -> Result<Elf64Shdr, ElfError> {
     ))
 }
 
+/// Version of the encoding which `Executable::hash()` digests, bumped whenever that changes
+const HASH_ENCODING_VERSION: u8 = 1;
+
+/// Magic number at the start of a zstd frame
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Upper bound of the size of a decompressed ELF, guards against decompression bombs
+#[cfg(feature = "zstd")]
+const MAX_DECOMPRESSED_ELF_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Returns a copy of the input if it has to be decompressed or realigned first
+///
+/// The new parser creates references from the input byte slice, so it must be properly
+/// aligned. We assume that HOST_ALIGN is a multiple of the ELF "natural" alignment. See
+/// test_load_unaligned.
+fn prepare_input(bytes: &[u8]) -> Result<Option<AlignedMemory<HOST_ALIGN>>, ElfError> {
+    #[cfg(feature = "zstd")]
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        use std::io::Read;
+        let mut source = bytes;
+        let decoder = ruzstd::decoding::StreamingDecoder::new(&mut source)
+            .map_err(|err| ElfError::FailedToDecompress(err.to_string()))?;
+        let mut decompressed = Vec::new();
+        decoder
+            .take(MAX_DECOMPRESSED_ELF_SIZE.saturating_add(1))
+            .read_to_end(&mut decompressed)
+            .map_err(|err| ElfError::FailedToDecompress(err.to_string()))?;
+        if decompressed.len() as u64 > MAX_DECOMPRESSED_ELF_SIZE {
+            return Err(ElfError::FailedToDecompress(format!(
+                "exceeds {MAX_DECOMPRESSED_ELF_SIZE} bytes"
+            )));
+        }
+        return Ok(Some(AlignedMemory::from_slice(&decompressed)));
+    }
+    if is_memory_aligned(bytes.as_ptr() as usize, HOST_ALIGN) {
+        Ok(None)
+    } else {
+        Ok(Some(AlignedMemory::from_slice(bytes)))
+    }
+}
+
+fn is_text_section_name(name: &[u8]) -> bool {
+    name == b".text" || name.starts_with(b".text.")
+}
+
+/// Whether the section is part of the read-only region, see `Executable::parse_ro_sections()`
+fn is_ro_section_name(config: &Config, name: &[u8]) -> bool {
+    name == b".text"
+        || (config.merge_text_sections && name.starts_with(b".text."))
+        || name == b".rodata"
+        || name == b".data.rel.ro"
+        || name == b".eh_frame"
+}
+
+/// Returns the .text section, merged with the .text.* sections if `config.merge_text_sections`
+fn get_text_section(config: &Config, elf: &Elf64) -> Result<Elf64Shdr, ElfError> {
+    if !config.merge_text_sections {
+        return get_section(elf, b".text");
+    }
+    let mut text_sections = elf
+        .section_header_table()
+        .iter()
+        .filter(|section_header| {
+            elf.section_name(section_header.sh_name)
+                .is_ok_and(is_text_section_name)
+        })
+        .collect::<Vec<_>>();
+    // Empty sections, e.g. a .text section without any functions, could be anywhere
+    if text_sections
+        .iter()
+        .any(|section_header| section_header.sh_size > 0)
+    {
+        text_sections.retain(|section_header| section_header.sh_size > 0);
+    }
+    text_sections.sort_by_key(|section_header| section_header.sh_addr);
+    let (first, rest) = text_sections
+        .split_first()
+        .ok_or_else(|| ElfError::SectionNotFound(".text".to_string()))?;
+    let mut text_section = (*first).clone();
+    for section_header in rest {
+        if section_header.sh_addr != text_section.vm_range().end
+            || section_header.sh_offset
+                != text_section.sh_offset.saturating_add(text_section.sh_size)
+        {
+            return Err(ElfError::NonContiguousTextSections);
+        }
+        text_section.sh_size = text_section.sh_size.saturating_add(section_header.sh_size);
+    }
+    Ok(text_section)
+}
+
+/// Copies the ELF for relocation, except for sections which are only read while loading
+///
+/// Sections which are neither loaded nor needed to look up symbols later on, e.g. debug
+/// information, stay zero-filled so that their memory is never touched and all offsets
+/// remain valid. Sections which relocations could patch are always copied.
+fn copy_retained_sections(elf: &Elf64, elf_bytes: &[u8]) -> AlignedMemory<{ HOST_ALIGN }> {
+    let patched_ranges = elf
+        .dynamic_relocations_table()
+        .unwrap_or(&[])
+        .iter()
+        .map(|relocation| {
+            let r_offset = relocation.r_offset as usize;
+            r_offset..r_offset.saturating_add(ebpf::INSN_SIZE.saturating_mul(2))
+        })
+        .chain(elf.program_header_table().iter().map(|program_header| {
+            let p_offset = program_header.p_offset as usize;
+            p_offset
+                ..p_offset
+                    .saturating_add(program_header.p_filesz.max(program_header.p_memsz) as usize)
+        }))
+        .collect::<Vec<_>>();
+    let overlaps = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+    let skipped_ranges = elf
+        .section_header_table()
+        .iter()
+        .filter(|section_header| {
+            section_header.sh_flags & SHF_ALLOC == 0
+                && section_header.sh_type != SHT_SYMTAB
+                && section_header.sh_type != SHT_STRTAB
+                && !elf.section_name(section_header.sh_name).is_ok_and(|name| {
+                    is_text_section_name(name)
+                        || matches!(name, b".rodata" | b".data.rel.ro" | b".eh_frame")
+                })
+                && !elf
+                    .section_name(section_header.sh_name)
+                    .is_ok_and(|name| name.starts_with(b".sbpf."))
+        })
+        .filter_map(|section_header| section_header.file_range())
+        .filter(|range| {
+            !patched_ranges
+                .iter()
+                .any(|patched| overlaps(patched, range))
+        });
+    // The parser ensures that the sections are in file order and don't overlap
+    let mut copy = AlignedMemory::zero_filled(elf_bytes.len());
+    let mut offset = 0;
+    for range in skipped_ranges.chain(std::iter::once(elf_bytes.len()..elf_bytes.len())) {
+        if let (Some(source), Some(destination)) = (
+            elf_bytes.get(offset..range.start),
+            copy.as_slice_mut().get_mut(offset..range.start),
+        ) {
+            destination.copy_from_slice(source);
+        }
+        offset = range.end;
+    }
+    copy
+}
+
+/// Distinguishes names cut off by the length limit of the parser from unknown symbols
+fn symbol_name_error(err: ElfParserError, st_name: Elf64Word) -> ElfError {
+    match err {
+        ElfParserError::StringTooLong(prefix, maximum_length) => {
+            ElfError::SymbolNameTooLong(prefix, maximum_length)
+        }
+        _ => ElfError::UnknownSymbol(st_name as usize),
+    }
+}
+
+/// Address of a data reference to a symbol which the ELF does not define, see
+/// `UnresolvedSymbolPolicy`
+///
+/// Returns None if the reference is collected as unresolved instead.
+fn resolve_undefined_data_symbol(
+    elf: &Elf64,
+    symbol: &Elf64Sym,
+    r_offset: usize,
+    unresolved_symbol_policy: UnresolvedSymbolPolicy,
+    unresolved_symbols: &mut Vec<ElfError>,
+) -> Result<Option<u64>, ElfError> {
+    match unresolved_symbol_policy {
+        UnresolvedSymbolPolicy::Error => {
+            let name = elf
+                .dynamic_symbol_name(symbol.st_name as Elf64Word)
+                .map_err(|err| symbol_name_error(err, symbol.st_name))?;
+            unresolved_symbols.push(ElfError::UnresolvedSymbol(
+                String::from_utf8_lossy(name).to_string(),
+                r_offset.checked_div(ebpf::INSN_SIZE).unwrap_or(0),
+                r_offset,
+            ));
+            Ok(None)
+        }
+        UnresolvedSymbolPolicy::StubWithTrap | UnresolvedSymbolPolicy::StubReturningZero => {
+            Ok(Some(0))
+        }
+    }
+}
+
+/// Rejects .dynsym function names which are defined at more than one instruction
+///
+/// Otherwise the symbols would share one name in the function registry and lookups by name
+/// would resolve to either of them.
+fn check_dynamic_function_names(elf: &Elf64, text_section: &Elf64Shdr) -> Result<(), ElfError> {
+    let mut defined_functions = BTreeMap::new();
+    for symbol in elf.dynamic_symbol_table().unwrap_or(&[]) {
+        if !symbol.is_function() || symbol.st_value == 0 {
+            continue;
+        }
+        let name = elf
+            .dynamic_symbol_name(symbol.st_name)
+            .map_err(|err| symbol_name_error(err, symbol.st_name))?;
+        let target_pc = (symbol.st_value.saturating_sub(text_section.sh_addr) as usize)
+            .checked_div(ebpf::INSN_SIZE)
+            .unwrap_or_default();
+        match defined_functions.insert(name, target_pc) {
+            Some(previous_pc) if previous_pc != target_pc => {
+                return Err(ElfError::DuplicateSymbolName(
+                    String::from_utf8_lossy(name).to_string(),
+                    previous_pc,
+                    target_pc,
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 // For more information on the BPF instruction set:
 // https://github.com/iovisor/bpf-docs/blob/master/eBPF.md
 
@@ -171,6 +545,18 @@ enum BpfRelocationType {
     /// (4 bytes). The relocation can be resolved with the symbol
     /// value plus implicit addend.
     R_Bpf_64_64 = 1,
+    /// 64 bit absolute relocation of a data word, emitted by newer
+    /// LLVM versions for pointers stored in data sections. The
+    /// implicit addend is the 64-bit value at r_offset and the
+    /// relocation is resolved with the symbol value plus addend.
+    R_Bpf_64_Abs64 = 2,
+    /// 32 bit absolute relocation, used for debug information. The
+    /// VM addresses do not fit into 32 bits, so it can only be
+    /// ignored if it targets a section which is not loaded.
+    R_Bpf_64_Abs32 = 3,
+    /// 32 bit relocation which is only meant to be resolved by the
+    /// static linker (e.g. in .BTF sections), ignored when loading.
+    R_Bpf_64_NoDyld32 = 4,
     /// 64 bit relocation of a ldxdw instruction.  The ldxdw
     /// instruction occupies two instruction slots. The 64-bit address
     /// to load from is split into the 32-bit imm field of each
@@ -200,6 +586,9 @@ impl BpfRelocationType {
         match from {
             R_X86_64_NONE => Some(BpfRelocationType::R_Bpf_None),
             R_X86_64_64 => Some(BpfRelocationType::R_Bpf_64_64),
+            R_BPF_64_ABS64 => Some(BpfRelocationType::R_Bpf_64_Abs64),
+            R_BPF_64_ABS32 => Some(BpfRelocationType::R_Bpf_64_Abs32),
+            R_BPF_64_NODYLD32 => Some(BpfRelocationType::R_Bpf_64_NoDyld32),
             R_X86_64_RELATIVE => Some(BpfRelocationType::R_Bpf_64_Relative),
             R_X86_64_32 => Some(BpfRelocationType::R_Bpf_64_32),
             _ => None,
@@ -234,11 +623,117 @@ pub(crate) enum Section {
     Borrowed(usize, Range<usize>),
 }
 
+/// Storage of the loaded ELF
+#[derive(Debug)]
+enum ElfBytes {
+    /// Copied from the input, see `copy_retained_sections()`
+    Copied(AlignedMemory<{ HOST_ALIGN }>),
+    /// Copy-on-write mapping of the file, see `Executable::load_file()`
+    #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+    Mapped(MappedFile),
+}
+
+impl ElfBytes {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Copied(elf_bytes) => elf_bytes.as_slice(),
+            #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+            Self::Mapped(mapped_file) => mapped_file.as_slice(),
+        }
+    }
+
+    fn as_slice_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::Copied(elf_bytes) => elf_bytes.as_slice_mut(),
+            #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+            Self::Mapped(mapped_file) => mapped_file.as_slice_mut(),
+        }
+    }
+
+    fn mem_size(&self) -> usize {
+        match self {
+            Self::Copied(elf_bytes) => elf_bytes.mem_size(),
+            // Only the pages patched by the relocation are private, the others can be
+            // shared with the page cache
+            #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+            Self::Mapped(mapped_file) => mapped_file.as_slice().len(),
+        }
+    }
+
+    /// Makes a mapping read-only once it is relocated
+    fn protect(&mut self) -> Result<(), ElfError> {
+        match self {
+            Self::Copied(_) => Ok(()),
+            #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+            Self::Mapped(mapped_file) => mapped_file
+                .protect()
+                .map_err(|err| ElfError::FailedToReadFile(err.to_string())),
+        }
+    }
+}
+
+impl PartialEq for ElfBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+/// A mismatch between an executable and the Config it is run with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// The SBPF version of the executable is not enabled
+    SBPFVersionNotEnabled(SBPFVersion),
+    /// The instruction at `pc` calls a syscall which the loader does not register
+    MissingSyscall {
+        /// Location of the call instruction
+        pc: usize,
+        /// Hash of the syscall name
+        key: u32,
+    },
+}
+
+/// Function of an executable, see `Executable::get_function_symbols()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSymbol {
+    /// Symbol name
+    pub name: String,
+    /// First instruction
+    pub pc: usize,
+    /// Number of instructions up to the next function or the end of the text section
+    pub size: usize,
+}
+
+/// Object in the read-only sections of an executable, see `Executable::get_data_symbols()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSymbol {
+    /// Symbol name
+    pub name: String,
+    /// Virtual address of the first byte
+    pub vm_addr: u64,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Region of the virtual address space of a program, see `Executable::memory_layout()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutRegion {
+    /// Name of the section the region is loaded from, or `[stack]`, `[heap]` and `[input]`
+    pub name: String,
+    /// Virtual address of the first byte
+    pub vm_addr: u64,
+    /// Length of the address range in bytes
+    pub len: u64,
+    /// Whether the program can store to the region
+    pub writable: bool,
+    /// Whether the region holds instructions of the program
+    pub executable: bool,
+}
+
 /// Elf loader/relocator
 #[derive(Debug, PartialEq)]
 pub struct Executable<C: ContextObject> {
     /// Loaded and executable elf
-    elf_bytes: AlignedMemory<{ HOST_ALIGN }>,
+    elf_bytes: ElfBytes,
     /// Required SBPF capabilities
     sbpf_version: SBPFVersion,
     /// Read-only section
@@ -251,9 +746,26 @@ pub struct Executable<C: ContextObject> {
     function_registry: FunctionRegistry<usize>,
     /// Loader built-in program
     loader: Arc<BuiltinProgram<C>>,
+    /// How calls to symbols which the loader does not register were loaded
+    unresolved_symbol_policy: UnresolvedSymbolPolicy,
+    /// Syscall implementations which replace those of the loader
+    rebound_syscalls: BTreeMap<u32, BuiltinFunction<C>>,
+    /// Exemptions requested by the .sbpf.verifier section
+    verifier_exemptions: Vec<VerifierExemption>,
+    /// Ranges of pcs generated by the host, which the verifier skips
+    pre_verified_ranges: Vec<Range<usize>>,
+    /// Edge counters recorded by a previous execution, read from the .sbpf.profile section
+    profile: Option<DynamicAnalysis>,
+    /// Source locations by pc, read from the .debug_line section
+    source_lines: Option<LineTable>,
     /// Compiled program and argument
     #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
-    compiled_program: Option<JitProgram>,
+    compiled_program: OnceLock<Arc<JitProgram>>,
+    /// Text section decoded once for the threaded dispatch of the interpreter
+    #[cfg(feature = "threaded-dispatch")]
+    threaded_instructions: OnceLock<Vec<(Handler<C>, ebpf::Insn)>>,
+    /// Result of `check_compatibility()` for the config of the loader
+    loader_incompatibilities: OnceLock<Vec<Incompatibility>>,
 }
 
 impl<C: ContextObject> Executable<C> {
@@ -302,6 +814,55 @@ impl<C: ContextObject> Executable<C> {
         get_ro_region(&self.ro_section, self.elf_bytes.as_slice())
     }
 
+    /// Stable digest of the loaded program image
+    ///
+    /// Covers the SBPF version, the relocated read-only sections including the text section, the
+    /// entrypoint, the keys and pcs of the function registry and the settings of the Config which
+    /// change how the program behaves. It is a SHA-256 of an explicit encoding (see
+    /// `HASH_ENCODING_VERSION`) which does not depend on the host, so it can be compared between
+    /// validators to check that they loaded identical programs. Host-local settings like tracing,
+    /// the JIT code generation or statistics are not part of it, neither are the syscalls, only
+    /// their keys which the relocated text section refers to.
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        let mut update = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        let config = self.get_config();
+        update(&[HASH_ENCODING_VERSION]);
+        update(&[match self.sbpf_version {
+            SBPFVersion::V1 => 1,
+            SBPFVersion::V2 => 2,
+            SBPFVersion::V3 => 3,
+        }]);
+        update(&(config.max_call_depth as u64).to_le_bytes());
+        update(&(config.stack_frame_size as u64).to_le_bytes());
+        update(
+            &config
+                .stack_frame_gap_size(&self.sbpf_version)
+                .to_le_bytes(),
+        );
+        update(&[
+            config.enable_address_translation as u8,
+            config.enable_instruction_meter as u8,
+            config.aligned_memory_mapping as u8,
+            config.zero_stack_frames as u8,
+            config.zero_memory_on_reset as u8,
+        ]);
+        update(&self.get_ro_region().vm_addr.to_le_bytes());
+        update(self.get_ro_section());
+        let (text_vaddr, text_bytes) = self.get_text_bytes();
+        update(&text_vaddr.to_le_bytes());
+        update(&(text_bytes.len() as u64).to_le_bytes());
+        update(&(self.entry_pc as u64).to_le_bytes());
+        for (key, (_name, pc)) in self.function_registry.iter() {
+            update(&key.to_le_bytes());
+            update(&(pc as u64).to_le_bytes());
+        }
+        hasher.finalize().into()
+    }
+
     /// Get the entry point offset into the text section
     pub fn get_entrypoint_instruction_offset(&self) -> usize {
         self.entry_pc
@@ -318,46 +879,570 @@ impl<C: ContextObject> Executable<C> {
         &self.loader
     }
 
+    /// Get the implementation of a syscall, taking `rebind_syscall()` into account
+    ///
+    /// If the executable is JIT compiled, this is the implementation the compiled program calls,
+    /// so that `JitProgram::rebind_syscall()` applies to the interpreter as well.
+    pub fn lookup_syscall(&self, key: u32) -> Option<BuiltinFunction<C>> {
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if let Some(host_address) = self
+            .compiled_program
+            .get()
+            .and_then(|compiled_program| compiled_program.lookup_syscall(key))
+        {
+            // The program was compiled for C, so its syscall table holds BuiltinFunction<C>
+            return Some(unsafe { std::mem::transmute::<usize, BuiltinFunction<C>>(host_address) });
+        }
+        self.rebound_syscalls.get(&key).copied().or_else(|| {
+            self.loader
+                .get_function_registry()
+                .lookup_by_key(key)
+                .map(|(_name, function)| function)
+        })
+    }
+
+    /// Replaces the implementation of a syscall which the loader registers
+    ///
+    /// This also applies to the compiled program, which calls syscalls through a table, so
+    /// implementations can be swapped between executions without compiling again. A compiled
+    /// program which is shared through a `JitCache` is released instead and has to be compiled
+    /// again, use `JitProgram::rebind_syscall()` to swap the syscall for all of its users.
+    pub fn rebind_syscall(
+        &mut self,
+        key: u32,
+        function: BuiltinFunction<C>,
+    ) -> Result<(), EbpfError> {
+        if self
+            .loader
+            .get_function_registry()
+            .lookup_by_key(key)
+            .is_none()
+        {
+            return Err(EbpfError::SyscallNotRegistered(key));
+        }
+        self.rebound_syscalls.insert(key, function);
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if let Some(compiled_program) = self.compiled_program.get_mut() {
+            if let Some(compiled_program) = Arc::get_mut(compiled_program) {
+                compiled_program.bind_syscall(key, function as usize);
+            } else {
+                // Shared through a JitCache, so it has to be compiled again
+                self.compiled_program = OnceLock::new();
+            }
+        }
+        Ok(())
+    }
+
     /// Get the JIT compiled program
     #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
     pub fn get_compiled_program(&self) -> Option<&JitProgram> {
-        self.compiled_program.as_ref()
+        self.compiled_program.get().map(Arc::as_ref)
+    }
+
+    /// Get the instructions of the text section with their interpreter handlers
+    ///
+    /// They are decoded on the first call, so that later executions do not have to.
+    #[cfg(feature = "threaded-dispatch")]
+    pub(crate) fn get_threaded_instructions(&self) -> &[(Handler<C>, ebpf::Insn)] {
+        self.threaded_instructions.get_or_init(|| {
+            let (_vaddr, text_bytes) = self.get_text_bytes();
+            (0..text_bytes.len() / ebpf::INSN_SIZE)
+                .map(|pc| {
+                    let insn = ebpf::get_insn_unchecked(text_bytes, pc);
+                    (DispatchTable::<C>::HANDLERS[insn.opc as usize], insn)
+                })
+                .collect()
+        })
+    }
+
+    /// Get the exemptions requested by the .sbpf.verifier section
+    pub fn get_verifier_exemptions(&self) -> &[VerifierExemption] {
+        &self.verifier_exemptions
+    }
+
+    /// Requests an exemption on behalf of the host, e.g. after `load_relocated()`
+    ///
+    /// Like the ones of the .sbpf.verifier section it is only granted if the host policy
+    /// (`Config::verifier_exemption_policy`) allows it.
+    pub fn request_verifier_exemption(&mut self, exemption: VerifierExemption) {
+        self.verifier_exemptions.push(exemption);
+    }
+
+    /// Get the edge counters of the .sbpf.profile section
+    pub fn get_profile(&self) -> Option<&DynamicAnalysis> {
+        self.profile.as_ref()
+    }
+
+    /// Get the file and line the instruction at `pc` was compiled from
+    ///
+    /// Only available if the ELF has a .debug_line section.
+    pub fn get_source_location(&self, pc: usize) -> Option<&SourceLocation> {
+        self.source_lines
+            .as_ref()
+            .and_then(|source_lines| source_lines.lookup(pc as u64))
+    }
+
+    /// Locates the instruction a verifier error refers to
+    ///
+    /// Returns None if the error does not refer to an instruction of the text section.
+    pub fn verifier_error_context(&self, error: &VerifierError) -> Option<VerifierErrorContext> {
+        let pc = error.pc()?;
+        let instruction = self.disassemble_at(pc)?;
+        let function_name = self
+            .get_function_symbols()
+            .into_iter()
+            .find(|symbol| {
+                !symbol.name.is_empty()
+                    && (symbol.pc..symbol.pc.saturating_add(symbol.size)).contains(&pc)
+            })
+            .map(|symbol| demangle(&symbol.name).to_string());
+        Some(VerifierErrorContext {
+            pc,
+            byte_offset: pc.saturating_mul(ebpf::INSN_SIZE),
+            instruction,
+            function_name,
+        })
+    }
+
+    /// Describes a verifier error in a machine-readable way, including the function it is in
+    pub fn verifier_diagnostic(&self, error: &VerifierError) -> VerifierDiagnostic {
+        VerifierDiagnostic {
+            function: self
+                .verifier_error_context(error)
+                .and_then(|context| context.function_name),
+            ..VerifierDiagnostic::from(error)
+        }
+    }
+
+    /// Renders the instructions up to `radius` slots around `pc`, marking the one at `pc`
+    ///
+    /// Every line holds the pc and the disassembled instruction, the second slot of a lddw is
+    /// rendered on its own as it can be the target of a broken jump.
+    pub fn render_snippet(&self, pc: usize, radius: usize) -> String {
+        let mut snippet = String::new();
+        for line_pc in pc.saturating_sub(radius)..=pc.saturating_add(radius) {
+            let Some(instruction) = self.disassemble_at(line_pc) else {
+                break;
+            };
+            let marker = if line_pc == pc { '>' } else { ' ' };
+            snippet.push_str(&format!("{marker} {line_pc:6}  {instruction}\n"));
+        }
+        snippet
+    }
+
+    /// Disassembles the instruction at `pc` without analyzing the control flow
+    fn disassemble_at(&self, pc: usize) -> Option<String> {
+        let text_bytes = self.get_text_bytes().1;
+        let mut insn = ebpf::get_insn_checked(text_bytes, pc).ok()?;
+        if insn.opc == ebpf::LD_DW_IMM
+            && ebpf::get_insn_checked(text_bytes, pc.saturating_add(1)).is_ok()
+        {
+            ebpf::augment_lddw_unchecked(text_bytes, &mut insn);
+        }
+        // Labels for the function starts and the jump target, which the disassembler resolves
+        let mut cfg_nodes = BTreeMap::new();
+        for (_key, (name, function_pc)) in self.function_registry.iter() {
+            cfg_nodes.insert(
+                function_pc,
+                CfgNode {
+                    label: demangle(&String::from_utf8_lossy(name)).to_string(),
+                    ..CfgNode::default()
+                },
+            );
+        }
+        let target_pc = (pc as i64)
+            .saturating_add(insn.off as i64)
+            .saturating_add(1) as usize;
+        cfg_nodes.entry(target_pc).or_insert_with(|| CfgNode {
+            label: format!("lbb_{target_pc}"),
+            ..CfgNode::default()
+        });
+        Some(disassemble_instruction(
+            &insn,
+            &cfg_nodes,
+            &self.function_registry,
+            &self.loader,
+            &self.sbpf_version,
+        ))
+    }
+
+    /// Get the ranges of pcs which were marked as pre-verified
+    pub fn get_pre_verified_ranges(&self) -> &[Range<usize>] {
+        &self.pre_verified_ranges
+    }
+
+    /// Marks a range of pcs as verified already, so that `verify()` skips it
+    ///
+    /// This is meant for trusted code which the host appended to the program itself,
+    /// such as a standardized prologue. Never mark code provided by the user.
+    pub fn mark_pre_verified(&mut self, pcs: Range<usize>) {
+        self.pre_verified_ranges.push(pcs);
     }
 
     /// Verify the executable
+    ///
+    /// Fails if the host policy (`Config::verifier_exemption_policy`) denies any of the
+    /// requested exemptions, otherwise the verifier is relaxed by all of them.
+    /// Instructions marked by `mark_pre_verified()` are not checked.
     pub fn verify<V: Verifier>(&self) -> Result<(), EbpfError> {
-        <V as Verifier>::verify(
+        let policy = &self.get_config().verifier_exemption_policy;
+        if let Some(denied) = self
+            .verifier_exemptions
+            .iter()
+            .find(|exemption| !policy.allows(exemption))
+        {
+            return Err(VerifierError::ExemptionDenied(
+                denied.kind,
+                denied.function_name.clone(),
+                denied.pc,
+            )
+            .into());
+        }
+        <V as Verifier>::verify_with_pre_verified_ranges(
             self.get_text_bytes().1,
             self.get_config(),
             self.get_sbpf_version(),
             self.get_function_registry(),
+            &self.verifier_exemptions,
+            &self.pre_verified_ranges,
         )?;
         Ok(())
     }
 
+    /// Verify the executable unless an equivalent one passed the same verifier before
+    #[cfg(feature = "unstable")]
+    pub fn verify_cached<V: Verifier>(&self, cache: &VerificationCache) -> Result<(), EbpfError> {
+        cache.get_or_verify::<V, C>(self)
+    }
+
+    /// Verify the executable, followed by the additional passes of the pipeline
+    #[cfg(feature = "unstable")]
+    pub fn verify_with_pipeline<V: Verifier>(
+        &self,
+        pipeline: &VerifierPipeline,
+    ) -> Result<(), EbpfError> {
+        self.verify::<V>()?;
+        pipeline.verify(
+            self.get_text_bytes().1,
+            self.get_config(),
+            self.get_sbpf_version(),
+            self.get_function_registry(),
+        )?;
+        Ok(())
+    }
+
+    /// Lists everything which prevents the executable from running with the given config
+    ///
+    /// `EbpfVm::new()` checks this, so that a mismatch fails up front instead of as an
+    /// unsupported instruction in the middle of the execution. Calls to missing syscalls are
+    /// only reported if unresolved symbols were loaded with `UnresolvedSymbolPolicy::Error`,
+    /// as the other policies deliberately keep them for the runtime.
+    pub fn check_compatibility(&self, config: &Config) -> Vec<Incompatibility> {
+        let mut incompatibilities = Vec::new();
+        let sbpf_version_enabled = match self.sbpf_version {
+            SBPFVersion::V1 => config.enable_sbpf_v1,
+            SBPFVersion::V2 => config.enable_sbpf_v2,
+            // V3 is not finalized yet, so no config can enable it
+            SBPFVersion::V3 => false,
+        };
+        if !sbpf_version_enabled {
+            incompatibilities.push(Incompatibility::SBPFVersionNotEnabled(
+                self.sbpf_version.clone(),
+            ));
+        }
+        if self.unresolved_symbol_policy != UnresolvedSymbolPolicy::Error {
+            return incompatibilities;
+        }
+        let static_syscalls = self.sbpf_version.static_syscalls();
+        let (_program_vm_addr, text_bytes) = self.get_text_bytes();
+        let mut pc = 0usize;
+        while pc.saturating_add(1).saturating_mul(INSN_SIZE) <= text_bytes.len() {
+            let insn = ebpf::get_insn_unchecked(text_bytes, pc);
+            if insn.opc == ebpf::CALL_IMM && (!static_syscalls || insn.src == 0) {
+                let key = insn.imm as u32;
+                let internal =
+                    !static_syscalls && self.function_registry.lookup_by_key(key).is_some();
+                if !internal
+                    && self
+                        .loader
+                        .get_function_registry()
+                        .lookup_by_key(key)
+                        .is_none()
+                {
+                    incompatibilities.push(Incompatibility::MissingSyscall { pc, key });
+                }
+            }
+            pc = pc.saturating_add(if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 });
+        }
+        incompatibilities
+    }
+
+    /// Lists everything which prevents the executable from running with the config of its loader
+    ///
+    /// This is `check_compatibility()` for `get_config()`, which only scans the text section on
+    /// the first call, as neither of them changes afterwards.
+    pub(crate) fn get_loader_incompatibilities(&self) -> &[Incompatibility] {
+        self.loader_incompatibilities
+            .get_or_init(|| self.check_compatibility(self.get_config()))
+    }
+
     /// JIT compile the executable
     #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
     pub fn jit_compile(&mut self) -> Result<(), crate::error::EbpfError> {
         let jit = JitCompiler::<C>::new(self)?;
-        self.compiled_program = Some(jit.compile()?);
+        self.compiled_program = OnceLock::from(Arc::new(jit.compile()?));
+        Ok(())
+    }
+
+    /// JIT compile the executable into memory provided by `allocator`
+    #[cfg(all(
+        feature = "unstable",
+        feature = "jit",
+        not(target_os = "windows"),
+        target_arch = "x86_64"
+    ))]
+    pub fn jit_compile_with_allocator(
+        &mut self,
+        allocator: Arc<dyn SectionAllocator>,
+    ) -> Result<(), crate::error::EbpfError> {
+        let jit = JitCompiler::<C>::new_with_allocator(self, allocator)?;
+        self.compiled_program = OnceLock::from(Arc::new(jit.compile()?));
         Ok(())
     }
 
+    /// JIT compile the executable or reuse the compiled program of an equivalent one
+    #[cfg(all(
+        feature = "unstable",
+        feature = "jit",
+        not(target_os = "windows"),
+        target_arch = "x86_64"
+    ))]
+    pub fn jit_compile_cached(&mut self, cache: &JitCache) -> Result<(), crate::error::EbpfError>
+    where
+        C: 'static,
+    {
+        self.compiled_program = OnceLock::from(cache.get_or_compile(self)?);
+        Ok(())
+    }
+
+    /// JIT compile the executable unless it was compiled already
+    ///
+    /// Unlike `jit_compile()` this works on a shared executable, e.g. while VMs borrow it.
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn jit_compile_once(&self) -> Result<&JitProgram, crate::error::EbpfError> {
+        if let Some(compiled_program) = self.compiled_program.get() {
+            return Ok(compiled_program);
+        }
+        let compiled_program = JitCompiler::<C>::new(self)?.compile()?;
+        // If another thread was faster, its compilation is used and this one is dropped
+        Ok(self
+            .compiled_program
+            .get_or_init(|| Arc::new(compiled_program)))
+    }
+
     /// Get the function registry
     pub fn get_function_registry(&self) -> &FunctionRegistry<usize> {
         &self.function_registry
     }
 
+    /// Lists the resolved functions ordered by pc
+    ///
+    /// These are the entries of the function registry, so several names can share one pc.
+    /// Without `Config::enable_symbol_and_section_labels` the registry only keeps the name of the
+    /// entrypoint, the others are then taken from the symbol tables of the ELF if it has one.
+    pub fn get_function_symbols(&self) -> Vec<FunctionSymbol> {
+        let (text_vaddr, text_bytes) = self.get_text_bytes();
+        let instruction_count = text_bytes.len() / ebpf::INSN_SIZE;
+        let elf_names = self
+            .elf_symbols()
+            .into_iter()
+            .filter(|(_name, symbol)| symbol.is_function())
+            .filter_map(|(name, symbol)| {
+                let pc = (Self::elf_vm_addr(symbol.st_value).checked_sub(text_vaddr)? as usize)
+                    .checked_div(ebpf::INSN_SIZE)?;
+                Some((pc, name))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut symbols = self
+            .function_registry
+            .iter()
+            .map(|(_key, (name, pc))| FunctionSymbol {
+                name: String::from_utf8_lossy(if name.is_empty() {
+                    elf_names.get(&pc).copied().unwrap_or_default()
+                } else {
+                    name
+                })
+                .to_string(),
+                pc,
+                size: 0,
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by(|a, b| (a.pc, &a.name).cmp(&(b.pc, &b.name)));
+        let starts = symbols.iter().map(|symbol| symbol.pc).collect::<Vec<_>>();
+        for symbol in symbols.iter_mut() {
+            let end = starts
+                .iter()
+                .find(|pc| **pc > symbol.pc)
+                .copied()
+                .unwrap_or(instruction_count);
+            symbol.size = end.saturating_sub(symbol.pc);
+        }
+        symbols
+    }
+
+    /// Lists the objects of the read-only sections ordered by address
+    ///
+    /// They are taken from the symbol tables of the ELF, so an executable created from text
+    /// bytes or loaded with `load_relocated()` has none.
+    pub fn get_data_symbols(&self) -> Vec<DataSymbol> {
+        let ro_region = self.get_ro_region();
+        let ro_range = ro_region.vm_addr..ro_region.vm_addr.saturating_add(ro_region.len);
+        let mut symbols = self
+            .elf_symbols()
+            .into_iter()
+            .filter(|(_name, symbol)| symbol.is_object())
+            .map(|(name, symbol)| DataSymbol {
+                name: String::from_utf8_lossy(name).to_string(),
+                vm_addr: Self::elf_vm_addr(symbol.st_value),
+                size: symbol.st_size,
+            })
+            .filter(|symbol| ro_range.contains(&symbol.vm_addr))
+            .collect::<Vec<_>>();
+        symbols.sort_by(|a, b| (a.vm_addr, &a.name).cmp(&(b.vm_addr, &b.name)));
+        // The same symbol can be in both .symtab and .dynsym
+        symbols.dedup();
+        symbols
+    }
+
+    /// Lists the regions of the virtual address space of the program ordered by address
+    ///
+    /// The loaded sections are taken from the section headers of the ELF, so an executable
+    /// created from text bytes only has its text section. The heap and the input are provided
+    /// when the program is executed, so they are only placeholders of length zero.
+    pub fn memory_layout(&self) -> Vec<LayoutRegion> {
+        let config = self.get_config();
+        let (text_vaddr, text_bytes) = self.get_text_bytes();
+        let text_range = text_vaddr..text_vaddr.saturating_add(text_bytes.len() as u64);
+        let section_region = |name: &[u8], vm_addr: u64, len: u64| LayoutRegion {
+            name: String::from_utf8_lossy(name).to_string(),
+            vm_addr,
+            len,
+            writable: false,
+            executable: text_range.contains(&vm_addr),
+        };
+        let mut layout = match Elf64::parse(self.elf_bytes.as_slice()) {
+            Ok(elf) => elf
+                .section_header_table()
+                .iter()
+                .filter_map(|section_header| {
+                    let name = elf.section_name(section_header.sh_name).ok()?;
+                    is_ro_section_name(config, name).then(|| {
+                        section_region(
+                            name,
+                            Self::elf_vm_addr(section_header.sh_addr),
+                            section_header.sh_size,
+                        )
+                    })
+                })
+                .collect(),
+            Err(_) => vec![section_region(
+                b".text",
+                text_range.start,
+                text_bytes.len() as u64,
+            )],
+        };
+        layout.sort_by_key(|region| region.vm_addr);
+        // Every frame is followed by a gap
+        let stack_len = (config.stack_size() as u64).saturating_add(
+            config
+                .stack_frame_gap_size(&self.sbpf_version)
+                .saturating_mul(config.max_call_depth as u64),
+        );
+        for (name, vm_addr, len) in [
+            ("[stack]", ebpf::MM_STACK_START, stack_len),
+            ("[heap]", ebpf::MM_HEAP_START, 0),
+            ("[input]", ebpf::MM_INPUT_START, 0),
+        ] {
+            layout.push(LayoutRegion {
+                name: name.to_string(),
+                vm_addr,
+                len,
+                writable: true,
+                executable: false,
+            });
+        }
+        layout
+    }
+
+    /// Named symbols of the .symtab and .dynsym sections of the ELF
+    fn elf_symbols(&self) -> Vec<(&[u8], Elf64Sym)> {
+        let Ok(elf) = Elf64::parse(self.elf_bytes.as_slice()) else {
+            return Vec::new();
+        };
+        let symbol_table = elf.symbol_table().ok().flatten().unwrap_or(&[]);
+        let dynamic_symbol_table = elf.dynamic_symbol_table().unwrap_or(&[]);
+        symbol_table
+            .iter()
+            .filter_map(|symbol| Some((elf.symbol_name(symbol.st_name).ok()?, symbol.clone())))
+            .chain(dynamic_symbol_table.iter().filter_map(|symbol| {
+                Some((
+                    elf.dynamic_symbol_name(symbol.st_name).ok()?,
+                    symbol.clone(),
+                ))
+            }))
+            .filter(|(name, _symbol)| !name.is_empty())
+            .collect()
+    }
+
+    /// Virtual address of a symbol or section, which the linker may not have placed at
+    /// MM_PROGRAM_START
+    fn elf_vm_addr(addr: u64) -> u64 {
+        if addr < ebpf::MM_PROGRAM_START {
+            ebpf::MM_PROGRAM_START.saturating_add(addr)
+        } else {
+            addr
+        }
+    }
+
     /// Create from raw text section bytes (list of instructions)
     pub fn new_from_text_bytes(
+        text_bytes: &[u8],
+        loader: Arc<BuiltinProgram<C>>,
+        sbpf_version: SBPFVersion,
+        function_registry: FunctionRegistry<usize>,
+    ) -> Result<Self, ElfError> {
+        Self::new_from_text_bytes_with_length_policy(
+            text_bytes,
+            loader,
+            sbpf_version,
+            function_registry,
+            TextLengthPolicy::Reject,
+        )
+    }
+
+    /// Create from raw text section bytes, adjusting their length according to the given policy
+    pub fn new_from_text_bytes_with_length_policy(
         text_bytes: &[u8],
         loader: Arc<BuiltinProgram<C>>,
         sbpf_version: SBPFVersion,
         mut function_registry: FunctionRegistry<usize>,
+        text_length_policy: TextLengthPolicy,
     ) -> Result<Self, ElfError> {
-        let elf_bytes = AlignedMemory::from_slice(text_bytes);
         let config = loader.get_config();
+        let text_length = text_length_policy.adjusted_length(text_bytes.len(), config)?;
+        let mut elf_bytes = AlignedMemory::zero_filled(text_length);
+        let copied_length = text_length.min(text_bytes.len());
+        elf_bytes
+            .as_slice_mut()
+            .get_mut(..copied_length)
+            .ok_or(ElfError::ValueOutOfBounds)?
+            .copy_from_slice(
+                text_bytes
+                    .get(..copied_length)
+                    .ok_or(ElfError::ValueOutOfBounds)?,
+            );
         let enable_symbol_and_section_labels = config.enable_symbol_and_section_labels;
+        let unresolved_symbol_policy = UnresolvedSymbolPolicy::from_config(config);
         let entry_pc = if let Some((_name, pc)) = function_registry.lookup_by_name(b"entrypoint") {
             pc
         } else {
@@ -370,9 +1455,9 @@ impl<C: ContextObject> Executable<C> {
             0
         };
         Ok(Self {
-            elf_bytes,
+            elf_bytes: ElfBytes::Copied(elf_bytes),
             sbpf_version,
-            ro_section: Section::Borrowed(0, 0..text_bytes.len()),
+            ro_section: Section::Borrowed(0, 0..text_length),
             text_section_info: SectionInfo {
                 name: if enable_symbol_and_section_labels {
                     ".text".to_string()
@@ -380,37 +1465,317 @@ impl<C: ContextObject> Executable<C> {
                     String::default()
                 },
                 vaddr: ebpf::MM_PROGRAM_START,
-                offset_range: 0..text_bytes.len(),
+                offset_range: 0..text_length,
             },
             entry_pc,
             function_registry,
             loader,
+            unresolved_symbol_policy,
+            rebound_syscalls: BTreeMap::new(),
+            verifier_exemptions: Vec::new(),
+            pre_verified_ranges: Vec::new(),
+            profile: None,
+            source_lines: None,
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
-            compiled_program: None,
+            compiled_program: OnceLock::new(),
+            #[cfg(feature = "threaded-dispatch")]
+            threaded_instructions: OnceLock::new(),
+            loader_incompatibilities: OnceLock::new(),
         })
     }
 
     /// Fully loads an ELF, including validation and relocation
+    ///
+    /// With the `zstd` feature the ELF can also be passed as a zstd frame.
     pub fn load(bytes: &[u8], loader: Arc<BuiltinProgram<C>>) -> Result<Self, ElfError> {
-        // The new parser creates references from the input byte slice, so
-        // it must be properly aligned. We assume that HOST_ALIGN is a
-        // multiple of the ELF "natural" alignment. See test_load_unaligned.
-        let aligned;
-        let bytes = if is_memory_aligned(bytes.as_ptr() as usize, HOST_ALIGN) {
-            bytes
+        let unresolved_symbol_policy = UnresolvedSymbolPolicy::from_config(loader.get_config());
+        Self::load_with_unresolved_symbol_policy(bytes, loader, unresolved_symbol_policy)
+    }
+
+    /// Fully loads an ELF, handling unresolved symbols according to the given policy
+    /// instead of `config.reject_broken_elfs`
+    pub fn load_with_unresolved_symbol_policy(
+        bytes: &[u8],
+        loader: Arc<BuiltinProgram<C>>,
+        unresolved_symbol_policy: UnresolvedSymbolPolicy,
+    ) -> Result<Self, ElfError> {
+        let input = prepare_input(bytes)?;
+        let bytes = input.as_ref().map_or(bytes, AlignedMemory::as_slice);
+        Self::load_with_parser(
+            &Elf64::parse(bytes)?,
+            bytes,
+            None,
+            loader,
+            unresolved_symbol_policy,
+            None,
+        )
+    }
+
+    /// Runs the checks of `load()` and returns all issues found instead of only the first one
+    ///
+    /// The header and section checks of `validate()`, the relocations and the entrypoint are
+    /// checked independently of each other. The remaining checks depend on those and only run
+    /// if they all passed. An empty list means that `load()` succeeds.
+    pub fn validate_report(bytes: &[u8], loader: Arc<BuiltinProgram<C>>) -> Vec<ElfError> {
+        let input = match prepare_input(bytes) {
+            Ok(input) => input,
+            Err(err) => return vec![err],
+        };
+        let bytes = input.as_ref().map_or(bytes, AlignedMemory::as_slice);
+        let elf = match Elf64::parse(bytes) {
+            Ok(elf) => elf,
+            Err(err) => return vec![err.into()],
+        };
+        let config = loader.get_config();
+        let unresolved_symbol_policy = UnresolvedSymbolPolicy::from_config(config);
+        let mut issues = Self::validation_issues(config, &elf, bytes);
+        if let Ok(text_section) = get_text_section(config, &elf) {
+            if !issues.contains(&ElfError::NotOneTextSection) {
+                let mut unresolved_symbols = Vec::new();
+                let result = Self::relocate(
+                    &mut FunctionRegistry::default(),
+                    &loader,
+                    &elf,
+                    copy_retained_sections(&elf, bytes).as_slice_mut(),
+                    unresolved_symbol_policy,
+                    &mut unresolved_symbols,
+                );
+                issues.append(&mut unresolved_symbols);
+                if let Err(err) = result {
+                    issues.push(err);
+                }
+            }
+            let offset = elf
+                .file_header()
+                .e_entry
+                .saturating_sub(text_section.sh_addr);
+            if offset.checked_rem(ebpf::INSN_SIZE as u64) != Some(0) {
+                issues.push(ElfError::InvalidEntrypoint);
+            }
+        }
+        if issues.is_empty() {
+            if let Err(err) =
+                Self::load_with_parser(&elf, bytes, None, loader, unresolved_symbol_policy, None)
+            {
+                issues.push(err);
+            }
+        }
+        issues
+    }
+
+    /// Fully loads an ELF, with the function symbol `entrypoint` as entrypoint instead of e_entry
+    ///
+    /// The symbol is looked up in the dynamic symbol table first and then in the symbol table.
+    pub fn load_with_entrypoint(
+        bytes: &[u8],
+        loader: Arc<BuiltinProgram<C>>,
+        entrypoint: &str,
+    ) -> Result<Self, ElfError> {
+        let input = prepare_input(bytes)?;
+        let bytes = input.as_ref().map_or(bytes, AlignedMemory::as_slice);
+        let unresolved_symbol_policy = UnresolvedSymbolPolicy::from_config(loader.get_config());
+        Self::load_with_parser(
+            &Elf64::parse(bytes)?,
+            bytes,
+            None,
+            loader,
+            unresolved_symbol_policy,
+            Some(entrypoint.as_bytes()),
+        )
+    }
+
+    /// Loads an ELF file through memory mappings instead of reading it
+    ///
+    /// The file is parsed from a read-only mapping and relocated in a private copy-on-write
+    /// mapping, so that only the pages which relocations patch are copied. That mapping is
+    /// read-only after loading and the memory regions of the read-only sections point into it.
+    /// Compressed files are decompressed into memory like in `load()` instead.
+    ///
+    /// # Safety
+    ///
+    /// The file must neither be modified nor truncated while the executable is alive, also not
+    /// by other processes. Modifications could change the program after it was verified and
+    /// accessing a truncated part of the mapping raises SIGBUS.
+    #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+    pub unsafe fn load_file<P: AsRef<Path>>(
+        path: P,
+        loader: Arc<BuiltinProgram<C>>,
+    ) -> Result<Self, ElfError> {
+        let map_err = |err: std::io::Error| ElfError::FailedToReadFile(err.to_string());
+        let file = File::open(path).map_err(map_err)?;
+        let input = MappedFile::new(&file, false).map_err(map_err)?;
+        let unresolved_symbol_policy = UnresolvedSymbolPolicy::from_config(loader.get_config());
+        if let Some(input) = prepare_input(input.as_slice())? {
+            return Self::load_with_parser(
+                &Elf64::parse(input.as_slice())?,
+                input.as_slice(),
+                None,
+                loader,
+                unresolved_symbol_policy,
+                None,
+            );
+        }
+        let elf_bytes = MappedFile::new(&file, true).map_err(map_err)?;
+        Self::load_with_parser(
+            &Elf64::parse(input.as_slice())?,
+            input.as_slice(),
+            Some(ElfBytes::Mapped(elf_bytes)),
+            loader,
+            unresolved_symbol_policy,
+            None,
+        )
+    }
+
+    /// Loads an ELF written by `to_elf_bytes()` without relocating it again
+    ///
+    /// The loader has to register the same syscalls and use a compatible Config as the one the
+    /// executable was originally loaded with, as the hashes of the calls are taken as they are.
+    /// The result has no verifier exemptions and no pre-verified ranges and has to be verified.
+    pub fn load_relocated(bytes: &[u8], loader: Arc<BuiltinProgram<C>>) -> Result<Self, ElfError> {
+        let elf_bytes = AlignedMemory::<{ HOST_ALIGN }>::from_slice(bytes);
+        let elf = Elf64::parse(elf_bytes.as_slice())?;
+        let config = loader.get_config();
+        let header = elf.file_header();
+        let sbpf_version = if header.e_flags == EF_SBPF_V2 {
+            SBPFVersion::V2
         } else {
-            aligned = AlignedMemory::<{ HOST_ALIGN }>::from_slice(bytes);
-            aligned.as_slice()
+            SBPFVersion::V1
+        };
+        Self::validate(config, &elf, elf_bytes.as_slice())?;
+
+        let text_section = get_section(&elf, b".text")?;
+        // The read-only sections are written contiguously, at their virtual addresses
+        let mut ro_range: Option<(u64, Range<usize>)> = None;
+        for section_header in elf.section_header_table().iter() {
+            match elf.section_name(section_header.sh_name) {
+                Ok(b".text") | Ok(b".rodata") => {}
+                _ => continue,
+            }
+            let file_range = section_header
+                .file_range()
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            ro_range = Some(match ro_range {
+                None => (section_header.sh_addr, file_range),
+                Some((vaddr, range))
+                    if range.end == file_range.start
+                        && vaddr.saturating_add(range.len() as u64) == section_header.sh_addr =>
+                {
+                    (vaddr, range.start..file_range.end)
+                }
+                Some(_) => return Err(ElfError::ValueOutOfBounds),
+            });
+        }
+        let (ro_vaddr, ro_range) = ro_range.ok_or(ElfError::ValueOutOfBounds)?;
+        if ro_vaddr < ebpf::MM_PROGRAM_START
+            || ro_vaddr.saturating_add(ro_range.len() as u64) > ebpf::MM_STACK_START
+        {
+            return Err(ElfError::ValueOutOfBounds);
+        }
+        let text_section_info = SectionInfo {
+            name: if config.enable_symbol_and_section_labels {
+                ".text".to_string()
+            } else {
+                String::default()
+            },
+            vaddr: text_section.sh_addr,
+            offset_range: text_section.file_range().unwrap_or_default(),
+        };
+
+        let offset = header.e_entry.saturating_sub(text_section.sh_addr);
+        if offset.checked_rem(ebpf::INSN_SIZE as u64) != Some(0) {
+            return Err(ElfError::InvalidEntrypoint);
+        }
+        let entry_pc = (offset as usize)
+            .checked_div(ebpf::INSN_SIZE)
+            .ok_or(ElfError::InvalidEntrypoint)?;
+        let text_insn_count = (text_section.sh_size as usize)
+            .checked_div(ebpf::INSN_SIZE)
+            .unwrap_or(0);
+        if entry_pc >= text_insn_count {
+            return Err(ElfError::InvalidEntrypoint);
+        }
+
+        let relocated_bytes = get_section(&elf, b".sbpf.relocated")?
+            .file_range()
+            .and_then(|range| elf_bytes.as_slice().get(range))
+            .ok_or(ElfError::ValueOutOfBounds)?;
+        let function_registry = Self::parse_relocated_section(relocated_bytes, text_insn_count)?;
+        let profile = get_section(&elf, b".sbpf.profile")
+            .ok()
+            .and_then(|section_header| section_header.file_range())
+            .and_then(|range| elf_bytes.as_slice().get(range))
+            .and_then(DynamicAnalysis::from_profile_bytes);
+
+        Ok(Self {
+            sbpf_version,
+            ro_section: Section::Borrowed(
+                ro_vaddr.saturating_sub(ebpf::MM_PROGRAM_START) as usize,
+                ro_range,
+            ),
+            text_section_info,
+            entry_pc,
+            function_registry,
+            unresolved_symbol_policy: UnresolvedSymbolPolicy::from_config(config),
+            loader,
+            rebound_syscalls: BTreeMap::new(),
+            verifier_exemptions: Vec::new(),
+            pre_verified_ranges: Vec::new(),
+            profile,
+            source_lines: None,
+            #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+            compiled_program: OnceLock::new(),
+            #[cfg(feature = "threaded-dispatch")]
+            threaded_instructions: OnceLock::new(),
+            loader_incompatibilities: OnceLock::new(),
+            elf_bytes: ElfBytes::Copied(elf_bytes),
+        })
+    }
+
+    /// Parses the function registry which `to_elf_bytes()` wrote into the .sbpf.relocated section
+    ///
+    /// Every function has to start inside the text section, which is `text_insn_count`
+    /// instructions long.
+    fn parse_relocated_section(
+        mut bytes: &[u8],
+        text_insn_count: usize,
+    ) -> Result<FunctionRegistry<usize>, ElfError> {
+        let truncated = || ElfError::InvalidRelocatedSection("truncated".to_string());
+        let mut take = |length: usize| -> Result<&[u8], ElfError> {
+            let head = bytes.get(..length).ok_or_else(truncated)?;
+            bytes = &bytes[length..];
+            Ok(head)
         };
-        Self::load_with_parser(&Elf64::parse(bytes)?, bytes, loader)
+        let mut function_registry = FunctionRegistry::default();
+        for _ in 0..LittleEndian::read_u32(take(4)?) {
+            let key = LittleEndian::read_u32(take(4)?);
+            let pc = LittleEndian::read_u64(take(8)?) as usize;
+            if pc >= text_insn_count {
+                return Err(ElfError::InvalidRelocatedSection(format!(
+                    "function pc {pc} out of range"
+                )));
+            }
+            let name_length = LittleEndian::read_u32(take(4)?) as usize;
+            function_registry.register_function(key, take(name_length)?, pc)?;
+        }
+        if !bytes.is_empty() {
+            return Err(ElfError::InvalidRelocatedSection(
+                "trailing bytes".to_string(),
+            ));
+        }
+        Ok(function_registry)
     }
 
+    /// Relocates `bytes` into `elf_bytes`, which is a copy of `bytes` by default
+    ///
+    /// The entrypoint is the function symbol named `entrypoint` if given, otherwise e_entry.
     fn load_with_parser(
         elf: &Elf64,
         bytes: &[u8],
+        elf_bytes: Option<ElfBytes>,
         loader: Arc<BuiltinProgram<C>>,
+        unresolved_symbol_policy: UnresolvedSymbolPolicy,
+        entrypoint: Option<&[u8]>,
     ) -> Result<Self, ElfError> {
-        let mut elf_bytes = AlignedMemory::from_slice(bytes);
         let config = loader.get_config();
         let header = elf.file_header();
         let sbpf_version = if header.e_flags == EF_SBPF_V2 {
@@ -419,10 +1784,12 @@ impl<C: ContextObject> Executable<C> {
             SBPFVersion::V1
         };
 
-        Self::validate(config, elf, elf_bytes.as_slice())?;
+        Self::validate(config, elf, bytes)?;
+        let mut elf_bytes =
+            elf_bytes.unwrap_or_else(|| ElfBytes::Copied(copy_retained_sections(elf, bytes)));
 
         // calculate the text section info
-        let text_section = get_section(elf, b".text")?;
+        let text_section = get_text_section(config, elf)?;
         let text_section_info = SectionInfo {
             name: if config.enable_symbol_and_section_labels {
                 elf.section_name(text_section.sh_name)
@@ -457,15 +1824,50 @@ impl<C: ContextObject> Executable<C> {
 
         // relocate symbols
         let mut function_registry = FunctionRegistry::default();
-        Self::relocate(
+        let mut unresolved_symbols = Vec::new();
+        let relocated_lddws = Self::relocate(
             &mut function_registry,
             &loader,
             elf,
             elf_bytes.as_slice_mut(),
-        )?;
+            unresolved_symbol_policy,
+            &mut unresolved_symbols,
+        );
+        if let Some(err) = unresolved_symbols.into_iter().next() {
+            return Err(err);
+        }
+        let relocated_lddws = relocated_lddws?;
+        elf_bytes.protect()?;
 
         // calculate entrypoint offset into the text section
-        let offset = header.e_entry.saturating_sub(text_section.sh_addr);
+        let entry_address = match entrypoint {
+            Some(name) => {
+                let address = elf
+                    .dynamic_symbol_table()
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter(|symbol| elf.dynamic_symbol_name(symbol.st_name).ok() == Some(name))
+                    .chain(
+                        elf.symbol_table()
+                            .ok()
+                            .flatten()
+                            .unwrap_or(&[])
+                            .iter()
+                            .filter(|symbol| elf.symbol_name(symbol.st_name).ok() == Some(name)),
+                    )
+                    .find(|symbol| symbol.is_function() && symbol.st_value != 0)
+                    .map(|symbol| symbol.st_value)
+                    .ok_or_else(|| {
+                        ElfError::EntrypointNotFound(String::from_utf8_lossy(name).to_string())
+                    })?;
+                if !text_section.vm_range().contains(&address) {
+                    return Err(ElfError::EntrypointOutOfBounds);
+                }
+                address
+            }
+            None => header.e_entry,
+        };
+        let offset = entry_address.saturating_sub(text_section.sh_addr);
         if offset.checked_rem(ebpf::INSN_SIZE as u64) != Some(0) {
             return Err(ElfError::InvalidEntrypoint);
         }
@@ -493,6 +1895,48 @@ impl<C: ContextObject> Executable<C> {
             elf_bytes.as_slice(),
         )?;
 
+        if config.reject_lddw_outside_loaded_sections {
+            let ro_region = get_ro_region(&ro_section, elf_bytes.as_slice());
+            // A pointer one past the end is still fine, e.g. the end of an array
+            let loaded_range = ro_region.vm_addr..=ro_region.vm_addr.saturating_add(ro_region.len);
+            if let Some((relocation, pc, addr)) = relocated_lddws
+                .into_iter()
+                .find(|(_relocation, _pc, addr)| !loaded_range.contains(addr))
+            {
+                return Err(ElfError::LddwOutsideLoadedSections(relocation, pc, addr));
+            }
+        }
+
+        let verifier_exemptions =
+            Self::parse_verifier_exemptions(elf, elf_bytes.as_slice(), &text_section)?;
+        // The profile is only advisory, so a malformed one is ignored
+        let profile = get_section(elf, b".sbpf.profile")
+            .ok()
+            .and_then(|section_header| section_header.file_range())
+            .and_then(|range| elf_bytes.as_slice().get(range))
+            .and_then(DynamicAnalysis::from_profile_bytes);
+        // Debug information is only advisory as well and not copied, see copy_retained_sections()
+        let section_bytes = |name: &[u8]| {
+            get_section(elf, name)
+                .ok()
+                .and_then(|section_header| section_header.file_range())
+                .and_then(|range| bytes.get(range))
+        };
+        let source_lines = section_bytes(b".debug_line")
+            .and_then(|debug_line| {
+                LineTable::parse(
+                    debug_line,
+                    section_bytes(b".debug_line_str").unwrap_or_default(),
+                    section_bytes(b".debug_str").unwrap_or_default(),
+                )
+            })
+            .map(|line_table| {
+                line_table.into_pcs(
+                    text_section.sh_addr,
+                    text_section_info.offset_range.len() / ebpf::INSN_SIZE,
+                )
+            });
+
         Ok(Self {
             elf_bytes,
             sbpf_version,
@@ -501,8 +1945,17 @@ impl<C: ContextObject> Executable<C> {
             entry_pc,
             function_registry,
             loader,
+            unresolved_symbol_policy,
+            rebound_syscalls: BTreeMap::new(),
+            verifier_exemptions,
+            pre_verified_ranges: Vec::new(),
+            profile,
+            source_lines,
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
-            compiled_program: None,
+            compiled_program: OnceLock::new(),
+            #[cfg(feature = "threaded-dispatch")]
+            threaded_instructions: OnceLock::new(),
+            loader_incompatibilities: OnceLock::new(),
         })
     }
 
@@ -522,12 +1975,27 @@ impl<C: ContextObject> Executable<C> {
             // text section info
             .saturating_add(self.text_section_info.mem_size())
             // bpf functions
-            .saturating_add(self.function_registry.mem_size());
+            .saturating_add(self.function_registry.mem_size())
+            // verifier exemptions
+            .saturating_add(self.verifier_exemptions.iter().fold(
+                self.verifier_exemptions.capacity().saturating_mul(mem::size_of::<VerifierExemption>()),
+                |total, exemption| total.saturating_add(exemption.function_name.capacity()),
+            ))
+            // pre-verified ranges
+            .saturating_add(self.pre_verified_ranges.capacity().saturating_mul(mem::size_of::<Range<usize>>()))
+            // rebound syscalls
+            .saturating_add(self.rebound_syscalls.len().saturating_mul(mem::size_of::<(u32, BuiltinFunction<C>)>()))
+            // source lines
+            .saturating_add(self.source_lines.as_ref().map_or(0, LineTable::mem_size))
+            // profile
+            .saturating_add(self.profile.as_ref().map_or(0, |profile| {
+                profile.edges.values().map(|destinations| destinations.len()).sum::<usize>().saturating_mul(mem::size_of::<[usize; 3]>())
+            }));
 
         #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
         {
             // compiled programs
-            total = total.saturating_add(self.compiled_program.as_ref().map_or(0, |program| program.mem_size()));
+            total = total.saturating_add(self.compiled_program.get().map_or(0, |program| program.mem_size()));
         }
 
         total
@@ -537,31 +2005,43 @@ impl<C: ContextObject> Executable<C> {
 
     /// Validates the ELF
     pub fn validate(config: &Config, elf: &Elf64, elf_bytes: &[u8]) -> Result<(), ElfError> {
+        match Self::validation_issues(config, elf, elf_bytes)
+            .into_iter()
+            .next()
+        {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs all checks of `validate()` and returns every failed one, in the same order
+    fn validation_issues(config: &Config, elf: &Elf64, elf_bytes: &[u8]) -> Vec<ElfError> {
+        let mut issues = Vec::new();
         let header = elf.file_header();
         if header.e_ident.ei_class != ELFCLASS64 {
-            return Err(ElfError::WrongClass);
+            issues.push(ElfError::WrongClass);
         }
         if header.e_ident.ei_data != ELFDATA2LSB {
-            return Err(ElfError::WrongEndianess);
+            issues.push(ElfError::WrongEndianess);
         }
         if header.e_ident.ei_osabi != ELFOSABI_NONE {
-            return Err(ElfError::WrongAbi);
+            issues.push(ElfError::WrongAbi);
         }
         if header.e_machine != EM_BPF && header.e_machine != EM_SBPF {
-            return Err(ElfError::WrongMachine);
+            issues.push(ElfError::WrongMachine);
         }
         if header.e_type != ET_DYN {
-            return Err(ElfError::WrongType);
+            issues.push(ElfError::WrongType);
         }
 
         let sbpf_version = if header.e_flags == EF_SBPF_V2 {
             if !config.enable_sbpf_v2 {
-                return Err(ElfError::UnsupportedSBPFVersion);
+                issues.push(ElfError::UnsupportedSBPFVersion);
             }
             SBPFVersion::V2
         } else {
             if !config.enable_sbpf_v1 {
-                return Err(ElfError::UnsupportedSBPFVersion);
+                issues.push(ElfError::UnsupportedSBPFVersion);
             }
             SBPFVersion::V1
         };
@@ -571,7 +2051,7 @@ impl<C: ContextObject> Executable<C> {
                 // When optimize_rodata=false, we allocate a vector and copy all
                 // rodata sections into it. In that case we can't allow virtual
                 // addresses or we'd potentially have to do huge allocations.
-                return Err(ElfError::UnsupportedSBPFVersion);
+                issues.push(ElfError::UnsupportedSBPFVersion);
             }
 
             // The toolchain currently emits up to 4 program headers. 10 is a
@@ -580,7 +2060,7 @@ impl<C: ContextObject> Executable<C> {
             // program_headers() returns an ExactSizeIterator so count doesn't
             // actually iterate again.
             if elf.program_header_table().iter().count() >= 10 {
-                return Err(ElfError::InvalidProgramHeader);
+                issues.push(ElfError::InvalidProgramHeader);
             }
         }
 
@@ -595,9 +2075,23 @@ impl<C: ContextObject> Executable<C> {
                     }
                     count
                 });
-        if 1 != num_text_sections {
-            return Err(ElfError::NotOneTextSection);
-        }
+        let text_section =
+            if num_text_sections > 1 || (num_text_sections == 0 && !config.merge_text_sections) {
+                issues.push(ElfError::NotOneTextSection);
+                None
+            } else {
+                match get_text_section(config, elf) {
+                    Ok(text_section) => Some(text_section),
+                    Err(ElfError::NonContiguousTextSections) => {
+                        issues.push(ElfError::NonContiguousTextSections);
+                        None
+                    }
+                    Err(_) => {
+                        issues.push(ElfError::NotOneTextSection);
+                        None
+                    }
+                }
+            };
 
         for section_header in elf.section_header_table().iter() {
             if let Ok(name) = elf.section_name(section_header.sh_name) {
@@ -605,29 +2099,53 @@ impl<C: ContextObject> Executable<C> {
                     || (section_header.is_writable()
                         && (name.starts_with(b".data") && !name.starts_with(b".data.rel")))
                 {
-                    return Err(ElfError::WritableSectionNotSupported(
+                    issues.push(ElfError::WritableSectionNotSupported(
                         String::from_utf8_lossy(name).to_string(),
                     ));
                 }
             }
         }
 
+        if config.reject_wx_sections {
+            // All loaded sections are mapped read-only and only the text is executable
+            for section_header in elf.section_header_table().iter() {
+                let name = elf.section_name(section_header.sh_name).unwrap_or_default();
+                let is_text = if config.merge_text_sections {
+                    is_text_section_name(name)
+                } else {
+                    name == b".text"
+                };
+                let issue = match (section_header.is_writable(), section_header.is_executable()) {
+                    (true, true) => ElfError::WritableAndExecutableSection,
+                    (false, true) if !is_text => ElfError::ExecutableSectionOutsideText,
+                    // .data.rel.ro is only writable while the dynamic linker relocates it
+                    (true, false) if is_text || matches!(name, b".rodata" | b".eh_frame") => {
+                        ElfError::WritableSectionNotSupported
+                    }
+                    _ => continue,
+                };
+                issues.push(issue(String::from_utf8_lossy(name).to_string()));
+            }
+        }
+
         for section_header in elf.section_header_table().iter() {
             let start = section_header.sh_offset as usize;
-            let end = section_header
+            let in_bounds = section_header
                 .sh_offset
                 .checked_add(section_header.sh_size)
-                .ok_or(ElfError::ValueOutOfBounds)? as usize;
-            let _ = elf_bytes
-                .get(start..end)
-                .ok_or(ElfError::ValueOutOfBounds)?;
+                .and_then(|end| elf_bytes.get(start..end as usize))
+                .is_some();
+            if !in_bounds {
+                issues.push(ElfError::ValueOutOfBounds);
+            }
         }
-        let text_section = get_section(elf, b".text")?;
-        if !text_section.vm_range().contains(&header.e_entry) {
-            return Err(ElfError::EntrypointOutOfBounds);
+        if let Some(text_section) = text_section {
+            if !text_section.vm_range().contains(&header.e_entry) {
+                issues.push(ElfError::EntrypointOutOfBounds);
+            }
         }
 
-        Ok(())
+        issues
     }
 
     pub(crate) fn parse_ro_sections<
@@ -660,11 +2178,7 @@ impl<C: ContextObject> Executable<C> {
         let mut ro_slices = vec![];
         for (i, (name, section_header)) in sections.into_iter().enumerate() {
             match name {
-                Some(name)
-                    if name == b".text"
-                        || name == b".rodata"
-                        || name == b".data.rel.ro"
-                        || name == b".eh_frame" => {}
+                Some(name) if is_ro_section_name(config, name) => {}
                 _ => continue,
             }
 
@@ -811,19 +2325,26 @@ impl<C: ContextObject> Executable<C> {
     }
 
     /// Relocates the ELF in-place
+    ///
+    /// Returns the relocated lddw instructions of the text section, with the symbol name or the
+    /// type of their relocation, their pc and the address they load.
     fn relocate(
         function_registry: &mut FunctionRegistry<usize>,
         loader: &BuiltinProgram<C>,
         elf: &Elf64,
         elf_bytes: &mut [u8],
-    ) -> Result<(), ElfError> {
+        unresolved_symbol_policy: UnresolvedSymbolPolicy,
+        unresolved_symbols: &mut Vec<ElfError>,
+    ) -> Result<Vec<(String, usize, u64)>, ElfError> {
         let mut syscall_cache = BTreeMap::new();
-        let text_section = get_section(elf, b".text")?;
+        let mut relocated_lddws = Vec::new();
+        let text_section = get_text_section(loader.get_config(), elf)?;
         let sbpf_version = if elf.file_header().e_flags == EF_SBPF_V2 {
             SBPFVersion::V2
         } else {
             SBPFVersion::V1
         };
+        check_dynamic_function_names(elf, &text_section)?;
 
         // Fixup all program counter relative call instructions
         let config = loader.get_config();
@@ -834,8 +2355,17 @@ impl<C: ContextObject> Executable<C> {
             .len()
             .checked_div(ebpf::INSN_SIZE)
             .ok_or(ElfError::ValueOutOfBounds)?;
+        let relocations = elf.dynamic_relocations_table().unwrap_or(&[]);
+        let work = instruction_count.saturating_add(relocations.len());
+        if work > config.verification_budget {
+            return Err(ElfError::VerificationBudgetExceeded(
+                work,
+                config.verification_budget,
+            ));
+        }
         for i in 0..instruction_count {
-            let insn = ebpf::get_insn(text_bytes, i);
+            let insn =
+                ebpf::get_insn_checked(text_bytes, i).map_err(|_| ElfError::ValueOutOfBounds)?;
             if insn.opc == ebpf::CALL_IMM
                 && insn.imm != -1
                 && !(sbpf_version.static_syscalls() && insn.src == 0)
@@ -868,7 +2398,7 @@ impl<C: ContextObject> Executable<C> {
         let mut program_header: Option<&Elf64Phdr> = None;
 
         // Fixup all the relocations in the relocation section if exists
-        for relocation in elf.dynamic_relocations_table().unwrap_or(&[]).iter() {
+        for relocation in relocations.iter() {
             let mut r_offset = relocation.r_offset as usize;
 
             // When sbpf_version.enable_elf_vaddr()=true, we allow section.sh_addr !=
@@ -916,16 +2446,47 @@ impl<C: ContextObject> Executable<C> {
                         .and_then(|table| table.get(relocation.r_sym() as usize).cloned())
                         .ok_or_else(|| ElfError::UnknownSymbol(relocation.r_sym() as usize))?;
 
-                    // The relocated address is relative to the address of the
-                    // symbol at index `r_sym`
-                    let mut addr = symbol.st_value.saturating_add(refd_addr);
+                    let is_undefined = relocation.r_sym() != 0 && symbol.st_shndx == SHN_UNDEF;
+                    let addr = if is_undefined {
+                        match resolve_undefined_data_symbol(
+                            elf,
+                            &symbol,
+                            r_offset,
+                            unresolved_symbol_policy,
+                            unresolved_symbols,
+                        )? {
+                            Some(addr) => addr,
+                            None => continue,
+                        }
+                    } else {
+                        // The relocated address is relative to the address of the
+                        // symbol at index `r_sym`
+                        let mut addr = symbol.st_value.saturating_add(refd_addr);
+
+                        // The "physical address" from the VM's perspective is rooted
+                        // at `MM_PROGRAM_START`. If the linker hasn't already put
+                        // the symbol within `MM_PROGRAM_START`, we need to do so
+                        // now.
+                        if addr < ebpf::MM_PROGRAM_START {
+                            addr = ebpf::MM_PROGRAM_START.saturating_add(addr);
+                        }
+                        addr
+                    };
 
-                    // The "physical address" from the VM's perspective is rooted
-                    // at `MM_PROGRAM_START`. If the linker hasn't already put
-                    // the symbol within `MM_PROGRAM_START`, we need to do so
-                    // now.
-                    if addr < ebpf::MM_PROGRAM_START {
-                        addr = ebpf::MM_PROGRAM_START.saturating_add(addr);
+                    let text_range = text_section.file_range().unwrap_or_default();
+                    // The null address of a stub is not checked against the loaded sections
+                    if text_range.contains(&r_offset) && !is_undefined {
+                        let name = elf
+                            .dynamic_symbol_name(symbol.st_name as Elf64Word)
+                            .unwrap_or_default();
+                        relocated_lddws.push((
+                            String::from_utf8_lossy(name).to_string(),
+                            r_offset
+                                .saturating_sub(text_range.start)
+                                .checked_div(ebpf::INSN_SIZE)
+                                .unwrap_or_default(),
+                            addr,
+                        ));
                     }
 
                     if text_section
@@ -1016,6 +2577,14 @@ impl<C: ContextObject> Executable<C> {
                             // MM_PROGRAM_START, so we do so now
                             refd_addr = ebpf::MM_PROGRAM_START.saturating_add(refd_addr);
                         }
+                        relocated_lddws.push((
+                            "R_BPF_64_RELATIVE".to_string(),
+                            r_offset
+                                .saturating_sub(text_section.file_range().unwrap_or_default().start)
+                                .checked_div(ebpf::INSN_SIZE)
+                                .unwrap_or_default(),
+                            refd_addr,
+                        ));
 
                         // Write back the low half
                         let imm_slice = elf_bytes
@@ -1085,7 +2654,7 @@ impl<C: ContextObject> Executable<C> {
 
                     let name = elf
                         .dynamic_symbol_name(symbol.st_name as Elf64Word)
-                        .map_err(|_| ElfError::UnknownSymbol(symbol.st_name as usize))?;
+                        .map_err(|err| symbol_name_error(err, symbol.st_name))?;
 
                     // If the symbol is defined, this is a bpf-to-bpf call
                     let key = if symbol.is_function() && symbol.st_value != 0 {
@@ -1107,16 +2676,35 @@ impl<C: ContextObject> Executable<C> {
                         let hash = *syscall_cache
                             .entry(symbol.st_name)
                             .or_insert_with(|| ebpf::hash_symbol_name(name));
-                        if config.reject_broken_elfs
-                            && loader.get_function_registry().lookup_by_key(hash).is_none()
-                        {
-                            return Err(ElfError::UnresolvedSymbol(
-                                String::from_utf8_lossy(name).to_string(),
-                                r_offset.checked_div(ebpf::INSN_SIZE).unwrap_or(0),
-                                r_offset,
-                            ));
+                        if loader.get_function_registry().lookup_by_key(hash).is_some() {
+                            hash
+                        } else if let Some(key) = loader.resolve_symbol(name) {
+                            key
+                        } else {
+                            match unresolved_symbol_policy {
+                                UnresolvedSymbolPolicy::Error => {
+                                    // Collected so that all of them can be reported at once
+                                    unresolved_symbols.push(ElfError::UnresolvedSymbol(
+                                        String::from_utf8_lossy(name).to_string(),
+                                        r_offset.checked_div(ebpf::INSN_SIZE).unwrap_or(0),
+                                        r_offset,
+                                    ));
+                                    continue;
+                                }
+                                UnresolvedSymbolPolicy::StubWithTrap => hash,
+                                UnresolvedSymbolPolicy::StubReturningZero => {
+                                    let stub = ebpf::Insn {
+                                        opc: ebpf::MOV64_IMM,
+                                        ..ebpf::Insn::default()
+                                    };
+                                    elf_bytes
+                                        .get_mut(r_offset..r_offset.saturating_add(ebpf::INSN_SIZE))
+                                        .ok_or(ElfError::ValueOutOfBounds)?
+                                        .copy_from_slice(&stub.to_array());
+                                    continue;
+                                }
+                            }
                         }
-                        hash
                     };
 
                     let checked_slice = elf_bytes
@@ -1124,6 +2712,52 @@ impl<C: ContextObject> Executable<C> {
                         .ok_or(ElfError::ValueOutOfBounds)?;
                     LittleEndian::write_u32(checked_slice, key);
                 }
+                Some(BpfRelocationType::R_Bpf_64_Abs64) => {
+                    let addr_slice = elf_bytes
+                        .get_mut(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
+                        .ok_or(ElfError::ValueOutOfBounds)?;
+                    let refd_addr = LittleEndian::read_u64(addr_slice);
+
+                    let symbol = elf
+                        .dynamic_symbol_table()
+                        .and_then(|table| table.get(relocation.r_sym() as usize).cloned())
+                        .ok_or_else(|| ElfError::UnknownSymbol(relocation.r_sym() as usize))?;
+
+                    let addr = if relocation.r_sym() != 0 && symbol.st_shndx == SHN_UNDEF {
+                        match resolve_undefined_data_symbol(
+                            elf,
+                            &symbol,
+                            r_offset,
+                            unresolved_symbol_policy,
+                            unresolved_symbols,
+                        )? {
+                            Some(addr) => addr,
+                            None => continue,
+                        }
+                    } else {
+                        // Same as for R_Bpf_64_64, the address has to be rooted at
+                        // `MM_PROGRAM_START`
+                        let mut addr = symbol.st_value.saturating_add(refd_addr);
+                        if addr < ebpf::MM_PROGRAM_START {
+                            addr = ebpf::MM_PROGRAM_START.saturating_add(addr);
+                        }
+                        addr
+                    };
+                    LittleEndian::write_u64(addr_slice, addr);
+                }
+                Some(BpfRelocationType::R_Bpf_64_Abs32) => {
+                    let is_loaded = elf.section_header_table().iter().any(|section_header| {
+                        section_header.sh_flags & SHF_ALLOC != 0
+                            && section_header
+                                .file_range()
+                                .unwrap_or_default()
+                                .contains(&r_offset)
+                    });
+                    if is_loaded {
+                        return Err(ElfError::UnknownRelocation(relocation.r_type()));
+                    }
+                }
+                Some(BpfRelocationType::R_Bpf_64_NoDyld32) => {}
                 _ => return Err(ElfError::UnknownRelocation(relocation.r_type())),
             }
         }
@@ -1142,7 +2776,7 @@ impl<C: ContextObject> Executable<C> {
                     .unwrap_or_default();
                 let name = elf
                     .symbol_name(symbol.st_name as Elf64Word)
-                    .map_err(|_| ElfError::UnknownSymbol(symbol.st_name as usize))?;
+                    .map_err(|err| symbol_name_error(err, symbol.st_name))?;
                 function_registry.register_function_hashed_legacy(
                     loader,
                     !sbpf_version.static_syscalls(),
@@ -1152,7 +2786,341 @@ impl<C: ContextObject> Executable<C> {
             }
         }
 
-        Ok(())
+        Ok(relocated_lddws)
+    }
+
+    /// Returns a copy of an ELF file with the given profile in its .sbpf.profile section
+    ///
+    /// The section, a new section name table and a new section header table are appended to
+    /// the end of the file. A previous .sbpf.profile section is replaced.
+    pub fn append_profile_section(
+        elf_bytes: &[u8],
+        profile: &DynamicAnalysis,
+    ) -> Result<Vec<u8>, ElfError> {
+        Self::append_section(elf_bytes, b".sbpf.profile", &profile.to_profile_bytes())
+    }
+
+    /// Returns a copy of an ELF file with the given contents in the section `name`
+    ///
+    /// See `append_profile_section()`.
+    pub(crate) fn append_section(
+        elf_bytes: &[u8],
+        name: &[u8],
+        section_bytes: &[u8],
+    ) -> Result<Vec<u8>, ElfError> {
+        let aligned = AlignedMemory::<{ HOST_ALIGN }>::from_slice(elf_bytes);
+        let elf = Elf64::parse(aligned.as_slice())?;
+        let file_header = elf.file_header();
+        let section_header_table = elf.section_header_table();
+        let section_names_index = file_header.e_shstrndx as usize;
+        let section_names_header = section_header_table
+            .get(section_names_index)
+            .ok_or(ElfError::ValueOutOfBounds)?;
+        let section_names = section_names_header
+            .file_range()
+            .and_then(|range| elf_bytes.get(range))
+            .ok_or(ElfError::ValueOutOfBounds)?;
+        let mut result = elf_bytes.to_vec();
+        let section_names_offset = result.len();
+        result.extend_from_slice(section_names);
+        result.extend_from_slice(name);
+        result.push(0);
+        let section_offset = result.len();
+        result.extend_from_slice(section_bytes);
+        result.resize(result.len().saturating_add(7) & !7, 0);
+        let section_header_table_offset = result.len();
+        let mut section_headers = section_header_table.to_vec();
+        for (index, section_header) in section_headers.iter_mut().enumerate() {
+            // The old section name table and section stay in place, but without a name
+            if index == section_names_index
+                || elf.section_name(section_header.sh_name).ok() == Some(name)
+            {
+                section_header.sh_name = 0;
+            }
+        }
+        section_headers.push(Elf64Shdr {
+            sh_offset: section_names_offset as u64,
+            sh_size: section_names
+                .len()
+                .saturating_add(name.len())
+                .saturating_add(1) as u64,
+            ..section_names_header.clone()
+        });
+        section_headers.push(Elf64Shdr {
+            sh_name: section_names.len() as Elf64Word,
+            sh_type: SHT_PROGBITS,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset: section_offset as u64,
+            sh_size: section_bytes.len() as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 1,
+            sh_entsize: 0,
+        });
+        for section_header in section_headers.iter() {
+            result.extend_from_slice(struct_as_bytes(section_header));
+        }
+        let mut file_header = file_header.clone();
+        file_header.e_shoff = section_header_table_offset as u64;
+        file_header.e_shstrndx =
+            u16::try_from(section_header_table.len()).map_err(|_| ElfError::ValueOutOfBounds)?;
+        file_header.e_shnum =
+            u16::try_from(section_headers.len()).map_err(|_| ElfError::ValueOutOfBounds)?;
+        result[..mem::size_of::<Elf64Ehdr>()].copy_from_slice(struct_as_bytes(&file_header));
+        Ok(result)
+    }
+
+    /// Writes the executable as it is loaded into an ELF, see `load_relocated()`
+    ///
+    /// The read-only sections are written at their virtual addresses, with the text split off,
+    /// so that they can be borrowed again. The function registry goes into a .sbpf.relocated
+    /// section and the profile, if any, into a .sbpf.profile section. Syscalls rebound with
+    /// `rebind_syscall()`, source locations and compiled programs are not part of the file.
+    /// Neither are verifier exemptions and pre-verified ranges, as the file is not trusted:
+    /// The host has to request and mark them again after `load_relocated()`.
+    pub fn to_elf_bytes(&self) -> Vec<u8> {
+        const SECTION_NAMES: &[u8] =
+            b"\0.text\0.rodata\0.sbpf.relocated\0.sbpf.profile\0.shstrtab\0";
+        let section_name = |name: &[u8]| {
+            let mut offset = 0usize;
+            for entry in SECTION_NAMES.split(|byte| *byte == 0) {
+                if entry == name {
+                    break;
+                }
+                offset = offset.saturating_add(entry.len()).saturating_add(1);
+            }
+            offset as Elf64Word
+        };
+        let align8 = |result: &mut Vec<u8>| result.resize(result.len().saturating_add(7) & !7, 0);
+
+        let mut result = vec![0; mem::size_of::<Elf64Ehdr>()];
+        let mut section_headers = vec![Elf64Shdr {
+            sh_name: 0,
+            sh_type: 0,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset: 0,
+            sh_size: 0,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 0,
+            sh_entsize: 0,
+        }];
+        let mut push_section = |result: &mut Vec<u8>,
+                                name: &[u8],
+                                sh_type: Elf64Word,
+                                sh_flags: u64,
+                                sh_addr: u64,
+                                bytes: &[u8]| {
+            section_headers.push(Elf64Shdr {
+                sh_name: section_name(name),
+                sh_type,
+                sh_flags,
+                sh_addr,
+                sh_offset: result.len() as u64,
+                sh_size: bytes.len() as u64,
+                sh_link: 0,
+                sh_info: 0,
+                sh_addralign: 1,
+                sh_entsize: 0,
+            });
+            result.extend_from_slice(bytes);
+        };
+
+        align8(&mut result);
+        let ro_region = self.get_ro_region();
+        let ro_section = self.get_ro_section();
+        let (text_vaddr, text_bytes) = self.get_text_bytes();
+        let text_start = text_vaddr.saturating_sub(ro_region.vm_addr) as usize;
+        let text_end = text_start.saturating_add(text_bytes.len());
+        for (name, flags, range) in [
+            (&b".rodata"[..], SHF_ALLOC, 0..text_start),
+            (b".text", SHF_ALLOC | SHF_EXECINSTR, text_start..text_end),
+            (b".rodata", SHF_ALLOC, text_end..ro_section.len()),
+        ] {
+            if !range.is_empty() || name == b".text" {
+                push_section(
+                    &mut result,
+                    name,
+                    SHT_PROGBITS,
+                    flags,
+                    ro_region.vm_addr.saturating_add(range.start as u64),
+                    &ro_section[range],
+                );
+            }
+        }
+
+        let mut relocated = Vec::new();
+        let push_name = |relocated: &mut Vec<u8>, name: &[u8]| {
+            relocated.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            relocated.extend_from_slice(name);
+        };
+        relocated.extend_from_slice(&(self.function_registry.keys().count() as u32).to_le_bytes());
+        for (key, (name, pc)) in self.function_registry.iter() {
+            relocated.extend_from_slice(&key.to_le_bytes());
+            relocated.extend_from_slice(&(pc as u64).to_le_bytes());
+            push_name(&mut relocated, name);
+        }
+        push_section(
+            &mut result,
+            b".sbpf.relocated",
+            SHT_PROGBITS,
+            0,
+            0,
+            &relocated,
+        );
+        if let Some(profile) = &self.profile {
+            push_section(
+                &mut result,
+                b".sbpf.profile",
+                SHT_PROGBITS,
+                0,
+                0,
+                &profile.to_profile_bytes(),
+            );
+        }
+        push_section(&mut result, b".shstrtab", SHT_STRTAB, 0, 0, SECTION_NAMES);
+
+        align8(&mut result);
+        let section_header_table_offset = result.len();
+        for section_header in section_headers.iter() {
+            result.extend_from_slice(struct_as_bytes(section_header));
+        }
+        let file_header = Elf64Ehdr {
+            e_ident: ElfIdent {
+                ei_mag: ELFMAG,
+                ei_class: ELFCLASS64,
+                ei_data: ELFDATA2LSB,
+                ei_version: EV_CURRENT as u8,
+                ei_osabi: ELFOSABI_NONE,
+                ei_abiversion: 0,
+                ei_pad: [0; 7],
+            },
+            e_type: ET_DYN,
+            e_machine: EM_SBPF,
+            e_version: EV_CURRENT,
+            e_entry: text_vaddr
+                .saturating_add((self.entry_pc as u64).saturating_mul(INSN_SIZE as u64)),
+            e_phoff: 0,
+            e_shoff: section_header_table_offset as u64,
+            e_flags: if self.sbpf_version == SBPFVersion::V2 {
+                EF_SBPF_V2
+            } else {
+                0
+            },
+            e_ehsize: mem::size_of::<Elf64Ehdr>() as u16,
+            e_phentsize: mem::size_of::<Elf64Phdr>() as u16,
+            e_phnum: 0,
+            e_shentsize: mem::size_of::<Elf64Shdr>() as u16,
+            e_shnum: section_headers.len() as u16,
+            e_shstrndx: section_headers.len().saturating_sub(1) as u16,
+        };
+        result[..mem::size_of::<Elf64Ehdr>()].copy_from_slice(struct_as_bytes(&file_header));
+        result
+    }
+
+    /// Parses the notes of the .sbpf.verifier section
+    ///
+    /// Each note is a regular ELF note with the owner name "SBPF", the exemption kind as type and
+    /// the symbol name of the function it applies to as descriptor.
+    pub fn parse_verifier_notes(
+        section_bytes: &[u8],
+    ) -> Result<Vec<(VerifierExemptionKind, &[u8])>, ElfError> {
+        fn align4(value: usize) -> Option<usize> {
+            value.checked_add(3).map(|value| value & !3)
+        }
+        let invalid = |reason: &str| ElfError::InvalidVerifierNote(reason.to_string());
+        let mut notes = Vec::new();
+        let mut offset = 0usize;
+        while offset < section_bytes.len() {
+            let header = section_bytes
+                .get(offset..offset.saturating_add(12))
+                .ok_or_else(|| invalid("truncated header"))?;
+            let name_size = LittleEndian::read_u32(&header[0..4]) as usize;
+            let desc_size = LittleEndian::read_u32(&header[4..8]) as usize;
+            let note_type = LittleEndian::read_u32(&header[8..12]);
+            let name_start = offset.saturating_add(12);
+            let desc_start = align4(name_start.saturating_add(name_size))
+                .ok_or_else(|| invalid("name too long"))?;
+            let next_offset = align4(desc_start.saturating_add(desc_size))
+                .ok_or_else(|| invalid("descriptor too long"))?;
+            let name = section_bytes
+                .get(name_start..name_start.saturating_add(name_size))
+                .ok_or_else(|| invalid("truncated name"))?;
+            if name.strip_suffix(b"\0").unwrap_or(name) != b"SBPF" {
+                return Err(invalid("unknown owner"));
+            }
+            let desc = section_bytes
+                .get(desc_start..desc_start.saturating_add(desc_size))
+                .ok_or_else(|| invalid("truncated descriptor"))?;
+            let kind = VerifierExemptionKind::from_note_type(note_type)
+                .ok_or_else(|| invalid(&format!("unknown exemption type {note_type}")))?;
+            let function_name = match desc.iter().position(|byte| *byte == 0) {
+                Some(end) => &desc[..end],
+                None => desc,
+            };
+            notes.push((kind, function_name));
+            offset = next_offset;
+        }
+        Ok(notes)
+    }
+
+    /// Resolves the notes of the .sbpf.verifier section to the functions they apply to
+    fn parse_verifier_exemptions(
+        elf: &Elf64,
+        elf_bytes: &[u8],
+        text_section: &Elf64Shdr,
+    ) -> Result<Vec<VerifierExemption>, ElfError> {
+        let section_header = match get_section(elf, b".sbpf.verifier") {
+            Ok(section_header) => section_header,
+            Err(ElfError::SectionNotFound(_)) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let section_bytes = section_header
+            .file_range()
+            .and_then(|range| elf_bytes.get(range))
+            .ok_or(ElfError::ValueOutOfBounds)?;
+        let symbols = elf
+            .symbol_table()
+            .ok()
+            .flatten()
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|symbol| Some((elf.symbol_name(symbol.st_name).ok()?, symbol)))
+            .chain(
+                elf.dynamic_symbol_table()
+                    .unwrap_or(&[])
+                    .iter()
+                    .filter_map(|symbol| {
+                        Some((elf.dynamic_symbol_name(symbol.st_name).ok()?, symbol))
+                    }),
+            )
+            .filter(|(_name, symbol)| {
+                symbol.is_function() && text_section.vm_range().contains(&symbol.st_value)
+            })
+            .collect::<Vec<_>>();
+        Self::parse_verifier_notes(section_bytes)?
+            .into_iter()
+            .map(|(kind, function_name)| {
+                let function_name = String::from_utf8_lossy(function_name).to_string();
+                let symbol = symbols
+                    .iter()
+                    .find(|(name, _symbol)| *name == function_name.as_bytes())
+                    .map(|(_name, symbol)| symbol)
+                    .ok_or_else(|| {
+                        ElfError::InvalidVerifierNote(format!("unknown function {function_name}"))
+                    })?;
+                let pc = (symbol.st_value.saturating_sub(text_section.sh_addr) as usize)
+                    .checked_div(ebpf::INSN_SIZE)
+                    .unwrap_or_default();
+                Ok(VerifierExemption {
+                    kind,
+                    function_name,
+                    pc,
+                })
+            })
+            .collect()
     }
 
     #[allow(dead_code)]
@@ -1170,6 +3138,11 @@ impl<C: ContextObject> Executable<C> {
     }
 }
 
+/// Only for the repr(C) structs of integers without padding of the ELF parser
+fn struct_as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((value as *const T).cast::<u8>(), mem::size_of::<T>()) }
+}
+
 pub(crate) fn get_ro_region(ro_section: &Section, elf: &[u8]) -> MemoryRegion {
     let (offset, ro_data) = match ro_section {
         Section::Owned(offset, data) => (*offset, data.as_slice()),
@@ -1191,8 +3164,8 @@ mod test {
     use crate::{
         elf_parser::{
             // FIXME consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
-            consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
-            types::{Elf64Ehdr, Elf64Shdr},
+            consts::{ELFCLASS32, ELFDATA2MSB, ET_REL, SHF_WRITE},
+            types::{Elf64Ehdr, Elf64Shdr, Elf64Sym},
             SECTION_NAME_LENGTH_MAXIMUM,
         },
         error::ProgramResult,
@@ -1222,65 +3195,366 @@ mod test {
     }
 
     #[test]
-    fn test_validate() {
-        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
-        let elf = Elf64::parse(&elf_bytes).unwrap();
-        let mut header = elf.file_header().clone();
-
-        let config = Config::default();
-
-        let write_header = |header: Elf64Ehdr| unsafe {
-            let mut bytes = elf_bytes.clone();
-            std::ptr::write(bytes.as_mut_ptr().cast::<Elf64Ehdr>(), header);
-            bytes
+    fn test_validate() {
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        let elf = Elf64::parse(&elf_bytes).unwrap();
+        let mut header = elf.file_header().clone();
+
+        let config = Config::default();
+
+        let write_header = |header: Elf64Ehdr| unsafe {
+            let mut bytes = elf_bytes.clone();
+            std::ptr::write(bytes.as_mut_ptr().cast::<Elf64Ehdr>(), header);
+            bytes
+        };
+
+        ElfExecutable::validate(&config, &elf, &elf_bytes).expect("validation failed");
+
+        header.e_ident.ei_class = ELFCLASS32;
+        let bytes = write_header(header.clone());
+        // the new parser rejects anything other than ELFCLASS64 directly
+        Elf64::parse(&bytes).expect_err("allowed bad class");
+
+        header.e_ident.ei_class = ELFCLASS64;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect("validation failed");
+
+        header.e_ident.ei_data = ELFDATA2MSB;
+        let bytes = write_header(header.clone());
+        // the new parser only supports little endian
+        Elf64::parse(&bytes).expect_err("allowed big endian");
+
+        header.e_ident.ei_data = ELFDATA2LSB;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect("validation failed");
+
+        header.e_ident.ei_osabi = 1;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect_err("allowed wrong abi");
+
+        header.e_ident.ei_osabi = ELFOSABI_NONE;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect("validation failed");
+
+        header.e_machine = 42;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect_err("allowed wrong machine");
+
+        header.e_machine = EM_BPF;
+        let bytes = write_header(header.clone());
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect("validation failed");
+
+        header.e_type = ET_REL;
+        let bytes = write_header(header);
+        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
+            .expect_err("allowed wrong type");
+    }
+
+    #[test]
+    fn test_validate_report() {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                reject_broken_elfs: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        assert!(ElfExecutable::validate_report(&elf_bytes, loader.clone()).is_empty());
+        assert_eq!(
+            ElfExecutable::validate_report(&elf_bytes[..32], loader.clone()),
+            vec![ElfError::ValueOutOfBounds]
+        );
+
+        let mut elf_bytes = std::fs::read("tests/elfs/syscall_reloc_64_32.so").unwrap();
+        let mut header = Elf64::parse(&elf_bytes).unwrap().file_header().clone();
+        header.e_ident.ei_osabi = 1;
+        header.e_machine = 42;
+        unsafe { std::ptr::write(elf_bytes.as_mut_ptr().cast::<Elf64Ehdr>(), header) };
+        assert_eq!(
+            ElfExecutable::validate_report(&elf_bytes, loader.clone()),
+            vec![
+                ElfError::WrongAbi,
+                ElfError::WrongMachine,
+                ElfError::UnresolvedSymbol("log".to_string(), 39, 312),
+            ]
+        );
+
+        let elf_bytes = std::fs::read("tests/elfs/data_section.so").unwrap();
+        assert_eq!(
+            ElfExecutable::validate_report(&elf_bytes, loader),
+            vec![ElfError::WritableSectionNotSupported(".data".to_string())]
+        );
+    }
+
+    /// Moves the text from `split_offset` on into a .text.entry section
+    #[allow(clippy::arithmetic_side_effects)]
+    fn split_text_section(elf_bytes: &[u8], split_offset: u64, gap: u64) -> Vec<u8> {
+        let mut elf_bytes = ElfExecutable::append_section(elf_bytes, b".text.entry", &[]).unwrap();
+        let elf = Elf64::parse(&elf_bytes).unwrap();
+        let mut file_header = elf.file_header().clone();
+        let mut section_headers = elf.section_header_table().to_vec();
+        let text_index = section_headers
+            .iter()
+            .position(|section_header| elf.section_name(section_header.sh_name) == Ok(b".text"))
+            .unwrap();
+        let text_section = &mut section_headers[text_index];
+        let split_section = Elf64Shdr {
+            sh_addr: text_section.sh_addr + split_offset + gap,
+            sh_offset: text_section.sh_offset + split_offset + gap,
+            sh_size: text_section.sh_size - split_offset - gap,
+            ..text_section.clone()
+        };
+        text_section.sh_size = split_offset;
+        let mut split_section = Elf64Shdr {
+            sh_name: section_headers.pop().unwrap().sh_name,
+            ..split_section
+        };
+        // The section header table stays in file order, so the indices behind it shift
+        split_section.sh_link = 0;
+        section_headers.insert(text_index + 1, split_section);
+        for section_header in section_headers.iter_mut() {
+            if section_header.sh_link as usize > text_index {
+                section_header.sh_link += 1;
+            }
+        }
+        if file_header.e_shstrndx as usize > text_index {
+            file_header.e_shstrndx += 1;
+        }
+        let mut offset = file_header.e_shoff as usize;
+        for section_header in section_headers.iter() {
+            let bytes = struct_as_bytes(section_header);
+            elf_bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        }
+        elf_bytes[..mem::size_of::<Elf64Ehdr>()].copy_from_slice(struct_as_bytes(&file_header));
+        elf_bytes
+    }
+
+    #[test]
+    fn test_merge_text_sections() {
+        let merging_loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                merge_text_sections: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let elf_bytes =
+            std::fs::read("tests/elfs/relative_call.so").expect("failed to read elf file");
+        let expected = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        // The entrypoint is the second function, at pc 4
+        let split_elf_bytes = split_text_section(&elf_bytes, 32, 0);
+        let executable = ElfExecutable::load(&split_elf_bytes, merging_loader.clone())
+            .expect("validation failed");
+        assert_eq!(executable.get_text_bytes(), expected.get_text_bytes());
+        assert_eq!(executable.get_ro_section(), expected.get_ro_section());
+        assert_eq!(
+            executable.get_entrypoint_instruction_offset(),
+            expected.get_entrypoint_instruction_offset()
+        );
+        assert_eq!(
+            executable.get_function_registry(),
+            expected.get_function_registry()
+        );
+
+        // By default only the .text section is loaded, which ends before the entrypoint
+        assert_error!(
+            ElfExecutable::load(&split_elf_bytes, loader()),
+            "EntrypointOutOfBounds"
+        );
+
+        assert_error!(
+            ElfExecutable::load(&split_text_section(&elf_bytes, 32, 8), merging_loader),
+            "NonContiguousTextSections"
+        );
+    }
+
+    #[test]
+    fn test_reject_wx_sections() {
+        let strict_loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                reject_wx_sections: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        for path in [
+            "tests/elfs/relative_call.so",
+            "tests/elfs/reloc_64_relative_data.so",
+            "tests/elfs/rodata_section.so",
+            "tests/elfs/syscall_static.so",
+        ] {
+            let elf_bytes = std::fs::read(path).unwrap();
+            ElfExecutable::load(&elf_bytes, strict_loader.clone()).expect("validation failed");
+        }
+
+        let set_flags = |path: &str, name: &[u8], flags: u64| {
+            let mut elf_bytes = std::fs::read(path).unwrap();
+            let elf = Elf64::parse(&elf_bytes).unwrap();
+            let index = elf
+                .section_header_table()
+                .iter()
+                .position(|section_header| elf.section_name(section_header.sh_name) == Ok(name))
+                .unwrap();
+            let offset = (elf.file_header().e_shoff as usize)
+                .saturating_add(index.saturating_mul(mem::size_of::<Elf64Shdr>()))
+                .saturating_add(8);
+            elf_bytes[offset..offset.saturating_add(8)].copy_from_slice(&flags.to_le_bytes());
+            elf_bytes
+        };
+        for (path, name, flags, expected) in [
+            (
+                "tests/elfs/relative_call.so",
+                &b".text"[..],
+                SHF_ALLOC | SHF_EXECINSTR | SHF_WRITE,
+                "WritableAndExecutableSection(\".text\")",
+            ),
+            (
+                "tests/elfs/relative_call.so",
+                b".dynsym",
+                SHF_ALLOC | SHF_EXECINSTR,
+                "ExecutableSectionOutsideText(\".dynsym\")",
+            ),
+            (
+                "tests/elfs/rodata_section.so",
+                b".rodata",
+                SHF_ALLOC | SHF_WRITE,
+                "WritableSectionNotSupported(\".rodata\")",
+            ),
+        ] {
+            let elf_bytes = set_flags(path, name, flags);
+            ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+            assert_error!(
+                ElfExecutable::load(&elf_bytes, strict_loader.clone()),
+                "{}",
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_abs_relocations() {
+        // .data.rel.ro at 0x100000040 is relocated by the first entry of .rel.dyn
+        let set_relocation = |r_type: u32| {
+            let mut elf_bytes = std::fs::read("tests/elfs/reloc_64_relative_data.so").unwrap();
+            elf_bytes[0x1040..0x1048].copy_from_slice(&0x20u64.to_le_bytes());
+            elf_bytes[0x11a8..0x11ac].copy_from_slice(&r_type.to_le_bytes());
+            elf_bytes
+        };
+        let read_data = |executable: &ElfExecutable| {
+            let offset = 0x100000040u64.saturating_sub(executable.get_ro_region().vm_addr) as usize;
+            LittleEndian::read_u64(&executable.get_ro_section()[offset..])
         };
 
-        ElfExecutable::validate(&config, &elf, &elf_bytes).expect("validation failed");
-
-        header.e_ident.ei_class = ELFCLASS32;
-        let bytes = write_header(header.clone());
-        // the new parser rejects anything other than ELFCLASS64 directly
-        Elf64::parse(&bytes).expect_err("allowed bad class");
-
-        header.e_ident.ei_class = ELFCLASS64;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect("validation failed");
-
-        header.e_ident.ei_data = ELFDATA2MSB;
-        let bytes = write_header(header.clone());
-        // the new parser only supports little endian
-        Elf64::parse(&bytes).expect_err("allowed big endian");
-
-        header.e_ident.ei_data = ELFDATA2LSB;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect("validation failed");
-
-        header.e_ident.ei_osabi = 1;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect_err("allowed wrong abi");
+        let executable = ElfExecutable::load(&set_relocation(R_BPF_64_ABS64), loader()).unwrap();
+        assert_eq!(read_data(&executable), 0x100000020);
+        let executable = ElfExecutable::load(&set_relocation(R_BPF_64_NODYLD32), loader()).unwrap();
+        assert_eq!(read_data(&executable), 0x20);
+        assert_error!(
+            ElfExecutable::load(&set_relocation(R_BPF_64_ABS32), loader()),
+            "UnknownRelocation(3)"
+        );
+        assert_error!(
+            ElfExecutable::load(&set_relocation(5), loader()),
+            "UnknownRelocation(5)"
+        );
+    }
 
-        header.e_ident.ei_osabi = ELFOSABI_NONE;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect("validation failed");
+    #[test]
+    fn test_memory_layout() {
+        let region = |name: &str, vm_addr, len, writable, executable| LayoutRegion {
+            name: name.to_string(),
+            vm_addr,
+            len,
+            writable,
+            executable,
+        };
+        // SBPFv2 has dynamic stack frames, so the stack has no gaps
+        let stack_len = Config::default().stack_size() as u64;
+        let elf_bytes = std::fs::read("tests/elfs/reloc_64_relative_data.so").unwrap();
+        let executable = ElfExecutable::load(&elf_bytes, loader()).unwrap();
+        assert_eq!(
+            executable.memory_layout(),
+            vec![
+                region(".text", 0x100000000, 0x20, false, true),
+                region(".rodata", 0x100000020, 0x19, false, false),
+                region(".data.rel.ro", 0x100000040, 0x8, false, false),
+                region("[stack]", ebpf::MM_STACK_START, stack_len, true, false),
+                region("[heap]", ebpf::MM_HEAP_START, 0, true, false),
+                region("[input]", ebpf::MM_INPUT_START, 0, true, false),
+            ]
+        );
 
-        header.e_machine = 42;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect_err("allowed wrong machine");
+        let executable = ElfExecutable::new_from_text_bytes(
+            &[0x95, 0, 0, 0, 0, 0, 0, 0],
+            loader(),
+            SBPFVersion::V2,
+            FunctionRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            executable.memory_layout()[0],
+            region(".text", ebpf::MM_PROGRAM_START, 8, false, true)
+        );
+    }
 
-        header.e_machine = EM_BPF;
-        let bytes = write_header(header.clone());
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect("validation failed");
+    #[test]
+    fn test_hash() {
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        let executable = ElfExecutable::load(&elf_bytes, loader()).unwrap();
+        assert_eq!(
+            executable.hash(),
+            ElfExecutable::load(&elf_bytes, loader()).unwrap().hash()
+        );
+        // The encoding is stable, a change has to bump HASH_ENCODING_VERSION
+        assert_eq!(
+            executable.hash(),
+            [
+                163, 165, 161, 163, 93, 199, 203, 225, 145, 31, 165, 187, 185, 82, 170, 139, 146,
+                164, 198, 240, 61, 192, 146, 111, 218, 232, 203, 209, 165, 86, 198, 177,
+            ]
+        );
 
-        header.e_type = ET_REL;
-        let bytes = write_header(header);
-        ElfExecutable::validate(&config, &Elf64::parse(&bytes).unwrap(), &elf_bytes)
-            .expect_err("allowed wrong type");
+        // The entrypoint, the Config and the program change the digest
+        let other_entrypoint = ElfExecutable::load_with_entrypoint(
+            &elf_bytes,
+            loader(),
+            "_ZN13relative_call12function_sum17h374600da0fe56a1aE",
+        )
+        .unwrap();
+        assert_ne!(executable.hash(), other_entrypoint.hash());
+        let other_loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_instruction_meter: false,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let other_config = ElfExecutable::load(&elf_bytes, other_loader).unwrap();
+        assert_ne!(executable.hash(), other_config.hash());
+        // Host-local settings do not
+        let host_loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_instruction_tracing: true,
+                enable_perf_map: true,
+                jit_diversification_seed: Some(42),
+                enable_region_statistics: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let host_config = ElfExecutable::load(&elf_bytes, host_loader).unwrap();
+        assert_eq!(executable.hash(), host_config.hash());
+        let other_elf_bytes = std::fs::read("tests/elfs/reloc_64_64.so").unwrap();
+        let other_program = ElfExecutable::load(&other_elf_bytes, loader()).unwrap();
+        assert_ne!(executable.hash(), other_program.hash());
     }
 
     #[test]
@@ -1292,6 +3566,129 @@ mod test {
         ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
     }
 
+    #[test]
+    fn test_symbols() {
+        let mut file = File::open("tests/elfs/relative_call.so").expect("file open failed");
+        let mut elf_bytes = Vec::new();
+        file.read_to_end(&mut elf_bytes)
+            .expect("failed to read elf file");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        // Without labels the names of the called functions come from the symbol table
+        let function = |name: &str, pc, size| FunctionSymbol {
+            name: name.to_string(),
+            pc,
+            size,
+        };
+        assert_eq!(
+            executable.get_function_symbols(),
+            vec![
+                function(
+                    "_ZN13relative_call18function_stack_ref17h18588da2f576338eE",
+                    0,
+                    4
+                ),
+                function("entrypoint", 4, 11),
+                function(
+                    "_ZN13relative_call12function_sum17h374600da0fe56a1aE",
+                    15,
+                    3
+                ),
+            ]
+        );
+        assert!(executable.get_data_symbols().is_empty());
+
+        let mut file = File::open("tests/elfs/rodata_section.so").expect("file open failed");
+        let mut elf_bytes = Vec::new();
+        file.read_to_end(&mut elf_bytes)
+            .expect("failed to read elf file");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(
+            executable.get_function_symbols(),
+            vec![function("entrypoint", 0, 4)]
+        );
+        assert_eq!(
+            executable.get_data_symbols(),
+            vec![DataSymbol {
+                name: "_ZN14rodata_section5VAL_B17h3c7e8b8cbded1ee1E".to_string(),
+                vm_addr: ebpf::MM_PROGRAM_START + 0x20,
+                size: 8,
+            }]
+        );
+
+        let executable = ElfExecutable::from_text_bytes(
+            &[0x95, 0, 0, 0, 0, 0, 0, 0],
+            loader(),
+            SBPFVersion::V2,
+            FunctionRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            executable.get_function_symbols(),
+            vec![function("entrypoint", 0, 1)]
+        );
+        assert!(executable.get_data_symbols().is_empty());
+    }
+
+    #[test]
+    fn test_source_locations() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/relative_call.so").expect("failed to read elf file");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(executable.get_source_location(0), None);
+
+        // Line number program for the first four instructions, lines 4, 5, 7 and 8 of prog.s
+        let mut debug_line = vec![
+            0x38, 0x00, 0x00, 0x00, 0x04, 0x00, 0x1e, 0x00, 0x00, 0x00, 0x08, 0x01, 0x01, 0xfb,
+            0x0e, 0x0d, 0x00, 0x01, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01,
+            0x00, 0x70, 0x72, 0x6f, 0x67, 0x2e, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09,
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x21, 0x22, 0x21, 0x02,
+            0x01, 0x00, 0x01, 0x01,
+        ];
+        let text_address = ebpf::MM_PROGRAM_START.to_le_bytes();
+        debug_line[43..51].copy_from_slice(&text_address);
+        let elf_bytes = ElfExecutable::append_section(&elf_bytes, b".debug_line", &debug_line)
+            .expect("failed to append section");
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let location = |line| {
+            Some(SourceLocation {
+                file: "prog.s".to_string(),
+                line,
+            })
+        };
+        assert_eq!(executable.get_source_location(0).cloned(), location(4));
+        assert_eq!(executable.get_source_location(1).cloned(), location(5));
+        assert_eq!(executable.get_source_location(2).cloned(), location(7));
+        assert_eq!(executable.get_source_location(3).cloned(), location(8));
+        assert_eq!(executable.get_source_location(4), None);
+    }
+
+    #[test]
+    fn test_load_skips_debug_sections() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/relative_call.so").expect("failed to read elf file");
+        let expected = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        let debug_info = vec![0xAB; 0x1000];
+        let elf_bytes = ElfExecutable::append_section(&elf_bytes, b".debug_info", &debug_info)
+            .expect("failed to append section");
+        let elf = Elf64::parse(&elf_bytes).unwrap();
+        let debug_info_range = get_section(&elf, b".debug_info")
+            .unwrap()
+            .file_range()
+            .unwrap();
+        let executable = ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        assert_eq!(executable.elf_bytes.as_slice().len(), elf_bytes.len());
+        assert!(executable.elf_bytes.as_slice()[debug_info_range]
+            .iter()
+            .all(|byte| *byte == 0));
+        // Everything needed after loading is still there
+        assert_eq!(executable.get_text_bytes(), expected.get_text_bytes());
+        assert_eq!(executable.get_ro_section(), expected.get_ro_section());
+        assert_eq!(
+            executable.get_function_symbols(),
+            expected.get_function_symbols()
+        );
+    }
+
     #[test]
     fn test_load_unaligned() {
         let mut elf_bytes =
@@ -1303,6 +3700,67 @@ mod test {
         ElfExecutable::load(&elf_bytes[1..], loader()).expect("validation failed");
     }
 
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_load_compressed() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/relative_call.so").expect("failed to read elf file");
+        let compressed = ruzstd::encoding::compress_to_vec(
+            elf_bytes.as_slice(),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        assert_eq!(compressed[..4], ZSTD_MAGIC);
+        let executable = ElfExecutable::load(&compressed, loader()).expect("validation failed");
+        let uncompressed = ElfExecutable::load(&elf_bytes, loader()).unwrap();
+        assert_eq!(executable.get_ro_section(), uncompressed.get_ro_section());
+        assert_eq!(
+            executable.get_entrypoint_instruction_offset(),
+            uncompressed.get_entrypoint_instruction_offset()
+        );
+        assert!(ElfExecutable::validate_report(&compressed, loader()).is_empty());
+        assert_error!(
+            ElfExecutable::load(&compressed[..compressed.len() / 2], loader()),
+            "FailedToDecompress"
+        );
+
+        // Mapped files are decompressed the same way
+        #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+        {
+            let path = std::env::temp_dir().join(format!(
+                "solana_rbpf_test_load_compressed_{}.so.zst",
+                std::process::id()
+            ));
+            std::fs::write(&path, &compressed).unwrap();
+            // The decompressed ELF is a copy, so the file can be removed right away
+            let mapped = unsafe { ElfExecutable::load_file(&path, loader()) };
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(
+                mapped.unwrap().get_ro_section(),
+                uncompressed.get_ro_section()
+            );
+        }
+    }
+
+    #[test]
+    fn test_verification_budget() {
+        let elf_bytes =
+            std::fs::read("tests/elfs/reloc_64_relative_data.so").expect("failed to read elf file");
+        let loader = |verification_budget| {
+            Arc::new(BuiltinProgram::new_loader(
+                Config {
+                    verification_budget,
+                    ..Config::default()
+                },
+                FunctionRegistry::default(),
+            ))
+        };
+        ElfExecutable::load(&elf_bytes, loader(6)).expect("validation failed");
+        assert_error!(
+            ElfExecutable::load(&elf_bytes, loader(5)),
+            "VerificationBudgetExceeded(6, 5)"
+        );
+    }
+
     #[test]
     fn test_entrypoint() {
         let loader = loader();
@@ -1970,4 +4428,324 @@ mod test {
             SECTION_NAME_LENGTH_MAXIMUM
         );
     }
+
+    #[test]
+    fn test_dynamic_symbol_names() {
+        let elf_bytes = std::fs::read("tests/elfs/syscall_reloc_64_32.so").unwrap();
+        let parsed_elf = Elf64::parse(&elf_bytes).unwrap();
+        let section_header = |name: &[u8]| {
+            parsed_elf
+                .section_header_table()
+                .iter()
+                .find(|section_header| {
+                    parsed_elf.section_name(section_header.sh_name).unwrap() == name
+                })
+                .unwrap()
+                .clone()
+        };
+
+        // Remove the terminator of the last name in .dynstr, which is the syscall "log"
+        let dynstr = section_header(b".dynstr");
+        let mut long_name_bytes = elf_bytes.clone();
+        long_name_bytes[(dynstr.sh_offset + dynstr.sh_size - 1) as usize] = b'x';
+        assert_error!(
+            ElfExecutable::load(&long_name_bytes, loader()),
+            "SymbolNameTooLong(\"logx\", 64)"
+        );
+
+        // Turn the syscall into a second definition of the entrypoint one instruction later
+        let dynsym = section_header(b".dynsym");
+        let symbols = parsed_elf.dynamic_symbol_table().unwrap();
+        let mut duplicate_name_bytes = elf_bytes.clone();
+        let mut symbol = symbols[2].clone();
+        symbol.st_name = symbols[1].st_name;
+        symbol.st_info = symbols[1].st_info;
+        symbol.st_shndx = symbols[1].st_shndx;
+        symbol.st_value = symbols[1].st_value + ebpf::INSN_SIZE as u64;
+        unsafe {
+            std::ptr::write_unaligned(
+                duplicate_name_bytes
+                    .as_mut_ptr()
+                    .add(dynsym.sh_offset as usize + 2 * mem::size_of::<Elf64Sym>())
+                    .cast::<Elf64Sym>(),
+                symbol,
+            );
+        }
+        assert_error!(
+            ElfExecutable::load(&duplicate_name_bytes, loader()),
+            "DuplicateSymbolName(\"entrypoint\", 0, 1)"
+        );
+    }
+
+    fn verifier_note(name: &[u8], note_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        note.extend_from_slice(&note_type.to_le_bytes());
+        note.extend_from_slice(name);
+        note.resize(note.len().saturating_add(3) & !3, 0);
+        note.extend_from_slice(desc);
+        note.resize(note.len().saturating_add(3) & !3, 0);
+        note
+    }
+
+    #[test]
+    fn test_parse_verifier_notes() {
+        let mut section = verifier_note(b"SBPF\0", 1, b"function_foo");
+        section.extend(verifier_note(b"SBPF\0", 1, b"bar\0"));
+        assert_eq!(
+            ElfExecutable::parse_verifier_notes(&section),
+            Ok(vec![
+                (VerifierExemptionKind::CallxR10, &b"function_foo"[..]),
+                (VerifierExemptionKind::CallxR10, &b"bar"[..]),
+            ])
+        );
+        assert_eq!(ElfExecutable::parse_verifier_notes(&[]), Ok(vec![]));
+        assert_error!(
+            ElfExecutable::parse_verifier_notes(&verifier_note(b"GNU\0", 1, b"foo")),
+            "InvalidVerifierNote(\"unknown owner\")"
+        );
+        assert_error!(
+            ElfExecutable::parse_verifier_notes(&verifier_note(b"SBPF\0", 7, b"foo")),
+            "InvalidVerifierNote(\"unknown exemption type 7\")"
+        );
+        assert_error!(
+            ElfExecutable::parse_verifier_notes(&section[0..section.len().saturating_sub(4)]),
+            "InvalidVerifierNote(\"truncated descriptor\")"
+        );
+        assert_error!(
+            ElfExecutable::parse_verifier_notes(&section[0..8]),
+            "InvalidVerifierNote(\"truncated header\")"
+        );
+    }
+
+    #[test]
+    fn test_load_verifier_exemptions() {
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        let function_sum = b"_ZN13relative_call12function_sum17h374600da0fe56a1aE";
+        let mut section = verifier_note(b"SBPF\0", 1, b"entrypoint\0");
+        section.extend(verifier_note(b"SBPF\0", 1, function_sum));
+        let elf_bytes =
+            ElfExecutable::append_section(&elf_bytes, b".sbpf.verifier", &section).unwrap();
+        let executable = ElfExecutable::load(&elf_bytes, loader()).unwrap();
+        assert_eq!(
+            executable.get_verifier_exemptions(),
+            &[
+                VerifierExemption {
+                    kind: VerifierExemptionKind::CallxR10,
+                    function_name: "entrypoint".to_string(),
+                    pc: 4,
+                },
+                VerifierExemption {
+                    kind: VerifierExemptionKind::CallxR10,
+                    function_name: String::from_utf8_lossy(function_sum).to_string(),
+                    pc: 15,
+                },
+            ]
+        );
+
+        // Only functions in the text section can be exempted
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        let elf_bytes = ElfExecutable::append_section(
+            &elf_bytes,
+            b".sbpf.verifier",
+            &verifier_note(b"SBPF\0", 1, b"_DYNAMIC"),
+        )
+        .unwrap();
+        assert_error!(
+            ElfExecutable::load(&elf_bytes, loader()),
+            "InvalidVerifierNote(\"unknown function _DYNAMIC\")"
+        );
+
+        // Malformed notes fail the load
+        let elf_bytes = std::fs::read("tests/elfs/relative_call.so").unwrap();
+        let elf_bytes = ElfExecutable::append_section(
+            &elf_bytes,
+            b".sbpf.verifier",
+            &verifier_note(b"GNU\0", 1, b"entrypoint"),
+        )
+        .unwrap();
+        assert_error!(
+            ElfExecutable::load(&elf_bytes, loader()),
+            "InvalidVerifierNote(\"unknown owner\")"
+        );
+    }
+
+    #[test]
+    fn test_verifier_exemption_policy() {
+        let source = "
+            call function_foo
+            exit
+            function_foo:
+            callx r10
+            exit";
+        let loader_with_policy = |allow_callx_r10| {
+            Arc::new(BuiltinProgram::new_loader(
+                Config {
+                    verifier_exemption_policy: crate::verifier::VerifierExemptionPolicy {
+                        allow_callx_r10,
+                    },
+                    ..Config::default()
+                },
+                FunctionRegistry::default(),
+            ))
+        };
+        let exemption = VerifierExemption {
+            kind: VerifierExemptionKind::CallxR10,
+            function_name: "function_foo".to_string(),
+            pc: 2,
+        };
+
+        // Without the exemption callx r10 is rejected
+        let executable =
+            crate::assembler::assemble::<TestContextObject>(source, loader_with_policy(true))
+                .unwrap();
+        assert_error!(
+            executable.verify::<crate::verifier::RequisiteVerifier>(),
+            "InvalidRegister(2)"
+        );
+
+        // The exemption is requested but the host denies it
+        let mut executable =
+            crate::assembler::assemble::<TestContextObject>(source, loader_with_policy(false))
+                .unwrap();
+        executable.verifier_exemptions = vec![exemption.clone()];
+        assert_error!(
+            executable.verify::<crate::verifier::RequisiteVerifier>(),
+            "ExemptionDenied(CallxR10, \"function_foo\", 2)"
+        );
+
+        // The exemption is granted
+        let mut executable =
+            crate::assembler::assemble::<TestContextObject>(source, loader_with_policy(true))
+                .unwrap();
+        executable.verifier_exemptions = vec![exemption.clone()];
+        executable
+            .verify::<crate::verifier::RequisiteVerifier>()
+            .unwrap();
+
+        // The exemption does not extend to other functions
+        let mut executable = crate::assembler::assemble::<TestContextObject>(
+            "
+            call function_foo
+            callx r10
+            exit
+            function_foo:
+            exit",
+            loader_with_policy(true),
+        )
+        .unwrap();
+        executable.verifier_exemptions = vec![VerifierExemption { pc: 3, ..exemption }];
+        assert_error!(
+            executable.verify::<crate::verifier::RequisiteVerifier>(),
+            "InvalidRegister(1)"
+        );
+    }
+
+    #[test]
+    fn test_text_length_policy() {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                max_text_length: 20,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let from_text_bytes = |text_bytes: &[u8], text_length_policy| {
+            ElfExecutable::new_from_text_bytes_with_length_policy(
+                text_bytes,
+                loader.clone(),
+                SBPFVersion::V2,
+                FunctionRegistry::default(),
+                text_length_policy,
+            )
+            .map(|executable| executable.get_text_bytes().1.to_vec())
+        };
+
+        assert_error!(from_text_bytes(&[], TextLengthPolicy::Reject), "EmptyText");
+        assert_error!(
+            from_text_bytes(&[0x95; 9], TextLengthPolicy::Reject),
+            "UnalignedTextLength(9)"
+        );
+        assert_error!(
+            from_text_bytes(&[0x95; 24], TextLengthPolicy::Reject),
+            "TextTooLong(24, 20)"
+        );
+        assert_eq!(
+            from_text_bytes(&[0x95; 16], TextLengthPolicy::Reject).unwrap(),
+            [0x95; 16]
+        );
+
+        assert_eq!(from_text_bytes(&[], TextLengthPolicy::Pad).unwrap(), [0; 8]);
+        let mut padded = vec![0x95; 9];
+        padded.resize(16, 0);
+        assert_eq!(
+            from_text_bytes(&[0x95; 9], TextLengthPolicy::Pad).unwrap(),
+            padded
+        );
+        assert_eq!(
+            from_text_bytes(&[0x95; 25], TextLengthPolicy::Pad).unwrap(),
+            [0x95; 16]
+        );
+
+        assert_error!(
+            from_text_bytes(&[0x95; 7], TextLengthPolicy::Truncate),
+            "EmptyText"
+        );
+        assert_eq!(
+            from_text_bytes(&[0x95; 15], TextLengthPolicy::Truncate).unwrap(),
+            [0x95; 8]
+        );
+        assert_eq!(
+            from_text_bytes(&[0x95; 40], TextLengthPolicy::Truncate).unwrap(),
+            [0x95; 16]
+        );
+    }
+
+    #[test]
+    fn test_lddw_outside_loaded_sections() {
+        for (path, relocation) in [
+            ("tests/elfs/reloc_64_64_sbpfv1.so", "entrypoint"),
+            (
+                "tests/elfs/reloc_64_relative_sbpfv1.so",
+                "R_BPF_64_RELATIVE",
+            ),
+        ] {
+            let mut elf_bytes = std::fs::read(path).unwrap();
+            let config = Config {
+                reject_lddw_outside_loaded_sections: true,
+                ..Config::default()
+            };
+            let strict_loader = Arc::new(BuiltinProgram::new_loader(
+                config,
+                FunctionRegistry::default(),
+            ));
+            ElfExecutable::load(&elf_bytes, strict_loader.clone()).expect("validation failed");
+
+            // Let the relocated lddw point far beyond the end of the file
+            let elf = Elf64::parse(&elf_bytes).unwrap();
+            let text_range = get_section(&elf, b".text").unwrap().file_range().unwrap();
+            let r_offset = elf
+                .dynamic_relocations_table()
+                .unwrap()
+                .iter()
+                .map(|relocation| relocation.r_offset as usize)
+                .find(|r_offset| text_range.contains(r_offset))
+                .unwrap();
+            let imm_offset = r_offset + BYTE_OFFSET_IMMEDIATE;
+            LittleEndian::write_u32(&mut elf_bytes[imm_offset..imm_offset + 4], 0x1000_0000);
+            let pc = (r_offset - text_range.start) / ebpf::INSN_SIZE;
+            let Err(ElfError::LddwOutsideLoadedSections(name, error_pc, addr)) =
+                ElfExecutable::load(&elf_bytes, strict_loader)
+            else {
+                panic!("relocation outside of the loaded sections was accepted");
+            };
+            assert_eq!(name, relocation);
+            assert_eq!(error_pc, pc);
+            assert!(addr >= ebpf::MM_PROGRAM_START + 0x1000_0000);
+
+            // The default config still loads the file
+            ElfExecutable::load(&elf_bytes, loader()).expect("validation failed");
+        }
+    }
 }
@@ -166,3 +166,7 @@ pub const R_X86_64_RELATIVE64: u32 = 38;
 pub const R_X86_64_GOTPCRELX: u32 = 41;
 pub const R_X86_64_REX_GOTPCRELX: u32 = 42;
 pub const R_X86_64_NUM: u32 = 43;
+
+pub const R_BPF_64_ABS64: u32 = 2;
+pub const R_BPF_64_ABS32: u32 = 3;
+pub const R_BPF_64_NODYLD32: u32 = 4;
@@ -76,6 +76,11 @@ impl Elf64Shdr {
         self.sh_flags & (SHF_ALLOC | SHF_WRITE) == SHF_ALLOC | SHF_WRITE
     }
 
+    /// Returns whether the section is executable.
+    pub fn is_executable(&self) -> bool {
+        self.sh_flags & (SHF_ALLOC | SHF_EXECINSTR) == SHF_ALLOC | SHF_EXECINSTR
+    }
+
     /// Returns the byte range the section spans in the file.
     pub fn file_range(&self) -> Option<Range<usize>> {
         (self.sh_type != SHT_NOBITS).then(|| {
@@ -95,6 +100,11 @@ impl Elf64Sym {
     pub fn is_function(&self) -> bool {
         (self.st_info & 0xF) == STT_FUNC
     }
+
+    /// Returns whether the symbol is a data object.
+    pub fn is_object(&self) -> bool {
+        (self.st_info & 0xF) == STT_OBJECT
+    }
 }
 
 impl Elf64Rel {
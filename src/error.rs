@@ -7,13 +7,18 @@
 //! This module contains error and result types
 
 use {
-    crate::{elf::ElfError, memory_region::AccessType, verifier::VerifierError},
+    crate::{
+        elf::{ElfError, Incompatibility},
+        memory_region::AccessType,
+        verifier::VerifierError,
+    },
     std::error::Error,
 };
 
 /// Error definitions
 #[derive(Debug, thiserror::Error)]
 #[repr(u64)] // discriminant size, used in emit_exception_kind in JIT
+#[non_exhaustive]
 pub enum EbpfError {
     /// ELF error
     #[error("ELF error: {0}")]
@@ -75,10 +80,144 @@ pub enum EbpfError {
     /// Syscall error
     #[error("Syscall error: {0}")]
     SyscallError(Box<dyn Error>),
+    /// Executable can not run with the config of its loader
+    #[error("executable is incompatible with the config: {0:?}")]
+    IncompatibleExecutable(Vec<Incompatibility>),
+    /// Syscall is not registered in the loader
+    #[error("syscall #{0} is not registered")]
+    SyscallNotRegistered(u32),
+    /// No trampoline is left to inject another syscall fault
+    #[error("at most {0} syscalls can have faults injected")]
+    TooManySyscallFaults(usize),
+    /// Instruction is not (completely) inside of the program
+    #[error("instruction #{0} is out of bounds")]
+    InstructionOutOfBounds(usize),
+    /// No trampoline is left to register another legacy helper
+    #[error("at most {0} helpers can be registered")]
+    TooManyHelpers(usize),
+    /// A legacy VM was asked to run before a program was set
+    #[error("no program was set")]
+    NoProgram,
+    /// The section allocator of the JIT can not map memory twice
+    #[error("the section allocator does not support dual mapping")]
+    DualMappingUnsupported,
+    /// A VmSnapshot was restored into a VM with different memory regions
+    #[error("the snapshot does not match the memory region at {0:#x}")]
+    SnapshotMismatch(u64),
+    /// The instruction meter was exhausted and the execution can be resumed with more budget
+    #[error("execution suspended after exhausting the instruction meter")]
+    ExecutionSuspended,
+    /// A VM was asked to resume without a suspended execution
+    #[error("there is no suspended execution to resume")]
+    NotSuspended,
+    /// A typed guest access is not aligned to its type
+    #[error("guest address {0:#x} is not aligned to {1} bytes")]
+    UnalignedGuestAccess(u64, u64),
+    /// A translated reference into guest memory overlaps a mutable one
+    #[error("guest range at {0:#x} of {1} bytes aliases a mutable reference")]
+    AliasedGuestAccess(u64, u64),
+    /// A load or store touched a watched address range
+    #[error("watchpoint hit by {0:?} of {2} bytes at {1:#x}")]
+    WatchpointHit(AccessType, u64, u64),
+    /// A suspended execution was resumed with another executable than it was suspended in
+    #[error("the suspended execution belongs to another executable")]
+    ResumedWithOtherExecutable,
 }
 
+/// Error which replaces a syscall error, so that it can leave the thread it occurred on
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct SyscallErrorMessage(String);
+
+/// Declares SendableError, its conversions and the equality of EbpfError from a list of all
+/// EbpfError variants but the SyscallError, naming the fields of each
+///
+/// The conversions match exhaustively, so a variant missing from the list does not compile.
+macro_rules! declare_sendable_error {
+    ($($variant:ident $(($($field:ident: $type:ty),+))?,)*) => {
+        /// Mirror of EbpfError which only keeps the message of a syscall error, so that it is Send
+        #[derive(Debug)]
+        pub(crate) enum SendableError {
+            SyscallError(String),
+            $($variant $(($($type),+))?,)*
+        }
+
+        impl From<EbpfError> for SendableError {
+            fn from(error: EbpfError) -> Self {
+                match error {
+                    EbpfError::SyscallError(error) => Self::SyscallError(error.to_string()),
+                    $(EbpfError::$variant $(($($field),+))? => Self::$variant $(($($field),+))?,)*
+                }
+            }
+        }
+
+        impl PartialEq for EbpfError {
+            /// Syscall errors are equal if their messages are
+            fn eq(&self, other: &Self) -> bool {
+                match self {
+                    Self::SyscallError(error) => matches!(
+                        other,
+                        Self::SyscallError(other_error) if error.to_string() == other_error.to_string()
+                    ),
+                    $(Self::$variant $(($($field),+))? => {
+                        let fields = ($($($field,)+)?);
+                        matches!(other, Self::$variant $(($($field),+))? if fields == ($($($field,)+)?))
+                    })*
+                }
+            }
+        }
+
+        impl From<SendableError> for EbpfError {
+            fn from(error: SendableError) -> Self {
+                match error {
+                    SendableError::SyscallError(message) => {
+                        Self::SyscallError(Box::new(SyscallErrorMessage(message)))
+                    }
+                    $(SendableError::$variant $(($($field),+))? => Self::$variant $(($($field),+))?,)*
+                }
+            }
+        }
+    };
+}
+
+declare_sendable_error!(
+    ElfError(value: ElfError),
+    FunctionAlreadyRegistered(value: usize),
+    CallDepthExceeded,
+    ExitRootCallFrame,
+    DivideByZero,
+    DivideOverflow,
+    ExecutionOverrun,
+    CallOutsideTextSegment,
+    ExceededMaxInstructions,
+    JitNotCompiled,
+    InvalidVirtualAddress(value: u64),
+    InvalidMemoryRegion(value: usize),
+    AccessViolation(access_type: AccessType, vm_addr: u64, len: u64, name: &'static str),
+    StackAccessViolation(access_type: AccessType, vm_addr: u64, len: u64, frame: i64),
+    InvalidInstruction,
+    UnsupportedInstruction,
+    ExhaustedTextSegment(value: usize),
+    LibcInvocationFailed(name: &'static str, arguments: Vec<String>, error_code: i32),
+    VerifierError(value: VerifierError),
+    IncompatibleExecutable(value: Vec<Incompatibility>),
+    SyscallNotRegistered(value: u32),
+    TooManySyscallFaults(value: usize),
+    InstructionOutOfBounds(value: usize),
+    TooManyHelpers(value: usize),
+    NoProgram,
+    DualMappingUnsupported,
+    SnapshotMismatch(value: u64),
+    ExecutionSuspended,
+    NotSuspended,
+    UnalignedGuestAccess(vm_addr: u64, align: u64),
+    AliasedGuestAccess(vm_addr: u64, len: u64),
+    WatchpointHit(access_type: AccessType, vm_addr: u64, len: u64),
+    ResumedWithOtherExecutable,
+);
+
 /// Same as `Result` but provides a stable memory layout
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[repr(C, u64)]
 pub enum StableResult<T, E> {
     /// Success
@@ -181,4 +320,31 @@ mod tests {
         let err = ProgramResult::Err(EbpfError::JitNotCompiled);
         assert_eq!(err.discriminant(), 1);
     }
+
+    #[test]
+    fn test_program_result_eq() {
+        assert_eq!(ProgramResult::Ok(42), ProgramResult::Ok(42));
+        assert_ne!(ProgramResult::Ok(42), ProgramResult::Ok(43));
+        assert_ne!(
+            ProgramResult::Ok(0),
+            ProgramResult::Err(EbpfError::JitNotCompiled)
+        );
+        assert_eq!(EbpfError::DivideByZero, EbpfError::DivideByZero);
+        assert_ne!(EbpfError::DivideByZero, EbpfError::DivideOverflow);
+        assert_eq!(
+            EbpfError::AccessViolation(AccessType::Load, 0x100000000, 8, "program"),
+            EbpfError::AccessViolation(AccessType::Load, 0x100000000, 8, "program")
+        );
+        assert_ne!(
+            EbpfError::AccessViolation(AccessType::Load, 0x100000000, 8, "program"),
+            EbpfError::AccessViolation(AccessType::Store, 0x100000000, 8, "program")
+        );
+        // Syscall errors are compared by their message
+        let syscall_error = |message: &str| {
+            EbpfError::SyscallError(Box::new(SyscallErrorMessage(message.to_string())))
+        };
+        assert_eq!(syscall_error("failed"), syscall_error("failed"));
+        assert_ne!(syscall_error("failed"), syscall_error("aborted"));
+        assert_ne!(syscall_error("failed"), EbpfError::DivideByZero);
+    }
 }
@@ -1,7 +1,15 @@
-//! This module defines memory regions
+//! Utilities for fuzzing and for triaging its findings
 
 use rand::Rng;
 use std::ops::Range;
+#[cfg(feature = "unstable")]
+use {
+    crate::{
+        ebpf::{self, Insn},
+        program::{FunctionRegistry, SBPFVersion},
+    },
+    std::convert::TryFrom,
+};
 
 /// fuzzing utility function
 pub fn fuzz<F>(
@@ -25,3 +33,225 @@ pub fn fuzz<F>(
         }
     }
 }
+
+/// Program as a list of instructions, an `lddw` and its second half form one entry
+#[cfg(feature = "unstable")]
+struct MinimizerProgram {
+    instructions: Vec<Vec<Insn>>,
+    /// Names and pcs of the functions
+    functions: Vec<(Vec<u8>, usize)>,
+}
+
+#[cfg(feature = "unstable")]
+impl MinimizerProgram {
+    fn new(
+        prog: &[u8],
+        function_registry: &FunctionRegistry<usize>,
+        sbpf_version: &SBPFVersion,
+    ) -> Self {
+        let mut instructions = Vec::new();
+        let mut pc = 0;
+        while pc < prog.len() / ebpf::INSN_SIZE {
+            let insn = ebpf::get_insn(prog, pc);
+            let length = if insn.opc == ebpf::LD_DW_IMM && sbpf_version.enable_lddw() {
+                2
+            } else {
+                1
+            };
+            instructions.push(
+                (pc..pc.saturating_add(length).min(prog.len() / ebpf::INSN_SIZE))
+                    .map(|pc| ebpf::get_insn(prog, pc))
+                    .collect(),
+            );
+            pc = pc.saturating_add(length);
+        }
+        Self {
+            instructions,
+            functions: function_registry
+                .iter()
+                .map(|(_key, (name, pc))| (name.to_vec(), pc))
+                .collect(),
+        }
+    }
+
+    fn text_bytes(&self) -> Vec<u8> {
+        self.instructions
+            .iter()
+            .flatten()
+            .flat_map(|insn| insn.to_array())
+            .collect()
+    }
+
+    fn function_registry(&self, sbpf_version: &SBPFVersion) -> Option<FunctionRegistry<usize>> {
+        let mut function_registry = FunctionRegistry::default();
+        for (name, pc) in self.functions.iter() {
+            let key = if sbpf_version.static_syscalls() {
+                *pc as u32
+            } else {
+                ebpf::hash_symbol_name(name)
+            };
+            function_registry
+                .register_function(key, name.as_slice(), *pc)
+                .ok()?;
+        }
+        Some(function_registry)
+    }
+
+    /// Removes the instructions in `range` and retargets the jumps, calls and functions
+    ///
+    /// Whatever pointed into the removed range points to the next remaining instruction.
+    fn without(&self, range: Range<usize>, sbpf_version: &SBPFVersion) -> Self {
+        let mut new_pcs = Vec::new();
+        let mut new_pc = 0usize;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            let removed = range.contains(&index);
+            for _ in instruction {
+                new_pcs.push(new_pc);
+                if !removed {
+                    new_pc = new_pc.saturating_add(1);
+                }
+            }
+        }
+        new_pcs.push(new_pc);
+        let relocate = |target: i64| -> Option<i64> {
+            usize::try_from(target)
+                .ok()
+                .and_then(|target| new_pcs.get(target))
+                .map(|new_pc| *new_pc as i64)
+        };
+        let mut instructions = Vec::with_capacity(self.instructions.len());
+        let mut old_pc = 0usize;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if !range.contains(&index) {
+                let mut instruction = instruction.clone();
+                let insn = &mut instruction[0];
+                let pc = new_pcs[old_pc] as i64;
+                if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP
+                    && !matches!(insn.opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT)
+                {
+                    let target = (old_pc as i64)
+                        .saturating_add(insn.off as i64)
+                        .saturating_add(1);
+                    if let Some(new_target) = relocate(target) {
+                        insn.off = new_target.saturating_sub(pc).saturating_sub(1) as i16;
+                    }
+                } else if insn.opc == ebpf::CALL_IMM
+                    && sbpf_version.static_syscalls()
+                    && insn.src != 0
+                {
+                    if let Some(new_target) = relocate(insn.imm) {
+                        insn.imm = new_target;
+                    }
+                }
+                instructions.push(instruction);
+            }
+            old_pc = old_pc.saturating_add(instruction.len());
+        }
+        Self {
+            instructions,
+            functions: self
+                .functions
+                .iter()
+                .map(|(name, pc)| (name.clone(), new_pcs.get(*pc).copied().unwrap_or(*pc)))
+                .collect(),
+        }
+    }
+
+    /// Simpler variants of the instruction at `index`: A no-op and a zero immediate
+    fn neutralizations(&self, index: usize) -> Vec<Self> {
+        let insn = &self.instructions[index][0];
+        let is_control_flow = insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP;
+        let mut replacements = Vec::new();
+        if !(insn.opc == ebpf::JA && insn.off == 0) && self.instructions[index].len() == 1 {
+            replacements.push(Insn {
+                opc: ebpf::JA,
+                ..Insn::default()
+            });
+        }
+        if insn.imm != 0 && !is_control_flow && self.instructions[index].len() == 1 {
+            replacements.push(Insn {
+                imm: 0,
+                ..insn.clone()
+            });
+        }
+        replacements
+            .into_iter()
+            .map(|replacement| {
+                let mut instructions = self.instructions.clone();
+                instructions[index] = vec![Insn {
+                    ptr: insn.ptr,
+                    ..replacement
+                }];
+                Self {
+                    instructions,
+                    functions: self.functions.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Shrinks a program while `predicate` holds, e.g. to obtain a minimal reproducer of a fuzzer finding
+///
+/// The predicate is given the text bytes and function registry of every candidate, e.g. to check
+/// that the JIT and the interpreter still diverge or that the verifier still rejects it. This
+/// is a delta debugging which removes ever smaller groups of instructions (keeping `lddw`
+/// intact) and then replaces the remaining ones with no-ops or zero immediates. Jumps, internal
+/// calls and functions which refer to a removed instruction are retargeted to the next
+/// remaining one. If the predicate does not hold for the given program, it is returned as is.
+#[cfg(feature = "unstable")]
+pub fn minimize<F>(
+    prog: &[u8],
+    function_registry: &FunctionRegistry<usize>,
+    sbpf_version: &SBPFVersion,
+    mut predicate: F,
+) -> (Vec<u8>, FunctionRegistry<usize>)
+where
+    F: FnMut(&[u8], FunctionRegistry<usize>) -> bool,
+{
+    let mut program = MinimizerProgram::new(prog, function_registry, sbpf_version);
+    let mut test = |candidate: &MinimizerProgram| {
+        candidate
+            .function_registry(sbpf_version)
+            .map(|function_registry| predicate(&candidate.text_bytes(), function_registry))
+            .unwrap_or(false)
+    };
+    if test(&program) {
+        // Every round goes from large groups of instructions down to single ones
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let mut chunk_length = program.instructions.len();
+            loop {
+                chunk_length = chunk_length.div_ceil(2).max(1);
+                let mut start = 0;
+                while start < program.instructions.len() {
+                    let end = start
+                        .saturating_add(chunk_length)
+                        .min(program.instructions.len());
+                    let candidate = program.without(start..end, sbpf_version);
+                    if test(&candidate) {
+                        program = candidate;
+                        progress = true;
+                    } else {
+                        start = end;
+                    }
+                }
+                if chunk_length == 1 {
+                    break;
+                }
+            }
+            for index in 0..program.instructions.len() {
+                for candidate in program.neutralizations(index) {
+                    if test(&candidate) {
+                        program = candidate;
+                        progress = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let function_registry = program.function_registry(sbpf_version).unwrap_or_default();
+    (program.text_bytes(), function_registry)
+}
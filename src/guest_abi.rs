@@ -0,0 +1,522 @@
+//! Typed access to structured data in guest memory
+//!
+//! Syscalls which exchange structs with the program implement [GuestAbi] for a `#[repr(C)]`
+//! type, usually through [declare_guest_abi](crate::declare_guest_abi), and read or write it
+//! as a whole instead of computing the offsets of the individual fields. Every access is bounds
+//! checked through the [MemoryMapping] and requires the virtual address to be aligned to the
+//! alignment of the type. Slices, strings and structs can also be borrowed in place with
+//! `MemoryMapping::translate_slice()` and its siblings.
+//!
+//! ```
+//! use solana_rbpf::{
+//!     declare_guest_abi, ebpf,
+//!     guest_abi::GuestAbi,
+//!     memory_region::{MemoryMapping, MemoryRegion},
+//!     program::SBPFVersion,
+//!     vm::Config,
+//! };
+//!
+//! declare_guest_abi!(
+//!     /// Slice descriptor as laid out by the program
+//!     pub struct GuestSlice {
+//!         /// Virtual address of the first element
+//!         pub addr: u64,
+//!         /// Number of elements
+//!         pub len: u64,
+//!     }
+//! );
+//!
+//! let config = Config::default();
+//! let mut mem = [0u8; 16];
+//! let regions = vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_PROGRAM_START)];
+//! let memory_mapping = MemoryMapping::new(regions, &config, &SBPFVersion::V2).unwrap();
+//! let slice = GuestSlice { addr: 0x400000000, len: 3 };
+//! slice.write_to(&memory_mapping, ebpf::MM_PROGRAM_START).unwrap();
+//! assert_eq!(GuestSlice::read_from(&memory_mapping, ebpf::MM_PROGRAM_START).unwrap(), slice);
+//! ```
+
+use crate::{
+    error::EbpfError,
+    memory_region::{AccessType, MemoryMapping},
+};
+use std::{cell::RefCell, mem, ops::Range, ptr, slice, str};
+
+/// Type which is exchanged with the program by value
+///
+/// # Safety
+/// The type is `#[repr(C)]` (or a primitive or an array thereof), has no padding and every bit
+/// pattern is a valid value of it.
+pub unsafe trait GuestAbi: Copy {
+    /// Reads a value from the guest at `vm_addr`
+    fn read_from(memory_mapping: &MemoryMapping, vm_addr: u64) -> Result<Self, EbpfError> {
+        let host_addr = translate::<Self>(memory_mapping, AccessType::Load, vm_addr)?;
+        Ok(unsafe { ptr::read_unaligned(host_addr as *const Self) })
+    }
+
+    /// Writes this value to the guest at `vm_addr`
+    fn write_to(&self, memory_mapping: &MemoryMapping, vm_addr: u64) -> Result<(), EbpfError> {
+        let host_addr = translate::<Self>(memory_mapping, AccessType::Store, vm_addr)?;
+        unsafe { ptr::write_unaligned(host_addr as *mut Self, *self) };
+        Ok(())
+    }
+}
+
+fn translate<T: GuestAbi>(
+    memory_mapping: &MemoryMapping,
+    access_type: AccessType,
+    vm_addr: u64,
+) -> Result<u64, EbpfError> {
+    let align = mem::align_of::<T>() as u64;
+    if vm_addr.checked_rem(align).unwrap_or_default() != 0 {
+        return Err(EbpfError::UnalignedGuestAccess(vm_addr, align));
+    }
+    memory_mapping
+        .map(access_type, vm_addr, mem::size_of::<T>() as u64)
+        .into()
+}
+
+/// Host memory which translated references point to
+///
+/// Rejects a translation which overlaps an earlier one if either of them is mutable, so that
+/// the references never alias. See [MemoryMapping::guest_args].
+#[derive(Debug, Default)]
+pub struct GuestBorrows(RefCell<Vec<(Range<u64>, bool)>>);
+
+impl GuestBorrows {
+    /// Records a borrow of `len` bytes at `host_addr`, which `vm_addr` was translated to
+    fn borrow(
+        &self,
+        vm_addr: u64,
+        host_addr: u64,
+        len: u64,
+        mutable: bool,
+    ) -> Result<(), EbpfError> {
+        if len == 0 {
+            return Ok(());
+        }
+        let range = host_addr..host_addr.saturating_add(len);
+        let mut borrowed = self.0.borrow_mut();
+        if borrowed.iter().any(|(other, other_mutable)| {
+            (mutable || *other_mutable) && other.start < range.end && range.start < other.end
+        }) {
+            return Err(EbpfError::AliasedGuestAccess(vm_addr, len));
+        }
+        borrowed.push((range, mutable));
+        Ok(())
+    }
+}
+
+/// Typed parameter of a syscall declared with [declare_syscall](crate::declare_syscall)
+///
+/// Occupies `REGISTERS` of the five argument registers, in the order of the parameters.
+/// References and slices are translated through the [MemoryMapping] and borrow the host memory
+/// of the program for the duration of the syscall. Parameters which overlap a mutable one fail
+/// with `EbpfError::AliasedGuestAccess`.
+pub trait SyscallArg: Sized {
+    /// Number of argument registers the parameter occupies
+    const REGISTERS: usize;
+
+    /// Translates the parameter from its argument registers
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError>;
+}
+
+impl SyscallArg for u64 {
+    const REGISTERS: usize = 1;
+
+    fn translate(
+        _memory_mapping: &MemoryMapping,
+        _borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        Ok(registers[0])
+    }
+}
+
+impl SyscallArg for i64 {
+    const REGISTERS: usize = 1;
+
+    fn translate(
+        _memory_mapping: &MemoryMapping,
+        _borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        Ok(registers[0] as i64)
+    }
+}
+
+/// A value at the address in the register
+impl<T: GuestAbi> SyscallArg for &T {
+    const REGISTERS: usize = 1;
+
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        let host_addr = translate_slice::<T>(memory_mapping, borrows, false, registers[0], 1)?;
+        Ok(unsafe { &*(host_addr as *const T) })
+    }
+}
+
+/// A value at the address in the register
+impl<T: GuestAbi> SyscallArg for &mut T {
+    const REGISTERS: usize = 1;
+
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        let host_addr = translate_slice::<T>(memory_mapping, borrows, true, registers[0], 1)?;
+        Ok(unsafe { &mut *(host_addr as *mut T) })
+    }
+}
+
+/// Elements at the address in the first register, their number in the second one
+impl<T: GuestAbi> SyscallArg for &[T] {
+    const REGISTERS: usize = 2;
+
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        let (vm_addr, len) = (registers[0], registers[1]);
+        let host_addr = translate_slice::<T>(memory_mapping, borrows, false, vm_addr, len)?;
+        Ok(unsafe { slice::from_raw_parts(host_addr as *const T, len as usize) })
+    }
+}
+
+/// Elements at the address in the first register, their number in the second one
+impl<T: GuestAbi> SyscallArg for &mut [T] {
+    const REGISTERS: usize = 2;
+
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        let (vm_addr, len) = (registers[0], registers[1]);
+        let host_addr = translate_slice::<T>(memory_mapping, borrows, true, vm_addr, len)?;
+        Ok(unsafe { slice::from_raw_parts_mut(host_addr as *mut T, len as usize) })
+    }
+}
+
+/// UTF-8 bytes at the address in the first register, their number in the second one
+impl SyscallArg for &str {
+    const REGISTERS: usize = 2;
+
+    fn translate(
+        memory_mapping: &MemoryMapping,
+        borrows: &GuestBorrows,
+        registers: &[u64],
+    ) -> Result<Self, EbpfError> {
+        let bytes = <&[u8]>::translate(memory_mapping, borrows, registers)?;
+        str::from_utf8(bytes).map_err(|err| EbpfError::SyscallError(Box::new(err)))
+    }
+}
+
+/// Returns the host address of `len` elements at `vm_addr` and records the borrow of them
+///
+/// Empty slices are not translated, so that programs can pass any address with them.
+fn translate_slice<T: GuestAbi>(
+    memory_mapping: &MemoryMapping,
+    borrows: &GuestBorrows,
+    mutable: bool,
+    vm_addr: u64,
+    len: u64,
+) -> Result<u64, EbpfError> {
+    let align = mem::align_of::<T>() as u64;
+    if len == 0 {
+        return Ok(align);
+    }
+    if vm_addr.checked_rem(align).unwrap_or_default() != 0 {
+        return Err(EbpfError::UnalignedGuestAccess(vm_addr, align));
+    }
+    // An overflowing size can not be mapped either
+    let size = len.saturating_mul(mem::size_of::<T>() as u64);
+    let access_type = if mutable {
+        AccessType::Store
+    } else {
+        AccessType::Load
+    };
+    let host_addr: Result<u64, EbpfError> = memory_mapping.map(access_type, vm_addr, size).into();
+    let host_addr = host_addr?;
+    // References require the host memory to be aligned too, which the guest can not ensure
+    if host_addr.checked_rem(align).unwrap_or_default() != 0 {
+        return Err(EbpfError::UnalignedGuestAccess(vm_addr, align));
+    }
+    borrows.borrow(vm_addr, host_addr, size, mutable)?;
+    Ok(host_addr)
+}
+
+/// Bounds and alignment checked references into guest memory, e.g. for syscall arguments
+///
+/// The references must not be held while the program runs, and the guest memory they point to
+/// must not be written through the mapping in the meantime.
+impl<'a> MemoryMapping<'a> {
+    /// Returns the `len` elements of type `T` at `vm_addr`
+    ///
+    /// Fails if `vm_addr` is not aligned to `T` or the elements are not mapped.
+    pub fn translate_slice<T: GuestAbi>(&self, vm_addr: u64, len: u64) -> Result<&[T], EbpfError> {
+        <&[T]>::translate(self, &GuestBorrows::default(), &[vm_addr, len])
+    }
+
+    /// Returns the `len` bytes at `vm_addr`, which must be valid UTF-8
+    pub fn translate_string(&self, vm_addr: u64, len: u64) -> Result<&str, EbpfError> {
+        <&str>::translate(self, &GuestBorrows::default(), &[vm_addr, len])
+    }
+
+    /// Returns the `T` at `vm_addr`, see `translate_slice()`
+    pub fn translate_struct<T: GuestAbi>(&self, vm_addr: u64) -> Result<&T, EbpfError> {
+        <&T>::translate(self, &GuestBorrows::default(), &[vm_addr])
+    }
+
+    /// Same as `translate_slice()` but the elements are writable
+    ///
+    /// Borrows the mapping exclusively, see `guest_args()` to hold several references at once.
+    pub fn translate_slice_mut<T: GuestAbi>(
+        &mut self,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<&mut [T], EbpfError> {
+        <&mut [T]>::translate(self, &GuestBorrows::default(), &[vm_addr, len])
+    }
+
+    /// Same as `translate_struct()` but the `T` is writable
+    pub fn translate_struct_mut<T: GuestAbi>(&mut self, vm_addr: u64) -> Result<&mut T, EbpfError> {
+        <&mut T>::translate(self, &GuestBorrows::default(), &[vm_addr])
+    }
+
+    /// Translates several references which are held at the same time
+    ///
+    /// A reference which overlaps a mutable one fails with `EbpfError::AliasedGuestAccess`.
+    pub fn guest_args(&mut self) -> GuestArgs<'_, 'a> {
+        GuestArgs {
+            memory_mapping: self,
+            borrows: GuestBorrows::default(),
+        }
+    }
+}
+
+/// References into guest memory which do not alias, see [MemoryMapping::guest_args]
+#[derive(Debug)]
+pub struct GuestArgs<'b, 'a> {
+    memory_mapping: &'b MemoryMapping<'a>,
+    borrows: GuestBorrows,
+}
+
+impl GuestArgs<'_, '_> {
+    /// See [MemoryMapping::translate_slice]
+    pub fn translate_slice<T: GuestAbi>(&self, vm_addr: u64, len: u64) -> Result<&[T], EbpfError> {
+        <&[T]>::translate(self.memory_mapping, &self.borrows, &[vm_addr, len])
+    }
+
+    /// See [MemoryMapping::translate_string]
+    pub fn translate_string(&self, vm_addr: u64, len: u64) -> Result<&str, EbpfError> {
+        <&str>::translate(self.memory_mapping, &self.borrows, &[vm_addr, len])
+    }
+
+    /// See [MemoryMapping::translate_struct]
+    pub fn translate_struct<T: GuestAbi>(&self, vm_addr: u64) -> Result<&T, EbpfError> {
+        <&T>::translate(self.memory_mapping, &self.borrows, &[vm_addr])
+    }
+
+    /// See [MemoryMapping::translate_slice_mut]
+    #[allow(clippy::mut_from_ref)]
+    pub fn translate_slice_mut<T: GuestAbi>(
+        &self,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<&mut [T], EbpfError> {
+        <&mut [T]>::translate(self.memory_mapping, &self.borrows, &[vm_addr, len])
+    }
+
+    /// See [MemoryMapping::translate_struct_mut]
+    #[allow(clippy::mut_from_ref)]
+    pub fn translate_struct_mut<T: GuestAbi>(&self, vm_addr: u64) -> Result<&mut T, EbpfError> {
+        <&mut T>::translate(self.memory_mapping, &self.borrows, &[vm_addr])
+    }
+}
+
+unsafe impl GuestAbi for u8 {}
+unsafe impl GuestAbi for u16 {}
+unsafe impl GuestAbi for u32 {}
+unsafe impl GuestAbi for u64 {}
+unsafe impl GuestAbi for i8 {}
+unsafe impl GuestAbi for i16 {}
+unsafe impl GuestAbi for i32 {}
+unsafe impl GuestAbi for i64 {}
+unsafe impl<T: GuestAbi, const N: usize> GuestAbi for [T; N] {}
+
+/// Declares a `#[repr(C)]` struct and implements [GuestAbi] for it
+///
+/// All fields must implement [GuestAbi] themselves and the struct must not contain padding,
+/// both is checked at compile time.
+#[cfg(feature = "unstable")]
+#[macro_export]
+macro_rules! declare_guest_abi {
+    ($(#[$attr:meta])* $vis:vis struct $name:ident {
+        $($(#[$field_attr:meta])* $field_vis:vis $field:ident : $field_type:ty),* $(,)?
+    }) => {
+        $(#[$attr])*
+        #[repr(C)]
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+        $vis struct $name {
+            $($(#[$field_attr])* $field_vis $field: $field_type),*
+        }
+        #[allow(clippy::arithmetic_side_effects)]
+        const _: () = {
+            const fn assert_guest_abi<T: $crate::guest_abi::GuestAbi>() {}
+            $(assert_guest_abi::<$field_type>();)*
+            assert!(
+                std::mem::size_of::<$name>() == 0 $(+ std::mem::size_of::<$field_type>())*,
+                "struct contains padding"
+            );
+        };
+        unsafe impl $crate::guest_abi::GuestAbi for $name {}
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ebpf::MM_PROGRAM_START, memory_region::MemoryRegion, program::SBPFVersion, vm::Config,
+    };
+    use test_utils::assert_error;
+
+    declare_guest_abi!(
+        struct Account {
+            key: [u8; 8],
+            lamports: u64,
+            data_len: u32,
+            flags: u32,
+        }
+    );
+
+    #[test]
+    fn test_guest_abi() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem = [0u8; 32];
+            let regions = vec![MemoryRegion::new_writable(&mut mem, MM_PROGRAM_START)];
+            let memory_mapping = MemoryMapping::new(regions, &config, &SBPFVersion::V2).unwrap();
+            let account = Account {
+                key: *b"abcdefgh",
+                lamports: 0x0102030405060708,
+                data_len: 42,
+                flags: 1,
+            };
+            account
+                .write_to(&memory_mapping, MM_PROGRAM_START + 8)
+                .unwrap();
+            assert_eq!(
+                Account::read_from(&memory_mapping, MM_PROGRAM_START + 8).unwrap(),
+                account
+            );
+            assert_eq!(
+                u32::read_from(&memory_mapping, MM_PROGRAM_START + 24).unwrap(),
+                42
+            );
+            assert_error!(
+                Account::read_from(&memory_mapping, MM_PROGRAM_START + 4),
+                "UnalignedGuestAccess(4294967300, 8)"
+            );
+            assert_error!(
+                account.write_to(&memory_mapping, MM_PROGRAM_START + 16),
+                "AccessViolation(Store, 4294967312, 24"
+            );
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem = [0u64; 4];
+            mem[0] = u64::from_le_bytes(*b"hello\xff\0\0");
+            let regions = vec![MemoryRegion::new_writable(
+                unsafe { slice::from_raw_parts_mut(mem.as_mut_ptr().cast::<u8>(), 32) },
+                MM_PROGRAM_START,
+            )];
+            let mut memory_mapping =
+                MemoryMapping::new(regions, &config, &SBPFVersion::V2).unwrap();
+
+            assert_eq!(
+                memory_mapping
+                    .translate_string(MM_PROGRAM_START, 5)
+                    .unwrap(),
+                "hello"
+            );
+            assert_error!(
+                memory_mapping.translate_string(MM_PROGRAM_START, 6),
+                "SyscallError(Utf8Error"
+            );
+            assert_eq!(
+                memory_mapping
+                    .translate_slice::<u16>(MM_PROGRAM_START + 2, 2)
+                    .unwrap(),
+                &[u16::from_le_bytes(*b"ll"), u16::from_le_bytes(*b"o\xff")]
+            );
+            assert_error!(
+                memory_mapping.translate_slice::<u16>(MM_PROGRAM_START + 1, 2),
+                "UnalignedGuestAccess(4294967297, 2)"
+            );
+            assert_error!(
+                memory_mapping.translate_slice::<u64>(MM_PROGRAM_START + 8, u64::MAX),
+                "AccessViolation(Load, 4294967304, 18446744073709551615"
+            );
+            // Empty slices are not translated
+            assert!(memory_mapping
+                .translate_slice::<u64>(0, 0)
+                .unwrap()
+                .is_empty());
+            *memory_mapping
+                .translate_struct_mut::<u64>(MM_PROGRAM_START + 8)
+                .unwrap() = 42;
+            assert_eq!(
+                *memory_mapping
+                    .translate_struct::<u64>(MM_PROGRAM_START + 8)
+                    .unwrap(),
+                42
+            );
+            memory_mapping
+                .translate_slice_mut::<u32>(MM_PROGRAM_START + 16, 4)
+                .unwrap()
+                .fill(7);
+
+            let args = memory_mapping.guest_args();
+            let source = args
+                .translate_slice::<u64>(MM_PROGRAM_START + 16, 2)
+                .unwrap();
+            // Shared references may overlap
+            assert_eq!(
+                args.translate_struct::<u64>(MM_PROGRAM_START + 24).unwrap(),
+                &source[1]
+            );
+            assert_error!(
+                args.translate_struct_mut::<u32>(MM_PROGRAM_START + 20),
+                "AliasedGuestAccess(4294967316, 4)"
+            );
+            let destination = args
+                .translate_slice_mut::<u64>(MM_PROGRAM_START, 2)
+                .unwrap();
+            assert_error!(
+                args.translate_string(MM_PROGRAM_START + 15, 1),
+                "AliasedGuestAccess(4294967311, 1)"
+            );
+            destination.copy_from_slice(source);
+        }
+    }
+}
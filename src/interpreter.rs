@@ -16,17 +16,29 @@ use crate::{
     ebpf::{self, STACK_PTR_REG},
     elf::Executable,
     error::{EbpfError, ProgramResult},
+    program::charge_syscall_cost,
     vm::{Config, ContextObject, EbpfVm},
 };
+#[cfg(feature = "unstable")]
+use crate::{
+    recorder::{ExecutionRecord, ExecutionRecorder},
+    vm::VmSnapshot,
+};
 
 /// Virtual memory operation helper.
 macro_rules! translate_memory_access {
-    (_impl, $self:ident, $op:ident, $vm_addr:ident, $T:ty, $($rest:expr),*) => {
+    (_impl, $self:ident, $op:ident, $is_store:expr, $vm_addr:ident, $T:ty, $($rest:expr),*) => {
         match $self.vm.memory_mapping.$op::<$T>(
             $($rest,)*
             $vm_addr,
         ) {
-            ProgramResult::Ok(v) => v,
+            ProgramResult::Ok(v) => {
+                #[cfg(feature = "unstable")]
+                if !$is_store {
+                    $self.vm.detect_uninitialized_read($self.reg[11], $vm_addr, std::mem::size_of::<$T>() as u64);
+                }
+                v
+            },
             ProgramResult::Err(err) => {
                 throw_error!($self, err);
             },
@@ -35,17 +47,25 @@ macro_rules! translate_memory_access {
 
     // MemoryMapping::load()
     ($self:ident, load, $vm_addr:ident, $T:ty) => {
-        translate_memory_access!(_impl, $self, load, $vm_addr, $T,)
+        translate_memory_access!(_impl, $self, load, false, $vm_addr, $T,)
     };
 
     // MemoryMapping::store()
     ($self:ident, store, $value:expr, $vm_addr:ident, $T:ty) => {
-        translate_memory_access!(_impl, $self, store, $vm_addr, $T, ($value) as $T);
+        #[cfg(feature = "unstable")]
+        if let Some(recorder) = $self.recorder.as_mut() {
+            if let ProgramResult::Ok(previous) = $self.vm.memory_mapping.load_unwatched::<$T>($vm_addr) {
+                recorder.record_memory_write($vm_addr, &(previous as $T).to_le_bytes());
+            }
+        }
+        translate_memory_access!(_impl, $self, store, true, $vm_addr, $T, ($value) as $T);
     };
 }
 
 macro_rules! throw_error {
     ($self:expr, $err:expr) => {{
+        $self.vm.registers[0..ebpf::FRAME_PTR_REG]
+            .copy_from_slice(&$self.reg[0..ebpf::FRAME_PTR_REG]);
         $self.vm.registers[11] = $self.reg[11];
         $self.vm.program_result = ProgramResult::Err($err);
         return false;
@@ -62,6 +82,15 @@ macro_rules! throw_error {
     };
 }
 
+macro_rules! suspend {
+    ($self:expr) => {{
+        $self.vm.registers = $self.reg;
+        $self.vm.suspended = Some($self.executable.hash());
+        $self.vm.program_result = ProgramResult::Err(EbpfError::ExecutionSuspended);
+        return false;
+    }};
+}
+
 macro_rules! check_pc {
     ($self:expr, $next_pc:ident, $target_pc:expr) => {
         if ($target_pc as usize)
@@ -83,6 +112,20 @@ pub enum DebugState {
     Step,
     /// Continue execution till the end or till a breakpoint is hit
     Continue,
+    /// Undo the last step
+    ReverseStep,
+    /// Undo steps till the beginning of the recording or till a breakpoint is hit
+    ReverseContinue,
+}
+
+/// Outcome of [Interpreter::single_step]
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SingleStep {
+    /// The pc of the instruction which was executed
+    pub pc: u64,
+    /// The program exited or threw an error, so there is nothing left to step through
+    pub done: bool,
 }
 
 /// State of an interpreter
@@ -91,9 +134,14 @@ pub struct Interpreter<'a, 'b, C: ContextObject> {
     pub(crate) executable: &'a Executable<C>,
     pub(crate) program: &'a [u8],
     pub(crate) program_vm_addr: u64,
+    /// Instruction meter before the first step, see `EbpfVm::interpreter()`
+    pub(crate) initial_insn_count: u64,
 
     /// General purpose registers and pc
     pub reg: [u64; 12],
+    /// Log of the executed instructions, see `start_recording()`
+    #[cfg(feature = "unstable")]
+    pub(crate) recorder: Option<ExecutionRecorder>,
 
     #[cfg(feature = "debugger")]
     pub(crate) debug_state: DebugState,
@@ -114,7 +162,10 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
             executable,
             program,
             program_vm_addr,
+            initial_insn_count: 0,
             reg: registers,
+            #[cfg(feature = "unstable")]
+            recorder: None,
             #[cfg(feature = "debugger")]
             debug_state: DebugState::Continue,
             #[cfg(feature = "debugger")]
@@ -128,6 +179,108 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
         (self.reg[11] * ebpf::INSN_SIZE as u64) + self.executable.get_text_section_offset()
     }
 
+    /// Returns the registers r0 to r10 and the pc as r11
+    pub fn get_registers(&self) -> &[u64; 12] {
+        &self.reg
+    }
+
+    /// Sets a register between two steps, r11 being the pc
+    ///
+    /// A pc outside of the text section is rejected, so that the next step does not have to
+    /// fail on it.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not a register (r0 to r11).
+    pub fn set_register(&mut self, index: usize, value: u64) -> Result<(), EbpfError> {
+        assert!(index < self.reg.len(), "r{} is not a register", index);
+        if index == 11
+            && (value as usize)
+                .checked_mul(ebpf::INSN_SIZE)
+                .and_then(|offset| self.program.get(offset..offset + ebpf::INSN_SIZE))
+                .is_none()
+        {
+            return Err(EbpfError::InstructionOutOfBounds(value as usize));
+        }
+        self.reg[index] = value;
+        Ok(())
+    }
+
+    /// Executes exactly one instruction, an `lddw` counts as one
+    ///
+    /// Once `done` is set the result is available from `finish()`.
+    #[cfg(feature = "unstable")]
+    pub fn single_step(&mut self) -> SingleStep {
+        let pc = self.reg[11];
+        SingleStep {
+            pc,
+            done: !self.step(),
+        }
+    }
+
+    /// Captures the state of the VM between two steps, see `EbpfVm::snapshot()`
+    #[cfg(feature = "unstable")]
+    pub fn snapshot(&mut self) -> VmSnapshot {
+        self.vm.registers = self.reg;
+        self.vm.snapshot()
+    }
+
+    /// Continues from a snapshot on the next step, see `EbpfVm::restore()`
+    #[cfg(feature = "unstable")]
+    pub fn restore(&mut self, snapshot: &VmSnapshot) -> Result<(), EbpfError> {
+        self.vm.restore(snapshot)?;
+        self.reg = self.vm.registers;
+        Ok(())
+    }
+
+    /// Records every following step, keeping the last `capacity` of them
+    #[cfg(feature = "unstable")]
+    pub fn start_recording(&mut self, capacity: usize) {
+        self.recorder = Some(ExecutionRecorder::new(capacity));
+    }
+
+    /// Stops recording and returns the log
+    #[cfg(feature = "unstable")]
+    pub fn stop_recording(&mut self) -> Option<ExecutionRecorder> {
+        self.recorder.take()
+    }
+
+    /// Returns the log if recording
+    #[cfg(feature = "unstable")]
+    pub fn get_recorder(&self) -> Option<&ExecutionRecorder> {
+        self.recorder.as_ref()
+    }
+
+    /// Undoes the last recorded step, even if the program exited or threw an error in it
+    ///
+    /// Returns false if there is no recorded step left.
+    #[cfg(feature = "unstable")]
+    pub fn step_back(&mut self) -> bool {
+        let Some(record) = self.recorder.as_mut().and_then(ExecutionRecorder::pop) else {
+            return false;
+        };
+        for (vm_addr, previous) in record.memory_writes.iter().rev() {
+            for (vm_addr, byte) in (*vm_addr..).zip(previous.iter()) {
+                let _ = self.vm.memory_mapping.store_unwatched(*byte, vm_addr);
+            }
+        }
+        self.reg = record.registers;
+        self.vm.call_depth = record.call_depth;
+        self.vm.stack_pointer = record.stack_pointer;
+        self.vm.due_insn_count = record.due_insn_count;
+        self.vm.program_result = ProgramResult::Ok(0);
+        true
+    }
+
+    /// Ends an execution started with `EbpfVm::interpreter()`
+    ///
+    /// Returns the number of executed instructions and the result, like
+    /// `EbpfVm::execute_program()`. If the program is not done yet, the result is `Ok(0)`.
+    pub fn finish(self) -> (u64, ProgramResult) {
+        self.vm
+            .finish_execution(self.executable.get_config(), self.initial_insn_count)
+    }
+
     fn push_frame(&mut self, config: &Config) -> bool {
         let frame = &mut self.vm.call_frames[self.vm.call_depth as usize];
         frame.caller_saved_registers.copy_from_slice(
@@ -143,24 +296,100 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
 
         if !self.executable.get_sbpf_version().dynamic_stack_frames() {
             // With fixed frames we start the new frame at the next fixed offset
-            let stack_frame_size =
-                config.stack_frame_size * if config.enable_stack_frame_gaps { 2 } else { 1 };
-            self.vm.stack_pointer += stack_frame_size as u64;
+            let sbpf_version = self.executable.get_sbpf_version();
+            self.vm.stack_pointer +=
+                config.stack_frame_size as u64 + config.stack_frame_gap_size(sbpf_version);
+            if config.zero_stack_frames {
+                self.vm.zero_stack_frame(self.vm.stack_pointer);
+            }
         }
         self.reg[ebpf::FRAME_PTR_REG] = self.vm.stack_pointer;
 
         true
     }
 
+    fn count_function_entry(&mut self, target_pc: u64) {
+        if let Some(function_entries) = self.vm.function_entries.as_mut() {
+            *function_entries.entry(target_pc as usize).or_default() += 1;
+        }
+    }
+
     /// Advances the interpreter state by one instruction
     ///
     /// Returns false if the program terminated or threw an error.
-    #[rustfmt::skip]
     pub fn step(&mut self) -> bool {
+        if self.is_accounting() {
+            self.step_impl::<true>()
+        } else {
+            self.step_impl::<false>()
+        }
+    }
+
+    /// Steps until the program terminates or throws an error
+    ///
+    /// The config is only checked once, so that an execution with neither instruction meter,
+    /// tracing nor recording runs a loop without any of their bookkeeping.
+    pub(crate) fn run(&mut self) {
+        if self.is_accounting() {
+            while self.step_impl::<true>() {}
+        } else {
+            #[cfg(feature = "threaded-dispatch")]
+            if !self.has_instruction_hooks() {
+                return self.run_threaded();
+            }
+            while self.step_impl::<false>() {}
+        }
+    }
+
+    fn is_accounting(&self) -> bool {
+        let config = self.executable.get_config();
+        config.enable_instruction_meter || config.enable_instruction_tracing || self.is_recording()
+    }
+
+    /// Whether the executed steps are recorded, see `start_recording()`
+    #[cfg(feature = "unstable")]
+    fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    #[cfg(not(feature = "unstable"))]
+    fn is_recording(&self) -> bool {
+        false
+    }
+
+    /// Whether an instruction hook has to be called around every step
+    #[cfg(all(feature = "threaded-dispatch", feature = "unstable"))]
+    fn has_instruction_hooks(&self) -> bool {
+        self.vm.pre_instruction_hook.is_some() || self.vm.post_instruction_hook.is_some()
+    }
+
+    #[cfg(all(feature = "threaded-dispatch", not(feature = "unstable")))]
+    fn has_instruction_hooks(&self) -> bool {
+        false
+    }
+
+    /// Monomorphized `step()`, with `ACCOUNTING` the instruction meter, tracing and recording
+    /// are checked
+    #[rustfmt::skip]
+    #[inline(always)]
+    fn step_impl<const ACCOUNTING: bool>(&mut self) -> bool {
         let config = &self.executable.get_config();
 
-        self.vm.due_insn_count += 1;
-        let mut next_pc = self.reg[11] + 1;
+        if ACCOUNTING {
+            #[cfg(feature = "unstable")]
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.push(ExecutionRecord {
+                    registers: self.reg,
+                    call_depth: self.vm.call_depth,
+                    stack_pointer: self.vm.stack_pointer,
+                    due_insn_count: self.vm.due_insn_count,
+                    memory_writes: Vec::new(),
+                });
+            }
+            self.vm.due_insn_count += 1;
+        }
+        let pc = self.reg[11];
+        let mut next_pc = pc + 1;
         if next_pc as usize * ebpf::INSN_SIZE > self.program.len() {
             throw_error!(self, EbpfError::ExecutionOverrun);
         }
@@ -168,8 +397,13 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
         let dst = insn.dst as usize;
         let src = insn.src as usize;
 
-        if config.enable_instruction_tracing {
-            self.vm.context_object_pointer.trace(self.reg);
+        if ACCOUNTING && config.enable_instruction_tracing {
+            self.vm
+                .trace_instruction(&self.reg, insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP);
+        }
+        #[cfg(feature = "unstable")]
+        if let Some(hook) = self.vm.pre_instruction_hook {
+            hook(self.vm.context_object_pointer, pc, &self.reg);
         }
 
         match insn.opc {
@@ -183,7 +417,7 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                 self.vm.stack_pointer = self.vm.stack_pointer.overflowing_add(insn.imm as u64).0;
             }
 
-            ebpf::LD_DW_IMM if self.executable.get_sbpf_version().enable_lddw() => {
+            ebpf::LD_DW_IMM => {
                 ebpf::augment_lddw_unchecked(self.program, &mut insn);
                 self.reg[dst] = insn.imm as u64;
                 self.reg[11] += 1;
@@ -253,10 +487,10 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                                 self.reg[dst] = (self.reg[dst] as i32).wrapping_sub(insn.imm as i32)      as u64
             },
             ebpf::SUB32_REG  => self.reg[dst] = (self.reg[dst] as i32).wrapping_sub(self.reg[src] as i32) as u64,
-            ebpf::MUL32_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(insn.imm as i32)      as u64,
-            ebpf::MUL32_REG  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(self.reg[src] as i32) as u64,
-            ebpf::DIV32_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as u32             / insn.imm as u32)      as u64,
-            ebpf::DIV32_REG  if !self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::MUL32_IMM  => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(insn.imm as i32)      as u64,
+            ebpf::MUL32_REG  => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(self.reg[src] as i32) as u64,
+            ebpf::DIV32_IMM  => self.reg[dst] = (self.reg[dst] as u32             / insn.imm as u32)      as u64,
+            ebpf::DIV32_REG  => {
                 throw_error!(DivideByZero; self, self.reg[src], u32);
                                 self.reg[dst] = (self.reg[dst] as u32             / self.reg[src] as u32) as u64;
             },
@@ -268,9 +502,9 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
             ebpf::LSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shl(self.reg[src] as u32) as u64,
             ebpf::RSH32_IMM  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(insn.imm as u32)      as u64,
             ebpf::RSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(self.reg[src] as u32) as u64,
-            ebpf::NEG32      if self.executable.get_sbpf_version().enable_neg() => self.reg[dst] = (self.reg[dst] as i32).wrapping_neg()                     as u64 & (u32::MAX as u64),
-            ebpf::MOD32_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as u32             % insn.imm as u32)      as u64,
-            ebpf::MOD32_REG  if !self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::NEG32      => self.reg[dst] = (self.reg[dst] as i32).wrapping_neg()                     as u64 & (u32::MAX as u64),
+            ebpf::MOD32_IMM  => self.reg[dst] = (self.reg[dst] as u32             % insn.imm as u32)      as u64,
+            ebpf::MOD32_REG  => {
                 throw_error!(DivideByZero; self, self.reg[src], u32);
                                 self.reg[dst] = (self.reg[dst] as u32             % self.reg[src] as u32) as u64;
             },
@@ -280,7 +514,7 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
             ebpf::MOV32_REG  => self.reg[dst] = (self.reg[src] as u32) as u64,
             ebpf::ARSH32_IMM => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(insn.imm as u32)      as u64 & (u32::MAX as u64),
             ebpf::ARSH32_REG => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(self.reg[src] as u32) as u64 & (u32::MAX as u64),
-            ebpf::LE if self.executable.get_sbpf_version().enable_le() => {
+            ebpf::LE         => {
                 self.reg[dst] = match insn.imm {
                     16 => (self.reg[dst] as u16).to_le() as u64,
                     32 => (self.reg[dst] as u32).to_le() as u64,
@@ -310,10 +544,10 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                                 self.reg[dst] =  self.reg[dst].wrapping_sub(insn.imm as u64)
             },
             ebpf::SUB64_REG  => self.reg[dst] =  self.reg[dst].wrapping_sub(self.reg[src]),
-            ebpf::MUL64_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] =  self.reg[dst].wrapping_mul(insn.imm as u64),
-            ebpf::MUL64_REG  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] =  self.reg[dst].wrapping_mul(self.reg[src]),
-            ebpf::DIV64_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] /= insn.imm as u64,
-            ebpf::DIV64_REG  if !self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::MUL64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_mul(insn.imm as u64),
+            ebpf::MUL64_REG  => self.reg[dst] =  self.reg[dst].wrapping_mul(self.reg[src]),
+            ebpf::DIV64_IMM  => self.reg[dst] /= insn.imm as u64,
+            ebpf::DIV64_REG  => {
                 throw_error!(DivideByZero; self, self.reg[src], u64);
                                 self.reg[dst] /= self.reg[src];
             },
@@ -325,9 +559,9 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
             ebpf::LSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shl(self.reg[src] as u32),
             ebpf::RSH64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_shr(insn.imm as u32),
             ebpf::RSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shr(self.reg[src] as u32),
-            ebpf::NEG64      if self.executable.get_sbpf_version().enable_neg() => self.reg[dst] = (self.reg[dst] as i64).wrapping_neg() as u64,
-            ebpf::MOD64_IMM  if !self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] %= insn.imm as u64,
-            ebpf::MOD64_REG  if !self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::NEG64      => self.reg[dst] = (self.reg[dst] as i64).wrapping_neg() as u64,
+            ebpf::MOD64_IMM  => self.reg[dst] %= insn.imm as u64,
+            ebpf::MOD64_REG  => {
                 throw_error!(DivideByZero; self, self.reg[src], u64);
                                 self.reg[dst] %= self.reg[src];
             },
@@ -337,79 +571,79 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
             ebpf::MOV64_REG  => self.reg[dst] =  self.reg[src],
             ebpf::ARSH64_IMM => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(insn.imm as u32)      as u64,
             ebpf::ARSH64_REG => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(self.reg[src] as u32) as u64,
-            ebpf::HOR64_IMM if !self.executable.get_sbpf_version().enable_lddw() => {
+            ebpf::HOR64_IMM  => {
                 self.reg[dst] |= (insn.imm as u64).wrapping_shl(32);
             }
 
             // BPF_PQR class
-            ebpf::LMUL32_IMM if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(insn.imm as i32)      as u64,
-            ebpf::LMUL32_REG if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(self.reg[src] as i32) as u64,
-            ebpf::LMUL64_IMM if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = self.reg[dst].wrapping_mul(insn.imm as u64),
-            ebpf::LMUL64_REG if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = self.reg[dst].wrapping_mul(self.reg[src]),
-            ebpf::UHMUL64_IMM if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as u128).wrapping_mul(insn.imm as u64 as u128).wrapping_shr(64) as u64,
-            ebpf::UHMUL64_REG if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as u128).wrapping_mul(self.reg[src] as u128).wrapping_shr(64) as u64,
-            ebpf::SHMUL64_IMM if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i64 as i128).wrapping_mul(insn.imm as i128).wrapping_shr(64) as u64,
-            ebpf::SHMUL64_REG if self.executable.get_sbpf_version().enable_pqr() => self.reg[dst] = (self.reg[dst] as i64 as i128).wrapping_mul(self.reg[src] as i64 as i128).wrapping_shr(64) as u64,
-            ebpf::UDIV32_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::LMUL32_IMM => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(insn.imm as i32)      as u64,
+            ebpf::LMUL32_REG => self.reg[dst] = (self.reg[dst] as i32).wrapping_mul(self.reg[src] as i32) as u64,
+            ebpf::LMUL64_IMM => self.reg[dst] = self.reg[dst].wrapping_mul(insn.imm as u64),
+            ebpf::LMUL64_REG => self.reg[dst] = self.reg[dst].wrapping_mul(self.reg[src]),
+            ebpf::UHMUL64_IMM => self.reg[dst] = (self.reg[dst] as u128).wrapping_mul(insn.imm as u64 as u128).wrapping_shr(64) as u64,
+            ebpf::UHMUL64_REG => self.reg[dst] = (self.reg[dst] as u128).wrapping_mul(self.reg[src] as u128).wrapping_shr(64) as u64,
+            ebpf::SHMUL64_IMM => self.reg[dst] = (self.reg[dst] as i64 as i128).wrapping_mul(insn.imm as i128).wrapping_shr(64) as u64,
+            ebpf::SHMUL64_REG => self.reg[dst] = (self.reg[dst] as i64 as i128).wrapping_mul(self.reg[src] as i64 as i128).wrapping_shr(64) as u64,
+            ebpf::UDIV32_IMM => {
                                 self.reg[dst] = (self.reg[dst] as u32 / insn.imm as u32)      as u64;
             }
-            ebpf::UDIV32_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UDIV32_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], u32);
                                 self.reg[dst] = (self.reg[dst] as u32 / self.reg[src] as u32) as u64;
             },
-            ebpf::UDIV64_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UDIV64_IMM => {
                                 self.reg[dst] /= insn.imm as u64;
             }
-            ebpf::UDIV64_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UDIV64_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], u64);
                                 self.reg[dst] /= self.reg[src];
             },
-            ebpf::UREM32_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UREM32_IMM => {
                                 self.reg[dst] = (self.reg[dst] as u32 % insn.imm as u32)      as u64;
             }
-            ebpf::UREM32_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UREM32_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], u32);
                                 self.reg[dst] = (self.reg[dst] as u32 % self.reg[src] as u32) as u64;
             },
-            ebpf::UREM64_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UREM64_IMM => {
                                 self.reg[dst] %= insn.imm as u64;
             }
-            ebpf::UREM64_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::UREM64_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], u64);
                                 self.reg[dst] %= self.reg[src];
             },
-            ebpf::SDIV32_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SDIV32_IMM => {
                 throw_error!(DivideOverflow; self, insn.imm, self.reg[dst], i32);
                                 self.reg[dst] = (self.reg[dst] as i32 / insn.imm as i32)      as u64;
             }
-            ebpf::SDIV32_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SDIV32_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], i32);
                 throw_error!(DivideOverflow; self, self.reg[src], self.reg[dst], i32);
                                 self.reg[dst] = (self.reg[dst] as i32 / self.reg[src] as i32) as u64;
             },
-            ebpf::SDIV64_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SDIV64_IMM => {
                 throw_error!(DivideOverflow; self, insn.imm, self.reg[dst], i64);
                                 self.reg[dst] = (self.reg[dst] as i64 / insn.imm)             as u64;
             }
-            ebpf::SDIV64_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SDIV64_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], i64);
                 throw_error!(DivideOverflow; self, self.reg[src], self.reg[dst], i64);
                                 self.reg[dst] = (self.reg[dst] as i64 / self.reg[src] as i64) as u64;
             },
-            ebpf::SREM32_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SREM32_IMM => {
                 throw_error!(DivideOverflow; self, insn.imm, self.reg[dst], i32);
                                 self.reg[dst] = (self.reg[dst] as i32 % insn.imm as i32)      as u64;
             }
-            ebpf::SREM32_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SREM32_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], i32);
                 throw_error!(DivideOverflow; self, self.reg[src], self.reg[dst], i32);
                                 self.reg[dst] = (self.reg[dst] as i32 % self.reg[src] as i32) as u64;
             },
-            ebpf::SREM64_IMM if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SREM64_IMM => {
                 throw_error!(DivideOverflow; self, insn.imm, self.reg[dst], i64);
                                 self.reg[dst] = (self.reg[dst] as i64 % insn.imm)             as u64;
             }
-            ebpf::SREM64_REG if self.executable.get_sbpf_version().enable_pqr() => {
+            ebpf::SREM64_REG => {
                 throw_error!(DivideByZero; self, self.reg[src], i64);
                 throw_error!(DivideOverflow; self, self.reg[src], self.reg[dst], i64);
                                 self.reg[dst] = (self.reg[dst] as i64 % self.reg[src] as i64) as u64;
@@ -458,6 +692,7 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                     self.reg[11] = next_pc;
                     throw_error!(self, EbpfError::UnsupportedInstruction);
                 }
+                self.count_function_entry(next_pc);
             },
 
             // Do not delegate the check to the verifier, since self.registered functions can be
@@ -471,16 +706,35 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                 };
 
                 if external {
-                    if let Some((_function_name, function)) = self.executable.get_loader().get_function_registry().lookup_by_key(insn.imm as u32) {
+                    if let Some(function) = self.executable.lookup_syscall(insn.imm as u32) {
                         resolved = true;
 
                         self.vm.due_insn_count = self.vm.previous_instruction_meter - self.vm.due_insn_count;
                         self.vm.registers[0..6].copy_from_slice(&self.reg[0..6]);
-                        self.vm.invoke_function(function);
+                        let charges_cost = config.enable_instruction_meter && self.executable.get_loader().has_syscall_costs();
+                        if charges_cost || config.enable_syscall_tracing {
+                            self.vm.invoked_syscall = insn.imm as u32 as u64;
+                        }
+                        if config.enable_syscall_tracing {
+                            self.vm.trace_syscall_entry(self.reg[1], self.reg[2], self.reg[3], self.reg[4], self.reg[5]);
+                        }
+                        if charges_cost {
+                            self.vm.invoke_function(charge_syscall_cost::<C>);
+                        }
+                        if self.vm.program_result.is_ok() {
+                            self.vm.invoke_function(function);
+                        }
+                        if config.enable_syscall_tracing {
+                            self.vm.trace_syscall_exit(self.reg[1], self.reg[2], self.reg[3], self.reg[4], self.reg[5]);
+                        }
                         self.vm.due_insn_count = 0;
                         self.reg[0] = match &self.vm.program_result {
                             ProgramResult::Ok(value) => *value,
-                            ProgramResult::Err(_err) => return false,
+                            ProgramResult::Err(_err) => {
+                                self.vm.registers[0..ebpf::FRAME_PTR_REG]
+                                    .copy_from_slice(&self.reg[0..ebpf::FRAME_PTR_REG]);
+                                return false;
+                            }
                         };
                     }
                 }
@@ -494,6 +748,7 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                             return false;
                         }
                         check_pc!(self, next_pc, target_pc as u64);
+                        self.count_function_entry(next_pc);
                     }
                 }
 
@@ -504,9 +759,15 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
 
             ebpf::EXIT       => {
                 if self.vm.call_depth == 0 {
-                    if config.enable_instruction_meter && self.vm.due_insn_count > self.vm.previous_instruction_meter {
+                    if ACCOUNTING && config.enable_instruction_meter && self.vm.due_insn_count > self.vm.previous_instruction_meter {
+                        if self.vm.suspend_on_exhaustion {
+                            // Exit once resumed, without charging it now
+                            self.vm.due_insn_count -= 1;
+                            suspend!(self);
+                        }
                         throw_error!(self, EbpfError::ExceededMaxInstructions);
                     }
+                    self.vm.registers[0..ebpf::FRAME_PTR_REG].copy_from_slice(&self.reg[0..ebpf::FRAME_PTR_REG]);
                     self.vm.program_result = ProgramResult::Ok(self.reg[0]);
                     return false;
                 }
@@ -518,16 +779,25 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
                     ..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS]
                     .copy_from_slice(&frame.caller_saved_registers);
                 if !self.executable.get_sbpf_version().dynamic_stack_frames() {
-                    let stack_frame_size =
-                        config.stack_frame_size * if config.enable_stack_frame_gaps { 2 } else { 1 };
-                    self.vm.stack_pointer -= stack_frame_size as u64;
+                    let sbpf_version = self.executable.get_sbpf_version();
+                    self.vm.stack_pointer -=
+                        config.stack_frame_size as u64 + config.stack_frame_gap_size(sbpf_version);
                 }
                 check_pc!(self, next_pc, frame.target_pc);
             }
             _ => throw_error!(self, EbpfError::UnsupportedInstruction),
         }
 
-        if config.enable_instruction_meter && self.vm.due_insn_count >= self.vm.previous_instruction_meter {
+        #[cfg(feature = "unstable")]
+        if let Some(hook) = self.vm.post_instruction_hook {
+            hook(self.vm.context_object_pointer, pc, &self.reg);
+        }
+
+        if ACCOUNTING && config.enable_instruction_meter && self.vm.due_insn_count >= self.vm.previous_instruction_meter {
+            if self.vm.suspend_on_exhaustion {
+                self.reg[11] = next_pc;
+                suspend!(self);
+            }
             self.reg[11] += 1;
             throw_error!(self, EbpfError::ExceededMaxInstructions);
         }
@@ -536,3 +806,159 @@ impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
         true
     }
 }
+
+/// Handles one instruction and advances the pc, returns false like `Interpreter::step()`
+#[cfg(feature = "threaded-dispatch")]
+pub(crate) type Handler<C> =
+    for<'a, 'b, 'c> fn(&'a mut Interpreter<'b, 'c, C>, &ebpf::Insn) -> bool;
+
+/// Defines the handler of an instruction which continues with the next one
+#[cfg(feature = "threaded-dispatch")]
+macro_rules! alu_handler {
+    ($table:ident, $opc:expr, |$this:ident, $dst:ident, $src:ident, $insn:ident| $body:expr) => {
+        $table[$opc as usize] = |$this: &mut Interpreter<C>, $insn: &ebpf::Insn| {
+            #[allow(unused_variables)]
+            let ($dst, $src) = ($insn.dst as usize, $insn.src as usize);
+            $body;
+            $this.reg[11] += 1;
+            true
+        };
+    };
+}
+
+/// Defines the handler of a conditional jump
+#[cfg(feature = "threaded-dispatch")]
+macro_rules! jmp_handler {
+    ($table:ident, $opc:expr, |$this:ident, $dst:ident, $src:ident, $insn:ident| $condition:expr) => {
+        $table[$opc as usize] = |$this: &mut Interpreter<C>, $insn: &ebpf::Insn| {
+            #[allow(unused_variables)]
+            let ($dst, $src) = ($insn.dst as usize, $insn.src as usize);
+            let offset = if $condition { $insn.off as i64 } else { 0 };
+            $this.reg[11] = ($this.reg[11] as i64 + 1 + offset) as u64;
+            true
+        };
+    };
+}
+
+/// Table dispatch for executions without instruction meter, tracing and instruction hooks
+///
+/// The executable decodes its text section once and pairs every instruction with a small handler
+/// from this table, so that each step is a single indirect call instead of decoding and matching
+/// the opcode. Only the frequent instructions, whose semantics do not depend on the SBPFVersion,
+/// have a handler of their own. All others go through `step()`, so that both dispatchers agree.
+#[cfg(feature = "threaded-dispatch")]
+pub(crate) struct DispatchTable<C>(std::marker::PhantomData<C>);
+
+#[cfg(feature = "threaded-dispatch")]
+impl<C: ContextObject> DispatchTable<C> {
+    #[rustfmt::skip]
+    pub(crate) const HANDLERS: [Handler<C>; 256] = {
+        let mut table = [step_fallback as Handler<C>; 256];
+
+        // BPF_LDX class
+        alu_handler!(table, ebpf::LD_B_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[src] as i64).wrapping_add(insn.off as i64) as u64; this.reg[dst] = translate_memory_access!(this, load, vm_addr, u8); });
+        alu_handler!(table, ebpf::LD_H_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[src] as i64).wrapping_add(insn.off as i64) as u64; this.reg[dst] = translate_memory_access!(this, load, vm_addr, u16); });
+        alu_handler!(table, ebpf::LD_W_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[src] as i64).wrapping_add(insn.off as i64) as u64; this.reg[dst] = translate_memory_access!(this, load, vm_addr, u32); });
+        alu_handler!(table, ebpf::LD_DW_REG,  |this, dst, src, insn| { let vm_addr = (this.reg[src] as i64).wrapping_add(insn.off as i64) as u64; this.reg[dst] = translate_memory_access!(this, load, vm_addr, u64); });
+
+        // BPF_ST class
+        alu_handler!(table, ebpf::ST_B_IMM,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, insn.imm, vm_addr, u8); });
+        alu_handler!(table, ebpf::ST_H_IMM,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, insn.imm, vm_addr, u16); });
+        alu_handler!(table, ebpf::ST_W_IMM,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, insn.imm, vm_addr, u32); });
+        alu_handler!(table, ebpf::ST_DW_IMM,  |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, insn.imm, vm_addr, u64); });
+
+        // BPF_STX class
+        alu_handler!(table, ebpf::ST_B_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, this.reg[src], vm_addr, u8); });
+        alu_handler!(table, ebpf::ST_H_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, this.reg[src], vm_addr, u16); });
+        alu_handler!(table, ebpf::ST_W_REG,   |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, this.reg[src], vm_addr, u32); });
+        alu_handler!(table, ebpf::ST_DW_REG,  |this, dst, src, insn| { let vm_addr = (this.reg[dst] as i64).wrapping_add(insn.off as i64) as u64; translate_memory_access!(this, store, this.reg[src], vm_addr, u64); });
+
+        // BPF_ALU class
+        alu_handler!(table, ebpf::ADD32_IMM,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i32).wrapping_add(insn.imm as i32)      as u64);
+        alu_handler!(table, ebpf::ADD32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i32).wrapping_add(this.reg[src] as i32) as u64);
+        alu_handler!(table, ebpf::SUB32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i32).wrapping_sub(this.reg[src] as i32) as u64);
+        alu_handler!(table, ebpf::OR32_IMM,   |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             | insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::OR32_REG,   |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             | this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::AND32_IMM,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             & insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::AND32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             & this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::LSH32_IMM,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32).wrapping_shl(insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::LSH32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32).wrapping_shl(this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::RSH32_IMM,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32).wrapping_shr(insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::RSH32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32).wrapping_shr(this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::XOR32_IMM,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             ^ insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::XOR32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as u32             ^ this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::MOV32_IMM,  |this, dst, src, insn| this.reg[dst] = insn.imm as u32 as u64);
+        alu_handler!(table, ebpf::MOV32_REG,  |this, dst, src, insn| this.reg[dst] = (this.reg[src] as u32) as u64);
+        alu_handler!(table, ebpf::ARSH32_IMM, |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i32).wrapping_shr(insn.imm as u32)      as u64 & (u32::MAX as u64));
+        alu_handler!(table, ebpf::ARSH32_REG, |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i32).wrapping_shr(this.reg[src] as u32) as u64 & (u32::MAX as u64));
+
+        // BPF_ALU64 class, ADD64_IMM moves the stack pointer with dynamic stack frames
+        alu_handler!(table, ebpf::ADD64_REG,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_add(this.reg[src]));
+        alu_handler!(table, ebpf::SUB64_REG,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_sub(this.reg[src]));
+        alu_handler!(table, ebpf::OR64_IMM,   |this, dst, src, insn| this.reg[dst] |= insn.imm as u64);
+        alu_handler!(table, ebpf::OR64_REG,   |this, dst, src, insn| this.reg[dst] |= this.reg[src]);
+        alu_handler!(table, ebpf::AND64_IMM,  |this, dst, src, insn| this.reg[dst] &= insn.imm as u64);
+        alu_handler!(table, ebpf::AND64_REG,  |this, dst, src, insn| this.reg[dst] &= this.reg[src]);
+        alu_handler!(table, ebpf::LSH64_IMM,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_shl(insn.imm as u32));
+        alu_handler!(table, ebpf::LSH64_REG,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_shl(this.reg[src] as u32));
+        alu_handler!(table, ebpf::RSH64_IMM,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_shr(insn.imm as u32));
+        alu_handler!(table, ebpf::RSH64_REG,  |this, dst, src, insn| this.reg[dst] =  this.reg[dst].wrapping_shr(this.reg[src] as u32));
+        alu_handler!(table, ebpf::XOR64_IMM,  |this, dst, src, insn| this.reg[dst] ^= insn.imm as u64);
+        alu_handler!(table, ebpf::XOR64_REG,  |this, dst, src, insn| this.reg[dst] ^= this.reg[src]);
+        alu_handler!(table, ebpf::MOV64_IMM,  |this, dst, src, insn| this.reg[dst] =  insn.imm as u64);
+        alu_handler!(table, ebpf::MOV64_REG,  |this, dst, src, insn| this.reg[dst] =  this.reg[src]);
+        alu_handler!(table, ebpf::ARSH64_IMM, |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i64).wrapping_shr(insn.imm as u32)      as u64);
+        alu_handler!(table, ebpf::ARSH64_REG, |this, dst, src, insn| this.reg[dst] = (this.reg[dst] as i64).wrapping_shr(this.reg[src] as u32) as u64);
+
+        // BPF_JMP class
+        jmp_handler!(table, ebpf::JA,         |this, dst, src, insn| true);
+        jmp_handler!(table, ebpf::JEQ_IMM,    |this, dst, src, insn|  this.reg[dst] == insn.imm as u64);
+        jmp_handler!(table, ebpf::JEQ_REG,    |this, dst, src, insn|  this.reg[dst] == this.reg[src]);
+        jmp_handler!(table, ebpf::JGT_IMM,    |this, dst, src, insn|  this.reg[dst] >  insn.imm as u64);
+        jmp_handler!(table, ebpf::JGT_REG,    |this, dst, src, insn|  this.reg[dst] >  this.reg[src]);
+        jmp_handler!(table, ebpf::JGE_IMM,    |this, dst, src, insn|  this.reg[dst] >= insn.imm as u64);
+        jmp_handler!(table, ebpf::JGE_REG,    |this, dst, src, insn|  this.reg[dst] >= this.reg[src]);
+        jmp_handler!(table, ebpf::JLT_IMM,    |this, dst, src, insn|  this.reg[dst] <  insn.imm as u64);
+        jmp_handler!(table, ebpf::JLT_REG,    |this, dst, src, insn|  this.reg[dst] <  this.reg[src]);
+        jmp_handler!(table, ebpf::JLE_IMM,    |this, dst, src, insn|  this.reg[dst] <= insn.imm as u64);
+        jmp_handler!(table, ebpf::JLE_REG,    |this, dst, src, insn|  this.reg[dst] <= this.reg[src]);
+        jmp_handler!(table, ebpf::JSET_IMM,   |this, dst, src, insn|  this.reg[dst] &  insn.imm as u64 != 0);
+        jmp_handler!(table, ebpf::JSET_REG,   |this, dst, src, insn|  this.reg[dst] &  this.reg[src] != 0);
+        jmp_handler!(table, ebpf::JNE_IMM,    |this, dst, src, insn|  this.reg[dst] != insn.imm as u64);
+        jmp_handler!(table, ebpf::JNE_REG,    |this, dst, src, insn|  this.reg[dst] != this.reg[src]);
+        jmp_handler!(table, ebpf::JSGT_IMM,   |this, dst, src, insn| (this.reg[dst] as i64) >  insn.imm);
+        jmp_handler!(table, ebpf::JSGT_REG,   |this, dst, src, insn| (this.reg[dst] as i64) >  this.reg[src] as i64);
+        jmp_handler!(table, ebpf::JSGE_IMM,   |this, dst, src, insn| (this.reg[dst] as i64) >= insn.imm);
+        jmp_handler!(table, ebpf::JSGE_REG,   |this, dst, src, insn| (this.reg[dst] as i64) >= this.reg[src] as i64);
+        jmp_handler!(table, ebpf::JSLT_IMM,   |this, dst, src, insn| (this.reg[dst] as i64) <  insn.imm);
+        jmp_handler!(table, ebpf::JSLT_REG,   |this, dst, src, insn| (this.reg[dst] as i64) <  this.reg[src] as i64);
+        jmp_handler!(table, ebpf::JSLE_IMM,   |this, dst, src, insn| (this.reg[dst] as i64) <= insn.imm);
+        jmp_handler!(table, ebpf::JSLE_REG,   |this, dst, src, insn| (this.reg[dst] as i64) <= this.reg[src] as i64);
+
+        table
+    };
+}
+
+/// Runs an instruction without a handler of its own through the match of `step()`
+#[cfg(feature = "threaded-dispatch")]
+fn step_fallback<C: ContextObject>(interpreter: &mut Interpreter<C>, _insn: &ebpf::Insn) -> bool {
+    interpreter.step_impl::<false>()
+}
+
+#[cfg(feature = "threaded-dispatch")]
+impl<'a, 'b, C: ContextObject> Interpreter<'a, 'b, C> {
+    /// Steps through the dispatch table until the program terminates or throws an error
+    fn run_threaded(&mut self) {
+        let instructions = self.executable.get_threaded_instructions();
+        loop {
+            let Some((handler, insn)) = instructions.get(self.reg[11] as usize) else {
+                // Let step() report the ExecutionOverrun
+                self.step_impl::<false>();
+                return;
+            };
+            if !handler(self, insn) {
+                return;
+            }
+        }
+    }
+}
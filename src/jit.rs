@@ -11,80 +11,246 @@
 // copied, modified, or distributed except according to those terms.
 
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use std::{fmt::Debug, mem, ptr};
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    fs::OpenOptions,
+    io::Write,
+    mem,
+    ops::Range,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
     ebpf::{self, FIRST_SCRATCH_REG, FRAME_PTR_REG, INSN_SIZE, SCRATCH_REGS, STACK_PTR_REG},
     elf::Executable,
     error::{EbpfError, ProgramResult},
-    memory_management::{
-        allocate_pages, free_pages, get_system_page_size, protect_pages, round_to_page_size,
+    memory_management::{protect_pages, round_to_page_size, unprotect_pages},
+    memory_region::{AccessType, MemoryMapping, MemoryRegion},
+    program::{charge_syscall_cost, BuiltinFunction, FunctionRegistry},
+    section_allocator::{PageAllocator, SectionAllocator},
+    unwind::{CallSite, EhFrame},
+    verifier::is_opcode_supported,
+    vm::{
+        get_runtime_environment_key, Config, ContextObject, DynamicAnalysis, EbpfVm,
+        SpeculationHardening,
     },
-    memory_region::{AccessType, MemoryMapping},
-    vm::{get_runtime_environment_key, Config, ContextObject, EbpfVm},
     x86::*,
 };
 
 const MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH: usize = 4096;
-const MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION: usize = 110;
+const MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION: usize = 170;
+const EXPECTED_MACHINE_CODE_LENGTH_PER_INSTRUCTION: usize = 64;
 const MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT: usize = 13;
+const MAX_LIVENESS_LOOKAHEAD: usize = 16;
 const MAX_START_PADDING_LENGTH: usize = 256;
 
+/// Measurements of a JIT compilation, e.g. to track the size of the generated code
+///
+/// See `JitProgram::statistics()`. With lazy compilation they only cover the functions which
+/// were compiled up front.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct JitStatistics {
+    /// Length of the emitted machine code in bytes, including the subroutines
+    pub machine_code_length: usize,
+    /// Number of compiled instructions (an `lddw` counts twice)
+    pub instruction_count: usize,
+    /// Number of forward jumps which were resolved after the emission
+    pub resolved_jumps: usize,
+    /// Number of anchors (shared subroutines and exception handlers) which were emitted
+    pub anchors_used: usize,
+    /// Wall clock time of the compilation, including restarts with a larger text section
+    pub compile_time: std::time::Duration,
+}
+
+impl JitStatistics {
+    /// Average length of the machine code per compiled instruction
+    pub fn bytes_per_instruction(&self) -> f64 {
+        if self.instruction_count == 0 {
+            return 0.0;
+        }
+        self.machine_code_length as f64 / self.instruction_count as f64
+    }
+}
+
 pub struct JitProgram {
-    /// OS page size in bytes and the alignment of the sections
+    /// Provides and releases the memory of the sections
+    allocator: Arc<dyn SectionAllocator>,
+    /// Size of the allocation which starts at the pc_section, zero if the pc_section is a copy on the heap
+    allocation_size: usize,
+    /// Page size of the allocator in bytes and the alignment of the sections
     page_size: usize,
     /// A `*const u8` pointer into the text_section for each BPF instruction
-    pc_section: &'static mut [usize],
+    ///
+    /// Atomic because lazy compilation updates it while other threads execute the program.
+    pc_section: &'static [AtomicUsize],
     /// The x86 machinecode
     text_section: &'static mut [u8],
+    /// Offset into the text_section for each BPF instruction, unaffected by the pc_section patching
+    ///
+    /// Atomic because lazy compilation records the functions it compiles later.
+    #[cfg(feature = "jit-disassembler")]
+    instruction_offsets: Box<[AtomicUsize]>,
+    /// Present if functions are compiled on their first call
+    lazy_compilation: Option<Mutex<LazyCompilation>>,
+    /// Distance from the executed mapping to the writable mapping, zero unless dual mapped
+    ///
+    /// While it is non-zero the pc_section points into the writable mapping.
+    write_offset: isize,
+    /// Keys of the syscalls registered in the loader, sorted, one per slot of the syscall_table
+    syscall_keys: Box<[u32]>,
+    /// Names of the syscalls, in the order of the syscall_keys
+    syscall_names: Box<[Box<[u8]>]>,
+    /// Host addresses of the syscalls, which the machine code calls indirectly
+    syscall_table: Box<[AtomicUsize]>,
+    /// Set once `rebind_syscall()` changed the syscall_table, which a `JitCache` then no longer
+    /// hands out
+    syscalls_rebound: AtomicBool,
+    /// Registered unwind information of the text section, if enabled
+    unwind_info: Option<EhFrame>,
+    /// Measurements of the compilation
+    statistics: JitStatistics,
+}
+
+/// State of a JitProgram whose functions are compiled on their first call
+struct LazyCompilation {
+    /// Host addresses of the subroutines, which later compilations reuse
+    anchors: [usize; ANCHOR_COUNT],
+    /// Size of the text section including the pages reserved for later compilations
+    text_section_reservation: usize,
+    /// Length of the machine code emitted so far
+    text_section_length: usize,
+    /// Address of the text section, captured with exclusive access when sealing
+    text_section: usize,
+    /// Address through which the reserved pages of the text section are written
+    writable_text_section: usize,
+    /// First pc of every function which has been compiled
+    compiled_functions: BTreeSet<usize>,
+    /// Registered unwind information of every later compilation
+    unwind_info: Vec<EhFrame>,
 }
 
 impl JitProgram {
-    fn new(pc: usize, code_size: usize) -> Result<Self, EbpfError> {
-        let page_size = get_system_page_size();
+    fn new(
+        allocator: Arc<dyn SectionAllocator>,
+        pc: usize,
+        code_size: usize,
+        dual_mapping: bool,
+    ) -> Result<Self, EbpfError> {
+        let page_size = allocator.page_size();
         let pc_loc_table_size = round_to_page_size(pc * 8, page_size);
         let over_allocated_code_size = round_to_page_size(code_size, page_size);
+        let allocation_size = pc_loc_table_size + over_allocated_code_size;
         unsafe {
-            let raw = allocate_pages(pc_loc_table_size + over_allocated_code_size)?;
+            let (raw, write_offset) = if dual_mapping {
+                let (read_only, writable) = allocator.allocate_dual_mapped(allocation_size)?;
+                (read_only, writable as isize - read_only as isize)
+            } else {
+                (allocator.allocate(allocation_size)?, 0)
+            };
             Ok(Self {
+                allocator,
+                allocation_size,
                 page_size,
-                pc_section: std::slice::from_raw_parts_mut(raw.cast::<usize>(), pc),
+                pc_section: std::slice::from_raw_parts(
+                    raw.wrapping_offset(write_offset).cast::<AtomicUsize>(),
+                    pc,
+                ),
                 text_section: std::slice::from_raw_parts_mut(
                     raw.add(pc_loc_table_size),
                     over_allocated_code_size,
                 ),
+                #[cfg(feature = "jit-disassembler")]
+                instruction_offsets: (0..pc).map(|_| AtomicUsize::new(usize::MAX)).collect(),
+                lazy_compilation: None,
+                write_offset,
+                syscall_keys: Box::default(),
+                syscall_names: Box::default(),
+                syscall_table: Box::default(),
+                syscalls_rebound: AtomicBool::new(false),
+                unwind_info: None,
+                statistics: JitStatistics::default(),
             })
         }
     }
 
+    /// Host address of the instruction at `pc`
+    fn host_address(&self, pc: usize) -> usize {
+        self.pc_section[pc].load(Ordering::Acquire)
+    }
+
+    fn set_host_address(&self, pc: usize, host_address: usize) {
+        self.pc_section[pc].store(host_address, Ordering::Release);
+    }
+
+    /// Address of the pc_section in the executed mapping
+    fn pc_section_address(&self) -> usize {
+        self.pc_section
+            .as_ptr()
+            .cast::<u8>()
+            .wrapping_offset(-self.write_offset) as usize
+    }
+
+    /// Pointer through which the text_section is written
+    fn writable_text_section(&self) -> *mut u8 {
+        (self.text_section.as_ptr() as *mut u8).wrapping_offset(self.write_offset)
+    }
+
     fn seal(&mut self, text_section_usage: usize) -> Result<(), EbpfError> {
         if self.page_size == 0 {
             return Ok(());
         }
-        let raw = self.pc_section.as_ptr() as *mut u8;
+        let text_section = self.text_section.as_mut_ptr();
         let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
         let over_allocated_code_size = round_to_page_size(self.text_section.len(), self.page_size);
         let code_size = round_to_page_size(text_section_usage, self.page_size);
         unsafe {
             // Fill with debugger traps
             std::ptr::write_bytes(
-                raw.add(pc_loc_table_size).add(text_section_usage),
+                self.writable_text_section().add(text_section_usage),
                 0xcc,
                 code_size - text_section_usage,
             );
-            if over_allocated_code_size > code_size {
-                free_pages(
-                    raw.add(pc_loc_table_size).add(code_size),
-                    over_allocated_code_size - code_size,
+            let writable_text_section = self.writable_text_section() as usize;
+            if let Some(lazy_compilation) = self.lazy_compilation.as_mut() {
+                // Keep the remaining pages for the functions which are compiled on their first call
+                let lazy_compilation = lazy_compilation
+                    .get_mut()
+                    .unwrap_or_else(PoisonError::into_inner);
+                lazy_compilation.text_section_reservation = over_allocated_code_size;
+                lazy_compilation.text_section_length = text_section_usage;
+                lazy_compilation.text_section = text_section as usize;
+                lazy_compilation.writable_text_section = writable_text_section;
+            } else {
+                if self.write_offset != 0 {
+                    // Nothing is written anymore, so the writable mapping can go
+                    let writable = self.pc_section.as_ptr() as *mut u8;
+                    self.pc_section = std::slice::from_raw_parts(
+                        self.pc_section_address() as *const AtomicUsize,
+                        self.pc_section.len(),
+                    );
+                    self.allocator.free(writable, self.allocation_size)?;
+                    self.write_offset = 0;
+                }
+                self.allocation_size = self.allocator.shrink(
+                    self.pc_section.as_ptr() as *mut u8,
+                    self.allocation_size,
+                    pc_loc_table_size + code_size,
+                )?;
+                // The pc_section of a lazily compiled program stays writable, as later
+                // compilations update it while other threads execute the program
+                protect_pages(
+                    self.pc_section.as_ptr() as *mut u8,
+                    pc_loc_table_size,
+                    false,
                 )?;
             }
-            self.text_section =
-                std::slice::from_raw_parts_mut(raw.add(pc_loc_table_size), text_section_usage);
-            protect_pages(
-                self.pc_section.as_mut_ptr().cast::<u8>(),
-                pc_loc_table_size,
-                false,
-            )?;
+            self.text_section = std::slice::from_raw_parts_mut(text_section, text_section_usage);
             protect_pages(self.text_section.as_mut_ptr(), code_size, true)?;
         }
         Ok(())
@@ -122,7 +288,7 @@ impl JitProgram {
                 host_stack_pointer = in(reg) &mut vm.host_stack_pointer,
                 inlateout("rdi") std::ptr::addr_of_mut!(*vm).cast::<u64>().offset(get_runtime_environment_key() as isize) => _,
                 inlateout("rax") (vm.previous_instruction_meter as i64).wrapping_add(registers[11] as i64) => _,
-                inlateout("r10") self.pc_section[registers[11] as usize] => _,
+                inlateout("r10") self.host_address(registers[11] as usize) => _,
                 inlateout("r11") &registers => _,
                 lateout("rsi") _, lateout("rdx") _, lateout("rcx") _, lateout("r8") _,
                 lateout("r9") _, lateout("r12") _, lateout("r13") _, lateout("r14") _, lateout("r15") _,
@@ -132,26 +298,210 @@ impl JitProgram {
     }
 
     pub fn machine_code_length(&self) -> usize {
-        self.text_section.len()
+        match self.lock_lazy_compilation() {
+            Some(lazy_compilation) => lazy_compilation.text_section_length,
+            None => self.text_section.len(),
+        }
+    }
+
+    /// Returns the measurements of the compilation
+    pub fn statistics(&self) -> JitStatistics {
+        self.statistics
     }
 
     pub fn mem_size(&self) -> usize {
         let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
-        let code_size = round_to_page_size(self.text_section.len(), self.page_size);
-        pc_loc_table_size + code_size
+        let code_size = round_to_page_size(self.machine_code_length(), self.page_size);
+        let syscall_table_size = self.syscall_keys.len() * mem::size_of::<u32>()
+            + self
+                .syscall_names
+                .iter()
+                .map(|name| mem::size_of::<Box<[u8]>>() + name.len())
+                .sum::<usize>()
+            + self.syscall_table.len() * mem::size_of::<usize>();
+        pc_loc_table_size + code_size + syscall_table_size
+    }
+
+    /// Lets the machine code call `host_address` for the syscall `key`
+    pub(crate) fn bind_syscall(&self, key: u32, host_address: usize) {
+        if let Ok(slot) = self.syscall_keys.binary_search(&key) {
+            self.syscall_table[slot].store(host_address, Ordering::Release);
+        }
+    }
+
+    /// Replaces the implementation of the syscall registered as `name` in the compiled program
+    ///
+    /// The machine code calls syscalls indirectly through a table, so only the entry in that
+    /// table is exchanged and nothing has to be compiled again. This also works on a program
+    /// which is shared, e.g. through a `JitCache`, and thus affects every executable using it.
+    /// Executions which are running concurrently call either the old or the new implementation.
+    /// The interpreter of these executables calls the new implementation as well. A `JitCache`
+    /// does not hand out the program to other executables afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `C` has to be the context object of the executables this program was compiled for.
+    pub unsafe fn rebind_syscall<C: ContextObject>(
+        &self,
+        name: &[u8],
+        function: BuiltinFunction<C>,
+    ) -> Result<(), EbpfError> {
+        let slot = self
+            .syscall_names
+            .iter()
+            .position(|syscall_name| syscall_name.as_ref() == name)
+            .ok_or_else(|| EbpfError::SyscallNotRegistered(ebpf::hash_symbol_name(name)))?;
+        self.syscall_table[slot].store(function as usize, Ordering::Release);
+        self.syscalls_rebound.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns true if `rebind_syscall()` changed the syscall_table
+    #[cfg(feature = "unstable")]
+    pub(crate) fn has_rebound_syscalls(&self) -> bool {
+        self.syscalls_rebound.load(Ordering::Acquire)
+    }
+
+    /// Host address which the machine code calls for the syscall `key`
+    pub(crate) fn lookup_syscall(&self, key: u32) -> Option<usize> {
+        let slot = self.syscall_keys.binary_search(&key).ok()?;
+        Some(self.syscall_table.get(slot)?.load(Ordering::Acquire))
+    }
+
+    fn lock_lazy_compilation(&self) -> Option<MutexGuard<'_, LazyCompilation>> {
+        self.lazy_compilation.as_ref().map(|lazy_compilation| {
+            lazy_compilation
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+        })
+    }
+
+    /// Compiles the function containing `target_pc` and returns the host address of `target_pc`
+    ///
+    /// Does nothing if another call compiled it already.
+    fn compile_lazily<C: ContextObject>(
+        &self,
+        executable: &Executable<C>,
+        target_pc: usize,
+    ) -> Result<usize, EbpfError> {
+        let mut lazy_compilation = self
+            .lock_lazy_compilation()
+            .ok_or(EbpfError::JitNotCompiled)?;
+        if target_pc >= self.pc_section.len() {
+            return Err(EbpfError::CallOutsideTextSegment);
+        }
+        let host_address = self.host_address(target_pc);
+        if host_address != lazy_compilation.anchors[ANCHOR_LAZY_COMPILATION] {
+            return Ok(host_address);
+        }
+        JitCompiler::compile_lazily(executable, self, &mut lazy_compilation, target_pc)?;
+        Ok(self.host_address(target_pc))
+    }
+
+    /// Writes the generated machine code in Intel syntax, labeled with the originating BPF pc
+    #[cfg(feature = "jit-disassembler")]
+    pub fn disassemble(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, IntelFormatter};
+        let text_section_base = self.text_section.as_ptr() as usize;
+        let pc_labels = self
+            .instruction_offsets
+            .iter()
+            .map(|offset| offset.load(Ordering::Acquire))
+            .enumerate()
+            .filter(|(_pc, offset)| *offset != usize::MAX)
+            .map(|(pc, offset)| (text_section_base + offset, pc))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        // Includes the functions which were compiled lazily, as that machine code is sealed
+        let machine_code = unsafe {
+            std::slice::from_raw_parts(self.text_section.as_ptr(), self.machine_code_length())
+        };
+        let mut decoder = Decoder::with_ip(
+            64,
+            machine_code,
+            text_section_base as u64,
+            DecoderOptions::NONE,
+        );
+        let mut formatter = IntelFormatter::new();
+        let mut instruction = Instruction::default();
+        let mut text = String::new();
+        while decoder.can_decode() {
+            decoder.decode_out(&mut instruction);
+            let address = instruction.ip() as usize;
+            if let Some(pc) = pc_labels.get(&address) {
+                writeln!(output, "pc {pc}:")?;
+            }
+            text.clear();
+            formatter.format(&instruction, &mut text);
+            writeln!(output, "    {:08x}  {}", address - text_section_base, text)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one line in the perf map format (`START SIZE symbol`) per BPF function
+    ///
+    /// Each function spans from its first instruction up to the start of the next function
+    /// or the end of the text section.
+    pub fn write_perf_map<W: Write>(
+        &self,
+        output: &mut W,
+        function_registry: &FunctionRegistry<usize>,
+    ) -> std::io::Result<()> {
+        let mut functions = function_registry
+            .iter()
+            .filter(|(_key, (_name, pc))| *pc < self.pc_section.len())
+            .map(|(_key, (name, pc))| (pc, name))
+            .collect::<Vec<_>>();
+        functions.sort_unstable_by_key(|(pc, _name)| *pc);
+        functions.dedup_by_key(|(pc, _name)| *pc);
+        // Lazily compiled functions are neither ordered by pc nor necessarily compiled yet
+        let lazy_compilation_address = self
+            .lock_lazy_compilation()
+            .map(|lazy_compilation| lazy_compilation.anchors[ANCHOR_LAZY_COMPILATION]);
+        functions.retain(|(pc, _name)| Some(self.host_address(*pc)) != lazy_compilation_address);
+        functions.sort_by_key(|(pc, _name)| self.host_address(*pc));
+        let text_section_end = self.text_section.as_ptr() as usize + self.machine_code_length();
+        for (index, (pc, name)) in functions.iter().enumerate() {
+            let start = self.host_address(*pc);
+            let end = functions
+                .get(index + 1)
+                .map(|(next_pc, _name)| self.host_address(*next_pc))
+                .unwrap_or(text_section_end);
+            if name.is_empty() {
+                writeln!(output, "{:x} {:x} function_{}", start, end - start, pc)?;
+            } else {
+                writeln!(
+                    output,
+                    "{:x} {:x} {}",
+                    start,
+                    end - start,
+                    String::from_utf8_lossy(name)
+                )?;
+            }
+        }
+        Ok(())
     }
 }
 
 impl Drop for JitProgram {
     fn drop(&mut self) {
-        let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
-        let code_size = round_to_page_size(self.text_section.len(), self.page_size);
-        if pc_loc_table_size + code_size > 0 {
+        if self.allocation_size > 0 {
             unsafe {
-                let _ = free_pages(
-                    self.pc_section.as_ptr() as *mut u8,
-                    pc_loc_table_size + code_size,
-                );
+                // The pages go back to the allocator as they came from it, so that it can reuse them
+                let raw = self.pc_section_address() as *mut u8;
+                let _ = unprotect_pages(raw, self.allocation_size);
+                let _ = self.allocator.free(raw, self.allocation_size);
+                if self.write_offset != 0 {
+                    let _ = self
+                        .allocator
+                        .free(self.pc_section.as_ptr() as *mut u8, self.allocation_size);
+                }
+            }
+        } else {
+            // The copy made by JitCompiler::with_shared_text_section()
+            unsafe {
+                drop(Box::from_raw(
+                    self.pc_section as *const [AtomicUsize] as *mut [AtomicUsize],
+                ));
             }
         }
     }
@@ -169,6 +519,24 @@ impl PartialEq for JitProgram {
     }
 }
 
+/// Compiles the function containing `target_pc` and returns the host address to continue at
+///
+/// Returns zero after storing the error in the `program_result` if the compilation failed.
+fn lazily_compile<C: ContextObject>(vm: &mut EbpfVm<C>, target_pc: u64) -> u64 {
+    let executable = unsafe { &*vm.executable };
+    let result = executable
+        .get_compiled_program()
+        .ok_or(EbpfError::JitNotCompiled)
+        .and_then(|jit_program| jit_program.compile_lazily(executable, target_pc as usize));
+    match result {
+        Ok(host_address) => host_address as u64,
+        Err(error) => {
+            vm.program_result = ProgramResult::Err(error);
+            0
+        }
+    }
+}
+
 // Used to define subroutines and then call them
 // See JitCompiler::set_anchor() and JitCompiler::relative_to_anchor()
 const ANCHOR_TRACE: usize = 0;
@@ -185,6 +553,10 @@ const ANCHOR_CALL_UNSUPPORTED_INSTRUCTION: usize = 10;
 const ANCHOR_EXTERNAL_FUNCTION_CALL: usize = 11;
 const ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_PROLOGUE: usize = 12;
 const ANCHOR_ANCHOR_INTERNAL_FUNCTION_CALL_REG: usize = 13;
+const ANCHOR_LAZY_COMPILATION: usize = 14;
+const ANCHOR_RETPOLINE_SET_TARGET: usize = 15;
+const ANCHOR_RETPOLINE: usize = 16;
+const ANCHOR_THROW_UNSUPPORTED_INSTRUCTION: usize = 17;
 const ANCHOR_TRANSLATE_MEMORY_ADDRESS: usize = 21;
 const ANCHOR_COUNT: usize = 30; // Update me when adding or removing anchors
 
@@ -235,23 +607,55 @@ struct Argument {
 
 #[derive(Debug)]
 struct Jump {
-    location: *const u8,
+    /// Offset of the displacement in the text section
+    location: usize,
     target_pc: usize,
 }
 
+/// Text section of a JitProgram which further compilers emit into
+///
+/// Only holds plain addresses, so that it can be handed to other threads.
+struct SharedTextSection<'a> {
+    allocator: &'a Arc<dyn SectionAllocator>,
+    page_size: usize,
+    /// Host address of the start of the text section
+    base: usize,
+    /// Distance from the executed mapping to the writable mapping
+    write_offset: isize,
+    /// Host addresses of the subroutines
+    anchors: [usize; ANCHOR_COUNT],
+    syscall_keys: &'a [u32],
+    pc_section_address: usize,
+    syscall_table_address: usize,
+}
+
+/// Output of a compiler which emitted into a SharedTextSection
+struct SharedTextSectionUsage {
+    pc_section: Box<[usize]>,
+    #[cfg(feature = "jit-disassembler")]
+    instruction_offsets: Vec<usize>,
+    #[cfg(feature = "parallel-jit")]
+    text_section_jumps: Vec<Jump>,
+    unwind_call_sites: Vec<CallSite>,
+    /// End of the emitted machine code in the text section
+    text_section_length: usize,
+}
+
 /// Indices of slots inside RuntimeEnvironment
 enum RuntimeEnvironmentSlot {
     HostStackPointer = 0,
     CallDepth = 1,
     StackPointer = 2,
-    ContextObjectPointer = 3,
     PreviousInstructionMeter = 4,
     DueInsnCount = 5,
     StopwatchNumerator = 6,
     StopwatchDenominator = 7,
     Registers = 8,
     ProgramResult = 20,
-    MemoryMapping = 28,
+    MemoryRegions = 28,
+    MemoryRegionCount = 29,
+    InvokedSyscall = 30,
+    MemoryMapping = 31,
 }
 
 /* Explaination of the Instruction Meter
@@ -307,6 +711,10 @@ pub struct JitCompiler<'a, C: ContextObject> {
     result: JitProgram,
     text_section_jumps: Vec<Jump>,
     anchors: [*const u8; ANCHOR_COUNT],
+    /// Start of the text section in the executed mapping, which the offsets are relative to
+    text_section: *const u8,
+    /// Offset up to which this compiler may emit into the text section
+    text_section_end: usize,
     offset_in_text_section: usize,
     executable: &'a Executable<C>,
     program: &'a [u8],
@@ -318,12 +726,25 @@ pub struct JitCompiler<'a, C: ContextObject> {
     runtime_environment_key: i32,
     diversification_rng: SmallRng,
     stopwatch_is_active: bool,
+    /// Text section size which no program of this length can exceed
+    max_code_length: usize,
+    /// The pc_section used at runtime, which differs from result.pc_section when compiling lazily
+    pc_section_address: usize,
+    /// Address of the syscall_table of the JitProgram at runtime
+    syscall_table_address: usize,
+    /// Calls into Rust emitted so far, described by the unwind information
+    unwind_call_sites: Vec<CallSite>,
 }
 
 #[rustfmt::skip]
 impl<'a, C: ContextObject> JitCompiler<'a, C> {
     /// Constructs a new compiler and allocates memory for the compilation output
     pub fn new(executable: &'a Executable<C>) -> Result<Self, EbpfError> {
+        Self::new_with_allocator(executable, Arc::new(PageAllocator))
+    }
+
+    /// Constructs a new compiler which allocates the compilation output with `allocator`
+    pub fn new_with_allocator(executable: &'a Executable<C>, allocator: Arc<dyn SectionAllocator>) -> Result<Self, EbpfError> {
         let config = executable.get_config();
         let (program_vm_addr, program) = executable.get_text_bytes();
 
@@ -341,23 +762,54 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             pc = program.len() / ebpf::INSN_SIZE;
         }
 
-        let mut code_length_estimate = MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH + MAX_START_PADDING_LENGTH + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION * pc;
-        if config.noop_instruction_rate != 0 {
-            code_length_estimate += code_length_estimate / config.noop_instruction_rate as usize;
-        }
-        if config.instruction_meter_checkpoint_distance != 0 {
-            code_length_estimate += pc / config.instruction_meter_checkpoint_distance * MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
+        // Start with the expected code length and grow up to the worst case if that is exhausted
+        let function_count = executable.get_function_registry().iter().count() + 1;
+        let mut max_code_length = Self::estimate_code_length(config, pc, MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION);
+        if Self::compiles_in_parallel(config) {
+            // Every function might end with a bumper
+            max_code_length += function_count * MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION;
         }
+        let code_length_estimate = Self::estimate_code_length(config, pc, EXPECTED_MACHINE_CODE_LENGTH_PER_INSTRUCTION).min(max_code_length);
         // Relative jump destinations limit the maximum output size
-        debug_assert!(code_length_estimate < (i32::MAX as usize));
+        debug_assert!(max_code_length < (i32::MAX as usize));
 
         let runtime_environment_key = get_runtime_environment_key();
-        let mut diversification_rng = SmallRng::from_rng(rand::thread_rng()).map_err(|_| EbpfError::JitNotCompiled)?;
-        
+        let mut diversification_rng = Self::diversification_rng(config, 0)?;
+
+        let mut result = if config.enable_lazy_jit_compilation {
+            // Reserve the worst case up front, as every function might start on a page of its own and end with a bumper
+            let page_size = allocator.page_size();
+            let mut result = JitProgram::new(allocator, pc, max_code_length + function_count * (page_size + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION), config.enable_jit_dual_mapping)?;
+            result.lazy_compilation = Some(Mutex::new(LazyCompilation {
+                anchors: [0; ANCHOR_COUNT],
+                text_section_reservation: 0,
+                text_section_length: 0,
+                text_section: 0,
+                writable_text_section: 0,
+                compiled_functions: BTreeSet::new(),
+                unwind_info: Vec::new(),
+            }));
+            result
+        } else {
+            JitProgram::new(allocator, pc, code_length_estimate, config.enable_jit_dual_mapping)?
+        };
+        let pc_section_address = result.pc_section_address();
+        let function_registry = executable.get_loader().get_function_registry();
+        result.syscall_keys = function_registry.iter().map(|(key, _)| key).collect();
+        result.syscall_names = function_registry.iter().map(|(_key, (name, _function))| name.into()).collect();
+        result.syscall_table = function_registry.iter()
+            .map(|(key, (_name, function))| AtomicUsize::new(executable.lookup_syscall(key).unwrap_or(function) as usize))
+            .collect();
+        let syscall_table_address = result.syscall_table.as_ptr() as usize;
+        let text_section = result.text_section.as_mut_ptr() as *const u8;
+        let text_section_end = result.text_section.len();
+
         Ok(Self {
-            result: JitProgram::new(pc, code_length_estimate)?,
+            result,
             text_section_jumps: vec![],
             anchors: [std::ptr::null(); ANCHOR_COUNT],
+            text_section,
+            text_section_end,
             offset_in_text_section: 0,
             executable,
             program_vm_addr,
@@ -365,19 +817,244 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             config,
             pc: 0,
             last_instruction_meter_validation_pc: 0,
-            next_noop_insertion: if config.noop_instruction_rate == 0 { u32::MAX } else { diversification_rng.gen_range(0..config.noop_instruction_rate * 2) },
+            next_noop_insertion: Self::first_noop_insertion(config, &mut diversification_rng),
             runtime_environment_key,
             diversification_rng,
             stopwatch_is_active: false,
+            max_code_length,
+            pc_section_address,
+            syscall_table_address,
+            unwind_call_sites: Vec::new(),
+        })
+    }
+
+    /// Constructs a compiler which emits into `region` of a text section allocated before
+    ///
+    /// It calls the subroutines of the shared text section instead of emitting its own and
+    /// records the host addresses of the instructions in the given copy of the pc_section.
+    /// Only the bytes in `region` are written, so that concurrent compilers with disjoint
+    /// regions do not interfere.
+    fn with_shared_text_section(executable: &'a Executable<C>, shared_text_section: &SharedTextSection, pc_section: Vec<usize>, region: Range<usize>) -> Result<Self, EbpfError> {
+        let config = executable.get_config();
+        let (program_vm_addr, program) = executable.get_text_bytes();
+        let mut diversification_rng = Self::diversification_rng(config, region.start as u64)?;
+        #[cfg(feature = "jit-disassembler")]
+        let pc_count = pc_section.len();
+        debug_assert!(region.start <= region.end);
+        Ok(Self {
+            result: JitProgram {
+                allocator: shared_text_section.allocator.clone(),
+                allocation_size: 0,
+                page_size: shared_text_section.page_size,
+                pc_section: Box::leak(pc_section.into_iter().map(AtomicUsize::new).collect()),
+                // Emission goes through `text_section` below, this compiler does not own any of it
+                text_section: &mut [],
+                #[cfg(feature = "jit-disassembler")]
+                instruction_offsets: (0..pc_count).map(|_| AtomicUsize::new(usize::MAX)).collect(),
+                lazy_compilation: None,
+                write_offset: shared_text_section.write_offset,
+                syscall_keys: shared_text_section.syscall_keys.into(),
+                syscall_names: Box::default(),
+                syscall_table: Box::default(),
+                syscalls_rebound: AtomicBool::new(false),
+                unwind_info: None,
+                statistics: JitStatistics::default(),
+            },
+            text_section_jumps: vec![],
+            anchors: shared_text_section.anchors.map(|anchor| anchor as *const u8),
+            text_section: shared_text_section.base as *const u8,
+            text_section_end: region.end,
+            offset_in_text_section: region.start,
+            executable,
+            program_vm_addr,
+            program,
+            config,
+            pc: 0,
+            last_instruction_meter_validation_pc: 0,
+            next_noop_insertion: Self::first_noop_insertion(config, &mut diversification_rng),
+            runtime_environment_key: get_runtime_environment_key(),
+            diversification_rng,
+            stopwatch_is_active: false,
+            max_code_length: region.end,
+            pc_section_address: shared_text_section.pc_section_address,
+            syscall_table_address: shared_text_section.syscall_table_address,
+            unwind_call_sites: Vec::new(),
         })
     }
 
+    /// Takes the output of a compiler constructed by `with_shared_text_section()`
+    fn into_shared_text_section_usage(self) -> SharedTextSectionUsage {
+        SharedTextSectionUsage {
+            pc_section: self.result.pc_section.iter().map(|host_address| host_address.load(Ordering::Relaxed)).collect(),
+            #[cfg(feature = "jit-disassembler")]
+            instruction_offsets: self.result.instruction_offsets.iter().map(|offset| offset.load(Ordering::Relaxed)).collect(),
+            #[cfg(feature = "parallel-jit")]
+            text_section_jumps: self.text_section_jumps,
+            unwind_call_sites: self.unwind_call_sites,
+            text_section_length: self.offset_in_text_section,
+        }
+    }
+
+    /// Compiles the functions needed to execute `target_pc` into the reserved pages of `jit_program`
+    ///
+    /// The machine code is emitted with a copy of the pc_section, which only replaces the original
+    /// entries once the new pages are sealed. Thus, other threads never jump into unsealed code.
+    /// Only the reserved pages, which no other thread executes yet, change their protection.
+    /// The pc_section stays writable and its entries are replaced atomically.
+    fn compile_lazily(executable: &'a Executable<C>, jit_program: &JitProgram, lazy_compilation: &mut LazyCompilation, target_pc: usize) -> Result<(), EbpfError> {
+        let config = executable.get_config();
+        let page_size = jit_program.page_size;
+        let text_section_base = lazy_compilation.text_section as *mut u8;
+        let text_section_offset = round_to_page_size(lazy_compilation.text_section_length, page_size);
+        let shared_text_section = SharedTextSection {
+            allocator: &jit_program.allocator,
+            page_size,
+            base: text_section_base as usize,
+            write_offset: jit_program.write_offset,
+            anchors: lazy_compilation.anchors,
+            syscall_keys: &jit_program.syscall_keys,
+            pc_section_address: jit_program.pc_section_address(),
+            syscall_table_address: jit_program.syscall_table.as_ptr() as usize,
+        };
+        let mut compiler = Self::with_shared_text_section(
+            executable,
+            &shared_text_section,
+            jit_program.pc_section.iter().map(|host_address| host_address.load(Ordering::Acquire)).collect(),
+            text_section_offset..lazy_compilation.text_section_reservation,
+        )?;
+
+        let (functions, spans) = compiler.select_function_batch(target_pc, &lazy_compilation.compiled_functions);
+        for span in spans.iter() {
+            for pc in span.clone() {
+                compiler.result.set_host_address(pc, 0);
+            }
+        }
+        let result = compiler.compile_spans(&spans);
+        if result.is_ok() {
+            compiler.resolve_jumps();
+        }
+        let host_stack_pointer_offset = compiler.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer);
+        let usage = compiler.into_shared_text_section_usage();
+        let (pc_section, text_section_length) = (&usage.pc_section, usage.text_section_length);
+        result?;
+
+        let code_size = round_to_page_size(text_section_length, page_size);
+        unsafe {
+            // Fill with debugger traps
+            let writable_text_section = lazy_compilation.writable_text_section as *mut u8;
+            ptr::write_bytes(writable_text_section.add(text_section_length), 0xcc, code_size - text_section_length);
+            protect_pages(text_section_base.add(text_section_offset), code_size - text_section_offset, true)?;
+        }
+        for pc in spans.iter().flat_map(|span| span.clone()) {
+            jit_program.set_host_address(pc, pc_section[pc]);
+            #[cfg(feature = "jit-disassembler")]
+            jit_program.instruction_offsets[pc].store(usage.instruction_offsets[pc], Ordering::Release);
+        }
+        if config.enable_jit_unwind_info {
+            let text_section_base = text_section_base as usize;
+            lazy_compilation.unwind_info.push(EhFrame::new(
+                text_section_base,
+                text_section_base + text_section_offset..text_section_base + text_section_length,
+                host_stack_pointer_offset,
+                &usage.unwind_call_sites,
+            ));
+        }
+        lazy_compilation.text_section_length = text_section_length;
+        lazy_compilation.compiled_functions.extend(functions);
+        Ok(())
+    }
+
+    /// Creates the randomness of a compilation, `stream` tells concurrent compilers apart
+    fn diversification_rng(config: &Config, stream: u64) -> Result<SmallRng, EbpfError> {
+        match config.jit_diversification_seed {
+            Some(seed) => Ok(SmallRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))),
+            None => SmallRng::from_rng(rand::thread_rng()).map_err(|_| EbpfError::JitNotCompiled),
+        }
+    }
+
+    /// Number of instructions to emit before the first random no-op
+    fn first_noop_insertion(config: &Config, diversification_rng: &mut SmallRng) -> u32 {
+        if !config.enable_jit_diversification || config.noop_instruction_rate == 0 {
+            u32::MAX
+        } else {
+            diversification_rng.gen_range(0..config.noop_instruction_rate * 2)
+        }
+    }
+
+    /// Size of the text section for `pc` instructions of the given machine code length
+    fn estimate_code_length(config: &Config, pc: usize, machine_code_length_per_instruction: usize) -> usize {
+        // The bumper after the last instruction has the same bound as an instruction
+        let mut code_length_estimate = MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH + MAX_START_PADDING_LENGTH + machine_code_length_per_instruction * pc + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION;
+        if config.noop_instruction_rate != 0 {
+            code_length_estimate += code_length_estimate / config.noop_instruction_rate as usize;
+        }
+        if config.exact_instruction_meter {
+            code_length_estimate += pc * MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
+        } else if let Some(checkpoints) = pc.checked_div(config.instruction_meter_checkpoint_distance) {
+            code_length_estimate += checkpoints * MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
+        }
+        code_length_estimate
+    }
+
     /// Compiles the given executable, consuming the compiler
+    ///
+    /// If the text section turns out to be too small, it is reallocated with twice the size
+    /// (up to the worst case estimate) and the compilation starts over.
     pub fn compile(mut self) -> Result<JitProgram, EbpfError> {
-        let text_section_base = self.result.text_section.as_ptr();
+        let start_time = Instant::now();
+        loop {
+            match self.compile_into_text_section() {
+                Err(EbpfError::ExhaustedTextSegment(_)) if self.result.text_section.len() < self.max_code_length => {
+                    let code_length = self.result.text_section.len().saturating_mul(2).min(self.max_code_length);
+                    let syscall_keys = mem::take(&mut self.result.syscall_keys);
+                    let syscall_names = mem::take(&mut self.result.syscall_names);
+                    let syscall_table = mem::take(&mut self.result.syscall_table);
+                    self.result = JitProgram::new(self.result.allocator.clone(), self.result.pc_section.len(), code_length, self.config.enable_jit_dual_mapping)?;
+                    self.result.syscall_keys = syscall_keys;
+                    self.result.syscall_names = syscall_names;
+                    self.result.syscall_table = syscall_table;
+                    self.pc_section_address = self.result.pc_section_address();
+                    self.text_section = self.result.text_section.as_mut_ptr() as *const u8;
+                    self.text_section_end = self.result.text_section.len();
+                    self.text_section_jumps.clear();
+                    self.unwind_call_sites.clear();
+                    self.anchors = [std::ptr::null(); ANCHOR_COUNT];
+                    self.offset_in_text_section = 0;
+                    self.pc = 0;
+                    self.last_instruction_meter_validation_pc = 0;
+                    self.stopwatch_is_active = false;
+                }
+                Err(error) => return Err(error),
+                Ok(()) => break,
+            }
+        }
 
+        if self.config.enable_jit_unwind_info {
+            let text_section_base = self.result.text_section.as_ptr() as usize;
+            self.result.unwind_info = Some(EhFrame::new(
+                text_section_base,
+                text_section_base..text_section_base + self.offset_in_text_section,
+                self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer),
+                &self.unwind_call_sites,
+            ));
+        }
+
+        // Profiling with perf is best effort, failing to write the map must not fail the compilation
+        if self.config.enable_perf_map {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(format!("/tmp/perf-{}.map", std::process::id()))
+                .and_then(|mut file| self.result.write_perf_map(&mut file, self.executable.get_function_registry()));
+        }
+        self.result.statistics.compile_time = start_time.elapsed();
+        Ok(self.result)
+    }
+
+    /// Emits the machine code into the current text section and seals it
+    fn compile_into_text_section(&mut self) -> Result<(), EbpfError> {
         // Randomized padding at the start before random intervals begin
-        if self.config.noop_instruction_rate != 0 {
+        if self.config.enable_jit_diversification && self.config.noop_instruction_rate != 0 {
             for _ in 0..self.diversification_rng.gen_range(0..MAX_START_PADDING_LENGTH) {
                 // X86Instruction::noop().emit(self)?;
                 self.emit::<u8>(0x90);
@@ -386,347 +1063,578 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
 
         self.emit_subroutines();
 
-        while self.pc * ebpf::INSN_SIZE < self.program.len() {
-            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
-                return Err(EbpfError::ExhaustedTextSegment(self.pc));
+        let profile = self.executable.get_profile().filter(|_| self.config.enable_profile_guided_layout);
+        let (functions, spans) = if self.config.enable_lazy_jit_compilation {
+            self.select_function_batch(self.executable.get_entrypoint_instruction_offset(), &BTreeSet::new())
+        } else if let Some(profile) = profile {
+            (BTreeSet::new(), self.select_spans_by_profile(profile))
+        } else {
+            (BTreeSet::new(), std::iter::once(0..self.result.pc_section.len()).collect())
+        };
+        if Self::compiles_in_parallel(self.config) {
+            #[cfg(feature = "parallel-jit")]
+            self.compile_spans_in_parallel()?;
+        } else {
+            self.compile_spans(&spans)?;
+        }
+
+        if let Some(lazy_compilation) = self.result.lazy_compilation.as_mut() {
+            // All other functions are compiled on their first call
+            let lazy_compilation = lazy_compilation.get_mut().unwrap_or_else(PoisonError::into_inner);
+            lazy_compilation.anchors = self.anchors.map(|anchor| anchor as usize);
+            lazy_compilation.compiled_functions = functions;
+            for host_address in self.result.pc_section.iter().filter(|host_address| host_address.load(Ordering::Relaxed) == 0) {
+                host_address.store(lazy_compilation.anchors[ANCHOR_LAZY_COMPILATION], Ordering::Relaxed);
             }
-            let mut insn = ebpf::get_insn_unchecked(self.program, self.pc);
-            self.result.pc_section[self.pc] = unsafe { text_section_base.add(self.offset_in_text_section) } as usize;
+        }
+
+        self.resolve_jumps();
+        self.result.statistics = JitStatistics {
+            machine_code_length: self.offset_in_text_section,
+            instruction_count: spans.iter().map(|span| span.len()).sum(),
+            resolved_jumps: self.text_section_jumps.len(),
+            anchors_used: self.anchors.iter().filter(|anchor| !anchor.is_null()).count(),
+            compile_time: Duration::ZERO,
+        };
+        self.result.seal(self.offset_in_text_section)
+    }
 
-            // Regular instruction meter checkpoints to prevent long linear runs from exceeding their budget
-            if self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= self.pc {
-                self.emit_validate_instruction_count(true, Some(self.pc));
+    /// Whether the whole program is compiled up front on multiple threads
+    fn compiles_in_parallel(config: &Config) -> bool {
+        cfg!(feature = "parallel-jit") && config.enable_parallel_jit_compilation && !config.enable_lazy_jit_compilation
+    }
+
+    /// Emits the whole program on multiple threads, each into its own region of the text section
+    ///
+    /// The spans are distributed in batches of about the same number of instructions and the
+    /// remaining text section is divided in proportion to them. Jumps between the regions are
+    /// resolved afterwards, like any other forward jump.
+    #[cfg(feature = "parallel-jit")]
+    fn compile_spans_in_parallel(&mut self) -> Result<(), EbpfError> {
+        use rayon::prelude::*;
+        let pc_count = self.result.pc_section.len();
+        let batch_length = pc_count / rayon::current_num_threads() + 1;
+        let mut batches: Vec<Vec<Range<usize>>> = Vec::new();
+        let mut current_batch_length = batch_length;
+        for span in self.select_independent_spans() {
+            if current_batch_length >= batch_length {
+                batches.push(Vec::new());
+                current_batch_length = 0;
             }
+            current_batch_length += span.len();
+            batches.last_mut().unwrap().push(span);
+        }
+        // Every span ends with a bumper, which has the same bound as an instruction
+        let weights = batches.iter().map(|spans| spans.iter().map(|span| span.len() + 1).sum::<usize>()).collect::<Vec<_>>();
+        let total_weight = weights.iter().sum::<usize>();
+        let available_length = self.text_section_end - self.offset_in_text_section;
+        let mut region_start = self.offset_in_text_section;
+        let regions = weights.iter().map(|weight| {
+            let region = region_start..region_start + available_length * weight / total_weight;
+            region_start = region.end;
+            region
+        }).collect::<Vec<_>>();
+
+        let shared_text_section = SharedTextSection {
+            allocator: &self.result.allocator,
+            page_size: self.result.page_size,
+            base: self.text_section as usize,
+            write_offset: self.result.write_offset,
+            anchors: self.anchors.map(|anchor| anchor as usize),
+            syscall_keys: &self.result.syscall_keys,
+            pc_section_address: self.pc_section_address,
+            syscall_table_address: self.syscall_table_address,
+        };
+        let executable = self.executable;
+        let compiled_batches = batches.par_iter().zip(regions.par_iter()).map(|(spans, region)| {
+            Self::with_shared_text_section(executable, &shared_text_section, vec![0; pc_count], region.clone()).and_then(|mut compiler| {
+                let result = compiler.compile_spans(spans);
+                let usage = compiler.into_shared_text_section_usage();
+                result.map(|_| usage)
+            }).map_err(crate::error::SendableError::from)
+        }).collect::<Vec<_>>();
+
+        for ((spans, region), compiled_batch) in batches.iter().zip(regions.iter()).zip(compiled_batches) {
+            let usage = compiled_batch?;
+            if self.offset_in_text_section < region.start {
+                // Fill the gap after the previous region with debugger traps
+                unsafe { ptr::write_bytes(self.writable_text_section().add(self.offset_in_text_section), 0xcc, region.start - self.offset_in_text_section); }
+            }
+            for span in spans {
+                for pc in span.clone() {
+                    self.result.set_host_address(pc, usage.pc_section[pc]);
+                    #[cfg(feature = "jit-disassembler")]
+                    self.result.instruction_offsets[pc].store(usage.instruction_offsets[pc], Ordering::Relaxed);
+                }
+            }
+            self.text_section_jumps.extend(usage.text_section_jumps);
+            self.unwind_call_sites.extend(usage.unwind_call_sites);
+            self.offset_in_text_section = usage.text_section_length;
+        }
+        Ok(())
+    }
 
-            if self.config.enable_instruction_tracing {
-                self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
-                self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_TRACE, 5)));
-                self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, 0));
+    /// Splits the program in front of every function which the previous one does not fall through into
+    fn select_independent_spans(&self) -> Vec<Range<usize>> {
+        let pc_count = self.result.pc_section.len();
+        let function_starts = self.executable.get_function_registry().iter()
+            .map(|(_key, (_name, pc))| pc)
+            .collect::<BTreeSet<_>>();
+        let mut spans = std::iter::once(0..pc_count).collect::<Vec<_>>();
+        let mut pc = 0;
+        let mut falls_through = true;
+        while pc < pc_count && (pc + 1) * ebpf::INSN_SIZE <= self.program.len() {
+            if !falls_through && function_starts.contains(&pc) {
+                spans.last_mut().unwrap().end = pc;
+                spans.push(pc..pc_count);
             }
+            let insn = ebpf::get_insn_unchecked(self.program, pc);
+            falls_through = !matches!(insn.opc, ebpf::JA | ebpf::EXIT);
+            pc += if insn.opc == ebpf::LD_DW_IMM && self.executable.get_sbpf_version().enable_lddw() { 2 } else { 1 };
+        }
+        spans
+    }
 
-            let dst = if insn.dst == STACK_PTR_REG as u8 { u8::MAX } else { REGISTER_MAP[insn.dst as usize] };
-            let src = REGISTER_MAP[insn.src as usize];
-            let target_pc = (self.pc as isize + insn.off as isize + 1) as usize;
+    /// Orders the independent spans by the edge counters of a profile, hottest first
+    ///
+    /// The hot functions are thereby emitted next to each other, so that they share cache lines
+    /// and pages. Spans which the profile never entered keep their order at the end.
+    fn select_spans_by_profile(&self, profile: &DynamicAnalysis) -> Vec<Range<usize>> {
+        let pc_count = self.result.pc_section.len();
+        let spans = self.select_independent_spans();
+        let mut counters = vec![0usize; spans.len()];
+        for (destination, counter) in profile.edges.values().flat_map(|destinations| destinations.iter()) {
+            if *destination < pc_count {
+                let index = spans.partition_point(|span| span.start <= *destination) - 1;
+                counters[index] = counters[index].saturating_add(*counter);
+            }
+        }
+        let mut order = (0..spans.len()).collect::<Vec<_>>();
+        order.sort_by_key(|index| std::cmp::Reverse(counters[*index]));
+        order.into_iter().map(|index| spans[index].clone()).collect()
+    }
 
-            match insn.opc {
-                ebpf::ADD64_IMM if insn.dst == STACK_PTR_REG as u8 && self.executable.get_sbpf_version().dynamic_stack_frames() => {
-                    let stack_ptr_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::StackPointer));
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, REGISTER_PTR_TO_VM, insn.imm, Some(stack_ptr_access)));
+    /// Emits the instructions of each span of pcs, each followed by a bumper
+    fn compile_spans(&mut self, spans: &[Range<usize>]) -> Result<(), EbpfError> {
+        let text_section_base = self.text_section;
+        for span in spans {
+            self.pc = span.start;
+            self.last_instruction_meter_validation_pc = span.start;
+            while self.pc < span.end && self.pc * ebpf::INSN_SIZE < self.program.len() {
+                if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.text_section_end {
+                    return Err(EbpfError::ExhaustedTextSegment(self.pc));
+                }
+                let mut insn = ebpf::get_insn_unchecked(self.program, self.pc);
+                self.result.set_host_address(self.pc, unsafe { text_section_base.add(self.offset_in_text_section) } as usize);
+                #[cfg(feature = "jit-disassembler")]
+                self.result.instruction_offsets[self.pc].store(self.offset_in_text_section, Ordering::Relaxed);
+
+                // Regular instruction meter checkpoints to prevent long linear runs from exceeding their budget
+                if self.config.exact_instruction_meter || self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= self.pc {
+                    self.emit_validate_instruction_count(true, Some(self.pc));
                 }
 
-                ebpf::LD_DW_IMM if self.executable.get_sbpf_version().enable_lddw() => {
-                    self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
-                    self.pc += 1;
-                    self.result.pc_section[self.pc] = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
-                    ebpf::augment_lddw_unchecked(self.program, &mut insn);
-                    if self.should_sanitize_constant(insn.imm) {
-                        self.emit_sanitized_load_immediate(OperandSize::S64, dst, insn.imm);
-                    } else {
-                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, insn.imm));
+                if self.config.enable_instruction_tracing {
+                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
+                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_OTHER_SCRATCH, (insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP) as i64));
+                    self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_TRACE, 5)));
+                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, 0));
+                }
+
+                let dst = if insn.dst == STACK_PTR_REG as u8 { u8::MAX } else { REGISTER_MAP[insn.dst as usize] };
+                let src = REGISTER_MAP[insn.src as usize];
+                let target_pc = (self.pc as isize + insn.off as isize + 1) as usize;
+
+                if !is_opcode_supported(insn.opc, self.executable.get_sbpf_version()) {
+                    return Err(EbpfError::UnsupportedInstruction);
+                }
+                match insn.opc {
+                    ebpf::ADD64_IMM if insn.dst == STACK_PTR_REG as u8 && self.executable.get_sbpf_version().dynamic_stack_frames() => {
+                        let stack_ptr_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::StackPointer));
+                        self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, REGISTER_PTR_TO_VM, insn.imm, Some(stack_ptr_access)));
                     }
-                },
 
-                // BPF_LDX class
-                ebpf::LD_B_REG   => {
-                    self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 1, None);
-                },
-                ebpf::LD_H_REG   => {
-                    self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 2, None);
-                },
-                ebpf::LD_W_REG   => {
-                    self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 4, None);
-                },
-                ebpf::LD_DW_REG  => {
-                    self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 8, None);
-                },
+                    ebpf::LD_DW_IMM => {
+                        self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
+                        self.pc += 1;
+                        self.result.set_host_address(self.pc, self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize);
+                        ebpf::augment_lddw_unchecked(self.program, &mut insn);
+                        if self.should_sanitize_constant(insn.imm) {
+                            self.emit_sanitized_load_immediate(OperandSize::S64, dst, insn.imm);
+                        } else {
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, insn.imm));
+                        }
+                    },
 
-                // BPF_ST class
-                ebpf::ST_B_IMM   => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 1, Some(Value::Constant64(insn.imm, true)));
-                },
-                ebpf::ST_H_IMM   => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 2, Some(Value::Constant64(insn.imm, true)));
-                },
-                ebpf::ST_W_IMM   => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 4, Some(Value::Constant64(insn.imm, true)));
-                },
-                ebpf::ST_DW_IMM  => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, Some(Value::Constant64(insn.imm, true)));
-                },
+                    // BPF_LDX class
+                    ebpf::LD_B_REG   => {
+                        self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 1, None);
+                    },
+                    ebpf::LD_H_REG   => {
+                        self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 2, None);
+                    },
+                    ebpf::LD_W_REG   => {
+                        self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 4, None);
+                    },
+                    ebpf::LD_DW_REG  => {
+                        self.emit_address_translation(Some(dst), Value::RegisterPlusConstant64(src, insn.off as i64, true), 8, None);
+                    },
 
-                // BPF_STX class
-                ebpf::ST_B_REG  => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 1, Some(Value::Register(src)));
-                },
-                ebpf::ST_H_REG  => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 2, Some(Value::Register(src)));
-                },
-                ebpf::ST_W_REG  => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 4, Some(Value::Register(src)));
-                },
-                ebpf::ST_DW_REG  => {
-                    self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, Some(Value::Register(src)));
-                },
+                    // BPF_ST class
+                    ebpf::ST_B_IMM   => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 1, Some(Value::Constant64(insn.imm, true)));
+                    },
+                    ebpf::ST_H_IMM   => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 2, Some(Value::Constant64(insn.imm, true)));
+                    },
+                    ebpf::ST_W_IMM   => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 4, Some(Value::Constant64(insn.imm, true)));
+                    },
+                    ebpf::ST_DW_IMM  => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, Some(Value::Constant64(insn.imm, true)));
+                    },
 
-                // BPF_ALU class
-                ebpf::ADD32_IMM  => {
-                    self.emit_sanitized_alu(OperandSize::S32, 0x01, 0, dst, insn.imm);
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
-                },
-                ebpf::ADD32_REG  => {
-                    self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x01, src, dst, 0, None));
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
-                },
-                ebpf::SUB32_IMM  => {
-                    if self.executable.get_sbpf_version().swap_sub_reg_imm_operands() {
-                        self.emit_ins(X86Instruction::alu(OperandSize::S32, 0xf7, 3, dst, 0, None));
-                        if insn.imm != 0 {
-                            self.emit_sanitized_alu(OperandSize::S32, 0x01, 0, dst, insn.imm);
+                    // BPF_STX class
+                    ebpf::ST_B_REG  => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 1, Some(Value::Register(src)));
+                    },
+                    ebpf::ST_H_REG  => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 2, Some(Value::Register(src)));
+                    },
+                    ebpf::ST_W_REG  => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 4, Some(Value::Register(src)));
+                    },
+                    ebpf::ST_DW_REG  => {
+                        self.emit_address_translation(None, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, Some(Value::Register(src)));
+                    },
+
+                    // BPF_ALU class
+                    ebpf::ADD32_IMM  => {
+                        self.emit_sanitized_alu(OperandSize::S32, 0x01, 0, dst, insn.imm);
+                        self.emit_sign_extension(dst);
+                    },
+                    ebpf::ADD32_REG  => {
+                        self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x01, src, dst, 0, None));
+                        self.emit_sign_extension(dst);
+                    },
+                    ebpf::SUB32_IMM  => {
+                        if self.executable.get_sbpf_version().swap_sub_reg_imm_operands() {
+                            self.emit_ins(X86Instruction::alu(OperandSize::S32, 0xf7, 3, dst, 0, None));
+                            if insn.imm != 0 {
+                                self.emit_sanitized_alu(OperandSize::S32, 0x01, 0, dst, insn.imm);
+                            }
+                        } else {
+                            self.emit_sanitized_alu(OperandSize::S32, 0x29, 5, dst, insn.imm);
+                        }
+                        self.emit_sign_extension(dst);
+                    },
+                    ebpf::SUB32_REG  => {
+                        self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x29, src, dst, 0, None));
+                        self.emit_sign_extension(dst);
+                    },
+                    ebpf::MUL32_IMM | ebpf::DIV32_IMM | ebpf::MOD32_IMM =>
+                        self.emit_product_quotient_remainder(OperandSize::S32, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, dst, dst, Some(insn.imm)),
+                    ebpf::MUL32_REG | ebpf::DIV32_REG | ebpf::MOD32_REG =>
+                        self.emit_product_quotient_remainder(OperandSize::S32, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, src, dst, None),
+                    ebpf::OR32_IMM   => self.emit_sanitized_alu(OperandSize::S32, 0x09, 1, dst, insn.imm),
+                    ebpf::OR32_REG   => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x09, src, dst, 0, None)),
+                    ebpf::AND32_IMM  => self.emit_sanitized_alu(OperandSize::S32, 0x21, 4, dst, insn.imm),
+                    ebpf::AND32_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x21, src, dst, 0, None)),
+                    ebpf::LSH32_IMM  => self.emit_shift(OperandSize::S32, 4, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::LSH32_REG  => self.emit_shift(OperandSize::S32, 4, src, dst, None),
+                    ebpf::RSH32_IMM  => self.emit_shift(OperandSize::S32, 5, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::RSH32_REG  => self.emit_shift(OperandSize::S32, 5, src, dst, None),
+                    ebpf::NEG32     => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0xf7, 3, dst, 0, None)),
+                    ebpf::XOR32_IMM  => self.emit_sanitized_alu(OperandSize::S32, 0x31, 6, dst, insn.imm),
+                    ebpf::XOR32_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x31, src, dst, 0, None)),
+                    ebpf::MOV32_IMM  => {
+                        if self.should_sanitize_constant(insn.imm) {
+                            self.emit_sanitized_load_immediate(OperandSize::S32, dst, insn.imm);
+                        } else {
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S32, dst, insn.imm));
                         }
-                    } else {
-                        self.emit_sanitized_alu(OperandSize::S32, 0x29, 5, dst, insn.imm);
-                    }
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
-                },
-                ebpf::SUB32_REG  => {
-                    self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x29, src, dst, 0, None));
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
-                },
-                ebpf::MUL32_IMM | ebpf::DIV32_IMM | ebpf::MOD32_IMM if !self.executable.get_sbpf_version().enable_pqr() =>
-                    self.emit_product_quotient_remainder(OperandSize::S32, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, dst, dst, Some(insn.imm)),
-                ebpf::MUL32_REG | ebpf::DIV32_REG | ebpf::MOD32_REG if !self.executable.get_sbpf_version().enable_pqr() =>
-                    self.emit_product_quotient_remainder(OperandSize::S32, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, src, dst, None),
-                ebpf::OR32_IMM   => self.emit_sanitized_alu(OperandSize::S32, 0x09, 1, dst, insn.imm),
-                ebpf::OR32_REG   => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x09, src, dst, 0, None)),
-                ebpf::AND32_IMM  => self.emit_sanitized_alu(OperandSize::S32, 0x21, 4, dst, insn.imm),
-                ebpf::AND32_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x21, src, dst, 0, None)),
-                ebpf::LSH32_IMM  => self.emit_shift(OperandSize::S32, 4, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::LSH32_REG  => self.emit_shift(OperandSize::S32, 4, src, dst, None),
-                ebpf::RSH32_IMM  => self.emit_shift(OperandSize::S32, 5, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::RSH32_REG  => self.emit_shift(OperandSize::S32, 5, src, dst, None),
-                ebpf::NEG32     if self.executable.get_sbpf_version().enable_neg() => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0xf7, 3, dst, 0, None)),
-                ebpf::XOR32_IMM  => self.emit_sanitized_alu(OperandSize::S32, 0x31, 6, dst, insn.imm),
-                ebpf::XOR32_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x31, src, dst, 0, None)),
-                ebpf::MOV32_IMM  => {
-                    if self.should_sanitize_constant(insn.imm) {
-                        self.emit_sanitized_load_immediate(OperandSize::S32, dst, insn.imm);
-                    } else {
-                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S32, dst, insn.imm));
                     }
-                }
-                ebpf::MOV32_REG  => self.emit_ins(X86Instruction::mov(OperandSize::S32, src, dst)),
-                ebpf::ARSH32_IMM => self.emit_shift(OperandSize::S32, 7, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::ARSH32_REG => self.emit_shift(OperandSize::S32, 7, src, dst, None),
-                ebpf::LE if self.executable.get_sbpf_version().enable_le() => {
-                    match insn.imm {
-                        16 => {
-                            self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xffff, None)); // Mask to 16 bit
+                    ebpf::MOV32_REG  => self.emit_ins(X86Instruction::mov(OperandSize::S32, src, dst)),
+                    ebpf::ARSH32_IMM => self.emit_shift(OperandSize::S32, 7, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::ARSH32_REG => self.emit_shift(OperandSize::S32, 7, src, dst, None),
+                    ebpf::LE        => {
+                        match insn.imm {
+                            16 => {
+                                self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xffff, None)); // Mask to 16 bit
+                            }
+                            32 => {
+                                self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, -1, None)); // Mask to 32 bit
+                            }
+                            64 => {}
+                            _ => {
+                                return Err(EbpfError::InvalidInstruction);
+                            }
                         }
-                        32 => {
-                            self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, -1, None)); // Mask to 32 bit
+                    },
+                    ebpf::BE         => {
+                        match insn.imm {
+                            16 => {
+                                self.emit_ins(X86Instruction::bswap(OperandSize::S16, dst));
+                                self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xffff, None)); // Mask to 16 bit
+                            }
+                            32 => self.emit_ins(X86Instruction::bswap(OperandSize::S32, dst)),
+                            64 => self.emit_ins(X86Instruction::bswap(OperandSize::S64, dst)),
+                            _ => {
+                                return Err(EbpfError::InvalidInstruction);
+                            }
                         }
-                        64 => {}
-                        _ => {
-                            return Err(EbpfError::InvalidInstruction);
+                    },
+
+                    // BPF_ALU64 class
+                    ebpf::ADD64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x01, 0, dst, insn.imm),
+                    ebpf::ADD64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, src, dst, 0, None)),
+                    ebpf::SUB64_IMM  => {
+                        if self.executable.get_sbpf_version().swap_sub_reg_imm_operands() {
+                            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xf7, 3, dst, 0, None));
+                            if insn.imm != 0 {
+                                self.emit_sanitized_alu(OperandSize::S64, 0x01, 0, dst, insn.imm);
+                            }
+                        } else {
+                            self.emit_sanitized_alu(OperandSize::S64, 0x29, 5, dst, insn.imm);
                         }
                     }
-                },
-                ebpf::BE         => {
-                    match insn.imm {
-                        16 => {
-                            self.emit_ins(X86Instruction::bswap(OperandSize::S16, dst));
-                            self.emit_ins(X86Instruction::alu(OperandSize::S32, 0x81, 4, dst, 0xffff, None)); // Mask to 16 bit
-                        }
-                        32 => self.emit_ins(X86Instruction::bswap(OperandSize::S32, dst)),
-                        64 => self.emit_ins(X86Instruction::bswap(OperandSize::S64, dst)),
-                        _ => {
-                            return Err(EbpfError::InvalidInstruction);
+                    ebpf::SUB64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x29, src, dst, 0, None)),
+                    ebpf::MUL64_IMM | ebpf::DIV64_IMM | ebpf::MOD64_IMM =>
+                        self.emit_product_quotient_remainder(OperandSize::S64, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, dst, dst, Some(insn.imm)),
+                    ebpf::MUL64_REG | ebpf::DIV64_REG | ebpf::MOD64_REG =>
+                        self.emit_product_quotient_remainder(OperandSize::S64, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, src, dst, None),
+                    ebpf::OR64_IMM   => self.emit_sanitized_alu(OperandSize::S64, 0x09, 1, dst, insn.imm),
+                    ebpf::OR64_REG   => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x09, src, dst, 0, None)),
+                    ebpf::AND64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x21, 4, dst, insn.imm),
+                    ebpf::AND64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x21, src, dst, 0, None)),
+                    ebpf::LSH64_IMM  => self.emit_shift(OperandSize::S64, 4, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::LSH64_REG  => self.emit_shift(OperandSize::S64, 4, src, dst, None),
+                    ebpf::RSH64_IMM  => self.emit_shift(OperandSize::S64, 5, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::RSH64_REG  => self.emit_shift(OperandSize::S64, 5, src, dst, None),
+                    ebpf::NEG64     => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xf7, 3, dst, 0, None)),
+                    ebpf::XOR64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x31, 6, dst, insn.imm),
+                    ebpf::XOR64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x31, src, dst, 0, None)),
+                    ebpf::MOV64_IMM  => {
+                        if self.should_sanitize_constant(insn.imm) {
+                            self.emit_sanitized_load_immediate(OperandSize::S64, dst, insn.imm);
+                        } else {
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, insn.imm));
                         }
                     }
-                },
+                    ebpf::MOV64_REG  => self.emit_ins(X86Instruction::mov(OperandSize::S64, src, dst)),
+                    ebpf::ARSH64_IMM => self.emit_shift(OperandSize::S64, 7, REGISTER_SCRATCH, dst, Some(insn.imm)),
+                    ebpf::ARSH64_REG => self.emit_shift(OperandSize::S64, 7, src, dst, None),
+                    ebpf::HOR64_IMM  => {
+                        self.emit_sanitized_alu(OperandSize::S64, 0x09, 1, dst, (insn.imm as u64).wrapping_shl(32) as i64);
+                    }
 
-                // BPF_ALU64 class
-                ebpf::ADD64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x01, 0, dst, insn.imm),
-                ebpf::ADD64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, src, dst, 0, None)),
-                ebpf::SUB64_IMM  => {
-                    if self.executable.get_sbpf_version().swap_sub_reg_imm_operands() {
-                        self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xf7, 3, dst, 0, None));
-                        if insn.imm != 0 {
-                            self.emit_sanitized_alu(OperandSize::S64, 0x01, 0, dst, insn.imm);
-                        }
-                    } else {
-                        self.emit_sanitized_alu(OperandSize::S64, 0x29, 5, dst, insn.imm);
+                    // BPF_PQR class
+                    ebpf::LMUL32_IMM | ebpf::LMUL64_IMM | ebpf::UHMUL64_IMM | ebpf::SHMUL64_IMM |
+                    ebpf::UDIV32_IMM | ebpf::UDIV64_IMM | ebpf::UREM32_IMM | ebpf::UREM64_IMM |
+                    ebpf::SDIV32_IMM | ebpf::SDIV64_IMM | ebpf::SREM32_IMM | ebpf::SREM64_IMM => {
+                        self.emit_product_quotient_remainder(
+                            if insn.opc & (1 << 4) != 0 { OperandSize::S64 } else { OperandSize::S32 },
+                            insn.opc & (1 << 5) != 0,
+                            insn.opc & (1 << 6) != 0,
+                            insn.opc & (1 << 7) != 0,
+                            dst, dst, Some(insn.imm),
+                        )
                     }
-                }
-                ebpf::SUB64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x29, src, dst, 0, None)),
-                ebpf::MUL64_IMM | ebpf::DIV64_IMM | ebpf::MOD64_IMM if !self.executable.get_sbpf_version().enable_pqr() =>
-                    self.emit_product_quotient_remainder(OperandSize::S64, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, dst, dst, Some(insn.imm)),
-                ebpf::MUL64_REG | ebpf::DIV64_REG | ebpf::MOD64_REG if !self.executable.get_sbpf_version().enable_pqr() =>
-                    self.emit_product_quotient_remainder(OperandSize::S64, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MOD, (insn.opc & ebpf::BPF_ALU_OP_MASK) != ebpf::BPF_MUL, (insn.opc & ebpf::BPF_ALU_OP_MASK) == ebpf::BPF_MUL, src, dst, None),
-                ebpf::OR64_IMM   => self.emit_sanitized_alu(OperandSize::S64, 0x09, 1, dst, insn.imm),
-                ebpf::OR64_REG   => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x09, src, dst, 0, None)),
-                ebpf::AND64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x21, 4, dst, insn.imm),
-                ebpf::AND64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x21, src, dst, 0, None)),
-                ebpf::LSH64_IMM  => self.emit_shift(OperandSize::S64, 4, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::LSH64_REG  => self.emit_shift(OperandSize::S64, 4, src, dst, None),
-                ebpf::RSH64_IMM  => self.emit_shift(OperandSize::S64, 5, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::RSH64_REG  => self.emit_shift(OperandSize::S64, 5, src, dst, None),
-                ebpf::NEG64     if self.executable.get_sbpf_version().enable_neg() => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xf7, 3, dst, 0, None)),
-                ebpf::XOR64_IMM  => self.emit_sanitized_alu(OperandSize::S64, 0x31, 6, dst, insn.imm),
-                ebpf::XOR64_REG  => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x31, src, dst, 0, None)),
-                ebpf::MOV64_IMM  => {
-                    if self.should_sanitize_constant(insn.imm) {
-                        self.emit_sanitized_load_immediate(OperandSize::S64, dst, insn.imm);
-                    } else {
-                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, dst, insn.imm));
+                    ebpf::LMUL32_REG | ebpf::LMUL64_REG | ebpf::UHMUL64_REG | ebpf::SHMUL64_REG |
+                    ebpf::UDIV32_REG | ebpf::UDIV64_REG | ebpf::UREM32_REG | ebpf::UREM64_REG |
+                    ebpf::SDIV32_REG | ebpf::SDIV64_REG | ebpf::SREM32_REG | ebpf::SREM64_REG => {
+                        self.emit_product_quotient_remainder(
+                            if insn.opc & (1 << 4) != 0 { OperandSize::S64 } else { OperandSize::S32 },
+                            insn.opc & (1 << 5) != 0,
+                            insn.opc & (1 << 6) != 0,
+                            insn.opc & (1 << 7) != 0,
+                            src, dst, None,
+                        )
                     }
-                }
-                ebpf::MOV64_REG  => self.emit_ins(X86Instruction::mov(OperandSize::S64, src, dst)),
-                ebpf::ARSH64_IMM => self.emit_shift(OperandSize::S64, 7, REGISTER_SCRATCH, dst, Some(insn.imm)),
-                ebpf::ARSH64_REG => self.emit_shift(OperandSize::S64, 7, src, dst, None),
-                ebpf::HOR64_IMM if !self.executable.get_sbpf_version().enable_lddw() => {
-                    self.emit_sanitized_alu(OperandSize::S64, 0x09, 1, dst, (insn.imm as u64).wrapping_shl(32) as i64);
-                }
 
-                // BPF_PQR class
-                ebpf::LMUL32_IMM | ebpf::LMUL64_IMM | ebpf::UHMUL64_IMM | ebpf::SHMUL64_IMM |
-                ebpf::UDIV32_IMM | ebpf::UDIV64_IMM | ebpf::UREM32_IMM | ebpf::UREM64_IMM |
-                ebpf::SDIV32_IMM | ebpf::SDIV64_IMM | ebpf::SREM32_IMM | ebpf::SREM64_IMM
-                if self.executable.get_sbpf_version().enable_pqr() => {
-                    self.emit_product_quotient_remainder(
-                        if insn.opc & (1 << 4) != 0 { OperandSize::S64 } else { OperandSize::S32 },
-                        insn.opc & (1 << 5) != 0,
-                        insn.opc & (1 << 6) != 0,
-                        insn.opc & (1 << 7) != 0,
-                        dst, dst, Some(insn.imm),
-                    )
-                }
-                ebpf::LMUL32_REG | ebpf::LMUL64_REG | ebpf::UHMUL64_REG | ebpf::SHMUL64_REG |
-                ebpf::UDIV32_REG | ebpf::UDIV64_REG | ebpf::UREM32_REG | ebpf::UREM64_REG |
-                ebpf::SDIV32_REG | ebpf::SDIV64_REG | ebpf::SREM32_REG | ebpf::SREM64_REG
-                if self.executable.get_sbpf_version().enable_pqr() => {
-                    self.emit_product_quotient_remainder(
-                        if insn.opc & (1 << 4) != 0 { OperandSize::S64 } else { OperandSize::S32 },
-                        insn.opc & (1 << 5) != 0,
-                        insn.opc & (1 << 6) != 0,
-                        insn.opc & (1 << 7) != 0,
-                        src, dst, None,
-                    )
-                }
+                    // BPF_JMP class
+                    ebpf::JA         => {
+                        self.emit_validate_and_profile_instruction_count(false, Some(target_pc));
+                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, target_pc as i64));
+                        let jump_offset = self.relative_to_target_pc(target_pc, 5);
+                        self.emit_ins(X86Instruction::jump_immediate(jump_offset));
+                    },
+                    ebpf::JEQ_IMM    => self.emit_conditional_branch_imm(0x84, false, insn.imm, dst, target_pc),
+                    ebpf::JEQ_REG    => self.emit_conditional_branch_reg(0x84, false, src, dst, target_pc),
+                    ebpf::JGT_IMM    => self.emit_conditional_branch_imm(0x87, false, insn.imm, dst, target_pc),
+                    ebpf::JGT_REG    => self.emit_conditional_branch_reg(0x87, false, src, dst, target_pc),
+                    ebpf::JGE_IMM    => self.emit_conditional_branch_imm(0x83, false, insn.imm, dst, target_pc),
+                    ebpf::JGE_REG    => self.emit_conditional_branch_reg(0x83, false, src, dst, target_pc),
+                    ebpf::JLT_IMM    => self.emit_conditional_branch_imm(0x82, false, insn.imm, dst, target_pc),
+                    ebpf::JLT_REG    => self.emit_conditional_branch_reg(0x82, false, src, dst, target_pc),
+                    ebpf::JLE_IMM    => self.emit_conditional_branch_imm(0x86, false, insn.imm, dst, target_pc),
+                    ebpf::JLE_REG    => self.emit_conditional_branch_reg(0x86, false, src, dst, target_pc),
+                    ebpf::JSET_IMM   => self.emit_conditional_branch_imm(0x85, true, insn.imm, dst, target_pc),
+                    ebpf::JSET_REG   => self.emit_conditional_branch_reg(0x85, true, src, dst, target_pc),
+                    ebpf::JNE_IMM    => self.emit_conditional_branch_imm(0x85, false, insn.imm, dst, target_pc),
+                    ebpf::JNE_REG    => self.emit_conditional_branch_reg(0x85, false, src, dst, target_pc),
+                    ebpf::JSGT_IMM   => self.emit_conditional_branch_imm(0x8f, false, insn.imm, dst, target_pc),
+                    ebpf::JSGT_REG   => self.emit_conditional_branch_reg(0x8f, false, src, dst, target_pc),
+                    ebpf::JSGE_IMM   => self.emit_conditional_branch_imm(0x8d, false, insn.imm, dst, target_pc),
+                    ebpf::JSGE_REG   => self.emit_conditional_branch_reg(0x8d, false, src, dst, target_pc),
+                    ebpf::JSLT_IMM   => self.emit_conditional_branch_imm(0x8c, false, insn.imm, dst, target_pc),
+                    ebpf::JSLT_REG   => self.emit_conditional_branch_reg(0x8c, false, src, dst, target_pc),
+                    ebpf::JSLE_IMM   => self.emit_conditional_branch_imm(0x8e, false, insn.imm, dst, target_pc),
+                    ebpf::JSLE_REG   => self.emit_conditional_branch_reg(0x8e, false, src, dst, target_pc),
+                    ebpf::CALL_IMM   => {
+                        // For JIT, external functions MUST be registered at compile time,
+                        // but they are called through the syscall_table so they can be rebound.
+
+                        let mut resolved = false;
+                        let (external, internal) = if self.executable.get_sbpf_version().static_syscalls() {
+                            (insn.src == 0, insn.src != 0)
+                        } else {
+                            (true, true)
+                        };
+
+                        if external {
+                            if let Ok(slot) = self.result.syscall_keys.binary_search(&(insn.imm as u32)) {
+                                self.emit_validate_and_profile_instruction_count(true, Some(0));
+                                if self.charges_syscall_costs() || self.config.enable_syscall_tracing {
+                                    self.emit_ins(X86Instruction::store_immediate(OperandSize::S64, REGISTER_PTR_TO_VM, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::InvokedSyscall)), insn.imm)); // *InvokedSyscall = insn.imm;
+                                }
+                                self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, (self.syscall_table_address + slot * mem::size_of::<usize>()) as i64));
+                                self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0)));
+                                self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_EXTERNAL_FUNCTION_CALL, 5)));
+                                self.emit_undo_profile_instruction_count(0);
+                                resolved = true;
+                            }
+                        }
 
-                // BPF_JMP class
-                ebpf::JA         => {
-                    self.emit_validate_and_profile_instruction_count(false, Some(target_pc));
-                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, target_pc as i64));
-                    let jump_offset = self.relative_to_target_pc(target_pc, 5);
-                    self.emit_ins(X86Instruction::jump_immediate(jump_offset));
-                },
-                ebpf::JEQ_IMM    => self.emit_conditional_branch_imm(0x84, false, insn.imm, dst, target_pc),
-                ebpf::JEQ_REG    => self.emit_conditional_branch_reg(0x84, false, src, dst, target_pc),
-                ebpf::JGT_IMM    => self.emit_conditional_branch_imm(0x87, false, insn.imm, dst, target_pc),
-                ebpf::JGT_REG    => self.emit_conditional_branch_reg(0x87, false, src, dst, target_pc),
-                ebpf::JGE_IMM    => self.emit_conditional_branch_imm(0x83, false, insn.imm, dst, target_pc),
-                ebpf::JGE_REG    => self.emit_conditional_branch_reg(0x83, false, src, dst, target_pc),
-                ebpf::JLT_IMM    => self.emit_conditional_branch_imm(0x82, false, insn.imm, dst, target_pc),
-                ebpf::JLT_REG    => self.emit_conditional_branch_reg(0x82, false, src, dst, target_pc),
-                ebpf::JLE_IMM    => self.emit_conditional_branch_imm(0x86, false, insn.imm, dst, target_pc),
-                ebpf::JLE_REG    => self.emit_conditional_branch_reg(0x86, false, src, dst, target_pc),
-                ebpf::JSET_IMM   => self.emit_conditional_branch_imm(0x85, true, insn.imm, dst, target_pc),
-                ebpf::JSET_REG   => self.emit_conditional_branch_reg(0x85, true, src, dst, target_pc),
-                ebpf::JNE_IMM    => self.emit_conditional_branch_imm(0x85, false, insn.imm, dst, target_pc),
-                ebpf::JNE_REG    => self.emit_conditional_branch_reg(0x85, false, src, dst, target_pc),
-                ebpf::JSGT_IMM   => self.emit_conditional_branch_imm(0x8f, false, insn.imm, dst, target_pc),
-                ebpf::JSGT_REG   => self.emit_conditional_branch_reg(0x8f, false, src, dst, target_pc),
-                ebpf::JSGE_IMM   => self.emit_conditional_branch_imm(0x8d, false, insn.imm, dst, target_pc),
-                ebpf::JSGE_REG   => self.emit_conditional_branch_reg(0x8d, false, src, dst, target_pc),
-                ebpf::JSLT_IMM   => self.emit_conditional_branch_imm(0x8c, false, insn.imm, dst, target_pc),
-                ebpf::JSLT_REG   => self.emit_conditional_branch_reg(0x8c, false, src, dst, target_pc),
-                ebpf::JSLE_IMM   => self.emit_conditional_branch_imm(0x8e, false, insn.imm, dst, target_pc),
-                ebpf::JSLE_REG   => self.emit_conditional_branch_reg(0x8e, false, src, dst, target_pc),
-                ebpf::CALL_IMM   => {
-                    // For JIT, external functions MUST be registered at compile time.
-
-                    let mut resolved = false;
-                    let (external, internal) = if self.executable.get_sbpf_version().static_syscalls() {
-                        (insn.src == 0, insn.src != 0)
-                    } else {
-                        (true, true)
-                    };
-
-                    if external {
-                        if let Some((_function_name, function)) = self.executable.get_loader().get_function_registry().lookup_by_key(insn.imm as u32) {
-                            self.emit_validate_and_profile_instruction_count(true, Some(0));
-                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, function as usize as i64));
-                            self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_EXTERNAL_FUNCTION_CALL, 5)));
-                            self.emit_undo_profile_instruction_count(0);
-                            resolved = true;
+                        if internal {
+                            if let Some((_function_name, target_pc)) = self.executable.get_function_registry().lookup_by_key(insn.imm as u32) {
+                                self.emit_internal_call(Value::Constant64(target_pc as i64, true));
+                                resolved = true;
+                            }
                         }
-                    }
 
-                    if internal {
-                        if let Some((_function_name, target_pc)) = self.executable.get_function_registry().lookup_by_key(insn.imm as u32) {
-                            self.emit_internal_call(Value::Constant64(target_pc as i64, true));
-                            resolved = true;
+                        if !resolved {
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
+                            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_UNSUPPORTED_INSTRUCTION, 5)));
                         }
-                    }
+                    },
+                    ebpf::CALL_REG  => {
+                        let target_pc = if self.executable.get_sbpf_version().callx_uses_src_reg() {
+                            src
+                        } else {
+                            REGISTER_MAP[insn.imm as usize]
+                        };
+                        self.emit_internal_call(Value::Register(target_pc));
+                    },
+                    ebpf::EXIT      => {
+                        if self.config.enable_jit_function_timing {
+                            let function_start = self.executable.get_function_registry().iter()
+                                .map(|(_key, (_name, target_pc))| target_pc)
+                                .filter(|target_pc| *target_pc <= self.pc)
+                                .max()
+                                .unwrap_or(0);
+                            self.emit_rust_call(Value::Constant64(EbpfVm::<C>::leave_function as *const u8 as i64, false), &[
+                                Argument { index: 1, value: Value::Constant64(function_start as i64, false) },
+                                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+                            ], None);
+                        }
+                        let call_depth_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::CallDepth));
+                        self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
 
-                    if !resolved {
-                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
-                        self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALL_UNSUPPORTED_INSTRUCTION, 5)));
-                    }
-                },
-                ebpf::CALL_REG  => {
-                    let target_pc = if self.executable.get_sbpf_version().callx_uses_src_reg() {
-                        src
-                    } else {
-                        REGISTER_MAP[insn.imm as usize]
-                    };
-                    self.emit_internal_call(Value::Register(target_pc));
-                },
-                ebpf::EXIT      => {
-                    let call_depth_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::CallDepth));
-                    self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
-
-                    // If CallDepth == 0, we've reached the exit instruction of the entry point
-                    self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S32, REGISTER_MAP[FRAME_PTR_REG], 0, None));
-                    if self.config.enable_instruction_meter {
-                        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
-                    }
-                    // we're done
-                    self.emit_ins(X86Instruction::conditional_jump_immediate(0x84, self.relative_to_anchor(ANCHOR_EXIT, 6)));
+                        // If CallDepth == 0, we've reached the exit instruction of the entry point
+                        self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S32, REGISTER_MAP[FRAME_PTR_REG], 0, None));
+                        if self.config.enable_instruction_meter {
+                            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, self.pc as i64));
+                        }
+                        // we're done
+                        self.emit_ins(X86Instruction::conditional_jump_immediate(0x84, self.relative_to_anchor(ANCHOR_EXIT, 6)));
 
-                    // else decrement and update CallDepth
-                    self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, REGISTER_MAP[FRAME_PTR_REG], 1, None));
-                    self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], REGISTER_PTR_TO_VM, call_depth_access));
+                        // else decrement and update CallDepth
+                        self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, REGISTER_MAP[FRAME_PTR_REG], 1, None));
+                        self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], REGISTER_PTR_TO_VM, call_depth_access));
 
-                    if !self.executable.get_sbpf_version().dynamic_stack_frames() {
-                        let stack_pointer_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::StackPointer));
-                        let stack_frame_size = self.config.stack_frame_size as i64 * if self.config.enable_stack_frame_gaps { 2 } else { 1 };
-                        self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, REGISTER_PTR_TO_VM, stack_frame_size, Some(stack_pointer_access))); // env.stack_pointer -= stack_frame_size;
-                    }
+                        if !self.executable.get_sbpf_version().dynamic_stack_frames() {
+                            let stack_pointer_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::StackPointer));
+                            let stack_frame_size = self.config.stack_frame_size as i64 + self.config.stack_frame_gap_size(self.executable.get_sbpf_version()) as i64;
+                            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 5, REGISTER_PTR_TO_VM, stack_frame_size, Some(stack_pointer_access))); // env.stack_pointer -= stack_frame_size;
+                        }
 
-                    // and return
-                    self.emit_validate_and_profile_instruction_count(false, Some(0));
-                    self.emit_ins(X86Instruction::return_near());
-                },
+                        // and return
+                        self.emit_validate_and_profile_instruction_count(false, Some(0));
+                        self.emit_ins(X86Instruction::return_near());
+                    },
+
+                    _               => return Err(EbpfError::UnsupportedInstruction),
+                }
+
+                self.pc += 1;
+            }
+
+            // Bumper in case there was no final exit
+            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.text_section_end {
+                return Err(EbpfError::ExhaustedTextSegment(self.pc));
+            }        
+            self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
+            self.emit_set_exception_kind(EbpfError::ExecutionOverrun);
+            self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
+        }
+        Ok(())
+    }
+
+    /// Selects the function containing `target_pc` and all functions it jumps or falls through into
+    ///
+    /// Functions in `compiled_functions` are excluded. Returns the first pcs of the selected
+    /// functions and their pcs merged into spans of adjacent functions.
+    fn select_function_batch(&self, target_pc: usize, compiled_functions: &BTreeSet<usize>) -> (BTreeSet<usize>, Vec<Range<usize>>) {
+        let pc_count = self.result.pc_section.len();
+        let mut function_starts = self.executable.get_function_registry().iter()
+            .map(|(_key, (_name, pc))| pc)
+            .chain(std::iter::once(0))
+            .filter(|pc| *pc < pc_count)
+            .collect::<Vec<_>>();
+        function_starts.sort_unstable();
+        function_starts.dedup();
+        let function_range = |pc: usize| {
+            let index = function_starts.partition_point(|start| *start <= pc) - 1;
+            function_starts[index]..function_starts.get(index + 1).copied().unwrap_or(pc_count)
+        };
 
-                _               => return Err(EbpfError::UnsupportedInstruction),
+        let mut functions = BTreeSet::new();
+        let mut pending = if target_pc < pc_count { vec![function_range(target_pc)] } else { Vec::new() };
+        while let Some(function) = pending.pop() {
+            if compiled_functions.contains(&function.start) || !functions.insert(function.start) {
+                continue;
+            }
+            let mut pc = function.start;
+            let mut falls_through = true;
+            while pc < function.end && (pc + 1) * ebpf::INSN_SIZE <= self.program.len() {
+                let insn = ebpf::get_insn_unchecked(self.program, pc);
+                falls_through = !matches!(insn.opc, ebpf::JA | ebpf::EXIT);
+                if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP && !matches!(insn.opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT) {
+                    let target_pc = (pc as isize + insn.off as isize + 1) as usize;
+                    if target_pc < pc_count {
+                        pending.push(function_range(target_pc));
+                    }
+                }
+                pc += if insn.opc == ebpf::LD_DW_IMM && self.executable.get_sbpf_version().enable_lddw() { 2 } else { 1 };
+            }
+            if falls_through && function.end < pc_count {
+                pending.push(function_range(function.end));
             }
-
-            self.pc += 1;
         }
 
-        // Bumper in case there was no final exit
-        if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
-            return Err(EbpfError::ExhaustedTextSegment(self.pc));
-        }        
-        self.emit_validate_and_profile_instruction_count(true, Some(self.pc + 2));
-        self.emit_set_exception_kind(EbpfError::ExecutionOverrun);
-        self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
-
-        self.resolve_jumps();
-        self.result.seal(self.offset_in_text_section)?;
-        Ok(self.result)
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for function in functions.iter().map(|start| function_range(*start)) {
+            match spans.last_mut() {
+                Some(span) if span.end == function.start => span.end = function.end,
+                _ => spans.push(function),
+            }
+        }
+        if spans.is_empty() {
+            // Still emit a bumper for empty programs
+            spans.push(pc_count..pc_count);
+        }
+        (functions, spans)
     }
 
     #[inline]
@@ -754,12 +1662,17 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         8 * (slot as i32 - self.runtime_environment_key)
     }
 
+    /// Pointer through which the text section is written
+    fn writable_text_section(&self) -> *mut u8 {
+        (self.text_section as *mut u8).wrapping_offset(self.result.write_offset)
+    }
+
     #[inline]
     pub(crate) fn emit<T>(&mut self, data: T) {
         unsafe {
-            let ptr = self.result.text_section.as_ptr().add(self.offset_in_text_section);
+            let ptr = self.writable_text_section().add(self.offset_in_text_section);
             #[allow(clippy::cast_ptr_alignment)]
-            ptr::write_unaligned(ptr as *mut T, data as T);
+            ptr::write_unaligned(ptr.cast::<T>(), data as T);
         }
         self.offset_in_text_section += mem::size_of::<T>();
     }
@@ -891,6 +1804,12 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         }
     }
 
+    /// Returns true if syscall invocations charge the costs set by BuiltinProgram::set_syscall_cost()
+    #[inline]
+    fn charges_syscall_costs(&self) -> bool {
+        self.config.enable_instruction_meter && self.executable.get_loader().has_syscall_costs()
+    }
+
     #[inline]
     fn emit_validate_and_profile_instruction_count(&mut self, exclusive: bool, target_pc: Option<usize>) {
         if self.config.enable_instruction_meter {
@@ -981,14 +1900,15 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             }
         }
     
+        let call_start = self.offset_in_text_section;
         match target {
             Value::Register(reg) => {
-                self.emit_ins(X86Instruction::call_reg(reg, None));
+                self.emit_indirect_branch(true, reg, None);
             },
             Value::Constant64(value, user_provided) => {
                 debug_assert!(!user_provided);
                 self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, RAX, value));
-                self.emit_ins(X86Instruction::call_reg(RAX, None));
+                self.emit_indirect_branch(true, RAX, None);
             },
             _ => {
                 #[cfg(debug_assertions)]
@@ -996,6 +1916,18 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             }
         }
     
+        if self.config.enable_jit_unwind_info {
+            // RDI might be clobbered during the call, so the unwinder reads the VM pointer from where it was saved
+            if let Some(index) = saved_registers.iter().position(|reg| *reg == REGISTER_PTR_TO_VM) {
+                let stack_arguments_size = (stack_arguments + stack_arguments % 2) as u32 * 8;
+                self.unwind_call_sites.push(CallSite {
+                    range: call_start..self.offset_in_text_section,
+                    unaligned_stack_pointer_offset: stack_arguments_size + 8,
+                    vm_pointer_offset: (saved_registers.len() - 1 - index) as u32 * 8,
+                });
+            }
+        }
+
         // Save returned value in result register
         if let Some(reg) = result_reg {
             self.emit_ins(X86Instruction::mov(OperandSize::S64, RAX, reg));
@@ -1011,6 +1943,37 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         }
     }
 
+    /// Emits an indirect call or jump, hardened according to Config::speculation_hardening
+    ///
+    /// Clobbers REGISTER_OTHER_SCRATCH when retpolines are used.
+    #[inline]
+    fn emit_indirect_branch(&mut self, call: bool, target: u8, indirect: Option<X86IndirectAccess>) {
+        match self.config.speculation_hardening {
+            SpeculationHardening::None => {},
+            SpeculationHardening::LoadFence => {
+                self.emit_ins(X86Instruction::fence(FenceType::Load));
+            },
+            SpeculationHardening::Retpoline => {
+                if let Some(indirect) = indirect {
+                    self.emit_ins(X86Instruction::load(OperandSize::S64, target, REGISTER_OTHER_SCRATCH, indirect));
+                } else if target != REGISTER_OTHER_SCRATCH {
+                    self.emit_ins(X86Instruction::mov(OperandSize::S64, target, REGISTER_OTHER_SCRATCH));
+                }
+                if call {
+                    self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_RETPOLINE, 5)));
+                } else {
+                    self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_RETPOLINE, 5)));
+                }
+                return;
+            },
+        }
+        if call {
+            self.emit_ins(X86Instruction::call_reg(target, indirect));
+        } else {
+            self.emit_ins(X86Instruction::jump_reg(target, indirect));
+        }
+    }
+
     #[inline]
     fn emit_internal_call(&mut self, dst: Value) {
         // Store PC in case the bounds check fails
@@ -1031,7 +1994,7 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
                 self.emit_validate_and_profile_instruction_count(false, None);
                 self.emit_ins(X86Instruction::mov(OperandSize::S64, REGISTER_MAP[0], REGISTER_OTHER_SCRATCH));
                 self.emit_ins(X86Instruction::pop(REGISTER_MAP[0])); // Restore RAX
-                self.emit_ins(X86Instruction::call_reg(REGISTER_OTHER_SCRATCH, None)); // callq *REGISTER_OTHER_SCRATCH
+                self.emit_indirect_branch(true, REGISTER_OTHER_SCRATCH, None); // callq *REGISTER_OTHER_SCRATCH
             },
             Value::Constant64(target_pc, user_provided) => {
                 debug_assert!(user_provided);
@@ -1041,8 +2004,14 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
                 } else {
                     self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_SCRATCH, target_pc));
                 }
-                let jump_offset = self.relative_to_target_pc(target_pc as usize, 5);
-                self.emit_ins(X86Instruction::call_immediate(jump_offset));
+                if self.config.enable_lazy_jit_compilation {
+                    // The target might not be compiled yet, so call it through the pc_section
+                    self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_OTHER_SCRATCH, (self.pc_section_address + target_pc as usize * mem::size_of::<usize>()) as i64));
+                    self.emit_indirect_branch(true, REGISTER_OTHER_SCRATCH, Some(X86IndirectAccess::Offset(0))); // callq *[REGISTER_OTHER_SCRATCH]
+                } else {
+                    let jump_offset = self.relative_to_target_pc(target_pc as usize, 5);
+                    self.emit_ins(X86Instruction::call_immediate(jump_offset));
+                }
             },
             _ => {
                 #[cfg(debug_assertions)]
@@ -1054,9 +2023,30 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
 
         // Restore the previous frame pointer
         self.emit_ins(X86Instruction::pop(REGISTER_MAP[FRAME_PTR_REG]));
+        // Restore the scratch registers, but only drop the saved values of runs of dead ones
+        let mut dead_registers = Vec::new();
         for reg in REGISTER_MAP.iter().skip(FIRST_SCRATCH_REG).take(SCRATCH_REGS).rev() {
+            if self.is_register_dead_after_instruction(*reg) {
+                dead_registers.push(*reg);
+                continue;
+            }
+            self.emit_drop_saved_registers(&dead_registers);
+            dead_registers.clear();
             self.emit_ins(X86Instruction::pop(*reg));
         }
+        self.emit_drop_saved_registers(&dead_registers);
+    }
+
+    /// Pops the saved values of dead registers from the stack
+    ///
+    /// A single one is restored anyway, as that is shorter than adjusting RSP.
+    #[inline]
+    fn emit_drop_saved_registers(&mut self, dead_registers: &[u8]) {
+        match dead_registers {
+            [] => {},
+            [reg] => self.emit_ins(X86Instruction::pop(*reg)),
+            _ => self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x83, 0, RSP, 8 * dead_registers.len() as i64, None)), // RSP += 8 * dead_registers.len();
+        }
     }
 
     #[inline]
@@ -1102,27 +2092,78 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         if self.config.enable_address_translation {
             let access_type = if value.is_none() { AccessType::Load } else { AccessType::Store };
             let anchor = ANCHOR_TRANSLATE_MEMORY_ADDRESS + len.trailing_zeros() as usize + 4 * (access_type as usize);
+            let mut slow_path_jumps = [None; 7];
+            let mut fast_path_exit = None;
+            if self.config.aligned_memory_mapping && !self.config.enable_region_statistics {
+                // Inline fast path: Look up the region by the upper half of the address and access the host memory directly
+                let region = if access_type == AccessType::Load { REGISTER_OTHER_SCRATCH } else { REGISTER_MAP[0] };
+                if access_type == AccessType::Store {
+                    self.emit_ins(X86Instruction::push(region, None));
+                }
+                debug_assert!(mem::size_of::<MemoryRegion>().is_power_of_two());
+                self.emit_ins(X86Instruction::mov(OperandSize::S64, REGISTER_SCRATCH, region));
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xc1, 5, region, ebpf::VIRTUAL_ADDRESS_BITS as i64, None)); // region >>= ebpf::VIRTUAL_ADDRESS_BITS;
+                self.emit_ins(X86Instruction::cmp(OperandSize::S64, region, REGISTER_PTR_TO_VM, Some(X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::MemoryRegionCount)))));
+                slow_path_jumps[0] = Some(self.emit_local_jump(Some(0x86))); // if *MemoryRegionCount <= region { goto slow_path; }
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xc1, 4, region, mem::size_of::<MemoryRegion>().trailing_zeros() as i64, None)); // region *= mem::size_of::<MemoryRegion>();
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x03, region, REGISTER_PTR_TO_VM, 0, Some(X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::MemoryRegions))))); // region += *MemoryRegions;
+                self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S8, region, 63, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_gap_shift) as i32))));
+                slow_path_jumps[1] = Some(self.emit_local_jump(Some(0x85))); // Gapped regions are translated in the slow path
+                self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, region, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_chunk_len) as i32))));
+                slow_path_jumps[2] = Some(self.emit_local_jump(Some(0x85))); // So are chunked regions
+                if access_type == AccessType::Store {
+                    // Readonly and CoW regions are handled in the slow path
+                    self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, region, 1, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, state) as i32)))); // MemoryState::Writable
+                    slow_path_jumps[3] = Some(self.emit_local_jump(Some(0x85)));
+                    self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, region, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, writable_ranges) as i32))));
+                    slow_path_jumps[4] = Some(self.emit_local_jump(Some(0x85))); // So are regions with writable ranges
+                }
+                self.emit_ins(X86Instruction::cmp(OperandSize::S64, REGISTER_SCRATCH, region, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_addr) as i32))));
+                slow_path_jumps[5] = Some(self.emit_local_jump(Some(0x87))); // if region.vm_addr > vm_addr { goto slow_path; }
+                self.emit_ins(X86Instruction::lea(OperandSize::S64, REGISTER_SCRATCH, REGISTER_SCRATCH, Some(X86IndirectAccess::Offset(len as i32))));
+                self.emit_ins(X86Instruction::cmp(OperandSize::S64, REGISTER_SCRATCH, region, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_addr_end) as i32))));
+                self.emit_ins(X86Instruction::lea(OperandSize::S64, REGISTER_SCRATCH, REGISTER_SCRATCH, Some(X86IndirectAccess::Offset(-(len as i32))))); // Restore vm_addr without touching the flags
+                slow_path_jumps[6] = Some(self.emit_local_jump(Some(0x82))); // if region.vm_addr_end < vm_addr + len { goto slow_path; }
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x2b, REGISTER_SCRATCH, region, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_addr) as i32)))); // vm_addr -= region.vm_addr;
+                self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x03, REGISTER_SCRATCH, region, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, host_addr) as i32)))); // host_addr = vm_addr + region.host_addr;
+                if access_type == AccessType::Store {
+                    self.emit_ins(X86Instruction::pop(region));
+                }
+                self.emit_host_memory_access(dst, len);
+                fast_path_exit = Some(self.emit_local_jump(None));
+                for jump in slow_path_jumps.iter().flatten() {
+                    self.patch_local_jump(*jump);
+                }
+                if access_type == AccessType::Store {
+                    self.emit_ins(X86Instruction::pop(region));
+                }
+            }
             self.emit_ins(X86Instruction::push_immediate(OperandSize::S64, self.pc as i32));
             self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(anchor, 5)));
             if let Some(dst) = dst {
                 self.emit_ins(X86Instruction::mov(OperandSize::S64, REGISTER_SCRATCH, dst));
             }
-        } else if let Some(dst) = dst {
-            match len {
-                1 => self.emit_ins(X86Instruction::load(OperandSize::S8, REGISTER_SCRATCH, dst, X86IndirectAccess::Offset(0))),
-                2 => self.emit_ins(X86Instruction::load(OperandSize::S16, REGISTER_SCRATCH, dst, X86IndirectAccess::Offset(0))),
-                4 => self.emit_ins(X86Instruction::load(OperandSize::S32, REGISTER_SCRATCH, dst, X86IndirectAccess::Offset(0))),
-                8 => self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_SCRATCH, dst, X86IndirectAccess::Offset(0))),
-                _ => unreachable!(),
+            if let Some(jump) = fast_path_exit {
+                self.patch_local_jump(jump);
             }
         } else {
-            match len {
-                1 => self.emit_ins(X86Instruction::store(OperandSize::S8, REGISTER_OTHER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0))),
-                2 => self.emit_ins(X86Instruction::store(OperandSize::S16, REGISTER_OTHER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0))),
-                4 => self.emit_ins(X86Instruction::store(OperandSize::S32, REGISTER_OTHER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0))),
-                8 => self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_OTHER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0))),
-                _ => unreachable!(),
-            }
+            self.emit_host_memory_access(dst, len);
+        }
+    }
+
+    #[inline]
+    fn emit_host_memory_access(&mut self, dst: Option<u8>, len: u64) {
+        let size = match len {
+            1 => OperandSize::S8,
+            2 => OperandSize::S16,
+            4 => OperandSize::S32,
+            8 => OperandSize::S64,
+            _ => unreachable!(),
+        };
+        if let Some(dst) = dst {
+            self.emit_ins(X86Instruction::load(size, REGISTER_SCRATCH, dst, X86IndirectAccess::Offset(0)));
+        } else {
+            self.emit_ins(X86Instruction::store(size, REGISTER_OTHER_SCRATCH, REGISTER_SCRATCH, X86IndirectAccess::Offset(0)));
         }
     }
 
@@ -1182,20 +2223,108 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
                 self.emit_ins(X86Instruction::pop(RCX));
             }
         } else if destination == RCX {
-            if source != REGISTER_SCRATCH {
+            let preserve_source = source != REGISTER_SCRATCH && !self.is_register_dead_after_instruction(source);
+            if preserve_source {
                 self.emit_ins(X86Instruction::push(source, None));
             }
             self.emit_ins(X86Instruction::xchg(OperandSize::S64, source, RCX, None));
             self.emit_ins(X86Instruction::alu(size, 0xd3, opcode_extension, source, 0, None));
             self.emit_ins(X86Instruction::mov(OperandSize::S64, source, RCX));
-            if source != REGISTER_SCRATCH {
+            if preserve_source {
                 self.emit_ins(X86Instruction::pop(source));
             }
         } else {
-            self.emit_ins(X86Instruction::push(RCX, None));
+            let preserve_rcx = !self.is_register_dead_after_instruction(RCX);
+            if preserve_rcx {
+                self.emit_ins(X86Instruction::push(RCX, None));
+            }
             self.emit_ins(X86Instruction::mov(OperandSize::S64, source, RCX));
             self.emit_ins(X86Instruction::alu(size, 0xd3, opcode_extension, destination, 0, None));
-            self.emit_ins(X86Instruction::pop(RCX));
+            if preserve_rcx {
+                self.emit_ins(X86Instruction::pop(RCX));
+            }
+        }
+    }
+
+    /// Sign extends the lower half of `dst`, unless the next instruction makes that redundant
+    fn emit_sign_extension(&mut self, dst: u8) {
+        if !self.is_sign_extension_redundant(dst) {
+            self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
+        }
+    }
+
+    /// Tells whether the next instruction ignores the upper half of `dst` and overwrites all of it
+    fn is_sign_extension_redundant(&self, dst: u8) -> bool {
+        if !self.config.optimize_sign_extensions || self.config.enable_instruction_tracing || (self.pc + 2) * ebpf::INSN_SIZE > self.program.len() {
+            return false;
+        }
+        let reg = match REGISTER_MAP.iter().position(|mapped| *mapped == dst) {
+            Some(reg) => reg as u8,
+            None => return false,
+        };
+        let insn = ebpf::get_insn_unchecked(self.program, self.pc + 1);
+        // The registers are captured if the next instruction aborts, so it has to complete
+        if insn.dst != reg || self.may_abort_at(self.pc + 1, &insn) {
+            return false;
+        }
+        match insn.opc {
+            // Only read the lower half and sign extend the result again
+            ebpf::ADD32_IMM | ebpf::ADD32_REG | ebpf::SUB32_IMM | ebpf::SUB32_REG => true,
+            ebpf::MOV32_IMM | ebpf::MOV32_REG | ebpf::MOV64_IMM => true,
+            ebpf::MOV64_REG => insn.src != reg,
+            _ => false,
+        }
+    }
+
+    /// Tells whether the guest register mapped to `host_register` is overwritten before it is read,
+    /// on the straight-line path following the current instruction.
+    ///
+    /// Such a register does not need to be preserved when it is used as a temporary.
+    /// This does not hold if every instruction is traced, as that observes all registers.
+    /// Neither does it if an instruction on the way can abort, as the registers are captured then.
+    fn is_register_dead_after_instruction(&self, host_register: u8) -> bool {
+        let reg = match REGISTER_MAP.iter().position(|mapped| *mapped == host_register) {
+            Some(reg) if !self.config.enable_instruction_tracing => reg as u8,
+            _ => return false,
+        };
+        for pc in self.pc + 1..self.pc + 1 + MAX_LIVENESS_LOOKAHEAD {
+            if (pc + 1) * ebpf::INSN_SIZE > self.program.len() {
+                return false;
+            }
+            let insn = ebpf::get_insn_unchecked(self.program, pc);
+            if self.may_abort_at(pc, &insn) {
+                return false;
+            }
+            match insn.opc & ebpf::BPF_CLS_MASK {
+                ebpf::BPF_ALU | ebpf::BPF_ALU64 | ebpf::BPF_PQR => {
+                    if insn.opc & ebpf::BPF_X != 0 && insn.src == reg {
+                        return false;
+                    }
+                    if insn.dst == reg {
+                        // Only a move does not read its destination
+                        return insn.opc & ebpf::BPF_CLS_MASK != ebpf::BPF_PQR && insn.opc & 0xf0 == ebpf::BPF_MOV;
+                    }
+                }
+                // Jumps, calls and exits end the straight-line path
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Tells whether the instruction at `pc` can end the execution with an error before it completes
+    ///
+    /// Besides faulting instructions, this covers the instruction meter checkpoints in front of them.
+    fn may_abort_at(&self, pc: usize, insn: &ebpf::Insn) -> bool {
+        if self.config.exact_instruction_meter || self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= pc {
+            return true;
+        }
+        match insn.opc & ebpf::BPF_CLS_MASK {
+            ebpf::BPF_ALU | ebpf::BPF_ALU64 => matches!(insn.opc & ebpf::BPF_ALU_OP_MASK, ebpf::BPF_DIV | ebpf::BPF_MOD),
+            // Multiplications can not fault, but divisions can
+            ebpf::BPF_PQR => insn.opc & ebpf::BPF_UDIV != 0,
+            // LDDW validates the instruction meter and memory accesses can fault
+            _ => true,
         }
     }
 
@@ -1247,11 +2376,15 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         } else {
             self.emit_ins(X86Instruction::mov(OperandSize::S64, src, REGISTER_SCRATCH));
         }
+        let preserve_rax = dst != RAX && !self.is_register_dead_after_instruction(RAX);
+        let preserve_rdx = dst != RDX && !self.is_register_dead_after_instruction(RDX);
         if dst != RAX {
-            self.emit_ins(X86Instruction::push(RAX, None));
+            if preserve_rax {
+                self.emit_ins(X86Instruction::push(RAX, None));
+            }
             self.emit_ins(X86Instruction::mov(OperandSize::S64, dst, RAX));
         }
-        if dst != RDX {
+        if preserve_rdx {
             self.emit_ins(X86Instruction::push(RDX, None));
         }
         if division {
@@ -1268,13 +2401,17 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             if alt_dst {
                 self.emit_ins(X86Instruction::mov(OperandSize::S64, RDX, dst));
             }
-            self.emit_ins(X86Instruction::pop(RDX));
+            if preserve_rdx {
+                self.emit_ins(X86Instruction::pop(RDX));
+            }
         }
         if dst != RAX {
             if !alt_dst {
                 self.emit_ins(X86Instruction::mov(OperandSize::S64, RAX, dst));
             }
-            self.emit_ins(X86Instruction::pop(RAX));
+            if preserve_rax {
+                self.emit_ins(X86Instruction::pop(RAX));
+            }
         }
         if let OperandSize::S32 = size {
             if signed {
@@ -1291,6 +2428,18 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         self.emit_ins(X86Instruction::store_immediate(OperandSize::S64, REGISTER_OTHER_SCRATCH, X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32), err_kind as i64)); // err.kind = err_kind;
     }
 
+    /// Calls EbpfVm::trace_syscall_entry() or EbpfVm::trace_syscall_exit() with the arguments of the syscall
+    fn emit_syscall_trace(&mut self, target: i64) {
+        self.emit_rust_call(Value::Constant64(target, false), &[
+            Argument { index: 5, value: Value::Register(ARGUMENT_REGISTERS[5]) },
+            Argument { index: 4, value: Value::Register(ARGUMENT_REGISTERS[4]) },
+            Argument { index: 3, value: Value::Register(ARGUMENT_REGISTERS[3]) },
+            Argument { index: 2, value: Value::Register(ARGUMENT_REGISTERS[2]) },
+            Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[1]) },
+            Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+        ], None);
+    }
+
     fn emit_result_is_err(&mut self, destination: u8) {
         let ok = ProgramResult::Ok(0);
         let ok_discriminant = ok.discriminant();
@@ -1299,6 +2448,20 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
     }
 
     fn emit_subroutines(&mut self) {
+        // Routine for indirect calls and jumps to REGISTER_OTHER_SCRATCH, see emit_indirect_branch()
+        if self.config.speculation_hardening == SpeculationHardening::Retpoline {
+            // Replace the return address with the actual target
+            self.set_anchor(ANCHOR_RETPOLINE_SET_TARGET);
+            self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_OTHER_SCRATCH, RSP, X86IndirectAccess::OffsetIndexShift(0, RSP, 0)));
+            self.emit_ins(X86Instruction::return_near());
+            self.set_anchor(ANCHOR_RETPOLINE);
+            self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_RETPOLINE_SET_TARGET, 5)));
+            // Speculation trap, the return above is predicted to land here
+            let speculation_trap = self.offset_in_text_section;
+            self.emit_ins(X86Instruction::fence(FenceType::Load));
+            self.emit_ins(X86Instruction::jump_immediate(speculation_trap as i32 - (self.offset_in_text_section + 5) as i32));
+        }
+
         // Routine for instruction tracing
         if self.config.enable_instruction_tracing {
             self.set_anchor(ANCHOR_TRACE);
@@ -1309,9 +2472,10 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             }
             self.emit_ins(X86Instruction::mov(OperandSize::S64, RSP, REGISTER_MAP[0]));
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, - 8 * 3, None)); // RSP -= 8 * 3;
-            self.emit_rust_call(Value::Constant64(C::trace as *const u8 as i64, false), &[
+            self.emit_rust_call(Value::Constant64(EbpfVm::<C>::trace_instruction as *const u8 as i64, false), &[
+                Argument { index: 2, value: Value::Register(REGISTER_OTHER_SCRATCH) }, // is_branch
                 Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) }, // registers
-                Argument { index: 0, value: Value::RegisterIndirect(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::ContextObjectPointer), false) },
+                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
             ], None);
             // Pop stack and return
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, 8 * 3, None)); // RSP += 8 * 3;
@@ -1353,12 +2517,16 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
 
         // Epilogue for errors
         self.set_anchor(ANCHOR_THROW_EXCEPTION_UNCHECKED);
+        self.emit_store_registers();
         self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_SCRATCH, REGISTER_PTR_TO_VM, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::Registers) + 11 * std::mem::size_of::<u64>() as i32))); // registers[11] = pc;
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EPILOGUE, 5)));
 
         // Quit gracefully
         self.set_anchor(ANCHOR_EXIT);
         self.emit_validate_instruction_count(false, None);
+        for (i, reg) in REGISTER_MAP.iter().take(ebpf::FRAME_PTR_REG).enumerate() {
+            self.emit_ins(X86Instruction::store(OperandSize::S64, *reg, REGISTER_PTR_TO_VM, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::Registers) + (i * std::mem::size_of::<u64>()) as i32))); // registers[i] = reg;
+        }
         self.emit_ins(X86Instruction::lea(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_OTHER_SCRATCH, Some(X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::ProgramResult)))));
         self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], REGISTER_OTHER_SCRATCH, X86IndirectAccess::Offset(std::mem::size_of::<u64>() as i32))); // result.return_value = R0;
         self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[0], 0));
@@ -1390,11 +2558,14 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         self.emit_set_exception_kind(EbpfError::DivideOverflow);
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
 
-        // Handler for EbpfError::UnsupportedInstruction
+        // Handler for EbpfError::UnsupportedInstruction, reached by jumping to the unsupported instruction
         self.set_anchor(ANCHOR_CALL_UNSUPPORTED_INSTRUCTION);
         if self.config.enable_instruction_tracing {
+            self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_OTHER_SCRATCH, 1)); // is_branch = true;
             self.emit_ins(X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_TRACE, 5)));
         }
+        // Handler for EbpfError::UnsupportedInstruction, reached from an instruction which was traced already
+        self.set_anchor(ANCHOR_THROW_UNSUPPORTED_INSTRUCTION);
         self.emit_set_exception_kind(EbpfError::UnsupportedInstruction);
         self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 5)));
 
@@ -1404,6 +2575,24 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         if self.config.enable_instruction_meter {
             self.emit_ins(X86Instruction::store(OperandSize::S64, REGISTER_INSTRUCTION_METER, REGISTER_PTR_TO_VM, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::DueInsnCount)))); // *DueInsnCount = REGISTER_INSTRUCTION_METER;
         }
+        if self.config.enable_syscall_tracing {
+            self.emit_syscall_trace(EbpfVm::<C>::trace_syscall_entry as *const u8 as i64);
+        }
+        let skip_syscall = if self.charges_syscall_costs() {
+            self.emit_rust_call(Value::Constant64(charge_syscall_cost::<C> as *const u8 as i64, false), &[
+                Argument { index: 5, value: Value::Register(ARGUMENT_REGISTERS[5]) },
+                Argument { index: 4, value: Value::Register(ARGUMENT_REGISTERS[4]) },
+                Argument { index: 3, value: Value::Register(ARGUMENT_REGISTERS[3]) },
+                Argument { index: 2, value: Value::Register(ARGUMENT_REGISTERS[2]) },
+                Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[1]) },
+                Argument { index: 0, value: Value::Register(REGISTER_PTR_TO_VM) },
+            ], None);
+            // Do not invoke the syscall if its cost exceeded the remaining budget
+            self.emit_result_is_err(REGISTER_OTHER_SCRATCH);
+            Some(self.emit_local_jump(Some(0x85)))
+        } else {
+            None
+        };
         self.emit_rust_call(Value::Register(REGISTER_SCRATCH), &[
             Argument { index: 5, value: Value::Register(ARGUMENT_REGISTERS[5]) },
             Argument { index: 4, value: Value::Register(ARGUMENT_REGISTERS[4]) },
@@ -1412,6 +2601,18 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
             Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[1]) },
             Argument { index: 0, value: Value::Register(REGISTER_PTR_TO_VM) },
         ], None);
+        if self.config.enable_address_translation && self.config.aligned_memory_mapping {
+            // The built-in function might have replaced the MemoryMapping
+            self.emit_rust_call(Value::Constant64(EbpfVm::<C>::cache_memory_regions as *const u8 as i64, false), &[
+                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
+        if let Some(skip_syscall) = skip_syscall {
+            self.patch_local_jump(skip_syscall);
+        }
+        if self.config.enable_syscall_tracing {
+            self.emit_syscall_trace(EbpfVm::<C>::trace_syscall_exit as *const u8 as i64);
+        }
         if self.config.enable_instruction_meter {
             self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_INSTRUCTION_METER, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::PreviousInstructionMeter)))); // REGISTER_INSTRUCTION_METER = *PreviousInstructionMeter;
         }
@@ -1419,7 +2620,10 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         // Test if result indicates that an error occured
         self.emit_result_is_err(REGISTER_SCRATCH);
         self.emit_ins(X86Instruction::pop(REGISTER_SCRATCH));
-        self.emit_ins(X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_EPILOGUE, 6)));
+        let no_error = self.emit_local_jump(Some(0x84));
+        self.emit_store_registers();
+        self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EPILOGUE, 5)));
+        self.patch_local_jump(no_error);
         // Store Ok value in result register
         self.emit_ins(X86Instruction::lea(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_SCRATCH, Some(X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::ProgramResult)))));
         self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_SCRATCH, REGISTER_MAP[0], X86IndirectAccess::Offset(8)));
@@ -1449,10 +2653,21 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         let stack_pointer_access = X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::StackPointer));
         if !self.executable.get_sbpf_version().dynamic_stack_frames() {
             // With fixed frames we start the new frame at the next fixed offset
-            let stack_frame_size = self.config.stack_frame_size as i64 * if self.config.enable_stack_frame_gaps { 2 } else { 1 };
+            let stack_frame_size = self.config.stack_frame_size as i64 + self.config.stack_frame_gap_size(self.executable.get_sbpf_version()) as i64;
             self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x81, 0, REGISTER_PTR_TO_VM, stack_frame_size, Some(stack_pointer_access))); // env.stack_pointer += stack_frame_size;
         }
         self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_MAP[FRAME_PTR_REG], stack_pointer_access)); // reg[ebpf::FRAME_PTR_REG] = env.stack_pointer;
+        if self.config.zero_stack_frames && !self.executable.get_sbpf_version().dynamic_stack_frames() {
+            self.emit_rust_call(Value::Constant64(EbpfVm::<C>::zero_stack_frame as *const u8 as i64, false), &[
+                Argument { index: 1, value: Value::Register(REGISTER_MAP[FRAME_PTR_REG]) },
+                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
+        if self.config.enable_jit_function_timing {
+            self.emit_rust_call(Value::Constant64(EbpfVm::<C>::enter_function as *const u8 as i64, false), &[
+                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], None);
+        }
         self.emit_ins(X86Instruction::return_near());
 
         // Routine for emit_internal_call(Value::Register())
@@ -1464,12 +2679,16 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         let number_of_instructions = self.result.pc_section.len();
         self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.program_vm_addr as i64 + (number_of_instructions * INSN_SIZE) as i64));
         self.emit_ins(X86Instruction::cmp(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], None));
-        self.emit_ins(X86Instruction::conditional_jump_immediate(0x83, self.relative_to_anchor(ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT, 6)));
+        let above_text_segment = self.emit_local_jump(Some(0x83));
         // Lower bound check
         // if(RAX < self.program_vm_addr) throw CALL_OUTSIDE_TEXT_SEGMENT;
         self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.program_vm_addr as i64));
         self.emit_ins(X86Instruction::cmp(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], None));
-        self.emit_ins(X86Instruction::conditional_jump_immediate(0x82, self.relative_to_anchor(ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT, 6)));
+        let below_text_segment = self.emit_local_jump(Some(0x82));
+        if self.config.speculation_hardening != SpeculationHardening::None {
+            // Do not load from the pc_section before the bounds checks are resolved
+            self.emit_ins(X86Instruction::fence(FenceType::Load));
+        }
         // Calculate offset relative to instruction_addresses
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x29, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], 0, None)); // RAX -= self.program_vm_addr;
         // Calculate the target_pc (dst / INSN_SIZE) to update REGISTER_INSTRUCTION_METER
@@ -1480,12 +2699,31 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0xc1, 5, REGISTER_SCRATCH, shift_amount as i64, None));
         // Load host target_address from self.result.pc_section
         debug_assert_eq!(INSN_SIZE, 8); // Because the instruction size is also the slot size we do not need to shift the offset
-        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.result.pc_section.as_ptr() as i64));
+        self.emit_ins(X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.pc_section_address as i64));
         self.emit_ins(X86Instruction::alu(OperandSize::S64, 0x01, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], 0, None)); // RAX += self.result.pc_section;
         self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_MAP[0], REGISTER_MAP[0], X86IndirectAccess::Offset(0))); // RAX = self.result.pc_section[RAX / 8];
         // Load the frame pointer again since we've clobbered REGISTER_MAP[FRAME_PTR_REG]
         self.emit_ins(X86Instruction::load(OperandSize::S64, REGISTER_PTR_TO_VM, REGISTER_MAP[FRAME_PTR_REG], stack_pointer_access));
         self.emit_ins(X86Instruction::return_near());
+        // Restore RAX, which emit_internal_call() pushed, so that the exception reports it
+        self.patch_local_jump(above_text_segment);
+        self.patch_local_jump(below_text_segment);
+        self.emit_ins(X86Instruction::load(OperandSize::S64, RSP, REGISTER_MAP[0], X86IndirectAccess::OffsetIndexShift(8, RSP, 0)));
+        self.emit_ins(X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT, 5)));
+
+        // Routine for functions which are compiled on their first call, expects the target pc in REGISTER_SCRATCH
+        if self.config.enable_lazy_jit_compilation {
+            self.set_anchor(ANCHOR_LAZY_COMPILATION);
+            self.emit_rust_call(Value::Constant64(lazily_compile::<C> as *const u8 as i64, false), &[
+                Argument { index: 1, value: Value::Register(REGISTER_SCRATCH) },
+                Argument { index: 0, value: Value::RegisterPlusConstant32(REGISTER_PTR_TO_VM, self.slot_in_vm(RuntimeEnvironmentSlot::HostStackPointer), false) },
+            ], Some(REGISTER_OTHER_SCRATCH));
+            // A compilation error was stored in the program_result
+            self.emit_ins(X86Instruction::cmp_immediate(OperandSize::S64, REGISTER_OTHER_SCRATCH, 0, None));
+            self.emit_ins(X86Instruction::conditional_jump_immediate(0x84, self.relative_to_anchor(ANCHOR_THROW_EXCEPTION, 6)));
+            // Continue at the host address of the target pc
+            self.emit_indirect_branch(false, REGISTER_OTHER_SCRATCH, None);
+        }
 
         // Translates a vm memory address to a host memory address
         for (access_type, len) in &[
@@ -1546,28 +2784,53 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
     }
 
     fn set_anchor(&mut self, anchor: usize) {
-        self.anchors[anchor] = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section) };
+        self.anchors[anchor] = unsafe { self.text_section.add(self.offset_in_text_section) };
     }
 
     // instruction_length = 5 (Unconditional jump / call)
     // instruction_length = 6 (Conditional jump)
     #[inline]
     fn relative_to_anchor(&self, anchor: usize, instruction_length: usize) -> i32 {
-        let instruction_end = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section).add(instruction_length) };
+        let instruction_end = unsafe { self.text_section.add(self.offset_in_text_section).add(instruction_length) };
         let destination = self.anchors[anchor];
         debug_assert!(!destination.is_null());
         (unsafe { destination.offset_from(instruction_end) } as i32) // Relative jump
     }
 
+    /// Stores the guest registers r0 to r9 into the registers of the EbpfVm
+    fn emit_store_registers(&mut self) {
+        for (i, reg) in REGISTER_MAP.iter().take(ebpf::FRAME_PTR_REG).enumerate() {
+            self.emit_ins(X86Instruction::store(OperandSize::S64, *reg, REGISTER_PTR_TO_VM, X86IndirectAccess::Offset(self.slot_in_vm(RuntimeEnvironmentSlot::Registers) + (i * std::mem::size_of::<u64>()) as i32))); // registers[i] = reg;
+        }
+    }
+
+    /// Emits a jump to a destination inside the current instruction, which is set by patch_local_jump()
+    #[inline]
+    fn emit_local_jump(&mut self, condition: Option<u8>) -> usize {
+        let instruction_end = self.offset_in_text_section + if condition.is_some() { 6 } else { 5 };
+        match condition {
+            Some(opcode) => self.emit_ins(X86Instruction::conditional_jump_immediate(opcode, 0)),
+            None => self.emit_ins(X86Instruction::jump_immediate(0)),
+        }
+        instruction_end
+    }
+
+    /// Sets the destination of a jump emitted by emit_local_jump() to the current position
+    #[inline]
+    fn patch_local_jump(&mut self, instruction_end: usize) {
+        let offset_value = (self.offset_in_text_section - instruction_end) as i32;
+        unsafe { ptr::write_unaligned(self.writable_text_section().add(instruction_end - mem::size_of::<i32>()).cast::<i32>(), offset_value); }
+    }
+
     #[inline]
     fn relative_to_target_pc(&mut self, target_pc: usize, instruction_length: usize) -> i32 {
-        let instruction_end = unsafe { self.result.text_section.as_ptr().add(self.offset_in_text_section).add(instruction_length) };
-        let destination = if self.result.pc_section[target_pc] != 0 {
+        let instruction_end = unsafe { self.text_section.add(self.offset_in_text_section).add(instruction_length) };
+        let destination = if self.result.host_address(target_pc) != 0 {
             // Backward jump
-            self.result.pc_section[target_pc] as *const u8
+            self.result.host_address(target_pc) as *const u8
         } else {
             // Forward jump, needs relocation
-            self.text_section_jumps.push(Jump { location: unsafe { instruction_end.sub(4) }, target_pc });
+            self.text_section_jumps.push(Jump { location: self.offset_in_text_section + instruction_length - mem::size_of::<i32>(), target_pc });
             return 0;
         };
         debug_assert!(!destination.is_null());
@@ -1577,14 +2840,16 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
     fn resolve_jumps(&mut self) {
         // Relocate forward jumps
         for jump in &self.text_section_jumps {
-            let destination = self.result.pc_section[jump.target_pc] as *const u8;
+            let destination = self.result.host_address(jump.target_pc) as *const u8;
+            let location = unsafe { self.text_section.add(jump.location) };
             let offset_value = 
-                unsafe { destination.offset_from(jump.location) } as i32 // Relative jump
+                unsafe { destination.offset_from(location) } as i32 // Relative jump
                 - mem::size_of::<i32>() as i32; // Jump from end of instruction
-            unsafe { ptr::write_unaligned(jump.location as *mut i32, offset_value); }
+            unsafe { ptr::write_unaligned(self.writable_text_section().add(jump.location).cast::<i32>(), offset_value); }
         }
         // There is no `VerifierError::JumpToMiddleOfLDDW` for `call imm` so patch it here
-        let call_unsupported_instruction = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
+        // Like the interpreter, a call to an unregistered function does not trace its target
+        let call_unsupported_instruction = self.anchors[ANCHOR_THROW_UNSUPPORTED_INSTRUCTION] as usize;
         if self.executable.get_sbpf_version().static_syscalls() {
             let mut prev_pc = 0;
             for current_pc in self.executable.get_function_registry().keys() {
@@ -1592,12 +2857,12 @@ impl<'a, C: ContextObject> JitCompiler<'a, C> {
                     break;
                 }
                 for pc in prev_pc..current_pc as usize {
-                    self.result.pc_section[pc] = call_unsupported_instruction;
+                    self.result.set_host_address(pc, call_unsupported_instruction);
                 }
                 prev_pc = current_pc as usize + 1;
             }
             for pc in prev_pc..self.result.pc_section.len() {
-                self.result.pc_section[pc] = call_unsupported_instruction;
+                self.result.set_host_address(pc, call_unsupported_instruction);
             }
         }
     }
@@ -1616,15 +2881,16 @@ mod tests {
 
     #[test]
     fn test_runtime_environment_slots() {
-        let executable = create_mockup_executable(&[]);
+        let executable =
+            create_mockup_executable(&[ebpf::EXIT, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
         let mut context_object = TestContextObject::new(0);
         let env = EbpfVm::new(
-            executable.get_loader().clone(),
-            executable.get_sbpf_version(),
+            &executable,
             &mut context_object,
             MemoryMapping::new_identity(),
             0,
-        );
+        )
+        .unwrap();
 
         macro_rules! check_slot {
             ($env:expr, $entry:ident, $slot:ident) => {
@@ -1642,29 +2908,46 @@ mod tests {
         check_slot!(env, host_stack_pointer, HostStackPointer);
         check_slot!(env, call_depth, CallDepth);
         check_slot!(env, stack_pointer, StackPointer);
-        check_slot!(env, context_object_pointer, ContextObjectPointer);
         check_slot!(env, previous_instruction_meter, PreviousInstructionMeter);
         check_slot!(env, due_insn_count, DueInsnCount);
         check_slot!(env, stopwatch_numerator, StopwatchNumerator);
         check_slot!(env, stopwatch_denominator, StopwatchDenominator);
         check_slot!(env, registers, Registers);
         check_slot!(env, program_result, ProgramResult);
+        check_slot!(env, memory_regions, MemoryRegions);
+        check_slot!(env, memory_region_count, MemoryRegionCount);
+        check_slot!(env, invoked_syscall, InvokedSyscall);
         check_slot!(env, memory_mapping, MemoryMapping);
     }
 
+    /// Sets r0 to 42 and exits
+    #[cfg(any(target_os = "linux", feature = "unstable", feature = "jit-disassembler"))]
+    #[rustfmt::skip]
+    const MOV_42_PROGRAM: [u8; 16] = [
+        ebpf::MOV64_IMM, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00,
+        ebpf::EXIT,      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
     fn create_mockup_executable(program: &[u8]) -> Executable<TestContextObject> {
+        create_mockup_executable_with_config(
+            Config {
+                noop_instruction_rate: 0,
+                ..Config::default()
+            },
+            program,
+        )
+    }
+
+    fn create_mockup_executable_with_config(
+        config: Config,
+        program: &[u8],
+    ) -> Executable<TestContextObject> {
         let mut function_registry =
             FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
         function_registry
             .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
             .unwrap();
-        let loader = BuiltinProgram::new_loader(
-            Config {
-                noop_instruction_rate: 0,
-                ..Config::default()
-            },
-            function_registry,
-        );
+        let loader = BuiltinProgram::new_loader(config, function_registry);
         let mut function_registry = FunctionRegistry::default();
         function_registry
             .register_function(8, *b"function_foo", 8)
@@ -1719,11 +3002,8 @@ mod tests {
             }
             let mut executable = create_mockup_executable(&prog);
             let result = Executable::<TestContextObject>::jit_compile(&mut executable);
-            if result.is_err() {
-                assert!(matches!(
-                    result.unwrap_err(),
-                    EbpfError::UnsupportedInstruction
-                ));
+            if let Err(err) = result {
+                assert!(matches!(err, EbpfError::UnsupportedInstruction));
                 continue;
             }
             let machine_code_length = executable
@@ -1748,4 +3028,437 @@ mod tests {
             }*/
         }
     }
+
+    #[test]
+    fn test_text_section_growth() {
+        const INSTRUCTION_COUNT: usize = 1024;
+        let mut prog = [0; ebpf::INSN_SIZE * INSTRUCTION_COUNT];
+        for pc in 0..INSTRUCTION_COUNT - 1 {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::ST_DW_IMM;
+            prog[pc * ebpf::INSN_SIZE + 1] = 0x0A;
+            LittleEndian::write_u16(&mut prog[pc * ebpf::INSN_SIZE + 2..], -8i16 as u16);
+            LittleEndian::write_u32(&mut prog[pc * ebpf::INSN_SIZE + 4..], 0xFFFFFFFF);
+        }
+        prog[(INSTRUCTION_COUNT - 1) * ebpf::INSN_SIZE] = ebpf::EXIT;
+        let mut executable = create_mockup_executable(&prog);
+        let initial_code_length = JitCompiler::<TestContextObject>::estimate_code_length(
+            executable.get_config(),
+            INSTRUCTION_COUNT,
+            EXPECTED_MACHINE_CODE_LENGTH_PER_INSTRUCTION,
+        );
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let machine_code_length = executable
+            .get_compiled_program()
+            .unwrap()
+            .machine_code_length();
+        assert!(machine_code_length > initial_code_length);
+    }
+
+    #[test]
+    fn test_diversification_seed() {
+        const INSTRUCTION_COUNT: usize = 256;
+        let mut prog = [0; ebpf::INSN_SIZE * INSTRUCTION_COUNT];
+        for pc in 0..INSTRUCTION_COUNT - 1 {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::ADD64_IMM;
+            LittleEndian::write_u32(&mut prog[pc * ebpf::INSN_SIZE + 4..], pc as u32);
+        }
+        prog[(INSTRUCTION_COUNT - 1) * ebpf::INSN_SIZE] = ebpf::EXIT;
+        let machine_code_length = |config: Config| {
+            let mut executable = create_mockup_executable_with_config(config, &prog);
+            Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+            executable
+                .get_compiled_program()
+                .unwrap()
+                .machine_code_length()
+        };
+
+        // The same seed pads the machine code the same way
+        let seeded_config = Config {
+            jit_diversification_seed: Some(42),
+            ..Config::default()
+        };
+        assert_eq!(
+            machine_code_length(seeded_config),
+            machine_code_length(seeded_config)
+        );
+
+        // Disabling the diversification is equivalent to not inserting any no-ops
+        assert_eq!(
+            machine_code_length(Config {
+                enable_jit_diversification: false,
+                ..seeded_config
+            }),
+            machine_code_length(Config {
+                noop_instruction_rate: 0,
+                ..seeded_config
+            })
+        );
+    }
+
+    #[test]
+    fn test_statistics() {
+        // Jumps over the second instruction
+        let prog = [
+            ebpf::JA,
+            0x00,
+            0x01,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            ebpf::MOV64_IMM,
+            0x00,
+            0x00,
+            0x00,
+            0x01,
+            0x00,
+            0x00,
+            0x00,
+            ebpf::EXIT,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        let mut executable = create_mockup_executable_with_config(
+            Config {
+                enable_jit_diversification: false,
+                ..Config::default()
+            },
+            &prog,
+        );
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let jit_program = executable.get_compiled_program().unwrap();
+        let statistics = jit_program.statistics();
+        assert_eq!(
+            statistics.machine_code_length,
+            jit_program.machine_code_length()
+        );
+        assert_eq!(statistics.instruction_count, 3);
+        assert_eq!(statistics.resolved_jumps, 1);
+        assert!((1..=ANCHOR_COUNT).contains(&statistics.anchors_used));
+        assert_eq!(
+            statistics.bytes_per_instruction(),
+            statistics.machine_code_length as f64 / 3.0
+        );
+    }
+
+    #[test]
+    fn test_dead_register_elision() {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                noop_instruction_rate: 0,
+                enable_jit_diversification: false,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        let machine_code_length = |source: &str| {
+            let mut executable =
+                crate::assembler::assemble::<TestContextObject>(source, loader.clone()).unwrap();
+            Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+            executable
+                .get_compiled_program()
+                .unwrap()
+                .machine_code_length()
+        };
+        // Each pair only differs in whether the registers preserved by the first instruction
+        // are overwritten or read afterwards, which is encoded in the same length
+        for (dead, live) in [
+            (
+                "
+                call function_foo
+                mov r6, r1
+                mov r7, r1
+                mov r8, r1
+                mov r9, r1
+                exit
+                function_foo:
+                exit",
+                "
+                call function_foo
+                mov r6, r6
+                mov r7, r7
+                mov r8, r8
+                mov r9, r9
+                exit
+                function_foo:
+                exit",
+            ),
+            (
+                "
+                lsh64 r1, r2
+                mov r3, r1
+                exit",
+                "
+                lsh64 r1, r2
+                mov r3, r3
+                exit",
+            ),
+        ] {
+            assert!(machine_code_length(dead) < machine_code_length(live));
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn test_section_allocator() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Keeps track of the bytes which are allocated, but does not support dual mapping
+        #[derive(Default)]
+        struct CountingAllocator {
+            allocated: AtomicUsize,
+        }
+
+        impl SectionAllocator for CountingAllocator {
+            unsafe fn allocate(&self, size_in_bytes: usize) -> Result<*mut u8, EbpfError> {
+                self.allocated.fetch_add(size_in_bytes, Ordering::Relaxed);
+                PageAllocator.allocate(size_in_bytes)
+            }
+
+            unsafe fn shrink(
+                &self,
+                raw: *mut u8,
+                size_in_bytes: usize,
+                new_size_in_bytes: usize,
+            ) -> Result<usize, EbpfError> {
+                self.allocated
+                    .fetch_sub(size_in_bytes - new_size_in_bytes, Ordering::Relaxed);
+                PageAllocator.shrink(raw, size_in_bytes, new_size_in_bytes)
+            }
+
+            unsafe fn free(&self, raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError> {
+                self.allocated.fetch_sub(size_in_bytes, Ordering::Relaxed);
+                PageAllocator.free(raw, size_in_bytes)
+            }
+        }
+
+        let allocator = Arc::new(CountingAllocator::default());
+        let mut executable = create_mockup_executable(&MOV_42_PROGRAM);
+        executable
+            .jit_compile_with_allocator(allocator.clone())
+            .unwrap();
+        let jit_program = executable.get_compiled_program().unwrap();
+        assert_eq!(
+            allocator.allocated.load(Ordering::Relaxed),
+            jit_program.mem_size()
+                - jit_program.syscall_keys.len() * mem::size_of::<u32>()
+                - jit_program
+                    .syscall_names
+                    .iter()
+                    .map(|name| mem::size_of::<Box<[u8]>>() + name.len())
+                    .sum::<usize>()
+                - jit_program.syscall_table.len() * mem::size_of::<usize>()
+        );
+        drop(executable);
+        assert_eq!(allocator.allocated.load(Ordering::Relaxed), 0);
+
+        let mut executable = create_mockup_executable_with_config(
+            Config {
+                enable_jit_dual_mapping: true,
+                ..Config::default()
+            },
+            &MOV_42_PROGRAM,
+        );
+        assert!(matches!(
+            executable.jit_compile_with_allocator(allocator.clone()),
+            Err(EbpfError::DualMappingUnsupported)
+        ));
+        assert_eq!(allocator.allocated.load(Ordering::Relaxed), 0);
+    }
+
+    /// Protection of the page at `address`, like "r-x"
+    #[cfg(target_os = "linux")]
+    fn protection_of(address: usize) -> String {
+        std::fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .find_map(|line| {
+                let mut columns = line.split_whitespace();
+                let (start, end) = columns.next()?.split_once('-')?;
+                let range =
+                    usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?;
+                range
+                    .contains(&address)
+                    .then(|| columns.next().unwrap()[0..3].to_string())
+            })
+            .unwrap()
+    }
+
+    #[cfg(all(feature = "unstable", target_os = "linux"))]
+    #[test]
+    fn test_freed_pages_are_writable() {
+        /// Records the protection of every allocation it gets back
+        #[derive(Default)]
+        struct RecordingAllocator {
+            freed: Mutex<Vec<String>>,
+        }
+
+        impl SectionAllocator for RecordingAllocator {
+            unsafe fn allocate(&self, size_in_bytes: usize) -> Result<*mut u8, EbpfError> {
+                PageAllocator.allocate(size_in_bytes)
+            }
+
+            unsafe fn allocate_dual_mapped(
+                &self,
+                size_in_bytes: usize,
+            ) -> Result<(*mut u8, *mut u8), EbpfError> {
+                PageAllocator.allocate_dual_mapped(size_in_bytes)
+            }
+
+            unsafe fn free(&self, raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError> {
+                self.freed.lock().unwrap().extend(
+                    (0..size_in_bytes)
+                        .step_by(self.page_size())
+                        .map(|offset| protection_of(raw as usize + offset)),
+                );
+                PageAllocator.free(raw, size_in_bytes)
+            }
+        }
+
+        for enable_jit_dual_mapping in [false, true] {
+            for enable_lazy_jit_compilation in [false, true] {
+                let allocator = Arc::new(RecordingAllocator::default());
+                let mut executable = create_mockup_executable_with_config(
+                    Config {
+                        enable_jit_dual_mapping,
+                        enable_lazy_jit_compilation,
+                        ..Config::default()
+                    },
+                    &MOV_42_PROGRAM,
+                );
+                executable
+                    .jit_compile_with_allocator(allocator.clone())
+                    .unwrap();
+                drop(executable);
+                let freed = allocator.freed.lock().unwrap();
+                assert!(!freed.is_empty());
+                assert!(freed.iter().all(|protection| protection == "rw-"));
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dual_mapping_protections() {
+        for enable_lazy_jit_compilation in [false, true] {
+            let mut executable = create_mockup_executable_with_config(
+                Config {
+                    enable_jit_dual_mapping: true,
+                    enable_lazy_jit_compilation,
+                    ..Config::default()
+                },
+                &MOV_42_PROGRAM,
+            );
+            executable.jit_compile().unwrap();
+            let jit_program = executable.get_compiled_program().unwrap();
+            // The emitted code is executable, but not writable
+            let text_section = jit_program.text_section.as_ptr() as usize;
+            assert_eq!(protection_of(text_section), "r-x");
+            match jit_program.lazy_compilation.as_ref() {
+                Some(lazy_compilation) => {
+                    // The reserved pages are only writable through the other mapping, which is
+                    // not executable
+                    let lazy_compilation = lazy_compilation.lock().unwrap();
+                    for offset in (0..lazy_compilation.text_section_reservation)
+                        .step_by(jit_program.page_size)
+                    {
+                        assert!(!protection_of(text_section + offset).contains('w'));
+                        assert_eq!(
+                            protection_of(lazy_compilation.writable_text_section + offset),
+                            "rw-"
+                        );
+                    }
+                }
+                // Nothing is written anymore, so the writable mapping is gone
+                None => assert_eq!(jit_program.write_offset, 0),
+            }
+        }
+    }
+
+    #[cfg(feature = "jit-disassembler")]
+    #[test]
+    fn test_disassemble() {
+        let mut executable = create_mockup_executable(&MOV_42_PROGRAM);
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let mut output = Vec::new();
+        executable
+            .get_compiled_program()
+            .unwrap()
+            .disassemble(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let pc_0 = output.find("pc 0:\n").unwrap();
+        let pc_1 = output.find("pc 1:\n").unwrap();
+        assert!(pc_0 < pc_1);
+        assert!(output[pc_0..pc_1].contains("mov rax,2Ah"));
+        assert!(output[pc_1..].contains("ret"));
+    }
+
+    #[cfg(feature = "jit-disassembler")]
+    #[test]
+    fn test_disassemble_lazily_compiled() {
+        let mut prog = [0; ebpf::INSN_SIZE * 16];
+        for pc in 0..16 {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::EXIT;
+        }
+        let mut executable = create_mockup_executable_with_config(
+            Config {
+                enable_lazy_jit_compilation: true,
+                ..Config::default()
+            },
+            &prog,
+        );
+        executable.jit_compile().unwrap();
+        let jit_program = executable.get_compiled_program().unwrap();
+        let disassemble = || {
+            let mut output = Vec::new();
+            jit_program.disassemble(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert!(disassemble().contains("pc 0:\n"));
+        assert!(!disassemble().contains("pc 8:\n"));
+        jit_program.compile_lazily(&executable, 8).unwrap();
+        let output = disassemble();
+        let pc_8 = output.find("pc 8:\n").unwrap();
+        assert!(output[pc_8..].contains("ret"));
+    }
+
+    #[test]
+    fn test_perf_map() {
+        let mut prog = [0; ebpf::INSN_SIZE * 16];
+        for pc in 0..16 {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::EXIT;
+        }
+        let mut executable = create_mockup_executable(&prog);
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let jit_program = executable.get_compiled_program().unwrap();
+        let mut output = Vec::new();
+        jit_program
+            .write_perf_map(&mut output, executable.get_function_registry())
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let entrypoint = lines[0].split(' ').collect::<Vec<_>>();
+        let function_foo = lines[1].split(' ').collect::<Vec<_>>();
+        assert_eq!(entrypoint[2], "entrypoint");
+        assert_eq!(function_foo[2], "function_foo");
+        let parse = |field: &str| usize::from_str_radix(field, 16).unwrap();
+        assert_eq!(parse(entrypoint[0]), jit_program.host_address(0));
+        assert_eq!(parse(function_foo[0]), jit_program.host_address(8));
+        assert_eq!(
+            parse(entrypoint[0]) + parse(entrypoint[1]),
+            parse(function_foo[0])
+        );
+        assert_eq!(
+            parse(function_foo[0]) + parse(function_foo[1]),
+            jit_program.text_section.as_ptr() as usize + jit_program.machine_code_length(),
+        );
+    }
 }
@@ -0,0 +1,192 @@
+//! Sharing of JIT compiled programs between executables which load the same code
+
+use crate::{
+    elf::Executable,
+    error::EbpfError,
+    jit::{JitCompiler, JitProgram},
+    program::SBPFVersion,
+    vm::{Config, ContextObject},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+/// Counters of a [JitCache], e.g. to tune its capacity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JitCacheStatistics {
+    /// Lookups which found a compiled program
+    pub hits: u64,
+    /// Lookups which had to compile the program
+    pub misses: u64,
+    /// Compiled programs which were dropped to make room for others
+    pub evictions: u64,
+    /// Number of compiled programs currently in the cache
+    pub len: usize,
+}
+
+/// Everything the machine code of a JitProgram depends on
+#[derive(PartialEq, Eq, Hash)]
+struct JitCacheKey {
+    /// The machine code calls into the host through functions which are generic over it
+    context_object: TypeId,
+    text_section_vaddr: u64,
+    /// SHA-256 of the text section
+    text_hash: [u8; 32],
+    sbpf_version: SBPFVersion,
+    config: Config,
+    /// Keys and pcs of the functions
+    functions: Vec<(u32, usize)>,
+    /// Keys and host addresses of the syscalls
+    syscalls: Vec<(u32, usize)>,
+    /// Whether the machine code charges syscall costs, which it looks up in the loader at runtime
+    charges_syscall_costs: bool,
+}
+
+impl JitCacheKey {
+    fn new<C: ContextObject + 'static>(executable: &Executable<C>) -> Self {
+        let (text_section_vaddr, text_bytes) = executable.get_text_bytes();
+        Self {
+            context_object: TypeId::of::<C>(),
+            text_section_vaddr,
+            text_hash: Sha256::digest(text_bytes).into(),
+            sbpf_version: executable.get_sbpf_version().clone(),
+            config: *executable.get_config(),
+            functions: executable
+                .get_function_registry()
+                .iter()
+                .map(|(key, (_name, pc))| (key, pc))
+                .collect(),
+            syscalls: executable
+                .get_loader()
+                .get_function_registry()
+                .iter()
+                .map(|(key, (_name, function))| {
+                    (
+                        key,
+                        executable.lookup_syscall(key).unwrap_or(function) as usize,
+                    )
+                })
+                .collect(),
+            charges_syscall_costs: executable.get_loader().has_syscall_costs(),
+        }
+    }
+}
+
+struct JitCacheEntry {
+    program: Arc<JitProgram>,
+    /// Value of the clock at the last lookup
+    last_use: u64,
+}
+
+#[derive(Default)]
+struct JitCacheState {
+    entries: HashMap<JitCacheKey, JitCacheEntry>,
+    clock: u64,
+    statistics: JitCacheStatistics,
+}
+
+/// In-process LRU cache of JIT compiled programs
+///
+/// Executables which are loaded from the same ELF by the same loader compile to equivalent
+/// machine code, so instead of compiling every one of them they can share a single JitProgram,
+/// see `Executable::jit_compile_cached()`. The key consists of the type of the ContextObject,
+/// a hash of the text section, the Config, the SBPFVersion, the function registry, the
+/// syscalls and whether the loader has syscall costs. Once `capacity` programs are
+/// cached the least recently used one is dropped, which only releases its memory when no
+/// executable uses it anymore. A program whose syscalls were swapped by
+/// `JitProgram::rebind_syscall()` is dropped as well, instead of being handed out again.
+pub struct JitCache {
+    capacity: usize,
+    state: Mutex<JitCacheState>,
+}
+
+impl JitCache {
+    /// Creates an empty cache which holds up to `capacity` compiled programs
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(JitCacheState::default()),
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, JitCacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the counters of the lookups so far
+    pub fn statistics(&self) -> JitCacheStatistics {
+        let state = self.lock_state();
+        JitCacheStatistics {
+            len: state.entries.len(),
+            ..state.statistics
+        }
+    }
+
+    /// Drops all compiled programs, the statistics are kept
+    pub fn clear(&self) {
+        self.lock_state().entries.clear();
+    }
+
+    /// Returns the compiled program of an equivalent executable or compiles this one
+    pub(crate) fn get_or_compile<C: ContextObject + 'static>(
+        &self,
+        executable: &Executable<C>,
+    ) -> Result<Arc<JitProgram>, EbpfError> {
+        let key = JitCacheKey::new(executable);
+        {
+            let mut state = self.lock_state();
+            state.clock = state.clock.saturating_add(1);
+            let clock = state.clock;
+            if let Some(entry) = state.entries.get_mut(&key) {
+                if entry.program.has_rebound_syscalls() {
+                    // The syscalls of the program no longer match the key
+                    state.entries.remove(&key);
+                    state.statistics.evictions = state.statistics.evictions.saturating_add(1);
+                } else {
+                    entry.last_use = clock;
+                    let program = entry.program.clone();
+                    state.statistics.hits = state.statistics.hits.saturating_add(1);
+                    return Ok(program);
+                }
+            }
+            state.statistics.misses = state.statistics.misses.saturating_add(1);
+        }
+        // Compile without holding the lock, so that lookups of other programs are not blocked
+        let program = Arc::new(JitCompiler::<C>::new(executable)?.compile()?);
+        if self.capacity == 0 {
+            return Ok(program);
+        }
+        let mut state = self.lock_state();
+        if let Some(entry) = state
+            .entries
+            .get(&key)
+            .filter(|entry| !entry.program.has_rebound_syscalls())
+        {
+            // Another thread compiled the same program in the meantime
+            return Ok(entry.program.clone());
+        }
+        if state.entries.len() >= self.capacity {
+            // Every lookup and insertion advances the clock, so the last uses are unique
+            if let Some(least_recent_use) = state.entries.values().map(|entry| entry.last_use).min()
+            {
+                state
+                    .entries
+                    .retain(|_key, entry| entry.last_use != least_recent_use);
+                state.statistics.evictions = state.statistics.evictions.saturating_add(1);
+            }
+        }
+        state.clock = state.clock.saturating_add(1);
+        let clock = state.clock;
+        state.entries.insert(
+            key,
+            JitCacheEntry {
+                program: program.clone(),
+                last_use: clock,
+            },
+        );
+        Ok(program)
+    }
+}
@@ -0,0 +1,283 @@
+//! Compatibility layer for integrations written against the old helper based `EbpfVm` API
+//!
+//! The VM used to own its program and helpers directly: It was created from the raw bytes of
+//! a program or an ELF and helpers were plain functions registered on the VM itself. This module
+//! provides those method names on top of [Executable] and [BuiltinProgram], so that such
+//! integrations can migrate step by step. New code should use [crate::vm::EbpfVm] instead.
+//!
+//! ```
+//! use solana_rbpf::{ebpf, legacy::EbpfVm};
+//!
+//! fn double(a: u64, _b: u64, _c: u64, _d: u64, _e: u64) -> u64 {
+//!     a * 2
+//! }
+//!
+//! let prog = &[
+//!     0xb7, 0x01, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, // mov64 r1, 21
+//!     0x85, 0x00, 0x00, 0x00, 0xff, 0xd3, 0xe6, 0x68, // call double
+//!     0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+//! ];
+//! let mut vm = EbpfVm::new(Some(prog)).unwrap();
+//! vm.register_helper_ex("double", double).unwrap();
+//! assert_eq!(ebpf::hash_symbol_name(b"double"), 0x68e6d3ff);
+//! assert_eq!(vm.execute_program(&mut []).unwrap(), 42);
+//! assert_eq!(vm.get_last_instruction_count(), 3);
+//! ```
+
+use crate::{
+    aligned_memory::AlignedMemory,
+    ebpf,
+    elf::Executable,
+    error::{EbpfError, ProgramResult},
+    memory_region::{MemoryMapping, MemoryRegion},
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
+    verifier::RequisiteVerifier,
+    vm::{self, get_runtime_environment_key, Config, ContextObject},
+};
+use std::sync::Arc;
+
+/// Function which can be called by the program, with the registers r1 to r5 as arguments
+pub type Helper = fn(u64, u64, u64, u64, u64) -> u64;
+
+/// Maximum number of helpers which can be registered on one [EbpfVm]
+pub const MAX_HELPERS: usize = 16;
+
+/// Instruction meter and helpers of a legacy [EbpfVm]
+#[derive(Debug, Clone)]
+pub struct LegacyContextObject {
+    /// Helpers in the order of their registration
+    helpers: Vec<Helper>,
+    /// Contains the number of remaining instructions
+    remaining: u64,
+}
+
+impl ContextObject for LegacyContextObject {
+    fn trace(&mut self, _state: [u64; 12]) {}
+
+    fn consume(&mut self, amount: u64) {
+        self.remaining = self.remaining.saturating_sub(amount);
+    }
+
+    fn get_remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+fn helper_trampolines() -> [BuiltinFunction<LegacyContextObject>; MAX_HELPERS] {
+    [
+        helper_trampoline::<0>,
+        helper_trampoline::<1>,
+        helper_trampoline::<2>,
+        helper_trampoline::<3>,
+        helper_trampoline::<4>,
+        helper_trampoline::<5>,
+        helper_trampoline::<6>,
+        helper_trampoline::<7>,
+        helper_trampoline::<8>,
+        helper_trampoline::<9>,
+        helper_trampoline::<10>,
+        helper_trampoline::<11>,
+        helper_trampoline::<12>,
+        helper_trampoline::<13>,
+        helper_trampoline::<14>,
+        helper_trampoline::<15>,
+    ]
+}
+
+/// Calls the helper which was registered in `SLOT`
+fn helper_trampoline<const SLOT: usize>(
+    vm_pointer: *mut vm::EbpfVm<LegacyContextObject>,
+    arg_a: u64,
+    arg_b: u64,
+    arg_c: u64,
+    arg_d: u64,
+    arg_e: u64,
+) {
+    let vm = unsafe {
+        &mut *(vm_pointer
+            .cast::<u64>()
+            .offset((get_runtime_environment_key() as isize).wrapping_neg())
+            .cast::<vm::EbpfVm<LegacyContextObject>>())
+    };
+    let enable_instruction_meter = vm.loader.get_config().enable_instruction_meter;
+    if enable_instruction_meter {
+        vm.context_object_pointer.consume(
+            vm.previous_instruction_meter
+                .saturating_sub(vm.due_insn_count),
+        );
+    }
+    let helper = vm.context_object_pointer.helpers[SLOT];
+    vm.program_result = ProgramResult::Ok(helper(arg_a, arg_b, arg_c, arg_d, arg_e));
+    if enable_instruction_meter {
+        vm.previous_instruction_meter = vm.context_object_pointer.get_remaining();
+    }
+}
+
+/// Bytes the program is loaded from
+enum LegacyProgram {
+    /// Raw instructions, see [EbpfVm::set_program]
+    Text(Vec<u8>),
+    /// ELF file, see [EbpfVm::set_elf]
+    Elf(Vec<u8>),
+}
+
+/// Virtual machine with the API of older versions of this crate
+pub struct EbpfVm {
+    /// Configuration of the loader
+    config: Config,
+    /// Keys, names and implementations of the registered helpers
+    helpers: Vec<(u32, Vec<u8>, Helper)>,
+    /// The program as it was set
+    program: Option<LegacyProgram>,
+    /// Verified executable of the program with the current helpers
+    executable: Option<Executable<LegacyContextObject>>,
+    /// Instruction budget of every execution
+    max_instruction_count: u64,
+    /// Number of instructions executed by the last execution
+    last_instruction_count: u64,
+}
+
+impl EbpfVm {
+    /// Creates a VM, optionally with raw instructions to execute
+    pub fn new(prog: Option<&[u8]>) -> Result<Self, EbpfError> {
+        Self::new_with_config(prog, Config::default())
+    }
+
+    /// Creates a VM with the given configuration
+    pub fn new_with_config(prog: Option<&[u8]>, config: Config) -> Result<Self, EbpfError> {
+        let mut vm = Self {
+            config,
+            helpers: Vec::new(),
+            program: None,
+            executable: None,
+            max_instruction_count: u64::MAX,
+            last_instruction_count: 0,
+        };
+        if let Some(prog) = prog {
+            vm.set_program(prog)?;
+        }
+        Ok(vm)
+    }
+
+    /// Loads raw instructions, which use SBPFv1
+    pub fn set_program(&mut self, prog: &[u8]) -> Result<(), EbpfError> {
+        self.program = Some(LegacyProgram::Text(prog.to_vec()));
+        self.reload()
+    }
+
+    /// Loads an ELF file
+    pub fn set_elf(&mut self, elf_bytes: &[u8]) -> Result<(), EbpfError> {
+        self.program = Some(LegacyProgram::Elf(elf_bytes.to_vec()));
+        self.reload()
+    }
+
+    /// Registers a helper under the given key, which `call imm` refers to
+    pub fn register_helper(&mut self, key: u32, function: Helper) -> Result<(), EbpfError> {
+        self.register_helper_with_name(key, format!("helper_{key:#x}").into_bytes(), function)
+    }
+
+    /// Registers a helper under the hash of its name, so that ELF files can refer to it
+    pub fn register_helper_ex(&mut self, name: &str, function: Helper) -> Result<(), EbpfError> {
+        let name = name.as_bytes();
+        self.register_helper_with_name(ebpf::hash_symbol_name(name), name.to_vec(), function)
+    }
+
+    fn register_helper_with_name(
+        &mut self,
+        key: u32,
+        name: Vec<u8>,
+        function: Helper,
+    ) -> Result<(), EbpfError> {
+        if let Some(helper) = self.helpers.iter_mut().find(|helper| helper.0 == key) {
+            helper.2 = function;
+            return Ok(());
+        }
+        if self.helpers.len() >= MAX_HELPERS {
+            return Err(EbpfError::TooManyHelpers(MAX_HELPERS));
+        }
+        self.helpers.push((key, name, function));
+        self.reload()
+    }
+
+    /// Limits the number of instructions every execution may run
+    pub fn set_max_instruction_count(&mut self, count: u64) {
+        self.max_instruction_count = count;
+    }
+
+    /// Number of instructions executed by the last execution
+    pub fn get_last_instruction_count(&self) -> u64 {
+        self.last_instruction_count
+    }
+
+    /// Compiles the program to x86-64 machine code
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn jit_compile(&mut self) -> Result<(), EbpfError> {
+        self.executable
+            .as_mut()
+            .ok_or(EbpfError::NoProgram)?
+            .jit_compile()
+    }
+
+    /// Interprets the program with `mem` as its writable input, which r1 points to
+    pub fn execute_program(&mut self, mem: &mut [u8]) -> Result<u64, EbpfError> {
+        self.execute(mem, true)
+    }
+
+    /// Runs the machine code of `jit_compile()` with `mem` as its writable input
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub fn execute_program_jit(&mut self, mem: &mut [u8]) -> Result<u64, EbpfError> {
+        self.execute(mem, false)
+    }
+
+    /// Builds and verifies the executable of the current program and helpers
+    fn reload(&mut self) -> Result<(), EbpfError> {
+        self.executable = None;
+        let Some(program) = &self.program else {
+            return Ok(());
+        };
+        let mut function_registry = FunctionRegistry::default();
+        for ((key, name, _function), trampoline) in self.helpers.iter().zip(helper_trampolines()) {
+            function_registry.register_function(*key, name.as_slice(), trampoline)?;
+        }
+        let loader = Arc::new(BuiltinProgram::new_loader(self.config, function_registry));
+        let executable = match program {
+            LegacyProgram::Text(prog) => Executable::from_text_bytes(
+                prog,
+                loader,
+                SBPFVersion::V1,
+                FunctionRegistry::default(),
+            )?,
+            LegacyProgram::Elf(elf_bytes) => Executable::from_elf(elf_bytes, loader)?,
+        };
+        executable.verify::<RequisiteVerifier>()?;
+        self.executable = Some(executable);
+        Ok(())
+    }
+
+    fn execute(&mut self, mem: &mut [u8], interpreted: bool) -> Result<u64, EbpfError> {
+        let executable = self.executable.as_ref().ok_or(EbpfError::NoProgram)?;
+        let config = executable.get_config();
+        let sbpf_version = executable.get_sbpf_version();
+        let mut stack = AlignedMemory::<{ ebpf::HOST_ALIGN }>::zero_filled(config.stack_size());
+        let stack_len = stack.len();
+        let regions = vec![
+            executable.get_ro_region(),
+            MemoryRegion::new_stack(stack.as_slice_mut(), config, sbpf_version),
+            MemoryRegion::new_writable(&mut [], ebpf::MM_HEAP_START),
+            MemoryRegion::new_writable(mem, ebpf::MM_INPUT_START),
+        ];
+        let memory_mapping = MemoryMapping::new(regions, config, sbpf_version)?;
+        let mut context_object = LegacyContextObject {
+            helpers: self
+                .helpers
+                .iter()
+                .map(|(_key, _name, function)| *function)
+                .collect(),
+            remaining: self.max_instruction_count,
+        };
+        let mut vm = vm::EbpfVm::new(executable, &mut context_object, memory_mapping, stack_len)?;
+        let (instruction_count, result) = vm.execute_program(executable, interpreted);
+        self.last_instruction_count = instruction_count;
+        result.into()
+    }
+}
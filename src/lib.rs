@@ -10,6 +10,9 @@
 // copied, modified, or distributed except according to those terms.
 
 //! Virtual machine and JIT compiler for eBPF programs.
+//!
+//! The stable API is re-exported in [prelude], newer interfaces which may still change are
+//! collected in `unstable` (requires the `unstable` feature).
 #![warn(missing_docs)]
 #![doc(
     html_logo_url = "https://raw.githubusercontent.com/qmonnet/rbpf/master/misc/rbpf.png",
@@ -28,6 +31,7 @@ extern crate thiserror;
 pub mod aligned_memory;
 mod asm_parser;
 pub mod assembler;
+pub mod debug_line;
 #[cfg(feature = "debugger")]
 pub mod debugger;
 pub mod disassembler;
@@ -36,16 +40,40 @@ pub mod elf;
 pub mod elf_parser;
 pub mod error;
 pub mod fuzz;
+#[cfg(feature = "unstable")]
+pub mod guest_abi;
 pub mod insn_builder;
 pub mod interpreter;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
 mod jit;
+#[cfg(all(
+    feature = "unstable",
+    feature = "jit",
+    not(target_os = "windows"),
+    target_arch = "x86_64"
+))]
+pub mod jit_cache;
+#[cfg(feature = "legacy-api")]
+pub mod legacy;
+#[cfg(all(feature = "mmap", not(target_os = "windows")))]
+mod mapped_file;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
 mod memory_management;
 pub mod memory_region;
+pub mod prelude;
 pub mod program;
+#[cfg(feature = "unstable")]
+pub mod recorder;
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+mod section_allocator;
 pub mod static_analysis;
 pub mod syscalls;
+#[cfg(feature = "unstable")]
+pub mod unstable;
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+mod unwind;
+#[cfg(feature = "unstable")]
+pub mod verification_cache;
 pub mod verifier;
 pub mod vm;
 #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
@@ -0,0 +1,80 @@
+//! Read-only and copy-on-write memory mappings of files
+
+extern crate libc;
+
+use std::{convert::TryFrom, fs::File, io, os::unix::io::AsRawFd, ptr, slice};
+
+/// Private memory mapping of a whole file
+///
+/// Writes only go to private copies of the touched pages and never reach the file.
+#[derive(Debug)]
+pub(crate) struct MappedFile {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is owned exclusively and only mutated through &mut self
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Maps the file, `writable` makes the mapping copy-on-write
+    ///
+    /// # Safety
+    ///
+    /// The file must neither be modified nor truncated while the mapping exists, as the mapped
+    /// bytes are handed out as slices.
+    pub unsafe fn new(file: &File, writable: bool) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty file"));
+        }
+        let protection = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            protection,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast::<u8>(),
+            len,
+        })
+    }
+
+    /// Makes the mapping read-only
+    pub fn protect(&mut self) -> io::Result<()> {
+        if unsafe { libc::mprotect(self.ptr.cast(), self.len, libc::PROT_READ) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Mapped bytes
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Mapped bytes, only valid for writable mappings which were not protected
+    pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
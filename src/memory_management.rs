@@ -116,6 +116,75 @@ pub unsafe fn allocate_pages(size_in_bytes: usize) -> Result<*mut u8, EbpfError>
     Ok(raw.cast::<u8>())
 }
 
+/// Maps the same memory twice, once read-only and once writable
+///
+/// The read-only mapping can later be made executable, so that no page is ever writable and
+/// executable at the same time. Returns the read-only mapping first.
+#[cfg(not(target_os = "windows"))]
+pub unsafe fn allocate_dual_mapped_pages(
+    size_in_bytes: usize,
+) -> Result<(*mut u8, *mut u8), EbpfError> {
+    #[cfg(target_os = "linux")]
+    {
+        let fd = libc::memfd_create(b"solana_rbpf_jit\0".as_ptr().cast(), libc::MFD_CLOEXEC);
+        if fd < 0 {
+            return Err(EbpfError::LibcInvocationFailed(
+                "memfd_create",
+                Vec::new(),
+                *libc::__errno_location(),
+            ));
+        }
+        // The mappings keep the memory alive after the file descriptor is closed
+        let result = map_shared_pages_twice(fd, size_in_bytes);
+        libc::close(fd);
+        result
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = size_in_bytes;
+        Err(EbpfError::LibcInvocationFailed(
+            "memfd_create",
+            Vec::new(),
+            libc::ENOSYS,
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn map_shared_pages_twice(
+    fd: libc::c_int,
+    size_in_bytes: usize,
+) -> Result<(*mut u8, *mut u8), EbpfError> {
+    libc_error_guard!(ftruncate, fd, size_in_bytes as libc::off_t);
+    let read_only = map_shared_pages(fd, size_in_bytes, libc::PROT_READ)?;
+    match map_shared_pages(fd, size_in_bytes, libc::PROT_READ | libc::PROT_WRITE) {
+        Ok(writable) => Ok((read_only, writable)),
+        Err(error) => {
+            let _ = free_pages(read_only, size_in_bytes);
+            Err(error)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn map_shared_pages(
+    fd: libc::c_int,
+    size_in_bytes: usize,
+    protection: libc::c_int,
+) -> Result<*mut u8, EbpfError> {
+    let mut raw: *mut c_void = std::ptr::null_mut();
+    libc_error_guard!(
+        mmap,
+        &mut raw,
+        size_in_bytes,
+        protection,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+    Ok(raw.cast::<u8>())
+}
+
 pub unsafe fn free_pages(raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError> {
     #[cfg(not(target_os = "windows"))]
     libc_error_guard!(munmap, raw.cast::<c_void>(), size_in_bytes);
@@ -165,3 +234,35 @@ pub unsafe fn protect_pages(
     }
     Ok(())
 }
+
+/// Makes pages readable and writable again, e.g. before they are handed back to an allocator
+///
+/// # Safety
+///
+/// `raw` has to be page aligned and point to `size_in_bytes` bytes of mapped memory which the
+/// caller owns, e.g. from `allocate_pages()`. No code may execute in these pages anymore, as they
+/// are no longer executable afterwards.
+pub unsafe fn unprotect_pages(raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError> {
+    #[cfg(not(target_os = "windows"))]
+    {
+        libc_error_guard!(
+            mprotect,
+            raw.cast::<c_void>(),
+            size_in_bytes,
+            libc::PROT_READ | libc::PROT_WRITE,
+        );
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let mut old: minwindef::DWORD = 0;
+        let ptr_old: *mut minwindef::DWORD = &mut old;
+        winapi_error_guard!(
+            VirtualProtect,
+            raw.cast::<c_void>(),
+            size_in_bytes,
+            winnt::PAGE_READWRITE,
+            ptr_old,
+        );
+    }
+    Ok(())
+}
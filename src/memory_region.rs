@@ -9,10 +9,12 @@ use crate::{
 };
 use std::{
     array,
-    cell::{Cell, UnsafeCell},
+    cell::{Cell, RefCell, UnsafeCell},
+    collections::BTreeMap,
     fmt, mem,
     ops::Range,
     ptr::{self, copy_nonoverlapping},
+    sync::Arc,
 };
 
 /* Explaination of the Gapped Memory
@@ -33,7 +35,10 @@ use std::{
 */
 
 /// The state of a memory region.
+///
+/// The layout is fixed as the JIT inspects the discriminant of a region directly.
 #[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[repr(u64)]
 pub enum MemoryState {
     /// The memory region is readable
     #[default]
@@ -48,9 +53,40 @@ pub enum MemoryState {
 /// Callback executed when a CoW memory region is written to
 pub type MemoryCowCallback = Box<dyn Fn(u64) -> Result<u64, ()>>;
 
+/// Callback executed when an access (`vm_addr`, `len`) hits no memory region
+///
+/// It can return a new region containing `vm_addr`, which is then inserted and the access retried.
+pub type AccessViolationHandler = Box<dyn Fn(AccessType, u64, u64) -> Option<MemoryRegion>>;
+
+/// Read-only host memory which concurrently executing VMs can map without copying it
+///
+/// Regions only alias the host memory, so the rules for sharing it are:
+/// - The bytes are immutable once shared, there is no way to get mutable access to them.
+/// - Every region created by [MemoryRegion::new_shared] is readonly, so neither a program nor
+///   a syscall can write through it. It must not be made writable by changing its state.
+/// - The memory lives as long as the last handle, so the host has to keep one for as long as
+///   any mapping uses a region created from it, e.g. by storing a clone next to the `EbpfVm`.
+///
+/// Handles are cheap to clone and can be sent to other threads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedMemory(Arc<[u8]>);
+
+impl SharedMemory {
+    /// Takes ownership of the bytes, e.g. a copy of the rodata or program image
+    pub fn new(bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self(bytes.into())
+    }
+
+    /// Returns the shared bytes
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 /// Memory region for bounds checking and address translation
 #[derive(Default, Eq, PartialEq)]
-#[repr(C, align(32))]
+// The JIT indexes the regions with a shift, so the size has to stay a power of two
+#[repr(C, align(64))]
 pub struct MemoryRegion {
     /// start host address
     pub host_addr: Cell<u64>,
@@ -62,8 +98,30 @@ pub struct MemoryRegion {
     pub len: u64,
     /// Size of regular gaps as bit shift (63 means this region is continuous)
     pub vm_gap_shift: u8,
+    /// Size of the gap following every chunk of `vm_chunk_len` bytes
+    pub vm_chunk_gap: u32,
     /// Whether the region is readonly, writable or must be copied before writing
     pub state: Cell<MemoryState>,
+    /// Bytes of data per chunk (0 means this region is not chunked)
+    pub vm_chunk_len: u64,
+    /// Name of the region in access violations, see `with_label()`
+    pub label: Option<&'static str>,
+    /// Parts of the region which stores are restricted to, see `with_writable_ranges()`
+    ///
+    /// Boxed, so that the JIT can tell regions without any apart by a null pointer.
+    pub writable_ranges: Option<Box<WritableRanges>>,
+}
+
+/// Sorted and disjoint offsets from the `vm_addr` of a region, see
+/// [MemoryRegion::with_writable_ranges]
+#[derive(Debug, PartialEq, Eq)]
+pub struct WritableRanges(Vec<Range<u64>>);
+
+impl WritableRanges {
+    /// Returns the ranges in ascending order
+    pub fn as_slice(&self) -> &[Range<u64>] {
+        &self.0
+    }
 }
 
 impl MemoryRegion {
@@ -83,7 +141,11 @@ impl MemoryRegion {
             vm_addr_end,
             len: slice.len() as u64,
             vm_gap_shift,
+            vm_chunk_gap: 0,
             state: Cell::new(state),
+            vm_chunk_len: 0,
+            label: None,
+            writable_ranges: None,
         }
     }
 
@@ -107,6 +169,13 @@ impl MemoryRegion {
         Self::new(&*slice, vm_addr, 0, MemoryState::Writable)
     }
 
+    /// Creates a new readonly MemoryRegion which aliases shared memory
+    ///
+    /// See [SharedMemory] for the aliasing rules.
+    pub fn new_shared(memory: &SharedMemory, vm_addr: u64) -> Self {
+        Self::new(memory.as_slice(), vm_addr, 0, MemoryState::Readable)
+    }
+
     /// Creates a new copy on write MemoryRegion.
     ///
     /// The region is made writable
@@ -119,6 +188,109 @@ impl MemoryRegion {
         Self::new(&*slice, vm_addr, vm_gap_size, MemoryState::Writable)
     }
 
+    /// Creates the writable MemoryRegion of the stack at `MM_STACK_START`
+    ///
+    /// Every fixed stack frame is followed by a gap of `Config::stack_frame_gap_size()` bytes,
+    /// which never exceeds `u32::MAX`. A gap as large as a frame is translated by the JIT inline,
+    /// other sizes use a chunked region.
+    pub fn new_stack(slice: &mut [u8], config: &Config, sbpf_version: &SBPFVersion) -> Self {
+        let gap_size = config.stack_frame_gap_size(sbpf_version);
+        let frame_size = config.stack_frame_size as u64;
+        if gap_size == 0 || (gap_size == frame_size && frame_size.is_power_of_two()) {
+            Self::new_writable_gapped(slice, ebpf::MM_STACK_START, gap_size)
+        } else {
+            Self::new_writable_chunked(slice, ebpf::MM_STACK_START, frame_size, gap_size as u32)
+        }
+    }
+
+    /// Creates a new writable chunked MemoryRegion from a mutable slice
+    ///
+    /// The slice is split into chunks of `chunk_len` bytes, which are spread out in the virtual
+    /// address space by inserting a gap of `gap_len` bytes after each of them. This is the layout
+    /// of the serialized input, so it can be mapped as one region instead of one per chunk.
+    pub fn new_writable_chunked(
+        slice: &mut [u8],
+        vm_addr: u64,
+        chunk_len: u64,
+        gap_len: u32,
+    ) -> Self {
+        debug_assert!(chunk_len > 0);
+        let mut region = Self::new(&*slice, vm_addr, 0, MemoryState::Writable);
+        region.vm_chunk_gap = gap_len;
+        region.vm_chunk_len = chunk_len;
+        if let Some(last_offset) = region.len.checked_sub(1) {
+            let chunks = last_offset.checked_div(chunk_len).unwrap_or(0);
+            let last_chunk_end = last_offset
+                .checked_rem(chunk_len)
+                .unwrap_or(0)
+                .saturating_add(1);
+            region.vm_addr_end = vm_addr
+                .saturating_add(chunks.saturating_mul(region.vm_chunk_period()))
+                .saturating_add(last_chunk_end);
+        }
+        region
+    }
+
+    /// Names the region, e.g. "account #3 data", instead of the section it is in
+    ///
+    /// Access violations in the region and past its end (up to the next region) carry the label.
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Restricts stores to the given offsets from `vm_addr`, e.g. to keep the header of an
+    /// otherwise writable buffer readonly
+    ///
+    /// Overlapping and adjacent ranges are merged. Stores reaching outside of them fail with an
+    /// access violation, and so do translations for stores through `MemoryMapping::map()` and
+    /// `MemoryMapping::region()`. Only matters if the region is writable or copy on write.
+    pub fn with_writable_ranges(mut self, ranges: impl IntoIterator<Item = Range<u64>>) -> Self {
+        let mut ranges = ranges
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.writable_ranges = Some(Box::new(WritableRanges(merged)));
+        self
+    }
+
+    /// Whether the `writable_ranges` allow a store of `len` bytes at `vm_addr`
+    ///
+    /// Only the part of the store inside of the region is checked, the rest is up to the next
+    /// region.
+    pub fn is_writable_range(&self, vm_addr: u64, len: u64) -> bool {
+        let Some(writable_ranges) = &self.writable_ranges else {
+            return true;
+        };
+        let start = vm_addr.saturating_sub(self.vm_addr);
+        let end = vm_addr
+            .saturating_add(len)
+            .min(self.vm_addr_end)
+            .saturating_sub(self.vm_addr);
+        writable_ranges
+            .0
+            .iter()
+            .any(|range| range.start <= start && start < range.end && end <= range.end)
+    }
+
+    /// Returns the label or else the name of the section the region is in
+    pub fn name(&self) -> &'static str {
+        self.label.unwrap_or_else(|| region_name(self.vm_addr))
+    }
+
+    /// Virtual distance between the starts of two consecutive chunks
+    fn vm_chunk_period(&self) -> u64 {
+        self.vm_chunk_len.saturating_add(self.vm_chunk_gap as u64)
+    }
+
     /// Convert a virtual machine address into a host address
     pub fn vm_to_host(&self, vm_addr: u64, len: u64) -> ProgramResult {
         // This can happen if a region starts at an offset from the base region
@@ -129,6 +301,23 @@ impl MemoryRegion {
         }
 
         let begin_offset = vm_addr.saturating_sub(self.vm_addr);
+        if self.vm_chunk_len != 0 {
+            let period = self.vm_chunk_period();
+            let chunk_offset = begin_offset.checked_rem(period).unwrap_or(0);
+            let host_offset = begin_offset
+                .checked_div(period)
+                .unwrap_or(0)
+                .saturating_mul(self.vm_chunk_len)
+                .saturating_add(chunk_offset);
+            // Accesses must neither start in a gap nor reach into the next one
+            if chunk_offset < self.vm_chunk_len
+                && chunk_offset.saturating_add(len) <= self.vm_chunk_len
+                && host_offset.saturating_add(len) <= self.len
+            {
+                return ProgramResult::Ok(self.host_addr.get().saturating_add(host_offset));
+            }
+            return ProgramResult::Err(EbpfError::InvalidVirtualAddress(vm_addr));
+        }
         let is_in_gap = (begin_offset
             .checked_shr(self.vm_gap_shift as u32)
             .unwrap_or(0)
@@ -156,7 +345,11 @@ impl fmt::Debug for MemoryRegion {
             self.vm_addr,
             self.vm_addr_end,
             self.len
-        )
+        )?;
+        if let Some(label) = &self.label {
+            write!(f, ", label: {:?}", label)?;
+        }
+        Ok(())
     }
 }
 impl std::cmp::PartialOrd for MemoryRegion {
@@ -179,6 +372,400 @@ pub enum AccessType {
     Store,
 }
 
+/// Accesses of one memory region, see [MemoryMapping::region_statistics]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RegionAccessStatistics {
+    /// Number of loads
+    pub loads: u64,
+    /// Number of stores
+    pub stores: u64,
+    /// Number of bytes loaded or stored
+    pub bytes: u64,
+}
+
+/// Counts the accesses per region if `Config::enable_region_statistics` is set
+#[derive(Debug, Default)]
+struct RegionStatistics(Option<RefCell<BTreeMap<u64, RegionAccessStatistics>>>);
+
+impl RegionStatistics {
+    fn new(config: &Config) -> Self {
+        Self(
+            config
+                .enable_region_statistics
+                .then(|| RefCell::new(BTreeMap::new())),
+        )
+    }
+
+    #[inline]
+    fn record(&self, region: &MemoryRegion, access_type: AccessType, len: u64) {
+        if let Some(statistics) = &self.0 {
+            let mut statistics = statistics.borrow_mut();
+            let statistics = statistics.entry(region.vm_addr).or_default();
+            match access_type {
+                AccessType::Load => statistics.loads = statistics.loads.saturating_add(1),
+                AccessType::Store => statistics.stores = statistics.stores.saturating_add(1),
+            }
+            statistics.bytes = statistics.bytes.saturating_add(len);
+        }
+    }
+
+    fn get(&self) -> BTreeMap<u64, RegionAccessStatistics> {
+        self.0
+            .as_ref()
+            .map(|statistics| statistics.borrow().clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Bytes of the stack and heap region which were written, one bit each, by region `vm_addr`
+///
+/// See [MemoryMapping::set_initialization_tracking].
+#[derive(Debug, Default)]
+struct InitializedBytes(Option<RefCell<BTreeMap<u64, Vec<u64>>>>);
+
+impl InitializedBytes {
+    fn is_tracked(region_vm_addr: u64) -> bool {
+        region_vm_addr == ebpf::MM_STACK_START || region_vm_addr == ebpf::MM_HEAP_START
+    }
+
+    #[inline]
+    fn record(&self, region: &MemoryRegion, access_type: AccessType, vm_addr: u64, len: u64) {
+        let Some(bitmaps) = &self.0 else {
+            return;
+        };
+        if access_type != AccessType::Store || !Self::is_tracked(region.vm_addr) {
+            return;
+        }
+        let mut bitmaps = bitmaps.borrow_mut();
+        let bitmap = bitmaps.entry(region.vm_addr).or_insert_with(|| {
+            let bits = region.vm_addr_end.saturating_sub(region.vm_addr);
+            vec![0; bits.div_ceil(64) as usize]
+        });
+        let start = vm_addr.saturating_sub(region.vm_addr);
+        for offset in start..start.saturating_add(len) {
+            if let Some(word) = bitmap.get_mut((offset / 64) as usize) {
+                *word |= 1u64.wrapping_shl((offset % 64) as u32);
+            }
+        }
+    }
+
+    fn is_initialized(&self, vm_addr: u64, len: u64) -> bool {
+        let Some(bitmaps) = &self.0 else {
+            return true;
+        };
+        let region_vm_addr = vm_addr
+            .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
+            .and_then(|index| index.checked_shl(ebpf::VIRTUAL_ADDRESS_BITS as u32))
+            .unwrap_or(0);
+        if !Self::is_tracked(region_vm_addr) {
+            return true;
+        }
+        let bitmaps = bitmaps.borrow();
+        let Some(bitmap) = bitmaps.get(&region_vm_addr) else {
+            return false;
+        };
+        let start = vm_addr.saturating_sub(region_vm_addr);
+        (start..start.saturating_add(len)).all(|offset| {
+            bitmap
+                .get((offset / 64) as usize)
+                .is_some_and(|word| word & 1u64.wrapping_shl((offset % 64) as u32) != 0)
+        })
+    }
+
+    fn clear(&self) {
+        if let Some(bitmaps) = &self.0 {
+            bitmaps.borrow_mut().clear();
+        }
+    }
+}
+
+/// Bytes of a region which stores changed, see [MemoryMapping::dirty_runs]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyRun {
+    /// `vm_addr` of the region
+    pub region: u64,
+    /// Offset of the first changed byte from the start of the host memory of the region
+    pub offset: u64,
+    /// Bytes before the first store
+    pub old: Vec<u8>,
+    /// Bytes now
+    pub new: Vec<u8>,
+}
+
+/// Saved block contents by region `vm_addr` and block index
+type SavedBlocks = BTreeMap<(u64, u64), Box<[u8]>>;
+
+/// Contents of blocks of regions before their first store
+///
+/// See [MemoryMapping::set_dirty_tracking].
+#[derive(Debug, Default)]
+struct DirtyBlocks(Option<RefCell<SavedBlocks>>);
+
+impl DirtyBlocks {
+    /// Bytes saved at once, so that a store rarely has to save anything
+    const BLOCK_SIZE: u64 = 64;
+
+    #[inline]
+    fn record(&self, region: &MemoryRegion, access_type: AccessType, host_addr: u64, len: u64) {
+        let Some(blocks) = &self.0 else {
+            return;
+        };
+        if access_type != AccessType::Store {
+            return;
+        }
+        let mut blocks = blocks.borrow_mut();
+        let start = host_addr.saturating_sub(region.host_addr.get());
+        let end = start.saturating_add(len).min(region.len);
+        let first_block = start / Self::BLOCK_SIZE;
+        let last_block = end.saturating_sub(1) / Self::BLOCK_SIZE;
+        for block in first_block..=last_block {
+            blocks.entry((region.vm_addr, block)).or_insert_with(|| {
+                let block_start = block.saturating_mul(Self::BLOCK_SIZE);
+                let block_end = block_start.saturating_add(Self::BLOCK_SIZE).min(region.len);
+                // Safety:
+                // The block lies within the host memory of the region
+                unsafe {
+                    std::slice::from_raw_parts(
+                        region.host_addr.get().saturating_add(block_start) as *const u8,
+                        block_end.saturating_sub(block_start) as usize,
+                    )
+                }
+                .into()
+            });
+        }
+    }
+
+    fn runs<'a>(&self, find_region: impl Fn(u64) -> Option<&'a MemoryRegion>) -> Vec<DirtyRun> {
+        let mut runs: Vec<DirtyRun> = Vec::new();
+        let Some(blocks) = &self.0 else {
+            return runs;
+        };
+        for ((region_vm_addr, block), old) in blocks.borrow().iter() {
+            let Some(region) = find_region(*region_vm_addr) else {
+                continue;
+            };
+            let block_start = block.saturating_mul(Self::BLOCK_SIZE);
+            // Safety:
+            // The block was saved from the host memory of the same region
+            let new = unsafe {
+                std::slice::from_raw_parts(
+                    region.host_addr.get().saturating_add(block_start) as *const u8,
+                    old.len(),
+                )
+            };
+            for (offset, (old, new)) in (block_start..).zip(old.iter().zip(new.iter())) {
+                if old == new {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run)
+                        if run.region == *region_vm_addr
+                            && run.offset.saturating_add(run.old.len() as u64) == offset =>
+                    {
+                        run.old.push(*old);
+                        run.new.push(*new);
+                    }
+                    _ => runs.push(DirtyRun {
+                        region: *region_vm_addr,
+                        offset,
+                        old: vec![*old],
+                        new: vec![*new],
+                    }),
+                }
+            }
+        }
+        runs
+    }
+
+    fn clear(&self) {
+        if let Some(blocks) = &self.0 {
+            blocks.borrow_mut().clear();
+        }
+    }
+}
+
+/// Kind of accesses a watchpoint stops at, see [MemoryMapping::add_watchpoint]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WatchpointKind {
+    /// Loads
+    Read,
+    /// Stores
+    Write,
+    /// Loads and stores
+    Any,
+}
+
+impl WatchpointKind {
+    fn matches(self, access_type: AccessType) -> bool {
+        match self {
+            WatchpointKind::Read => access_type == AccessType::Load,
+            WatchpointKind::Write => access_type == AccessType::Store,
+            WatchpointKind::Any => true,
+        }
+    }
+}
+
+/// Watched address ranges
+#[derive(Debug)]
+struct Watchpoints {
+    ranges: Vec<(Range<u64>, WatchpointKind)>,
+    enabled: bool,
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self {
+            ranges: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl Watchpoints {
+    fn is_active(&self) -> bool {
+        self.enabled && !self.ranges.is_empty()
+    }
+
+    fn add(&mut self, vm_addr: u64, len: u64, kind: WatchpointKind) -> bool {
+        if len == 0 {
+            return false;
+        }
+        self.ranges
+            .push((vm_addr..vm_addr.saturating_add(len), kind));
+        true
+    }
+
+    fn remove(&mut self, vm_addr: u64, len: u64, kind: WatchpointKind) -> bool {
+        let range = vm_addr..vm_addr.saturating_add(len);
+        match self
+            .ranges
+            .iter()
+            .position(|watchpoint| *watchpoint == (range.clone(), kind))
+        {
+            Some(index) => {
+                self.ranges.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn check(&self, access_type: AccessType, vm_addr: u64, len: u64) -> Option<EbpfError> {
+        if !self.is_active() {
+            return None;
+        }
+        let end = vm_addr.saturating_add(len);
+        self.ranges
+            .iter()
+            .any(|(range, kind)| {
+                kind.matches(access_type) && range.start < end && vm_addr < range.end
+            })
+            .then(|| EbpfError::WatchpointHit(access_type, vm_addr, len))
+    }
+}
+
+/// Regions inserted by an [AccessViolationHandler]
+///
+/// They are boxed and never removed, so references to them stay valid while new ones are added.
+#[derive(Default)]
+struct MaterializedRegions {
+    /// Inserts regions on demand
+    handler: Option<AccessViolationHandler>,
+    /// Regions returned by the handler so far
+    regions: UnsafeCell<Vec<*mut MemoryRegion>>,
+}
+
+impl fmt::Debug for MaterializedRegions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Safety:
+        // The regions are only added to in find(), which does not invoke this.
+        let regions = unsafe { &*self.regions.get() };
+        f.debug_struct("MaterializedRegions")
+            .field(
+                "handler",
+                &self
+                    .handler
+                    .as_ref()
+                    .map(|handler| format!("Some({:p})", &handler))
+                    .unwrap_or_else(|| "None".to_string()),
+            )
+            .field(
+                "regions",
+                &regions
+                    .iter()
+                    .map(|region| unsafe { &**region })
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Drop for MaterializedRegions {
+    fn drop(&mut self) {
+        for region in self.regions.get_mut().drain(..) {
+            // Safety:
+            // Every pointer was created by Box::into_raw() in find()
+            drop(unsafe { Box::from_raw(region) });
+        }
+    }
+}
+
+impl MaterializedRegions {
+    /// Finds the materialized region containing `vm_addr` without asking the handler
+    fn find_existing(&self, vm_addr: u64) -> Option<&MemoryRegion> {
+        // Safety:
+        // The list is only mutated in find(), which does not invoke this.
+        let regions = unsafe { &*self.regions.get() };
+        regions
+            .iter()
+            .map(|region| unsafe { &**region })
+            .find(|region| (region.vm_addr..region.vm_addr_end).contains(&vm_addr))
+    }
+
+    /// Finds the materialized region containing `vm_addr` or asks the handler for a new one
+    ///
+    /// Regions which do not contain `vm_addr` or overlap any other region are not inserted.
+    fn find(
+        &self,
+        mapped_regions: &[MemoryRegion],
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Option<&MemoryRegion> {
+        let handler = self.handler.as_ref()?;
+        let contains = |region: &MemoryRegion, vm_addr: u64| {
+            (region.vm_addr..region.vm_addr_end).contains(&vm_addr)
+        };
+        // Safety:
+        // The mappings are !Sync and this is the only place which creates a reference to the list,
+        // so the reference is unique. The handler can not reach the mapping, as it is owned by it.
+        let regions = unsafe { &mut *self.regions.get() };
+        if let Some(region) = regions
+            .iter()
+            .map(|region| unsafe { &**region })
+            .find(|region| contains(region, vm_addr))
+        {
+            return Some(region);
+        }
+        let region = handler(access_type, vm_addr, len)?;
+        let overlaps = |other: &MemoryRegion| {
+            region.vm_addr < other.vm_addr_end && other.vm_addr < region.vm_addr_end
+        };
+        if !contains(&region, vm_addr)
+            || mapped_regions.iter().any(overlaps)
+            || regions.iter().any(|other| overlaps(unsafe { &**other }))
+        {
+            return None;
+        }
+        let region = Box::into_raw(Box::new(region));
+        regions.push(region);
+        // Safety:
+        // The region is only freed when self is dropped
+        Some(unsafe { &*region })
+    }
+}
+
 /// Memory mapping based on eytzinger search.
 pub struct UnalignedMemoryMapping<'a> {
     /// Mapped memory regions
@@ -193,6 +780,16 @@ pub struct UnalignedMemoryMapping<'a> {
     sbpf_version: &'a SBPFVersion,
     /// CoW callback
     cow_cb: Option<MemoryCowCallback>,
+    /// Regions inserted on demand
+    materialized_regions: MaterializedRegions,
+    /// Accesses per region
+    statistics: RegionStatistics,
+    /// Watched address ranges
+    watchpoints: Watchpoints,
+    /// Written bytes of the stack and heap
+    initialized_bytes: InitializedBytes,
+    /// Contents of memory before its first store
+    dirty_blocks: DirtyBlocks,
 }
 
 impl<'a> fmt::Debug for UnalignedMemoryMapping<'a> {
@@ -210,6 +807,11 @@ impl<'a> fmt::Debug for UnalignedMemoryMapping<'a> {
                     .map(|cb| format!("Some({:p})", &cb))
                     .unwrap_or_else(|| "None".to_string()),
             )
+            .field("materialized_regions", &self.materialized_regions)
+            .field("statistics", &self.statistics)
+            .field("watchpoints", &self.watchpoints)
+            .field("initialized_bytes", &self.initialized_bytes)
+            .field("dirty_blocks", &self.dirty_blocks)
             .finish()
     }
 }
@@ -248,7 +850,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
         for index in 1..regions.len() {
             let first = &regions[index.saturating_sub(1)];
             let second = &regions[index];
-            if first.vm_addr_end > second.vm_addr {
+            if first.vm_addr_end > second.vm_addr || first.vm_addr == second.vm_addr {
                 return Err(EbpfError::InvalidMemoryRegion(index));
             }
         }
@@ -263,6 +865,11 @@ impl<'a> UnalignedMemoryMapping<'a> {
             config,
             sbpf_version,
             cow_cb,
+            materialized_regions: MaterializedRegions::default(),
+            statistics: RegionStatistics::new(config),
+            watchpoints: Watchpoints::default(),
+            initialized_bytes: InitializedBytes::default(),
+            dirty_blocks: DirtyBlocks::default(),
         };
         result.construct_eytzinger_order(&mut regions, 0, 0);
         Ok(result)
@@ -289,8 +896,27 @@ impl<'a> UnalignedMemoryMapping<'a> {
         Self::new_internal(regions, Some(cow_cb), config, sbpf_version)
     }
 
+    /// Finds the region containing `vm_addr`, if there is none it asks the
+    /// [AccessViolationHandler] and otherwise falls back to the preceding region
+    fn find_region(
+        &self,
+        cache: &mut MappingCache,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Option<&MemoryRegion> {
+        let region = self.find_mapped_region(cache, vm_addr);
+        match region {
+            Some(region) if vm_addr < region.vm_addr_end => Some(region),
+            _ => self
+                .materialized_regions
+                .find(&self.regions, access_type, vm_addr, len)
+                .or(region),
+        }
+    }
+
     #[allow(clippy::arithmetic_side_effects)]
-    fn find_region(&self, cache: &mut MappingCache, vm_addr: u64) -> Option<&MemoryRegion> {
+    fn find_mapped_region(&self, cache: &mut MappingCache, vm_addr: u64) -> Option<&MemoryRegion> {
         if let Some(index) = cache.find(vm_addr) {
             // Safety:
             // Cached index, we validated it before caching it. See the corresponding safety section
@@ -319,6 +945,54 @@ impl<'a> UnalignedMemoryMapping<'a> {
         }
     }
 
+    /// Checks whether `map()` succeeds without invoking the [MemoryCowCallback] or the
+    /// [AccessViolationHandler], see [check_translation]
+    fn check_map(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<bool, EbpfError> {
+        // Safety:
+        // &mut references to the mapping cache are only created internally from methods that do not
+        // invoke each other. UnalignedMemoryMapping is !Sync, so the cache reference below is
+        // guaranteed to be unique.
+        let cache = unsafe { &mut *self.cache.get() };
+        let mapped_region = self.find_mapped_region(cache, vm_addr);
+        let region = match mapped_region {
+            Some(region) if vm_addr < region.vm_addr_end => Some(region),
+            _ => self.materialized_regions.find_existing(vm_addr),
+        };
+        match region {
+            Some(region) => check_translation(region, access_type, vm_addr, len, &self.cow_cb),
+            None => self.materialized_regions.handler.as_ref().map(|_| true),
+        }
+        .ok_or_else(|| self.access_violation(access_type, vm_addr, len))
+    }
+
+    /// The error of a failing `map()`
+    fn access_violation(&self, access_type: AccessType, vm_addr: u64, len: u64) -> EbpfError {
+        // Safety:
+        // &mut references to the mapping cache are only created internally from methods that do not
+        // invoke each other. UnalignedMemoryMapping is !Sync, so the cache reference below is
+        // guaranteed to be unique.
+        let cache = unsafe { &mut *self.cache.get() };
+        let mapped_region = self.find_mapped_region(cache, vm_addr);
+        let region = match mapped_region {
+            Some(region) if vm_addr < region.vm_addr_end => Some(region),
+            _ => self.materialized_regions.find_existing(vm_addr),
+        };
+        generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            len,
+            region.or(mapped_region),
+        )
+        .unwrap_err()
+    }
+
     /// Given a list of regions translate from virtual machine to host address
     pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
         // Safety:
@@ -327,7 +1001,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // guaranteed to be unique.
         let cache = unsafe { &mut *self.cache.get() };
 
-        let region = match self.find_region(cache, vm_addr) {
+        let region = match self.find_region(cache, access_type, vm_addr, len) {
             Some(res) => res,
             None => {
                 return generate_access_violation(
@@ -336,17 +1010,32 @@ impl<'a> UnalignedMemoryMapping<'a> {
                     access_type,
                     vm_addr,
                     len,
+                    None,
                 )
             }
         };
 
-        if access_type == AccessType::Load || ensure_writable_region(region, &self.cow_cb) {
+        if access_type == AccessType::Load
+            || ensure_writable_region(region, vm_addr, len, &self.cow_cb)
+        {
             if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+                self.statistics.record(region, access_type, len);
+                self.initialized_bytes
+                    .record(region, access_type, vm_addr, len);
+                self.dirty_blocks
+                    .record(region, access_type, host_addr, len);
                 return ProgramResult::Ok(host_addr);
             }
         }
 
-        generate_access_violation(self.config, self.sbpf_version, access_type, vm_addr, len)
+        generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            len,
+            Some(region),
+        )
     }
 
     /// Loads `size_of::<T>()` bytes from the given address.
@@ -363,10 +1052,11 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // guaranteed to be unique.
         let cache = unsafe { &mut *self.cache.get() };
 
-        let mut region = match self.find_region(cache, vm_addr) {
+        let mut region = match self.find_region(cache, AccessType::Load, vm_addr, len) {
             Some(region) => {
                 if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
                     // fast path
+                    self.statistics.record(region, AccessType::Load, len);
                     return ProgramResult::Ok(unsafe {
                         ptr::read_unaligned::<T>(host_addr as *const _).into()
                     });
@@ -381,6 +1071,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
                     AccessType::Load,
                     vm_addr,
                     len,
+                    None,
                 )
             }
         };
@@ -388,6 +1079,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // slow path
         let initial_len = len;
         let initial_vm_addr = vm_addr;
+        let initial_region = region;
         let mut value = 0u64;
         let mut ptr = std::ptr::addr_of_mut!(value).cast::<u8>();
 
@@ -397,6 +1089,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
                 break;
             }
             if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, load_len) {
+                self.statistics.record(region, AccessType::Load, load_len);
                 // Safety:
                 // we debug_assert!(len <= mem::size_of::<u64>()) so we never
                 // overflow &value
@@ -409,7 +1102,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
                     return ProgramResult::Ok(value);
                 }
                 vm_addr = vm_addr.saturating_add(load_len);
-                region = match self.find_region(cache, vm_addr) {
+                region = match self.find_region(cache, AccessType::Load, vm_addr, len) {
                     Some(region) => region,
                     None => break,
                 };
@@ -424,6 +1117,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
             AccessType::Load,
             initial_vm_addr,
             initial_len,
+            Some(initial_region),
         )
     }
 
@@ -442,10 +1136,16 @@ impl<'a> UnalignedMemoryMapping<'a> {
 
         let mut src = std::ptr::addr_of!(value).cast::<u8>();
 
-        let mut region = match self.find_region(cache, vm_addr) {
-            Some(region) if ensure_writable_region(region, &self.cow_cb) => {
+        let region = self.find_region(cache, AccessType::Store, vm_addr, len);
+        let mut region = match region {
+            Some(region) if ensure_writable_region(region, vm_addr, len, &self.cow_cb) => {
                 // fast path
                 if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+                    self.statistics.record(region, AccessType::Store, len);
+                    self.initialized_bytes
+                        .record(region, AccessType::Store, vm_addr, len);
+                    self.dirty_blocks
+                        .record(region, AccessType::Store, host_addr, len);
                     // Safety:
                     // vm_to_host() succeeded so we know there's enough space to
                     // store `value`
@@ -461,6 +1161,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
                     AccessType::Store,
                     vm_addr,
                     len,
+                    region,
                 )
             }
         };
@@ -468,9 +1169,10 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // slow path
         let initial_len = len;
         let initial_vm_addr = vm_addr;
+        let initial_region = region;
 
         while len > 0 {
-            if !ensure_writable_region(region, &self.cow_cb) {
+            if !ensure_writable_region(region, vm_addr, len, &self.cow_cb) {
                 break;
             }
 
@@ -479,6 +1181,11 @@ impl<'a> UnalignedMemoryMapping<'a> {
                 break;
             }
             if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, write_len) {
+                self.statistics.record(region, AccessType::Store, write_len);
+                self.initialized_bytes
+                    .record(region, AccessType::Store, vm_addr, write_len);
+                self.dirty_blocks
+                    .record(region, AccessType::Store, host_addr, write_len);
                 // Safety:
                 // vm_to_host() succeeded so we have enough space for write_len
                 unsafe { copy_nonoverlapping(src, host_addr as *mut _, write_len as usize) };
@@ -488,7 +1195,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
                 }
                 src = unsafe { src.add(write_len as usize) };
                 vm_addr = vm_addr.saturating_add(write_len);
-                region = match self.find_region(cache, vm_addr) {
+                region = match self.find_region(cache, AccessType::Store, vm_addr, len) {
                     Some(region) => region,
                     None => break,
                 };
@@ -503,6 +1210,7 @@ impl<'a> UnalignedMemoryMapping<'a> {
             AccessType::Store,
             initial_vm_addr,
             initial_len,
+            Some(initial_region),
         )
     }
 
@@ -517,17 +1225,24 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // invoke each other. UnalignedMemoryMapping is !Sync, so the cache reference below is
         // guaranteed to be unique.
         let cache = unsafe { &mut *self.cache.get() };
-        if let Some(region) = self.find_region(cache, vm_addr) {
+        let region = self.find_region(cache, access_type, vm_addr, 0);
+        if let Some(region) = region {
             if (region.vm_addr..region.vm_addr_end).contains(&vm_addr)
-                && (access_type == AccessType::Load || ensure_writable_region(region, &self.cow_cb))
+                && (access_type == AccessType::Load
+                    || ensure_writable_region(region, vm_addr, 0, &self.cow_cb))
             {
                 return Ok(region);
             }
         }
-        Err(
-            generate_access_violation(self.config, self.sbpf_version, access_type, vm_addr, 0)
-                .unwrap_err(),
+        Err(generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            0,
+            region,
         )
+        .unwrap_err())
     }
 
     /// Returns the `MemoryRegion`s in this mapping
@@ -535,6 +1250,16 @@ impl<'a> UnalignedMemoryMapping<'a> {
         &self.regions
     }
 
+    /// Sets the handler which can insert regions when an access hits none
+    pub fn set_access_violation_handler(&mut self, handler: AccessViolationHandler) {
+        self.materialized_regions.handler = Some(handler);
+    }
+
+    /// Returns the accesses of every region touched so far, keyed by its `vm_addr`
+    pub fn region_statistics(&self) -> BTreeMap<u64, RegionAccessStatistics> {
+        self.statistics.get()
+    }
+
     /// Replaces the `MemoryRegion` at the given index
     pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
         if index >= self.regions.len() || self.regions[index].vm_addr != region.vm_addr {
@@ -557,6 +1282,16 @@ pub struct AlignedMemoryMapping<'a> {
     sbpf_version: &'a SBPFVersion,
     /// CoW callback
     cow_cb: Option<MemoryCowCallback>,
+    /// Regions inserted on demand
+    materialized_regions: MaterializedRegions,
+    /// Accesses per region
+    statistics: RegionStatistics,
+    /// Watched address ranges
+    watchpoints: Watchpoints,
+    /// Written bytes of the stack and heap
+    initialized_bytes: InitializedBytes,
+    /// Contents of memory before its first store
+    dirty_blocks: DirtyBlocks,
 }
 
 impl<'a> fmt::Debug for AlignedMemoryMapping<'a> {
@@ -572,6 +1307,11 @@ impl<'a> fmt::Debug for AlignedMemoryMapping<'a> {
                     .map(|cb| format!("Some({:p})", &cb))
                     .unwrap_or_else(|| "None".to_string()),
             )
+            .field("materialized_regions", &self.materialized_regions)
+            .field("statistics", &self.statistics)
+            .field("watchpoints", &self.watchpoints)
+            .field("initialized_bytes", &self.initialized_bytes)
+            .field("dirty_blocks", &self.dirty_blocks)
             .finish()
     }
 }
@@ -586,11 +1326,16 @@ impl<'a> AlignedMemoryMapping<'a> {
         regions.insert(0, MemoryRegion::new_readonly(&[], 0));
         regions.sort();
         for (index, region) in regions.iter().enumerate() {
+            let slot_end = (index as u64)
+                .saturating_add(1)
+                .checked_shl(ebpf::VIRTUAL_ADDRESS_BITS as u32)
+                .unwrap_or(u64::MAX);
             if region
                 .vm_addr
                 .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
                 .unwrap_or(0)
                 != index as u64
+                || region.vm_addr_end > slot_end
             {
                 return Err(EbpfError::InvalidMemoryRegion(index));
             }
@@ -600,6 +1345,11 @@ impl<'a> AlignedMemoryMapping<'a> {
             config,
             sbpf_version,
             cow_cb,
+            materialized_regions: MaterializedRegions::default(),
+            statistics: RegionStatistics::new(config),
+            watchpoints: Watchpoints::default(),
+            initialized_bytes: InitializedBytes::default(),
+            dirty_blocks: DirtyBlocks::default(),
         })
     }
 
@@ -624,40 +1374,106 @@ impl<'a> AlignedMemoryMapping<'a> {
         Self::new_internal(regions, Some(cow_cb), config, sbpf_version)
     }
 
-    /// Given a list of regions translate from virtual machine to host address
-    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
+    /// Returns the region occupying the slot of `vm_addr`, even if it does not contain it
+    fn slot_region(&self, vm_addr: u64) -> Option<&MemoryRegion> {
         let index = vm_addr
             .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
             .unwrap_or(0) as usize;
-        if (1..self.regions.len()).contains(&index) {
-            let region = &self.regions[index];
-            if access_type == AccessType::Load || ensure_writable_region(region, &self.cow_cb) {
-                if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
-                    return ProgramResult::Ok(host_addr);
-                }
-            }
-        }
-        generate_access_violation(self.config, self.sbpf_version, access_type, vm_addr, len)
+        self.regions.get(index).filter(|_| index > 0)
     }
 
-    /// Loads `size_of::<T>()` bytes from the given address.
-    ///
-    /// See [MemoryMapping::load].
-    #[inline]
-    pub fn load<T: Pod + Into<u64>>(&self, vm_addr: u64) -> ProgramResult {
-        let len = mem::size_of::<T>() as u64;
-        match self.map(AccessType::Load, vm_addr, len) {
-            ProgramResult::Ok(host_addr) => {
-                ProgramResult::Ok(unsafe { ptr::read_unaligned::<T>(host_addr as *const _) }.into())
-            }
-            err => err,
+    /// Finds the region containing `vm_addr`, if there is none it asks the
+    /// [AccessViolationHandler]
+    fn find_region(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Option<&MemoryRegion> {
+        match self.slot_region(vm_addr) {
+            Some(region) if (region.vm_addr..region.vm_addr_end).contains(&vm_addr) => Some(region),
+            _ => self
+                .materialized_regions
+                .find(&self.regions, access_type, vm_addr, len),
         }
     }
 
-    /// Store `value` at the given address.
-    ///
-    /// See [MemoryMapping::store].
-    #[inline]
+    /// Checks whether `map()` succeeds without invoking the [MemoryCowCallback] or the
+    /// [AccessViolationHandler], see [check_translation]
+    fn check_map(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<bool, EbpfError> {
+        let region = match self.slot_region(vm_addr) {
+            Some(region) if (region.vm_addr..region.vm_addr_end).contains(&vm_addr) => Some(region),
+            _ => self.materialized_regions.find_existing(vm_addr),
+        };
+        match region {
+            Some(region) => check_translation(region, access_type, vm_addr, len, &self.cow_cb),
+            None => self.materialized_regions.handler.as_ref().map(|_| true),
+        }
+        .ok_or_else(|| self.access_violation(access_type, vm_addr, len))
+    }
+
+    /// The error of a failing `map()`
+    fn access_violation(&self, access_type: AccessType, vm_addr: u64, len: u64) -> EbpfError {
+        generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            len,
+            self.slot_region(vm_addr),
+        )
+        .unwrap_err()
+    }
+
+    /// Given a list of regions translate from virtual machine to host address
+    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
+        if let Some(region) = self.find_region(access_type, vm_addr, len) {
+            if access_type == AccessType::Load
+                || ensure_writable_region(region, vm_addr, len, &self.cow_cb)
+            {
+                if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+                    self.statistics.record(region, access_type, len);
+                    self.initialized_bytes
+                        .record(region, access_type, vm_addr, len);
+                    self.dirty_blocks
+                        .record(region, access_type, host_addr, len);
+                    return ProgramResult::Ok(host_addr);
+                }
+            }
+        }
+        generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            len,
+            self.slot_region(vm_addr),
+        )
+    }
+
+    /// Loads `size_of::<T>()` bytes from the given address.
+    ///
+    /// See [MemoryMapping::load].
+    #[inline]
+    pub fn load<T: Pod + Into<u64>>(&self, vm_addr: u64) -> ProgramResult {
+        let len = mem::size_of::<T>() as u64;
+        match self.map(AccessType::Load, vm_addr, len) {
+            ProgramResult::Ok(host_addr) => {
+                ProgramResult::Ok(unsafe { ptr::read_unaligned::<T>(host_addr as *const _) }.into())
+            }
+            err => err,
+        }
+    }
+
+    /// Store `value` at the given address.
+    ///
+    /// See [MemoryMapping::store].
+    #[inline]
     pub fn store<T: Pod>(&self, value: T, vm_addr: u64) -> ProgramResult {
         let len = mem::size_of::<T>() as u64;
         debug_assert!(len <= mem::size_of::<u64>() as u64);
@@ -682,21 +1498,22 @@ impl<'a> AlignedMemoryMapping<'a> {
         access_type: AccessType,
         vm_addr: u64,
     ) -> Result<&MemoryRegion, EbpfError> {
-        let index = vm_addr
-            .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
-            .unwrap_or(0) as usize;
-        if (1..self.regions.len()).contains(&index) {
-            let region = &self.regions[index];
-            if (region.vm_addr..region.vm_addr_end).contains(&vm_addr)
-                && (access_type == AccessType::Load || ensure_writable_region(region, &self.cow_cb))
+        if let Some(region) = self.find_region(access_type, vm_addr, 0) {
+            if access_type == AccessType::Load
+                || ensure_writable_region(region, vm_addr, 0, &self.cow_cb)
             {
                 return Ok(region);
             }
         }
-        Err(
-            generate_access_violation(self.config, self.sbpf_version, access_type, vm_addr, 0)
-                .unwrap_err(),
+        Err(generate_access_violation(
+            self.config,
+            self.sbpf_version,
+            access_type,
+            vm_addr,
+            0,
+            self.slot_region(vm_addr),
         )
+        .unwrap_err())
     }
 
     /// Returns the `MemoryRegion`s in this mapping
@@ -704,6 +1521,16 @@ impl<'a> AlignedMemoryMapping<'a> {
         &self.regions
     }
 
+    /// Sets the handler which can insert regions when an access hits none
+    pub fn set_access_violation_handler(&mut self, handler: AccessViolationHandler) {
+        self.materialized_regions.handler = Some(handler);
+    }
+
+    /// Returns the accesses of every region touched so far, keyed by its `vm_addr`
+    pub fn region_statistics(&self) -> BTreeMap<u64, RegionAccessStatistics> {
+        self.statistics.get()
+    }
+
     /// Replaces the `MemoryRegion` at the given index
     pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
         if index >= self.regions.len() {
@@ -747,6 +1574,12 @@ impl<'a> MemoryMapping<'a> {
     ///
     /// Uses aligned or unaligned memory mapping depending on the value of
     /// `config.aligned_memory_mapping=true`.
+    ///
+    /// The order of `regions` does not matter, they are sorted by their `vm_addr`. Regions which
+    /// overlap or start at the same address are rejected with `InvalidMemoryRegion`, so every
+    /// address is served by at most one region and lookups do not depend on precedence. With
+    /// the aligned mapping each region must also stay within the 4 GiB slot its `vm_addr`
+    /// selects.
     pub fn new(
         regions: Vec<MemoryRegion>,
         config: &'a Config,
@@ -787,11 +1620,68 @@ impl<'a> MemoryMapping<'a> {
         }
     }
 
+    /// Map several ranges of virtual memory, given as `(vm_addr, len)`, to host memory at once.
+    ///
+    /// Either all ranges are translated or none is: Every range is checked without side effects
+    /// before the first one is mapped, so a failing range leaves no trace in the statistics, the
+    /// initialization tracking or the dirty tracking. Only then the [MemoryCowCallback] and the
+    /// [AccessViolationHandler] are invoked for the ranges which need them. As they can fail too,
+    /// the regions they prepared for earlier ranges stay copied or inserted in that case. Like
+    /// `map()` the ranges are not checked against watchpoints. Returns the host addresses in the
+    /// order of `ranges`, or the error of the first range which can not be translated.
+    pub fn map_iov(
+        &self,
+        access_type: AccessType,
+        ranges: &[(u64, u64)],
+    ) -> Result<Vec<u64>, EbpfError> {
+        let check_map = |vm_addr, len| match self {
+            MemoryMapping::Identity => Ok(false),
+            MemoryMapping::Aligned(m) => m.check_map(access_type, vm_addr, len),
+            MemoryMapping::Unaligned(m) => m.check_map(access_type, vm_addr, len),
+        };
+        let access_violation = |vm_addr, len| match self {
+            MemoryMapping::Identity => EbpfError::InvalidMemoryRegion(0),
+            MemoryMapping::Aligned(m) => m.access_violation(access_type, vm_addr, len),
+            MemoryMapping::Unaligned(m) => m.access_violation(access_type, vm_addr, len),
+        };
+        let needs_preparation = ranges
+            .iter()
+            .map(|(vm_addr, len)| check_map(*vm_addr, *len))
+            .collect::<Result<Vec<_>, _>>()?;
+        for ((vm_addr, len), _) in ranges
+            .iter()
+            .copied()
+            .zip(needs_preparation)
+            .filter(|(_range, needs_preparation)| *needs_preparation)
+        {
+            let prepared = self.region(access_type, vm_addr).is_ok_and(|region| {
+                check_translation(region, access_type, vm_addr, len, &None) == Some(false)
+            });
+            if !prepared {
+                return Err(access_violation(vm_addr, len));
+            }
+        }
+        ranges
+            .iter()
+            .map(|(vm_addr, len)| self.map(access_type, *vm_addr, *len).into())
+            .collect()
+    }
+
     /// Loads `size_of::<T>()` bytes from the given address.
     ///
-    /// Works across memory region boundaries.
+    /// Works across memory region boundaries. Fails with `EbpfError::WatchpointHit` before
+    /// touching any memory if a watchpoint covers part of the access.
     #[inline]
     pub fn load<T: Pod + Into<u64>>(&self, vm_addr: u64) -> ProgramResult {
+        if let Some(err) = self.check_watchpoints(AccessType::Load, vm_addr, mem::size_of::<T>()) {
+            return ProgramResult::Err(err);
+        }
+        self.load_unwatched::<T>(vm_addr)
+    }
+
+    /// Same as `load()` but ignores the watchpoints
+    #[inline]
+    pub(crate) fn load_unwatched<T: Pod + Into<u64>>(&self, vm_addr: u64) -> ProgramResult {
         match self {
             MemoryMapping::Identity => unsafe {
                 ProgramResult::Ok(ptr::read_unaligned(vm_addr as *const T).into())
@@ -804,8 +1694,19 @@ impl<'a> MemoryMapping<'a> {
     /// Store `value` at the given address.
     ///
     /// Works across memory region boundaries if `len` does not fit within a single region.
+    /// Fails with `EbpfError::WatchpointHit` before touching any memory if a watchpoint covers
+    /// part of the access.
     #[inline]
     pub fn store<T: Pod>(&self, value: T, vm_addr: u64) -> ProgramResult {
+        if let Some(err) = self.check_watchpoints(AccessType::Store, vm_addr, mem::size_of::<T>()) {
+            return ProgramResult::Err(err);
+        }
+        self.store_unwatched(value, vm_addr)
+    }
+
+    /// Same as `store()` but ignores the watchpoints
+    #[inline]
+    pub(crate) fn store_unwatched<T: Pod>(&self, value: T, vm_addr: u64) -> ProgramResult {
         match self {
             MemoryMapping::Identity => unsafe {
                 ptr::write_unaligned(vm_addr as *mut T, value);
@@ -830,6 +1731,9 @@ impl<'a> MemoryMapping<'a> {
     }
 
     /// Returns the `MemoryRegion`s in this mapping.
+    ///
+    /// This is the internal layout, which `replace_region()` indexes into. See `regions()` for
+    /// a stable order.
     pub fn get_regions(&self) -> &[MemoryRegion] {
         match self {
             MemoryMapping::Identity => &[],
@@ -838,6 +1742,261 @@ impl<'a> MemoryMapping<'a> {
         }
     }
 
+    /// Returns the `MemoryRegion`s passed to the constructor, sorted by their `vm_addr`
+    pub fn regions(&self) -> Vec<&MemoryRegion> {
+        let mut regions = match self {
+            MemoryMapping::Identity => Vec::new(),
+            // Skip the empty region which occupies the first slot
+            MemoryMapping::Aligned(m) => m.get_regions().iter().skip(1).collect(),
+            MemoryMapping::Unaligned(m) => m.get_regions().iter().collect::<Vec<_>>(),
+        };
+        regions.sort();
+        regions
+    }
+
+    /// Writes an annotated hexdump of `len` bytes starting at `vm_addr`
+    ///
+    /// Every memory region the range touches starts with a header naming the region and its
+    /// permissions, followed by lines of up to 16 bytes in hex and ASCII. Bytes which are not
+    /// mapped, e.g. in the gaps of the stack, are shown as `??`. Copy on write regions are not
+    /// copied.
+    pub fn dump_range<W: std::io::Write>(
+        &self,
+        vm_addr: u64,
+        len: u64,
+        output: &mut W,
+    ) -> std::io::Result<()> {
+        let mut line = Vec::with_capacity(HEXDUMP_BYTES_PER_LINE);
+        let mut line_start = vm_addr;
+        let mut current_region = None;
+        for offset in 0..len {
+            let Some(address) = vm_addr.checked_add(offset) else {
+                break;
+            };
+            let region = self.region(AccessType::Load, address).ok();
+            let region_start = region.map(|region| region.vm_addr);
+            if current_region != Some(region_start) || line.len() == HEXDUMP_BYTES_PER_LINE {
+                write_hexdump_line(output, line_start, &line)?;
+                line.clear();
+                line_start = address;
+            }
+            if current_region != Some(region_start) {
+                current_region = Some(region_start);
+                match region {
+                    Some(region) => writeln!(
+                        output,
+                        "{} ({}) {:#x}..{:#x}",
+                        region.name(),
+                        match region.state.get() {
+                            MemoryState::Readable => "r",
+                            MemoryState::Writable => "rw",
+                            MemoryState::Cow(_) => "copy on write",
+                        },
+                        region.vm_addr,
+                        region.vm_addr_end,
+                    )?,
+                    None => writeln!(output, "unmapped")?,
+                }
+            }
+            line.push(match (region, self.map(AccessType::Load, address, 1)) {
+                (Some(_), ProgramResult::Ok(host_addr)) => {
+                    Some(unsafe { ptr::read(host_addr as *const u8) })
+                }
+                _ => None,
+            });
+        }
+        write_hexdump_line(output, line_start, &line)
+    }
+
+    /// Sets the handler which can insert regions when an access hits none
+    ///
+    /// This allows to materialize memory lazily (e.g. to demand-page large account data) instead
+    /// of mapping everything eagerly. Inserted regions are not part of `get_regions()` and
+    /// `regions()`, and the JIT reaches them only through its slow path. Ignored by the identity
+    /// mapping.
+    pub fn set_access_violation_handler(&mut self, handler: AccessViolationHandler) {
+        match self {
+            MemoryMapping::Identity => {}
+            MemoryMapping::Aligned(m) => m.set_access_violation_handler(handler),
+            MemoryMapping::Unaligned(m) => m.set_access_violation_handler(handler),
+        }
+    }
+
+    /// Returns the loads, stores and translated bytes of every region touched so far
+    ///
+    /// Keyed by the `vm_addr` of the regions, the ones which were not accessed are left out.
+    /// Accesses spanning multiple regions count once in each of them, including the parts
+    /// which were translated before the access faulted. Only available with
+    /// `Config::enable_region_statistics`, otherwise this is empty.
+    pub fn region_statistics(&self) -> BTreeMap<u64, RegionAccessStatistics> {
+        match self {
+            MemoryMapping::Identity => BTreeMap::new(),
+            MemoryMapping::Aligned(m) => m.region_statistics(),
+            MemoryMapping::Unaligned(m) => m.region_statistics(),
+        }
+    }
+
+    /// Starts or stops recording which bytes stores change
+    ///
+    /// Before the first store to a block of a region its contents are saved, so that
+    /// `dirty_runs()` can compare them to the current ones later. This includes stores through
+    /// `map()`, e.g. by syscalls. Disabling forgets the saved contents. While enabled the JIT
+    /// translates all addresses in its slow path. Ignored by the identity mapping.
+    pub fn set_dirty_tracking(&mut self, enabled: bool) {
+        let dirty_blocks = match self {
+            MemoryMapping::Identity => return,
+            MemoryMapping::Aligned(m) => &mut m.dirty_blocks,
+            MemoryMapping::Unaligned(m) => &mut m.dirty_blocks,
+        };
+        *dirty_blocks = DirtyBlocks(enabled.then(|| RefCell::new(BTreeMap::new())));
+    }
+
+    /// Whether `set_dirty_tracking()` is enabled
+    pub fn tracks_dirty_memory(&self) -> bool {
+        self.dirty_blocks()
+            .is_some_and(|dirty_blocks| dirty_blocks.0.is_some())
+    }
+
+    /// Returns the bytes which changed since the tracking started or was reset
+    ///
+    /// Consecutive changed bytes form one run, ordered by region and offset. Bytes which were
+    /// stored to but hold their old value again are left out, so that the runs are a minimal
+    /// diff of the memory, e.g. to commit to the new state without hashing whole buffers.
+    pub fn dirty_runs(&self) -> Vec<DirtyRun> {
+        self.dirty_blocks()
+            .map(|dirty_blocks| {
+                dirty_blocks.runs(|vm_addr| self.region(AccessType::Load, vm_addr).ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Makes the current contents the baseline of `dirty_runs()`
+    pub fn reset_dirty_tracking(&self) {
+        if let Some(dirty_blocks) = self.dirty_blocks() {
+            dirty_blocks.clear();
+        }
+    }
+
+    fn dirty_blocks(&self) -> Option<&DirtyBlocks> {
+        match self {
+            MemoryMapping::Identity => None,
+            MemoryMapping::Aligned(m) => Some(&m.dirty_blocks),
+            MemoryMapping::Unaligned(m) => Some(&m.dirty_blocks),
+        }
+    }
+
+    /// Whether loads and stores must go through `load()` and `store()`, so that the JIT may not
+    /// access the host memory directly
+    pub(crate) fn observes_accesses(&self) -> bool {
+        self.has_active_watchpoints() || self.tracks_initialization() || self.tracks_dirty_memory()
+    }
+
+    /// Starts or stops tracking which bytes of the stack and heap region were written
+    ///
+    /// Stores and translations for stores through `map()`, e.g. by syscalls, mark the bytes as
+    /// written, see `is_initialized()`. Disabling forgets all written bytes. While enabled the JIT
+    /// translates all addresses in its slow path. Ignored by the identity mapping.
+    pub fn set_initialization_tracking(&mut self, enabled: bool) {
+        let initialized_bytes = match self {
+            MemoryMapping::Identity => return,
+            MemoryMapping::Aligned(m) => &mut m.initialized_bytes,
+            MemoryMapping::Unaligned(m) => &mut m.initialized_bytes,
+        };
+        *initialized_bytes = InitializedBytes(enabled.then(|| RefCell::new(BTreeMap::new())));
+    }
+
+    /// Whether `set_initialization_tracking()` is enabled
+    pub fn tracks_initialization(&self) -> bool {
+        self.initialized_bytes()
+            .is_some_and(|initialized_bytes| initialized_bytes.0.is_some())
+    }
+
+    /// Whether all `len` bytes at `vm_addr` were written since the tracking started or was reset
+    ///
+    /// Always true outside of the stack and heap region or without
+    /// `set_initialization_tracking()`.
+    pub fn is_initialized(&self, vm_addr: u64, len: u64) -> bool {
+        self.initialized_bytes().map_or(true, |initialized_bytes| {
+            initialized_bytes.is_initialized(vm_addr, len)
+        })
+    }
+
+    /// Forgets which bytes were written, e.g. before the next execution
+    pub fn reset_initialization_tracking(&self) {
+        if let Some(initialized_bytes) = self.initialized_bytes() {
+            initialized_bytes.clear();
+        }
+    }
+
+    fn initialized_bytes(&self) -> Option<&InitializedBytes> {
+        match self {
+            MemoryMapping::Identity => None,
+            MemoryMapping::Aligned(m) => Some(&m.initialized_bytes),
+            MemoryMapping::Unaligned(m) => Some(&m.initialized_bytes),
+        }
+    }
+
+    /// Stops `load()` and `store()` (and thus the interpreter and the JIT) at accesses
+    /// overlapping `len` bytes starting at `vm_addr`
+    ///
+    /// A hit fails the access with `EbpfError::WatchpointHit` without performing it. Translations
+    /// through `map()`, e.g. by syscalls, are not watched. While any watchpoint is active the JIT
+    /// translates all addresses in its slow path. Returns false if `len` is zero or for the
+    /// identity mapping, which can not be watched.
+    pub fn add_watchpoint(&mut self, vm_addr: u64, len: u64, kind: WatchpointKind) -> bool {
+        match self.watchpoints_mut() {
+            Some(watchpoints) => watchpoints.add(vm_addr, len, kind),
+            None => false,
+        }
+    }
+
+    /// Removes a watchpoint added with the same arguments, returns false if there is none
+    pub fn remove_watchpoint(&mut self, vm_addr: u64, len: u64, kind: WatchpointKind) -> bool {
+        match self.watchpoints_mut() {
+            Some(watchpoints) => watchpoints.remove(vm_addr, len, kind),
+            None => false,
+        }
+    }
+
+    /// Suspends or resumes all watchpoints without removing them
+    pub fn set_watchpoints_enabled(&mut self, enabled: bool) {
+        if let Some(watchpoints) = self.watchpoints_mut() {
+            watchpoints.enabled = enabled;
+        }
+    }
+
+    /// Whether accesses are currently checked against watchpoints
+    pub fn has_active_watchpoints(&self) -> bool {
+        self.watchpoints().is_some_and(Watchpoints::is_active)
+    }
+
+    fn watchpoints(&self) -> Option<&Watchpoints> {
+        match self {
+            MemoryMapping::Identity => None,
+            MemoryMapping::Aligned(m) => Some(&m.watchpoints),
+            MemoryMapping::Unaligned(m) => Some(&m.watchpoints),
+        }
+    }
+
+    fn watchpoints_mut(&mut self) -> Option<&mut Watchpoints> {
+        match self {
+            MemoryMapping::Identity => None,
+            MemoryMapping::Aligned(m) => Some(&mut m.watchpoints),
+            MemoryMapping::Unaligned(m) => Some(&mut m.watchpoints),
+        }
+    }
+
+    #[inline]
+    fn check_watchpoints(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: usize,
+    ) -> Option<EbpfError> {
+        self.watchpoints()
+            .and_then(|watchpoints| watchpoints.check(access_type, vm_addr, len as u64))
+    }
+
     /// Replaces the `MemoryRegion` at the given index
     pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
         match self {
@@ -848,10 +2007,45 @@ impl<'a> MemoryMapping<'a> {
     }
 }
 
+/// Checks whether `region` translates an access without side effects
+///
+/// Returns Some(true) if the [MemoryCowCallback] has to copy the region first, which can fail,
+/// and None if the translation fails.
+fn check_translation(
+    region: &MemoryRegion,
+    access_type: AccessType,
+    vm_addr: u64,
+    len: u64,
+    cow_cb: &Option<MemoryCowCallback>,
+) -> Option<bool> {
+    if region.vm_to_host(vm_addr, len).is_err() {
+        return None;
+    }
+    if access_type == AccessType::Load {
+        return Some(false);
+    }
+    if !region.is_writable_range(vm_addr, len) {
+        return None;
+    }
+    match (region.state.get(), cow_cb) {
+        (MemoryState::Writable, _) => Some(false),
+        (MemoryState::Cow(_), Some(_)) => Some(true),
+        _ => None,
+    }
+}
+
 // Ensure that the given region is writable.
 //
 // If the region is CoW, cow_cb is called to execute the CoW operation.
-fn ensure_writable_region(region: &MemoryRegion, cow_cb: &Option<MemoryCowCallback>) -> bool {
+fn ensure_writable_region(
+    region: &MemoryRegion,
+    vm_addr: u64,
+    len: u64,
+    cow_cb: &Option<MemoryCowCallback>,
+) -> bool {
+    if !region.is_writable_range(vm_addr, len) {
+        return false;
+    }
     match (region.state.get(), cow_cb) {
         (MemoryState::Writable, _) => true,
         (MemoryState::Cow(cow_id), Some(cb)) => match cb(cow_id) {
@@ -866,17 +2060,69 @@ fn ensure_writable_region(region: &MemoryRegion, cow_cb: &Option<MemoryCowCallba
     }
 }
 
+/// Number of bytes in a line of `MemoryMapping::dump_range()`
+const HEXDUMP_BYTES_PER_LINE: usize = 16;
+
+fn write_hexdump_line<W: std::io::Write>(
+    output: &mut W,
+    vm_addr: u64,
+    bytes: &[Option<u8>],
+) -> std::io::Result<()> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    write!(output, "{vm_addr:#018x}:")?;
+    for byte in bytes {
+        match byte {
+            Some(byte) => write!(output, " {byte:02x}")?,
+            None => write!(output, " ??")?,
+        }
+    }
+    let ascii = bytes
+        .iter()
+        .map(|byte| match byte {
+            Some(byte) if byte.is_ascii_graphic() || *byte == b' ' => *byte as char,
+            Some(_) => '.',
+            None => '?',
+        })
+        .collect::<String>();
+    writeln!(
+        output,
+        "{:width$}  |{ascii}|",
+        "",
+        width = 3usize.saturating_mul(HEXDUMP_BYTES_PER_LINE.saturating_sub(bytes.len())),
+    )
+}
+
+/// Name of the memory region which the given address belongs to by convention
+fn region_name(vm_addr: u64) -> &'static str {
+    match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
+        ebpf::MM_PROGRAM_START => "program",
+        ebpf::MM_STACK_START => "stack",
+        ebpf::MM_HEAP_START => "heap",
+        ebpf::MM_INPUT_START => "input",
+        _ => "unknown",
+    }
+}
+
 /// Helper for map to generate errors
+///
+/// `region` is the one containing `vm_addr` or preceding it, if any. Its label names the
+/// violation if it is in the same section.
 fn generate_access_violation(
     config: &Config,
     sbpf_version: &SBPFVersion,
     access_type: AccessType,
     vm_addr: u64,
     len: u64,
+    region: Option<&MemoryRegion>,
 ) -> ProgramResult {
+    // Every fixed frame is followed by its gap
+    let stack_frame_period = (config.stack_frame_size as i64)
+        .saturating_add(config.stack_frame_gap_size(sbpf_version) as i64);
     let stack_frame = (vm_addr as i64)
         .saturating_sub(ebpf::MM_STACK_START as i64)
-        .checked_div(config.stack_frame_size as i64)
+        .checked_div_euclid(stack_frame_period)
         .unwrap_or(0);
     if !sbpf_version.dynamic_stack_frames()
         && (-1..(config.max_call_depth as i64).saturating_add(1)).contains(&stack_frame)
@@ -888,18 +2134,20 @@ fn generate_access_violation(
             stack_frame,
         ))
     } else {
-        let region_name = match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
-            ebpf::MM_PROGRAM_START => "program",
-            ebpf::MM_STACK_START => "stack",
-            ebpf::MM_HEAP_START => "heap",
-            ebpf::MM_INPUT_START => "input",
-            _ => "unknown",
-        };
+        let label = region
+            .filter(|region| {
+                region.vm_addr <= vm_addr
+                    && region
+                        .vm_addr
+                        .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
+                        == vm_addr.checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
+            })
+            .and_then(|region| region.label);
         ProgramResult::Err(EbpfError::AccessViolation(
             access_type,
             vm_addr,
             len,
-            region_name,
+            label.unwrap_or_else(|| region_name(vm_addr)),
         ))
     }
 }
@@ -1023,6 +2271,45 @@ mod test {
         assert_eq!(cache.find(0), None);
     }
 
+    #[test]
+    fn test_dump_range() {
+        let config = Config::default();
+        let mut stack = b"0123456789abcdef".to_vec();
+        let input = [0x00, 0x41, 0xff];
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..config
+            };
+            let m = MemoryMapping::new(
+                vec![
+                    MemoryRegion::new_readonly(&[], ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable_gapped(&mut stack, ebpf::MM_STACK_START, 8),
+                    MemoryRegion::new_readonly(&[], ebpf::MM_HEAP_START),
+                    MemoryRegion::new_readonly(&input, ebpf::MM_INPUT_START),
+                ],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            let mut output = Vec::new();
+            m.dump_range(ebpf::MM_STACK_START + 4, 20, &mut output)
+                .unwrap();
+            m.dump_range(ebpf::MM_INPUT_START, 4, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "stack (rw) 0x200000000..0x200000020
+0x0000000200000004: 34 35 36 37 ?? ?? ?? ?? ?? ?? ?? ?? 38 39 61 62  |4567????????89ab|
+0x0000000200000014: 63 64 65 66                                      |cdef|
+input (r) 0x400000000..0x400000003
+0x0000000400000000: 00 41 ff                                         |.A.|
+unmapped
+0x0000000400000003: ??                                               |?|
+"
+            );
+        }
+    }
+
     #[test]
     fn test_map_empty() {
         let config = Config::default();
@@ -1039,6 +2326,58 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_access_violation_handler() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let page = Rc::new(vec![0x11; 8]);
+            let accesses = Rc::new(RefCell::new(Vec::new()));
+            let mut m = MemoryMapping::new(
+                vec![MemoryRegion::new_readonly(&[0; 8], ebpf::MM_PROGRAM_START)],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            assert_error!(
+                m.map(AccessType::Load, ebpf::MM_INPUT_START, 8),
+                "AccessViolation"
+            );
+
+            let (p, a) = (Rc::clone(&page), Rc::clone(&accesses));
+            m.set_access_violation_handler(Box::new(move |access_type, vm_addr, len| {
+                a.borrow_mut().push((access_type, vm_addr, len));
+                Some(if vm_addr >= ebpf::MM_INPUT_START {
+                    MemoryRegion::new_readonly(&p, ebpf::MM_INPUT_START)
+                } else {
+                    MemoryRegion::new_readonly(&p, ebpf::MM_PROGRAM_START)
+                })
+            }));
+            assert_eq!(m.load::<u32>(ebpf::MM_INPUT_START + 4).unwrap(), 0x11111111);
+            assert!(m.region(AccessType::Load, ebpf::MM_INPUT_START).is_ok());
+            assert_error!(m.store::<u8>(0x22, ebpf::MM_INPUT_START), "AccessViolation");
+            assert_error!(
+                m.map(AccessType::Load, ebpf::MM_INPUT_START + 8, 1),
+                "AccessViolation"
+            );
+            // Regions which do not contain the address or overlap others are not inserted
+            assert_error!(
+                m.map(AccessType::Load, ebpf::MM_HEAP_START, 1),
+                "AccessViolation"
+            );
+            assert_eq!(
+                *accesses.borrow(),
+                vec![
+                    (AccessType::Load, ebpf::MM_INPUT_START + 4, 4),
+                    (AccessType::Load, ebpf::MM_INPUT_START + 8, 1),
+                    (AccessType::Load, ebpf::MM_HEAP_START, 1),
+                ]
+            );
+        }
+    }
+
     #[test]
     fn test_gapped_map() {
         for aligned_memory_mapping in [false, true] {
@@ -1069,6 +2408,599 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_chunked_map() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+            let region =
+                MemoryRegion::new_writable_chunked(&mut mem, ebpf::MM_PROGRAM_START, 4, 12);
+            assert_eq!(region.vm_addr_end, ebpf::MM_PROGRAM_START + 34);
+            let m = MemoryMapping::new(vec![region], &config, &SBPFVersion::V2).unwrap();
+            for (chunk, data) in [[1u8, 2, 3, 4], [5, 6, 7, 8]].iter().enumerate() {
+                let address = ebpf::MM_PROGRAM_START + chunk as u64 * 16;
+                assert_eq!(
+                    m.load::<u32>(address).unwrap(),
+                    u32::from_le_bytes(*data) as u64
+                );
+                assert!(m.map(AccessType::Load, address + 3, 1).is_ok());
+                assert_error!(m.map(AccessType::Load, address + 3, 2), "AccessViolation");
+                assert_error!(m.map(AccessType::Load, address + 4, 1), "AccessViolation");
+                assert_error!(m.map(AccessType::Load, address + 15, 1), "AccessViolation");
+            }
+            assert!(m.store::<u16>(0x0B0B, ebpf::MM_PROGRAM_START + 32).is_ok());
+            assert_eq!(m.load::<u8>(ebpf::MM_PROGRAM_START + 33).unwrap(), 0x0B);
+            assert_error!(
+                m.store::<u16>(0, ebpf::MM_PROGRAM_START + 33),
+                "AccessViolation"
+            );
+        }
+    }
+
+    #[test]
+    fn test_region_statistics() {
+        for aligned_memory_mapping in [false, true] {
+            for enable_region_statistics in [false, true] {
+                let config = Config {
+                    aligned_memory_mapping,
+                    enable_region_statistics,
+                    ..Config::default()
+                };
+                let mut mem1 = [0; 4];
+                let mut mem2 = [0; 4];
+                let m = MemoryMapping::new(
+                    vec![
+                        MemoryRegion::new_writable(&mut mem1, ebpf::MM_PROGRAM_START),
+                        MemoryRegion::new_writable(&mut mem2, ebpf::MM_STACK_START),
+                    ],
+                    &config,
+                    &SBPFVersion::V2,
+                )
+                .unwrap();
+                m.load::<u32>(ebpf::MM_PROGRAM_START).unwrap();
+                m.store::<u16>(0, ebpf::MM_PROGRAM_START + 2).unwrap();
+                m.map(AccessType::Store, ebpf::MM_PROGRAM_START, 3).unwrap();
+                assert_error!(m.load::<u64>(ebpf::MM_STACK_START), "AccessViolation");
+                if !enable_region_statistics {
+                    assert!(m.region_statistics().is_empty());
+                    continue;
+                }
+                let mut expected = vec![(
+                    ebpf::MM_PROGRAM_START,
+                    RegionAccessStatistics {
+                        loads: 1,
+                        stores: 2,
+                        bytes: 9,
+                    },
+                )];
+                if !aligned_memory_mapping {
+                    // The unaligned mapping translates the first half of the faulting access
+                    expected.push((
+                        ebpf::MM_STACK_START,
+                        RegionAccessStatistics {
+                            loads: 1,
+                            stores: 0,
+                            bytes: 4,
+                        },
+                    ));
+                }
+                assert_eq!(
+                    m.region_statistics().into_iter().collect::<Vec<_>>(),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_map_iov() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mem1 = [0x11; 8];
+            let mut mem2 = [0x22; 16];
+            let mem1_addr = mem1.as_ptr() as u64;
+            let mem2_addr = mem2.as_ptr() as u64;
+            let mut m = MemoryMapping::new(
+                vec![
+                    MemoryRegion::new_readonly(&mem1, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut mem2, ebpf::MM_STACK_START),
+                ],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            m.set_initialization_tracking(true);
+
+            assert_eq!(m.map_iov(AccessType::Load, &[]).unwrap(), vec![]);
+            assert_eq!(
+                m.map_iov(
+                    AccessType::Load,
+                    &[(ebpf::MM_STACK_START + 8, 8), (ebpf::MM_PROGRAM_START, 8)]
+                )
+                .unwrap(),
+                vec![mem2_addr + 8, mem1_addr]
+            );
+            for ranges in [
+                [(ebpf::MM_STACK_START, 4), (ebpf::MM_PROGRAM_START, 4)],
+                [(ebpf::MM_STACK_START, 4), (ebpf::MM_STACK_START + 12, 8)],
+            ] {
+                assert_error!(
+                    m.map_iov(AccessType::Store, &ranges),
+                    "AccessViolation(Store, {}, {}",
+                    ranges[1].0,
+                    ranges[1].1
+                );
+                // The first range was not mapped either
+                assert!(!m.is_initialized(ebpf::MM_STACK_START, 4));
+            }
+            assert_eq!(
+                m.map_iov(
+                    AccessType::Store,
+                    &[(ebpf::MM_STACK_START, 4), (ebpf::MM_STACK_START + 12, 4)]
+                )
+                .unwrap(),
+                vec![mem2_addr, mem2_addr + 12]
+            );
+            assert!(m.is_initialized(ebpf::MM_STACK_START, 4));
+            assert!(m.is_initialized(ebpf::MM_STACK_START + 12, 4));
+        }
+    }
+
+    #[test]
+    fn test_map_iov_cow() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                enable_region_statistics: true,
+                ..Config::default()
+            };
+            let original = [11; 8];
+            let readonly = [33; 8];
+            let mut writable = [44; 8];
+            let copied = Rc::new(RefCell::new(Vec::new()));
+            let cow_calls = Rc::new(RefCell::new(0));
+
+            let c = Rc::clone(&copied);
+            let calls = Rc::clone(&cow_calls);
+            let mut m = MemoryMapping::new_with_cow(
+                vec![
+                    MemoryRegion::new_readonly(&readonly, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_cow(&original, ebpf::MM_STACK_START, 42),
+                    MemoryRegion::new_cow(&original, ebpf::MM_HEAP_START, 43),
+                    MemoryRegion::new_writable(&mut writable, ebpf::MM_INPUT_START),
+                ],
+                Box::new(move |cow_id| {
+                    *calls.borrow_mut() += 1;
+                    if cow_id == 43 {
+                        return Err(());
+                    }
+                    c.borrow_mut().extend_from_slice(&original);
+                    Ok(c.borrow().as_slice().as_ptr() as u64)
+                }),
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            m.set_initialization_tracking(true);
+
+            // A failing range behind a CoW range is found before anything is copied
+            assert_error!(
+                m.map_iov(
+                    AccessType::Store,
+                    &[(ebpf::MM_STACK_START, 4), (ebpf::MM_PROGRAM_START, 4)]
+                ),
+                "AccessViolation(Store, {}, 4",
+                ebpf::MM_PROGRAM_START
+            );
+            assert_eq!(*cow_calls.borrow(), 0);
+            assert!(m.region_statistics().is_empty());
+            assert!(!m.is_initialized(ebpf::MM_STACK_START, 4));
+
+            // A CoW range which can not be copied in the middle of the list
+            assert_error!(
+                m.map_iov(
+                    AccessType::Store,
+                    &[
+                        (ebpf::MM_STACK_START, 4),
+                        (ebpf::MM_HEAP_START, 4),
+                        (ebpf::MM_INPUT_START, 4)
+                    ]
+                ),
+                "AccessViolation(Store, {}, 4",
+                ebpf::MM_HEAP_START
+            );
+            // The region of the first range was copied, but the range was not mapped
+            assert_eq!(*cow_calls.borrow(), 2);
+            assert!(m.region_statistics().is_empty());
+            assert!(!m.is_initialized(ebpf::MM_STACK_START, 4));
+
+            // Watchpoints do not stop the translation
+            assert!(m.add_watchpoint(ebpf::MM_INPUT_START, 1, WatchpointKind::Any));
+            assert_eq!(
+                m.map_iov(
+                    AccessType::Store,
+                    &[
+                        (ebpf::MM_STACK_START, 4),
+                        (ebpf::MM_INPUT_START, 4),
+                        (ebpf::MM_INPUT_START + 4, 4)
+                    ]
+                )
+                .unwrap(),
+                vec![
+                    copied.borrow().as_ptr() as u64,
+                    writable.as_ptr() as u64,
+                    writable.as_ptr() as u64 + 4
+                ]
+            );
+            assert_eq!(*cow_calls.borrow(), 2);
+            assert!(m.is_initialized(ebpf::MM_STACK_START, 4));
+            assert_eq!(m.region_statistics().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem1 = [0x11; 200];
+            let mut mem2 = [0x22; 8];
+            let mut m = MemoryMapping::new(
+                vec![
+                    MemoryRegion::new_writable(&mut mem1, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable_gapped(&mut mem2, ebpf::MM_STACK_START, 4),
+                ],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            m.store::<u8>(0, ebpf::MM_PROGRAM_START).unwrap();
+            assert!(!m.tracks_dirty_memory());
+            assert!(m.dirty_runs().is_empty());
+            m.set_dirty_tracking(true);
+            assert!(m.tracks_dirty_memory());
+
+            // Spans two blocks
+            m.store::<u32>(0x33330000, ebpf::MM_PROGRAM_START + 62)
+                .unwrap();
+            // Unchanged
+            m.store::<u8>(0x11, ebpf::MM_PROGRAM_START + 199).unwrap();
+            // Changed and changed back
+            m.store::<u8>(0x44, ebpf::MM_PROGRAM_START + 100).unwrap();
+            m.store::<u8>(0x11, ebpf::MM_PROGRAM_START + 100).unwrap();
+            // Through map(), after the gap of the stack
+            let host_addr = m
+                .map(AccessType::Store, ebpf::MM_STACK_START + 8, 2)
+                .unwrap();
+            unsafe { *(host_addr as *mut u16) = 0x5555 };
+            assert_eq!(
+                m.dirty_runs(),
+                vec![
+                    DirtyRun {
+                        region: ebpf::MM_PROGRAM_START,
+                        offset: 62,
+                        old: vec![0x11, 0x11, 0x11, 0x11],
+                        new: vec![0x00, 0x00, 0x33, 0x33],
+                    },
+                    DirtyRun {
+                        region: ebpf::MM_STACK_START,
+                        offset: 4,
+                        old: vec![0x22, 0x22],
+                        new: vec![0x55, 0x55],
+                    },
+                ]
+            );
+
+            m.reset_dirty_tracking();
+            assert!(m.dirty_runs().is_empty());
+            m.store::<u8>(0x66, ebpf::MM_PROGRAM_START + 64).unwrap();
+            assert_eq!(
+                m.dirty_runs(),
+                vec![DirtyRun {
+                    region: ebpf::MM_PROGRAM_START,
+                    offset: 64,
+                    old: vec![0x33],
+                    new: vec![0x66],
+                }]
+            );
+            m.set_dirty_tracking(false);
+            assert!(m.dirty_runs().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_writable_ranges() {
+        let region = MemoryRegion::new_readonly(&[0; 16], ebpf::MM_PROGRAM_START)
+            .with_writable_ranges([8..12, 2..4, 10..14, 4..4, 0..1]);
+        assert_eq!(
+            region.writable_ranges.as_ref().unwrap().as_slice(),
+            &[0..1, 2..4, 8..14]
+        );
+        assert!(region.is_writable_range(ebpf::MM_PROGRAM_START + 8, 6));
+        assert!(region.is_writable_range(ebpf::MM_PROGRAM_START + 13, 0));
+        assert!(!region.is_writable_range(ebpf::MM_PROGRAM_START + 14, 0));
+        assert!(!region.is_writable_range(ebpf::MM_PROGRAM_START, 4));
+
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem1 = [0xff; 8];
+            let mut mem2 = [0xff; 8];
+            let mut regions = vec![
+                MemoryRegion::new_writable(&mut mem1, ebpf::MM_PROGRAM_START)
+                    .with_writable_ranges(Some(4..8)),
+            ];
+            if !aligned_memory_mapping {
+                regions.push(
+                    MemoryRegion::new_writable(&mut mem2, ebpf::MM_PROGRAM_START + 8)
+                        .with_writable_ranges(Some(0..2)),
+                );
+            }
+            let m = MemoryMapping::new(regions, &config, &SBPFVersion::V2).unwrap();
+            m.store::<u32>(0, ebpf::MM_PROGRAM_START + 4).unwrap();
+            assert_error!(
+                m.store::<u16>(0, ebpf::MM_PROGRAM_START + 3),
+                "AccessViolation(Store, {}, 2",
+                ebpf::MM_PROGRAM_START + 3
+            );
+            assert_error!(
+                m.map(AccessType::Store, ebpf::MM_PROGRAM_START, 1),
+                "AccessViolation(Store"
+            );
+            assert!(m.region(AccessType::Store, ebpf::MM_PROGRAM_START).is_err());
+            m.region(AccessType::Store, ebpf::MM_PROGRAM_START + 4)
+                .unwrap();
+            // Loads are not restricted
+            assert_eq!(
+                m.load::<u64>(ebpf::MM_PROGRAM_START).unwrap(),
+                0x00000000ffffffff
+            );
+            if !aligned_memory_mapping {
+                // Each region checks its part of a store spanning both
+                m.store::<u32>(0, ebpf::MM_PROGRAM_START + 6).unwrap();
+                assert_error!(
+                    m.store::<u32>(0, ebpf::MM_PROGRAM_START + 7),
+                    "AccessViolation(Store"
+                );
+            }
+        }
+
+        let config = Config::default();
+        let original = [0xff; 8];
+        let copied = Rc::new(RefCell::new(Vec::new()));
+        let c = Rc::clone(&copied);
+        let m = MemoryMapping::new_with_cow(
+            vec![MemoryRegion::new_cow(&original, ebpf::MM_PROGRAM_START, 42)
+                .with_writable_ranges(Some(4..8))],
+            Box::new(move |_| {
+                c.borrow_mut().extend_from_slice(&original);
+                Ok(c.borrow().as_slice().as_ptr() as u64)
+            }),
+            &config,
+            &SBPFVersion::V2,
+        )
+        .unwrap();
+        // A rejected store does not copy the region
+        assert_error!(
+            m.store::<u8>(0, ebpf::MM_PROGRAM_START),
+            "AccessViolation(Store"
+        );
+        assert!(copied.borrow().is_empty());
+        m.store::<u8>(1, ebpf::MM_PROGRAM_START + 4).unwrap();
+        assert_eq!(
+            copied.borrow()[..],
+            [0xff, 0xff, 0xff, 0xff, 1, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_labeled_regions() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mem1 = [0; 8];
+            let mem2 = [0; 8];
+            let mut regions = vec![
+                MemoryRegion::new_readonly(&mem1, ebpf::MM_PROGRAM_START),
+                MemoryRegion::new_readonly(&mem2, ebpf::MM_STACK_START)
+                    .with_label("account #3 data"),
+            ];
+            if !aligned_memory_mapping {
+                regions.push(
+                    MemoryRegion::new_readonly(&mem2, ebpf::MM_STACK_START + 0x100)
+                        .with_label("account #4 data"),
+                );
+            }
+            assert_eq!(regions[0].name(), "program");
+            assert_eq!(regions[1].name(), "account #3 data");
+            let m = MemoryMapping::new(regions, &config, &SBPFVersion::V2).unwrap();
+            assert_error!(
+                m.load::<u64>(ebpf::MM_STACK_START + 4),
+                "AccessViolation(Load, {}, 8, \"account #3 data\")",
+                ebpf::MM_STACK_START + 4
+            );
+            assert_error!(
+                m.store::<u8>(0, ebpf::MM_STACK_START),
+                "AccessViolation(Store, {}, 1, \"account #3 data\")",
+                ebpf::MM_STACK_START
+            );
+            assert_error!(
+                m.map(AccessType::Load, ebpf::MM_STACK_START + 0x20, 1),
+                "AccessViolation(Load, {}, 1, \"account #3 data\")",
+                ebpf::MM_STACK_START + 0x20
+            );
+            if !aligned_memory_mapping {
+                assert_error!(
+                    m.load::<u16>(ebpf::MM_STACK_START + 0x107),
+                    "AccessViolation(Load, {}, 2, \"account #4 data\")",
+                    ebpf::MM_STACK_START + 0x107
+                );
+            }
+            // Labels do not reach into other sections
+            assert_error!(
+                m.load::<u8>(ebpf::MM_HEAP_START),
+                "AccessViolation(Load, {}, 1, \"heap\")",
+                ebpf::MM_HEAP_START
+            );
+            assert_error!(
+                m.load::<u16>(ebpf::MM_PROGRAM_START + 7),
+                "AccessViolation(Load, {}, 2, \"program\")",
+                ebpf::MM_PROGRAM_START + 7
+            );
+        }
+    }
+
+    #[test]
+    fn test_initialization_tracking() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem1 = [0; 8];
+            let mut mem2 = [0; 8];
+            let mut m = MemoryMapping::new(
+                vec![
+                    MemoryRegion::new_writable(&mut mem1, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut mem2, ebpf::MM_STACK_START),
+                ],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            assert!(!m.tracks_initialization());
+            assert!(m.is_initialized(ebpf::MM_STACK_START, 8));
+            m.set_initialization_tracking(true);
+            assert!(m.tracks_initialization());
+            assert!(!m.is_initialized(ebpf::MM_STACK_START, 1));
+            // Only the stack and heap are tracked
+            assert!(m.is_initialized(ebpf::MM_PROGRAM_START, 8));
+
+            m.store::<u16>(0, ebpf::MM_STACK_START + 1).unwrap();
+            m.load::<u8>(ebpf::MM_STACK_START + 4).unwrap();
+            m.map(AccessType::Store, ebpf::MM_STACK_START + 6, 2)
+                .unwrap();
+            assert!(m.is_initialized(ebpf::MM_STACK_START + 1, 2));
+            assert!(m.is_initialized(ebpf::MM_STACK_START + 6, 2));
+            assert!(!m.is_initialized(ebpf::MM_STACK_START, 2));
+            assert!(!m.is_initialized(ebpf::MM_STACK_START + 2, 2));
+            assert!(!m.is_initialized(ebpf::MM_STACK_START + 4, 1));
+
+            m.reset_initialization_tracking();
+            assert!(!m.is_initialized(ebpf::MM_STACK_START + 1, 2));
+            m.store::<u16>(0, ebpf::MM_STACK_START + 1).unwrap();
+            m.set_initialization_tracking(false);
+            assert!(m.is_initialized(ebpf::MM_STACK_START, 8));
+            m.set_initialization_tracking(true);
+            assert!(!m.is_initialized(ebpf::MM_STACK_START + 1, 2));
+        }
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        for aligned_memory_mapping in [false, true] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mut mem = [0; 8];
+            let mut m = MemoryMapping::new(
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_PROGRAM_START)],
+                &config,
+                &SBPFVersion::V2,
+            )
+            .unwrap();
+            assert!(!m.add_watchpoint(ebpf::MM_PROGRAM_START, 0, WatchpointKind::Any));
+            assert!(!m.has_active_watchpoints());
+            assert!(m.add_watchpoint(ebpf::MM_PROGRAM_START + 2, 2, WatchpointKind::Write));
+            assert!(m.has_active_watchpoints());
+
+            m.load::<u32>(ebpf::MM_PROGRAM_START).unwrap();
+            m.store::<u16>(0x1111, ebpf::MM_PROGRAM_START).unwrap();
+            m.store::<u8>(0x11, ebpf::MM_PROGRAM_START + 4).unwrap();
+            assert_error!(
+                m.store::<u16>(0x2222, ebpf::MM_PROGRAM_START + 1),
+                "WatchpointHit(Store, {}, 2)",
+                ebpf::MM_PROGRAM_START + 1
+            );
+            assert_eq!(
+                m.load_unwatched::<u32>(ebpf::MM_PROGRAM_START).unwrap(),
+                0x1111
+            );
+            // Translations are not watched
+            m.map(AccessType::Store, ebpf::MM_PROGRAM_START + 2, 1)
+                .unwrap();
+
+            m.set_watchpoints_enabled(false);
+            assert!(!m.has_active_watchpoints());
+            m.store::<u16>(0x2222, ebpf::MM_PROGRAM_START + 1).unwrap();
+            m.set_watchpoints_enabled(true);
+
+            assert!(!m.remove_watchpoint(ebpf::MM_PROGRAM_START + 2, 2, WatchpointKind::Any));
+            assert!(m.remove_watchpoint(ebpf::MM_PROGRAM_START + 2, 2, WatchpointKind::Write));
+            assert!(!m.has_active_watchpoints());
+            m.store::<u16>(0x3333, ebpf::MM_PROGRAM_START + 1).unwrap();
+
+            assert!(m.add_watchpoint(ebpf::MM_PROGRAM_START + 7, 1, WatchpointKind::Any));
+            assert!(m.add_watchpoint(ebpf::MM_PROGRAM_START, 1, WatchpointKind::Read));
+            m.store::<u8>(0, ebpf::MM_PROGRAM_START).unwrap();
+            assert_error!(m.load::<u8>(ebpf::MM_PROGRAM_START), "WatchpointHit(Load");
+            assert_error!(m.load::<u64>(ebpf::MM_PROGRAM_START), "WatchpointHit(Load");
+            assert_error!(
+                m.store::<u32>(0, ebpf::MM_PROGRAM_START + 4),
+                "WatchpointHit(Store"
+            );
+        }
+
+        let mut m = MemoryMapping::Identity;
+        assert!(!m.add_watchpoint(0, 1, WatchpointKind::Any));
+        assert!(!m.has_active_watchpoints());
+    }
+
+    #[test]
+    fn test_shared_memory() {
+        let image = SharedMemory::new(vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let threads = [false, true]
+            .iter()
+            .map(|&aligned_memory_mapping| {
+                let image = image.clone();
+                std::thread::spawn(move || {
+                    let config = Config {
+                        aligned_memory_mapping,
+                        ..Config::default()
+                    };
+                    let m = MemoryMapping::new(
+                        vec![MemoryRegion::new_shared(&image, ebpf::MM_PROGRAM_START)],
+                        &config,
+                        &SBPFVersion::V2,
+                    )
+                    .unwrap();
+                    assert_error!(m.store::<u8>(0, ebpf::MM_PROGRAM_START), "AccessViolation");
+                    m.load::<u64>(ebpf::MM_PROGRAM_START).unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 0x0807060504030201);
+        }
+        assert_eq!(
+            MemoryRegion::new_shared(&image, 0).host_addr.get(),
+            image.as_slice().as_ptr() as u64
+        );
+    }
+
     #[test]
     fn test_unaligned_map_overlap() {
         let config = Config::default();
@@ -1094,6 +3026,109 @@ mod test {
             &SBPFVersion::V2,
         )
         .is_ok());
+        assert_error!(
+            UnalignedMemoryMapping::new(
+                vec![
+                    MemoryRegion::new_readonly(&[], ebpf::MM_INPUT_START),
+                    MemoryRegion::new_readonly(&[], ebpf::MM_INPUT_START),
+                ],
+                &config,
+                &SBPFVersion::V2,
+            ),
+            "InvalidMemoryRegion(1)"
+        );
+    }
+
+    #[test]
+    fn test_aligned_map_region_exceeds_slot() {
+        let config = Config::default();
+        let region = MemoryRegion::new_readonly(&[1, 2], ebpf::MM_STACK_START - 1);
+        assert_error!(
+            AlignedMemoryMapping::new(vec![region], &config, &SBPFVersion::V2),
+            "InvalidMemoryRegion(1)"
+        );
+    }
+
+    #[test]
+    fn test_canonical_region_order() {
+        use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+        let mem = [0xAAu8; 64];
+        let to_regions = |layout: &[(usize, usize, u64)]| {
+            layout
+                .iter()
+                .map(|(offset, len, vm_addr)| {
+                    MemoryRegion::new_readonly(&mem[*offset..][..*len], *vm_addr)
+                })
+                .collect::<Vec<_>>()
+        };
+        for seed in 0..64 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            for aligned_memory_mapping in [true, false] {
+                let config = Config {
+                    aligned_memory_mapping,
+                    ..Config::default()
+                };
+                // Random layout, either one region per slot or densely packed
+                let mut layout: Vec<(usize, usize, u64)> = Vec::new();
+                let mut vm_addr = ebpf::MM_PROGRAM_START;
+                for index in 0..rng.gen_range(1..8) {
+                    let len = rng.gen_range(0..8);
+                    if aligned_memory_mapping {
+                        vm_addr = ((index as u64 + 1) << ebpf::VIRTUAL_ADDRESS_BITS)
+                            + rng.gen_range(0..4);
+                    } else {
+                        // Regions may be adjacent, but must not start at the same address
+                        let previous_len = layout.last().map_or(1, |region| region.1);
+                        let min_gap = (len == 0 || previous_len == 0) as u64;
+                        vm_addr += rng.gen_range(min_gap..4);
+                    }
+                    layout.push((index * 8, len, vm_addr));
+                    vm_addr += len as u64;
+                }
+                let reference =
+                    MemoryMapping::new(to_regions(&layout), &config, &SBPFVersion::V2).unwrap();
+                assert_eq!(
+                    reference
+                        .regions()
+                        .iter()
+                        .map(|region| region.vm_addr)
+                        .collect::<Vec<_>>(),
+                    layout.iter().map(|region| region.2).collect::<Vec<_>>(),
+                );
+
+                // The order of the regions passed in does not matter
+                let mut shuffled = layout.clone();
+                shuffled.shuffle(&mut rng);
+                let m =
+                    MemoryMapping::new(to_regions(&shuffled), &config, &SBPFVersion::V2).unwrap();
+                assert_eq!(
+                    format!("{:?}", m.regions()),
+                    format!("{:?}", reference.regions())
+                );
+                for (_offset, len, start) in layout.iter() {
+                    for vm_addr in start.saturating_sub(2)..start + *len as u64 + 2 {
+                        assert_eq!(
+                            format!("{:?}", m.map(AccessType::Load, vm_addr, 1)),
+                            format!("{:?}", reference.map(AccessType::Load, vm_addr, 1)),
+                        );
+                    }
+                }
+
+                // Duplicates and overlaps are rejected, whatever their position
+                let (_offset, len, start) = *layout.choose(&mut rng).unwrap();
+                let mut invalid = layout.clone();
+                invalid.push(if len > 1 && rng.gen() {
+                    (0, 1, start + 1)
+                } else {
+                    (0, 0, start)
+                });
+                invalid.shuffle(&mut rng);
+                assert!(
+                    MemoryMapping::new(to_regions(&invalid), &config, &SBPFVersion::V2).is_err()
+                );
+            }
+        }
     }
 
     #[test]
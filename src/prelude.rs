@@ -0,0 +1,45 @@
+//! Stable public API
+//!
+//! Everything which is needed to load, verify and run programs and to declare built-in
+//! functions. These re-exports keep their names and paths across minor versions, even if the
+//! items move between modules internally, so embedders can `use solana_rbpf::prelude::*;`
+//! instead of importing from the individual modules. Newer interfaces which may still change
+//! are collected in `solana_rbpf::unstable` (requires the `unstable` feature).
+//!
+//! ```
+//! use solana_rbpf::prelude::*;
+//! use std::sync::Arc;
+//!
+//! let prog = &[
+//!     0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+//!     0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+//! ];
+//! let loader = Arc::new(BuiltinProgram::new_mock());
+//! let executable = Executable::<TestContextObject>::from_text_bytes(
+//!     prog,
+//!     loader,
+//!     SBPFVersion::V2,
+//!     FunctionRegistry::default(),
+//! )
+//! .unwrap();
+//! executable.verify::<RequisiteVerifier>().unwrap();
+//! let mut context_object = TestContextObject::new(2);
+//! let config = executable.get_config();
+//! let sbpf_version = executable.get_sbpf_version();
+//! let memory_mapping = MemoryMapping::new(Vec::new(), config, sbpf_version).unwrap();
+//! let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, 0).unwrap();
+//! let (instruction_count, result) = vm.execute_program(&executable, true);
+//! assert_eq!(instruction_count, 2);
+//! assert_eq!(result.unwrap(), 42);
+//! ```
+
+pub use crate::{
+    aligned_memory::AlignedMemory,
+    declare_builtin_function, ebpf,
+    elf::{ElfError, Executable, UnresolvedSymbolPolicy},
+    error::{EbpfError, ProgramResult, StableResult},
+    memory_region::{AccessType, MemoryMapping, MemoryRegion},
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
+    verifier::{RequisiteVerifier, Verifier, VerifierError},
+    vm::{Config, ContextObject, EbpfVm, TestContextObject},
+};
@@ -3,13 +3,19 @@ use {
     crate::{
         ebpf,
         elf::ElfError,
-        vm::{Config, ContextObject, EbpfVm},
+        error::{EbpfError, ProgramResult},
+        memory_region::MemoryMapping,
+        vm::{get_runtime_environment_key, Config, ContextObject, EbpfVm},
+    },
+    std::{
+        collections::{btree_map::Entry, BTreeMap},
+        convert::TryFrom,
+        error::Error,
     },
-    std::collections::{btree_map::Entry, BTreeMap},
 };
 
 /// Defines a set of sbpf_version of an executable
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum SBPFVersion {
     /// The legacy format
     V1,
@@ -211,13 +217,58 @@ impl<T: Copy + PartialEq> FunctionRegistry<T> {
 /// Syscall function without context
 pub type BuiltinFunction<C> = fn(*mut EbpfVm<C>, u64, u64, u64, u64, u64);
 
+/// Maximum number of syscalls of a loader which can have faults injected
+pub const MAX_SYSCALL_FAULTS: usize = 8;
+
+/// Failure scheduled by `BuiltinProgram::inject_syscall_fault()`
+struct SyscallFault<C: ContextObject> {
+    /// Key of the syscall in the function registry
+    key: u32,
+    /// The implementation which was replaced by the trampoline
+    function: BuiltinFunction<C>,
+    /// Which invocation fails, counting from one
+    invocation: u64,
+    /// Message of the resulting `EbpfError::SyscallError`
+    error: String,
+}
+
+/// Resolves the name of a symbol to the key of a registered function, see
+/// `BuiltinProgram::set_symbol_resolver()`
+pub type SymbolResolver = Box<dyn Fn(&str) -> Option<u64> + Send + Sync>;
+
+/// Computes the cost of a syscall invocation from its arguments
+pub type SyscallCostFunction = Box<dyn Fn([u64; 5]) -> u64 + Send + Sync>;
+
+/// Cost charged against the instruction meter whenever a syscall is invoked, see
+/// `BuiltinProgram::set_syscall_cost()`
+pub enum SyscallCost {
+    /// The same cost for every invocation
+    Static(u64),
+    /// A cost which depends on the arguments of the invocation
+    Dynamic(SyscallCostFunction),
+}
+
+impl std::fmt::Debug for SyscallCost {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Self::Static(cost) => write!(f, "Static({})", cost),
+            Self::Dynamic(_) => write!(f, "Dynamic"),
+        }
+    }
+}
+
 /// Represents the interface to a fixed functionality program
-#[derive(Eq)]
 pub struct BuiltinProgram<C: ContextObject> {
     /// Holds the Config if this is a loader program
     config: Option<Box<Config>>,
     /// Function pointers by symbol
     functions: FunctionRegistry<BuiltinFunction<C>>,
+    /// Injected faults, indexed by the slot of their trampoline
+    syscall_faults: Vec<SyscallFault<C>>,
+    /// Consulted for calls to symbols which are not registered
+    symbol_resolver: Option<SymbolResolver>,
+    /// Costs charged before the syscalls run, by their keys
+    syscall_costs: BTreeMap<u32, SyscallCost>,
 }
 
 impl<C: ContextObject> PartialEq for BuiltinProgram<C> {
@@ -226,12 +277,17 @@ impl<C: ContextObject> PartialEq for BuiltinProgram<C> {
     }
 }
 
+impl<C: ContextObject> Eq for BuiltinProgram<C> {}
+
 impl<C: ContextObject> BuiltinProgram<C> {
     /// Constructs a loader built-in program
     pub fn new_loader(config: Config, functions: FunctionRegistry<BuiltinFunction<C>>) -> Self {
         Self {
             config: Some(Box::new(config)),
             functions,
+            syscall_faults: Vec::new(),
+            symbol_resolver: None,
+            syscall_costs: BTreeMap::new(),
         }
     }
 
@@ -240,6 +296,9 @@ impl<C: ContextObject> BuiltinProgram<C> {
         Self {
             config: None,
             functions,
+            syscall_faults: Vec::new(),
+            symbol_resolver: None,
+            syscall_costs: BTreeMap::new(),
         }
     }
 
@@ -248,6 +307,9 @@ impl<C: ContextObject> BuiltinProgram<C> {
         Self {
             config: Some(Box::default()),
             functions: FunctionRegistry::default(),
+            syscall_faults: Vec::new(),
+            symbol_resolver: None,
+            syscall_costs: BTreeMap::new(),
         }
     }
 
@@ -270,9 +332,236 @@ impl<C: ContextObject> BuiltinProgram<C> {
                 0
             })
             .saturating_add(self.functions.mem_size())
+            .saturating_add(self.syscall_faults.iter().fold(0, |state: usize, fault| {
+                state
+                    .saturating_add(std::mem::size_of_val(fault))
+                    .saturating_add(fault.error.capacity())
+            }))
+            .saturating_add(
+                self.syscall_costs
+                    .len()
+                    .saturating_mul(std::mem::size_of::<(u32, SyscallCost)>()),
+            )
+    }
+
+    /// Lets the embedder resolve calls to symbols which are not registered while relocating
+    ///
+    /// The resolver is called with the name of the symbol and returns the key of a function of
+    /// this loader, e.g. to keep programs working which call a syscall by a former name. Symbols
+    /// it does not resolve, or resolves to a key which is not registered, are handled according
+    /// to the `UnresolvedSymbolPolicy`. It must be set before the loader is shared with any
+    /// executable.
+    pub fn set_symbol_resolver(&mut self, resolver: SymbolResolver) {
+        self.symbol_resolver = Some(resolver);
+    }
+
+    /// Resolves an unregistered symbol through the resolver of the embedder
+    pub(crate) fn resolve_symbol(&self, name: &[u8]) -> Option<u32> {
+        let resolver = self.symbol_resolver.as_ref()?;
+        let key = u32::try_from(resolver(std::str::from_utf8(name).ok()?)?).ok()?;
+        self.functions.lookup_by_key(key).map(|_| key)
+    }
+
+    /// Charges `cost` against the instruction meter whenever the syscall `name` is invoked
+    ///
+    /// The cost is consumed from the `ContextObject` before the syscall runs, by the interpreter
+    /// and the JIT alike, so syscalls do not have to meter themselves. If it exceeds the remaining
+    /// budget, the syscall does not run and the program terminates with
+    /// `EbpfError::ExceededMaxInstructions`. Costs are only charged if
+    /// `Config::enable_instruction_meter` is set. Setting the cost of the same syscall again
+    /// replaces it. It must be called before the loader is shared with any executable.
+    pub fn set_syscall_cost(&mut self, name: &[u8], cost: SyscallCost) -> Result<(), EbpfError> {
+        let key = match self
+            .functions
+            .map
+            .iter()
+            .find(|(_key, (registered_name, _function))| registered_name.as_slice() == name)
+        {
+            Some((key, _)) => *key,
+            None => {
+                return Err(EbpfError::SyscallNotRegistered(ebpf::hash_symbol_name(
+                    name,
+                )))
+            }
+        };
+        self.syscall_costs.insert(key, cost);
+        Ok(())
+    }
+
+    /// Returns true if any syscall has a cost, see `BuiltinProgram::set_syscall_cost()`
+    pub(crate) fn has_syscall_costs(&self) -> bool {
+        !self.syscall_costs.is_empty()
+    }
+
+    /// Makes the `invocation`th call of the syscall `name` fail with `error`
+    ///
+    /// Invocations are counted from one by every VM on its own, across all executions of the VM,
+    /// including the JIT compiled ones, as long as the syscall is not replaced by
+    /// `Executable::rebind_syscall()`. The failing invocation does not run the syscall and
+    /// terminates the program with `EbpfError::SyscallError(error)`. All other invocations are
+    /// forwarded unchanged. Injecting a fault into the same syscall again replaces its schedule.
+    ///
+    /// This is meant for exercising error handling paths in tests, so it must be called before
+    /// the loader is shared with any executable.
+    pub fn inject_syscall_fault(
+        &mut self,
+        name: &[u8],
+        invocation: u64,
+        error: impl Into<String>,
+    ) -> Result<(), EbpfError> {
+        let key = ebpf::hash_symbol_name(name);
+        let (key, function) = match self
+            .functions
+            .map
+            .iter_mut()
+            .find(|(_key, (registered_name, _function))| registered_name.as_slice() == name)
+        {
+            Some((key, (_name, function))) => (*key, function),
+            None => return Err(EbpfError::SyscallNotRegistered(key)),
+        };
+        let error = error.into();
+        if let Some(fault) = self
+            .syscall_faults
+            .iter_mut()
+            .find(|fault| fault.key == key)
+        {
+            fault.invocation = invocation;
+            fault.error = error;
+            return Ok(());
+        }
+        let slot = self.syscall_faults.len();
+        let trampoline = *syscall_fault_trampolines::<C>()
+            .get(slot)
+            .ok_or(EbpfError::TooManySyscallFaults(MAX_SYSCALL_FAULTS))?;
+        self.syscall_faults.push(SyscallFault {
+            key,
+            function: std::mem::replace(function, trampoline),
+            invocation,
+            error,
+        });
+        Ok(())
     }
 }
 
+fn syscall_fault_trampolines<C: ContextObject>() -> [BuiltinFunction<C>; MAX_SYSCALL_FAULTS] {
+    [
+        syscall_fault_trampoline::<C, 0>,
+        syscall_fault_trampoline::<C, 1>,
+        syscall_fault_trampoline::<C, 2>,
+        syscall_fault_trampoline::<C, 3>,
+        syscall_fault_trampoline::<C, 4>,
+        syscall_fault_trampoline::<C, 5>,
+        syscall_fault_trampoline::<C, 6>,
+        syscall_fault_trampoline::<C, 7>,
+    ]
+}
+
+/// Stands in for the syscall with the fault in `SLOT`, see `BuiltinProgram::inject_syscall_fault()`
+fn syscall_fault_trampoline<C: ContextObject, const SLOT: usize>(
+    vm_pointer: *mut EbpfVm<C>,
+    arg_a: u64,
+    arg_b: u64,
+    arg_c: u64,
+    arg_d: u64,
+    arg_e: u64,
+) {
+    let vm = unsafe {
+        &mut *(vm_pointer
+            .cast::<u64>()
+            .offset((get_runtime_environment_key() as isize).wrapping_neg())
+            .cast::<EbpfVm<C>>())
+    };
+    let invocations = &mut vm.syscall_fault_invocations[SLOT];
+    *invocations = invocations.saturating_add(1);
+    let invocation = *invocations;
+    let fault = &vm.loader.syscall_faults[SLOT];
+    if invocation != fault.invocation {
+        let function = fault.function;
+        return function(vm_pointer, arg_a, arg_b, arg_c, arg_d, arg_e);
+    }
+    let error = fault.error.clone();
+    invoke_builtin_function(vm_pointer, |_context_object, _memory_mapping| {
+        Err(error.into())
+    });
+}
+
+/// VM interface of a BuiltinFunction around its Rust interface, see [declare_builtin_function]
+///
+/// Settles the instruction meter before and after `rust` runs and stores its result in the VM.
+/// A panic is deferred to `EbpfVm::builtin_panic`, as it can not unwind through JIT compiled
+/// code.
+#[doc(hidden)]
+pub fn invoke_builtin_function<C: ContextObject>(
+    vm_pointer: *mut EbpfVm<C>,
+    rust: impl FnOnce(&mut C, &mut MemoryMapping) -> Result<u64, Box<dyn Error>>,
+) {
+    let vm = unsafe {
+        &mut *(vm_pointer
+            .cast::<u64>()
+            .offset((get_runtime_environment_key() as isize).wrapping_neg())
+            .cast::<EbpfVm<C>>())
+    };
+    let enable_instruction_meter = vm.loader.get_config().enable_instruction_meter;
+    if enable_instruction_meter {
+        vm.context_object_pointer.consume(
+            vm.previous_instruction_meter
+                .saturating_sub(vm.due_insn_count),
+        );
+    }
+    let (context_object, memory_mapping) =
+        (&mut *vm.context_object_pointer, &mut vm.memory_mapping);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        rust(context_object, memory_mapping)
+    }));
+    vm.program_result = match result {
+        Ok(result) => result.map_err(EbpfError::SyscallError).into(),
+        Err(payload) => {
+            vm.builtin_panic = Some(payload);
+            ProgramResult::Err(EbpfError::SyscallError("built-in function panicked".into()))
+        }
+    };
+    if enable_instruction_meter {
+        vm.previous_instruction_meter = vm.context_object_pointer.get_remaining();
+    }
+}
+
+/// Charges the cost of the syscall in `EbpfVm::invoked_syscall` before it runs, see
+/// `BuiltinProgram::set_syscall_cost()`
+///
+/// Leaves `EbpfError::ExceededMaxInstructions` in the program result if the remaining budget
+/// does not cover the cost, in which case the syscall must not be invoked.
+pub(crate) fn charge_syscall_cost<C: ContextObject>(
+    vm_pointer: *mut EbpfVm<C>,
+    arg_a: u64,
+    arg_b: u64,
+    arg_c: u64,
+    arg_d: u64,
+    arg_e: u64,
+) {
+    let vm = unsafe {
+        &mut *(vm_pointer
+            .cast::<u64>()
+            .offset((get_runtime_environment_key() as isize).wrapping_neg())
+            .cast::<EbpfVm<C>>())
+    };
+    let cost = match vm.loader.syscall_costs.get(&(vm.invoked_syscall as u32)) {
+        Some(SyscallCost::Static(cost)) => *cost,
+        Some(SyscallCost::Dynamic(cost)) => cost([arg_a, arg_b, arg_c, arg_d, arg_e]),
+        None => return,
+    };
+    vm.context_object_pointer.consume(
+        vm.previous_instruction_meter
+            .saturating_sub(vm.due_insn_count),
+    );
+    let remaining = vm.context_object_pointer.get_remaining();
+    if cost > remaining {
+        vm.program_result = ProgramResult::Err(EbpfError::ExceededMaxInstructions);
+    }
+    vm.context_object_pointer.consume(cost.min(remaining));
+    vm.previous_instruction_meter = vm.context_object_pointer.get_remaining();
+    vm.due_insn_count = vm.previous_instruction_meter;
+}
+
 impl<C: ContextObject> std::fmt::Debug for BuiltinProgram<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         writeln!(f, "{:?}", unsafe {
@@ -322,26 +611,89 @@ macro_rules! declare_builtin_function {
                 $arg_d: u64,
                 $arg_e: u64,
             ) {
-                use $crate::vm::ContextObject;
-                let vm = unsafe {
-                    &mut *($vm.cast::<u64>().offset(-($crate::vm::get_runtime_environment_key() as isize)).cast::<$crate::vm::EbpfVm<$ContextObject>>())
-                };
-                let config = vm.loader.get_config();
-                if config.enable_instruction_meter {
-                    vm.context_object_pointer.consume(vm.previous_instruction_meter - vm.due_insn_count);
-                }
-                let converted_result: $crate::error::ProgramResult = Self::rust $(::<$($generic_ident),+>)?(
-                    vm.context_object_pointer, $arg_a, $arg_b, $arg_c, $arg_d, $arg_e, &mut vm.memory_mapping,
-                ).map_err(|err| $crate::error::EbpfError::SyscallError(err)).into();
-                vm.program_result = converted_result;
-                if config.enable_instruction_meter {
-                    vm.previous_instruction_meter = vm.context_object_pointer.get_remaining();
-                }
+                $crate::program::invoke_builtin_function($vm, |context_object, memory_mapping| {
+                    Self::rust $(::<$($generic_ident),+>)?(
+                        context_object, $arg_a, $arg_b, $arg_c, $arg_d, $arg_e, memory_mapping,
+                    )
+                });
             }
         }
     };
 }
 
+/// Generates a BuiltinFunction like [declare_builtin_function] from a typed Rust signature
+///
+/// The parameters following the `MemoryMapping` implement [SyscallArg](crate::guest_abi::SyscallArg)
+/// and are translated from the argument registers in order, slices and strings taking two of
+/// them. A translation error fails the syscall before the body runs, this includes parameters
+/// overlapping a mutable one.
+///
+/// ```
+/// use solana_rbpf::{declare_syscall, memory_region::MemoryMapping, vm::TestContextObject};
+///
+/// declare_syscall!(
+///     /// Returns the number of bytes of the message
+///     SyscallLen,
+///     fn rust(
+///         _context_object: &mut TestContextObject,
+///         _memory_mapping: &mut MemoryMapping,
+///         message: &str,
+///     ) -> Result<u64, Box<dyn std::error::Error>> {
+///         Ok(message.len() as u64)
+///     }
+/// );
+/// ```
+#[cfg(feature = "unstable")]
+#[macro_export]
+macro_rules! declare_syscall {
+    ($(#[$attr:meta])* $name:ident $(<$($generic_ident:tt : $generic_type:tt),+>)?, fn rust(
+        $vm:ident : &mut $ContextObject:ty,
+        $memory_mapping:ident : &mut $MemoryMapping:ty,
+        $($arg:ident : $arg_type:ty),* $(,)?
+    ) -> $Result:ty { $($rust:tt)* }) => {
+        $crate::declare_builtin_function!(
+            $(#[$attr])*
+            $name $(<$($generic_ident : $generic_type),+>)?,
+            fn rust(
+                $vm: &mut $ContextObject,
+                arg_a: u64,
+                arg_b: u64,
+                arg_c: u64,
+                arg_d: u64,
+                arg_e: u64,
+                $memory_mapping: &mut $MemoryMapping,
+            ) -> $Result {
+                #[allow(clippy::arithmetic_side_effects)]
+                const _: () = assert!(
+                    0 $(+ <$arg_type as $crate::guest_abi::SyscallArg>::REGISTERS)* <= 5,
+                    "parameters exceed the five argument registers"
+                );
+                #[allow(clippy::too_many_arguments)]
+                fn typed $(<$($generic_ident : $generic_type),+>)? (
+                    $vm: &mut $ContextObject,
+                    $memory_mapping: &mut $MemoryMapping,
+                    $($arg: $arg_type),*
+                ) -> $Result {
+                    $($rust)*
+                }
+                let registers: &[u64] = &[arg_a, arg_b, arg_c, arg_d, arg_e];
+                let borrows = $crate::guest_abi::GuestBorrows::default();
+                $(
+                    let (arg_registers, registers) = registers
+                        .split_at(<$arg_type as $crate::guest_abi::SyscallArg>::REGISTERS);
+                    let $arg = <$arg_type as $crate::guest_abi::SyscallArg>::translate(
+                        &*$memory_mapping,
+                        &borrows,
+                        arg_registers,
+                    )?;
+                )*
+                let _ = registers;
+                typed $(::<$($generic_ident),+>)? ($vm, $memory_mapping, $($arg),*)
+            }
+        );
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
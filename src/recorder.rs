@@ -0,0 +1,79 @@
+//! Recording of interpreted executions, so that they can be stepped backwards
+//!
+//! While an [ExecutionRecorder] is attached with `Interpreter::start_recording()`, every step
+//! logs the registers and the VM state it is about to change, as well as the previous contents
+//! of the memory which it stores to. `Interpreter::step_back()` undoes the steps one by one.
+//! The debugger uses this for reverse-step and reverse-continue.
+//!
+//! Memory written by syscalls and the zeroing of stack frames are not recorded, so stepping
+//! back over them only restores the registers. Neither is the instruction meter of the context
+//! object refunded.
+
+use std::collections::VecDeque;
+
+/// State before one interpreted instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionRecord {
+    /// General purpose registers and pc
+    pub registers: [u64; 12],
+    /// Call depth of the VM
+    pub call_depth: u64,
+    /// Stack pointer of the VM
+    pub stack_pointer: u64,
+    /// Instructions which were not yet passed to `ContextObject::consume()`
+    pub due_insn_count: u64,
+    /// Previous contents of the stored to memory, by virtual address in order of the stores
+    pub memory_writes: Vec<(u64, Vec<u8>)>,
+}
+
+/// Bounded log of [ExecutionRecord]s, the oldest are dropped once it is full
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionRecorder {
+    capacity: usize,
+    records: VecDeque<ExecutionRecord>,
+}
+
+impl ExecutionRecorder {
+    /// Creates an empty log which holds up to `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Records of the instructions which can be undone, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &ExecutionRecord> {
+        self.records.iter()
+    }
+
+    /// Number of instructions which can be undone
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Whether there is no instruction to undo
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, record: ExecutionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub(crate) fn record_memory_write(&mut self, vm_addr: u64, previous: &[u8]) {
+        if let Some(record) = self.records.back_mut() {
+            record.memory_writes.push((vm_addr, previous.to_vec()));
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<ExecutionRecord> {
+        self.records.pop_back()
+    }
+}
@@ -0,0 +1,106 @@
+//! Allocation of the memory which holds JIT compiled programs
+//!
+//! By default the pc_section and the text section of a compiled program are mapped with mmap,
+//! see [PageAllocator]. Embedders which want to place the machine code elsewhere, e.g. in an
+//! arena of their own, on huge pages or in memfd backed regions, implement [SectionAllocator]
+//! and pass it to `Executable::jit_compile_with_allocator()`.
+
+use crate::{
+    error::EbpfError,
+    memory_management::{
+        allocate_dual_mapped_pages, allocate_pages, free_pages, get_system_page_size,
+    },
+};
+
+/// Provides the memory of JIT compiled programs
+///
+/// All allocations must be aligned to `page_size()` and must support changing the protection
+/// of their pages with mprotect, as the compiler makes the text section executable and the
+/// pc_section read-only once they are written.
+pub trait SectionAllocator: Send + Sync {
+    /// Granularity of the allocations and of the protection changes in bytes
+    fn page_size(&self) -> usize {
+        get_system_page_size()
+    }
+
+    /// Allocates `size_in_bytes` of readable and writable memory
+    ///
+    /// # Safety
+    /// `size_in_bytes` is a non-zero multiple of `page_size()`.
+    unsafe fn allocate(&self, size_in_bytes: usize) -> Result<*mut u8, EbpfError>;
+
+    /// Maps the same `size_in_bytes` of memory twice, returns the read-only mapping first
+    ///
+    /// Only used if `Config::enable_jit_dual_mapping` is set. Each mapping is released on its
+    /// own with `free()`.
+    ///
+    /// # Safety
+    /// `size_in_bytes` is a non-zero multiple of `page_size()`.
+    unsafe fn allocate_dual_mapped(
+        &self,
+        _size_in_bytes: usize,
+    ) -> Result<(*mut u8, *mut u8), EbpfError> {
+        Err(EbpfError::DualMappingUnsupported)
+    }
+
+    /// Gives the end of an allocation back, which the compiled program did not need
+    ///
+    /// Returns the size the allocation has afterwards. By default the allocation is kept as is.
+    ///
+    /// # Safety
+    /// `raw` and `size_in_bytes` describe a live allocation and `new_size_in_bytes` is a
+    /// multiple of `page_size()` which is not larger than `size_in_bytes`.
+    unsafe fn shrink(
+        &self,
+        _raw: *mut u8,
+        size_in_bytes: usize,
+        _new_size_in_bytes: usize,
+    ) -> Result<usize, EbpfError> {
+        Ok(size_in_bytes)
+    }
+
+    /// Releases an allocation
+    ///
+    /// Its pages are readable and writable again, like after `allocate()`.
+    ///
+    /// # Safety
+    /// `raw` and `size_in_bytes` describe a live allocation, its size being the one returned
+    /// by the last `shrink()` if any.
+    unsafe fn free(&self, raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError>;
+}
+
+/// Maps anonymous pages with mmap and dual mappings with memfd, which is the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageAllocator;
+
+impl SectionAllocator for PageAllocator {
+    unsafe fn allocate(&self, size_in_bytes: usize) -> Result<*mut u8, EbpfError> {
+        allocate_pages(size_in_bytes)
+    }
+
+    unsafe fn allocate_dual_mapped(
+        &self,
+        size_in_bytes: usize,
+    ) -> Result<(*mut u8, *mut u8), EbpfError> {
+        allocate_dual_mapped_pages(size_in_bytes)
+    }
+
+    unsafe fn shrink(
+        &self,
+        raw: *mut u8,
+        size_in_bytes: usize,
+        new_size_in_bytes: usize,
+    ) -> Result<usize, EbpfError> {
+        if size_in_bytes > new_size_in_bytes {
+            free_pages(
+                raw.add(new_size_in_bytes),
+                size_in_bytes.saturating_sub(new_size_in_bytes),
+            )?;
+        }
+        Ok(new_size_in_bytes)
+    }
+
+    unsafe fn free(&self, raw: *mut u8, size_in_bytes: usize) -> Result<(), EbpfError> {
+        free_pages(raw, size_in_bytes)
+    }
+}
@@ -6,6 +6,8 @@ use crate::{
     ebpf,
     elf::Executable,
     error::EbpfError,
+    program::SBPFVersion,
+    verifier::VerifierError,
     vm::{ContextObject, DynamicAnalysis, TestContextObject},
 };
 use rustc_demangle::demangle;
@@ -109,6 +111,165 @@ pub struct DfgEdge {
     pub resource: DataResource,
 }
 
+/// A timing side channel found by [Analysis::audit_constant_time]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Debug)]
+pub enum ConstantTimeViolation {
+    /// The jump or call at this pc depends on secret data
+    SecretDependentBranch(usize),
+    /// The address of the load or store at this pc depends on secret data
+    SecretDependentMemoryAccess(usize),
+}
+
+/// A finding of [Analysis::optimization_report]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Debug)]
+pub enum OptimizationHint {
+    /// The register written by the instruction at this pc is never read
+    UnusedResult(usize),
+    /// The conditional jump at this pc always jumps
+    BranchAlwaysTaken(usize),
+    /// The conditional jump at this pc never jumps
+    BranchNeverTaken(usize),
+    /// The basic block starting at this pc can not be reached from any function entry
+    UnreachableBasicBlock(usize),
+}
+
+/// Worst-case stack usage determined by [Analysis::stack_usage]
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum StackUsage {
+    /// The deepest call chain starting at the entrypoint
+    Bounded {
+        /// Bytes of stack used by all frames of the call chain
+        bytes: u64,
+        /// Number of frames in the call chain, including the entrypoint
+        call_depth: usize,
+    },
+    /// The function at this pc can call itself, directly or through other functions
+    Recursive(usize),
+    /// The callx at this pc has a target which is not known statically
+    IndirectCall(usize),
+}
+
+/// A read of a register which is not written on some path from the entrypoint
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Debug)]
+pub struct UninitializedRegisterRead {
+    /// The instruction which reads the register
+    pub pc: usize,
+    /// The register which is read
+    pub register: u8,
+}
+
+/// Stack frame and callees of one function
+#[derive(Default)]
+struct FunctionStackFrame {
+    size: u64,
+    callees: BTreeSet<usize>,
+    indirect_call: Option<usize>,
+}
+
+/// Registers r0 to r9 which an instruction reads and writes, as bit masks
+fn register_accesses(sbpf_version: &SBPFVersion, insn: &ebpf::Insn) -> (u16, u16) {
+    let dst = 1u16.checked_shl(insn.dst as u32).unwrap_or(0);
+    let src = 1u16.checked_shl(insn.src as u32).unwrap_or(0);
+    let src_if_reg = if insn.opc & ebpf::BPF_X != 0 { src } else { 0 };
+    let (reads, writes) = match insn.opc & ebpf::BPF_CLS_MASK {
+        ebpf::BPF_LD => (0, dst),
+        ebpf::BPF_LDX => (src, dst),
+        ebpf::BPF_ST => (dst, 0),
+        ebpf::BPF_STX => (src | dst, 0),
+        ebpf::BPF_ALU | ebpf::BPF_ALU64 if insn.opc & ebpf::BPF_ALU_OP_MASK == ebpf::BPF_MOV => {
+            (src_if_reg, dst)
+        }
+        ebpf::BPF_ALU | ebpf::BPF_ALU64 | ebpf::BPF_PQR => (dst | src_if_reg, dst),
+        _ => match insn.opc {
+            ebpf::JA | ebpf::CALL_IMM => (0, 0),
+            ebpf::CALL_REG => {
+                let target = if sbpf_version.callx_uses_src_reg() {
+                    insn.src as u32
+                } else {
+                    insn.imm as u32
+                };
+                (1u16.checked_shl(target).unwrap_or(0), 0)
+            }
+            ebpf::EXIT => (1, 0),
+            _ => (dst | src_if_reg, 0),
+        },
+    };
+    (reads & 0x3FF, writes & 0x3FF)
+}
+
+/// Known register values, None if not constant
+type ConstantRegisters = [Option<u64>; 12];
+
+/// Applies an instruction to the known register values (None if not constant)
+///
+/// Returns whether a conditional jump is taken, if that is decided by constant operands.
+fn propagate_constants(
+    sbpf_version: &SBPFVersion,
+    registers: &mut ConstantRegisters,
+    insn: &ebpf::Insn,
+) -> Option<bool> {
+    let dst = insn.dst as usize;
+    let imm = insn.imm as u64;
+    let src = registers.get(insn.src as usize).copied().flatten();
+    let operand = if insn.opc & ebpf::BPF_X != 0 {
+        src
+    } else {
+        Some(imm)
+    };
+    let fold = |registers: &mut ConstantRegisters, f: fn(u64, u64) -> u64| {
+        registers[dst] = registers[dst].zip(operand).map(|(a, b)| f(a, b));
+    };
+    match insn.opc {
+        ebpf::LD_DW_IMM | ebpf::MOV64_IMM => registers[dst] = Some(imm),
+        ebpf::MOV32_IMM => registers[dst] = Some(imm as u32 as u64),
+        ebpf::HOR64_IMM => registers[dst] = registers[dst].map(|a| a | imm.wrapping_shl(32)),
+        ebpf::MOV64_REG => registers[dst] = src,
+        ebpf::ADD64_IMM | ebpf::ADD64_REG => fold(registers, u64::wrapping_add),
+        ebpf::SUB64_IMM if sbpf_version.swap_sub_reg_imm_operands() => {
+            fold(registers, |a, b| b.wrapping_sub(a))
+        }
+        ebpf::SUB64_IMM | ebpf::SUB64_REG => fold(registers, u64::wrapping_sub),
+        ebpf::MUL64_IMM | ebpf::MUL64_REG => fold(registers, u64::wrapping_mul),
+        ebpf::OR64_IMM | ebpf::OR64_REG => fold(registers, |a, b| a | b),
+        ebpf::AND64_IMM | ebpf::AND64_REG => fold(registers, |a, b| a & b),
+        ebpf::XOR64_IMM | ebpf::XOR64_REG => fold(registers, |a, b| a ^ b),
+        ebpf::LSH64_IMM | ebpf::LSH64_REG => fold(registers, |a, b| a.wrapping_shl(b as u32)),
+        ebpf::RSH64_IMM | ebpf::RSH64_REG => fold(registers, |a, b| a.wrapping_shr(b as u32)),
+        ebpf::ARSH64_IMM | ebpf::ARSH64_REG => {
+            fold(registers, |a, b| (a as i64).wrapping_shr(b as u32) as u64)
+        }
+        ebpf::CALL_IMM | ebpf::CALL_REG => {
+            for register in registers.iter_mut().take(ebpf::FIRST_SCRATCH_REG) {
+                *register = None;
+            }
+        }
+        _ => match insn.opc & ebpf::BPF_CLS_MASK {
+            ebpf::BPF_LD | ebpf::BPF_LDX | ebpf::BPF_ALU | ebpf::BPF_ALU64 | ebpf::BPF_PQR => {
+                registers[dst] = None;
+            }
+            ebpf::BPF_JMP => {
+                let (a, b) = registers[dst].zip(operand)?;
+                return match insn.opc {
+                    ebpf::JEQ_IMM | ebpf::JEQ_REG => Some(a == b),
+                    ebpf::JGT_IMM | ebpf::JGT_REG => Some(a > b),
+                    ebpf::JGE_IMM | ebpf::JGE_REG => Some(a >= b),
+                    ebpf::JLT_IMM | ebpf::JLT_REG => Some(a < b),
+                    ebpf::JLE_IMM | ebpf::JLE_REG => Some(a <= b),
+                    ebpf::JSET_IMM | ebpf::JSET_REG => Some(a & b != 0),
+                    ebpf::JNE_IMM | ebpf::JNE_REG => Some(a != b),
+                    ebpf::JSGT_IMM | ebpf::JSGT_REG => Some(a as i64 > b as i64),
+                    ebpf::JSGE_IMM | ebpf::JSGE_REG => Some(a as i64 >= b as i64),
+                    ebpf::JSLT_IMM | ebpf::JSLT_REG => Some((a as i64) < b as i64),
+                    ebpf::JSLE_IMM | ebpf::JSLE_REG => Some(a as i64 <= b as i64),
+                    _ => None,
+                };
+            }
+            _ => {}
+        },
+    }
+    None
+}
+
 impl Default for CfgNode {
     fn default() -> Self {
         Self {
@@ -167,10 +328,10 @@ impl<'a> Analysis<'a> {
         let mut instructions = Vec::with_capacity(program.len() / ebpf::INSN_SIZE);
         let mut insn_ptr: usize = 0;
         while insn_ptr * ebpf::INSN_SIZE < program.len() {
-            let mut insn = ebpf::get_insn_unchecked(program, insn_ptr);
+            let mut insn = ebpf::get_insn_checked(program, insn_ptr)?;
             if insn.opc == ebpf::LD_DW_IMM {
                 insn_ptr += 1;
-                if insn_ptr * ebpf::INSN_SIZE >= program.len() {
+                if ebpf::get_insn_checked(program, insn_ptr).is_err() {
                     break;
                 }
                 ebpf::augment_lddw_unchecked(program, &mut insn);
@@ -415,6 +576,8 @@ impl<'a> Analysis<'a> {
     }
 
     /// Generates labels for assembler code
+    ///
+    /// If the executable has a profile, the labels are annotated with how often they were reached.
     pub fn disassemble_label<W: std::io::Write>(
         &self,
         output: &mut W,
@@ -428,7 +591,16 @@ impl<'a> Analysis<'a> {
                 if is_function && !suppress_extra_newlines {
                     writeln!(output)?;
                 }
-                writeln!(output, "{}:", cfg_node.label)?;
+                if let Some(profile) = self.executable.get_profile() {
+                    let counter: usize = profile
+                        .edges
+                        .values()
+                        .filter_map(|destinations| destinations.get(&pc))
+                        .sum();
+                    writeln!(output, "{}: // executed {} times", cfg_node.label, counter)?;
+                } else {
+                    writeln!(output, "{}:", cfg_node.label)?;
+                }
             }
             let last_insn = &self.instructions[cfg_node.instructions.end - 1];
             *last_basic_block = if last_insn.opc == ebpf::JA {
@@ -452,8 +624,12 @@ impl<'a> Analysis<'a> {
     }
 
     /// Generates assembler code for the analyzed executable
+    ///
+    /// If the executable has line number information, the first instruction of every source
+    /// line is annotated with its location.
     pub fn disassemble<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()> {
         let mut last_basic_block = usize::MAX;
+        let mut last_source_location = None;
         for insn in self.instructions.iter() {
             self.disassemble_label(
                 output,
@@ -461,7 +637,17 @@ impl<'a> Analysis<'a> {
                 insn.ptr,
                 &mut last_basic_block,
             )?;
-            writeln!(output, "    {}", self.disassemble_instruction(insn))?;
+            let source_location = self.executable.get_source_location(insn.ptr);
+            match source_location {
+                Some(location) if source_location != last_source_location => writeln!(
+                    output,
+                    "    {} // {}",
+                    self.disassemble_instruction(insn),
+                    location
+                )?,
+                _ => writeln!(output, "    {}", self.disassemble_instruction(insn))?,
+            }
+            last_source_location = source_location;
         }
         Ok(())
     }
@@ -486,7 +672,7 @@ impl<'a> Analysis<'a> {
         for (index, entry) in trace_log.iter().enumerate() {
             let pc = entry[11] as usize;
             let insn = &self.instructions[pc_to_insn_index[pc]];
-            writeln!(
+            write!(
                 output,
                 "{:5?} {:016X?} {:5?}: {}",
                 index,
@@ -494,6 +680,10 @@ impl<'a> Analysis<'a> {
                 pc,
                 self.disassemble_instruction(insn),
             )?;
+            match self.executable.get_source_location(pc) {
+                Some(location) => writeln!(output, " // {location}")?,
+                None => writeln!(output)?,
+            }
         }
         Ok(())
     }
@@ -611,10 +801,19 @@ impl<'a> Analysis<'a> {
             };
             let mut alias_nodes = HashSet::new();
             writeln!(output, "  subgraph cluster_{} {{", *function_start)?;
+            let timing = dynamic_analysis
+                .and_then(|dynamic_analysis| dynamic_analysis.function_timings.get(function_start))
+                .map(|timing| {
+                    format!(
+                        " ({} calls, {} ticks, {} self)",
+                        timing.calls, timing.ticks, timing.self_ticks
+                    )
+                })
+                .unwrap_or_default();
             writeln!(
                 output,
                 "    label={:?};",
-                html_escape(&self.cfg_nodes[function_start].label)
+                html_escape(&format!("{}{timing}", self.cfg_nodes[function_start].label))
             )?;
             writeln!(output, "    tooltip=lbb_{};", *function_start)?;
             emit_cfg_node(
@@ -1175,4 +1374,497 @@ impl<'a> Analysis<'a> {
             }
         }
     }
+
+    /// Propagates constant register values over the control-flow graph
+    ///
+    /// Returns the register values at the start of every reachable basic block and the
+    /// conditional jumps which are statically decided.
+    fn constant_block_inputs(&self) -> (BTreeMap<usize, ConstantRegisters>, BTreeMap<usize, bool>) {
+        let sbpf_version = self.executable.get_sbpf_version();
+        let mut block_inputs = BTreeMap::<usize, ConstantRegisters>::new();
+        let mut branch_decisions = BTreeMap::new();
+        let mut worklist = BTreeSet::new();
+        for function_start in self
+            .functions
+            .keys()
+            .chain(std::iter::once(&self.entrypoint))
+        {
+            if self.cfg_nodes.contains_key(function_start) {
+                block_inputs.insert(*function_start, [None; 12]);
+                worklist.insert(*function_start);
+            }
+        }
+        while let Some(cfg_node_start) = worklist.pop_first() {
+            let cfg_node = &self.cfg_nodes[&cfg_node_start];
+            let mut registers = block_inputs[&cfg_node_start];
+            let mut branch_decision = None;
+            for insn in self.instructions[cfg_node.instructions.clone()].iter() {
+                branch_decision = propagate_constants(sbpf_version, &mut registers, insn);
+            }
+            let last_insn = &self.instructions[cfg_node.instructions.end - 1];
+            if let Some(taken) = branch_decision {
+                branch_decisions.insert(last_insn.ptr, taken);
+            } else {
+                branch_decisions.remove(&last_insn.ptr);
+            }
+            let target_pc = (last_insn.ptr as isize + last_insn.off as isize + 1) as usize;
+            for destination in cfg_node.destinations.iter() {
+                let is_feasible = match branch_decision {
+                    Some(true) => *destination == target_pc,
+                    Some(false) => *destination == last_insn.ptr + 1,
+                    None => true,
+                };
+                if !is_feasible {
+                    continue;
+                }
+                let changed = match block_inputs.get_mut(destination) {
+                    Some(inputs) => {
+                        let mut changed = false;
+                        for (input, register) in inputs.iter_mut().zip(registers.iter()) {
+                            if input.is_some() && input != register {
+                                *input = None;
+                                changed = true;
+                            }
+                        }
+                        changed
+                    }
+                    None => {
+                        block_inputs.insert(*destination, registers);
+                        true
+                    }
+                };
+                if changed {
+                    worklist.insert(*destination);
+                }
+            }
+        }
+        (block_inputs, branch_decisions)
+    }
+
+    /// Reports what a constant propagation over the control-flow graph can prove
+    ///
+    /// Every function is analyzed on its own, starting with unknown registers, and only 64 bit
+    /// moves, mov32 and hor64 with immediates, lddw and simple 64 bit arithmetic are folded.
+    /// Conditional jumps with constant operands are statically decided, so the basic blocks
+    /// behind them can become unreachable. A result counts as unused if no edge of the data-flow
+    /// graph reads it, which ignores that the instruction might still fault (e.g. a load or a
+    /// division). Note that r6 to r9 are not considered to be read by exit.
+    pub fn optimization_report(&self) -> BTreeSet<OptimizationHint> {
+        let (block_inputs, branch_decisions) = self.constant_block_inputs();
+        let mut report = BTreeSet::new();
+        for (pc, taken) in branch_decisions {
+            report.insert(if taken {
+                OptimizationHint::BranchAlwaysTaken(pc)
+            } else {
+                OptimizationHint::BranchNeverTaken(pc)
+            });
+        }
+        for cfg_node_start in self.cfg_nodes.keys() {
+            if *cfg_node_start != self.super_root && !block_inputs.contains_key(cfg_node_start) {
+                report.insert(OptimizationHint::UnreachableBasicBlock(*cfg_node_start));
+            }
+        }
+        for insn in self.instructions.iter() {
+            let writes_dst = matches!(
+                insn.opc & ebpf::BPF_CLS_MASK,
+                ebpf::BPF_LD | ebpf::BPF_LDX | ebpf::BPF_ALU | ebpf::BPF_ALU64 | ebpf::BPF_PQR
+            );
+            if !writes_dst || insn.dst as usize >= ebpf::FRAME_PTR_REG {
+                continue;
+            }
+            let is_read = self
+                .dfg_forward_edges
+                .get(&DfgNode::InstructionNode(insn.ptr))
+                .map(|edges| {
+                    edges.iter().any(|edge| {
+                        edge.kind == DfgEdgeKind::Filled
+                            && edge.resource == DataResource::Register(insn.dst)
+                    })
+                })
+                .unwrap_or(false);
+            if !is_read {
+                report.insert(OptimizationHint::UnusedResult(insn.ptr));
+            }
+        }
+        report
+    }
+
+    /// Audits a trace log for secret dependent control flow and memory accesses
+    ///
+    /// This is a first-cut dynamic taint analysis: The bytes in the `secret` range of the VM
+    /// address space are tainted and the taint is propagated along the executed path. It only
+    /// covers what the trace observed, so the trace log must contain every instruction
+    /// (Config::enable_instruction_tracing and TraceSampling::All). Syscalls taint r0 if any
+    /// of their arguments is tainted, but their effects on memory are not tracked.
+    ///
+    /// Fails with `EbpfError::InstructionOutOfBounds` if the trace log contains a pc which is
+    /// not the start of an instruction of this executable, e.g. because it was recorded with
+    /// another one.
+    pub fn audit_constant_time(
+        &self,
+        trace_log: &[TraceLogEntry],
+        secret: std::ops::Range<u64>,
+    ) -> Result<BTreeSet<ConstantTimeViolation>, EbpfError> {
+        let mut pc_to_insn_index = vec![
+            None;
+            self.instructions
+                .last()
+                .map(|insn| insn.ptr + 1)
+                .unwrap_or(0)
+        ];
+        for (index, insn) in self.instructions.iter().enumerate() {
+            pc_to_insn_index[insn.ptr] = Some(index);
+        }
+        let mut violations = BTreeSet::new();
+        let mut registers = [false; 11];
+        // Memory written by the program, overriding the initial taint of the secret range
+        let mut memory = HashMap::<u64, bool>::new();
+        let is_tainted = |memory: &HashMap<u64, bool>, address: u64| {
+            memory
+                .get(&address)
+                .copied()
+                .unwrap_or_else(|| secret.contains(&address))
+        };
+        let mut call_frames = Vec::new();
+        for entry in trace_log.iter() {
+            let pc = entry[11] as usize;
+            let insn = pc_to_insn_index
+                .get(pc)
+                .copied()
+                .flatten()
+                .and_then(|index| self.instructions.get(index))
+                .ok_or(EbpfError::InstructionOutOfBounds(pc))?;
+            let dst = insn.dst as usize;
+            let src = insn.src as usize;
+            let access_size = match insn.opc & 0x18 {
+                ebpf::BPF_B => 1,
+                ebpf::BPF_H => 2,
+                ebpf::BPF_W => 4,
+                _ => 8,
+            };
+            match insn.opc & ebpf::BPF_CLS_MASK {
+                ebpf::BPF_LD => registers[dst] = false,
+                ebpf::BPF_LDX => {
+                    if registers[src] {
+                        violations.insert(ConstantTimeViolation::SecretDependentMemoryAccess(pc));
+                    }
+                    let address = entry[src].wrapping_add(insn.off as i64 as u64);
+                    registers[dst] = registers[src]
+                        || (0..access_size)
+                            .any(|offset| is_tainted(&memory, address.wrapping_add(offset)));
+                }
+                ebpf::BPF_ST | ebpf::BPF_STX => {
+                    if registers[dst] {
+                        violations.insert(ConstantTimeViolation::SecretDependentMemoryAccess(pc));
+                    }
+                    let address = entry[dst].wrapping_add(insn.off as i64 as u64);
+                    let value_is_tainted =
+                        insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_STX && registers[src];
+                    for offset in 0..access_size {
+                        memory.insert(address.wrapping_add(offset), value_is_tainted);
+                    }
+                }
+                ebpf::BPF_ALU | ebpf::BPF_ALU64 | ebpf::BPF_PQR => {
+                    let src_is_tainted = insn.opc & ebpf::BPF_X != 0 && registers[src];
+                    if insn.opc & ebpf::BPF_ALU_OP_MASK == ebpf::BPF_MOV
+                        && insn.opc & ebpf::BPF_CLS_MASK != ebpf::BPF_PQR
+                    {
+                        registers[dst] = src_is_tainted;
+                    } else {
+                        registers[dst] |= src_is_tainted;
+                    }
+                }
+                ebpf::BPF_JMP => match insn.opc {
+                    ebpf::JA => {}
+                    ebpf::CALL_IMM | ebpf::CALL_REG => {
+                        if insn.opc == ebpf::CALL_REG {
+                            let target = if self.executable.get_sbpf_version().callx_uses_src_reg()
+                            {
+                                src
+                            } else {
+                                insn.imm as usize
+                            };
+                            if registers.get(target).copied().unwrap_or(false) {
+                                violations.insert(ConstantTimeViolation::SecretDependentBranch(pc));
+                            }
+                        }
+                        if insn.opc == ebpf::CALL_IMM && self.internal_call_target(insn).is_none() {
+                            // Syscall
+                            registers[0] = registers[1..6].iter().any(|tainted| *tainted);
+                        } else {
+                            let mut caller_saved_registers = [false; ebpf::SCRATCH_REGS];
+                            caller_saved_registers.copy_from_slice(
+                                &registers[ebpf::FIRST_SCRATCH_REG
+                                    ..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS],
+                            );
+                            call_frames.push(caller_saved_registers);
+                        }
+                    }
+                    ebpf::EXIT => {
+                        if let Some(caller_saved_registers) = call_frames.pop() {
+                            registers[ebpf::FIRST_SCRATCH_REG
+                                ..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS]
+                                .copy_from_slice(&caller_saved_registers);
+                        }
+                    }
+                    _ => {
+                        if registers[dst] || (insn.opc & ebpf::BPF_X != 0 && registers[src]) {
+                            violations.insert(ConstantTimeViolation::SecretDependentBranch(pc));
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Bounds the stack usage by walking the call graph from the entrypoint
+    ///
+    /// With fixed stack frames every function uses one frame of `Config::stack_frame_size`.
+    /// With dynamic stack frames the frame of a function is the sum of all the stack space it
+    /// allocates via `add64 r11, -imm`, ignoring any releases in between, which overestimates
+    /// functions that resize their frame more than once. Recursion and indirect calls can not
+    /// be bounded statically and are reported instead.
+    pub fn stack_usage(&self) -> StackUsage {
+        let config = self.executable.get_config();
+        let sbpf_version = self.executable.get_sbpf_version();
+        let mut frames: BTreeMap<usize, FunctionStackFrame> = self
+            .functions
+            .keys()
+            .map(|pc| (*pc, FunctionStackFrame::default()))
+            .collect();
+        frames.entry(self.entrypoint).or_default();
+        for insn in self.instructions.iter() {
+            let frame = match frames.range_mut(..=insn.ptr).next_back() {
+                Some((_function_pc, frame)) => frame,
+                None => continue,
+            };
+            match insn.opc {
+                ebpf::ADD64_IMM
+                    if sbpf_version.dynamic_stack_frames()
+                        && insn.dst as usize == ebpf::STACK_PTR_REG
+                        && insn.imm < 0 =>
+                {
+                    frame.size = frame.size.saturating_add(insn.imm.unsigned_abs());
+                }
+                ebpf::CALL_IMM => {
+                    if let Some(target_pc) = self.internal_call_target(insn) {
+                        frame.callees.insert(target_pc);
+                    }
+                }
+                ebpf::CALL_REG => {
+                    frame.indirect_call.get_or_insert(insn.ptr);
+                }
+                _ => {}
+            }
+        }
+        if !sbpf_version.dynamic_stack_frames() {
+            for frame in frames.values_mut() {
+                frame.size = config.stack_frame_size as u64;
+            }
+        }
+        match Self::worst_case_call_chain(
+            &frames,
+            self.entrypoint,
+            &mut BTreeSet::new(),
+            &mut BTreeMap::new(),
+        ) {
+            Ok((bytes, call_depth)) => StackUsage::Bounded { bytes, call_depth },
+            Err(unbounded) => unbounded,
+        }
+    }
+
+    fn worst_case_call_chain(
+        frames: &BTreeMap<usize, FunctionStackFrame>,
+        function_pc: usize,
+        visiting: &mut BTreeSet<usize>,
+        visited: &mut BTreeMap<usize, (u64, usize)>,
+    ) -> Result<(u64, usize), StackUsage> {
+        if let Some(usage) = visited.get(&function_pc) {
+            return Ok(*usage);
+        }
+        let frame = match frames.get(&function_pc) {
+            Some(frame) => frame,
+            None => return Ok((0, 1)),
+        };
+        if !visiting.insert(function_pc) {
+            return Err(StackUsage::Recursive(function_pc));
+        }
+        if let Some(pc) = frame.indirect_call {
+            return Err(StackUsage::IndirectCall(pc));
+        }
+        let (mut bytes, mut call_depth) = (0u64, 0usize);
+        for callee_pc in frame.callees.iter() {
+            let usage = Self::worst_case_call_chain(frames, *callee_pc, visiting, visited)?;
+            bytes = bytes.max(usage.0);
+            call_depth = call_depth.max(usage.1);
+        }
+        visiting.remove(&function_pc);
+        let usage = (bytes.saturating_add(frame.size), call_depth + 1);
+        visited.insert(function_pc, usage);
+        Ok(usage)
+    }
+
+    /// Rejects programs which overflow the stack or exceed the call depth in the worst case
+    ///
+    /// Programs whose stack usage can not be bounded statically are accepted, the returned
+    /// [StackUsage] tells the caller why so that it can warn about them instead.
+    pub fn verify_stack_usage(&self) -> Result<StackUsage, VerifierError> {
+        let config = self.executable.get_config();
+        let usage = self.stack_usage();
+        if let StackUsage::Bounded { bytes, call_depth } = usage {
+            if bytes > config.stack_size() as u64 || call_depth > config.max_call_depth {
+                return Err(VerifierError::StackOverflow(bytes, call_depth));
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Finds reads of r0 to r9 which are not preceded by a write on every path from the entrypoint
+    ///
+    /// r1 and r2 are inputs of the entrypoint. Internal calls start with the registers which
+    /// are initialized at the call site and every call initializes r0. Functions which are only
+    /// reached through callx are not analyzed.
+    pub fn uninitialized_register_reads(&self) -> BTreeSet<UninitializedRegisterRead> {
+        let sbpf_version = self.executable.get_sbpf_version();
+        let mut block_inputs = BTreeMap::<usize, u16>::new();
+        let mut worklist = BTreeSet::new();
+        let mut reads = BTreeSet::new();
+        let merge = |block_inputs: &mut BTreeMap<usize, u16>,
+                     worklist: &mut BTreeSet<usize>,
+                     destination: usize,
+                     state: u16| {
+            let changed = match block_inputs.get_mut(&destination) {
+                Some(inputs) => {
+                    let merged = *inputs & state;
+                    std::mem::replace(inputs, merged) != merged
+                }
+                None => {
+                    block_inputs.insert(destination, state);
+                    true
+                }
+            };
+            if changed {
+                worklist.insert(destination);
+            }
+        };
+        if self.cfg_nodes.contains_key(&self.entrypoint) {
+            merge(&mut block_inputs, &mut worklist, self.entrypoint, 0b110);
+        }
+        while let Some(cfg_node_start) = worklist.pop_first() {
+            let cfg_node = &self.cfg_nodes[&cfg_node_start];
+            let mut state = block_inputs[&cfg_node_start];
+            for insn in self.instructions[cfg_node.instructions.clone()].iter() {
+                let (read, written) = register_accesses(sbpf_version, insn);
+                for register in 0..ebpf::FRAME_PTR_REG as u8 {
+                    if read & !state & (1 << register) != 0 {
+                        reads.insert(UninitializedRegisterRead {
+                            pc: insn.ptr,
+                            register,
+                        });
+                    }
+                }
+                state |= written;
+                if insn.opc == ebpf::CALL_IMM {
+                    if let Some(target_pc) = self.internal_call_target(insn) {
+                        if self.cfg_nodes.contains_key(&target_pc) {
+                            merge(&mut block_inputs, &mut worklist, target_pc, state);
+                        }
+                    }
+                }
+                if insn.opc == ebpf::CALL_IMM || insn.opc == ebpf::CALL_REG {
+                    state |= 1;
+                }
+            }
+            for destination in cfg_node.destinations.iter() {
+                merge(&mut block_inputs, &mut worklist, *destination, state);
+            }
+        }
+        reads
+    }
+
+    /// Rejects programs which read a register before initializing it, see
+    /// [Analysis::uninitialized_register_reads]
+    pub fn verify_register_initialization(&self) -> Result<(), VerifierError> {
+        match self.uninitialized_register_reads().into_iter().next() {
+            Some(read) => Err(VerifierError::UninitializedRegister(read.register, read.pc)),
+            None => Ok(()),
+        }
+    }
+
+    /// Collects the constant target addresses of callx instructions, keyed by their pc
+    ///
+    /// Uses the same constant propagation as [Analysis::optimization_report], so callx
+    /// instructions whose target register is not provably constant are left out.
+    pub fn callx_targets(&self) -> BTreeMap<usize, u64> {
+        let sbpf_version = self.executable.get_sbpf_version();
+        let (block_inputs, _branch_decisions) = self.constant_block_inputs();
+        let mut targets = BTreeMap::new();
+        for (cfg_node_start, inputs) in block_inputs.iter() {
+            let mut registers = *inputs;
+            for insn in
+                self.instructions[self.cfg_nodes[cfg_node_start].instructions.clone()].iter()
+            {
+                if insn.opc == ebpf::CALL_REG {
+                    let target_register = if sbpf_version.callx_uses_src_reg() {
+                        insn.src as usize
+                    } else {
+                        insn.imm as usize
+                    };
+                    if let Some(target_address) = registers.get(target_register).copied().flatten()
+                    {
+                        targets.insert(insn.ptr, target_address);
+                    }
+                }
+                propagate_constants(sbpf_version, &mut registers, insn);
+            }
+        }
+        targets
+    }
+
+    /// Rejects programs with a callx which provably does not call a registered function, see
+    /// [Analysis::callx_targets]
+    pub fn verify_callx_targets(&self) -> Result<(), VerifierError> {
+        let (program_vm_addr, program) = self.executable.get_text_bytes();
+        let insn_count = (program.len() / ebpf::INSN_SIZE) as u64;
+        for (pc, target_address) in self.callx_targets() {
+            let target_pc = target_address
+                .checked_sub(program_vm_addr)
+                .map(|offset| offset / ebpf::INSN_SIZE as u64)
+                .filter(|target_pc| *target_pc < insn_count);
+            if !target_pc
+                .is_some_and(|target_pc| self.functions.contains_key(&(target_pc as usize)))
+            {
+                return Err(VerifierError::InvalidCallxTarget(target_address, pc));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the pc of the function called by a `call imm`, None for syscalls
+    fn internal_call_target(&self, insn: &ebpf::Insn) -> Option<usize> {
+        let (external, internal) = if self.executable.get_sbpf_version().static_syscalls() {
+            (insn.src == 0, insn.src != 0)
+        } else {
+            (true, true)
+        };
+        if external
+            && self
+                .executable
+                .get_loader()
+                .get_function_registry()
+                .lookup_by_key(insn.imm as u32)
+                .is_some()
+        {
+            return None;
+        }
+        if !internal {
+            return None;
+        }
+        self.executable
+            .get_function_registry()
+            .lookup_by_key(insn.imm as u32)
+            .map(|(_function_name, target_pc)| target_pc)
+    }
 }
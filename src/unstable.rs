@@ -0,0 +1,38 @@
+//! Interfaces which may still change in minor versions
+//!
+//! Everything listed here, as well as the methods which take or return it, only exists with
+//! the `unstable` feature. This covers:
+//!
+//! - executors: `WholeProgramTieredExecutor`, `BatchExecutor` and `execute_lockstep()`
+//! - stepping through an execution: `Interpreter::single_step()`, `snapshot()`, `restore()` and
+//!   the recording behind `step_back()`, which the `debugger` feature builds on
+//! - instrumentation of the interpreter: the exit and instruction hooks, region access
+//!   accounting and the detection of uninitialized reads
+//! - typed syscalls: `guest_abi`, `declare_syscall!` and `declare_guest_abi!`
+//! - caches and custom memory of compiled programs: `JitCache`, `VerificationCache` and
+//!   `Executable::jit_compile_with_allocator()`
+//! - `Executable::verify_with_pipeline()` with custom `VerifierPass`es
+//! - `fuzz::minimize()` and `JitProgram::statistics()`
+//!
+//! Only the paths in `solana_rbpf::prelude` and the other public modules are covered by the
+//! semver guarantee.
+
+pub use crate::{
+    fuzz::minimize,
+    guest_abi::{GuestAbi, GuestArgs, GuestBorrows, SyscallArg},
+    interpreter::SingleStep,
+    recorder::{ExecutionRecord, ExecutionRecorder},
+    verification_cache::{VerificationCache, VerificationCacheStatistics},
+    verifier::{NoIndirectCalls, VerifierContext, VerifierPass, VerifierPipeline},
+    vm::{
+        BatchExecutor, BatchJob, ExecutionSummary, ExitHook, InstructionHook, LockstepDivergence,
+        LockstepMismatch, UninitializedRead, VmSnapshot, WholeProgramTieredExecutor,
+    },
+};
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+pub use crate::{
+    jit::JitStatistics,
+    jit_cache::{JitCache, JitCacheStatistics},
+    section_allocator::{PageAllocator, SectionAllocator},
+    vm::execute_lockstep,
+};
@@ -0,0 +1,228 @@
+#![allow(clippy::arithmetic_side_effects)]
+// Copyright 2024 Solana Maintainers <maintainers@solana.com>
+//
+// Licensed under the Apache License, Version 2.0 <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Unwind information (.eh_frame) for the machine code of the JIT
+//!
+//! All JIT frames, including nested BPF calls, are described as a single frame whose caller is
+//! `JitProgram::invoke()`. Its canonical frame address (CFA) is the stack pointer of `invoke()`
+//! before it pushed RBX and RBP, which is derived from the HostStackPointer slot of the VM:
+//!
+//! ```text
+//! CFA - 8:  RBX of invoke()
+//! CFA - 16: RBP of invoke()
+//! CFA - 24: return address into invoke(), stored in the HostStackPointer slot
+//! ```
+//!
+//! The VM pointer is read from RDI, except at calls into Rust, which may clobber RDI. There it is
+//! read from where the call sequence saved it on the stack instead.
+
+use std::ops::Range;
+
+const DWARF_RBX: u8 = 3;
+const DWARF_RDI: u8 = 5;
+const DWARF_RBP: u8 = 6;
+const DWARF_RSP: u8 = 7;
+const DWARF_RETURN_ADDRESS: u8 = 16;
+
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_DEF_CFA_EXPRESSION: u8 = 0x0f;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_OP_DEREF: u8 = 0x06;
+const DW_OP_CONSTS: u8 = 0x11;
+const DW_OP_PLUS: u8 = 0x22;
+const DW_OP_PLUS_UCONST: u8 = 0x23;
+const DW_OP_BREG0: u8 = 0x70;
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+
+/// Distance from the address stored in the HostStackPointer slot to the CFA
+const HOST_STACK_POINTER_TO_CFA: u64 = 24;
+
+/// A call from the machine code into a Rust function
+#[derive(Debug, Clone)]
+pub struct CallSite {
+    /// Location of the call sequence in the text section
+    pub range: Range<usize>,
+    /// Offset from RSP to the slot holding RSP before it was aligned for the call
+    pub unaligned_stack_pointer_offset: u32,
+    /// Offset from the unaligned RSP to the saved VM pointer (RDI)
+    pub vm_pointer_offset: u32,
+}
+
+/// Unwind information registered with the unwinder of the host, deregistered on drop
+#[derive(Debug)]
+pub struct EhFrame {
+    bytes: Box<[u8]>,
+}
+
+impl EhFrame {
+    /// Describes and registers the machine code at the host addresses `text`
+    ///
+    /// `host_stack_pointer_offset` is the offset of the HostStackPointer slot relative to the
+    /// VM pointer in RDI and `call_sites` are relative to the start of the text section, which
+    /// is `text_section_base`.
+    pub fn new(
+        text_section_base: usize,
+        text: Range<usize>,
+        host_stack_pointer_offset: i32,
+        call_sites: &[CallSite],
+    ) -> Self {
+        let mut bytes = Vec::new();
+
+        // Common information entry
+        let mut cie = Vec::new();
+        cie.extend_from_slice(&0u32.to_le_bytes()); // CIE id
+        cie.push(1); // Version
+        cie.extend_from_slice(b"zR\0"); // Augmentation
+        write_uleb128(&mut cie, 1); // Code alignment factor
+        write_sleb128(&mut cie, -8); // Data alignment factor
+        cie.push(DWARF_RETURN_ADDRESS);
+        write_uleb128(&mut cie, 1); // Augmentation data length
+        cie.push(DW_EH_PE_ABSPTR); // Encoding of the FDE pointers
+        push_record(&mut bytes, cie);
+
+        // Frame description entry
+        let mut fde = Vec::new();
+        fde.extend_from_slice(&(bytes.len() as u32 + 4).to_le_bytes()); // Offset to the CIE
+        fde.extend_from_slice(&(text.start as u64).to_le_bytes());
+        fde.extend_from_slice(&((text.end - text.start) as u64).to_le_bytes());
+        write_uleb128(&mut fde, 0); // Augmentation data length
+        let default_cfa = [
+            &[DW_OP_BREG0 + DWARF_RDI][..],
+            &sleb128(host_stack_pointer_offset as i64),
+            &[DW_OP_DEREF, DW_OP_PLUS_UCONST],
+            &uleb128(HOST_STACK_POINTER_TO_CFA),
+        ]
+        .concat();
+        write_cfa_expression(&mut fde, &default_cfa);
+        for (register, factored_offset) in
+            [(DWARF_RETURN_ADDRESS, 3), (DWARF_RBP, 2), (DWARF_RBX, 1)]
+        {
+            fde.push(DW_CFA_OFFSET | register);
+            write_uleb128(&mut fde, factored_offset);
+        }
+        let mut location = text.start - text_section_base;
+        for call_site in call_sites {
+            let call_site_cfa = [
+                &[DW_OP_BREG0 + DWARF_RSP][..],
+                &sleb128(call_site.unaligned_stack_pointer_offset as i64),
+                &[DW_OP_DEREF, DW_OP_PLUS_UCONST],
+                &uleb128(call_site.vm_pointer_offset as u64),
+                &[DW_OP_DEREF, DW_OP_CONSTS],
+                &sleb128(host_stack_pointer_offset as i64),
+                &[DW_OP_PLUS, DW_OP_DEREF, DW_OP_PLUS_UCONST],
+                &uleb128(HOST_STACK_POINTER_TO_CFA),
+            ]
+            .concat();
+            write_advance_location(&mut fde, &mut location, call_site.range.start);
+            write_cfa_expression(&mut fde, &call_site_cfa);
+            write_advance_location(&mut fde, &mut location, call_site.range.end);
+            write_cfa_expression(&mut fde, &default_cfa);
+        }
+        push_record(&mut bytes, fde);
+
+        // Terminator
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+
+        let eh_frame = Self {
+            bytes: bytes.into_boxed_slice(),
+        };
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        unsafe {
+            __register_frame(eh_frame.bytes.as_ptr());
+        }
+        eh_frame
+    }
+}
+
+impl Drop for EhFrame {
+    fn drop(&mut self) {
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        unsafe {
+            __deregister_frame(self.bytes.as_ptr());
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" {
+    fn __register_frame(begin: *const u8);
+    fn __deregister_frame(begin: *const u8);
+}
+
+/// Appends a CIE or FDE with its length, padded to the pointer size
+fn push_record(bytes: &mut Vec<u8>, mut record: Vec<u8>) {
+    while record.len() % 8 != 4 {
+        record.push(DW_CFA_NOP);
+    }
+    bytes.extend_from_slice(&(record.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&record);
+}
+
+fn write_advance_location(fde: &mut Vec<u8>, location: &mut usize, target: usize) {
+    debug_assert!(target >= *location);
+    fde.push(DW_CFA_ADVANCE_LOC4);
+    fde.extend_from_slice(&((target - *location) as u32).to_le_bytes());
+    *location = target;
+}
+
+fn write_cfa_expression(fde: &mut Vec<u8>, expression: &[u8]) {
+    fde.push(DW_CFA_DEF_CFA_EXPRESSION);
+    write_uleb128(fde, expression.len() as u64);
+    fde.extend_from_slice(expression);
+}
+
+fn uleb128(value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_uleb128(&mut bytes, value);
+    bytes
+}
+
+fn sleb128(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_sleb128(&mut bytes, value);
+    bytes
+}
+
+fn write_uleb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_sleb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128() {
+        assert_eq!(uleb128(0), [0x00]);
+        assert_eq!(uleb128(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(sleb128(-8), [0x78]);
+        assert_eq!(sleb128(63), [0x3f]);
+        assert_eq!(sleb128(64), [0xc0, 0x00]);
+        assert_eq!(sleb128(-123456), [0xc0, 0xbb, 0x78]);
+    }
+}
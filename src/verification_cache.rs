@@ -0,0 +1,151 @@
+//! Sharing of verification results between executables which load the same code
+
+use crate::{
+    elf::Executable,
+    error::EbpfError,
+    program::SBPFVersion,
+    verifier::{Verifier, VerifierExemption},
+    vm::{Config, ContextObject},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// Counters of a [VerificationCache], e.g. to tune its capacity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerificationCacheStatistics {
+    /// Lookups which found a successful verification
+    pub hits: u64,
+    /// Lookups which had to run the verifier
+    pub misses: u64,
+    /// Verifications which were dropped to make room for others
+    pub evictions: u64,
+    /// Number of verifications currently in the cache
+    pub len: usize,
+}
+
+/// Everything the outcome of a verification depends on
+#[derive(PartialEq, Eq, Hash)]
+struct VerificationCacheKey {
+    verifier: &'static str,
+    /// SHA-256 of the text section
+    text_hash: [u8; 32],
+    sbpf_version: SBPFVersion,
+    config: Config,
+    /// Keys and pcs of the functions
+    functions: Vec<(u32, usize)>,
+    exemptions: Vec<VerifierExemption>,
+    pre_verified_ranges: Vec<Range<usize>>,
+}
+
+impl VerificationCacheKey {
+    fn new<V: Verifier, C: ContextObject>(executable: &Executable<C>) -> Self {
+        Self {
+            verifier: std::any::type_name::<V>(),
+            text_hash: Sha256::digest(executable.get_text_bytes().1).into(),
+            sbpf_version: executable.get_sbpf_version().clone(),
+            config: *executable.get_config(),
+            functions: executable
+                .get_function_registry()
+                .iter()
+                .map(|(key, (_name, pc))| (key, pc))
+                .collect(),
+            exemptions: executable.get_verifier_exemptions().to_vec(),
+            pre_verified_ranges: executable.get_pre_verified_ranges().to_vec(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct VerificationCacheState {
+    /// Value of the clock at the last lookup of each key
+    entries: HashMap<VerificationCacheKey, u64>,
+    clock: u64,
+    statistics: VerificationCacheStatistics,
+}
+
+/// In-process LRU cache of successful verifications
+///
+/// Multi-tenant hosts often load the same program many times, so instead of verifying every
+/// executable they can remember which ones passed, see `Executable::verify_cached()`. The key
+/// consists of the verifier, a hash of the text section, the Config, the SBPFVersion, the
+/// function registry, the requested exemptions and the pre-verified ranges. Rejections are not
+/// cached, so programs which fail are verified again on every lookup. Once `capacity`
+/// verifications are cached the least recently used one is dropped.
+pub struct VerificationCache {
+    capacity: usize,
+    state: Mutex<VerificationCacheState>,
+}
+
+impl VerificationCache {
+    /// Creates an empty cache which holds up to `capacity` verifications
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(VerificationCacheState::default()),
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, VerificationCacheState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the counters of the lookups so far
+    pub fn statistics(&self) -> VerificationCacheStatistics {
+        let state = self.lock_state();
+        VerificationCacheStatistics {
+            len: state.entries.len(),
+            ..state.statistics
+        }
+    }
+
+    /// Drops all verifications, the statistics are kept
+    pub fn clear(&self) {
+        self.lock_state().entries.clear();
+    }
+
+    /// Succeeds if an equivalent executable passed before or verifies this one
+    pub(crate) fn get_or_verify<V: Verifier, C: ContextObject>(
+        &self,
+        executable: &Executable<C>,
+    ) -> Result<(), EbpfError> {
+        let key = VerificationCacheKey::new::<V, C>(executable);
+        {
+            let mut state = self.lock_state();
+            state.clock = state.clock.saturating_add(1);
+            let clock = state.clock;
+            if let Some(last_use) = state.entries.get_mut(&key) {
+                *last_use = clock;
+                state.statistics.hits = state.statistics.hits.saturating_add(1);
+                return Ok(());
+            }
+            state.statistics.misses = state.statistics.misses.saturating_add(1);
+        }
+        // Verify without holding the lock, so that lookups of other programs are not blocked
+        executable.verify::<V>()?;
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut state = self.lock_state();
+        if state.entries.contains_key(&key) {
+            // Another thread verified the same program in the meantime
+            return Ok(());
+        }
+        if state.entries.len() >= self.capacity {
+            // Every lookup and insertion advances the clock, so the last uses are unique
+            if let Some(least_recent_use) = state.entries.values().min().copied() {
+                state
+                    .entries
+                    .retain(|_key, last_use| *last_use != least_recent_use);
+                state.statistics.evictions = state.statistics.evictions.saturating_add(1);
+            }
+        }
+        state.clock = state.clock.saturating_add(1);
+        let clock = state.clock;
+        state.entries.insert(key, clock);
+        Ok(())
+    }
+}
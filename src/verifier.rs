@@ -28,10 +28,12 @@ use crate::{
     program::{FunctionRegistry, SBPFVersion},
     vm::Config,
 };
+use std::ops::Range;
 use thiserror::Error;
 
 /// Error definitions
 #[derive(Debug, Error, Eq, PartialEq)]
+#[non_exhaustive]
 pub enum VerifierError {
     /// ProgramLengthNotMultiple
     #[error("program length must be a multiple of {} octets", ebpf::INSN_SIZE)]
@@ -84,6 +86,345 @@ pub enum VerifierError {
     /// Invalid function
     #[error("Invalid function at instruction {0}")]
     InvalidFunction(usize),
+    /// The host policy does not grant a requested exemption
+    #[error("verifier exemption {0:?} denied for function {1} (insn #{2})")]
+    ExemptionDenied(VerifierExemptionKind, String, usize),
+    /// An additional rule of a `VerifierPipeline` rejected the instruction
+    #[error("{0} (insn #{1})")]
+    RuleViolation(String, usize),
+    /// The worst-case stack usage (bytes, call depth) exceeds the stack
+    #[error("worst-case stack usage of {0} bytes in {1} frames exceeds the stack")]
+    StackOverflow(u64, usize),
+    /// A register is read before it is written on some path from the entrypoint
+    #[error("read of uninitialized register r{0} (insn #{1})")]
+    UninitializedRegister(u8, usize),
+    /// The instruction can not be reached from any registered function
+    #[error("unreachable instruction (insn #{0})")]
+    UnreachableCode(usize),
+    /// The opcode is in `Config::forbidden_opcodes`
+    #[error("forbidden opcode 0x{0:02x} (insn #{1})")]
+    ForbiddenOpcode(u8, usize),
+    /// The number of instructions exceeds `Config::verification_budget`
+    #[error("{0} instructions exceed the verification budget of {1}")]
+    BudgetExceeded(usize, usize),
+    /// Signed division of the minimum value by -1
+    #[error("division overflow (insn #{0})")]
+    DivisionOverflow(usize),
+    /// The constant target address of a callx is not a registered function
+    #[error("callx to {0:#x} which is not a registered function (insn #{1})")]
+    InvalidCallxTarget(u64, usize),
+    /// A frame pointer relative access reaches beyond the gap around its fixed stack frame
+    #[error(
+        "stack access at r10{0:+} of {1} bytes reaches beyond the stack frame gap (insn #{2})"
+    )]
+    StackFrameOverrun(i16, u64, usize),
+}
+
+impl VerifierError {
+    /// Stable numeric code of the kind of error, e.g. to classify rejections in tooling
+    ///
+    /// Codes are never changed or reused, new kinds of errors get the next free code.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::ProgramLengthNotMultiple => 1,
+            Self::ProgramTooLarge(_) => 2,
+            Self::NoProgram => 3,
+            Self::DivisionByZero(_) => 4,
+            Self::UnsupportedLEBEArgument(_) => 5,
+            Self::LDDWCannotBeLast => 6,
+            Self::IncompleteLDDW(_) => 7,
+            Self::InfiniteLoop(_) => 8,
+            Self::JumpOutOfCode(..) => 9,
+            Self::JumpToMiddleOfLDDW(..) => 10,
+            Self::InvalidSourceRegister(_) => 11,
+            Self::CannotWriteR10(_) => 12,
+            Self::InvalidDestinationRegister(_) => 13,
+            Self::UnknownOpCode(..) => 14,
+            Self::ShiftWithOverflow(..) => 15,
+            Self::InvalidRegister(_) => 16,
+            Self::InvalidFunction(_) => 17,
+            Self::ExemptionDenied(..) => 18,
+            Self::RuleViolation(..) => 19,
+            Self::StackOverflow(..) => 20,
+            Self::UninitializedRegister(..) => 21,
+            Self::UnreachableCode(_) => 22,
+            Self::ForbiddenOpcode(..) => 23,
+            Self::BudgetExceeded(..) => 24,
+            Self::DivisionOverflow(_) => 25,
+            Self::InvalidCallxTarget(..) => 26,
+            Self::StackFrameOverrun(..) => 27,
+        }
+    }
+
+    /// The instruction the error was detected at, if it refers to one
+    pub fn pc(&self) -> Option<usize> {
+        match self {
+            Self::DivisionByZero(pc)
+            | Self::UnsupportedLEBEArgument(pc)
+            | Self::IncompleteLDDW(pc)
+            | Self::InfiniteLoop(pc)
+            | Self::JumpOutOfCode(_, pc)
+            | Self::JumpToMiddleOfLDDW(_, pc)
+            | Self::InvalidSourceRegister(pc)
+            | Self::CannotWriteR10(pc)
+            | Self::InvalidDestinationRegister(pc)
+            | Self::UnknownOpCode(_, pc)
+            | Self::ShiftWithOverflow(_, _, pc)
+            | Self::InvalidRegister(pc)
+            | Self::InvalidFunction(pc)
+            | Self::ExemptionDenied(_, _, pc)
+            | Self::RuleViolation(_, pc)
+            | Self::UninitializedRegister(_, pc)
+            | Self::UnreachableCode(pc)
+            | Self::ForbiddenOpcode(_, pc)
+            | Self::DivisionOverflow(pc)
+            | Self::InvalidCallxTarget(_, pc)
+            | Self::StackFrameOverrun(_, _, pc) => Some(*pc),
+            Self::ProgramLengthNotMultiple
+            | Self::ProgramTooLarge(_)
+            | Self::NoProgram
+            | Self::LDDWCannotBeLast
+            | Self::StackOverflow(_, _)
+            | Self::BudgetExceeded(_, _) => None,
+        }
+    }
+}
+
+/// Location of a [VerifierError] in the program, see `Executable::verifier_error_context()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifierErrorContext {
+    /// Instruction the error refers to
+    pub pc: usize,
+    /// Offset of the instruction in the text section in bytes
+    pub byte_offset: usize,
+    /// Disassembled instruction
+    pub instruction: String,
+    /// Demangled name of the function containing the instruction, if known
+    pub function_name: Option<String>,
+}
+
+impl std::fmt::Display for VerifierErrorContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "insn #{} at byte offset {:#x}",
+            self.pc, self.byte_offset
+        )?;
+        if let Some(function_name) = &self.function_name {
+            write!(f, " in {function_name}")?;
+        }
+        write!(f, ": {}", self.instruction)
+    }
+}
+
+/// Machine-readable description of a verifier error, see `Executable::verifier_diagnostic()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VerifierDiagnostic {
+    /// See [VerifierError::code]
+    pub code: u32,
+    /// Instruction the error refers to, if any
+    pub pc: Option<usize>,
+    /// Demangled name of the function containing the instruction, if known
+    pub function: Option<String>,
+    /// Human readable message of the error
+    pub message: String,
+}
+
+impl From<&VerifierError> for VerifierDiagnostic {
+    fn from(error: &VerifierError) -> Self {
+        Self {
+            code: error.code(),
+            pc: error.pc(),
+            function: None,
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Relaxations of the verifier rules which a program can request for one of its functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum VerifierExemptionKind {
+    /// Allow `callx r10` even though `Config::reject_callx_r10` is set
+    CallxR10 = 1,
+}
+
+impl VerifierExemptionKind {
+    /// Maps the type of a note in the `.sbpf.verifier` section to an exemption kind
+    pub fn from_note_type(note_type: u32) -> Option<Self> {
+        match note_type {
+            1 => Some(Self::CallxR10),
+            _ => None,
+        }
+    }
+}
+
+/// An exemption requested by the `.sbpf.verifier` section of an ELF
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VerifierExemption {
+    /// What is requested
+    pub kind: VerifierExemptionKind,
+    /// Symbol name of the function the exemption applies to
+    pub function_name: String,
+    /// First instruction of the function the exemption applies to
+    pub pc: usize,
+}
+
+/// The exemptions a host is willing to grant
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VerifierExemptionPolicy {
+    /// Grant `VerifierExemptionKind::CallxR10`
+    pub allow_callx_r10: bool,
+}
+
+impl VerifierExemptionPolicy {
+    /// Decides whether the exemption is granted
+    pub fn allows(&self, exemption: &VerifierExemption) -> bool {
+        match exemption.kind {
+            VerifierExemptionKind::CallxR10 => self.allow_callx_r10,
+        }
+    }
+}
+
+/// A set of opcodes, see `Config::forbidden_opcodes`
+///
+/// ```
+/// use solana_rbpf::{ebpf, verifier::OpcodeSet};
+///
+/// let opcodes = OpcodeSet::new().with(ebpf::CALL_REG).with(ebpf::LD_DW_IMM);
+/// assert!(opcodes.contains(ebpf::CALL_REG));
+/// assert!(!opcodes.contains(ebpf::CALL_IMM));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OpcodeSet([u64; 4]);
+
+impl OpcodeSet {
+    /// Creates an empty set
+    pub const fn new() -> Self {
+        Self([0; 4])
+    }
+
+    /// Returns the set with `opcode` added
+    pub const fn with(mut self, opcode: u8) -> Self {
+        self.0[(opcode / 64) as usize] |= 1 << (opcode % 64);
+        self
+    }
+
+    /// Adds `opcode` to the set
+    pub fn insert(&mut self, opcode: u8) {
+        *self = self.with(opcode);
+    }
+
+    /// Whether `opcode` is in the set
+    pub fn contains(&self, opcode: u8) -> bool {
+        self.0[(opcode / 64) as usize] & (1 << (opcode % 64)) != 0
+    }
+}
+
+/// The [SBPFVersion] feature an opcode belongs to, see [opcode_support]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeSupport {
+    /// No SBPF version defines the opcode
+    Unsupported,
+    /// Every SBPF version
+    All,
+    /// Only if `SBPFVersion::enable_lddw()`
+    Lddw,
+    /// Only if not `SBPFVersion::enable_lddw()`, replaces LD_DW_IMM
+    Hor,
+    /// Only if `SBPFVersion::enable_neg()`
+    Neg,
+    /// Only if `SBPFVersion::enable_le()`
+    Le,
+    /// Only if `SBPFVersion::enable_pqr()`
+    Pqr,
+    /// Only if not `SBPFVersion::enable_pqr()`, the mul, div and mod which BPF_PQR replaces
+    NoPqr,
+}
+
+impl OpcodeSupport {
+    /// Whether the opcode is legal in `sbpf_version`
+    pub fn includes(self, sbpf_version: &SBPFVersion) -> bool {
+        match self {
+            Self::Unsupported => false,
+            Self::All => true,
+            Self::Lddw => sbpf_version.enable_lddw(),
+            Self::Hor => !sbpf_version.enable_lddw(),
+            Self::Neg => sbpf_version.enable_neg(),
+            Self::Le => sbpf_version.enable_le(),
+            Self::Pqr => sbpf_version.enable_pqr(),
+            Self::NoPqr => !sbpf_version.enable_pqr(),
+        }
+    }
+}
+
+/// The [SBPFVersion] feature `opc` belongs to
+///
+/// Together with the feature predicates of [SBPFVersion] this decides which opcodes the
+/// verifier accepts and the JIT compiles. Opcodes whose semantics differ between versions
+/// (e.g. the operands of sub or the register of callx) are legal in all of them. The assembler
+/// encodes every opcode it knows regardless, so that illegal ones can be fed to the verifier.
+#[rustfmt::skip]
+pub const fn opcode_support(opc: u8) -> OpcodeSupport {
+    match opc {
+        ebpf::LD_DW_IMM => OpcodeSupport::Lddw,
+        ebpf::HOR64_IMM => OpcodeSupport::Hor,
+        ebpf::NEG32 | ebpf::NEG64 => OpcodeSupport::Neg,
+        ebpf::LE => OpcodeSupport::Le,
+
+        ebpf::MUL32_IMM | ebpf::MUL32_REG | ebpf::DIV32_IMM | ebpf::DIV32_REG
+        | ebpf::MOD32_IMM | ebpf::MOD32_REG
+        | ebpf::MUL64_IMM | ebpf::MUL64_REG | ebpf::DIV64_IMM | ebpf::DIV64_REG
+        | ebpf::MOD64_IMM | ebpf::MOD64_REG => OpcodeSupport::NoPqr,
+
+        ebpf::LMUL32_IMM | ebpf::LMUL32_REG | ebpf::LMUL64_IMM | ebpf::LMUL64_REG
+        | ebpf::UHMUL64_IMM | ebpf::UHMUL64_REG | ebpf::SHMUL64_IMM | ebpf::SHMUL64_REG
+        | ebpf::UDIV32_IMM | ebpf::UDIV32_REG | ebpf::UDIV64_IMM | ebpf::UDIV64_REG
+        | ebpf::UREM32_IMM | ebpf::UREM32_REG | ebpf::UREM64_IMM | ebpf::UREM64_REG
+        | ebpf::SDIV32_IMM | ebpf::SDIV32_REG | ebpf::SDIV64_IMM | ebpf::SDIV64_REG
+        | ebpf::SREM32_IMM | ebpf::SREM32_REG | ebpf::SREM64_IMM | ebpf::SREM64_REG => OpcodeSupport::Pqr,
+
+        ebpf::LD_B_REG | ebpf::LD_H_REG | ebpf::LD_W_REG | ebpf::LD_DW_REG
+        | ebpf::ST_B_IMM | ebpf::ST_H_IMM | ebpf::ST_W_IMM | ebpf::ST_DW_IMM
+        | ebpf::ST_B_REG | ebpf::ST_H_REG | ebpf::ST_W_REG | ebpf::ST_DW_REG
+        | ebpf::ADD32_IMM | ebpf::ADD32_REG | ebpf::SUB32_IMM | ebpf::SUB32_REG
+        | ebpf::OR32_IMM | ebpf::OR32_REG | ebpf::AND32_IMM | ebpf::AND32_REG
+        | ebpf::LSH32_IMM | ebpf::LSH32_REG | ebpf::RSH32_IMM | ebpf::RSH32_REG
+        | ebpf::XOR32_IMM | ebpf::XOR32_REG | ebpf::MOV32_IMM | ebpf::MOV32_REG
+        | ebpf::ARSH32_IMM | ebpf::ARSH32_REG | ebpf::BE
+        | ebpf::ADD64_IMM | ebpf::ADD64_REG | ebpf::SUB64_IMM | ebpf::SUB64_REG
+        | ebpf::OR64_IMM | ebpf::OR64_REG | ebpf::AND64_IMM | ebpf::AND64_REG
+        | ebpf::LSH64_IMM | ebpf::LSH64_REG | ebpf::RSH64_IMM | ebpf::RSH64_REG
+        | ebpf::XOR64_IMM | ebpf::XOR64_REG | ebpf::MOV64_IMM | ebpf::MOV64_REG
+        | ebpf::ARSH64_IMM | ebpf::ARSH64_REG
+        | ebpf::JA | ebpf::JEQ_IMM | ebpf::JEQ_REG | ebpf::JGT_IMM | ebpf::JGT_REG
+        | ebpf::JGE_IMM | ebpf::JGE_REG | ebpf::JLT_IMM | ebpf::JLT_REG
+        | ebpf::JLE_IMM | ebpf::JLE_REG | ebpf::JSET_IMM | ebpf::JSET_REG
+        | ebpf::JNE_IMM | ebpf::JNE_REG | ebpf::JSGT_IMM | ebpf::JSGT_REG
+        | ebpf::JSGE_IMM | ebpf::JSGE_REG | ebpf::JSLT_IMM | ebpf::JSLT_REG
+        | ebpf::JSLE_IMM | ebpf::JSLE_REG
+        | ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT => OpcodeSupport::All,
+
+        _ => OpcodeSupport::Unsupported,
+    }
+}
+
+/// [opcode_support] of every opcode
+const OPCODE_SUPPORT: [OpcodeSupport; 256] = {
+    let mut table = [OpcodeSupport::Unsupported; 256];
+    let mut opc = 0;
+    while opc < table.len() {
+        table[opc] = opcode_support(opc as u8);
+        opc += 1;
+    }
+    table
+};
+
+/// Whether `opc` is legal in `sbpf_version`, see [opcode_support]
+#[inline]
+pub fn is_opcode_supported(opc: u8, sbpf_version: &SBPFVersion) -> bool {
+    OPCODE_SUPPORT[opc as usize].includes(sbpf_version)
 }
 
 /// eBPF Verifier
@@ -102,6 +443,34 @@ pub trait Verifier {
         sbpf_version: &SBPFVersion,
         function_registry: &FunctionRegistry<usize>,
     ) -> Result<(), VerifierError>;
+
+    /// Same as `verify` but honors the exemptions which the host policy granted.
+    ///
+    /// Verifiers which do not support exemptions ignore them and stay strict.
+    fn verify_with_exemptions(
+        prog: &[u8],
+        config: &Config,
+        sbpf_version: &SBPFVersion,
+        function_registry: &FunctionRegistry<usize>,
+        _exemptions: &[VerifierExemption],
+    ) -> Result<(), VerifierError> {
+        Self::verify(prog, config, sbpf_version, function_registry)
+    }
+
+    /// Same as `verify_with_exemptions` but skips the instructions in the `pre_verified` ranges
+    /// of pcs, which the host generated and vouches for.
+    ///
+    /// Verifiers which do not support this ignore the ranges and check every instruction.
+    fn verify_with_pre_verified_ranges(
+        prog: &[u8],
+        config: &Config,
+        sbpf_version: &SBPFVersion,
+        function_registry: &FunctionRegistry<usize>,
+        exemptions: &[VerifierExemption],
+        _pre_verified: &[Range<usize>],
+    ) -> Result<(), VerifierError> {
+        Self::verify_with_exemptions(prog, config, sbpf_version, function_registry, exemptions)
+    }
 }
 
 fn check_prog_len(prog: &[u8]) -> Result<(), VerifierError> {
@@ -114,6 +483,17 @@ fn check_prog_len(prog: &[u8]) -> Result<(), VerifierError> {
     Ok(())
 }
 
+fn check_verification_budget(prog: &[u8], config: &Config) -> Result<(), VerifierError> {
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    if insn_count > config.verification_budget {
+        return Err(VerifierError::BudgetExceeded(
+            insn_count,
+            config.verification_budget,
+        ));
+    }
+    Ok(())
+}
+
 fn check_imm_nonzero(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierError> {
     if insn.imm == 0 {
         return Err(VerifierError::DivisionByZero(insn_ptr));
@@ -121,6 +501,39 @@ fn check_imm_nonzero(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierE
     Ok(())
 }
 
+/// Rejects a signed division by -1 of a dividend which was just set to the minimum value
+fn check_imm_signed_overflow(
+    prog: &[u8],
+    insn: &ebpf::Insn,
+    insn_ptr: usize,
+    function_range: &std::ops::Range<usize>,
+    size: usize,
+) -> Result<(), VerifierError> {
+    if insn.imm != -1 || insn_ptr <= function_range.start {
+        return Ok(());
+    }
+    let prev_insn = ebpf::get_insn(prog, insn_ptr - 1);
+    let dividend_is_min = if size == 32 {
+        (prev_insn.opc == ebpf::MOV32_IMM || prev_insn.opc == ebpf::MOV64_IMM)
+            && prev_insn.dst == insn.dst
+            && prev_insn.imm as i32 == i32::MIN
+    } else if insn_ptr >= function_range.start + 2 {
+        let mov_insn = ebpf::get_insn(prog, insn_ptr - 2);
+        (mov_insn.opc == ebpf::MOV32_IMM || mov_insn.opc == ebpf::MOV64_IMM)
+            && mov_insn.dst == insn.dst
+            && mov_insn.imm == 0
+            && prev_insn.opc == ebpf::HOR64_IMM
+            && prev_insn.dst == insn.dst
+            && prev_insn.imm as i32 == i32::MIN
+    } else {
+        false
+    };
+    if dividend_is_min {
+        return Err(VerifierError::DivisionOverflow(insn_ptr));
+    }
+    Ok(())
+}
+
 fn check_imm_endian(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierError> {
     match insn.imm {
         16 | 32 | 64 => Ok(()),
@@ -129,11 +542,9 @@ fn check_imm_endian(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierEr
 }
 
 fn check_load_dw(prog: &[u8], insn_ptr: usize) -> Result<(), VerifierError> {
-    if (insn_ptr + 1) * ebpf::INSN_SIZE >= prog.len() {
-        // Last instruction cannot be LD_DW because there would be no 2nd DW
-        return Err(VerifierError::LDDWCannotBeLast);
-    }
-    let next_insn = ebpf::get_insn(prog, insn_ptr + 1);
+    // Last instruction cannot be LD_DW because there would be no 2nd DW
+    let next_insn =
+        ebpf::get_insn_checked(prog, insn_ptr + 1).map_err(|_| VerifierError::LDDWCannotBeLast)?;
     if next_insn.opc != 0 {
         return Err(VerifierError::IncompleteLDDW(insn_ptr));
     }
@@ -154,7 +565,8 @@ fn check_jmp_offset(
             insn_ptr,
         ));
     }
-    let dst_insn = ebpf::get_insn(prog, dst_insn_ptr as usize);
+    let dst_insn = ebpf::get_insn_checked(prog, dst_insn_ptr as usize)
+        .map_err(|_| VerifierError::JumpOutOfCode(dst_insn_ptr as usize, insn_ptr))?;
     if dst_insn.opc == 0 {
         return Err(VerifierError::JumpToMiddleOfLDDW(
             dst_insn_ptr as usize,
@@ -174,6 +586,53 @@ fn check_call_target(
         .ok_or(VerifierError::InvalidFunction(key as usize))
 }
 
+/// Walks the control flow from the function starts, calls are assumed to return
+///
+/// Syscalls are assumed to return too and callx is not followed, the targets of both have to
+/// be registered functions on their own.
+fn check_reachability(
+    prog: &[u8],
+    sbpf_version: &SBPFVersion,
+    function_starts: &[usize],
+    pre_verified: &[Range<usize>],
+) -> Result<(), VerifierError> {
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    let mut reachable = vec![false; insn_count];
+    let mut pending = function_starts.to_vec();
+    while let Some(insn_ptr) = pending.pop() {
+        if insn_ptr >= insn_count || reachable[insn_ptr] {
+            continue;
+        }
+        reachable[insn_ptr] = true;
+        let insn = ebpf::get_insn(prog, insn_ptr);
+        let jump_target = (insn_ptr as isize + insn.off as isize + 1) as usize;
+        match insn.opc {
+            ebpf::LD_DW_IMM if sbpf_version.enable_lddw() => {
+                if let Some(second_slot) = reachable.get_mut(insn_ptr + 1) {
+                    *second_slot = true;
+                }
+                pending.push(insn_ptr + 2);
+            }
+            ebpf::EXIT => {}
+            ebpf::JA => pending.push(jump_target),
+            _ if insn.opc & ebpf::BPF_CLS_MASK == ebpf::BPF_JMP
+                && insn.opc != ebpf::CALL_IMM
+                && insn.opc != ebpf::CALL_REG =>
+            {
+                pending.push(insn_ptr + 1);
+                pending.push(jump_target);
+            }
+            _ => pending.push(insn_ptr + 1),
+        }
+    }
+    match reachable.iter().enumerate().find(|(insn_ptr, reachable)| {
+        !**reachable && !pre_verified.iter().any(|range| range.contains(insn_ptr))
+    }) {
+        Some((insn_ptr, _)) => Err(VerifierError::UnreachableCode(insn_ptr)),
+        None => Ok(()),
+    }
+}
+
 fn check_registers(
     insn: &ebpf::Insn,
     store: bool,
@@ -192,6 +651,41 @@ fn check_registers(
     }
 }
 
+/// Check that a frame pointer relative access can not skip the gaps around its stack frame
+///
+/// Accesses reaching into a gap fault at runtime, but the neighbouring frames beyond the gaps
+/// are mapped and would be corrupted silently.
+fn check_stack_frame_access(
+    insn: &ebpf::Insn,
+    store: bool,
+    insn_ptr: usize,
+    config: &Config,
+    sbpf_version: &SBPFVersion,
+) -> Result<(), VerifierError> {
+    let base = if store { insn.dst } else { insn.src };
+    let gap_size = config.stack_frame_gap_size(sbpf_version) as i64;
+    if base != ebpf::FRAME_PTR_REG as u8 || gap_size == 0 {
+        return Ok(());
+    }
+    // Size modifier of the opcode
+    let len = match insn.opc & 0x18 {
+        ebpf::BPF_B => 1,
+        ebpf::BPF_H => 2,
+        ebpf::BPF_W => 4,
+        _ => 8,
+    };
+    // The frame ends at r10, so it is preceded by the gap of the previous frame
+    let lowest = 0_i64
+        .saturating_sub(config.stack_frame_size as i64)
+        .saturating_sub(gap_size);
+    if (insn.off as i64) < lowest || (insn.off as i64).saturating_add(len) > gap_size {
+        return Err(VerifierError::StackFrameOverrun(
+            insn.off, len as u64, insn_ptr,
+        ));
+    }
+    Ok(())
+}
+
 /// Check that the imm is a valid shift operand
 fn check_imm_shift(insn: &ebpf::Insn, insn_ptr: usize, imm_bits: u64) -> Result<(), VerifierError> {
     let shift_by = insn.imm as u64;
@@ -209,13 +703,14 @@ fn check_callx_register(
     insn_ptr: usize,
     config: &Config,
     sbpf_version: &SBPFVersion,
+    callx_r10_exempted: bool,
 ) -> Result<(), VerifierError> {
     let reg = if sbpf_version.callx_uses_src_reg() {
         insn.src as i64
     } else {
         insn.imm
     };
-    if !(0..=10).contains(&reg) || (reg == 10 && config.reject_callx_r10) {
+    if !(0..=10).contains(&reg) || (reg == 10 && config.reject_callx_r10 && !callx_r10_exempted) {
         return Err(VerifierError::InvalidRegister(insn_ptr));
     }
     Ok(())
@@ -226,9 +721,49 @@ fn check_callx_register(
 pub struct RequisiteVerifier {}
 impl Verifier for RequisiteVerifier {
     /// Check the program against the verifier's rules
+    fn verify(
+        prog: &[u8],
+        config: &Config,
+        sbpf_version: &SBPFVersion,
+        function_registry: &FunctionRegistry<usize>,
+    ) -> Result<(), VerifierError> {
+        Self::verify_with_exemptions(prog, config, sbpf_version, function_registry, &[])
+    }
+
+    /// Check the program against the verifier's rules, relaxed by the granted exemptions
+    fn verify_with_exemptions(
+        prog: &[u8],
+        config: &Config,
+        sbpf_version: &SBPFVersion,
+        function_registry: &FunctionRegistry<usize>,
+        exemptions: &[VerifierExemption],
+    ) -> Result<(), VerifierError> {
+        Self::verify_with_pre_verified_ranges(
+            prog,
+            config,
+            sbpf_version,
+            function_registry,
+            exemptions,
+            &[],
+        )
+    }
+
+    /// Check the program against the verifier's rules, except for the pre-verified instructions
     #[rustfmt::skip]
-    fn verify(prog: &[u8], config: &Config, sbpf_version: &SBPFVersion, function_registry: &FunctionRegistry<usize>) -> Result<(), VerifierError> {
+    fn verify_with_pre_verified_ranges(prog: &[u8], config: &Config, sbpf_version: &SBPFVersion, function_registry: &FunctionRegistry<usize>, exemptions: &[VerifierExemption], pre_verified: &[Range<usize>]) -> Result<(), VerifierError> {
         check_prog_len(prog)?;
+        check_verification_budget(prog, config)?;
+
+        // An exemption covers its function up to the start of the next function
+        let mut function_starts = function_registry.iter().map(|(_key, (_name, pc))| pc).collect::<Vec<_>>();
+        function_starts.sort_unstable();
+        let callx_r10_exempted_ranges = exemptions.iter()
+            .filter(|exemption| exemption.kind == VerifierExemptionKind::CallxR10)
+            .map(|exemption| {
+                let end = function_starts.iter().find(|start| **start > exemption.pc).copied().unwrap_or(usize::MAX);
+                exemption.pc..end
+            })
+            .collect::<Vec<_>>();
 
         let program_range = 0..prog.len() / ebpf::INSN_SIZE;
         let mut function_iter = function_registry.keys().map(|insn_ptr| insn_ptr as usize).peekable();
@@ -241,17 +776,34 @@ impl Verifier for RequisiteVerifier {
             if sbpf_version.static_syscalls() && function_iter.peek() == Some(&insn_ptr) {
                 function_range.start = function_iter.next().unwrap_or(0);
                 function_range.end = *function_iter.peek().unwrap_or(&program_range.end);
-                let insn = ebpf::get_insn(prog, function_range.end.saturating_sub(1));
-                match insn.opc {
-                    ebpf::JA | ebpf::EXIT => {},
+                // The next function might be registered beyond the end of the program
+                let insn = ebpf::get_insn_checked(prog, function_range.end.saturating_sub(1));
+                match insn.map(|insn| insn.opc) {
+                    Ok(ebpf::JA) | Ok(ebpf::EXIT) => {},
                     _ => return Err(VerifierError::InvalidFunction(
                         function_range.end.saturating_sub(1),
                     )),
                 }
             }
 
+            // Instructions generated by the host are not checked, but still have to be stepped over
+            if pre_verified.iter().any(|range| range.contains(&insn_ptr)) {
+                if insn.opc == ebpf::LD_DW_IMM && sbpf_version.enable_lddw() {
+                    insn_ptr += 1;
+                }
+                insn_ptr += 1;
+                continue;
+            }
+
+            if !is_opcode_supported(insn.opc, sbpf_version) {
+                return Err(VerifierError::UnknownOpCode(insn.opc, insn_ptr));
+            }
+            if config.forbidden_opcodes.contains(insn.opc) {
+                return Err(VerifierError::ForbiddenOpcode(insn.opc, insn_ptr));
+            }
+
             match insn.opc {
-                ebpf::LD_DW_IMM if sbpf_version.enable_lddw() => {
+                ebpf::LD_DW_IMM => {
                     check_load_dw(prog, insn_ptr)?;
                     insn_ptr += 1;
                 },
@@ -279,10 +831,10 @@ impl Verifier for RequisiteVerifier {
                 ebpf::ADD32_REG  => {},
                 ebpf::SUB32_IMM  => {},
                 ebpf::SUB32_REG  => {},
-                ebpf::MUL32_IMM  if !sbpf_version.enable_pqr() => {},
-                ebpf::MUL32_REG  if !sbpf_version.enable_pqr() => {},
-                ebpf::DIV32_IMM  if !sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::DIV32_REG  if !sbpf_version.enable_pqr() => {},
+                ebpf::MUL32_IMM  => {},
+                ebpf::MUL32_REG  => {},
+                ebpf::DIV32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::DIV32_REG  => {},
                 ebpf::OR32_IMM   => {},
                 ebpf::OR32_REG   => {},
                 ebpf::AND32_IMM  => {},
@@ -291,16 +843,16 @@ impl Verifier for RequisiteVerifier {
                 ebpf::LSH32_REG  => {},
                 ebpf::RSH32_IMM  => { check_imm_shift(&insn, insn_ptr, 32)?; },
                 ebpf::RSH32_REG  => {},
-                ebpf::NEG32      if sbpf_version.enable_neg() => {},
-                ebpf::MOD32_IMM  if !sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::MOD32_REG  if !sbpf_version.enable_pqr() => {},
+                ebpf::NEG32      => {},
+                ebpf::MOD32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::MOD32_REG  => {},
                 ebpf::XOR32_IMM  => {},
                 ebpf::XOR32_REG  => {},
                 ebpf::MOV32_IMM  => {},
                 ebpf::MOV32_REG  => {},
                 ebpf::ARSH32_IMM => { check_imm_shift(&insn, insn_ptr, 32)?; },
                 ebpf::ARSH32_REG => {},
-                ebpf::LE         if sbpf_version.enable_le() => { check_imm_endian(&insn, insn_ptr)?; },
+                ebpf::LE         => { check_imm_endian(&insn, insn_ptr)?; },
                 ebpf::BE         => { check_imm_endian(&insn, insn_ptr)?; },
 
                 // BPF_ALU64 class
@@ -308,10 +860,10 @@ impl Verifier for RequisiteVerifier {
                 ebpf::ADD64_REG  => {},
                 ebpf::SUB64_IMM  => {},
                 ebpf::SUB64_REG  => {},
-                ebpf::MUL64_IMM  if !sbpf_version.enable_pqr() => {},
-                ebpf::MUL64_REG  if !sbpf_version.enable_pqr() => {},
-                ebpf::DIV64_IMM  if !sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::DIV64_REG  if !sbpf_version.enable_pqr() => {},
+                ebpf::MUL64_IMM  => {},
+                ebpf::MUL64_REG  => {},
+                ebpf::DIV64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::DIV64_REG  => {},
                 ebpf::OR64_IMM   => {},
                 ebpf::OR64_REG   => {},
                 ebpf::AND64_IMM  => {},
@@ -320,42 +872,42 @@ impl Verifier for RequisiteVerifier {
                 ebpf::LSH64_REG  => {},
                 ebpf::RSH64_IMM  => { check_imm_shift(&insn, insn_ptr, 64)?; },
                 ebpf::RSH64_REG  => {},
-                ebpf::NEG64      if sbpf_version.enable_neg() => {},
-                ebpf::MOD64_IMM  if !sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::MOD64_REG  if !sbpf_version.enable_pqr() => {},
+                ebpf::NEG64      => {},
+                ebpf::MOD64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::MOD64_REG  => {},
                 ebpf::XOR64_IMM  => {},
                 ebpf::XOR64_REG  => {},
                 ebpf::MOV64_IMM  => {},
                 ebpf::MOV64_REG  => {},
                 ebpf::ARSH64_IMM => { check_imm_shift(&insn, insn_ptr, 64)?; },
                 ebpf::ARSH64_REG => {},
-                ebpf::HOR64_IMM  if !sbpf_version.enable_lddw() => {},
+                ebpf::HOR64_IMM  => {},
 
                 // BPF_PQR class
-                ebpf::LMUL32_IMM if sbpf_version.enable_pqr() => {},
-                ebpf::LMUL32_REG if sbpf_version.enable_pqr() => {},
-                ebpf::LMUL64_IMM if sbpf_version.enable_pqr() => {},
-                ebpf::LMUL64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::UHMUL64_IMM if sbpf_version.enable_pqr() => {},
-                ebpf::UHMUL64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::SHMUL64_IMM if sbpf_version.enable_pqr() => {},
-                ebpf::SHMUL64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::UDIV32_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::UDIV32_REG if sbpf_version.enable_pqr() => {},
-                ebpf::UDIV64_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::UDIV64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::UREM32_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::UREM32_REG if sbpf_version.enable_pqr() => {},
-                ebpf::UREM64_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::UREM64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::SDIV32_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SDIV32_REG if sbpf_version.enable_pqr() => {},
-                ebpf::SDIV64_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SDIV64_REG if sbpf_version.enable_pqr() => {},
-                ebpf::SREM32_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SREM32_REG if sbpf_version.enable_pqr() => {},
-                ebpf::SREM64_IMM if sbpf_version.enable_pqr() => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SREM64_REG if sbpf_version.enable_pqr() => {},
+                ebpf::LMUL32_IMM => {},
+                ebpf::LMUL32_REG => {},
+                ebpf::LMUL64_IMM => {},
+                ebpf::LMUL64_REG => {},
+                ebpf::UHMUL64_IMM => {},
+                ebpf::UHMUL64_REG => {},
+                ebpf::SHMUL64_IMM => {},
+                ebpf::SHMUL64_REG => {},
+                ebpf::UDIV32_IMM => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::UDIV32_REG => {},
+                ebpf::UDIV64_IMM => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::UDIV64_REG => {},
+                ebpf::UREM32_IMM => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::UREM32_REG => {},
+                ebpf::UREM64_IMM => { check_imm_nonzero(&insn, insn_ptr)?; },
+                ebpf::UREM64_REG => {},
+                ebpf::SDIV32_IMM => { check_imm_nonzero(&insn, insn_ptr)?; check_imm_signed_overflow(prog, &insn, insn_ptr, &function_range, 32)?; },
+                ebpf::SDIV32_REG => {},
+                ebpf::SDIV64_IMM => { check_imm_nonzero(&insn, insn_ptr)?; check_imm_signed_overflow(prog, &insn, insn_ptr, &function_range, 64)?; },
+                ebpf::SDIV64_REG => {},
+                ebpf::SREM32_IMM => { check_imm_nonzero(&insn, insn_ptr)?; check_imm_signed_overflow(prog, &insn, insn_ptr, &function_range, 32)?; },
+                ebpf::SREM32_REG => {},
+                ebpf::SREM64_IMM => { check_imm_nonzero(&insn, insn_ptr)?; check_imm_signed_overflow(prog, &insn, insn_ptr, &function_range, 64)?; },
+                ebpf::SREM64_REG => {},
 
                 // BPF_JMP class
                 ebpf::JA         => { check_jmp_offset(prog, insn_ptr, &function_range)?; },
@@ -383,7 +935,10 @@ impl Verifier for RequisiteVerifier {
                 ebpf::JSLE_REG   => { check_jmp_offset(prog, insn_ptr, &function_range)?; },
                 ebpf::CALL_IMM   if sbpf_version.static_syscalls() && insn.src != 0 => { check_call_target(insn.imm as u32, function_registry)?; },
                 ebpf::CALL_IMM   => {},
-                ebpf::CALL_REG   => { check_callx_register(&insn, insn_ptr, config, sbpf_version)?; },
+                ebpf::CALL_REG   => {
+                    let callx_r10_exempted = callx_r10_exempted_ranges.iter().any(|range| range.contains(&insn_ptr));
+                    check_callx_register(&insn, insn_ptr, config, sbpf_version, callx_r10_exempted)?;
+                },
                 ebpf::EXIT       => {},
 
                 _                => {
@@ -392,6 +947,9 @@ impl Verifier for RequisiteVerifier {
             }
 
             check_registers(&insn, store, insn_ptr, sbpf_version)?;
+            if config.reject_stack_frame_overruns && matches!(insn.opc & ebpf::BPF_CLS_MASK, ebpf::BPF_LDX | ebpf::BPF_ST | ebpf::BPF_STX) {
+                check_stack_frame_access(&insn, store, insn_ptr, config, sbpf_version)?;
+            }
 
             insn_ptr += 1;
         }
@@ -401,6 +959,111 @@ impl Verifier for RequisiteVerifier {
             return Err(VerifierError::JumpOutOfCode(insn_ptr, insn_ptr));
         }
 
+        if config.reject_unreachable_code {
+            check_reachability(prog, sbpf_version, &function_starts, pre_verified)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "unstable")]
+/// What a [VerifierPass] can inspect besides the instruction it checks
+pub struct VerifierContext<'a> {
+    /// Text section of the program
+    pub prog: &'a [u8],
+    /// Configuration the program is loaded with
+    pub config: &'a Config,
+    /// SBPF version of the program
+    pub sbpf_version: &'a SBPFVersion,
+    /// Functions of the program by key
+    pub function_registry: &'a FunctionRegistry<usize>,
+}
+
+#[cfg(feature = "unstable")]
+/// Additional verification rule which an embedder adds to a [VerifierPipeline]
+pub trait VerifierPass {
+    /// Checks the instruction at `pc`, the second slot of a lddw is not visited
+    ///
+    /// Custom rules usually report `VerifierError::RuleViolation`.
+    fn check_instruction(
+        &self,
+        context: &VerifierContext,
+        pc: usize,
+        insn: &ebpf::Insn,
+    ) -> Result<(), VerifierError>;
+}
+
+#[cfg(feature = "unstable")]
+/// Rejects `callx`, so that the program can only call known functions and syscalls
+#[derive(Debug, Default)]
+pub struct NoIndirectCalls;
+#[cfg(feature = "unstable")]
+impl VerifierPass for NoIndirectCalls {
+    fn check_instruction(
+        &self,
+        _context: &VerifierContext,
+        pc: usize,
+        insn: &ebpf::Insn,
+    ) -> Result<(), VerifierError> {
+        if insn.opc == ebpf::CALL_REG {
+            return Err(VerifierError::RuleViolation(
+                "indirect calls are not allowed".to_string(),
+                pc,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "unstable")]
+/// Passes which run over the instruction stream after a [Verifier] accepted the program
+///
+/// See `Executable::verify_with_pipeline()`. The passes run in the order they were added and
+/// the first error is reported.
+#[derive(Default)]
+pub struct VerifierPipeline {
+    passes: Vec<Box<dyn VerifierPass + Send + Sync>>,
+}
+
+#[cfg(feature = "unstable")]
+impl VerifierPipeline {
+    /// Creates a pipeline without any passes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a pass which runs after the previously added ones
+    pub fn add_pass<P: VerifierPass + Send + Sync + 'static>(&mut self, pass: P) -> &mut Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Runs all passes over a program which already passed a [Verifier]
+    pub fn verify(
+        &self,
+        prog: &[u8],
+        config: &Config,
+        sbpf_version: &SBPFVersion,
+        function_registry: &FunctionRegistry<usize>,
+    ) -> Result<(), VerifierError> {
+        check_verification_budget(prog, config)?;
+        let context = VerifierContext {
+            prog,
+            config,
+            sbpf_version,
+            function_registry,
+        };
+        let mut pc = 0;
+        while let Ok(insn) = ebpf::get_insn_checked(prog, pc) {
+            for pass in self.passes.iter() {
+                pass.check_instruction(&context, pc, &insn)?;
+            }
+            if insn.opc == ebpf::LD_DW_IMM && sbpf_version.enable_lddw() {
+                pc += 1;
+            }
+            pc += 1;
+        }
         Ok(())
     }
 }
@@ -17,12 +17,26 @@ use crate::{
     elf::Executable,
     error::{EbpfError, ProgramResult},
     interpreter::Interpreter,
-    memory_region::MemoryMapping,
-    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
+    memory_region::{AccessType, MemoryMapping, MemoryRegion},
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion, MAX_SYSCALL_FAULTS},
     static_analysis::{Analysis, TraceLogEntry},
+    verifier::{OpcodeSet, VerifierExemptionPolicy},
 };
+use byteorder::{ByteOrder, LittleEndian};
 use rand::Rng;
 use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
+#[cfg(feature = "unstable")]
+use {
+    crate::{
+        aligned_memory::AlignedMemory,
+        error::SendableError,
+        memory_region::{MemoryState, RegionAccessStatistics},
+    },
+    std::{
+        collections::{HashSet, VecDeque},
+        sync::{Mutex, PoisonError},
+    },
+};
 
 /// Shift the RUNTIME_ENVIRONMENT_KEY by this many bits to the LSB
 ///
@@ -36,8 +50,21 @@ pub fn get_runtime_environment_key() -> i32 {
         .get_or_init(|| rand::thread_rng().gen::<i32>() >> PROGRAM_ENVIRONMENT_KEY_SHIFT)
 }
 
+/// Mitigation against speculative execution of indirect branches in JIT compiled code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpeculationHardening {
+    /// Indirect calls and jumps are emitted as they are
+    None,
+    /// Indirect calls and jumps, as well as the host address lookup after the bounds check of
+    /// callx, are preceded by an lfence
+    LoadFence,
+    /// Indirect calls and jumps go through a retpoline, the host address lookup after the bounds
+    /// check of callx is preceded by an lfence
+    Retpoline,
+}
+
 /// VM configuration settings
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Config {
     /// Maximum call depth
     pub max_call_depth: usize,
@@ -47,24 +74,50 @@ pub struct Config {
     pub enable_address_translation: bool,
     /// Enables gaps in VM address space between the stack frames
     pub enable_stack_frame_gaps: bool,
+    /// Size of the gap in VM address space after every fixed stack frame in bytes, the same as
+    /// `stack_frame_size` if None
+    ///
+    /// Accesses which overrun a frame by up to this many bytes fault instead of reaching the
+    /// neighbouring frame, see `reject_stack_frame_overruns` for accesses reaching further.
+    /// Only takes effect with `enable_stack_frame_gaps`, see `Config::stack_frame_gap_size()`.
+    /// Larger gaps than `u32::MAX` bytes are clamped to that.
+    pub stack_frame_gap: Option<usize>,
     /// Maximal pc distance after which a new instruction meter validation is emitted by the JIT
     pub instruction_meter_checkpoint_distance: usize,
     /// Enable instruction meter and limiting
     pub enable_instruction_meter: bool,
+    /// Makes the JIT validate the instruction meter before every instruction instead of only at branches
+    ///
+    /// This is slower but stops at exactly the same instruction as the interpreter when the budget
+    /// runs out, which is useful for conformance testing.
+    pub exact_instruction_meter: bool,
     /// Enable instruction tracing
     pub enable_instruction_tracing: bool,
+    /// Call `ContextObject::trace_syscall_entry()` and `ContextObject::trace_syscall_exit()`
+    /// around every syscall
+    pub enable_syscall_tracing: bool,
     /// Enable dynamic string allocation for labels
     pub enable_symbol_and_section_labels: bool,
     /// Reject ELF files containing issues that the verifier did not catch before (up to v0.2.21)
     pub reject_broken_elfs: bool,
     /// Ratio of native host instructions per random no-op in JIT (0 = OFF)
     pub noop_instruction_rate: u32,
+    /// Let the JIT insert random no-ops (see noop_instruction_rate) and shift the start of the
+    /// machine code by a random offset, which makes JIT spraying harder
+    pub enable_jit_diversification: bool,
+    /// Seed of the randomness of JIT compilations, a fresh one is drawn for every compilation if
+    /// None. Fixing it makes the emitted machine code reproducible, apart from the host addresses
+    /// and the runtime environment key which it contains.
+    pub jit_diversification_seed: Option<u64>,
     /// Enable disinfection of immediate values and offsets provided by the user in JIT
     pub sanitize_user_provided_values: bool,
     /// Throw ElfError::SymbolHashCollision when a BPF function collides with a registered syscall
     pub external_internal_function_hash_collision: bool,
     /// Have the verifier reject "callx r10"
     pub reject_callx_r10: bool,
+    /// Have the verifier reject frame pointer relative accesses which reach beyond the gaps
+    /// around their fixed stack frame, into the neighbouring frames
+    pub reject_stack_frame_overruns: bool,
     /// Avoid copying read only sections when possible
     pub optimize_rodata: bool,
     /// Use aligned memory mapping
@@ -73,6 +126,64 @@ pub struct Config {
     pub enable_sbpf_v1: bool,
     /// Allow ExecutableCapability::V2
     pub enable_sbpf_v2: bool,
+    /// Append the JIT compiled functions to /tmp/perf-<pid>.map for profiling with perf
+    pub enable_perf_map: bool,
+    /// Let the JIT omit sign extensions whose result is overwritten by the next instruction
+    pub optimize_sign_extensions: bool,
+    /// Let the JIT compile only the entrypoint up front and every other function on its first call
+    pub enable_lazy_jit_compilation: bool,
+    /// Let the JIT emit the functions in the order of the edge counters of the .sbpf.profile
+    /// section, hottest first, so that the hot code is contiguous (not combined with lazy or
+    /// parallel compilation)
+    pub enable_profile_guided_layout: bool,
+    /// Let the JIT write its code through a second mapping of the same memory, so that no page
+    /// is ever writable and executable at the same time (strict W^X, only supported on Linux)
+    pub enable_jit_dual_mapping: bool,
+    /// Register unwind information for the JIT compiled code, so that backtraces taken in
+    /// syscalls continue through it into the host (only supported on Linux with glibc)
+    pub enable_jit_unwind_info: bool,
+    /// Let the JIT compile independent functions on multiple threads (requires the parallel-jit
+    /// feature, not combined with lazy compilation)
+    pub enable_parallel_jit_compilation: bool,
+    /// Spectre mitigation for the indirect calls of callx and syscalls in JIT
+    pub speculation_hardening: SpeculationHardening,
+    /// Zero every fixed size stack frame on function entry, including the one of the entrypoint
+    pub zero_stack_frames: bool,
+    /// Zero the whole stack when the VM is reset, which happens before every execution
+    pub zero_memory_on_reset: bool,
+    /// Exemptions requested by the .sbpf.verifier section of an ELF which the verifier grants
+    pub verifier_exemption_policy: VerifierExemptionPolicy,
+    /// Maximum length in bytes of the text which `Executable::from_text_bytes()` accepts
+    pub max_text_length: usize,
+    /// Reject ELF files with a relocated lddw which points outside of the loaded sections,
+    /// as that hints at broken relocations or a toolchain bug. Off by default, as such files
+    /// were accepted before.
+    pub reject_lddw_outside_loaded_sections: bool,
+    /// Let the JIT read the time stamp counter on every function entry and exit, see
+    /// `EbpfVm::get_function_timings()`
+    pub enable_jit_function_timing: bool,
+    /// Load .text.* sections, e.g. from -ffunction-sections, together with the .text section
+    ///
+    /// They have to be contiguous, both in the file and in the virtual address space. Like all
+    /// section names, theirs are limited to 16 bytes by the parser. Off by default, as it makes
+    /// ELFs load which were rejected before.
+    pub merge_text_sections: bool,
+    /// Reject ELFs with sections whose flags contradict how they are loaded
+    ///
+    /// These are writable and executable sections, executable sections other than the text and
+    /// writable text or read-only data sections.
+    pub reject_wx_sections: bool,
+    /// Reject programs with instructions which can not be reached from any registered function,
+    /// e.g. dead padding between or after the functions
+    pub reject_unreachable_code: bool,
+    /// Opcodes which the verifier rejects in addition to the ones the SBPF version lacks
+    pub forbidden_opcodes: OpcodeSet,
+    /// Maximum number of instructions and relocations which loading and verifying a program may
+    /// process, so that crafted programs can not stall the loader
+    pub verification_budget: usize,
+    /// Count the loads, stores and translated bytes of every memory region, see
+    /// `MemoryMapping::region_statistics()`. The JIT then translates all addresses in Rust.
+    pub enable_region_statistics: bool,
 }
 
 impl Config {
@@ -80,6 +191,18 @@ impl Config {
     pub fn stack_size(&self) -> usize {
         self.stack_frame_size * self.max_call_depth
     }
+
+    /// Returns the size of the gap after every stack frame in VM address space
+    ///
+    /// This is 0 if `enable_stack_frame_gaps` is off or the SBPF version has dynamic stack frames.
+    /// At most `u32::MAX`, the largest gap the chunked stack region can have.
+    pub fn stack_frame_gap_size(&self, sbpf_version: &SBPFVersion) -> u64 {
+        if !sbpf_version.dynamic_stack_frames() && self.enable_stack_frame_gaps {
+            (self.stack_frame_gap.unwrap_or(self.stack_frame_size) as u64).min(u32::MAX as u64)
+        } else {
+            0
+        }
+    }
 }
 
 impl Default for Config {
@@ -89,31 +212,86 @@ impl Default for Config {
             stack_frame_size: 4_096,
             enable_address_translation: true,
             enable_stack_frame_gaps: true,
+            stack_frame_gap: None,
             instruction_meter_checkpoint_distance: 10000,
             enable_instruction_meter: true,
+            exact_instruction_meter: false,
             enable_instruction_tracing: false,
+            enable_syscall_tracing: false,
             enable_symbol_and_section_labels: false,
             reject_broken_elfs: false,
             noop_instruction_rate: 256,
+            enable_jit_diversification: true,
+            jit_diversification_seed: None,
             sanitize_user_provided_values: true,
             external_internal_function_hash_collision: true,
             reject_callx_r10: true,
+            reject_stack_frame_overruns: false,
             optimize_rodata: true,
             aligned_memory_mapping: true,
             enable_sbpf_v1: true,
             enable_sbpf_v2: true,
+            enable_perf_map: false,
+            optimize_sign_extensions: true,
+            enable_lazy_jit_compilation: false,
+            enable_profile_guided_layout: false,
+            enable_jit_dual_mapping: false,
+            enable_jit_unwind_info: false,
+            enable_parallel_jit_compilation: false,
+            speculation_hardening: SpeculationHardening::None,
+            zero_stack_frames: false,
+            zero_memory_on_reset: false,
+            verifier_exemption_policy: VerifierExemptionPolicy::default(),
+            max_text_length: 16 * 1024 * 1024,
+            reject_lddw_outside_loaded_sections: false,
+            enable_jit_function_timing: false,
+            merge_text_sections: false,
+            reject_wx_sections: false,
+            reject_unreachable_code: false,
+            forbidden_opcodes: OpcodeSet::new(),
+            verification_budget: 4 * 1024 * 1024,
+            enable_region_statistics: false,
         }
     }
 }
 
 /// Static constructors for Executable
 impl<C: ContextObject> Executable<C> {
-    /// Creates an executable from an ELF file
+    /// Creates an executable from an ELF file, which may be zstd compressed
     pub fn from_elf(elf_bytes: &[u8], loader: Arc<BuiltinProgram<C>>) -> Result<Self, EbpfError> {
         let executable = Executable::load(elf_bytes, loader)?;
         Ok(executable)
     }
+    /// Creates an executable from an ELF file which starts at the exported function `entrypoint`
+    ///
+    /// See `Executable::load_with_entrypoint()`.
+    pub fn from_elf_with_entry(
+        elf_bytes: &[u8],
+        loader: Arc<BuiltinProgram<C>>,
+        entrypoint: &str,
+    ) -> Result<Self, EbpfError> {
+        let executable = Executable::load_with_entrypoint(elf_bytes, loader, entrypoint)?;
+        Ok(executable)
+    }
+    /// Creates an executable from an ELF file without reading it into memory
+    ///
+    /// See `Executable::load_file()`.
+    ///
+    /// # Safety
+    ///
+    /// The file must neither be modified nor truncated while the executable is alive.
+    #[cfg(all(feature = "mmap", not(target_os = "windows")))]
+    pub unsafe fn from_elf_file<P: AsRef<std::path::Path>>(
+        path: P,
+        loader: Arc<BuiltinProgram<C>>,
+    ) -> Result<Self, EbpfError> {
+        let executable = Executable::load_file(path, loader)?;
+        Ok(executable)
+    }
     /// Creates an executable from machine code
+    ///
+    /// Fails unless the length of `text_bytes` is a non-zero multiple of the instruction size
+    /// and at most `Config::max_text_length`.
     pub fn from_text_bytes(
         text_bytes: &[u8],
         loader: Arc<BuiltinProgram<C>>,
@@ -125,10 +303,74 @@ impl<C: ContextObject> Executable<C> {
     }
 }
 
+/// Selects which executed instructions are passed to [ContextObject::trace]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceSampling {
+    /// Every instruction
+    #[default]
+    All,
+    /// Every n-th instruction, starting with the first one
+    EveryNth(u64),
+    /// Jumps, calls and exits only
+    Branches,
+}
+
+/// Called once the guest exited, with its result (`Ok(r0)` or the error which ended it)
+///
+/// The returned result replaces the one of `EbpfVm::execute_program()`. Like built-in
+/// functions, it keeps any state in the context object.
+#[cfg(feature = "unstable")]
+pub type ExitHook<C> = fn(ProgramResult, &mut C, &MemoryMapping) -> ProgramResult;
+
+/// A load of the interpreter which read stack or heap bytes the program never wrote
+///
+/// See `EbpfVm::set_uninitialized_read_detection()`.
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UninitializedRead {
+    /// Instruction which loaded
+    pub pc: u64,
+    /// First byte of the load
+    pub vm_addr: u64,
+    /// Size of the load in bytes
+    pub len: u64,
+}
+
+/// Host time spent in the calls of one function, measured in time stamp counter ticks
+///
+/// The time includes the syscalls the function made, so that functions which spend most of
+/// their time in syscalls stand out, although they execute few instructions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionTiming {
+    /// Number of calls which returned
+    pub calls: u64,
+    /// Ticks from entry to exit, including the functions it called
+    pub ticks: u64,
+    /// Ticks from entry to exit, excluding the functions it called
+    pub self_ticks: u64,
+}
+
+/// Called by the interpreter with the pc of an instruction and the registers
+///
+/// See `EbpfVm::set_pre_instruction_hook()` and `EbpfVm::set_post_instruction_hook()`. Like
+/// built-in functions, it keeps any state in the context object.
+#[cfg(feature = "unstable")]
+pub type InstructionHook<C> = fn(&mut C, u64, &[u64; 12]);
+
 /// Runtime context
 pub trait ContextObject {
-    /// Called for every instruction executed when tracing is enabled
+    /// Called for every sampled instruction when tracing is enabled
     fn trace(&mut self, state: [u64; 12]);
+    /// Which instructions to trace, queried for every executed instruction
+    fn trace_sampling(&self) -> TraceSampling {
+        TraceSampling::All
+    }
+    /// Called before every syscall with its name and the arguments in r1 to r5, if
+    /// `Config::enable_syscall_tracing` is set
+    fn trace_syscall_entry(&mut self, _name: &[u8], _arguments: [u64; 5]) {}
+    /// Called after every syscall with its name, the arguments in r1 to r5 and its result, if
+    /// `Config::enable_syscall_tracing` is set
+    fn trace_syscall_exit(&mut self, _name: &[u8], _arguments: [u64; 5], _result: &ProgramResult) {}
     /// Consume instructions from meter
     fn consume(&mut self, amount: u64);
     /// Get the number of remaining instructions allowed
@@ -142,6 +384,10 @@ pub struct TestContextObject {
     pub trace_log: Vec<TraceLogEntry>,
     /// Maximal amount of instructions which still can be executed
     pub remaining: u64,
+    /// Which instructions are recorded in the trace_log
+    pub trace_sampling: TraceSampling,
+    /// Describes every syscall entry and exit in order, see `Config::enable_syscall_tracing`
+    pub syscall_log: Vec<String>,
 }
 
 impl ContextObject for TestContextObject {
@@ -149,6 +395,27 @@ impl ContextObject for TestContextObject {
         self.trace_log.push(state);
     }
 
+    fn trace_sampling(&self) -> TraceSampling {
+        self.trace_sampling
+    }
+
+    fn trace_syscall_entry(&mut self, name: &[u8], arguments: [u64; 5]) {
+        self.syscall_log.push(format!(
+            "enter {} {:?}",
+            String::from_utf8_lossy(name),
+            arguments
+        ));
+    }
+
+    fn trace_syscall_exit(&mut self, name: &[u8], arguments: [u64; 5], result: &ProgramResult) {
+        self.syscall_log.push(format!(
+            "exit {} {:?} {:?}",
+            String::from_utf8_lossy(name),
+            arguments,
+            result
+        ));
+    }
+
     fn consume(&mut self, amount: u64) {
         self.remaining = self.remaining.saturating_sub(amount);
     }
@@ -164,6 +431,8 @@ impl TestContextObject {
         Self {
             trace_log: Vec::new(),
             remaining,
+            trace_sampling: TraceSampling::All,
+            syscall_log: Vec::new(),
         }
     }
 
@@ -181,11 +450,14 @@ impl TestContextObject {
 }
 
 /// Statistic of taken branches (from a recorded trace)
+#[derive(Debug, Default, PartialEq, Eq)]
 pub struct DynamicAnalysis {
     /// Maximal edge counter value
     pub edge_counter_max: usize,
     /// src_node, dst_node, edge_counter
     pub edges: BTreeMap<usize, BTreeMap<usize, usize>>,
+    /// Host time per function (by first pc), see `with_function_timings()`
+    pub function_timings: BTreeMap<usize, FunctionTiming>,
 }
 
 impl DynamicAnalysis {
@@ -194,6 +466,7 @@ impl DynamicAnalysis {
         let mut result = Self {
             edge_counter_max: 0,
             edges: BTreeMap::new(),
+            function_timings: BTreeMap::new(),
         };
         let mut last_basic_block = usize::MAX;
         for traced_instruction in trace_log.iter() {
@@ -212,10 +485,63 @@ impl DynamicAnalysis {
         }
         result
     }
+
+    /// Adds the host time per function which a JIT execution measured
+    ///
+    /// See `Config::enable_jit_function_timing` and `EbpfVm::get_function_timings()`.
+    pub fn with_function_timings(
+        mut self,
+        function_timings: &BTreeMap<usize, FunctionTiming>,
+    ) -> Self {
+        for (function_start, timing) in function_timings.iter() {
+            let entry = self.function_timings.entry(*function_start).or_default();
+            entry.calls = entry.calls.saturating_add(timing.calls);
+            entry.ticks = entry.ticks.saturating_add(timing.ticks);
+            entry.self_ticks = entry.self_ticks.saturating_add(timing.self_ticks);
+        }
+        self
+    }
+
+    /// Serializes the edge counters, e.g. for the .sbpf.profile section of an ELF
+    ///
+    /// Each edge is stored as three little endian u64: source, destination and counter.
+    pub fn to_profile_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (src_node, destinations) in self.edges.iter() {
+            for (dst_node, counter) in destinations.iter() {
+                for value in [*src_node, *dst_node, *counter] {
+                    let mut buffer = [0; 8];
+                    LittleEndian::write_u64(&mut buffer, value as u64);
+                    bytes.extend_from_slice(&buffer);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Deserializes the edge counters written by `to_profile_bytes()`
+    pub fn from_profile_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() % 24 != 0 {
+            return None;
+        }
+        let mut result = Self::default();
+        for edge in bytes.chunks_exact(24) {
+            let src_node = LittleEndian::read_u64(&edge[0..8]) as usize;
+            let dst_node = LittleEndian::read_u64(&edge[8..16]) as usize;
+            let counter = LittleEndian::read_u64(&edge[16..24]) as usize;
+            result
+                .edges
+                .entry(src_node)
+                .or_default()
+                .insert(dst_node, counter);
+            result.edge_counter_max = result.edge_counter_max.max(counter);
+        }
+        Some(result)
+    }
 }
 
 /// A call frame used for function calls inside the Interpreter
-#[derive(Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct CallFrame {
     /// The caller saved registers
     pub caller_saved_registers: [u64; ebpf::SCRATCH_REGS],
@@ -225,6 +551,28 @@ pub struct CallFrame {
     pub target_pc: u64,
 }
 
+/// State of an [EbpfVm] in the middle of an execution, see `EbpfVm::snapshot()`
+#[cfg(feature = "unstable")]
+#[derive(Clone)]
+pub struct VmSnapshot {
+    registers: [u64; 12],
+    call_depth: u64,
+    stack_pointer: u64,
+    call_frames: Vec<CallFrame>,
+    previous_instruction_meter: u64,
+    due_insn_count: u64,
+    /// Contents of the stack and the heap region by their start address
+    memory: Vec<(u64, Vec<u8>)>,
+}
+
+#[cfg(feature = "unstable")]
+impl VmSnapshot {
+    /// Registers at the time of the snapshot, r11 being the pc
+    pub fn get_registers(&self) -> &[u64; 12] {
+        &self.registers
+    }
+}
+
 /// A virtual machine to run eBPF programs.
 ///
 /// # Examples
@@ -270,7 +618,7 @@ pub struct CallFrame {
 ///
 /// let memory_mapping = MemoryMapping::new(regions, executable.get_config(), sbpf_version).unwrap();
 ///
-/// let mut vm = EbpfVm::new(loader, sbpf_version, &mut context_object, memory_mapping, stack_len);
+/// let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, stack_len).unwrap();
 ///
 /// let (instruction_count, result) = vm.execute_program(&executable, true);
 /// assert_eq!(instruction_count, 1);
@@ -306,39 +654,89 @@ pub struct EbpfVm<'a, C: ContextObject> {
     pub registers: [u64; 12],
     /// ProgramResult inlined
     pub program_result: ProgramResult,
+    /// Regions of an aligned MemoryMapping, cached for the inline address translation of the JIT
+    pub memory_regions: *const MemoryRegion,
+    /// Number of cached memory regions, zero if the MemoryMapping is unaligned
+    pub memory_region_count: u64,
+    /// Key of the syscall being invoked, only kept up to date while syscall costs are charged or
+    /// syscalls are traced
+    pub(crate) invoked_syscall: u64,
     /// MemoryMapping inlined
     pub memory_mapping: MemoryMapping<'a>,
     /// Stack of CallFrames used by the Interpreter
     pub call_frames: Vec<CallFrame>,
     /// Loader built-in program
     pub loader: Arc<BuiltinProgram<C>>,
+    /// Panic of a built-in function, resumed once execution returns to the host
+    pub builtin_panic: Option<Box<dyn std::any::Any + Send>>,
+    /// Number of instructions seen by the trace sampler in the current execution
+    pub trace_sampling_counter: u64,
+    /// Values of r1 to r5 for the next execution, replacing the pointer to the input region
+    arguments: Option<[u64; 5]>,
+    /// Executable run by the JIT, needed to compile functions on their first call
+    pub(crate) executable: *const Executable<C>,
+    /// Number of calls per function (by first pc) which the interpreter counts if present
+    pub(crate) function_entries: Option<BTreeMap<usize, u64>>,
+    /// Rewrites the result of every execution
+    #[cfg(feature = "unstable")]
+    exit_hook: Option<ExitHook<C>>,
+    /// Inspects the registers before every interpreted instruction
+    #[cfg(feature = "unstable")]
+    pub(crate) pre_instruction_hook: Option<InstructionHook<C>>,
+    /// Inspects the registers after every interpreted instruction
+    #[cfg(feature = "unstable")]
+    pub(crate) post_instruction_hook: Option<InstructionHook<C>>,
+    /// Loads of unwritten memory which the interpreter reports if present
+    #[cfg(feature = "unstable")]
+    pub(crate) uninitialized_reads: Option<Vec<UninitializedRead>>,
+    /// The same loads as `uninitialized_reads`, to find the ones reported before quickly
+    #[cfg(feature = "unstable")]
+    reported_uninitialized_reads: HashSet<UninitializedRead>,
+    /// Suspend interpreted executions instead of failing once the instruction meter is exhausted
+    pub(crate) suspend_on_exhaustion: bool,
+    /// Hash of the executable whose execution was suspended and can be continued with
+    /// `resume_program()`, see `Executable::hash()`
+    pub(crate) suspended: Option<[u8; 32]>,
+    /// Invocations of the syscalls with injected faults (by their slot) so far, see
+    /// `BuiltinProgram::inject_syscall_fault()`
+    pub(crate) syscall_fault_invocations: [u64; MAX_SYSCALL_FAULTS],
+    /// Host time per function (by first pc) which the JIT measured in the last execution
+    pub(crate) function_timings: BTreeMap<usize, FunctionTiming>,
+    /// Time stamp counter at the entry of every timed call in progress and the ticks of its
+    /// callees so far
+    pub(crate) function_timing_stack: Vec<(u64, u64)>,
+    /// Length of the stack region, which starts at ebpf::MM_STACK_START
+    stack_len: usize,
     /// TCP port for the debugger interface
     #[cfg(feature = "debugger")]
     pub debug_port: Option<u16>,
 }
 
 impl<'a, C: ContextObject> EbpfVm<'a, C> {
-    /// Creates a new virtual machine instance.
+    /// Creates a new virtual machine instance for the given executable.
+    ///
+    /// Fails with `EbpfError::IncompatibleExecutable` if the executable can not run with the
+    /// config of its loader, see `Executable::check_compatibility()`.
     pub fn new(
-        loader: Arc<BuiltinProgram<C>>,
-        sbpf_version: &SBPFVersion,
+        executable: &Executable<C>,
         context_object: &'a mut C,
         mut memory_mapping: MemoryMapping<'a>,
         stack_len: usize,
-    ) -> Self {
+    ) -> Result<Self, EbpfError> {
+        let loader = executable.get_loader().clone();
+        let sbpf_version = executable.get_sbpf_version();
         let config = loader.get_config();
-        let stack_pointer =
-            ebpf::MM_STACK_START.saturating_add(if sbpf_version.dynamic_stack_frames() {
-                // the stack is fully descending, frames start as empty and change size anytime r11 is modified
-                stack_len
-            } else {
-                // within a frame the stack grows down, but frames are ascending
-                config.stack_frame_size
-            } as u64);
+        let incompatibilities = executable.get_loader_incompatibilities();
+        if !incompatibilities.is_empty() {
+            return Err(EbpfError::IncompatibleExecutable(
+                incompatibilities.to_vec(),
+            ));
+        }
+        let stack_pointer = Self::initial_stack_pointer(config, sbpf_version, stack_len);
         if !config.enable_address_translation {
             memory_mapping = MemoryMapping::new_identity();
         }
-        EbpfVm {
+        Ok(EbpfVm {
             host_stack_pointer: std::ptr::null_mut(),
             call_depth: 0,
             stack_pointer,
@@ -349,36 +747,309 @@ impl<'a, C: ContextObject> EbpfVm<'a, C> {
             stopwatch_denominator: 0,
             registers: [0u64; 12],
             program_result: ProgramResult::Ok(0),
+            memory_regions: std::ptr::null(),
+            memory_region_count: 0,
+            invoked_syscall: 0,
             memory_mapping,
             call_frames: vec![CallFrame::default(); config.max_call_depth],
             loader,
+            builtin_panic: None,
+            trace_sampling_counter: 0,
+            arguments: None,
+            executable: std::ptr::null(),
+            function_entries: None,
+            #[cfg(feature = "unstable")]
+            exit_hook: None,
+            #[cfg(feature = "unstable")]
+            pre_instruction_hook: None,
+            #[cfg(feature = "unstable")]
+            post_instruction_hook: None,
+            #[cfg(feature = "unstable")]
+            uninitialized_reads: None,
+            #[cfg(feature = "unstable")]
+            reported_uninitialized_reads: HashSet::new(),
+            suspend_on_exhaustion: false,
+            suspended: None,
+            syscall_fault_invocations: [0; MAX_SYSCALL_FAULTS],
+            function_timings: BTreeMap::new(),
+            function_timing_stack: Vec::new(),
+            stack_len,
             #[cfg(feature = "debugger")]
             debug_port: None,
+        })
+    }
+
+    fn initial_stack_pointer(config: &Config, sbpf_version: &SBPFVersion, stack_len: usize) -> u64 {
+        ebpf::MM_STACK_START.saturating_add(if sbpf_version.dynamic_stack_frames() {
+            // the stack is fully descending, frames start as empty and change size anytime r11 is modified
+            stack_len
+        } else {
+            // within a frame the stack grows down, but frames are ascending
+            config.stack_frame_size
+        } as u64)
+    }
+
+    /// Returns the VM to the state it had after `new()`
+    ///
+    /// Clears the registers, the call frames and pending arguments, but keeps the MemoryMapping
+    /// and the exit hook. If `Config::zero_memory_on_reset` is set the stack region is zeroed
+    /// too, so that a pooled VM does not leak data of one program into the next one.
+    pub fn reset(&mut self, executable: &Executable<C>) {
+        let config = executable.get_config();
+        self.call_depth = 0;
+        self.stack_pointer =
+            Self::initial_stack_pointer(config, executable.get_sbpf_version(), self.stack_len);
+        self.registers = [0; 12];
+        self.program_result = ProgramResult::Ok(0);
+        self.call_frames.fill(CallFrame::default());
+        self.arguments = None;
+        self.suspended = None;
+        if config.zero_memory_on_reset {
+            self.zero_memory(ebpf::MM_STACK_START, self.stack_len as u64);
+        }
+        #[cfg(feature = "unstable")]
+        if let Some(uninitialized_reads) = self.uninitialized_reads.as_mut() {
+            uninitialized_reads.clear();
+            self.reported_uninitialized_reads.clear();
+            self.memory_mapping.reset_initialization_tracking();
+        }
+    }
+
+    /// Zeroes the guest memory at `vm_addr` if it is mapped as writable
+    fn zero_memory(&mut self, vm_addr: u64, len: u64) {
+        if let ProgramResult::Ok(host_addr) =
+            self.memory_mapping.map(AccessType::Store, vm_addr, len)
+        {
+            unsafe { std::ptr::write_bytes(host_addr as *mut u8, 0, len as usize) };
+        }
+    }
+
+    /// Zeroes the fixed size stack frame below `frame_pointer`, see `Config::zero_stack_frames`
+    pub(crate) fn zero_stack_frame(&mut self, frame_pointer: u64) {
+        let stack_frame_size = self.loader.get_config().stack_frame_size as u64;
+        self.zero_memory(
+            frame_pointer.saturating_sub(stack_frame_size),
+            stack_frame_size,
+        );
+    }
+
+    /// Passes the arguments in r1 to r5 to the next execution
+    ///
+    /// This replaces the default calling convention in which r1 points to the input region and
+    /// r2 to r5 are zero. It only applies to the next call of `execute_program()`.
+    pub fn set_arguments(&mut self, arguments: &[u64; 5]) {
+        self.arguments = Some(*arguments);
+    }
+
+    /// Installs a hook which can inspect and rewrite the result of every execution
+    ///
+    /// It runs after the guest exited and the instruction meter was settled, e.g. to map the
+    /// error codes a program returns in r0 to host errors. It is not called if a built-in
+    /// function panicked.
+    #[cfg(feature = "unstable")]
+    pub fn set_exit_hook(&mut self, exit_hook: ExitHook<C>) {
+        self.exit_hook = Some(exit_hook);
+    }
+
+    /// Installs a hook which the interpreter calls before every instruction
+    ///
+    /// It receives the pc and the registers as they are before the instruction executes, e.g.
+    /// to collect coverage or to check invariants. The JIT does not call it.
+    #[cfg(feature = "unstable")]
+    pub fn set_pre_instruction_hook(&mut self, hook: InstructionHook<C>) {
+        self.pre_instruction_hook = Some(hook);
+    }
+
+    /// Installs a hook which the interpreter calls after every instruction
+    ///
+    /// It receives the pc of the executed instruction and the registers it left behind. It is
+    /// not called for an instruction which exits the program or throws an error. The JIT does
+    /// not call it.
+    #[cfg(feature = "unstable")]
+    pub fn set_post_instruction_hook(&mut self, hook: InstructionHook<C>) {
+        self.post_instruction_hook = Some(hook);
+    }
+
+    /// Lets the interpreter report loads of stack or heap bytes which were never written
+    ///
+    /// Such loads see whatever the memory held before, which makes the program depend on
+    /// the host. The written bytes are tracked by the MemoryMapping, so that stores of syscalls
+    /// count too. `reset()` forgets them. Only the interpreter reports loads, the JIT does not.
+    /// See `get_uninitialized_reads()`.
+    #[cfg(feature = "unstable")]
+    pub fn set_uninitialized_read_detection(&mut self, enabled: bool) {
+        self.memory_mapping.set_initialization_tracking(enabled);
+        self.uninitialized_reads = enabled.then(Vec::new);
+        self.reported_uninitialized_reads.clear();
+    }
+
+    /// Returns the distinct loads of unwritten memory in the order they happened first
+    ///
+    /// None unless enabled by `set_uninitialized_read_detection()`.
+    #[cfg(feature = "unstable")]
+    pub fn get_uninitialized_reads(&self) -> Option<&[UninitializedRead]> {
+        self.uninitialized_reads.as_deref()
+    }
+
+    /// Returns the host time per function (by first pc) of the last JIT execution
+    ///
+    /// Empty unless `Config::enable_jit_function_timing` is set. Calls which did not return,
+    /// because the program aborted in them, are missing.
+    pub fn get_function_timings(&self) -> &BTreeMap<usize, FunctionTiming> {
+        &self.function_timings
+    }
+
+    /// Makes interpreted executions stop with `EbpfError::ExecutionSuspended` once the
+    /// instruction meter is exhausted, instead of failing with `ExceededMaxInstructions`
+    ///
+    /// The registers, call frames and memory stay as they are, so that the execution can be
+    /// continued with `resume_program()` after the context object got more budget. This allows
+    /// to schedule many programs cooperatively.
+    ///
+    /// JIT compiled executions ignore this mode: They still fail with `ExceededMaxInstructions`
+    /// and can not be resumed.
+    pub fn set_suspend_on_exhaustion(&mut self, enabled: bool) {
+        self.suspend_on_exhaustion = enabled;
+    }
+
+    /// Whether the last execution was suspended and can be resumed
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Reports a successful load of the interpreter at `pc` if it read unwritten memory
+    #[cfg(feature = "unstable")]
+    pub(crate) fn detect_uninitialized_read(&mut self, pc: u64, vm_addr: u64, len: u64) {
+        if let Some(uninitialized_reads) = self.uninitialized_reads.as_mut() {
+            let read = UninitializedRead { pc, vm_addr, len };
+            if !self.memory_mapping.is_initialized(vm_addr, len)
+                && self.reported_uninitialized_reads.insert(read)
+            {
+                uninitialized_reads.push(read);
+            }
         }
     }
 
+    /// Captures the registers, call frames, instruction meter, stack and heap
+    ///
+    /// Together with `restore()` this allows to backtrack or to replay an execution from any
+    /// point, e.g. between `Interpreter::single_step()` calls (see `Interpreter::snapshot()`).
+    /// The input region and the context object are not part of the snapshot.
+    #[cfg(feature = "unstable")]
+    pub fn snapshot(&self) -> VmSnapshot {
+        let memory = self
+            .snapshot_regions()
+            .map(|region| {
+                let contents = unsafe {
+                    std::slice::from_raw_parts(
+                        region.host_addr.get() as *const u8,
+                        region.len as usize,
+                    )
+                };
+                (region.vm_addr, contents.to_vec())
+            })
+            .collect();
+        VmSnapshot {
+            registers: self.registers,
+            call_depth: self.call_depth,
+            stack_pointer: self.stack_pointer,
+            call_frames: self.call_frames.clone(),
+            previous_instruction_meter: self.previous_instruction_meter,
+            due_insn_count: self.due_insn_count,
+            memory,
+        }
+    }
+
+    /// Resets the VM to the state captured by `snapshot()`
+    ///
+    /// Fails if the stack or heap region of this VM does not match the one of the snapshot.
+    #[cfg(feature = "unstable")]
+    pub fn restore(&mut self, snapshot: &VmSnapshot) -> Result<(), EbpfError> {
+        let regions = self.snapshot_regions().collect::<Vec<_>>();
+        for index in 0..regions.len().max(snapshot.memory.len()) {
+            match (regions.get(index), snapshot.memory.get(index)) {
+                (Some(region), Some((vm_addr, contents)))
+                    if region.vm_addr == *vm_addr
+                        && region.len == contents.len() as u64
+                        && region.state.get() == MemoryState::Writable => {}
+                (_, Some((vm_addr, _contents))) => {
+                    return Err(EbpfError::SnapshotMismatch(*vm_addr));
+                }
+                (Some(region), None) => return Err(EbpfError::SnapshotMismatch(region.vm_addr)),
+                (None, None) => {}
+            }
+        }
+        for (region, (_vm_addr, contents)) in regions.iter().zip(snapshot.memory.iter()) {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    contents.as_ptr(),
+                    region.host_addr.get() as *mut u8,
+                    contents.len(),
+                );
+            }
+        }
+        self.registers = snapshot.registers;
+        self.call_depth = snapshot.call_depth;
+        self.stack_pointer = snapshot.stack_pointer;
+        self.call_frames.clone_from(&snapshot.call_frames);
+        self.previous_instruction_meter = snapshot.previous_instruction_meter;
+        self.due_insn_count = snapshot.due_insn_count;
+        self.program_result = ProgramResult::Ok(0);
+        Ok(())
+    }
+
+    /// Stack and heap region of the memory mapping
+    #[cfg(feature = "unstable")]
+    fn snapshot_regions(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.memory_mapping.regions().into_iter().filter(|region| {
+            region.vm_addr == ebpf::MM_STACK_START || region.vm_addr == ebpf::MM_HEAP_START
+        })
+    }
+
+    /// Returns the frames of the BPF to BPF calls in progress, the outermost first
+    ///
+    /// The length is the current call depth. Each frame holds the pc its callee returns to and
+    /// the frame pointer of the caller, which lets a built-in function capture a backtrace of
+    /// the program, e.g. before aborting it. Only the interpreter records the frames, JIT
+    /// compiled code merely tracks the call depth.
+    pub fn get_call_frames(&self) -> &[CallFrame] {
+        &self.call_frames[..(self.call_depth as usize).min(self.call_frames.len())]
+    }
+
+    /// Returns the value of a general purpose register (r0 to r9) after the last execution
+    ///
+    /// The registers are captured when the program exits, gracefully or with an error. In the
+    /// latter case they reflect the state of the faulting instruction, except for a JIT run
+    /// which exceeded its instruction meter, which may have advanced up to the next checkpoint.
+    ///
+    /// # Panics
+    ///
+    /// If `index` is not a general purpose register.
+    pub fn get_register(&self, index: usize) -> u64 {
+        assert!(
+            index < ebpf::FRAME_PTR_REG,
+            "r{} is not a general purpose register",
+            index
+        );
+        self.registers[index]
+    }
+
     /// Execute the program
     ///
     /// If interpreted = `false` then the JIT compiled executable is used.
+    ///
+    /// # Panics
+    ///
+    /// A panic inside a built-in function never unwinds through the guest. It is caught at the
+    /// call boundary, aborts the execution and is then resumed from here. At that point the VM is
+    /// in a consistent state again and can be dropped or reused. The JIT compiled code is owned by
+    /// the Executable, which makes its pages writable again and frees them when it is dropped.
     pub fn execute_program(
         &mut self,
         executable: &Executable<C>,
         interpreted: bool,
     ) -> (u64, ProgramResult) {
-        debug_assert!(Arc::ptr_eq(&self.loader, executable.get_loader()));
-        // R1 points to beginning of input memory, R10 to the stack of the first frame, R11 is the pc (hidden)
-        self.registers[1] = ebpf::MM_INPUT_START;
-        self.registers[ebpf::FRAME_PTR_REG] = self.stack_pointer;
-        self.registers[11] = executable.get_entrypoint_instruction_offset() as u64;
-        let config = executable.get_config();
-        let initial_insn_count = if config.enable_instruction_meter {
-            self.context_object_pointer.get_remaining()
-        } else {
-            0
-        };
-        self.previous_instruction_meter = initial_insn_count;
-        self.due_insn_count = 0;
-        self.program_result = ProgramResult::Ok(0);
+        let initial_insn_count = self.prepare_execution(executable);
         if interpreted {
             #[cfg(feature = "debugger")]
             let debug_port = self.debug_port.clone();
@@ -387,10 +1058,10 @@ impl<'a, C: ContextObject> EbpfVm<'a, C> {
             if let Some(debug_port) = debug_port {
                 crate::debugger::execute(&mut interpreter, debug_port);
             } else {
-                while interpreter.step() {}
+                interpreter.run();
             }
             #[cfg(not(feature = "debugger"))]
-            while interpreter.step() {}
+            interpreter.run();
         } else {
             #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
             {
@@ -401,6 +1072,14 @@ impl<'a, C: ContextObject> EbpfVm<'a, C> {
                     Ok(compiled_program) => compiled_program,
                     Err(error) => return (0, ProgramResult::Err(error)),
                 };
+                self.cache_memory_regions();
+                self.executable = executable;
+                let config = executable.get_config();
+                if config.enable_jit_function_timing {
+                    self.function_timings.clear();
+                    self.function_timing_stack.clear();
+                    self.enter_function();
+                }
                 compiled_program.invoke(config, self, self.registers);
             }
             #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
@@ -408,17 +1087,230 @@ impl<'a, C: ContextObject> EbpfVm<'a, C> {
                 return (0, ProgramResult::Err(EbpfError::JitNotCompiled));
             }
         };
+        self.finish_execution(executable.get_config(), initial_insn_count)
+    }
+
+    /// Continues a suspended execution with the budget the context object has now
+    ///
+    /// Returns the instructions executed since the suspension and the result, which is
+    /// `EbpfError::ExecutionSuspended` again if the budget ran out once more. Fails with
+    /// `EbpfError::NotSuspended` if there is nothing to resume and with
+    /// `EbpfError::ResumedWithOtherExecutable` if `executable` does not have the same hash as the
+    /// one the execution was suspended in, which leaves the suspended execution as it is.
+    pub fn resume_program(&mut self, executable: &Executable<C>) -> (u64, ProgramResult) {
+        match self.suspended {
+            None => return (0, ProgramResult::Err(EbpfError::NotSuspended)),
+            Some(hash) if hash != executable.hash() => {
+                return (0, ProgramResult::Err(EbpfError::ResumedWithOtherExecutable));
+            }
+            Some(_) => {}
+        }
+        self.suspended = None;
+        let config = executable.get_config();
+        let initial_insn_count = if config.enable_instruction_meter {
+            self.context_object_pointer.get_remaining()
+        } else {
+            0
+        };
+        self.previous_instruction_meter = initial_insn_count;
+        self.due_insn_count = 0;
+        self.program_result = ProgramResult::Ok(0);
+        let mut interpreter = Interpreter::new(self, executable, self.registers);
+        interpreter.run();
+        self.finish_execution(config, initial_insn_count)
+    }
+
+    /// Prepares an interpreted execution which the caller advances one instruction at a time
+    ///
+    /// The registers can be inspected between `Interpreter::step()` calls and
+    /// `Interpreter::finish()` returns the same as `execute_program()` would have.
+    ///
+    /// ```
+    /// use solana_rbpf::{
+    ///     elf::Executable,
+    ///     memory_region::MemoryMapping,
+    ///     program::{BuiltinProgram, FunctionRegistry, SBPFVersion},
+    ///     vm::{Config, EbpfVm, TestContextObject},
+    /// };
+    /// use std::sync::Arc;
+    ///
+    /// let prog = &[
+    ///     0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov64 r0, 42
+    ///     0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    /// ];
+    /// let loader = Arc::new(BuiltinProgram::new_mock());
+    /// let function_registry = FunctionRegistry::default();
+    /// let executable = Executable::<TestContextObject>::from_text_bytes(prog, loader, SBPFVersion::V2, function_registry).unwrap();
+    /// let mut context_object = TestContextObject::new(2);
+    /// let memory_mapping = MemoryMapping::new(Vec::new(), executable.get_config(), executable.get_sbpf_version()).unwrap();
+    /// let mut vm = EbpfVm::new(&executable, &mut context_object, memory_mapping, 0).unwrap();
+    ///
+    /// let mut interpreter = vm.interpreter(&executable);
+    /// assert!(interpreter.step());
+    /// assert_eq!(interpreter.reg[0], 42);
+    /// assert!(!interpreter.step());
+    /// let (instruction_count, result) = interpreter.finish();
+    /// assert_eq!(instruction_count, 2);
+    /// assert_eq!(result.unwrap(), 42);
+    /// ```
+    pub fn interpreter<'b>(&'b mut self, executable: &'b Executable<C>) -> Interpreter<'b, 'a, C> {
+        let initial_insn_count = self.prepare_execution(executable);
+        let registers = self.registers;
+        let mut interpreter = Interpreter::new(self, executable, registers);
+        interpreter.initial_insn_count = initial_insn_count;
+        interpreter
+    }
+
+    /// Resets the VM and sets up the registers of the entrypoint, returns the initial meter
+    fn prepare_execution(&mut self, executable: &Executable<C>) -> u64 {
+        debug_assert!(Arc::ptr_eq(&self.loader, executable.get_loader()));
+        let arguments = self
+            .arguments
+            .take()
+            .unwrap_or([ebpf::MM_INPUT_START, 0, 0, 0, 0]);
+        self.reset(executable);
+        let config = executable.get_config();
+        if config.zero_stack_frames && !executable.get_sbpf_version().dynamic_stack_frames() {
+            self.zero_stack_frame(self.stack_pointer);
+        }
+        // R1 points to beginning of input memory, R10 to the stack of the first frame, R11 is the pc (hidden)
+        self.registers[1..6].copy_from_slice(&arguments);
+        self.registers[ebpf::FRAME_PTR_REG] = self.stack_pointer;
+        self.registers[11] = executable.get_entrypoint_instruction_offset() as u64;
+        let initial_insn_count = if config.enable_instruction_meter {
+            self.context_object_pointer.get_remaining()
+        } else {
+            0
+        };
+        self.previous_instruction_meter = initial_insn_count;
+        self.due_insn_count = 0;
+        self.trace_sampling_counter = 0;
+        initial_insn_count
+    }
+
+    /// Settles the instruction meter and takes the result of an execution
+    pub(crate) fn finish_execution(
+        &mut self,
+        config: &Config,
+        initial_insn_count: u64,
+    ) -> (u64, ProgramResult) {
+        if let Some(payload) = self.builtin_panic.take() {
+            std::panic::resume_unwind(payload);
+        }
         let instruction_count = if config.enable_instruction_meter {
             self.context_object_pointer.consume(self.due_insn_count);
             initial_insn_count.saturating_sub(self.context_object_pointer.get_remaining())
         } else {
             0
         };
-        let mut result = ProgramResult::Ok(0);
-        std::mem::swap(&mut result, &mut self.program_result);
+        let result = std::mem::replace(&mut self.program_result, ProgramResult::Ok(0));
+        #[cfg(feature = "unstable")]
+        let result = match self.exit_hook.filter(|_| self.suspended.is_none()) {
+            Some(exit_hook) => exit_hook(result, self.context_object_pointer, &self.memory_mapping),
+            None => result,
+        };
         (instruction_count, result)
     }
 
+    /// Passes the registers to ContextObject::trace() if the instruction is sampled
+    pub(crate) fn trace_instruction(&mut self, registers: &[u64; 12], is_branch: bool) {
+        let sampled = match self.context_object_pointer.trace_sampling() {
+            TraceSampling::All => true,
+            TraceSampling::EveryNth(n) => self.trace_sampling_counter % n.max(1) == 0,
+            TraceSampling::Branches => is_branch,
+        };
+        self.trace_sampling_counter += 1;
+        if sampled {
+            self.context_object_pointer.trace(*registers);
+        }
+    }
+
+    /// Passes the syscall in `invoked_syscall` to ContextObject::trace_syscall_entry()
+    pub(crate) fn trace_syscall_entry(
+        &mut self,
+        arg_a: u64,
+        arg_b: u64,
+        arg_c: u64,
+        arg_d: u64,
+        arg_e: u64,
+    ) {
+        let name = Self::syscall_name(&self.loader, self.invoked_syscall);
+        self.context_object_pointer
+            .trace_syscall_entry(name, [arg_a, arg_b, arg_c, arg_d, arg_e]);
+    }
+
+    /// Passes the syscall in `invoked_syscall` and its result to
+    /// ContextObject::trace_syscall_exit()
+    pub(crate) fn trace_syscall_exit(
+        &mut self,
+        arg_a: u64,
+        arg_b: u64,
+        arg_c: u64,
+        arg_d: u64,
+        arg_e: u64,
+    ) {
+        let name = Self::syscall_name(&self.loader, self.invoked_syscall);
+        self.context_object_pointer.trace_syscall_exit(
+            name,
+            [arg_a, arg_b, arg_c, arg_d, arg_e],
+            &self.program_result,
+        );
+    }
+
+    /// Name under which the syscall with `key` is registered in the loader
+    fn syscall_name(loader: &BuiltinProgram<C>, key: u64) -> &[u8] {
+        loader
+            .get_function_registry()
+            .lookup_by_key(key as u32)
+            .map(|(name, _function)| name)
+            .unwrap_or_default()
+    }
+
+    /// Starts timing a function call, see `Config::enable_jit_function_timing`
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub(crate) fn enter_function(&mut self) {
+        let now = unsafe { std::arch::x86_64::_rdtsc() };
+        self.function_timing_stack.push((now, 0));
+    }
+
+    /// Attributes the time since the matching `enter_function()` to `function_start`
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    pub(crate) fn leave_function(&mut self, function_start: u64) {
+        let now = unsafe { std::arch::x86_64::_rdtsc() };
+        let Some((entry, callee_ticks)) = self.function_timing_stack.pop() else {
+            return;
+        };
+        let ticks = now.saturating_sub(entry);
+        let timing = self
+            .function_timings
+            .entry(function_start as usize)
+            .or_default();
+        timing.calls = timing.calls.saturating_add(1);
+        timing.ticks = timing.ticks.saturating_add(ticks);
+        timing.self_ticks = timing
+            .self_ticks
+            .saturating_add(ticks.saturating_sub(callee_ticks));
+        if let Some((_entry, callee_ticks)) = self.function_timing_stack.last_mut() {
+            *callee_ticks = callee_ticks.saturating_add(ticks);
+        }
+    }
+
+    /// Refreshes memory_regions and memory_region_count from the current MemoryMapping
+    ///
+    /// Active watchpoints, initialization and dirty tracking leave no regions for the inline
+    /// fast path of the JIT, so that every access goes through `MemoryMapping::load()` or
+    /// `MemoryMapping::store()`.
+    pub(crate) fn cache_memory_regions(&mut self) {
+        let regions = match &self.memory_mapping {
+            MemoryMapping::Aligned(mapping) if !self.memory_mapping.observes_accesses() => {
+                mapping.get_regions()
+            }
+            _ => &[],
+        };
+        self.memory_regions = regions.as_ptr();
+        self.memory_region_count = regions.len() as u64;
+    }
+
     /// Invokes a built-in function
     pub fn invoke_function(&mut self, function: BuiltinFunction<C>) {
         function(
@@ -436,3 +1328,388 @@ impl<'a, C: ContextObject> EbpfVm<'a, C> {
         );
     }
 }
+
+/// Starts executing a program in the interpreter and switches the whole program to the JIT once
+/// it is hot
+///
+/// The entries of every function (found by the static `Analysis`) are counted while the program
+/// is interpreted. As soon as any one function reaches the hotness threshold, the executable is
+/// JIT compiled and all later executions run only compiled code, as an execution can not mix
+/// the two tiers. With `Config::enable_lazy_jit_compilation` only the functions which are
+/// actually called get compiled, so cold code stays cheap.
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WholeProgramTieredExecutor {
+    /// Number of entries after which a function is considered hot
+    hotness_threshold: u64,
+    /// Number of entries per function (by first pc) while interpreted
+    function_entries: BTreeMap<usize, u64>,
+}
+
+#[cfg(feature = "unstable")]
+impl WholeProgramTieredExecutor {
+    /// Creates an executor which JIT compiles the executable once any function was entered
+    /// `hotness_threshold` times
+    pub fn new<C: ContextObject>(
+        executable: &Executable<C>,
+        hotness_threshold: u64,
+    ) -> Result<Self, EbpfError> {
+        let analysis = Analysis::from_executable(executable)?;
+        Ok(Self {
+            hotness_threshold,
+            function_entries: analysis.functions.keys().map(|pc| (*pc, 0)).collect(),
+        })
+    }
+
+    /// Number of entries per function (by first pc) while interpreted
+    pub fn get_function_entries(&self) -> &BTreeMap<usize, u64> {
+        &self.function_entries
+    }
+
+    /// Whether any function reached the hotness threshold
+    pub fn is_hot(&self) -> bool {
+        self.function_entries
+            .values()
+            .any(|entries| *entries >= self.hotness_threshold)
+    }
+
+    /// Executes the program in the tier it reached, see `EbpfVm::execute_program()`
+    ///
+    /// If the JIT compilation fails, the program simply stays interpreted.
+    pub fn execute_program<C: ContextObject>(
+        &mut self,
+        vm: &mut EbpfVm<C>,
+        executable: &Executable<C>,
+    ) -> (u64, ProgramResult) {
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if executable.get_compiled_program().is_some() {
+            return vm.execute_program(executable, false);
+        }
+        let mut function_entries = std::mem::take(&mut self.function_entries);
+        *function_entries
+            .entry(executable.get_entrypoint_instruction_offset())
+            .or_default() += 1;
+        vm.function_entries = Some(function_entries);
+        let result = vm.execute_program(executable, true);
+        self.function_entries = vm.function_entries.take().unwrap_or_default();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if self.is_hot() {
+            let _ = executable.jit_compile_once();
+        }
+        result
+    }
+}
+
+/// One execution of a [BatchExecutor]
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Default)]
+pub struct BatchJob<C: ContextObject> {
+    /// Contents of the writable input region at `ebpf::MM_INPUT_START`
+    pub input: Vec<u8>,
+    /// Size of the zero filled heap region at `ebpf::MM_HEAP_START`
+    pub heap_size: usize,
+    /// Context object of the execution, its instruction meter is the budget of the job
+    pub context_object: C,
+}
+
+/// Outcome of a [BatchJob]
+#[cfg(feature = "unstable")]
+#[derive(Debug)]
+pub struct ExecutionSummary<C: ContextObject> {
+    /// Number of executed instructions, zero if the instruction meter is disabled
+    pub instruction_count: u64,
+    /// Result of the execution
+    ///
+    /// A `EbpfError::SyscallError` only keeps the message of the original error, as that does
+    /// not have to be `Send`.
+    pub result: ProgramResult,
+    /// The input region as the program left it
+    pub input: Vec<u8>,
+    /// The context object as the program left it
+    pub context_object: C,
+    /// Accesses per region, keyed by the start address of the region, see
+    /// `MemoryMapping::region_statistics()`
+    ///
+    /// Only present if `Config::enable_region_statistics` is set.
+    pub region_statistics: Option<BTreeMap<u64, RegionAccessStatistics>>,
+}
+
+/// ExecutionSummary with the result in a representation which can leave the worker thread
+#[cfg(feature = "unstable")]
+struct SendableExecutionSummary<C: ContextObject> {
+    instruction_count: u64,
+    result: Result<u64, SendableError>,
+    input: Vec<u8>,
+    context_object: C,
+    region_statistics: Option<BTreeMap<u64, RegionAccessStatistics>>,
+}
+
+#[cfg(feature = "unstable")]
+impl<C: ContextObject> From<ExecutionSummary<C>> for SendableExecutionSummary<C> {
+    fn from(summary: ExecutionSummary<C>) -> Self {
+        Self {
+            instruction_count: summary.instruction_count,
+            result: Result::from(summary.result).map_err(SendableError::from),
+            input: summary.input,
+            context_object: summary.context_object,
+            region_statistics: summary.region_statistics,
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl<C: ContextObject> From<SendableExecutionSummary<C>> for ExecutionSummary<C> {
+    fn from(summary: SendableExecutionSummary<C>) -> Self {
+        Self {
+            instruction_count: summary.instruction_count,
+            result: summary.result.map_err(EbpfError::from).into(),
+            input: summary.input,
+            context_object: summary.context_object,
+            region_statistics: summary.region_statistics,
+        }
+    }
+}
+
+/// Runs many jobs of the same program on a pool of threads
+///
+/// This covers the common workload of simulating many transactions against one program: Every
+/// worker thread has its own VM and stack and takes the next job as soon as it is done with the
+/// previous one, so that long running jobs do not hold up the others. The executable is shared
+/// by all workers, which run its JIT compiled program if present and interpret it otherwise.
+/// A panic of a built-in function is resumed on the calling thread.
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchExecutor {
+    /// Number of worker threads
+    thread_count: usize,
+}
+
+#[cfg(feature = "unstable")]
+impl Default for BatchExecutor {
+    fn default() -> Self {
+        Self::new(
+            std::thread::available_parallelism()
+                .map(|thread_count| thread_count.get())
+                .unwrap_or(1),
+        )
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl BatchExecutor {
+    /// Creates an executor with up to `thread_count` worker threads (at least one)
+    pub fn new(thread_count: usize) -> Self {
+        Self {
+            thread_count: thread_count.max(1),
+        }
+    }
+
+    /// Executes all jobs and returns their summaries in the same order
+    pub fn execute<C: ContextObject + Send>(
+        &self,
+        executable: &Executable<C>,
+        jobs: Vec<BatchJob<C>>,
+    ) -> Vec<ExecutionSummary<C>> {
+        let job_count = jobs.len();
+        let queue = Mutex::new(jobs.into_iter().enumerate().collect::<VecDeque<_>>());
+        let summaries = Mutex::new(
+            std::iter::repeat_with(|| None)
+                .take(job_count)
+                .collect::<Vec<_>>(),
+        );
+        std::thread::scope(|scope| {
+            for _ in 0..self.thread_count.min(job_count) {
+                scope.spawn(|| {
+                    let mut stack = AlignedMemory::<{ ebpf::HOST_ALIGN }>::zero_filled(
+                        executable.get_config().stack_size(),
+                    );
+                    loop {
+                        let job = queue
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .pop_front();
+                        let Some((index, job)) = job else {
+                            break;
+                        };
+                        let summary = self.execute_job(executable, &mut stack, job);
+                        summaries.lock().unwrap_or_else(PoisonError::into_inner)[index] =
+                            Some(SendableExecutionSummary::from(summary));
+                    }
+                });
+            }
+        });
+        summaries
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .into_iter()
+            .map(|summary| summary.expect("every job is executed").into())
+            .collect()
+    }
+
+    fn execute_job<C: ContextObject>(
+        &self,
+        executable: &Executable<C>,
+        stack: &mut AlignedMemory<{ ebpf::HOST_ALIGN }>,
+        job: BatchJob<C>,
+    ) -> ExecutionSummary<C> {
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        let interpreted = executable.get_compiled_program().is_none();
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        let interpreted = true;
+        execute_job_in(executable, stack, job, interpreted).0
+    }
+}
+
+/// Executes a job in the given tier and also returns the registers in which it ended
+#[cfg(feature = "unstable")]
+fn execute_job_in<C: ContextObject>(
+    executable: &Executable<C>,
+    stack: &mut AlignedMemory<{ ebpf::HOST_ALIGN }>,
+    mut job: BatchJob<C>,
+    interpreted: bool,
+) -> (ExecutionSummary<C>, [u64; 12]) {
+    let config = executable.get_config();
+    let sbpf_version = executable.get_sbpf_version();
+    stack.as_slice_mut().fill(0);
+    let stack_len = stack.len();
+    let mut heap = AlignedMemory::<{ ebpf::HOST_ALIGN }>::zero_filled(job.heap_size);
+    let regions = vec![
+        executable.get_ro_region(),
+        MemoryRegion::new_stack(stack.as_slice_mut(), config, sbpf_version),
+        MemoryRegion::new_writable(heap.as_slice_mut(), ebpf::MM_HEAP_START),
+        MemoryRegion::new_writable(&mut job.input, ebpf::MM_INPUT_START),
+    ];
+    let mut region_statistics = None;
+    let mut registers = [0; 12];
+    let (instruction_count, result) = match MemoryMapping::new(regions, config, sbpf_version)
+        .and_then(|memory_mapping| {
+            EbpfVm::new(
+                executable,
+                &mut job.context_object,
+                memory_mapping,
+                stack_len,
+            )
+        }) {
+        Ok(mut vm) => {
+            let summary = vm.execute_program(executable, interpreted);
+            region_statistics = config
+                .enable_region_statistics
+                .then(|| vm.memory_mapping.region_statistics());
+            registers = vm.registers;
+            summary
+        }
+        Err(error) => (0, ProgramResult::Err(error)),
+    };
+    (
+        ExecutionSummary {
+            instruction_count,
+            result,
+            input: job.input,
+            context_object: job.context_object,
+            region_statistics,
+        },
+        registers,
+    )
+}
+
+/// Part of the outcome in which the interpreter and the JIT disagreed, see [execute_lockstep]
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockstepMismatch {
+    /// The register states in the trace logs differ
+    TraceLog,
+    /// The results differ
+    Result,
+    /// The instruction meters differ
+    InstructionCount,
+    /// The pcs at which the executions ended differ
+    FinalPc,
+    /// The registers in which the executions ended differ
+    Registers,
+    /// The input regions differ after a successful execution
+    Input,
+}
+
+/// First disagreement between the interpreter and the JIT, see [execute_lockstep]
+#[cfg(feature = "unstable")]
+#[derive(Debug)]
+pub struct LockstepDivergence<C: ContextObject> {
+    /// What differs, checked in the order of the variants
+    pub mismatch: LockstepMismatch,
+    /// Pc of the first trace log entry which differs, otherwise the final pc of the interpreter
+    pub pc: u64,
+    /// Outcome of the interpreter
+    pub interpreter: ExecutionSummary<C>,
+    /// Outcome of the JIT compiled program
+    pub jit: ExecutionSummary<C>,
+}
+
+/// Executes a job with the interpreter and with the JIT compiled program and compares them
+///
+/// Both start from identical copies of the job and are compared in their trace logs, results,
+/// instruction meters, final pcs and registers, as well as in their input regions if they
+/// succeeded. `trace_log` extracts the trace log from the context object, it is empty unless
+/// `Config::enable_instruction_tracing` is set. The trace logs have to be equal, except when the
+/// instruction meter was exceeded: As the JIT only validates it at checkpoints, it may trace
+/// additional instructions and end in different registers then. The executable has to be JIT
+/// compiled beforehand, otherwise the JIT diverges with `EbpfError::JitNotCompiled`.
+///
+/// Returns the outcome of the interpreter if both agree.
+#[cfg(all(
+    feature = "unstable",
+    feature = "jit",
+    not(target_os = "windows"),
+    target_arch = "x86_64"
+))]
+pub fn execute_lockstep<C: ContextObject + Clone>(
+    executable: &Executable<C>,
+    job: BatchJob<C>,
+    trace_log: impl Fn(&C) -> &[[u64; 12]],
+) -> Result<ExecutionSummary<C>, Box<LockstepDivergence<C>>> {
+    let mut stack =
+        AlignedMemory::<{ ebpf::HOST_ALIGN }>::zero_filled(executable.get_config().stack_size());
+    let (interpreter, interpreter_registers) =
+        execute_job_in(executable, &mut stack, job.clone(), true);
+    let (jit, jit_registers) = execute_job_in(executable, &mut stack, job, false);
+    let interpreter_trace_log = trace_log(&interpreter.context_object);
+    let jit_trace_log = trace_log(&jit.context_object);
+    let exceeded_max_instructions = matches!(
+        interpreter.result,
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions)
+    );
+    let compared_trace_length = if exceeded_max_instructions {
+        interpreter_trace_log.len()
+    } else {
+        interpreter_trace_log.len().max(jit_trace_log.len())
+    };
+    let first_divergent_entry = (0..compared_trace_length)
+        .find(|index| interpreter_trace_log.get(*index) != jit_trace_log.get(*index))
+        .and_then(|index| {
+            interpreter_trace_log
+                .get(index)
+                .or_else(|| jit_trace_log.get(index))
+        })
+        .map(|entry| entry[11]);
+    let interpreter_final_pc = interpreter_registers[11];
+    let (mismatch, pc) = if let Some(pc) = first_divergent_entry {
+        (LockstepMismatch::TraceLog, pc)
+    } else if interpreter.result != jit.result {
+        (LockstepMismatch::Result, interpreter_final_pc)
+    } else if interpreter.instruction_count != jit.instruction_count {
+        (LockstepMismatch::InstructionCount, interpreter_final_pc)
+    } else if interpreter_final_pc != jit_registers[11] {
+        (LockstepMismatch::FinalPc, interpreter_final_pc)
+    } else if !exceeded_max_instructions && interpreter_registers != jit_registers {
+        (LockstepMismatch::Registers, interpreter_final_pc)
+    } else if interpreter.result.is_ok() && interpreter.input != jit.input {
+        (LockstepMismatch::Input, interpreter_final_pc)
+    } else {
+        return Ok(interpreter);
+    };
+    Err(Box::new(LockstepDivergence {
+        mismatch,
+        pc,
+        interpreter,
+        jit,
+    }))
+}
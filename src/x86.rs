@@ -194,7 +194,7 @@ impl X86Instruction {
             first_operand: source,
             second_operand: destination,
             immediate_size: match opcode {
-                0xc1 => OperandSize::S8,
+                0xc1 | 0x83 => OperandSize::S8,
                 0x81 => OperandSize::S32,
                 0xf7 if source == 0 => OperandSize::S32,
                 _ => OperandSize::S0,
@@ -639,6 +639,19 @@ impl X86Instruction {
         }
     }
 
+    /// Jump to absolute destination
+    #[inline]
+    pub const fn jump_reg(destination: u8, indirect: Option<X86IndirectAccess>) -> Self {
+        Self {
+            size: OperandSize::S64,
+            opcode: 0xff,
+            first_operand: 4,
+            second_operand: destination,
+            indirect,
+            ..Self::DEFAULT
+        }
+    }
+
     /// Pop RIP
     #[inline]
     pub const fn return_near() -> Self {
@@ -698,7 +711,6 @@ impl X86Instruction {
     }
 
     /// lfence / sfence / mfence
-    #[allow(dead_code)]
     #[inline]
     pub const fn fence(fence_type: FenceType) -> Self {
         Self {
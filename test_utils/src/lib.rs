@@ -169,15 +169,7 @@ pub fn create_memory_mapping<'a, C: ContextObject>(
     let sbpf_version = executable.get_sbpf_version();
     let regions: Vec<MemoryRegion> = vec![
         executable.get_ro_region(),
-        MemoryRegion::new_writable_gapped(
-            stack.as_slice_mut(),
-            ebpf::MM_STACK_START,
-            if !sbpf_version.dynamic_stack_frames() && config.enable_stack_frame_gaps {
-                config.stack_frame_size as u64
-            } else {
-                0
-            },
-        ),
+        MemoryRegion::new_stack(stack.as_slice_mut(), config, sbpf_version),
         MemoryRegion::new_writable(heap.as_slice_mut(), ebpf::MM_HEAP_START),
     ]
     .into_iter()
@@ -208,12 +200,12 @@ macro_rules! create_vm {
         )
         .unwrap();
         let mut $vm_name = solana_rbpf::vm::EbpfVm::new(
-            $verified_executable.get_loader().clone(),
-            $verified_executable.get_sbpf_version(),
+            $verified_executable,
             $context_object,
             memory_mapping,
             stack_len,
-        );
+        )
+        .unwrap();
     };
 }
 
@@ -8,18 +8,38 @@
 extern crate solana_rbpf;
 extern crate test_utils;
 
-use solana_rbpf::{assembler::assemble, ebpf, program::BuiltinProgram, vm::TestContextObject};
+use solana_rbpf::{
+    assembler::assemble,
+    ebpf,
+    program::{BuiltinProgram, FunctionRegistry},
+    vm::{Config, TestContextObject},
+};
 use std::sync::Arc;
 use test_utils::{TCP_SACK_ASM, TCP_SACK_BIN};
 
-fn asm(src: &str) -> Result<Vec<ebpf::Insn>, String> {
-    let executable = assemble::<TestContextObject>(src, Arc::new(BuiltinProgram::new_mock()))?;
+fn asm_with_config(src: &str, config: Config) -> Result<Vec<ebpf::Insn>, String> {
+    let loader = BuiltinProgram::new_loader(config, FunctionRegistry::default());
+    let executable = assemble::<TestContextObject>(src, Arc::new(loader))?;
     let (_program_vm_addr, program) = executable.get_text_bytes();
     Ok((0..program.len() / ebpf::INSN_SIZE)
         .map(|insn_ptr| ebpf::get_insn(program, insn_ptr))
         .collect())
 }
 
+fn asm(src: &str) -> Result<Vec<ebpf::Insn>, String> {
+    asm_with_config(src, Config::default())
+}
+
+fn asm_v1(src: &str) -> Result<Vec<ebpf::Insn>, String> {
+    asm_with_config(
+        src,
+        Config {
+            enable_sbpf_v2: false,
+            ..Config::default()
+        },
+    )
+}
+
 fn insn(ptr: usize, opc: u8, dst: u8, src: u8, off: i16, imm: i64) -> ebpf::Insn {
     ebpf::Insn {
         ptr,
@@ -33,7 +53,10 @@ fn insn(ptr: usize, opc: u8, dst: u8, src: u8, off: i16, imm: i64) -> ebpf::Insn
 
 #[test]
 fn test_empty() {
-    assert_eq!(asm(""), Ok(vec![]));
+    assert_eq!(
+        asm(""),
+        Err("Executable constructor ElfError(EmptyText)".to_string())
+    );
 }
 
 #[test]
@@ -69,7 +92,10 @@ fn test_add64() {
 // Example for InstructionType::AluUnary.
 #[test]
 fn test_neg64() {
-    assert_eq!(asm("neg64 r1"), Ok(vec![insn(0, ebpf::NEG64, 1, 0, 0, 0)]));
+    assert_eq!(
+        asm_v1("neg64 r1"),
+        Ok(vec![insn(0, ebpf::NEG64, 1, 0, 0, 0)])
+    );
 }
 
 // Example for InstructionType::LoadReg.
@@ -146,14 +172,14 @@ fn test_be32() {
 #[test]
 fn test_lddw() {
     assert_eq!(
-        asm("lddw r1, 0x1234abcd5678eeff"),
+        asm_v1("lddw r1, 0x1234abcd5678eeff"),
         Ok(vec![
             insn(0, ebpf::LD_DW_IMM, 1, 0, 0, 0x5678eeff),
             insn(1, 0, 0, 0, 0, 0x1234abcd)
         ])
     );
     assert_eq!(
-        asm("lddw r1, 0xff11ee22dd33cc44"),
+        asm_v1("lddw r1, 0xff11ee22dd33cc44"),
         Ok(vec![
             insn(0, ebpf::LD_DW_IMM, 1, 0, 0, 0xffffffffdd33cc44u64 as i64),
             insn(1, 0, 0, 0, 0, 0xffffffffff11ee22u64 as i64)
@@ -192,7 +218,8 @@ fn test_stxh() {
 #[test]
 fn test_alu_binary() {
     assert_eq!(
-        asm("add r1, r2
+        asm_v1(
+            "add r1, r2
              sub r1, r2
              mul r1, r2
              div r1, r2
@@ -203,7 +230,8 @@ fn test_alu_binary() {
              mod r1, r2
              xor r1, r2
              mov r1, r2
-             arsh r1, r2"),
+             arsh r1, r2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD64_REG, 1, 2, 0, 0),
             insn(1, ebpf::SUB64_REG, 1, 2, 0, 0),
@@ -221,7 +249,8 @@ fn test_alu_binary() {
     );
 
     assert_eq!(
-        asm("add r1, 2
+        asm_v1(
+            "add r1, 2
              sub r1, 2
              mul r1, 2
              div r1, 2
@@ -232,7 +261,8 @@ fn test_alu_binary() {
              mod r1, 2
              xor r1, 2
              mov r1, 2
-             arsh r1, 2"),
+             arsh r1, 2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD64_IMM, 1, 0, 0, 2),
             insn(1, ebpf::SUB64_IMM, 1, 0, 0, 2),
@@ -250,7 +280,8 @@ fn test_alu_binary() {
     );
 
     assert_eq!(
-        asm("add64 r1, r2
+        asm_v1(
+            "add64 r1, r2
              sub64 r1, r2
              mul64 r1, r2
              div64 r1, r2
@@ -261,7 +292,8 @@ fn test_alu_binary() {
              mod64 r1, r2
              xor64 r1, r2
              mov64 r1, r2
-             arsh64 r1, r2"),
+             arsh64 r1, r2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD64_REG, 1, 2, 0, 0),
             insn(1, ebpf::SUB64_REG, 1, 2, 0, 0),
@@ -279,7 +311,8 @@ fn test_alu_binary() {
     );
 
     assert_eq!(
-        asm("add64 r1, 2
+        asm_v1(
+            "add64 r1, 2
              sub64 r1, 2
              mul64 r1, 2
              div64 r1, 2
@@ -290,7 +323,8 @@ fn test_alu_binary() {
              mod64 r1, 2
              xor64 r1, 2
              mov64 r1, 2
-             arsh64 r1, 2"),
+             arsh64 r1, 2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD64_IMM, 1, 0, 0, 2),
             insn(1, ebpf::SUB64_IMM, 1, 0, 0, 2),
@@ -308,7 +342,8 @@ fn test_alu_binary() {
     );
 
     assert_eq!(
-        asm("add32 r1, r2
+        asm_v1(
+            "add32 r1, r2
              sub32 r1, r2
              mul32 r1, r2
              div32 r1, r2
@@ -319,7 +354,8 @@ fn test_alu_binary() {
              mod32 r1, r2
              xor32 r1, r2
              mov32 r1, r2
-             arsh32 r1, r2"),
+             arsh32 r1, r2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD32_REG, 1, 2, 0, 0),
             insn(1, ebpf::SUB32_REG, 1, 2, 0, 0),
@@ -337,7 +373,8 @@ fn test_alu_binary() {
     );
 
     assert_eq!(
-        asm("add32 r1, 2
+        asm_v1(
+            "add32 r1, 2
              sub32 r1, 2
              mul32 r1, 2
              div32 r1, 2
@@ -348,7 +385,8 @@ fn test_alu_binary() {
              mod32 r1, 2
              xor32 r1, 2
              mov32 r1, 2
-             arsh32 r1, 2"),
+             arsh32 r1, 2"
+        ),
         Ok(vec![
             insn(0, ebpf::ADD32_IMM, 1, 0, 0, 2),
             insn(1, ebpf::SUB32_IMM, 1, 0, 0, 2),
@@ -370,9 +408,11 @@ fn test_alu_binary() {
 #[test]
 fn test_alu_unary() {
     assert_eq!(
-        asm("neg r1
+        asm_v1(
+            "neg r1
              neg64 r1
-             neg32 r1"),
+             neg32 r1"
+        ),
         Ok(vec![
             insn(0, ebpf::NEG64, 1, 0, 0, 0),
             insn(1, ebpf::NEG64, 1, 0, 0, 0),
@@ -494,12 +534,14 @@ fn test_jump_conditional() {
 #[test]
 fn test_endian() {
     assert_eq!(
-        asm("be16 r1
+        asm_v1(
+            "be16 r1
              be32 r1
              be64 r1
              le16 r1
              le32 r1
-             le64 r1"),
+             le64 r1"
+        ),
         Ok(vec![
             insn(0, ebpf::BE, 1, 0, 0, 16),
             insn(1, ebpf::BE, 1, 0, 0, 32),
@@ -536,6 +578,18 @@ fn test_error_invalid_instruction() {
     assert_eq!(asm("abcd"), Err("Invalid instruction \"abcd\"".to_string()));
 }
 
+#[test]
+fn test_error_unsupported_instruction() {
+    assert_eq!(
+        asm("neg64 r1"),
+        Err("Instruction neg64 is not supported by V2".to_string())
+    );
+    assert_eq!(
+        asm_v1("sdiv64 r1, r2"),
+        Err("Instruction sdiv64 is not supported by V1".to_string())
+    );
+}
+
 #[test]
 fn test_error_unexpected_operands() {
     assert_eq!(
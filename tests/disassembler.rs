@@ -18,11 +18,14 @@ use std::sync::Arc;
 // Using a macro to keep actual line numbers in failure output
 macro_rules! disasm {
     ($src:expr) => {{
+        disasm!($src, Config::default())
+    }};
+    ($src:expr, $config:expr) => {{
         let src = $src;
         let loader = BuiltinProgram::new_loader(
             Config {
                 enable_symbol_and_section_labels: true,
-                ..Config::default()
+                ..$config
             },
             FunctionRegistry::default(),
         );
@@ -34,9 +37,17 @@ macro_rules! disasm {
     }};
 }
 
+fn config_v1() -> Config {
+    Config {
+        enable_sbpf_v2: false,
+        ..Config::default()
+    }
+}
+
 #[test]
 fn test_empty() {
-    disasm!("");
+    let loader = BuiltinProgram::new_loader(Config::default(), FunctionRegistry::default());
+    assert!(assemble::<TestContextObject>("", Arc::new(loader)).is_err());
 }
 
 // Example for InstructionType::NoOperand.
@@ -55,7 +66,7 @@ fn test_add64() {
 // Example for InstructionType::AluUnary.
 #[test]
 fn test_neg64() {
-    disasm!("entrypoint:\n    neg64 r1\n");
+    disasm!("entrypoint:\n    neg64 r1\n", config_v1());
 }
 
 // Example for InstructionType::LoadReg.
@@ -132,8 +143,14 @@ fn test_be32() {
 // Example for InstructionType::LoadImm.
 #[test]
 fn test_lddw() {
-    disasm!("entrypoint:\n    lddw r1, 0x1234abcd5678eeff\n");
-    disasm!("entrypoint:\n    lddw r1, 0xff11ee22dd33cc44\n");
+    disasm!(
+        "entrypoint:\n    lddw r1, 0x1234abcd5678eeff\n",
+        config_v1()
+    );
+    disasm!(
+        "entrypoint:\n    lddw r1, 0xff11ee22dd33cc44\n",
+        config_v1()
+    );
 }
 
 // Example for InstructionType::LoadReg.
@@ -174,7 +191,8 @@ fn test_alu_binary() {
     xor64 r1, r2
     mov64 r1, r2
     arsh64 r1, r2
-"
+",
+        config_v1()
     );
 
     disasm!(
@@ -191,7 +209,8 @@ fn test_alu_binary() {
     xor64 r1, 2
     mov64 r1, 2
     arsh64 r1, 2
-"
+",
+        config_v1()
     );
 
     disasm!(
@@ -208,7 +227,8 @@ fn test_alu_binary() {
     xor32 r1, r2
     mov32 r1, r2
     arsh32 r1, r2
-"
+",
+        config_v1()
     );
 
     disasm!(
@@ -225,7 +245,8 @@ fn test_alu_binary() {
     xor32 r1, 2
     mov32 r1, 2
     arsh32 r1, 2
-"
+",
+        config_v1()
     );
 }
 
@@ -236,7 +257,8 @@ fn test_alu_unary() {
         "entrypoint:
     neg64 r1
     neg32 r1
-"
+",
+        config_v1()
     );
 }
 
@@ -330,7 +352,8 @@ fn test_endian() {
     le16 r1
     le32 r1
     le64 r1
-"
+",
+        config_v1()
     );
 }
 
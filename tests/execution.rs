@@ -18,16 +18,29 @@ use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use solana_rbpf::{
     assembler::assemble,
     declare_builtin_function, ebpf,
-    elf::Executable,
+    elf::{Executable, UnresolvedSymbolPolicy},
+    elf_parser::{consts::SHN_UNDEF, types::Elf64Sym, Elf64},
     error::{EbpfError, ProgramResult},
-    memory_region::{AccessType, MemoryMapping, MemoryRegion},
-    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion},
-    static_analysis::Analysis,
+    memory_region::{
+        AccessType, DirtyRun, MemoryMapping, MemoryRegion, RegionAccessStatistics, WatchpointKind,
+    },
+    program::{BuiltinFunction, BuiltinProgram, FunctionRegistry, SBPFVersion, SyscallCost},
+    static_analysis::{Analysis, ConstantTimeViolation, OptimizationHint, StackUsage},
     syscalls,
     verifier::RequisiteVerifier,
-    vm::{Config, ContextObject, TestContextObject},
+    vm::{
+        get_runtime_environment_key, Config, ContextObject, DynamicAnalysis, EbpfVm,
+        SpeculationHardening, TestContextObject, TraceSampling,
+    },
 };
-use std::{fs::File, io::Read, sync::Arc};
+#[cfg(feature = "unstable")]
+use solana_rbpf::{
+    declare_syscall,
+    unstable::{
+        execute_lockstep, BatchExecutor, BatchJob, LockstepMismatch, WholeProgramTieredExecutor,
+    },
+};
+use std::{fs::File, io::Read, mem, sync::Arc};
 use test_utils::{
     assert_error, create_vm, PROG_TCP_PORT_80, TCP_SACK_ASM, TCP_SACK_MATCH, TCP_SACK_NOMATCH,
 };
@@ -78,6 +91,8 @@ macro_rules! test_interpreter_and_jit {
         {
             #[allow(unused_mut)]
             let compilation_result = $executable.jit_compile();
+            #[cfg(feature = "unstable")]
+            let context_object_before_execution = context_object.clone();
             let mut mem = $mem;
             let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
             create_vm!(
@@ -125,6 +140,22 @@ macro_rules! test_interpreter_and_jit {
                         interpreter_final_pc, vm.registers[11],
                         "Interpreter and JIT instruction final PC diverged",
                     );
+                    #[cfg(feature = "unstable")]
+                    {
+                        let job = BatchJob {
+                            input: $mem.to_vec(),
+                            heap_size: 0,
+                            context_object: context_object_before_execution,
+                        };
+                        let trace_log: fn(&TestContextObject) -> &[[u64; 12]] =
+                            |context_object| context_object.trace_log.as_slice();
+                        if let Err(divergence) = execute_lockstep(&$executable, job, trace_log) {
+                            panic!(
+                                "Interpreter and JIT diverged in lockstep in {:?} at pc {}",
+                                divergence.mismatch, divergence.pc,
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -921,6 +952,37 @@ fn test_err_ldxdw_nomem() {
     );
 }
 
+#[test]
+fn test_mem_access_region_end() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r2, 0x11
+        stxb [r1+15], r2
+        ldxdw r3, [r1+8]
+        stxdw [r1+8], r3
+        ldxb r0, [r1+15]
+        exit",
+        [0; 16],
+        (),
+        TestContextObject::new(6),
+        ProgramResult::Ok(0x11),
+    );
+    test_interpreter_and_jit_asm!(
+        "
+        stxw [r1+14], r2
+        exit",
+        [0; 16],
+        (),
+        TestContextObject::new(1),
+        ProgramResult::Err(EbpfError::AccessViolation(
+            AccessType::Store,
+            0x40000000e,
+            4,
+            "input"
+        )),
+    );
+}
+
 #[test]
 fn test_ldxb_all() {
     test_interpreter_and_jit_asm!(
@@ -2161,6 +2223,147 @@ fn test_stack_call_depth_tracking() {
     }
 }
 
+#[test]
+fn test_zero_stack_frames() {
+    for zero_stack_frames in [false, true] {
+        let config = Config {
+            enable_sbpf_v2: false,
+            zero_stack_frames,
+            ..Config::default()
+        };
+        // Sibling calls share a frame, so the second one sees what the first one left behind
+        test_interpreter_and_jit_asm!(
+            "
+            call function_foo
+            call function_bar
+            exit
+            function_foo:
+            stdw [r10-8], 0x42
+            exit
+            function_bar:
+            ldxdw r0, [r10-8]
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(7),
+            ProgramResult::Ok(if zero_stack_frames { 0 } else { 0x42 }),
+        );
+    }
+}
+
+#[test]
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+fn test_exact_instruction_meter() {
+    let source = "
+        stb [r1], 1
+        stb [r1+1], 2
+        stb [r1+2], 3
+        stb [r1+3], 4
+        stb [r1+4], 5
+        exit";
+    for exact_instruction_meter in [false, true] {
+        let config = Config {
+            exact_instruction_meter,
+            ..Config::default()
+        };
+        let mut executable = assemble::<TestContextObject>(
+            source,
+            Arc::new(BuiltinProgram::new_loader(
+                config,
+                FunctionRegistry::default(),
+            )),
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        executable.jit_compile().unwrap();
+        let memories = [true, false].map(|interpreted| {
+            let mut mem = [0u8; 8];
+            let mut context_object = TestContextObject::new(2);
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            let (instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_error!(result, "ExceededMaxInstructions");
+            assert_eq!(instruction_count, 2);
+            mem
+        });
+        assert_eq!(memories[0], [1, 2, 0, 0, 0, 0, 0, 0]);
+        if exact_instruction_meter {
+            assert_eq!(memories[1], memories[0]);
+        } else {
+            // Without exact metering the JIT only notices the exhausted budget at the exit
+            assert_eq!(memories[1], [1, 2, 3, 4, 5, 0, 0, 0]);
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_minimize() {
+    let loader = Arc::new(BuiltinProgram::new_mock());
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 5
+        call function_foo
+        ja +1
+        mov64 r0, 1
+        exit
+        function_foo:
+        mov64 r2, 7
+        mov64 r0, 42
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let returns_42 = |prog: &[u8], function_registry| {
+        let Ok(executable) = Executable::<TestContextObject>::from_text_bytes(
+            prog,
+            loader.clone(),
+            SBPFVersion::V2,
+            function_registry,
+        ) else {
+            return false;
+        };
+        if executable.verify::<RequisiteVerifier>().is_err() {
+            return false;
+        }
+        let mut context_object = TestContextObject::new(100);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(&executable, true);
+        matches!(result, ProgramResult::Ok(42))
+    };
+    let (minimized, _function_registry) = solana_rbpf::fuzz::minimize(
+        executable.get_text_bytes().1,
+        executable.get_function_registry(),
+        executable.get_sbpf_version(),
+        returns_42,
+    );
+    let expected = assemble::<TestContextObject>(
+        "
+        mov64 r0, 42
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    assert_eq!(minimized, expected.get_text_bytes().1);
+}
+
 #[test]
 fn test_err_mem_access_out_of_bound() {
     let mem = [0; 512];
@@ -2274,6 +2477,49 @@ fn test_callx() {
     );
 }
 
+#[test]
+fn test_speculation_hardening() {
+    for speculation_hardening in [
+        SpeculationHardening::LoadFence,
+        SpeculationHardening::Retpoline,
+    ] {
+        for enable_lazy_jit_compilation in [false, true] {
+            let config = Config {
+                speculation_hardening,
+                enable_lazy_jit_compilation,
+                ..Config::default()
+            };
+            test_interpreter_and_jit_asm!(
+                "
+                mov64 r8, 0x1
+                lsh64 r8, 0x20
+                or64 r8, 0x30
+                callx r8
+                call function_bar
+                exit
+                function_foo:
+                mov64 r1, 0x1
+                mov64 r2, 0x2
+                mov64 r3, 0x3
+                mov64 r4, 0x4
+                mov64 r5, 0x5
+                syscall gather_bytes
+                exit
+                function_bar:
+                add64 r0, 0x1
+                exit",
+                config,
+                [],
+                (
+                    "gather_bytes" => syscalls::SyscallGatherBytes::vm,
+                ),
+                TestContextObject::new(15),
+                ProgramResult::Ok(0x0102030406),
+            );
+        }
+    }
+}
+
 #[test]
 fn test_err_callx_unregistered() {
     test_interpreter_and_jit_asm!(
@@ -2562,82 +2808,2710 @@ fn test_nested_vm_syscall() {
     assert_error!(result, "CallDepthExceeded");
 }
 
-// Instruction Meter Limit
+declare_builtin_function!(
+    /// For test_syscall_panic()
+    SyscallPanic,
+    fn rust(
+        _context_object: &mut TestContextObject,
+        should_panic: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &mut MemoryMapping,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        if should_panic != 0 {
+            panic!("syscall panicked on purpose");
+        }
+        Ok(0)
+    }
+);
 
 #[test]
-fn test_tight_infinite_loop_conditional() {
-    test_interpreter_and_jit_asm!(
+fn test_syscall_panic() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"panic_syscall", SyscallPanic::vm)
+        .unwrap();
+    let loader = BuiltinProgram::new_loader(Config::default(), function_registry);
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
         "
-        jsge r0, r0, -1
-        exit",
-        [],
-        (),
-        TestContextObject::new(4),
-        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
-    );
+        ldxb r1, [r1]
+        syscall panic_syscall
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        let mut mem = [1u8];
+        let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+        let mut context_object = TestContextObject::new(3);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![mem_region],
+            None
+        );
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vm.execute_program(&executable, interpreted)
+        }))
+        .unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<&str>(),
+            Some(&"syscall panicked on purpose")
+        );
+        assert!(vm.builtin_panic.is_none());
+
+        // The VM is still usable after the panic was resumed
+        assert!(vm.memory_mapping.store(0u8, ebpf::MM_INPUT_START).is_ok());
+        vm.context_object_pointer.remaining = 3;
+        let (instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert_eq!(instruction_count, 3);
+        assert!(matches!(result, ProgramResult::Ok(0)));
+    }
 }
 
+#[cfg(feature = "unstable")]
+declare_syscall!(
+    /// For test_typed_syscall()
+    SyscallSumLengths,
+    fn rust(
+        _context_object: &mut TestContextObject,
+        _memory_mapping: &mut MemoryMapping,
+        message: &str,
+        values: &[u32],
+        sum: &mut u64,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        *sum = message.len() as u64 + values.iter().map(|value| *value as u64).sum::<u64>();
+        Ok(values.len() as u64)
+    }
+);
+
+#[cfg(feature = "unstable")]
 #[test]
-fn test_tight_infinite_loop_unconditional() {
-    test_interpreter_and_jit_asm!(
-        "
-        ja -1
-        exit",
-        [],
-        (),
-        TestContextObject::new(4),
-        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
-    );
+fn test_typed_syscall() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"sum_lengths", SyscallSumLengths::vm)
+        .unwrap();
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        function_registry,
+    ));
+    for (message, sum_offset, expected_result) in [
+        (*b"hello", 8, "Ok(3)"),
+        (*b"hel\xffo", 8, "SyscallError(Utf8Error"),
+        (
+            *b"hello",
+            12,
+            "SyscallError(UnalignedGuestAccess(17179869196, 8))",
+        ),
+        (
+            *b"hello",
+            16,
+            "SyscallError(AliasedGuestAccess(17179869200, 8))",
+        ),
+    ] {
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            &format!(
+                "
+                mov r2, 5
+                mov r3, r1
+                add r3, 16
+                mov r4, 3
+                mov r5, r1
+                add r5, {sum_offset}
+                syscall sum_lengths
+                exit"
+            ),
+            loader.clone(),
+        )
+        .unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut mem = [0u8; 28];
+            mem[..5].copy_from_slice(&message);
+            for (index, value) in [1u32, 20, 300].iter().enumerate() {
+                LittleEndian::write_u32(&mut mem[16 + index * 4..], *value);
+            }
+            let mut context_object = TestContextObject::new(8);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_error!(result, "{}", expected_result);
+            drop(vm);
+            if sum_offset == 8 && message.is_ascii() {
+                assert_eq!(LittleEndian::read_u64(&mem[8..]), 326);
+            }
+        }
+    }
 }
 
+#[cfg(feature = "unstable")]
+declare_syscall!(
+    /// For test_typed_syscall_overlapping_slices()
+    SyscallCopy,
+    fn rust(
+        _context_object: &mut TestContextObject,
+        _memory_mapping: &mut MemoryMapping,
+        dst: &mut [u8],
+        src: &[u8],
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let len = dst.len().min(src.len());
+        dst[..len].copy_from_slice(&src[..len]);
+        Ok(len as u64)
+    }
+);
+
+#[cfg(feature = "unstable")]
 #[test]
-fn test_tight_infinite_recursion() {
-    test_interpreter_and_jit_asm!(
-        "
-        entrypoint:
-        mov64 r3, 0x41414141
-        call entrypoint
-        exit",
-        [],
-        (),
-        TestContextObject::new(4),
-        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
-    );
+fn test_typed_syscall_overlapping_slices() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"copy", SyscallCopy::vm)
+        .unwrap();
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        function_registry,
+    ));
+    for (dst_offset, src_offset, expected_result) in [
+        (0, 8, "Ok(8)"),
+        (0, 4, "SyscallError(AliasedGuestAccess(17179869188, 8))"),
+        (4, 0, "SyscallError(AliasedGuestAccess(17179869184, 8))"),
+        (0, 0, "SyscallError(AliasedGuestAccess(17179869184, 8))"),
+    ] {
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            &format!(
+                "
+                mov r2, 8
+                mov r3, r1
+                add r3, {src_offset}
+                mov r4, 8
+                add r1, {dst_offset}
+                syscall copy
+                exit"
+            ),
+            loader.clone(),
+        )
+        .unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut mem = [0u8; 16];
+            mem[8..].copy_from_slice(b"overlaps");
+            let mut context_object = TestContextObject::new(7);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_error!(result, "{}", expected_result);
+            drop(vm);
+            // Overlapping slices fail before the syscall writes anything
+            let expected_mem = if src_offset == 8 {
+                *b"overlapsoverlaps"
+            } else {
+                *b"\0\0\0\0\0\0\0\0overlaps"
+            };
+            assert_eq!(mem, expected_mem);
+        }
+    }
 }
 
 #[test]
-fn test_tight_infinite_recursion_callx() {
-    test_interpreter_and_jit_asm!(
+fn test_call_frames() {
+    static BACKTRACE: std::sync::Mutex<Vec<(u64, u64)>> = std::sync::Mutex::new(Vec::new());
+    fn capture_backtrace(
+        vm: *mut EbpfVm<TestContextObject>,
+        _arg_a: u64,
+        _arg_b: u64,
+        _arg_c: u64,
+        _arg_d: u64,
+        _arg_e: u64,
+    ) {
+        let vm = unsafe {
+            &mut *(vm
+                .cast::<u64>()
+                .offset(-(get_runtime_environment_key() as isize))
+                .cast::<EbpfVm<TestContextObject>>())
+        };
+        *BACKTRACE.lock().unwrap() = vm
+            .get_call_frames()
+            .iter()
+            .map(|frame| (frame.target_pc, frame.frame_pointer))
+            .collect();
+        vm.program_result = ProgramResult::Ok(vm.get_call_frames().len() as u64);
+    }
+
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"capture_backtrace", capture_backtrace)
+        .unwrap();
+    let loader = BuiltinProgram::new_loader(Config::default(), function_registry);
+    let executable = assemble::<TestContextObject>(
         "
-        mov64 r8, 0x1
-        lsh64 r8, 0x20
-        or64 r8, 0x28
         call function_foo
         exit
         function_foo:
-        mov64 r3, 0x41414141
-        callx r8
-        exit",
-        [],
-        (),
-        TestContextObject::new(8),
-        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+        call function_bar
+        exit
+        function_bar:
+        syscall capture_backtrace
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(8);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    assert!(vm.get_call_frames().is_empty());
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert_eq!(result.unwrap(), 2);
+    let frame_pointer = ebpf::MM_STACK_START + executable.get_config().stack_size() as u64;
+    assert_eq!(
+        *BACKTRACE.lock().unwrap(),
+        vec![(1, frame_pointer), (3, frame_pointer)]
     );
+    assert!(vm.get_call_frames().is_empty());
 }
 
 #[test]
-fn test_instruction_count_syscall() {
-    test_interpreter_and_jit_asm!(
+fn test_rebind_syscall() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
+        .unwrap();
+    let loader = BuiltinProgram::new_loader(Config::default(), function_registry);
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
         "
-        mov64 r2, 0x5
-        syscall bpf_syscall_string
-        mov64 r0, 0x0
-        exit",
-        [72, 101, 108, 108, 111],
-        (
-            "bpf_syscall_string" => syscalls::SyscallString::vm,
-        ),
-        TestContextObject::new(4),
+        mov r1, 0
+        mov r2, 2
+        mov r3, 3
+        mov r4, 4
+        mov r5, 5
+        syscall gather_bytes
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    let key = ebpf::hash_symbol_name(b"gather_bytes");
+    assert_error!(
+        executable.rebind_syscall(key ^ 1, SyscallPanic::vm),
+        "SyscallNotRegistered({})",
+        key ^ 1
+    );
+    for expected_result in [0x0002030405, 0] {
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject::new(7);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(result.unwrap(), expected_result);
+        }
+        // Swapped without compiling again
+        executable.rebind_syscall(key, SyscallPanic::vm).unwrap();
+    }
+}
+
+#[test]
+#[cfg(all(
+    feature = "unstable",
+    feature = "jit",
+    not(target_os = "windows"),
+    target_arch = "x86_64"
+))]
+fn test_jit_cache() {
+    use solana_rbpf::jit_cache::{JitCache, JitCacheStatistics};
+
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
+        .unwrap();
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        function_registry,
+    ));
+    let source = "
+        mov r1, 0
+        mov r2, 2
+        mov r3, 3
+        mov r4, 4
+        mov r5, 5
+        syscall gather_bytes
+        exit";
+    let cache = JitCache::new(1);
+    let mut executables = (0..2)
+        .map(|_| {
+            let mut executable = assemble::<TestContextObject>(source, loader.clone()).unwrap();
+            executable.verify::<RequisiteVerifier>().unwrap();
+            executable.jit_compile_cached(&cache).unwrap();
+            executable
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        cache.statistics(),
+        JitCacheStatistics {
+            hits: 1,
+            misses: 1,
+            evictions: 0,
+            len: 1,
+        }
+    );
+    assert!(std::ptr::eq(
+        executables[0].get_compiled_program().unwrap(),
+        executables[1].get_compiled_program().unwrap(),
+    ));
+    for executable in executables.iter() {
+        let mut context_object = TestContextObject::new(7);
+        create_vm!(
+            vm,
+            executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(executable, false);
+        assert_eq!(result.unwrap(), 0x0002030405);
+    }
+
+    // Rebinding a syscall of the shared program affects all executables using it, in both engines
+    let compiled_program = executables[0].get_compiled_program().unwrap();
+    assert_error!(
+        unsafe { compiled_program.rebind_syscall(b"not_registered", SyscallPanic::vm) },
+        "SyscallNotRegistered({})",
+        ebpf::hash_symbol_name(b"not_registered")
+    );
+    unsafe { compiled_program.rebind_syscall(b"gather_bytes", SyscallPanic::vm) }.unwrap();
+    for executable in executables.iter() {
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject::new(7);
+            create_vm!(
+                vm,
+                executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(executable, interpreted);
+            assert_eq!(result.unwrap(), 0);
+        }
+    }
+
+    // The rebound program is not handed out to new executables anymore
+    let mut executable = assemble::<TestContextObject>(source, loader.clone()).unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    executable.jit_compile_cached(&cache).unwrap();
+    assert_eq!(
+        cache.statistics(),
+        JitCacheStatistics {
+            hits: 1,
+            misses: 2,
+            evictions: 1,
+            len: 1,
+        }
+    );
+    assert!(!std::ptr::eq(
+        executable.get_compiled_program().unwrap(),
+        executables[0].get_compiled_program().unwrap(),
+    ));
+
+    // A different program evicts the least recently used one
+    let mut executable =
+        assemble::<TestContextObject>(&source.replace("mov r2, 2", "mov r2, 1"), loader).unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    executable.jit_compile_cached(&cache).unwrap();
+    assert_eq!(
+        cache.statistics(),
+        JitCacheStatistics {
+            hits: 1,
+            misses: 3,
+            evictions: 2,
+            len: 1,
+        }
+    );
+
+    // Rebinding a syscall releases the shared program instead of patching it
+    let key = ebpf::hash_symbol_name(b"gather_bytes");
+    executables[0]
+        .rebind_syscall(key, SyscallPanic::vm)
+        .unwrap();
+    assert!(executables[0].get_compiled_program().is_none());
+    assert!(executables[1].get_compiled_program().is_some());
+
+    // The same text is not shared between different ContextObjects
+    struct OtherContextObject;
+    impl ContextObject for OtherContextObject {
+        fn trace(&mut self, _state: [u64; 12]) {}
+        fn consume(&mut self, _amount: u64) {}
+        fn get_remaining(&self) -> u64 {
+            0
+        }
+    }
+    let cache = JitCache::new(2);
+    let mut executable =
+        assemble::<TestContextObject>("exit", Arc::new(BuiltinProgram::new_mock())).unwrap();
+    executable.jit_compile_cached(&cache).unwrap();
+    let mut executable =
+        assemble::<OtherContextObject>("exit", Arc::new(BuiltinProgram::new_mock())).unwrap();
+    executable.jit_compile_cached(&cache).unwrap();
+    assert_eq!(
+        cache.statistics(),
+        JitCacheStatistics {
+            hits: 0,
+            misses: 2,
+            evictions: 0,
+            len: 2,
+        }
+    );
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_batch_executor() {
+    let mut executable = assemble::<TestContextObject>(
+        "
+        ldxdw r2, [r1]
+        add64 r2, 1
+        stxdw [r1], r2
+        mov r0, r2
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let jobs = || {
+        (0..32u64)
+            .map(|index| BatchJob {
+                input: index.to_le_bytes().to_vec(),
+                heap_size: 0,
+                // Every fourth job runs out of budget
+                context_object: TestContextObject::new(if index % 4 == 3 { 2 } else { 5 }),
+            })
+            .collect::<Vec<_>>()
+    };
+    let check = |summaries: Vec<solana_rbpf::vm::ExecutionSummary<TestContextObject>>| {
+        assert_eq!(summaries.len(), 32);
+        for (index, summary) in (0..32u64).zip(summaries) {
+            if index % 4 == 3 {
+                assert_error!(summary.result, "ExceededMaxInstructions");
+            } else {
+                assert_eq!(summary.result.unwrap(), index + 1);
+                assert_eq!(summary.instruction_count, 5);
+                assert_eq!(summary.input, (index + 1).to_le_bytes());
+                assert_eq!(summary.context_object.remaining, 0);
+            }
+        }
+    };
+    check(BatchExecutor::new(4).execute(&executable, jobs()));
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    {
+        executable.jit_compile().unwrap();
+        check(BatchExecutor::default().execute(&executable, jobs()));
+    }
+}
+
+declare_builtin_function!(
+    /// For test_execute_lockstep()
+    SyscallCounter,
+    fn rust(
+        _context_object: &mut TestContextObject,
+        count: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &mut MemoryMapping,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        if count == 0 {
+            return Ok(0);
+        }
+        Ok(COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+);
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_execute_lockstep() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"counter_syscall", SyscallCounter::vm)
+        .unwrap();
+    let loader = BuiltinProgram::new_loader(config, function_registry);
+    let mut executable = assemble::<TestContextObject>(
+        "
+        ldxb r1, [r1]
+        syscall counter_syscall
+        mov64 r2, r0
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let job = |count: u8| BatchJob {
+        input: vec![count],
+        heap_size: 0,
+        context_object: TestContextObject::new(4),
+    };
+    let trace_log: fn(&TestContextObject) -> &[[u64; 12]] =
+        |context_object| context_object.trace_log.as_slice();
+    // Without a compiled program the JIT can not even start
+    let divergence = execute_lockstep(&executable, job(0), trace_log).unwrap_err();
+    assert_eq!(divergence.mismatch, LockstepMismatch::TraceLog);
+    assert_eq!(divergence.pc, 0);
+    assert_error!(divergence.jit.result, "JitNotCompiled");
+    executable.jit_compile().unwrap();
+    let summary = execute_lockstep(&executable, job(0), trace_log).unwrap();
+    assert_eq!(summary.result.unwrap(), 0);
+    assert_eq!(summary.instruction_count, 4);
+    assert_eq!(summary.context_object.trace_log.len(), 4);
+    // The syscall returns a different value on every call, so the first instruction which sees
+    // it diverges
+    let divergence = execute_lockstep(&executable, job(1), trace_log).unwrap_err();
+    assert_eq!(divergence.mismatch, LockstepMismatch::TraceLog);
+    assert_eq!(divergence.pc, 2);
+    assert_eq!(
+        divergence.jit.result.unwrap(),
+        divergence.interpreter.result.unwrap() + 1
+    );
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_batch_region_statistics() {
+    for enable_region_statistics in [false, true] {
+        let mut executable = assemble::<TestContextObject>(
+            "
+            ldxdw r2, [r1]
+            ldxb r3, [r1+8]
+            stxdw [r10-8], r2
+            stxw [r1+4], r3
+            ldxdw r0, [r10-8]
+            exit",
+            Arc::new(BuiltinProgram::new_loader(
+                Config {
+                    enable_region_statistics,
+                    ..Config::default()
+                },
+                FunctionRegistry::default(),
+            )),
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        let jobs = vec![BatchJob {
+            input: vec![0; 16],
+            heap_size: 0,
+            context_object: TestContextObject::new(6),
+        }];
+        let expected_statistics = enable_region_statistics.then(|| {
+            std::collections::BTreeMap::from([
+                (
+                    ebpf::MM_STACK_START,
+                    RegionAccessStatistics {
+                        loads: 1,
+                        stores: 1,
+                        bytes: 16,
+                    },
+                ),
+                (
+                    ebpf::MM_INPUT_START,
+                    RegionAccessStatistics {
+                        loads: 2,
+                        stores: 1,
+                        bytes: 13,
+                    },
+                ),
+            ])
+        });
+        let summary = BatchExecutor::new(1)
+            .execute(&executable, jobs.clone())
+            .pop()
+            .unwrap();
+        assert_eq!(summary.result.unwrap(), 0);
+        assert_eq!(summary.region_statistics, expected_statistics);
+
+        // The JIT counts the same accesses
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        {
+            executable.jit_compile().unwrap();
+            let summary = BatchExecutor::new(1)
+                .execute(&executable, jobs)
+                .pop()
+                .unwrap();
+            assert_eq!(summary.result.unwrap(), 0);
+            assert_eq!(summary.region_statistics, expected_statistics);
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_single_step() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 1
+        add64 r1, 2
+        mov64 r0, r1
+        ldxb r2, [r0]
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(10);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let mut interpreter = vm.interpreter(&executable);
+    let mut executed_pcs = Vec::new();
+    let mut r1_values = Vec::new();
+    loop {
+        let step = interpreter.single_step();
+        executed_pcs.push(step.pc);
+        if step.done {
+            break;
+        }
+        r1_values.push(interpreter.reg[1]);
+    }
+    // The load from the unmapped address 3 faults
+    assert_eq!(executed_pcs, [0, 1, 2, 3]);
+    assert_eq!(r1_values, [1, 3, 3]);
+    let (instruction_count, result) = interpreter.finish();
+    assert_eq!(instruction_count, 4);
+    assert_error!(result, "AccessViolation");
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_set_register() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 1
+        add64 r0, r1
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(10);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let mut interpreter = vm.interpreter(&executable);
+    assert!(!interpreter.single_step().done);
+    assert_eq!(interpreter.get_registers()[0], 1);
+    interpreter.set_register(1, 41).unwrap();
+    assert!(!interpreter.single_step().done);
+    assert_eq!(interpreter.get_registers()[0], 42);
+    // Run the addition again
+    assert_error!(interpreter.set_register(11, 3), "InstructionOutOfBounds(3)");
+    assert_eq!(interpreter.get_registers()[11], 2);
+    interpreter.set_register(11, 1).unwrap();
+    while !interpreter.single_step().done {}
+    let (instruction_count, result) = interpreter.finish();
+    assert_eq!(instruction_count, 4);
+    assert_eq!(result.unwrap(), 83);
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_instruction_hooks() {
+    // Both hooks record the pc in the otherwise unused r11 slot of a trace log entry
+    fn pre_hook(context_object: &mut TestContextObject, pc: u64, registers: &[u64; 12]) {
+        let mut entry = *registers;
+        entry[11] = pc;
+        context_object.trace_log.push(entry);
+    }
+    fn post_hook(context_object: &mut TestContextObject, pc: u64, registers: &[u64; 12]) {
+        let mut entry = *registers;
+        entry[11] = pc | 0x8000_0000;
+        context_object.trace_log.push(entry);
+    }
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 1
+        ja +1
+        mov64 r0, 2
+        add64 r0, 3
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(10);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    vm.set_pre_instruction_hook(pre_hook);
+    vm.set_post_instruction_hook(post_hook);
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert_eq!(result.unwrap(), 4);
+    let log = context_object
+        .trace_log
+        .iter()
+        .map(|entry| (entry[11], entry[0]))
+        .collect::<Vec<_>>();
+    // The exit is not followed by a post hook call
+    assert_eq!(
+        log,
+        [
+            (0, 0),
+            (0x8000_0000, 1),
+            (1, 1),
+            (0x8000_0001, 1),
+            (3, 1),
+            (0x8000_0003, 4),
+            (4, 4),
+        ]
+    );
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_snapshot_and_restore() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 0
+        add64 r1, 1
+        stxdw [r10-8], r1
+        jlt r1, 5, -3
+        ldxdw r0, [r10-8]
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(100);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let mut interpreter = vm.interpreter(&executable);
+    for _ in 0..4 {
+        interpreter.single_step();
+    }
+    let snapshot = interpreter.snapshot();
+    assert_eq!(snapshot.get_registers()[1], 1);
+    assert_eq!(snapshot.get_registers()[11], 1);
+    let mut executed_pcs = Vec::new();
+    while !interpreter.single_step().done {
+        executed_pcs.push(interpreter.reg[11]);
+    }
+    // Stepping again from the snapshot replays the same instructions
+    interpreter.restore(&snapshot).unwrap();
+    assert_eq!(interpreter.reg[1], 1);
+    let mut replayed_pcs = Vec::new();
+    while !interpreter.single_step().done {
+        replayed_pcs.push(interpreter.reg[11]);
+    }
+    assert_eq!(executed_pcs, replayed_pcs);
+    let (instruction_count, result) = interpreter.finish();
+    assert_eq!(result.unwrap(), 5);
+    assert_eq!(instruction_count, 18);
+
+    // A VM without a stack region can not take over the snapshot
+    let mut context_object = TestContextObject::new(100);
+    let mut vm = EbpfVm::new(
+        &executable,
+        &mut context_object,
+        MemoryMapping::new(
+            Vec::new(),
+            executable.get_config(),
+            executable.get_sbpf_version(),
+        )
+        .unwrap(),
+        0,
+    )
+    .unwrap();
+    assert_error!(
+        vm.restore(&snapshot),
+        "SnapshotMismatch({})",
+        ebpf::MM_STACK_START
+    );
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_record_and_step_back() {
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r0, [r10-8]
+        mov64 r1, 0
+        add64 r1, 1
+        stxdw [r10-8], r1
+        jlt r1, 5, -3
+        call function_foo
+        add64 r0, 1
+        exit
+        function_foo:
+        stdw [r10-8], 7
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut context_object = TestContextObject::new(100);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let mut interpreter = vm.interpreter(&executable);
+    assert!(!interpreter.step_back());
+    interpreter.start_recording(64);
+    let mut forward = vec![interpreter.reg];
+    while !interpreter.single_step().done {
+        forward.push(interpreter.reg);
+    }
+    assert_eq!(interpreter.reg[0], 1);
+    let recorder = interpreter.get_recorder().unwrap();
+    assert_eq!(recorder.len(), 22);
+    assert_eq!(
+        recorder
+            .records()
+            .filter(|record| !record.memory_writes.is_empty())
+            .count(),
+        6
+    );
+
+    // Stepping back visits the same states in reverse
+    while interpreter.step_back() {
+        assert_eq!(Some(interpreter.reg), forward.pop());
+    }
+    assert!(forward.is_empty());
+    assert_eq!(interpreter.reg[11], 0);
+
+    // The stores were undone too, so running again loads the same value
+    interpreter.stop_recording();
+    while !interpreter.single_step().done {}
+    let (_instruction_count, result) = interpreter.finish();
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[test]
+fn test_suspend_and_resume() {
+    let source = "
+        mov64 r1, 0
+        add64 r1, 1
+        stxdw [r10-8], r1
+        jlt r1, 5, -3
+        ldxdw r0, [r10-8]
+        exit";
+    let executable =
+        assemble::<TestContextObject>(source, Arc::new(BuiltinProgram::new_mock())).unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    // Loading the same program again yields an executable with the same hash
+    let same_executable =
+        assemble::<TestContextObject>(source, Arc::new(BuiltinProgram::new_mock())).unwrap();
+    let other_executable = assemble::<TestContextObject>(
+        &source.replace("jlt r1, 5", "jlt r1, 6"),
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    let mut context_object = TestContextObject::new(5);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    assert_error!(vm.resume_program(&executable).1, "NotSuspended");
+    vm.set_suspend_on_exhaustion(true);
+    let (mut instruction_count, mut result) = vm.execute_program(&executable, true);
+    let mut slices = 1;
+    while vm.is_suspended() {
+        assert_error!(result, "ExecutionSuspended");
+        assert_eq!(instruction_count, 5);
+        vm.context_object_pointer.remaining = 5;
+        // Only the program the execution was suspended in can continue it
+        assert_error!(
+            vm.resume_program(&other_executable).1,
+            "ResumedWithOtherExecutable"
+        );
+        assert!(vm.is_suspended());
+        let (slice_instruction_count, slice_result) = if slices % 2 == 0 {
+            vm.resume_program(&same_executable)
+        } else {
+            vm.resume_program(&executable)
+        };
+        instruction_count = slice_instruction_count;
+        result = slice_result;
+        slices += 1;
+    }
+    // Same 18 instructions as an execution with enough budget
+    assert_eq!(slices, 4);
+    assert_eq!(instruction_count, 3);
+    assert_eq!(result.unwrap(), 5);
+
+    // Without the mode the exhausted meter is fatal
+    vm.set_suspend_on_exhaustion(false);
+    vm.context_object_pointer.remaining = 5;
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert_error!(result, "ExceededMaxInstructions");
+    assert!(!vm.is_suspended());
+}
+
+#[test]
+fn test_interpreter_without_accounting() {
+    let config = Config {
+        enable_instruction_meter: false,
+        enable_instruction_tracing: false,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 0
+        add64 r1, 1
+        jlt r1, 1000, -2
+        call function_foo
+        exit
+        function_foo:
+        mov64 r0, r1
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    // The budget is ignored, so it does not run out
+    let mut context_object = TestContextObject::new(1);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    let (instruction_count, result) = vm.execute_program(&executable, true);
+    assert_eq!(result.unwrap(), 1000);
+    assert_eq!(instruction_count, 0);
+    assert_eq!(vm.context_object_pointer.remaining, 1);
+    assert!(vm.context_object_pointer.trace_log.is_empty());
+}
+
+#[test]
+fn test_interpreter_dispatch_agrees_without_accounting() {
+    for (source, mem, expected_result) in [
+        (TCP_SACK_ASM, TCP_SACK_MATCH.to_vec(), "Ok(1)"),
+        (TCP_SACK_ASM, TCP_SACK_NOMATCH.to_vec(), "Ok(0)"),
+        (
+            "
+            mov64 r0, 0
+            ldxdw r0, [r1+8]
+            exit",
+            vec![0; 8],
+            "AccessViolation(Load, 17179869192, 8, \"input\")",
+        ),
+        (
+            "
+            mov32 r0, 1
+            mov32 r1, 0
+            udiv32 r0, r1
+            exit",
+            Vec::new(),
+            "DivideByZero",
+        ),
+    ] {
+        let mut results = Vec::new();
+        for enable_instruction_meter in [true, false] {
+            let config = Config {
+                enable_instruction_meter,
+                ..Config::default()
+            };
+            let executable = assemble::<TestContextObject>(
+                source,
+                Arc::new(BuiltinProgram::new_loader(
+                    config,
+                    FunctionRegistry::default(),
+                )),
+            )
+            .unwrap();
+            executable.verify::<RequisiteVerifier>().unwrap();
+            let mut mem = mem.clone();
+            let mut context_object = TestContextObject::new(INSTRUCTION_METER_BUDGET);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, true);
+            results.push(format!("{result:?}"));
+        }
+        assert!(results[0].contains(expected_result), "{}", results[0]);
+        assert_eq!(results[0], results[1]);
+    }
+}
+
+#[test]
+fn test_chunked_input_region() {
+    for (source, expected_result) in [
+        (
+            "
+            ldxw r0, [r1+0x8]
+            stb [r1+0xB], 0x33
+            ldxb r2, [r1+0xB]
+            add r0, r2
+            exit",
+            "Ok(572662357)",
+        ),
+        (
+            "
+            ldxb r0, [r1+0x4]
+            exit",
+            "AccessViolation(Load, 17179869188, 1",
+        ),
+        (
+            "
+            stw [r1+0x2], 0
+            exit",
+            "AccessViolation(Store, 17179869186, 4",
+        ),
+    ] {
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            #[allow(unused_mut)]
+            let mut executable =
+                assemble::<TestContextObject>(source, Arc::new(BuiltinProgram::new_mock()))
+                    .unwrap();
+            #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+            if !interpreted {
+                executable.jit_compile().unwrap();
+            }
+            // Two chunks of 4 bytes, each followed by a gap of 4 bytes
+            let mut mem = [0x11, 0x11, 0x11, 0x11, 0x22, 0x22, 0x22, 0x22];
+            let mut context_object = TestContextObject::new(5);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable_chunked(
+                    &mut mem,
+                    ebpf::MM_INPUT_START,
+                    4,
+                    4
+                )],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert!(
+                format!("{result:?}").contains(expected_result),
+                "{:?}",
+                result
+            );
+        }
+    }
+}
+
+#[test]
+fn test_writable_ranges() {
+    for (source, expected_result) in [
+        (
+            "
+            stxw [r1+0x4], r1
+            ldxb r0, [r1+0x4]
+            exit",
+            "Ok(0)",
+        ),
+        (
+            "
+            stb [r1+0x3], 0
+            exit",
+            "AccessViolation(Store, 17179869187, 1, \"account #0 data\")",
+        ),
+        (
+            "
+            stxdw [r1+0x0], r1
+            exit",
+            "AccessViolation(Store, 17179869184, 8, \"account #0 data\")",
+        ),
+    ] {
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            #[allow(unused_mut)]
+            let mut executable =
+                assemble::<TestContextObject>(source, Arc::new(BuiltinProgram::new_mock()))
+                    .unwrap();
+            #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+            if !interpreted {
+                executable.jit_compile().unwrap();
+            }
+            // A readonly header of 4 bytes followed by a writable body
+            let mut mem = [0x11; 8];
+            let mut context_object = TestContextObject::new(3);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)
+                    .with_label("account #0 data")
+                    .with_writable_ranges(Some(4..8))],
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert!(
+                format!("{result:?}").contains(expected_result),
+                "{:?}",
+                result
+            );
+            drop(vm);
+            assert_eq!(mem[..4], [0x11; 4]);
+        }
+    }
+}
+
+#[test]
+fn test_dirty_tracking() {
+    for interpreted in [true, false] {
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        if !interpreted {
+            continue;
+        }
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            stw [r1+0x2], 0x11
+            stb [r1+0x8], 0x22
+            stb [r1+0x9], 0x33
+            exit",
+            Arc::new(BuiltinProgram::new_mock()),
+        )
+        .unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if !interpreted {
+            executable.jit_compile().unwrap();
+        }
+        let mut mem = [0x11; 16];
+        let mut context_object = TestContextObject::new(4);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        vm.memory_mapping.set_dirty_tracking(true);
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(
+            vm.memory_mapping.dirty_runs(),
+            vec![
+                DirtyRun {
+                    region: ebpf::MM_INPUT_START,
+                    offset: 3,
+                    old: vec![0x11; 3],
+                    new: vec![0x00; 3],
+                },
+                DirtyRun {
+                    region: ebpf::MM_INPUT_START,
+                    offset: 8,
+                    old: vec![0x11, 0x11],
+                    new: vec![0x22, 0x33],
+                },
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_region_statistics() {
+    let config = Config {
+        enable_region_statistics: true,
+        ..Config::default()
+    };
+    for interpreted in [true, false] {
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        if !interpreted {
+            continue;
+        }
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            ldxw r0, [r1+0x4]
+            ldxb r2, [r1+0x0]
+            stxdw [r1+0x8], r0
+            stxdw [r10-0x8], r2
+            exit",
+            Arc::new(BuiltinProgram::new_loader(
+                config,
+                FunctionRegistry::default(),
+            )),
+        )
+        .unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if !interpreted {
+            executable.jit_compile().unwrap();
+        }
+        let mut mem = [0; 16];
+        let mut context_object = TestContextObject::new(5);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert!(result.is_ok());
+        let statistics = vm.memory_mapping.region_statistics();
+        assert_eq!(
+            statistics.keys().copied().collect::<Vec<_>>(),
+            vec![ebpf::MM_STACK_START, ebpf::MM_INPUT_START]
+        );
+        assert_eq!(
+            statistics[&ebpf::MM_INPUT_START],
+            RegionAccessStatistics {
+                loads: 2,
+                stores: 1,
+                bytes: 13,
+            }
+        );
+        assert_eq!(statistics[&ebpf::MM_STACK_START].stores, 1);
+    }
+}
+
+#[test]
+fn test_watchpoints() {
+    for interpreted in [true, false] {
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        if !interpreted {
+            continue;
+        }
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            ldxw r0, [r1+0x4]
+            add32 r0, 1
+            stxb [r1+0x0], r0
+            stxdw [r1+0x8], r0
+            exit",
+            Arc::new(BuiltinProgram::new_loader(
+                Config::default(),
+                FunctionRegistry::default(),
+            )),
+        )
+        .unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if !interpreted {
+            executable.jit_compile().unwrap();
+        }
+        for (kind, expected_pc, expected_error) in [
+            (
+                WatchpointKind::Read,
+                0,
+                "WatchpointHit(Load, 17179869188, 4)",
+            ),
+            (
+                WatchpointKind::Write,
+                3,
+                "WatchpointHit(Store, 17179869192, 8)",
+            ),
+            (
+                WatchpointKind::Any,
+                0,
+                "WatchpointHit(Load, 17179869188, 4)",
+            ),
+        ] {
+            let mut mem = [0x11; 16];
+            let mut context_object = TestContextObject::new(5);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START)],
+                None
+            );
+            assert!(vm
+                .memory_mapping
+                .add_watchpoint(ebpf::MM_INPUT_START + 4, 8, kind));
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_error!(result, "{}", expected_error);
+            assert_eq!(vm.registers[11], expected_pc);
+            drop(vm);
+            // The watched store did not happen, the unwatched one before it did
+            assert_eq!(mem[0], if expected_pc == 3 { 0x12 } else { 0x11 });
+            assert_eq!(&mem[8..], &[0x11; 8]);
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_uninitialized_read_detection() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"bpf_mem_frob", syscalls::SyscallMemFrob::vm)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        stw [r10-0x8], 1
+        ldxw r0, [r10-0x8]
+        ldxdw r0, [r10-0x8]
+        mov r1, r10
+        add r1, -0x10
+        mov r2, 8
+        syscall bpf_mem_frob
+        ldxdw r0, [r10-0x10]
+        ldxb r0, [r10-0x18]
+        add r3, 1
+        jlt r3, 2, -3
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            Config::default(),
+            function_registry,
+        )),
+    )
+    .unwrap();
+    let mut context_object = TestContextObject::new(17);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        Vec::new(),
+        None
+    );
+    assert_eq!(vm.get_uninitialized_reads(), None);
+    vm.set_uninitialized_read_detection(true);
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert!(result.is_ok(), "{:?}", result);
+    let reads = vm.get_uninitialized_reads().unwrap().to_vec();
+    assert_eq!(
+        reads
+            .iter()
+            .map(|read| (read.pc, read.len))
+            .collect::<Vec<_>>(),
+        vec![(2, 8), (8, 1)]
+    );
+    assert_eq!(reads[0].vm_addr >> 32, ebpf::MM_STACK_START >> 32);
+    assert_eq!(reads[1].vm_addr, reads[0].vm_addr - 0x10);
+    assert!(vm.memory_mapping.is_initialized(reads[0].vm_addr, 4));
+
+    // The written bytes are forgotten on reset
+    vm.reset(&executable);
+    assert!(vm.get_uninitialized_reads().unwrap().is_empty());
+    assert!(!vm.memory_mapping.is_initialized(reads[0].vm_addr, 4));
+}
+
+#[test]
+fn test_syscall_fault_injection() {
+    for interpreted in [true, false] {
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        if !interpreted {
+            continue;
+        }
+        let mut function_registry =
+            FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+        function_registry
+            .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
+            .unwrap();
+        let mut loader = BuiltinProgram::new_loader(Config::default(), function_registry);
+        assert_error!(
+            loader.inject_syscall_fault(b"log", 1, "unreachable"),
+            "SyscallNotRegistered({})",
+            ebpf::hash_symbol_name(b"log")
+        );
+        loader
+            .inject_syscall_fault(b"gather_bytes", 1, "replaced")
+            .unwrap();
+        loader
+            .inject_syscall_fault(b"gather_bytes", 3, "injected")
+            .unwrap();
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            mov r1, 0
+            mov r2, 2
+            mov r3, 3
+            mov r4, 4
+            mov r5, 5
+            syscall gather_bytes
+            mov r6, r0
+            syscall gather_bytes
+            add r0, r6
+            exit",
+            Arc::new(loader),
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if !interpreted {
+            executable.jit_compile().unwrap();
+        }
+        // Every VM counts on its own, so the third invocation fails in the second run of a VM
+        let mut context_object = TestContextObject::new(100);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        for expected_result in [
+            ProgramResult::Ok(0x000406080a),
+            ProgramResult::Err(EbpfError::SyscallError("injected".into())),
+            ProgramResult::Ok(0x000406080a),
+        ] {
+            let (instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(format!("{result:?}"), format!("{expected_result:?}"));
+            assert_eq!(
+                instruction_count,
+                if expected_result.is_ok() { 10 } else { 6 }
+            );
+        }
+        let mut context_object = TestContextObject::new(100);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert_eq!(result.unwrap(), 0x000406080a);
+    }
+}
+
+#[test]
+fn test_syscall_cost() {
+    let create_loader = |costs: Vec<SyscallCost>| {
+        let mut function_registry =
+            FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+        function_registry
+            .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
+            .unwrap();
+        let mut loader = BuiltinProgram::new_loader(Config::default(), function_registry);
+        for cost in costs {
+            loader.set_syscall_cost(b"gather_bytes", cost).unwrap();
+        }
+        Arc::new(loader)
+    };
+    let source = "
+        mov r1, 0
+        mov r2, 2
+        mov r3, 3
+        mov r4, 4
+        mov r5, 5
+        syscall gather_bytes
+        mov r6, r0
+        syscall gather_bytes
+        add r0, r6
+        exit";
+    let mut loader = BuiltinProgram::<TestContextObject>::new_mock();
+    assert_error!(
+        loader.set_syscall_cost(b"log", SyscallCost::Static(1)),
+        "SyscallNotRegistered({})",
+        ebpf::hash_symbol_name(b"log")
+    );
+
+    // Ten instructions and two invocations costing 20 each
+    let mut executable = assemble::<TestContextObject>(
+        source,
+        create_loader(vec![SyscallCost::Dynamic(Box::new(|arguments| {
+            arguments[1] * 10
+        }))]),
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(50),
+        ProgramResult::Ok(0x000406080a),
+    );
+
+    // The second invocation does not fit into the budget, so it does not run
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(35),
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+    );
+
+    // Setting the cost again replaces it
+    let mut executable = assemble::<TestContextObject>(
+        source,
+        create_loader(vec![SyscallCost::Static(1000), SyscallCost::Static(1)]),
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(12),
+        ProgramResult::Ok(0x000406080a),
+    );
+
+    // Loaders which only differ in their costs do not share compiled programs
+    #[cfg(all(
+        feature = "unstable",
+        feature = "jit",
+        not(target_os = "windows"),
+        target_arch = "x86_64"
+    ))]
+    {
+        let cache = solana_rbpf::jit_cache::JitCache::new(2);
+        for costs in [vec![], vec![SyscallCost::Static(1)]] {
+            let mut executable =
+                assemble::<TestContextObject>(source, create_loader(costs)).unwrap();
+            executable.jit_compile_cached(&cache).unwrap();
+        }
+        assert_eq!(cache.statistics().misses, 2);
+    }
+}
+
+#[test]
+fn test_syscall_tracing() {
+    for interpreted in [true, false] {
+        #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+        if !interpreted {
+            continue;
+        }
+        let mut function_registry =
+            FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+        function_registry
+            .register_function_hashed(*b"gather_bytes", syscalls::SyscallGatherBytes::vm)
+            .unwrap();
+        let config = Config {
+            enable_syscall_tracing: true,
+            ..Config::default()
+        };
+        let mut loader = BuiltinProgram::new_loader(config, function_registry);
+        loader
+            .set_syscall_cost(b"gather_bytes", SyscallCost::Static(20))
+            .unwrap();
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            mov r1, 1
+            mov r2, 2
+            mov r3, 3
+            mov r4, 4
+            mov r5, 5
+            syscall gather_bytes
+            mov r1, 6
+            syscall gather_bytes
+            exit",
+            Arc::new(loader),
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        if !interpreted {
+            executable.jit_compile().unwrap();
+        }
+        // The second invocation exceeds the budget, so it exits without running
+        let mut context_object = TestContextObject::new(40);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert_error!(result, "ExceededMaxInstructions");
+        assert_eq!(
+            context_object.syscall_log,
+            vec![
+                "enter gather_bytes [1, 2, 3, 4, 5]",
+                "exit gather_bytes [1, 2, 3, 4, 5] Ok(4328719365)",
+                "enter gather_bytes [6, 2, 3, 4, 5]",
+                "exit gather_bytes [6, 2, 3, 4, 5] Err(ExceededMaxInstructions)",
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_trace_sampling() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let loader = BuiltinProgram::new_loader(config, FunctionRegistry::default());
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        mov r1, 4
+        add r0, r1
+        add r1, -1
+        jne r1, 0, -3
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    for (trace_sampling, expected_pcs) in [
+        (
+            TraceSampling::All,
+            vec![0, 1, 2, 3, 4, 2, 3, 4, 2, 3, 4, 2, 3, 4, 5],
+        ),
+        (TraceSampling::EveryNth(4), vec![0, 4, 2, 3]),
+        (TraceSampling::Branches, vec![4, 4, 4, 4, 5]),
+    ] {
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject {
+                trace_sampling,
+                ..TestContextObject::new(15)
+            };
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert!(matches!(result, ProgramResult::Ok(10)));
+            let pcs = vm
+                .context_object_pointer
+                .trace_log
+                .iter()
+                .map(|state| state[11])
+                .collect::<Vec<_>>();
+            assert_eq!(pcs, expected_pcs);
+        }
+    }
+}
+
+#[test]
+fn test_set_arguments() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r0, r1
+        add r0, r2
+        add r0, r3
+        add r0, r4
+        add r0, r5
+        mov r6, r1
+        mov r9, r5
+        exit",
+        loader,
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(16);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        vm.set_arguments(&[1, 2, 3, 4, 5]);
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert!(matches!(result, ProgramResult::Ok(15)));
+        assert_eq!(vm.get_register(0), 15);
+        assert_eq!(vm.get_register(1), 1);
+        assert_eq!(vm.get_register(5), 5);
+        assert_eq!(vm.get_register(6), 1);
+        assert_eq!(vm.get_register(7), 0);
+        assert_eq!(vm.get_register(9), 5);
+        // The arguments only apply to one execution
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert!(matches!(result, ProgramResult::Ok(ebpf::MM_INPUT_START)));
+        assert_eq!(vm.get_register(9), 0);
+    }
+}
+
+#[test]
+fn test_registers_on_error() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r0, 7
+        mov r2, 0
+        mov r6, r1
+        mov r9, 3
+        udiv64 r0, r2
+        exit",
+        loader,
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(16);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+        assert_error!(result, "DivideByZero");
+        assert_eq!(vm.registers[11], 4);
+        assert_eq!(vm.get_register(0), 7);
+        assert_eq!(vm.get_register(2), 0);
+        assert_eq!(vm.get_register(6), ebpf::MM_INPUT_START);
+        assert_eq!(vm.get_register(9), 3);
+    }
+}
+
+#[test]
+fn test_registers_on_access_violation() {
+    // Without instruction tracing the JIT omits saving dead registers and redundant sign
+    // extensions, which must not change the registers captured when the next instruction faults
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r0, 7
+        udiv64 r4, 3
+        ldxdw r0, [r2]
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(3),
+        ProgramResult::Err(EbpfError::AccessViolation(
+            AccessType::Load,
+            0,
+            8,
+            "unknown"
+        )),
+    );
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        add32 r0, -1
+        ldxw r0, [r2]
+        exit",
+        loader,
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(3),
+        ProgramResult::Err(EbpfError::AccessViolation(
+            AccessType::Load,
+            0,
+            4,
+            "unknown"
+        )),
+    );
+}
+
+#[test]
+fn test_zero_memory_on_reset() {
+    for zero_memory_on_reset in [false, true] {
+        let config = Config {
+            zero_memory_on_reset,
+            ..Config::default()
+        };
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        ));
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            ldxdw r0, [r10-8]
+            stxdw [r10-8], r1
+            exit",
+            loader,
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            let mut context_object = TestContextObject::new(6);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            vm.set_arguments(&[7, 0, 0, 0, 0]);
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert!(matches!(result, ProgramResult::Ok(0)));
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            let expected = if zero_memory_on_reset { 0 } else { 7 };
+            assert!(matches!(result, ProgramResult::Ok(value) if value == expected));
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_exit_hook() {
+    #[derive(Debug, thiserror::Error)]
+    #[error("program returned error code {0}")]
+    struct ErrorCode(u64);
+
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    #[allow(unused_mut)]
+    let mut executable = assemble::<TestContextObject>(
+        "
+        ldxb r0, [r1]
+        exit",
+        loader,
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    executable.jit_compile().unwrap();
+    for interpreted in [true, false] {
+        for (error_code, expected_result) in [(0, "Ok(0)"), (3, "Err(SyscallError(ErrorCode(3)))")]
+        {
+            let mut mem = [error_code];
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            let mut context_object = TestContextObject::new(16);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                vec![mem_region],
+                None
+            );
+            // Maps a non-zero r0 to a host error
+            vm.set_exit_hook(|result, context_object, memory_mapping| {
+                assert_eq!(context_object.remaining, 14);
+                let input = memory_mapping
+                    .map(AccessType::Load, ebpf::MM_INPUT_START, 1)
+                    .unwrap();
+                match result {
+                    ProgramResult::Ok(r0) if r0 != 0 => {
+                        assert_eq!(unsafe { *(input as *const u8) } as u64, r0);
+                        ProgramResult::Err(EbpfError::SyscallError(Box::new(ErrorCode(r0))))
+                    }
+                    result => result,
+                }
+            });
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(format!("{result:?}"), expected_result);
+        }
+    }
+}
+
+#[test]
+fn test_constant_time_audit() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let loader = BuiltinProgram::new_loader(config, FunctionRegistry::default());
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r2, [r1]
+        ldxdw r3, [r1+8]
+        jeq r3, 0, +1
+        add r0, 1
+        mov r4, r2
+        and r4, 7
+        add r4, r1
+        ldxb r5, [r4+16]
+        jne r2, 0, +1
+        mov r0, 2
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut mem = [0u8; 32];
+    mem[0] = 3;
+    let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(11);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![mem_region],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert!(matches!(result, ProgramResult::Ok(0)));
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let violations = analysis
+        .audit_constant_time(
+            &vm.context_object_pointer.trace_log,
+            ebpf::MM_INPUT_START..ebpf::MM_INPUT_START + 8,
+        )
+        .unwrap();
+    assert_eq!(
+        violations.into_iter().collect::<Vec<_>>(),
+        vec![
+            ConstantTimeViolation::SecretDependentBranch(8),
+            ConstantTimeViolation::SecretDependentMemoryAccess(7),
+        ]
+    );
+}
+
+#[test]
+fn test_constant_time_audit_foreign_trace() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        config,
+        FunctionRegistry::default(),
+    ));
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r2, [r1]
+        jeq r2, 0, +1
+        mov r0, 1
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut mem = [0u8; 8];
+    let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(4);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![mem_region],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert!(matches!(result, ProgramResult::Ok(0)));
+    let trace_log = &vm.context_object_pointer.trace_log;
+
+    // The trace was recorded with another executable, which has fewer instructions
+    let other_executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        exit",
+        loader,
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&other_executable).unwrap();
+    assert_error!(
+        analysis.audit_constant_time(trace_log, ebpf::MM_INPUT_START..ebpf::MM_INPUT_START + 8),
+        "InstructionOutOfBounds(3)"
+    );
+
+    // The trace points into the middle of an lddw of another executable
+    let other_executable = assemble::<TestContextObject>(
+        "
+        lddw r0, 0x1122334455667788
+        mov r0, 0
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_sbpf_v2: false,
+                ..config
+            },
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&other_executable).unwrap();
+    assert_error!(
+        analysis.audit_constant_time(trace_log, ebpf::MM_INPUT_START..ebpf::MM_INPUT_START + 8),
+        "InstructionOutOfBounds(1)"
+    );
+
+    // A truncated trace is audited as far as it goes
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert!(analysis
+        .audit_constant_time(
+            &trace_log[..2],
+            ebpf::MM_INPUT_START..ebpf::MM_INPUT_START + 8
+        )
+        .unwrap()
+        .contains(&ConstantTimeViolation::SecretDependentBranch(1)));
+}
+
+#[test]
+fn test_constant_time_audit_call_to_next_pc() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        enable_sbpf_v2: false,
+        ..Config::default()
+    };
+    let loader = BuiltinProgram::new_loader(config, FunctionRegistry::default());
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r2, [r1]
+        call function_foo
+        function_foo:
+        jne r0, 0, +0
+        exit",
+        Arc::new(loader),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut mem = [0u8; 8];
+    let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(6);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![mem_region],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert!(matches!(result, ProgramResult::Ok(0)));
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    // The callee follows the call directly, which must not be mistaken for a syscall
+    // tainting r0 with the secret in r2
+    let violations = analysis
+        .audit_constant_time(
+            &vm.context_object_pointer.trace_log,
+            ebpf::MM_INPUT_START..ebpf::MM_INPUT_START + 8,
+        )
+        .unwrap();
+    assert!(violations.is_empty());
+}
+
+#[test]
+fn test_optimization_report() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        mov r1, 5
+        add r1, 3
+        mov r2, 1
+        mov r2, 2
+        jgt r1, 7, +2
+        mov r0, 1
+        exit
+        mov r0, r2
+        exit",
+        loader,
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis
+            .optimization_report()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![
+            OptimizationHint::UnusedResult(0),
+            OptimizationHint::UnusedResult(3),
+            OptimizationHint::BranchAlwaysTaken(5),
+            OptimizationHint::UnreachableBasicBlock(6),
+        ]
+    );
+}
+
+#[test]
+fn test_stack_usage() {
+    let loader = Arc::new(BuiltinProgram::new_mock());
+    let executable = assemble::<TestContextObject>(
+        "
+        add64 r11, -32
+        call function_foo
+        call function_bar
+        add64 r11, 32
+        exit
+        function_foo:
+        add64 r11, -64
+        call function_bar
+        add64 r11, 64
+        exit
+        function_bar:
+        add64 r11, -16
+        add64 r11, 16
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis.verify_stack_usage().unwrap(),
+        StackUsage::Bounded {
+            bytes: 112,
+            call_depth: 3,
+        }
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        add64 r11, -100000
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_error!(analysis.verify_stack_usage(), "StackOverflow(100000, 1)");
+
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        call function_foo
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis.verify_stack_usage().unwrap(),
+        StackUsage::Recursive(2)
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        callx r1
+        exit",
+        loader,
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis.verify_stack_usage().unwrap(),
+        StackUsage::IndirectCall(0)
+    );
+
+    // Fixed stack frames are bounded by the call depth
+    let config = Config {
+        enable_sbpf_v2: false,
+        max_call_depth: 2,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        call function_bar
+        exit
+        function_bar:
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_error!(analysis.verify_stack_usage(), "StackOverflow(12288, 3)");
+}
+
+#[test]
+fn test_uninitialized_register_reads() {
+    let loader = Arc::new(BuiltinProgram::new_mock());
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, r1
+        jeq r2, 0, +1
+        mov r3, 1
+        add r0, r3
+        call function_foo
+        exit
+        function_foo:
+        add r0, r4
+        mov r0, r6
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis
+            .uninitialized_register_reads()
+            .into_iter()
+            .map(|read| (read.pc, read.register))
+            .collect::<Vec<_>>(),
+        vec![(3, 3), (6, 4), (7, 6)]
+    );
+    assert_error!(
+        analysis.verify_register_initialization(),
+        "UninitializedRegister(3, 3)"
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        call function_foo
+        exit
+        function_foo:
+        mov r0, r1
+        exit",
+        loader,
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert!(analysis.verify_register_initialization().is_ok());
+}
+
+#[test]
+fn test_callx_targets() {
+    let loader = Arc::new(BuiltinProgram::new_mock());
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
+        callx r1
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_eq!(
+        analysis.callx_targets().into_iter().collect::<Vec<_>>(),
+        vec![(3, 0x100000030)]
+    );
+    assert!(analysis.verify_callx_targets().is_ok());
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov32 r8, 0x28
+        hor64 r8, 0x1
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        loader,
+    )
+    .unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    assert_error!(
+        analysis.verify_callx_targets(),
+        "InvalidCallxTarget(4294967336, 2)"
+    );
+}
+
+#[test]
+fn test_profile_section() {
+    let config = Config {
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        config,
+        FunctionRegistry::default(),
+    ));
+    let mut file = File::open("tests/elfs/relative_call.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(&elf, loader.clone()).unwrap();
+    assert!(executable.get_profile().is_none());
+    let mut mem = [1u8];
+    let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(18);
+    create_vm!(
+        vm,
+        &executable,
+        &mut context_object,
+        stack,
+        heap,
+        vec![mem_region],
+        None
+    );
+    let (_instruction_count, result) = vm.execute_program(&executable, true);
+    assert!(matches!(result, ProgramResult::Ok(3)));
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let profile = DynamicAnalysis::new(&vm.context_object_pointer.trace_log, &analysis);
+    assert!(!profile.edges.is_empty());
+
+    let elf_with_profile =
+        Executable::<TestContextObject>::append_profile_section(&elf, &profile).unwrap();
+    #[allow(unused_mut)]
+    let mut executable =
+        Executable::<TestContextObject>::from_elf(&elf_with_profile, loader.clone()).unwrap();
+    assert_eq!(executable.get_profile(), Some(&profile));
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let mut disassembly = Vec::new();
+    analysis.disassemble(&mut disassembly).unwrap();
+    assert!(String::from_utf8(disassembly)
+        .unwrap()
+        .contains(": // executed 1 times"));
+    test_interpreter_and_jit!(
+        executable,
+        [1],
+        TestContextObject::new(18),
+        ProgramResult::Ok(3),
+    );
+
+    // Replacing the profile
+    let profile = DynamicAnalysis::default();
+    let elf_with_profile =
+        Executable::<TestContextObject>::append_profile_section(&elf_with_profile, &profile)
+            .unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(&elf_with_profile, loader).unwrap();
+    assert_eq!(executable.get_profile(), Some(&profile));
+}
+
+#[test]
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+fn test_profile_guided_layout() {
+    let mut file = File::open("tests/elfs/relative_call.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    let executable = Executable::<TestContextObject>::from_elf(&elf, loader).unwrap();
+    let hot_function = executable
+        .get_function_registry()
+        .iter()
+        .map(|(_key, (_name, pc))| pc)
+        .max()
+        .unwrap();
+    assert_ne!(hot_function, executable.get_entrypoint_instruction_offset());
+    // The functions of this ELF are unnamed, apart from the entrypoint
+    let hot_function_name = format!("function_{hot_function}");
+
+    // Pretend that the last function was entered far more often than any other
+    let mut profile = DynamicAnalysis::default();
+    profile
+        .edges
+        .entry(usize::MAX)
+        .or_default()
+        .insert(hot_function, 1000);
+    profile.edge_counter_max = 1000;
+    let elf_with_profile =
+        Executable::<TestContextObject>::append_profile_section(&elf, &profile).unwrap();
+    for enable_profile_guided_layout in [false, true] {
+        let config = Config {
+            enable_profile_guided_layout,
+            ..Config::default()
+        };
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        ));
+        #[allow(unused_mut)]
+        let mut executable =
+            Executable::<TestContextObject>::from_elf(&elf_with_profile, loader).unwrap();
+        executable.jit_compile().unwrap();
+        // The perf map lists the functions in the order of their machine code
+        let mut perf_map = Vec::new();
+        executable
+            .get_compiled_program()
+            .unwrap()
+            .write_perf_map(&mut perf_map, executable.get_function_registry())
+            .unwrap();
+        let perf_map = String::from_utf8(perf_map).unwrap();
+        let first_function = perf_map.lines().next().unwrap().split(' ').nth(2);
+        assert_eq!(
+            first_function == Some(hot_function_name.as_str()),
+            enable_profile_guided_layout
+        );
+        test_interpreter_and_jit!(
+            executable,
+            [1],
+            TestContextObject::new(18),
+            ProgramResult::Ok(3),
+        );
+    }
+}
+
+#[test]
+#[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+fn test_jit_function_timing() {
+    for enable_jit_function_timing in [false, true] {
+        let config = Config {
+            enable_jit_function_timing,
+            ..Config::default()
+        };
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        ));
+        let mut executable = assemble::<TestContextObject>(
+            "
+            call function_foo
+            call function_foo
+            exit
+            function_foo:
+            call function_bar
+            exit
+            function_bar:
+            mov r0, 1
+            exit",
+            loader,
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        executable.jit_compile().unwrap();
+        let mut context_object = TestContextObject::new(11);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (instruction_count, result) = vm.execute_program(&executable, false);
+        assert_eq!(instruction_count, 11);
+        assert_eq!(result.unwrap(), 1);
+        let function_timings = vm.get_function_timings();
+        if !enable_jit_function_timing {
+            assert!(function_timings.is_empty());
+            continue;
+        }
+        assert_eq!(
+            function_timings
+                .iter()
+                .map(|(function_start, timing)| (*function_start, timing.calls))
+                .collect::<Vec<_>>(),
+            vec![(0, 1), (3, 2), (5, 2)]
+        );
+        for timing in function_timings.values() {
+            assert!(timing.self_ticks <= timing.ticks);
+        }
+        // Calls include the time of their callees
+        assert!(function_timings[&3].ticks >= function_timings[&5].ticks);
+        assert!(function_timings[&0].ticks >= function_timings[&3].ticks);
+        assert_eq!(
+            function_timings[&3].self_ticks,
+            function_timings[&3].ticks - function_timings[&5].ticks
+        );
+        let profile = DynamicAnalysis::default().with_function_timings(function_timings);
+        assert_eq!(&profile.function_timings, function_timings);
+    }
+}
+
+// Instruction Meter Limit
+
+#[test]
+fn test_tight_infinite_loop_conditional() {
+    test_interpreter_and_jit_asm!(
+        "
+        jsge r0, r0, -1
+        exit",
+        [],
+        (),
+        TestContextObject::new(4),
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+    );
+}
+
+#[test]
+fn test_tight_infinite_loop_unconditional() {
+    test_interpreter_and_jit_asm!(
+        "
+        ja -1
+        exit",
+        [],
+        (),
+        TestContextObject::new(4),
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+    );
+}
+
+#[test]
+fn test_tight_infinite_recursion() {
+    test_interpreter_and_jit_asm!(
+        "
+        entrypoint:
+        mov64 r3, 0x41414141
+        call entrypoint
+        exit",
+        [],
+        (),
+        TestContextObject::new(4),
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+    );
+}
+
+#[test]
+fn test_tight_infinite_recursion_callx() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x28
+        call function_foo
+        exit
+        function_foo:
+        mov64 r3, 0x41414141
+        callx r8
+        exit",
+        [],
+        (),
+        TestContextObject::new(8),
+        ProgramResult::Err(EbpfError::ExceededMaxInstructions),
+    );
+}
+
+#[test]
+fn test_instruction_count_syscall() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r2, 0x5
+        syscall bpf_syscall_string
+        mov64 r0, 0x0
+        exit",
+        [72, 101, 108, 108, 111],
+        (
+            "bpf_syscall_string" => syscalls::SyscallString::vm,
+        ),
+        TestContextObject::new(4),
         ProgramResult::Ok(0),
     );
 }
@@ -2908,6 +5782,151 @@ fn test_err_unresolved_syscall_reloc_64_32() {
     );
 }
 
+#[test]
+fn test_unresolved_syscall_reloc_64_32_policy() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            reject_broken_elfs: true,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    let mut file = File::open("tests/elfs/syscall_reloc_64_32.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+
+    let mut executable = Executable::<TestContextObject>::load_with_unresolved_symbol_policy(
+        &elf,
+        loader.clone(),
+        UnresolvedSymbolPolicy::StubWithTrap,
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(3),
+        ProgramResult::Err(EbpfError::UnsupportedInstruction),
+    );
+
+    let mut executable = Executable::<TestContextObject>::load_with_unresolved_symbol_policy(
+        &elf,
+        loader,
+        UnresolvedSymbolPolicy::StubReturningZero,
+    )
+    .unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(5),
+        ProgramResult::Ok(0),
+    );
+}
+
+#[test]
+fn test_unresolved_data_reloc_64_64_policy() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            reject_broken_elfs: true,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    // The program returns the address of the entrypoint, filled in by an R_BPF_64_64 relocation
+    let mut file = File::open("tests/elfs/reloc_64_64.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let parsed_elf = Elf64::parse(&elf).unwrap();
+    let relocation = parsed_elf.dynamic_relocations_table().unwrap()[0].clone();
+    let dynsym = parsed_elf
+        .section_header_table()
+        .iter()
+        .find(|section_header| {
+            parsed_elf.section_name(section_header.sh_name).unwrap() == b".dynsym"
+        })
+        .unwrap();
+    let symbol_offset =
+        dynsym.sh_offset as usize + relocation.r_sym() as usize * mem::size_of::<Elf64Sym>();
+    // Undefine the symbol of the relocation
+    let mut symbol =
+        parsed_elf.dynamic_symbol_table().unwrap()[relocation.r_sym() as usize].clone();
+    symbol.st_shndx = SHN_UNDEF;
+    symbol.st_value = 0;
+    unsafe {
+        std::ptr::write_unaligned(
+            elf.as_mut_ptr().add(symbol_offset).cast::<Elf64Sym>(),
+            symbol,
+        );
+    }
+
+    assert_error!(
+        Executable::<TestContextObject>::from_elf(&elf, loader.clone()),
+        "UnresolvedSymbol(\"entrypoint\", 512, 4096)"
+    );
+    for unresolved_symbol_policy in [
+        UnresolvedSymbolPolicy::StubWithTrap,
+        UnresolvedSymbolPolicy::StubReturningZero,
+    ] {
+        let mut executable = Executable::<TestContextObject>::load_with_unresolved_symbol_policy(
+            &elf,
+            loader.clone(),
+            unresolved_symbol_policy,
+        )
+        .unwrap();
+        test_interpreter_and_jit!(
+            executable,
+            [],
+            TestContextObject::new(3),
+            ProgramResult::Ok(0),
+        );
+    }
+}
+
+#[test]
+fn test_symbol_resolver() {
+    let mut function_registry = FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+    function_registry
+        .register_function_hashed(*b"sol_log", syscalls::SyscallString::vm)
+        .unwrap();
+    let mut loader = BuiltinProgram::new_loader(
+        Config {
+            reject_broken_elfs: true,
+            ..Config::default()
+        },
+        function_registry,
+    );
+    loader.set_symbol_resolver(Box::new(|name| {
+        (name == "log").then(|| ebpf::hash_symbol_name(b"sol_log") as u64)
+    }));
+    let loader = Arc::new(loader);
+    let mut file = File::open("tests/elfs/syscall_reloc_64_32.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+
+    let mut executable = Executable::<TestContextObject>::from_elf(&elf, loader).unwrap();
+    test_interpreter_and_jit!(
+        executable,
+        [],
+        TestContextObject::new(5),
+        ProgramResult::Ok(0),
+    );
+
+    // Keys which are not registered do not resolve the symbol
+    let mut loader = BuiltinProgram::new_loader(
+        Config {
+            reject_broken_elfs: true,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    );
+    loader.set_symbol_resolver(Box::new(
+        |_name| Some(ebpf::hash_symbol_name(b"log") as u64),
+    ));
+    assert_error!(
+        Executable::<TestContextObject>::from_elf(&elf, Arc::new(loader)),
+        "UnresolvedSymbol(\"log\", 39, 312)"
+    );
+}
+
 #[test]
 fn test_err_unresolved_syscall_static() {
     test_interpreter_and_jit_elf!(
@@ -2919,6 +5938,71 @@ fn test_err_unresolved_syscall_static() {
     );
 }
 
+#[test]
+fn test_err_incompatible_executable() {
+    // The SBPF version is not enabled
+    let executable = Executable::<TestContextObject>::from_text_bytes(
+        &[0x95, 0, 0, 0, 0, 0, 0, 0],
+        Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_sbpf_v2: false,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        )),
+        SBPFVersion::V2,
+        FunctionRegistry::default(),
+    )
+    .unwrap();
+    let mut context_object = TestContextObject::new(1);
+    assert_error!(
+        EbpfVm::new(
+            &executable,
+            &mut context_object,
+            MemoryMapping::new(
+                Vec::new(),
+                executable.get_config(),
+                executable.get_sbpf_version()
+            )
+            .unwrap(),
+            0
+        )
+        .err(),
+        "IncompatibleExecutable([SBPFVersionNotEnabled(V2)])"
+    );
+
+    // A syscall is missing and unresolved symbols are rejected
+    let mut file = File::open("tests/elfs/syscall_static.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(
+        &elf,
+        Arc::new(BuiltinProgram::new_loader(
+            Config {
+                reject_broken_elfs: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    assert_error!(
+        EbpfVm::new(
+            &executable,
+            &mut context_object,
+            MemoryMapping::new(
+                Vec::new(),
+                executable.get_config(),
+                executable.get_sbpf_version()
+            )
+            .unwrap(),
+            0
+        )
+        .err(),
+        "IncompatibleExecutable([MissingSyscall {{ pc: 3, key: 1811268606 }}])"
+    );
+}
+
 #[test]
 fn test_reloc_64_64_sbpfv1() {
     // Tests the correctness of R_BPF_64_64 relocations. The program returns the
@@ -3069,6 +6153,248 @@ fn test_load_elf_rodata_sbpfv1() {
     );
 }
 
+#[test]
+fn test_load_relocated() {
+    for (source, optimize_rodata, instruction_count, expected_result) in [
+        ("tests/elfs/relative_call.so", true, 18, 3),
+        ("tests/elfs/rodata_section.so", true, 4, 42),
+        ("tests/elfs/rodata_section_sbpfv1.so", false, 3, 42),
+        (
+            "tests/elfs/reloc_64_relative_data.so",
+            true,
+            4,
+            ebpf::MM_PROGRAM_START + 0x20,
+        ),
+        (
+            "tests/elfs/reloc_64_relative_data_sbpfv1.so",
+            true,
+            3,
+            ebpf::MM_PROGRAM_START + 0x108,
+        ),
+    ] {
+        let mut file = File::open(source).unwrap();
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf).unwrap();
+        let config = Config {
+            enable_instruction_tracing: true,
+            optimize_rodata,
+            ..Config::default()
+        };
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        ));
+        let executable = Executable::<TestContextObject>::from_elf(&elf, loader.clone()).unwrap();
+        let relocated_elf = executable.to_elf_bytes();
+        let mut relocated_executable =
+            Executable::<TestContextObject>::load_relocated(&relocated_elf, loader.clone())
+                .unwrap();
+        assert_eq!(
+            relocated_executable.get_ro_section(),
+            executable.get_ro_section()
+        );
+        assert_eq!(
+            relocated_executable.get_text_bytes(),
+            executable.get_text_bytes()
+        );
+        assert_eq!(
+            relocated_executable.get_entrypoint_instruction_offset(),
+            executable.get_entrypoint_instruction_offset()
+        );
+        assert_eq!(
+            relocated_executable.get_function_registry(),
+            executable.get_function_registry()
+        );
+        assert_eq!(relocated_executable.to_elf_bytes(), relocated_elf);
+        test_interpreter_and_jit!(
+            relocated_executable,
+            [1],
+            TestContextObject::new(instruction_count),
+            ProgramResult::Ok(expected_result),
+        );
+    }
+    assert_error!(
+        Executable::<TestContextObject>::load_relocated(
+            &Executable::<TestContextObject>::from_text_bytes(
+                &[0x95, 0, 0, 0, 0, 0, 0, 0],
+                Arc::new(BuiltinProgram::new_mock()),
+                SBPFVersion::V2,
+                FunctionRegistry::default(),
+            )
+            .unwrap()
+            .to_elf_bytes()[..100],
+            Arc::new(BuiltinProgram::new_mock()),
+        ),
+        "ValueOutOfBounds"
+    );
+}
+
+#[test]
+fn test_load_relocated_does_not_trust_the_file() {
+    // mov64 r12, 1; exit
+    let mut executable = Executable::<TestContextObject>::from_text_bytes(
+        &[
+            0xb7, 0x0c, 0, 0, 1, 0, 0, 0, //
+            0x95, 0, 0, 0, 0, 0, 0, 0,
+        ],
+        Arc::new(BuiltinProgram::new_mock()),
+        SBPFVersion::V2,
+        FunctionRegistry::default(),
+    )
+    .unwrap();
+    assert_error!(
+        executable.verify::<RequisiteVerifier>(),
+        "InvalidDestinationRegister(0)"
+    );
+    executable.mark_pre_verified(0..1);
+    assert!(executable.verify::<RequisiteVerifier>().is_ok());
+
+    // The pre-verified ranges are not part of the file and have to be marked again
+    let mut relocated_elf = executable.to_elf_bytes();
+    let relocated_executable = Executable::<TestContextObject>::load_relocated(
+        &relocated_elf,
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    assert!(relocated_executable.get_pre_verified_ranges().is_empty());
+    assert_error!(
+        relocated_executable.verify::<RequisiteVerifier>(),
+        "InvalidDestinationRegister(0)"
+    );
+
+    // Functions have to start inside the text section
+    let parsed_elf = Elf64::parse(&relocated_elf).unwrap();
+    let pc_offset = parsed_elf
+        .section_header_table()
+        .iter()
+        .find(|section_header| {
+            parsed_elf.section_name(section_header.sh_name).unwrap() == b".sbpf.relocated"
+        })
+        .unwrap()
+        .sh_offset as usize
+        + 8;
+    LittleEndian::write_u64(&mut relocated_elf[pc_offset..pc_offset + 8], 2);
+    assert_error!(
+        Executable::<TestContextObject>::load_relocated(
+            &relocated_elf,
+            Arc::new(BuiltinProgram::new_mock()),
+        ),
+        "InvalidRelocatedSection(\"function pc 2 out of range\")"
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_load_file() {
+    for (source, optimize_rodata, instruction_count, expected_result) in [
+        ("tests/elfs/relative_call.so", true, 18, 3),
+        ("tests/elfs/rodata_section_sbpfv1.so", false, 3, 42),
+        (
+            "tests/elfs/reloc_64_relative_data.so",
+            true,
+            4,
+            ebpf::MM_PROGRAM_START + 0x20,
+        ),
+        (
+            "tests/elfs/reloc_64_relative_data_sbpfv1.so",
+            true,
+            3,
+            ebpf::MM_PROGRAM_START + 0x108,
+        ),
+    ] {
+        let mut file = File::open(source).unwrap();
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf).unwrap();
+        let config = Config {
+            enable_instruction_tracing: true,
+            optimize_rodata,
+            ..Config::default()
+        };
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        ));
+        let executable = Executable::<TestContextObject>::from_elf(&elf, loader.clone()).unwrap();
+        // The test files are not modified while the tests run
+        let mut mapped_executable = unsafe {
+            Executable::<TestContextObject>::from_elf_file(source, loader.clone()).unwrap()
+        };
+        assert_eq!(
+            mapped_executable.get_ro_section(),
+            executable.get_ro_section()
+        );
+        assert_eq!(
+            mapped_executable.get_text_bytes(),
+            executable.get_text_bytes()
+        );
+        assert_eq!(
+            mapped_executable.get_function_registry(),
+            executable.get_function_registry()
+        );
+        // The relocation must not write through to the file
+        assert_eq!(std::fs::read(source).unwrap(), elf);
+        test_interpreter_and_jit!(
+            mapped_executable,
+            [1],
+            TestContextObject::new(instruction_count),
+            ProgramResult::Ok(expected_result),
+        );
+    }
+    assert_error!(
+        unsafe {
+            Executable::<TestContextObject>::from_elf_file(
+                "tests/elfs/does_not_exist.so",
+                Arc::new(BuiltinProgram::new_mock()),
+            )
+        },
+        "FailedToReadFile"
+    );
+}
+
+#[test]
+fn test_load_with_entrypoint() {
+    let mut file = File::open("tests/elfs/relative_call.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            enable_instruction_tracing: true,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    let mut executable = Executable::<TestContextObject>::from_elf_with_entry(
+        &elf,
+        loader.clone(),
+        "_ZN13relative_call12function_sum17h374600da0fe56a1aE",
+    )
+    .unwrap();
+    assert_eq!(executable.get_entrypoint_instruction_offset(), 15);
+    // function_sum(r1, r2) with r1 pointing to the input and r2 being zero
+    test_interpreter_and_jit!(
+        executable,
+        [1],
+        TestContextObject::new(3),
+        ProgramResult::Ok(ebpf::MM_INPUT_START),
+    );
+
+    let mut executable =
+        Executable::<TestContextObject>::from_elf_with_entry(&elf, loader.clone(), "entrypoint")
+            .unwrap();
+    assert_eq!(executable.get_entrypoint_instruction_offset(), 4);
+    test_interpreter_and_jit!(
+        executable,
+        [1],
+        TestContextObject::new(18),
+        ProgramResult::Ok(3),
+    );
+
+    assert_error!(
+        Executable::<TestContextObject>::from_elf_with_entry(&elf, loader, "process_instruction"),
+        "EntrypointNotFound(\"process_instruction\")"
+    );
+}
+
 // Programs
 
 #[test]
@@ -3406,26 +6732,111 @@ fn test_total_chaos() {
 
 // SBPFv1 only [DEPRECATED]
 
-#[test]
-fn test_err_fixed_stack_out_of_bound() {
+#[test]
+fn test_err_fixed_stack_out_of_bound() {
+    let config = Config {
+        enable_sbpf_v2: false,
+        max_call_depth: 3,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        stb [r10-0x4000], 0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(1),
+        ProgramResult::Err(EbpfError::AccessViolation(
+            AccessType::Store,
+            0x1FFFFD000,
+            1,
+            "program"
+        )),
+    );
+}
+
+#[test]
+fn test_err_stack_gap_below_first_frame() {
+    let config = Config {
+        enable_sbpf_v2: false,
+        max_call_depth: 3,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        stb [r10-0x2000], 0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(1),
+        ProgramResult::Err(EbpfError::StackAccessViolation(
+            AccessType::Store,
+            0x1FFFFF000,
+            1,
+            -1
+        )),
+    );
+}
+
+#[test]
+fn test_stack_frame_gap() {
+    // By default the gaps are as large as the frames
+    let config = Config {
+        stack_frame_size: 0x2000,
+        ..Config::default()
+    };
+    assert_eq!(config.stack_frame_gap_size(&SBPFVersion::V1), 0x2000);
+    assert_eq!(config.stack_frame_gap_size(&SBPFVersion::V2), 0);
+    // Oversized gaps are clamped to what the chunked stack region can express
+    let oversized_config = Config {
+        stack_frame_gap: Some(usize::MAX),
+        ..config
+    };
+    assert_eq!(
+        oversized_config.stack_frame_gap_size(&SBPFVersion::V1),
+        u32::MAX as u64
+    );
+
     let config = Config {
         enable_sbpf_v2: false,
         max_call_depth: 3,
+        stack_frame_gap: Some(0x2000),
         ..Config::default()
     };
+    // The second frame starts after the first one and its gap
     test_interpreter_and_jit_asm!(
         "
-        stb [r10-0x4000], 0
+        call function_foo
+        exit
+        function_foo:
+        stb [r10-0x1000], 0
+        mov r0, r10
         exit",
         config,
         [],
         (),
-        TestContextObject::new(1),
-        ProgramResult::Err(EbpfError::AccessViolation(
+        TestContextObject::new(5),
+        ProgramResult::Ok(ebpf::MM_STACK_START + 0x4000),
+    );
+    // Overrunning a frame by less than the gap faults
+    test_interpreter_and_jit_asm!(
+        "
+        call function_foo
+        exit
+        function_foo:
+        stb [r10+0x1fff], 0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(2),
+        ProgramResult::Err(EbpfError::StackAccessViolation(
             AccessType::Store,
-            0x1FFFFD000,
+            ebpf::MM_STACK_START + 0x5fff,
             1,
-            "program"
+            1
         )),
     );
 }
@@ -3860,6 +7271,570 @@ fn test_div() {
     );
 }
 
+#[test]
+fn test_scratch_register_liveness() {
+    // Tracing observes all registers, so it is disabled to let the JIT skip saving dead ones
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config::default(),
+        FunctionRegistry::default(),
+    ));
+    for (source, expected_result) in [
+        // r0 dead, r2 live after a division
+        (
+            "
+            mov r0, 7
+            mov r2, 5
+            mov r4, 100
+            udiv64 r4, 3
+            mov r0, r4
+            add r0, r2
+            exit",
+            38,
+        ),
+        // r0 live after a remainder
+        (
+            "
+            mov r0, 7
+            mov r4, 100
+            urem64 r4, 3
+            add r0, r4
+            exit",
+            8,
+        ),
+        // r0 and r2 dead after a multiplication
+        (
+            "
+            mov r2, 3
+            mov r4, 6
+            lmul64 r4, 7
+            mov r2, r4
+            mov r0, r2
+            exit",
+            42,
+        ),
+        // r3 dead after a shift
+        (
+            "
+            mov r3, 9
+            mov r4, 1
+            mov r5, 4
+            lsh64 r4, r5
+            mov r3, 2
+            add r4, r3
+            mov r0, r4
+            exit",
+            18,
+        ),
+        // r3 live after a shift
+        (
+            "
+            mov r3, 9
+            mov r4, 1
+            mov r5, 4
+            lsh64 r4, r5
+            add r4, r3
+            mov r0, r4
+            exit",
+            25,
+        ),
+        // Shift amount dead after shifting r3
+        (
+            "
+            mov r3, 1
+            mov r5, 3
+            lsh64 r3, r5
+            mov r5, 0
+            mov r0, r3
+            exit",
+            8,
+        ),
+        // Shift amount live after shifting r3
+        (
+            "
+            mov r3, 1
+            mov r5, 3
+            lsh64 r3, r5
+            add r3, r5
+            mov r0, r3
+            exit",
+            11,
+        ),
+        // All scratch registers dead after a call
+        (
+            "
+            mov r6, 1
+            mov r7, 2
+            call function_foo
+            mov r6, 3
+            mov r7, 4
+            mov r8, 5
+            mov r9, 6
+            add r6, r7
+            mov r0, r6
+            exit
+            function_foo:
+            mov r6, 10
+            mov r7, 20
+            mov r8, 30
+            mov r9, 40
+            exit",
+            7,
+        ),
+        // r7 live after a call
+        (
+            "
+            mov r6, 1
+            mov r7, 2
+            call function_foo
+            mov r6, 3
+            mov r8, 5
+            add r6, r7
+            mov r0, r6
+            exit
+            function_foo:
+            mov r6, 10
+            mov r7, 20
+            mov r8, 30
+            mov r9, 40
+            exit",
+            5,
+        ),
+    ] {
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(source, loader.clone()).unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut context_object = TestContextObject::new(16);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(result.unwrap(), expected_result);
+        }
+    }
+}
+
+#[test]
+fn test_sign_extension_elision() {
+    for optimize_sign_extensions in [true, false] {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                optimize_sign_extensions,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        for (source, expected_result) in [
+            // Re-extended by the next instruction
+            (
+                "
+                mov32 r1, 0x7fffffff
+                add32 r1, 1
+                add32 r1, 0
+                mov r0, r1
+                exit",
+                0xffffffff80000000,
+            ),
+            // Read by the next instruction
+            (
+                "
+                mov32 r1, 0x7fffffff
+                add32 r1, 1
+                mov r0, r1
+                exit",
+                0xffffffff80000000,
+            ),
+            // Overwritten by the next instruction
+            (
+                "
+                mov r1, 1
+                add32 r1, -2
+                mov r1, 7
+                mov r0, r1
+                exit",
+                7,
+            ),
+        ] {
+            #[allow(unused_mut)]
+            let mut executable = assemble::<TestContextObject>(source, loader.clone()).unwrap();
+            executable.verify::<RequisiteVerifier>().unwrap();
+            #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+            executable.jit_compile().unwrap();
+            for interpreted in [true, false] {
+                #[cfg(not(all(
+                    feature = "jit",
+                    not(target_os = "windows"),
+                    target_arch = "x86_64"
+                )))]
+                if !interpreted {
+                    continue;
+                }
+                let mut context_object = TestContextObject::new(8);
+                create_vm!(
+                    vm,
+                    &executable,
+                    &mut context_object,
+                    stack,
+                    heap,
+                    Vec::new(),
+                    None
+                );
+                let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+                assert_eq!(result.unwrap(), expected_result);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_lazy_jit_compilation() {
+    for (enable_sbpf_v2, source, expected_result) in [
+        // Calls, and a function which is never called
+        (
+            true,
+            "
+            mov64 r0, 0x0
+            call function_foo
+            mov64 r8, 0x1
+            lsh64 r8, 0x20
+            or64 r8, 0x60
+            callx r8
+            exit
+            function_foo:
+            add64 r0, 0x1
+            call function_bar
+            exit
+            function_bar:
+            add64 r0, 0x2
+            exit
+            function_baz:
+            add64 r0, 0x4
+            exit
+            function_unused:
+            mov64 r0, 0x0
+            exit",
+            7,
+        ),
+        // Jumps and falls through into other functions
+        (
+            false,
+            "
+            mov64 r0, 0x0
+            call function_foo
+            exit
+            function_foo:
+            add64 r0, 0x1
+            jeq r0, 0x1, +3
+            exit
+            function_bar:
+            add64 r0, 0x2
+            add64 r0, 0x4
+            add64 r0, 0x8
+            function_baz:
+            add64 r0, 0x10
+            exit",
+            0x19,
+        ),
+    ] {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_sbpf_v2,
+                enable_lazy_jit_compilation: true,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(source, loader).unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        let initial_machine_code_length = executable
+            .get_compiled_program()
+            .unwrap()
+            .machine_code_length();
+        for interpreted in [true, false, false] {
+            #[cfg(not(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut context_object = TestContextObject::new(16);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(result.unwrap(), expected_result);
+        }
+        // The called functions were compiled during the first execution
+        #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+        assert!(
+            executable
+                .get_compiled_program()
+                .unwrap()
+                .machine_code_length()
+                > initial_machine_code_length
+        );
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_jit_dual_mapping() {
+    for enable_lazy_jit_compilation in [false, true] {
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_jit_dual_mapping: true,
+                enable_lazy_jit_compilation,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        ));
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            mov64 r0, 0x0
+            mov64 r6, 0x3
+            call function_foo
+            add64 r6, -1
+            jne r6, 0x0, -3
+            exit
+            function_foo:
+            add64 r0, 0x2
+            jeq r0, 0x4, +1
+            exit
+            add64 r0, 0x1
+            exit",
+            loader,
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false, false] {
+            #[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut context_object = TestContextObject::new(22);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(result.unwrap(), 7);
+        }
+    }
+}
+
+declare_builtin_function!(
+    /// For test_jit_unwind_info()
+    SyscallBacktrace,
+    fn rust(
+        _context_object: &mut TestContextObject,
+        _arg1: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &mut MemoryMapping,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        Ok(backtrace.contains("execute_program") as u64)
+    }
+);
+
+#[test]
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn test_jit_unwind_info() {
+    for enable_lazy_jit_compilation in [false, true] {
+        let mut function_registry =
+            FunctionRegistry::<BuiltinFunction<TestContextObject>>::default();
+        function_registry
+            .register_function_hashed(*b"backtrace", SyscallBacktrace::vm)
+            .unwrap();
+        let loader = Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_jit_unwind_info: true,
+                enable_lazy_jit_compilation,
+                ..Config::default()
+            },
+            function_registry,
+        ));
+        #[allow(unused_mut)]
+        let mut executable = assemble::<TestContextObject>(
+            "
+            call function_foo
+            exit
+            function_foo:
+            mov64 r1, 0x1
+            mov64 r2, 0x2
+            mov64 r3, 0x3
+            mov64 r4, 0x4
+            mov64 r5, 0x5
+            syscall backtrace
+            exit",
+            loader,
+        )
+        .unwrap();
+        executable.verify::<RequisiteVerifier>().unwrap();
+        #[cfg(all(feature = "jit", target_arch = "x86_64"))]
+        executable.jit_compile().unwrap();
+        for interpreted in [true, false] {
+            #[cfg(not(all(feature = "jit", target_arch = "x86_64")))]
+            if !interpreted {
+                continue;
+            }
+            let mut context_object = TestContextObject::new(10);
+            create_vm!(
+                vm,
+                &executable,
+                &mut context_object,
+                stack,
+                heap,
+                Vec::new(),
+                None
+            );
+            // The backtrace only reaches the caller of the VM if it continues through the JIT frames
+            let (_instruction_count, result) = vm.execute_program(&executable, interpreted);
+            assert_eq!(result.unwrap(), 1);
+        }
+    }
+}
+
+#[test]
+#[cfg(all(
+    feature = "parallel-jit",
+    not(target_os = "windows"),
+    target_arch = "x86_64"
+))]
+fn test_parallel_jit_compilation() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            enable_parallel_jit_compilation: true,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0x0
+        mov64 r6, 0x3
+        call function_foo
+        add64 r6, -1
+        jne r6, 0x0, -3
+        call function_bar
+        exit
+        function_foo:
+        add64 r0, 0x2
+        jeq r0, 0x4, +1
+        ja +1
+        add64 r0, 0x1
+        exit
+        function_bar:
+        mov64 r1, 0x11
+        add64 r0, r1
+        call function_baz
+        exit
+        function_baz:
+        lsh64 r0, 0x1
+        exit",
+        loader,
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    // More threads than functions, so that every span is emitted into a region of its own
+    assert!(rayon::ThreadPoolBuilder::new()
+        .num_threads(8)
+        .build()
+        .unwrap()
+        .install(|| executable.jit_compile().is_ok()));
+    let mut expected_result = None;
+    for interpreted in [true, false] {
+        let mut context_object = TestContextObject::new(100);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (instruction_count, result) = vm.execute_program(&executable, interpreted);
+        let result = (instruction_count, result.unwrap());
+        assert_eq!(*expected_result.get_or_insert(result), result);
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_whole_program_tiered_execution() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 0x0
+        mov64 r6, 0x3
+        call function_foo
+        add64 r6, -1
+        jne r6, 0x0, -3
+        exit
+        function_foo:
+        add64 r0, 0x1
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    let mut tiered_executor = WholeProgramTieredExecutor::new(&executable, 4).unwrap();
+    // The entrypoint and function_foo are entered until function_foo becomes hot
+    for expected_entries in [(1, 3), (2, 6), (2, 6)] {
+        let mut context_object = TestContextObject::new(18);
+        create_vm!(
+            vm,
+            &executable,
+            &mut context_object,
+            stack,
+            heap,
+            Vec::new(),
+            None
+        );
+        let (instruction_count, result) = tiered_executor.execute_program(&mut vm, &executable);
+        assert_eq!(instruction_count, 18);
+        assert_eq!(result.unwrap(), 3);
+        let function_entries = tiered_executor.get_function_entries();
+        assert_eq!(
+            (function_entries[&0], function_entries[&6]),
+            expected_entries
+        );
+    }
+    assert!(tiered_executor.is_hot());
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    assert!(executable.get_compiled_program().is_some());
+}
+
 #[test]
 fn test_mod() {
     let config = Config {
@@ -0,0 +1,98 @@
+// Copyright 2020 Solana Maintainers <maintainers@solana.com>
+//
+// Licensed under the Apache License, Version 2.0 <http://www.apache.org/licenses/LICENSE-2.0> or
+// the MIT license <http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(feature = "legacy-api")]
+
+use solana_rbpf::legacy::{EbpfVm, MAX_HELPERS};
+use std::{
+    fs::File,
+    io::Read,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use test_utils::assert_error;
+
+fn gather_bytes(a: u64, b: u64, c: u64, d: u64, e: u64) -> u64 {
+    a.wrapping_shl(32) | b.wrapping_shl(24) | c.wrapping_shl(16) | d.wrapping_shl(8) | e
+}
+
+#[rustfmt::skip]
+const GATHER_BYTES_PROG: [u8; 56] = [
+    0xb7, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // mov64 r1, 1
+    0xb7, 0x02, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, // mov64 r2, 2
+    0xb7, 0x03, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, // mov64 r3, 3
+    0xb7, 0x04, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, // mov64 r4, 4
+    0xb7, 0x05, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, // mov64 r5, 5
+    0x85, 0x00, 0x00, 0x00, 0x78, 0x56, 0x34, 0x12, // call 0x12345678
+    0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+];
+
+#[test]
+fn test_legacy_register_helper() {
+    let mut vm = EbpfVm::new(Some(&GATHER_BYTES_PROG)).unwrap();
+    vm.register_helper(0x12345678, gather_bytes).unwrap();
+    assert_eq!(vm.execute_program(&mut []).unwrap(), 0x0102030405);
+    assert_eq!(vm.get_last_instruction_count(), 7);
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    {
+        vm.jit_compile().unwrap();
+        assert_eq!(vm.execute_program_jit(&mut []).unwrap(), 0x0102030405);
+        assert_eq!(vm.get_last_instruction_count(), 7);
+    }
+}
+
+#[test]
+fn test_legacy_input() {
+    #[rustfmt::skip]
+    let prog = [
+        0x71, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ldxb r0, [r1]
+        0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // add64 r0, 1
+        0x73, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // stxb [r1], r0
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let mut vm = EbpfVm::new(Some(&prog)).unwrap();
+    let mut mem = [0x41];
+    assert_eq!(vm.execute_program(&mut mem).unwrap(), 0x42);
+    assert_eq!(mem, [0x42]);
+}
+
+#[test]
+fn test_legacy_set_elf() {
+    static LOGGED_LENGTH: AtomicU64 = AtomicU64::new(0);
+    fn log(_ptr: u64, len: u64, _c: u64, _d: u64, _e: u64) -> u64 {
+        LOGGED_LENGTH.store(len, Ordering::Relaxed);
+        0
+    }
+    let mut file = File::open("tests/elfs/syscall_static.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let mut vm = EbpfVm::new(None).unwrap();
+    vm.register_helper_ex("log", log).unwrap();
+    vm.set_elf(&elf).unwrap();
+    assert_eq!(vm.execute_program(&mut []).unwrap(), 0);
+    assert_eq!(LOGGED_LENGTH.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_legacy_errors() {
+    let mut vm = EbpfVm::new(None).unwrap();
+    assert_error!(vm.execute_program(&mut []), "NoProgram");
+
+    vm.set_program(&GATHER_BYTES_PROG).unwrap();
+    vm.register_helper(0x12345678, gather_bytes).unwrap();
+    vm.set_max_instruction_count(3);
+    assert_error!(vm.execute_program(&mut []), "ExceededMaxInstructions");
+
+    for index in 1..MAX_HELPERS {
+        vm.register_helper(index as u32, gather_bytes).unwrap();
+    }
+    assert_error!(
+        vm.register_helper_ex("one_too_many", gather_bytes),
+        "TooManyHelpers({})",
+        MAX_HELPERS
+    );
+    // Replacing a helper does not need another slot
+    vm.register_helper(1, gather_bytes).unwrap();
+}
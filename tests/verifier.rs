@@ -22,12 +22,21 @@
 extern crate solana_rbpf;
 extern crate thiserror;
 
+#[cfg(feature = "unstable")]
+use solana_rbpf::unstable::{
+    NoIndirectCalls, VerificationCache, VerificationCacheStatistics, VerifierContext, VerifierPass,
+    VerifierPipeline,
+};
 use solana_rbpf::{
     assembler::assemble,
     ebpf,
     elf::Executable,
+    error::EbpfError,
     program::{BuiltinProgram, FunctionRegistry, SBPFVersion},
-    verifier::{RequisiteVerifier, Verifier, VerifierError},
+    verifier::{
+        is_opcode_supported, opcode_support, OpcodeSet, OpcodeSupport, RequisiteVerifier, Verifier,
+        VerifierDiagnostic, VerifierError,
+    },
     vm::{Config, TestContextObject},
 };
 use std::sync::Arc;
@@ -113,6 +122,30 @@ fn test_verifier_err_div_by_zero_imm() {
     executable.verify::<RequisiteVerifier>().unwrap();
 }
 
+#[test]
+fn test_verifier_err_division_overflow_imm() {
+    for (source, expected) in [
+        ("mov32 r0, -0x80000000\nsdiv32 r0, -1", Some(1)),
+        ("mov64 r0, -0x80000000\nsrem32 r0, -1", Some(1)),
+        ("mov32 r0, 0\nhor64 r0, -0x80000000\nsdiv64 r0, -1", Some(2)),
+        ("mov64 r0, 0\nhor64 r0, -0x80000000\nsrem64 r0, -1", Some(2)),
+        ("mov32 r0, -0x80000000\nsdiv32 r0, 1", None),
+        ("mov32 r1, -0x80000000\nsdiv32 r0, -1", None),
+        ("mov32 r0, -0x80000000\nsdiv64 r0, -1", None),
+    ] {
+        let executable = assemble::<TestContextObject>(
+            &format!("{}\nexit", source),
+            Arc::new(BuiltinProgram::new_mock()),
+        )
+        .unwrap();
+        let result = executable.verify::<RequisiteVerifier>();
+        match expected {
+            Some(pc) => assert_error!(result, "DivisionOverflow({})", pc),
+            None => assert!(result.is_ok()),
+        }
+    }
+}
+
 #[test]
 #[should_panic(expected = "UnsupportedLEBEArgument(0)")]
 fn test_verifier_err_endian_size() {
@@ -222,7 +255,13 @@ fn test_verifier_err_jmp_lddw() {
         ja +1
         lddw r0, 0x1122334455667788
         exit",
-        Arc::new(BuiltinProgram::new_mock()),
+        Arc::new(BuiltinProgram::new_loader(
+            Config {
+                enable_sbpf_v2: false,
+                ..Config::default()
+            },
+            FunctionRegistry::default(),
+        )),
     )
     .unwrap();
     executable.verify::<RequisiteVerifier>().unwrap();
@@ -231,10 +270,12 @@ fn test_verifier_err_jmp_lddw() {
 #[test]
 #[should_panic(expected = "InvalidFunction(1)")]
 fn test_verifier_err_call_lddw() {
+    // V2 does not support lddw, so it is spelled out as raw instructions
     let executable = assemble::<TestContextObject>(
         "
         call 1
-        lddw r0, 0x1122334455667788
+        .fill 1, 0x5566778800000018
+        .fill 1, 0x1122334400000000
         exit",
         Arc::new(BuiltinProgram::new_mock()),
     )
@@ -256,6 +297,30 @@ fn test_verifier_err_function_fallthrough() {
     executable.verify::<RequisiteVerifier>().unwrap();
 }
 
+#[test]
+fn test_verifier_err_function_out_of_bounds() {
+    let prog = &[
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let mut function_registry = FunctionRegistry::default();
+    function_registry
+        .register_function(0, *b"entrypoint", 0)
+        .unwrap();
+    function_registry
+        .register_function(8, *b"function_foo", 8)
+        .unwrap();
+    assert_eq!(
+        RequisiteVerifier::verify(
+            prog,
+            &Config::default(),
+            &SBPFVersion::V2,
+            &function_registry
+        ),
+        Err(VerifierError::InvalidFunction(7))
+    );
+}
+
 #[test]
 #[should_panic(expected = "JumpOutOfCode(3, 0)")]
 fn test_verifier_err_jmp_out() {
@@ -312,6 +377,179 @@ fn test_verifier_err_write_r10() {
     executable.verify::<RequisiteVerifier>().unwrap();
 }
 
+#[test]
+fn test_verifier_pre_verified_ranges() {
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov r10, 1
+        mov r0, 0
+        mov r10, 2
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    executable.mark_pre_verified(0..1);
+    assert_eq!(
+        executable
+            .verify::<RequisiteVerifier>()
+            .unwrap_err()
+            .to_string(),
+        "Verifier error: cannot write into register r10 (insn #2)"
+    );
+    executable.mark_pre_verified(2..3);
+    assert_eq!(executable.get_pre_verified_ranges(), &[0..1, 2..3]);
+    executable.verify::<RequisiteVerifier>().unwrap();
+}
+
+#[test]
+fn test_verifier_reject_unreachable_code() {
+    let config = Config {
+        reject_unreachable_code: true,
+        ..Config::default()
+    };
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        config,
+        FunctionRegistry::default(),
+    ));
+    let executable = assemble::<TestContextObject>(
+        "
+        jeq r1, 0, +1
+        mov r0, 1
+        call function_foo
+        exit
+        function_foo:
+        mov r0, 0
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+
+    let executable = assemble::<TestContextObject>(
+        "
+        ja +1
+        mov r0, 1
+        exit
+        function_foo:
+        exit
+        exit",
+        loader,
+    )
+    .unwrap();
+    assert_error!(
+        executable.verify::<RequisiteVerifier>(),
+        "UnreachableCode(1)"
+    );
+    assert!(RequisiteVerifier::verify(
+        executable.get_text_bytes().1,
+        &Config::default(),
+        executable.get_sbpf_version(),
+        executable.get_function_registry(),
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_verifier_forbidden_opcodes() {
+    let config = Config {
+        forbidden_opcodes: OpcodeSet::new().with(ebpf::CALL_REG),
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        callx r1
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    assert_error!(
+        executable.verify::<RequisiteVerifier>(),
+        "ForbiddenOpcode(141, 1)"
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        exit",
+        Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        )),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_verification_cache() {
+    let cache = VerificationCache::new(1);
+    let loader = Arc::new(BuiltinProgram::new_mock());
+    for _ in 0..2 {
+        let executable = assemble::<TestContextObject>(
+            "
+            mov r0, 0
+            exit",
+            loader.clone(),
+        )
+        .unwrap();
+        executable
+            .verify_cached::<RequisiteVerifier>(&cache)
+            .unwrap();
+    }
+    assert_eq!(
+        cache.statistics(),
+        VerificationCacheStatistics {
+            hits: 1,
+            misses: 1,
+            evictions: 0,
+            len: 1,
+        }
+    );
+
+    // Other verifiers do not share the result and rejections are not cached
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    for _ in 0..2 {
+        assert_error!(
+            executable.verify_cached::<ContradictionVerifier>(&cache),
+            "NoProgram"
+        );
+    }
+    assert_eq!(cache.statistics().misses, 3);
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 1
+        exit",
+        loader,
+    )
+    .unwrap();
+    executable
+        .verify_cached::<RequisiteVerifier>(&cache)
+        .unwrap();
+    assert_eq!(
+        cache.statistics(),
+        VerificationCacheStatistics {
+            hits: 1,
+            misses: 4,
+            evictions: 1,
+            len: 1,
+        }
+    );
+    cache.clear();
+    assert_eq!(cache.statistics().len, 0);
+}
+
 #[test]
 fn test_verifier_err_all_shift_overflows() {
     let testcases = [
@@ -362,23 +600,360 @@ fn test_sdiv_disabled() {
     for (opc, instruction) in instructions {
         for enable_sbpf_v2 in [true, false] {
             let assembly = format!("\n{instruction}\nexit");
-            let executable = assemble::<TestContextObject>(
-                &assembly,
-                Arc::new(BuiltinProgram::new_loader(
-                    Config {
-                        enable_sbpf_v2,
-                        ..Config::default()
-                    },
-                    FunctionRegistry::default(),
-                )),
-            )
-            .unwrap();
-            let result = executable.verify::<RequisiteVerifier>();
+            let loader = Arc::new(BuiltinProgram::new_loader(
+                Config {
+                    enable_sbpf_v2,
+                    ..Config::default()
+                },
+                FunctionRegistry::default(),
+            ));
+            let result = assemble::<TestContextObject>(&assembly, loader.clone());
             if enable_sbpf_v2 {
-                assert!(result.is_ok());
+                assert!(result.unwrap().verify::<RequisiteVerifier>().is_ok());
             } else {
-                assert_error!(result, "VerifierError(UnknownOpCode({}, {}))", opc, 0);
+                let mnemonic = instruction.split(' ').next().unwrap();
+                assert_eq!(
+                    result.err(),
+                    Some(format!("Instruction {mnemonic} is not supported by V1"))
+                );
+                let prog = &[
+                    opc, 0x10, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, //
+                    0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+                ];
+                let executable = Executable::<TestContextObject>::from_text_bytes(
+                    prog,
+                    loader,
+                    SBPFVersion::V1,
+                    FunctionRegistry::default(),
+                )
+                .unwrap();
+                assert_error!(
+                    executable.verify::<RequisiteVerifier>(),
+                    "VerifierError(UnknownOpCode({}, {}))",
+                    opc,
+                    0
+                );
+            }
+        }
+    }
+}
+
+/// Only allows calls to functions of the program itself
+#[cfg(feature = "unstable")]
+struct NoSyscalls {}
+#[cfg(feature = "unstable")]
+impl VerifierPass for NoSyscalls {
+    fn check_instruction(
+        &self,
+        context: &VerifierContext,
+        pc: usize,
+        insn: &ebpf::Insn,
+    ) -> std::result::Result<(), VerifierError> {
+        if insn.opc == ebpf::CALL_IMM
+            && context
+                .function_registry
+                .lookup_by_key(insn.imm as u32)
+                .is_none()
+        {
+            return Err(VerifierError::RuleViolation("syscall".to_string(), pc));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[test]
+fn test_verifier_pipeline() {
+    let loader = Arc::new(BuiltinProgram::new_loader(
+        Config {
+            enable_sbpf_v2: false,
+            ..Config::default()
+        },
+        FunctionRegistry::default(),
+    ));
+    let mut pipeline = VerifierPipeline::new();
+    pipeline.add_pass(NoIndirectCalls).add_pass(NoSyscalls {});
+
+    let executable = assemble::<TestContextObject>(
+        "
+        lddw r1, 0x95
+        call function_foo
+        exit
+        function_foo:
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    executable
+        .verify_with_pipeline::<RequisiteVerifier>(&pipeline)
+        .unwrap();
+    // The passes only run after the verifier accepted the program
+    assert_error!(
+        executable.verify_with_pipeline::<ContradictionVerifier>(&pipeline),
+        "NoProgram"
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 0
+        callx r1
+        exit",
+        loader.clone(),
+    )
+    .unwrap();
+    executable.verify::<RequisiteVerifier>().unwrap();
+    assert_error!(
+        executable.verify_with_pipeline::<RequisiteVerifier>(&pipeline),
+        "RuleViolation(\"indirect calls are not allowed\", 1)"
+    );
+
+    let executable = assemble::<TestContextObject>(
+        "
+        syscall bpf_trace_printf
+        exit",
+        loader,
+    )
+    .unwrap();
+    assert_error!(
+        executable.verify_with_pipeline::<RequisiteVerifier>(&pipeline),
+        "RuleViolation(\"syscall\", 0)"
+    );
+}
+
+#[test]
+fn test_verifier_error_context() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov32 r0, 1
+        call function_foo
+        exit
+        function_foo:
+        udiv32 r0, 0
+        ja -3
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    let err = RequisiteVerifier::verify(
+        executable.get_text_bytes().1,
+        executable.get_config(),
+        executable.get_sbpf_version(),
+        executable.get_function_registry(),
+    )
+    .unwrap_err();
+    let context = executable.verifier_error_context(&err).unwrap();
+    assert_eq!(
+        context.to_string(),
+        "insn #3 at byte offset 0x18 in function_foo: udiv32 r0, 0"
+    );
+    assert_eq!(
+        executable.render_snippet(context.pc, 2),
+        "       1  call function_foo\n       2  exit\n>      3  udiv32 r0, 0\n       4  ja lbb_2\n       5  exit\n"
+    );
+    assert_eq!(
+        executable.render_snippet(0, 1),
+        ">      0  mov32 r0, 1\n       1  call function_foo\n"
+    );
+    assert!(executable
+        .verifier_error_context(&VerifierError::NoProgram)
+        .is_none());
+}
+
+#[test]
+fn test_verifier_diagnostic() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov32 r0, 1
+        call function_foo
+        exit
+        function_foo:
+        udiv32 r0, 0
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    let err = executable.verify::<RequisiteVerifier>().unwrap_err();
+    let err = match err {
+        EbpfError::VerifierError(err) => err,
+        _ => panic!("{:?}", err),
+    };
+    let diagnostic = executable.verifier_diagnostic(&err);
+    assert_eq!(
+        diagnostic,
+        VerifierDiagnostic {
+            code: 4,
+            pc: Some(3),
+            function: Some("function_foo".to_string()),
+            message: "division by 0 (insn #3)".to_string(),
+        }
+    );
+    assert_eq!(
+        executable.verifier_diagnostic(&VerifierError::NoProgram),
+        VerifierDiagnostic {
+            code: 3,
+            pc: None,
+            function: None,
+            message: "no program set, call prog_set() to load one".to_string(),
+        }
+    );
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&diagnostic).unwrap();
+        assert_eq!(
+            json,
+            r#"{"code":4,"pc":3,"function":"function_foo","message":"division by 0 (insn #3)"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<VerifierDiagnostic>(&json).unwrap(),
+            diagnostic
+        );
+    }
+}
+
+#[test]
+fn test_opcode_support() {
+    for sbpf_version in [SBPFVersion::V1, SBPFVersion::V2, SBPFVersion::V3] {
+        for opc in 0..=u8::MAX {
+            let expected = match opc {
+                ebpf::LD_DW_IMM | ebpf::HOR64_IMM => {
+                    Some(sbpf_version.enable_lddw() == (opc == ebpf::LD_DW_IMM))
+                }
+                ebpf::NEG32 | ebpf::NEG64 => Some(sbpf_version.enable_neg()),
+                ebpf::LE => Some(sbpf_version.enable_le()),
+                _ if opc & ebpf::BPF_CLS_MASK == ebpf::BPF_PQR => {
+                    (opcode_support(opc) == OpcodeSupport::Pqr).then(|| sbpf_version.enable_pqr())
+                }
+                ebpf::MUL32_IMM
+                | ebpf::MUL32_REG
+                | ebpf::DIV32_IMM
+                | ebpf::DIV32_REG
+                | ebpf::MOD32_IMM
+                | ebpf::MOD32_REG
+                | ebpf::MUL64_IMM
+                | ebpf::MUL64_REG
+                | ebpf::DIV64_IMM
+                | ebpf::DIV64_REG
+                | ebpf::MOD64_IMM
+                | ebpf::MOD64_REG => Some(!sbpf_version.enable_pqr()),
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                assert_eq!(
+                    is_opcode_supported(opc, &sbpf_version),
+                    expected,
+                    "{:#x}",
+                    opc
+                );
             }
         }
     }
+    assert_eq!(opcode_support(ebpf::ADD64_REG), OpcodeSupport::All);
+    assert_eq!(opcode_support(0x20), OpcodeSupport::Unsupported);
+
+    // The JIT rejects what the verifier rejects
+    let config = Config {
+        enable_sbpf_v1: false,
+        ..Config::default()
+    };
+    let prog = &[
+        0x87, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let mut executable = Executable::<TestContextObject>::from_text_bytes(
+        prog,
+        Arc::new(BuiltinProgram::new_loader(
+            config,
+            FunctionRegistry::default(),
+        )),
+        SBPFVersion::V2,
+        FunctionRegistry::default(),
+    )
+    .unwrap();
+    assert_error!(
+        executable.verify::<RequisiteVerifier>(),
+        "UnknownOpCode(135, 0)"
+    );
+    #[cfg(all(feature = "jit", not(target_os = "windows"), target_arch = "x86_64"))]
+    assert_error!(executable.jit_compile(), "UnsupportedInstruction");
+}
+
+#[test]
+fn test_verification_budget() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        mov r1, 1
+        exit",
+        Arc::new(BuiltinProgram::new_mock()),
+    )
+    .unwrap();
+    let verify = |verification_budget| {
+        RequisiteVerifier::verify(
+            executable.get_text_bytes().1,
+            &Config {
+                verification_budget,
+                ..Config::default()
+            },
+            executable.get_sbpf_version(),
+            executable.get_function_registry(),
+        )
+    };
+    assert!(verify(3).is_ok());
+    assert_eq!(verify(2), Err(VerifierError::BudgetExceeded(3, 2)));
+}
+
+#[test]
+fn test_stack_frame_overrun() {
+    let config = Config {
+        enable_sbpf_v2: false,
+        reject_stack_frame_overruns: true,
+        ..Config::default()
+    };
+    for (source, expected_result) in [
+        ("stb [r10-0x2000], 0", Ok(())),
+        ("ldxdw r0, [r10+0xff8]", Ok(())),
+        ("ldxdw r0, [r1+0x2000]", Ok(())),
+        (
+            "stb [r10-0x2001], 0",
+            Err(VerifierError::StackFrameOverrun(-0x2001, 1, 0)),
+        ),
+        (
+            "stxw [r10+0xffd], r1",
+            Err(VerifierError::StackFrameOverrun(0xffd, 4, 0)),
+        ),
+        (
+            "ldxdw r0, [r10+0xff9]",
+            Err(VerifierError::StackFrameOverrun(0xff9, 8, 0)),
+        ),
+    ] {
+        let executable = assemble::<TestContextObject>(
+            &format!("{source}\nexit"),
+            Arc::new(BuiltinProgram::new_loader(
+                config,
+                FunctionRegistry::default(),
+            )),
+        )
+        .unwrap();
+        let verify = |config: &Config| {
+            RequisiteVerifier::verify(
+                executable.get_text_bytes().1,
+                config,
+                executable.get_sbpf_version(),
+                executable.get_function_registry(),
+            )
+        };
+        assert_eq!(verify(&config), expected_result);
+        // Without gaps the neighbouring frames are reachable anyway
+        assert!(verify(&Config {
+            enable_stack_frame_gaps: false,
+            ..config
+        })
+        .is_ok());
+        // Only rejected on request
+        assert!(verify(&Config {
+            reject_stack_frame_overruns: false,
+            ..config
+        })
+        .is_ok());
+    }
 }